@@ -6,16 +6,31 @@ import java.nio.ByteOrder
 data class AudioPacket(
     val sampleRate: Int,
     val channels: Int,
+    val flags: Int,
     val seq: Long,
     val sendTimeUs: Long,
     val samplesPerChannel: Int,
+    /** Sample frames since the stream started, wrapping at 2^32 like an RTP timestamp. */
+    val mediaTime: Long,
     val payload: ShortArray
 ) {
     companion object {
         private val MAGIC = byteArrayOf('A'.code.toByte(), 'U'.code.toByte(), 'D'.code.toByte(), '0'.code.toByte())
-        private const val VERSION: Int = 1
+        private const val VERSION: Int = 2
         private const val CODEC_PCM16: Int = 0
-        const val HEADER_SIZE = 28
+        const val HEADER_SIZE = 32
+        /** Padding from a sender's bandwidth probe; counted, never played. */
+        const val FLAG_PROBE: Int = 1
+        /** Payload sealed with the pre-shared key; see [PacketCipher]. */
+        const val FLAG_ENCRYPTED: Int = 2
+        /** With [FLAG_ENCRYPTED]: sealed with ChaCha20-Poly1305 instead of AES-256-GCM. */
+        const val FLAG_CHACHA20: Int = 4
+        /** Payload left readable but authenticated with the pre-shared key. */
+        const val FLAG_AUTHENTICATED: Int = 8
+        /** With a sealed payload: a rotating key whose epoch follows the session id. */
+        const val FLAG_KEY_EPOCH: Int = 16
+        /** First packet after the sender dropped audio too late to send; mediaTime jumps. */
+        const val FLAG_DISCONTINUITY: Int = 64
 
         fun parse(packetBytes: ByteArray, packetLen: Int): AudioPacket? {
             if (packetLen < HEADER_SIZE) return null
@@ -24,6 +39,7 @@ data class AudioPacket(
             val version = packetBytes[4].toInt() and 0xFF
             val codec = packetBytes[5].toInt() and 0xFF
             val channels = packetBytes[6].toInt() and 0xFF
+            val flags = packetBytes[7].toInt() and 0xFF
 
             if (version != VERSION || codec != CODEC_PCM16 || channels !in 1..2) {
                 return null
@@ -35,6 +51,7 @@ data class AudioPacket(
             val sendTimeUs = bb.long
             val samplesPerChannel = bb.short.toInt() and 0xFFFF
             val payloadLen = bb.short.toInt() and 0xFFFF
+            val mediaTime = bb.int.toLong() and 0xFFFF_FFFFL
 
             if (payloadLen <= 0 || HEADER_SIZE + payloadLen > packetLen || payloadLen % 2 != 0) {
                 return null
@@ -53,9 +70,11 @@ data class AudioPacket(
             return AudioPacket(
                 sampleRate = sampleRate,
                 channels = channels,
+                flags = flags,
                 seq = seq,
                 sendTimeUs = sendTimeUs,
                 samplesPerChannel = samplesPerChannel,
+                mediaTime = mediaTime,
                 payload = pcm
             )
         }
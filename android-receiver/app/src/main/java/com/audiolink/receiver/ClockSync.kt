@@ -0,0 +1,107 @@
+package com.audiolink.receiver
+
+import java.nio.ByteBuffer
+import java.nio.ByteOrder
+
+/**
+ * Sender clock against ours, from NTP-style pings sent along with each [FeedbackReport].
+ *
+ * A ping leaves here at t1; the sender stamps its arrival (t2) and its answer (t3), which
+ * arrives back at t4. The offset comes from the recent exchange with the shortest round
+ * trip; the drift is the slope through the best exchange of each stretch of the history.
+ */
+class ClockSync {
+    private class Sample(val localUs: Long, val rttUs: Long, val offsetUs: Long)
+
+    private val samples = ArrayDeque<Sample>()
+
+    /** Multi-room delay from send time to playout the sender asks for, or 0 to play on arrival. */
+    @Volatile
+    var playoutDelayMs: Int = 0
+        private set
+
+    /** Folds in an answer to one of our pings; anything else is ignored. */
+    @Synchronized
+    fun handleReply(data: ByteArray, len: Int, nowUs: Long) {
+        if (len < SIZE || !isClock(data, len)) return
+        if (data[5].toInt() and FLAG_REPLY == 0) return
+        val bb = ByteBuffer.wrap(data, 8, SIZE - 8).order(ByteOrder.LITTLE_ENDIAN)
+        val t1 = bb.long
+        val t2 = bb.long
+        val t3 = bb.long
+        if (t1 <= 0 || t1 > nowUs) return
+        playoutDelayMs = (data[6].toInt() and 0xFF) or ((data[7].toInt() and 0xFF) shl 8)
+        val sample = Sample(
+            localUs = nowUs,
+            rttUs = ((nowUs - t1) - (t3 - t2)).coerceAtLeast(0),
+            offsetUs = ((t2 - t1) + (t3 - nowUs)) / 2
+        )
+        // A local clock that jumped back makes the history useless.
+        if (samples.lastOrNull()?.let { it.localUs > nowUs } == true) samples.clear()
+        if (samples.size == HISTORY) samples.removeFirst()
+        samples.addLast(sample)
+    }
+
+    @Synchronized
+    fun rttUs(): Long? = samples.lastOrNull()?.rttUs
+
+    /** Sender clock minus ours at [localUs], or null before the first answer. */
+    @Synchronized
+    fun offsetUs(localUs: Long): Long? {
+        val best = samples.takeLast(RECENT).minByOrNull { it.rttUs } ?: return null
+        val drift = (driftPpb() ?: 0L) / 1e9
+        return best.offsetUs + ((localUs - best.localUs) * drift).toLong()
+    }
+
+    /** How many nanoseconds per second the sender clock gains on ours. */
+    @Synchronized
+    fun driftPpb(): Long? {
+        val first = samples.firstOrNull() ?: return null
+        if (samples.last().localUs - first.localUs < MIN_DRIFT_SPAN_US) return null
+        val stretch = (samples.size + DRIFT_POINTS - 1) / DRIFT_POINTS
+        val points = samples.chunked(stretch).map { chunk ->
+            val best = chunk.minBy { it.rttUs }
+            Pair((best.localUs - first.localUs).toDouble(), best.offsetUs.toDouble())
+        }
+        val meanX = points.sumOf { it.first } / points.size
+        val meanY = points.sumOf { it.second } / points.size
+        var num = 0.0
+        var den = 0.0
+        for ((x, y) in points) {
+            num += (x - meanX) * (y - meanY)
+            den += (x - meanX) * (x - meanX)
+        }
+        return if (den > 0.0) (num / den * 1e9).toLong() else null
+    }
+
+    @Synchronized
+    fun reset() {
+        samples.clear()
+        playoutDelayMs = 0
+    }
+
+    companion object {
+        private val MAGIC = byteArrayOf('A'.code.toByte(), 'U'.code.toByte(), 'D'.code.toByte(), 'T'.code.toByte())
+        private const val VERSION: Int = 1
+        private const val FLAG_REPLY: Int = 1
+        const val SIZE = 32
+        private const val HISTORY = 256
+        private const val RECENT = 16
+        private const val DRIFT_POINTS = 8
+        private const val MIN_DRIFT_SPAN_US = 10_000_000L
+
+        fun isClock(data: ByteArray, len: Int): Boolean =
+            len >= 4 && (0 until 4).all { data[it] == MAGIC[it] }
+
+        /** Magic, version, flags, the playout delay (answers only), then t1..t3 as little-endian u64. */
+        fun ping(originUs: Long): ByteArray {
+            val bb = ByteBuffer.allocate(SIZE).order(ByteOrder.LITTLE_ENDIAN)
+            bb.put(MAGIC)
+            bb.put(VERSION.toByte())
+            bb.put(0.toByte())
+            bb.putShort(0)
+            bb.putLong(originUs)
+            return bb.array()
+        }
+    }
+}
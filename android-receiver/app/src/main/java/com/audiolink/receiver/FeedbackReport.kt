@@ -0,0 +1,73 @@
+package com.audiolink.receiver
+
+import java.nio.ByteBuffer
+import java.nio.ByteOrder
+
+/**
+ * Receiver -> sender report, sent back over the audio socket every [INTERVAL_MS].
+ *
+ * Echoes the sendTimeUs of the newest packet with the local clock when it arrived and
+ * when the report left, so the sender can estimate round trip and clock offset, plus the
+ * frames played since the previous report and when the last of them reached the speaker.
+ * The delivery counts are cumulative, from [SequenceTracker]; jitter is RFC 3550
+ * interarrival jitter. probeBytes counts bandwidth probe packets received so far.
+ */
+data class FeedbackReport(
+    val playedFirstSeq: Long,
+    val playedLastSeq: Long,
+    val playoutTimeUs: Long,
+    val echoSendTimeUs: Long,
+    val echoRecvTimeUs: Long,
+    val reportTimeUs: Long,
+    val received: Long,
+    val lost: Long,
+    val reordered: Long,
+    val duplicates: Long,
+    val jitterUs: Long,
+    val probeBytes: Long
+) {
+    fun toBytes(): ByteArray {
+        val played = playedLastSeq >= 0
+        val bb = ByteBuffer.allocate(SIZE).order(ByteOrder.LITTLE_ENDIAN)
+        bb.put(MAGIC)
+        bb.put(VERSION.toByte())
+        bb.put((if (played) FLAG_PLAYED else 0).toByte())
+        bb.putShort(0)
+        bb.putInt(if (played) playedFirstSeq.toInt() else 0)
+        bb.putInt(if (played) playedLastSeq.toInt() else 0)
+        bb.putLong(if (played) playoutTimeUs else 0L)
+        bb.putLong(echoSendTimeUs)
+        bb.putLong(echoRecvTimeUs)
+        bb.putLong(reportTimeUs)
+        bb.putLong(received)
+        bb.putLong(lost)
+        bb.putLong(reordered)
+        bb.putLong(duplicates)
+        bb.putLong(jitterUs)
+        bb.putLong(probeBytes)
+        return bb.array()
+    }
+
+    companion object {
+        private val MAGIC = byteArrayOf('A'.code.toByte(), 'U'.code.toByte(), 'D'.code.toByte(), 'F'.code.toByte())
+        private val ACCESS_MAGIC = byteArrayOf('A'.code.toByte(), 'U'.code.toByte(), 'D'.code.toByte(), 'A'.code.toByte())
+        private const val VERSION: Int = 1
+        private const val FLAG_PLAYED: Int = 1
+        const val SIZE = 96
+        const val INTERVAL_MS = 250L
+
+        /**
+         * Presents the sender's `--access-token`: magic, version, three reserved bytes and
+         * the token as UTF-8. Sent first on a TCP stream and ahead of every UDP report.
+         */
+        fun accessFrame(token: String): ByteArray {
+            val bytes = token.toByteArray(Charsets.UTF_8)
+            val bb = ByteBuffer.allocate(8 + bytes.size)
+            bb.put(ACCESS_MAGIC)
+            bb.put(VERSION.toByte())
+            bb.put(ByteArray(3))
+            bb.put(bytes)
+            return bb.array()
+        }
+    }
+}
@@ -16,11 +16,15 @@ data class JitterSnapshot(
 
 class JitterBuffer(
     initialTargetFrames: Int,
-    private val maxFrames: Int
+    private var maxFrames: Int
 ) {
+    private class Entry(val seq: Long, val sendTimeUs: Long, val frame: ShortArray)
+
     private val lock = java.lang.Object()
-    private val queue = ArrayDeque<ShortArray>()
+    private val queue = ArrayDeque<Entry>()
     private var primed = false
+    /** The sender paused the stream: what is queued plays out faded, then silence. */
+    private var paused = false
     private var targetFrames: Int = initialTargetFrames.coerceIn(2, max(2, maxFrames - 1))
 
     private var pushed: Long = 0
@@ -29,14 +33,20 @@ class JitterBuffer(
     private var late: Long = 0
     private var overflowDropped: Long = 0
 
-    fun push(@Suppress("UNUSED_PARAMETER") seq: Long, frame: ShortArray) {
+    /** Sequence number of the frame most recently handed out by [pop], or -1. */
+    @Volatile
+    var lastPlayedSeq: Long = -1
+        private set
+
+    fun push(seq: Long, sendTimeUs: Long, frame: ShortArray) {
         synchronized(lock) {
             pushed++
+            paused = false
             if (queue.size >= maxFrames) {
                 queue.removeFirst()
                 overflowDropped++
             }
-            queue.addLast(frame)
+            queue.addLast(Entry(seq, sendTimeUs, frame))
             if (!primed && queue.size >= targetFrames) {
                 primed = true
             }
@@ -59,7 +69,7 @@ class JitterBuffer(
             if (!primed) return null
 
             val lowWaterFrames = max(1, targetFrames / 2)
-            while (queue.size <= lowWaterFrames && System.currentTimeMillis() < deadline) {
+            while (!paused && queue.size <= lowWaterFrames && System.currentTimeMillis() < deadline) {
                 val remaining = deadline - System.currentTimeMillis()
                 if (remaining <= 0) break
                 try {
@@ -79,13 +89,83 @@ class JitterBuffer(
                 }
             }
 
+            if (queue.isEmpty() && paused) {
+                primed = false
+                return NOT_DUE
+            }
             if (queue.isEmpty()) {
                 missing++
                 played++
                 return null
             }
             played++
-            return queue.removeFirst()
+            val entry = queue.removeFirst()
+            lastPlayedSeq = entry.seq
+            return entry.frame
+        }
+    }
+
+    /**
+     * Like [pop] for multi-room playback: hands out the oldest frame once [playoutUs], the
+     * sender-clock send time due at the output now, reaches its send time, and drops frames
+     * a whole frame past it. Returns [NOT_DUE] while the next frame is still early.
+     */
+    fun popScheduled(timeoutMs: Long, frameUs: Long, playoutUs: Long): ShortArray? {
+        synchronized(lock) {
+            val deadline = System.currentTimeMillis() + max(1, timeoutMs)
+            while (queue.isEmpty() && System.currentTimeMillis() < deadline) {
+                val remaining = deadline - System.currentTimeMillis()
+                if (remaining <= 0) break
+                try {
+                    lock.wait(remaining)
+                } catch (_: InterruptedException) {
+                    return null
+                }
+            }
+            while (queue.isNotEmpty() && queue.first().sendTimeUs + frameUs <= playoutUs) {
+                queue.removeFirst()
+                late++
+            }
+            val entry = queue.peekFirst()
+            if (entry == null && paused) return NOT_DUE
+            if (entry == null) {
+                missing++
+                played++
+                return null
+            }
+            if (entry.sendTimeUs > playoutUs) return NOT_DUE
+            played++
+            queue.removeFirst()
+            lastPlayedSeq = entry.seq
+            return entry.frame
+        }
+    }
+
+    /**
+     * Fades out the queued frames of a stream the sender paused. Once they have played, the
+     * buffer primes again for the resumed stream, handing out [NOT_DUE] instead of counting
+     * frames missing.
+     */
+    fun pause() {
+        synchronized(lock) {
+            if (paused) return
+            paused = true
+            val total = max(1, queue.sumOf { it.frame.size }).toFloat()
+            var at = 0
+            for (entry in queue) {
+                for (i in entry.frame.indices) {
+                    entry.frame[i] = (entry.frame[i] * (1f - at / total)).toInt().toShort()
+                    at++
+                }
+            }
+            lock.notifyAll()
+        }
+    }
+
+    /** Grows the buffer to hold at least [frames], e.g. a multi-room delay's worth. */
+    fun ensureCapacity(frames: Int) {
+        synchronized(lock) {
+            maxFrames = max(maxFrames, frames)
         }
     }
 
@@ -114,4 +194,9 @@ class JitterBuffer(
             )
         }
     }
+
+    companion object {
+        /** Returned while the next frame is not due yet, or the stream is paused; play silence. */
+        val NOT_DUE = ShortArray(0)
+    }
 }
@@ -1,17 +1,25 @@
 package com.audiolink.receiver
 
+import android.Manifest
 import android.content.Intent
+import android.content.pm.PackageManager
+import android.net.Uri
 import android.os.Build
 import android.os.Bundle
 import android.widget.Button
+import android.widget.CheckBox
 import android.widget.EditText
 import android.widget.TextView
 import androidx.appcompat.app.AppCompatActivity
+import java.security.MessageDigest
 
 class MainActivity : AppCompatActivity() {
     private lateinit var portInput: EditText
     private lateinit var jitterInput: EditText
     private lateinit var transportInput: EditText
+    private lateinit var keyInput: EditText
+    private lateinit var accessTokenInput: EditText
+    private lateinit var remoteVolumeCheck: CheckBox
     private lateinit var statusText: TextView
 
     override fun onCreate(savedInstanceState: Bundle?) {
@@ -21,16 +29,24 @@ class MainActivity : AppCompatActivity() {
         portInput = findViewById(R.id.portInput)
         jitterInput = findViewById(R.id.jitterInput)
         transportInput = findViewById(R.id.transportInput)
+        keyInput = findViewById(R.id.keyInput)
+        accessTokenInput = findViewById(R.id.accessTokenInput)
+        remoteVolumeCheck = findViewById(R.id.remoteVolumeCheck)
         statusText = findViewById(R.id.statusText)
 
         findViewById<Button>(R.id.startButton).setOnClickListener {
             val port = portInput.text.toString().toIntOrNull() ?: 50000
             val jitterMs = jitterInput.text.toString().toIntOrNull() ?: 20
             val transportRaw = transportInput.text.toString().trim().lowercase()
-            val transport = if (transportRaw == UdpAudioService.TRANSPORT_TCP) {
-                UdpAudioService.TRANSPORT_TCP
-            } else {
-                UdpAudioService.TRANSPORT_UDP
+            val transport = when (transportRaw) {
+                UdpAudioService.TRANSPORT_TCP -> UdpAudioService.TRANSPORT_TCP
+                UdpAudioService.TRANSPORT_BLUETOOTH -> UdpAudioService.TRANSPORT_BLUETOOTH
+                else -> UdpAudioService.TRANSPORT_UDP
+            }
+            if (transport == UdpAudioService.TRANSPORT_BLUETOOTH && !hasBluetoothPermission()) {
+                requestPermissions(arrayOf(Manifest.permission.BLUETOOTH_CONNECT), REQUEST_BLUETOOTH)
+                statusText.setText(R.string.status_bluetooth_permission)
+                return@setOnClickListener
             }
 
             val intent = Intent(this, UdpAudioService::class.java).apply {
@@ -38,6 +54,9 @@ class MainActivity : AppCompatActivity() {
                 putExtra(UdpAudioService.EXTRA_PORT, port)
                 putExtra(UdpAudioService.EXTRA_JITTER_MS, jitterMs)
                 putExtra(UdpAudioService.EXTRA_TRANSPORT, transport)
+                putExtra(UdpAudioService.EXTRA_KEY, keyInput.text.toString().trim())
+                putExtra(UdpAudioService.EXTRA_ACCESS_TOKEN, accessTokenInput.text.toString().trim())
+                putExtra(UdpAudioService.EXTRA_REMOTE_VOLUME, remoteVolumeCheck.isChecked)
             }
 
             if (Build.VERSION.SDK_INT >= Build.VERSION_CODES.O) {
@@ -55,5 +74,49 @@ class MainActivity : AppCompatActivity() {
             startService(intent)
             statusText.setText(R.string.status_idle)
         }
+
+        intent?.data?.let { applyPairingLink(it) }
+    }
+
+    /** The RFCOMM server needs BLUETOOTH_CONNECT granted at runtime from Android 12. */
+    private fun hasBluetoothPermission(): Boolean =
+        Build.VERSION.SDK_INT < Build.VERSION_CODES.S ||
+            checkSelfPermission(Manifest.permission.BLUETOOTH_CONNECT) == PackageManager.PERMISSION_GRANTED
+
+    override fun onNewIntent(intent: Intent) {
+        super.onNewIntent(intent)
+        intent.data?.let { applyPairingLink(it) }
+    }
+
+    /**
+     * Fills the form from the sender's `--pair` QR code:
+     * `audiolink://pair?host=..&port=..&transport=..&key=..&fp=..[&token=..][&volume=1]`. The
+     * fingerprint is the start of the key's SHA-256, so a mangled link is refused instead of
+     * half applied.
+     */
+    private fun applyPairingLink(uri: Uri) {
+        if (uri.scheme != "audiolink" || uri.host != "pair") return
+        val key = uri.getQueryParameter("key") ?: return
+        val keyBytes = PacketCipher.parseKey(key)
+        val fingerprint = keyBytes?.let { bytes ->
+            MessageDigest.getInstance("SHA-256").digest(bytes).take(8)
+                .joinToString("") { String.format("%02x", it) }
+        }
+        if (fingerprint == null || fingerprint != uri.getQueryParameter("fp")) {
+            statusText.setText(R.string.status_pair_invalid)
+            return
+        }
+        uri.getQueryParameter("port")?.let { portInput.setText(it) }
+        uri.getQueryParameter("transport")?.let { transportInput.setText(it) }
+        keyInput.setText(key)
+        accessTokenInput.setText(uri.getQueryParameter("token").orEmpty())
+        remoteVolumeCheck.isChecked = uri.getQueryParameter("volume") == "1"
+        val host = uri.getQueryParameter("host") ?: "?"
+        val grouped = fingerprint.chunked(4).joinToString(" ")
+        statusText.text = getString(R.string.status_paired, host, grouped)
+    }
+
+    private companion object {
+        const val REQUEST_BLUETOOTH = 1
     }
 }
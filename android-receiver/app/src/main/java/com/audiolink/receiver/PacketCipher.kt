@@ -0,0 +1,216 @@
+package com.audiolink.receiver
+
+import android.util.Base64
+import android.util.Log
+import java.security.GeneralSecurityException
+import java.security.MessageDigest
+import javax.crypto.Cipher
+import javax.crypto.Mac
+import javax.crypto.spec.GCMParameterSpec
+import javax.crypto.spec.IvParameterSpec
+import javax.crypto.spec.SecretKeySpec
+
+/**
+ * Opens packets the sender sealed with `--key`: AES-256-GCM or ChaCha20-Poly1305 over the
+ * payload, the header as associated data, and a nonce of the 8-byte session id that leads the
+ * payload plus the little-endian sequence number. With `--cipher hmac-sha256` the payload
+ * stays readable and the tag is HMAC-SHA256 over nonce, header and payload, truncated to 16
+ * bytes. With `--rotate-key-mins` a 4-byte epoch follows the session id and each epoch's key
+ * is HKDF-SHA256 of the one before, with "audio-link key ratchet" as info, starting from
+ * the key itself. Not thread-safe; use one instance per receive loop.
+ */
+class PacketCipher(private val key: ByteArray) {
+    private val aes = Cipher.getInstance("AES/GCM/NoPadding")
+    private val baseKeys = Keys(key)
+    /** The newest epoch seen and its key, which later epochs are ratcheted from. */
+    private var chain: Pair<Long, ByteArray>? = null
+    /** The newest epoch seen and the one before, for packets in flight across a rotation. */
+    private val epochKeys = HashMap<Long, Keys>()
+    private var chacha: Cipher? = null
+    private var chachaUnavailable = false
+
+    /** The packet as the sender built it before sealing, or null if it fails authentication. */
+    fun open(packetBytes: ByteArray, packetLen: Int): ByteArray? {
+        if (packetLen < AudioPacket.HEADER_SIZE) return null
+        val payloadLen = (packetBytes[26].toInt() and 0xFF) or ((packetBytes[27].toInt() and 0xFF) shl 8)
+        val flags = packetBytes[7].toInt() and 0xFF
+        val rotating = flags and AudioPacket.FLAG_KEY_EPOCH != 0
+        val prefixLen = if (rotating) SESSION_ID_LEN + EPOCH_LEN else SESSION_ID_LEN
+        if (payloadLen < prefixLen + TAG_BITS / 8 || AudioPacket.HEADER_SIZE + payloadLen > packetLen) {
+            return null
+        }
+
+        val nonce = ByteArray(12)
+        System.arraycopy(packetBytes, AudioPacket.HEADER_SIZE, nonce, 0, SESSION_ID_LEN)
+        System.arraycopy(packetBytes, 12, nonce, SESSION_ID_LEN, 4)
+        val plaintext = if (!rotating) {
+            open(baseKeys, packetBytes, payloadLen, prefixLen, nonce, flags) ?: return null
+        } else {
+            val epoch = readEpoch(packetBytes)
+            val known = epochKeys[epoch]
+            if (known != null) {
+                open(known, packetBytes, payloadLen, prefixLen, nonce, flags) ?: return null
+            } else {
+                val epochKey = epochKey(epoch) ?: return null
+                // Only a packet that authenticates under the epoch's key moves the window.
+                val keys = Keys(epochKey)
+                val opened = open(keys, packetBytes, payloadLen, prefixLen, nonce, flags) ?: return null
+                // A late packet of the epoch before leaves the chain where it is.
+                val current = chain
+                if (current == null || epoch > current.first || epoch + 1 < current.first) {
+                    chain = epoch to epochKey
+                }
+                val newest = chain?.first ?: epoch
+                epochKeys.keys.removeAll { it > newest || it + 1 < newest }
+                epochKeys[epoch] = keys
+                opened
+            }
+        }
+
+        val out = ByteArray(AudioPacket.HEADER_SIZE + plaintext.size)
+        System.arraycopy(packetBytes, 0, out, 0, AudioPacket.HEADER_SIZE)
+        val cipherFlags = AudioPacket.FLAG_ENCRYPTED or AudioPacket.FLAG_CHACHA20 or
+            AudioPacket.FLAG_AUTHENTICATED or AudioPacket.FLAG_KEY_EPOCH
+        out[7] = (flags and cipherFlags.inv()).toByte()
+        out[26] = plaintext.size.toByte()
+        out[27] = (plaintext.size shr 8).toByte()
+        System.arraycopy(plaintext, 0, out, AudioPacket.HEADER_SIZE, plaintext.size)
+        return out
+    }
+
+    private fun open(
+        keys: Keys,
+        packetBytes: ByteArray,
+        payloadLen: Int,
+        prefixLen: Int,
+        nonce: ByteArray,
+        flags: Int
+    ): ByteArray? {
+        if (flags and AudioPacket.FLAG_AUTHENTICATED != 0) {
+            return verifyHmac(keys.hmac, packetBytes, payloadLen, prefixLen, nonce)
+        }
+        return try {
+            val cipher = if (flags and AudioPacket.FLAG_CHACHA20 != 0) {
+                val chacha = chachaCipher() ?: return null
+                chacha.init(Cipher.DECRYPT_MODE, keys.chacha, IvParameterSpec(nonce))
+                chacha
+            } else {
+                aes.init(Cipher.DECRYPT_MODE, keys.aes, GCMParameterSpec(TAG_BITS, nonce))
+                aes
+            }
+            cipher.updateAAD(packetBytes, 0, AudioPacket.HEADER_SIZE)
+            cipher.doFinal(packetBytes, AudioPacket.HEADER_SIZE + prefixLen, payloadLen - prefixLen)
+        } catch (_: GeneralSecurityException) {
+            null
+        }
+    }
+
+    /** The readable payload, or null if the truncated HMAC does not match. */
+    private fun verifyHmac(
+        hmac: Mac,
+        packetBytes: ByteArray,
+        payloadLen: Int,
+        prefixLen: Int,
+        nonce: ByteArray
+    ): ByteArray? {
+        val start = AudioPacket.HEADER_SIZE + prefixLen
+        val dataLen = payloadLen - prefixLen - TAG_BITS / 8
+        hmac.update(nonce)
+        hmac.update(packetBytes, 0, AudioPacket.HEADER_SIZE)
+        hmac.update(packetBytes, start, dataLen)
+        val expected = hmac.doFinal().copyOf(TAG_BITS / 8)
+        val tag = packetBytes.copyOfRange(start + dataLen, start + dataLen + TAG_BITS / 8)
+        if (!MessageDigest.isEqual(expected, tag)) return null
+        return packetBytes.copyOfRange(start, start + dataLen)
+    }
+
+    private fun readEpoch(packetBytes: ByteArray): Long {
+        val at = AudioPacket.HEADER_SIZE + SESSION_ID_LEN
+        var epoch = 0L
+        for (i in 0 until EPOCH_LEN) {
+            epoch = epoch or ((packetBytes[at + i].toLong() and 0xFF) shl (8 * i))
+        }
+        return epoch
+    }
+
+    /**
+     * Later epochs ratchet on from the newest key. Earlier ones only come from a sender that
+     * started over, at a low epoch, and ratchet from the pre-shared key again.
+     */
+    private fun epochKey(epoch: Long): ByteArray? {
+        val current = chain
+        var (from, start) = when {
+            current != null && epoch > current.first -> {
+                if (epoch - current.first > MAX_EPOCH_SKIP) return null
+                current
+            }
+            current != null && epoch >= MAX_EPOCH_SKIP -> return null
+            epoch >= MAX_EPOCH_JOIN -> return null
+            else -> 0L to ratchet(key)
+        }
+        while (from < epoch) {
+            start = ratchet(start)
+            from++
+        }
+        return start
+    }
+
+    /** HKDF-SHA256 of [key] with no salt and one 32-byte block of output. */
+    private fun ratchet(key: ByteArray): ByteArray {
+        val extract = Mac.getInstance("HmacSHA256")
+        extract.init(SecretKeySpec(ByteArray(KEY_LEN), "HmacSHA256"))
+        val prk = extract.doFinal(key)
+        val expand = Mac.getInstance("HmacSHA256")
+        expand.init(SecretKeySpec(prk, "HmacSHA256"))
+        expand.update("audio-link key ratchet".toByteArray(Charsets.US_ASCII))
+        expand.update(1.toByte())
+        return expand.doFinal()
+    }
+
+    private class Keys(key: ByteArray) {
+        val aes = SecretKeySpec(key, "AES")
+        val chacha = SecretKeySpec(key, "ChaCha20")
+        val hmac: Mac = Mac.getInstance("HmacSHA256").apply { init(SecretKeySpec(key, "HmacSHA256")) }
+    }
+
+    /** Android ships ChaCha20-Poly1305 from API 28; older phones need `--cipher aes-256-gcm`. */
+    private fun chachaCipher(): Cipher? {
+        if (chacha == null && !chachaUnavailable) {
+            try {
+                chacha = Cipher.getInstance("ChaCha20-Poly1305")
+            } catch (_: GeneralSecurityException) {
+                chachaUnavailable = true
+                Log.w(TAG, "ChaCha20-Poly1305 is not available; send with --cipher aes-256-gcm")
+            }
+        }
+        return chacha
+    }
+
+    companion object {
+        private const val TAG = "PacketCipher"
+        private const val KEY_LEN = 32
+        private const val SESSION_ID_LEN = 8
+        private const val EPOCH_LEN = 4
+        private const val TAG_BITS = 128
+        /** Epochs ratcheted forward for one packet once a stream is followed. */
+        private const val MAX_EPOCH_SKIP = 64L
+        /** Epochs ratcheted through from the pre-shared key to join a stream. */
+        private const val MAX_EPOCH_JOIN = 1L shl 16
+
+        /** A 32-byte key from 64 hex digits or base64, as accepted by the sender's `--key`. */
+        fun parseKey(text: String): ByteArray? {
+            val trimmed = text.trim()
+            val isHex = trimmed.all { it in '0'..'9' || it.lowercaseChar() in 'a'..'f' }
+            val bytes = if (trimmed.length == 2 * KEY_LEN && isHex) {
+                ByteArray(KEY_LEN) { i -> trimmed.substring(2 * i, 2 * i + 2).toInt(16).toByte() }
+            } else {
+                try {
+                    Base64.decode(trimmed, Base64.NO_WRAP)
+                } catch (_: IllegalArgumentException) {
+                    return null
+                }
+            }
+            return bytes.takeIf { it.size == KEY_LEN }
+        }
+    }
+}
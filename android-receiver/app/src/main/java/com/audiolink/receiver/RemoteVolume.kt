@@ -0,0 +1,59 @@
+package com.audiolink.receiver
+
+import java.nio.ByteBuffer
+import java.nio.ByteOrder
+
+/**
+ * Gain and mute asked of a sender started with `--remote-volume`, so turning the phone down
+ * attenuates before encoding and quiet listening keeps the codec's whole range. Each of the
+ * [STEPS] volume steps is [STEP_DB] dB; step 0 mutes.
+ */
+class RemoteVolume {
+    @Volatile
+    var step: Int = STEPS
+        private set
+
+    /**
+     * Counts up with every change. Starting from the clock keeps a restarted app from
+     * looking like late copies of what it sent before.
+     */
+    @Volatile
+    var seq: Int = (System.currentTimeMillis() / 100).toInt()
+        private set
+
+    /** Moves to [value], clamped to the steps; false when that changes nothing. */
+    @Synchronized
+    fun setStep(value: Int): Boolean {
+        val clamped = value.coerceIn(0, STEPS)
+        if (clamped == step) return false
+        step = clamped
+        seq++
+        return true
+    }
+
+    /**
+     * Magic, version, muted flag, gain in hundredths of a dB and the sequence number.
+     * Sending the same request again is harmless; the sender applies each seq once.
+     */
+    @Synchronized
+    fun toBytes(): ByteArray {
+        val muted = step == 0
+        val gainDb = if (muted) -STEPS * STEP_DB else (step - STEPS) * STEP_DB
+        val bb = ByteBuffer.allocate(SIZE).order(ByteOrder.LITTLE_ENDIAN)
+        bb.put(MAGIC)
+        bb.put(VERSION.toByte())
+        bb.put((if (muted) FLAG_MUTED else 0).toByte())
+        bb.putShort((gainDb * 100).toShort())
+        bb.putInt(seq)
+        return bb.array()
+    }
+
+    companion object {
+        private val MAGIC = byteArrayOf('A'.code.toByte(), 'U'.code.toByte(), 'D'.code.toByte(), 'V'.code.toByte())
+        private const val VERSION: Int = 1
+        private const val FLAG_MUTED: Int = 1
+        private const val SIZE = 12
+        const val STEPS = 30
+        const val STEP_DB = 2
+    }
+}
@@ -0,0 +1,65 @@
+package com.audiolink.receiver
+
+import java.nio.ByteBuffer
+import java.nio.ByteOrder
+
+/**
+ * Rejects sealed packets that were already accepted once, so a recorded packet cannot be
+ * played again. Mirrors ReplayWindow in the sender crate: a sliding window over the sequence
+ * numbers of the current session id, and a new session id only takes over when its
+ * authenticated send time is newer. Not thread-safe; use one instance per receive loop.
+ */
+class ReplayWindow {
+    private val seen = LongArray(WINDOW / 64)
+    private var sessionId: ByteArray? = null
+    private var sendTimeUs = 0L
+    private var highest = 0L
+
+    /** Records a packet that [PacketCipher.open] accepted and returns whether it is new. */
+    fun accept(packetBytes: ByteArray): Boolean {
+        val bb = ByteBuffer.wrap(packetBytes).order(ByteOrder.LITTLE_ENDIAN)
+        val seq = bb.getInt(12).toLong() and 0xFFFF_FFFFL
+        val time = bb.getLong(16)
+        val start = AudioPacket.HEADER_SIZE
+        val id = packetBytes.copyOfRange(start, start + SESSION_ID_LEN)
+        val current = sessionId
+        if (current != null && current.contentEquals(id)) {
+            if (!acceptSeq(seq)) return false
+            sendTimeUs = maxOf(sendTimeUs, time)
+            return true
+        }
+        if (current != null && time <= sendTimeUs) return false
+        sessionId = id
+        sendTimeUs = time
+        highest = seq
+        seen.fill(0)
+        acceptSeq(seq)
+        return true
+    }
+
+    private fun acceptSeq(seq: Long): Boolean {
+        if (seq > highest) {
+            if (seq - highest >= WINDOW) {
+                seen.fill(0)
+            } else {
+                for (newer in highest + 1..seq) {
+                    val slot = (newer % WINDOW).toInt()
+                    seen[slot / 64] = seen[slot / 64] and (1L shl (slot % 64)).inv()
+                }
+            }
+            highest = seq
+        } else if (highest - seq >= WINDOW) {
+            return false
+        }
+        val slot = (seq % WINDOW).toInt()
+        val bit = 1L shl (slot % 64)
+        if (seen[slot / 64] and bit != 0L) return false
+        seen[slot / 64] = seen[slot / 64] or bit
+        return true
+    }
+
+    companion object {
+        private const val WINDOW = 1024
+        private const val SESSION_ID_LEN = 8
+    }
+}
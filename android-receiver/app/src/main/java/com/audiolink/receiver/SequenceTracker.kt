@@ -0,0 +1,77 @@
+package com.audiolink.receiver
+
+/**
+ * Counts loss, reordering and duplicates from packet sequence numbers, RTP style.
+ * Mirrors SequenceTracker in the sender crate; the counts go back in [FeedbackReport].
+ */
+class SequenceTracker {
+    private val seen = LongArray(WINDOW)
+    private var highest: Long = -1
+    private var expected: Long = 0
+    private var unique: Long = 0
+    private var lostBefore: Long = 0
+
+    var received: Long = 0
+        private set
+    var reordered: Long = 0
+        private set
+    var duplicates: Long = 0
+        private set
+    val lost: Long
+        get() = lostBefore + max0(expected - unique)
+
+    fun record(seq: Long) {
+        received++
+        val slot = (seq % WINDOW).toInt()
+        if (highest < 0) {
+            restart(seq)
+            return
+        }
+        val ahead = (seq - highest) and SEQ_MASK
+        val behind = (highest - seq) and SEQ_MASK
+        if (ahead > MAX_JUMP && behind >= WINDOW) {
+            lostBefore += max0(expected - unique)
+            restart(seq)
+            return
+        }
+        if (seen[slot] == seq + 1) {
+            duplicates++
+            return
+        }
+        seen[slot] = seq + 1
+        unique++
+        if (ahead != 0L && ahead <= MAX_JUMP) {
+            expected += ahead
+            highest = seq
+        } else {
+            reordered++
+        }
+    }
+
+    fun reset() {
+        seen.fill(0)
+        highest = -1
+        expected = 0
+        unique = 0
+        lostBefore = 0
+        received = 0
+        reordered = 0
+        duplicates = 0
+    }
+
+    private fun restart(seq: Long) {
+        seen.fill(0)
+        seen[(seq % WINDOW).toInt()] = seq + 1
+        highest = seq
+        expected = 1
+        unique = 1
+    }
+
+    private fun max0(value: Long): Long = if (value > 0) value else 0
+
+    companion object {
+        private const val WINDOW = 1024
+        private const val MAX_JUMP = 1L shl 15
+        private const val SEQ_MASK = 0xFFFF_FFFFL
+    }
+}
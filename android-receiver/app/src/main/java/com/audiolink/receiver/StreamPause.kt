@@ -0,0 +1,21 @@
+package com.audiolink.receiver
+
+import java.nio.ByteBuffer
+import java.nio.ByteOrder
+
+/** Notice that the sender paused the stream, repeated until the audio resumes. */
+object StreamPause {
+    private val MAGIC = byteArrayOf('A'.code.toByte(), 'U'.code.toByte(), 'D'.code.toByte(), 'P'.code.toByte())
+    private const val VERSION: Int = 1
+    private const val SIZE = 12
+
+    fun isPause(data: ByteArray, len: Int): Boolean =
+        len >= 4 && (0 until 4).all { data[it] == MAGIC[it] }
+
+    /** Magic, version, three reserved bytes, then the media time the audio stopped at. */
+    fun parseMediaTime(data: ByteArray, len: Int): Long? {
+        if (len < SIZE || !isPause(data, len)) return null
+        if (data[4].toInt() and 0xFF != VERSION) return null
+        return ByteBuffer.wrap(data, 8, 4).order(ByteOrder.LITTLE_ENDIAN).int.toLong() and 0xFFFFFFFFL
+    }
+}
@@ -0,0 +1,21 @@
+package com.audiolink.receiver
+
+import java.nio.ByteBuffer
+import java.nio.ByteOrder
+
+/** Notice of a deferred start, repeated by the sender until the audio begins. */
+object StreamStart {
+    private val MAGIC = byteArrayOf('A'.code.toByte(), 'U'.code.toByte(), 'D'.code.toByte(), 'S'.code.toByte())
+    private const val VERSION: Int = 1
+    private const val SIZE = 16
+
+    fun isStart(data: ByteArray, len: Int): Boolean =
+        len >= 4 && (0 until 4).all { data[it] == MAGIC[it] }
+
+    /** Magic, version, three reserved bytes, then the start time on the sender clock. */
+    fun parseStartUs(data: ByteArray, len: Int): Long? {
+        if (len < SIZE || !isStart(data, len)) return null
+        if (data[4].toInt() and 0xFF != VERSION) return null
+        return ByteBuffer.wrap(data, 8, 8).order(ByteOrder.LITTLE_ENDIAN).long
+    }
+}
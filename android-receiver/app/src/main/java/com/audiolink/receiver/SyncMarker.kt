@@ -0,0 +1,27 @@
+package com.audiolink.receiver
+
+import java.nio.ByteBuffer
+import java.nio.ByteOrder
+
+/** A named position the sender placed in the stream, on the clock of [AudioPacket.mediaTime]. */
+data class SyncMarker(val name: String, val mediaTime: Long, val sendTimeUs: Long) {
+    companion object {
+        private val MAGIC = byteArrayOf('A'.code.toByte(), 'U'.code.toByte(), 'D'.code.toByte(), 'M'.code.toByte())
+        private const val VERSION: Int = 1
+        private const val HEADER_SIZE = 20
+
+        fun isMarker(data: ByteArray, len: Int): Boolean =
+            len >= 4 && (0 until 4).all { data[it] == MAGIC[it] }
+
+        /** Magic, version, three reserved bytes, send time, media time, then the UTF-8 name. */
+        fun parse(data: ByteArray, len: Int): SyncMarker? {
+            if (len < HEADER_SIZE || !isMarker(data, len)) return null
+            if (data[4].toInt() and 0xFF != VERSION) return null
+            val bb = ByteBuffer.wrap(data, 8, HEADER_SIZE - 8).order(ByteOrder.LITTLE_ENDIAN)
+            val sendTimeUs = bb.long
+            val mediaTime = bb.int.toLong() and 0xFFFF_FFFFL
+            val name = String(data, HEADER_SIZE, len - HEADER_SIZE, Charsets.UTF_8)
+            return SyncMarker(name, mediaTime, sendTimeUs)
+        }
+    }
+}
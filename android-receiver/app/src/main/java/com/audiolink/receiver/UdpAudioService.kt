@@ -1,25 +1,36 @@
 package com.audiolink.receiver
 
+import android.annotation.SuppressLint
 import android.app.Notification
 import android.app.NotificationChannel
 import android.app.NotificationManager
 import android.app.Service
+import android.bluetooth.BluetoothManager
+import android.bluetooth.BluetoothServerSocket
+import android.bluetooth.BluetoothSocket
 import android.content.Intent
 import android.media.AudioAttributes
 import android.media.AudioFormat
 import android.media.AudioManager
+import android.media.AudioTimestamp
 import android.media.AudioTrack
+import android.media.VolumeProvider
+import android.media.session.MediaSession
+import android.media.session.PlaybackState
 import android.os.Build
 import android.os.IBinder
 import android.util.Log
 import androidx.core.app.NotificationCompat
 import java.io.BufferedInputStream
+import java.io.OutputStream
 import java.net.DatagramPacket
 import java.net.DatagramSocket
 import java.net.ServerSocket
 import java.net.Socket
+import java.net.SocketAddress
 import java.net.SocketTimeoutException
 import java.util.Locale
+import java.util.UUID
 import java.util.concurrent.atomic.AtomicLong
 import kotlin.concurrent.thread
 import kotlin.math.abs
@@ -30,6 +41,8 @@ class UdpAudioService : Service() {
     private var udpSocket: DatagramSocket? = null
     private var tcpServerSocket: ServerSocket? = null
     private var tcpClientSocket: Socket? = null
+    private var btServerSocket: BluetoothServerSocket? = null
+    private var btClientSocket: BluetoothSocket? = null
     private var receiverThread: Thread? = null
     private var playerThread: Thread? = null
     private var statsThread: Thread? = null
@@ -38,6 +51,10 @@ class UdpAudioService : Service() {
     private var expectedFrameSamples: Int = 0
     private var silenceFrame: ShortArray = shortArrayOf()
     private var frameMs: Int = 5
+    private var frameUs: Long = 5_000
+    private var sampleRate: Int = 0
+    private var channels: Int = 1
+    private var framesWritten: Long = 0
     private var adaptiveBaseTargetFrames: Int = 0
     private var adaptiveTargetFrames: Int = 0
     private var adaptiveMinTargetFrames: Int = 2
@@ -48,6 +65,14 @@ class UdpAudioService : Service() {
     private var adaptiveZeroBufferStreak: Int = 0
     private var adaptiveCooldownSec: Int = 0
     private var adaptiveLastReason: String = "init"
+    private var packetCipher: PacketCipher? = null
+    private var replayWindow = ReplayWindow()
+    private var accessFrame: ByteArray? = null
+    private var remoteVolume: RemoteVolume? = null
+    private var mediaSession: MediaSession? = null
+    private var announcedStartUs = 0L
+    private var streamPaused = false
+    private var recvBufferBytes = DEFAULT_RECV_BUFFER_KB * 1024
 
     @Volatile
     private var running = false
@@ -56,6 +81,8 @@ class UdpAudioService : Service() {
     private val rxBytes = AtomicLong(0)
     private val parseErrors = AtomicLong(0)
     private val payloadMismatch = AtomicLong(0)
+    private val authFailures = AtomicLong(0)
+    private val replays = AtomicLong(0)
     private val playoutUnderruns = AtomicLong(0)
     private val netDelayUsSum = AtomicLong(0)
     private val netDelaySamples = AtomicLong(0)
@@ -67,6 +94,19 @@ class UdpAudioService : Service() {
     private val lastNetAgeUs = AtomicLong(-1)
     private val decodeUsSum = AtomicLong(0)
     private val decodeSamples = AtomicLong(0)
+    private val probeBytes = AtomicLong(0)
+
+    private val feedbackLock = Any()
+    private var lastPacketSendUs = 0L
+    private var lastPacketRecvUs = 0L
+    private var lastFrameMediaTime = -1L
+    private var lastFrameArrivalNs = 0L
+    private var playedFirstSeq = -1L
+    private var playedLastSeq = -1L
+    private var playoutTimeUs = 0L
+    private var interarrivalJitterUs = 0.0
+    private val sequenceTracker = SequenceTracker()
+    private val clockSync = ClockSync()
 
     override fun onBind(intent: Intent?): IBinder? = null
 
@@ -76,7 +116,12 @@ class UdpAudioService : Service() {
                 val port = intent.getIntExtra(EXTRA_PORT, 50000)
                 val jitterMs = intent.getIntExtra(EXTRA_JITTER_MS, 20)
                 val transport = normalizeTransport(intent.getStringExtra(EXTRA_TRANSPORT))
-                startStreaming(port, jitterMs, transport)
+                val key = intent.getStringExtra(EXTRA_KEY)?.takeIf { it.isNotBlank() }
+                val accessToken = intent.getStringExtra(EXTRA_ACCESS_TOKEN)?.takeIf { it.isNotBlank() }
+                val volumeAtSender = intent.getBooleanExtra(EXTRA_REMOTE_VOLUME, false)
+                recvBufferBytes =
+                    intent.getIntExtra(EXTRA_RECV_BUFFER_KB, DEFAULT_RECV_BUFFER_KB).coerceAtLeast(1) * 1024
+                startStreaming(port, jitterMs, transport, key, accessToken, volumeAtSender)
             }
 
             ACTION_STOP -> stopStreaming()
@@ -89,20 +134,42 @@ class UdpAudioService : Service() {
         super.onDestroy()
     }
 
-    private fun startStreaming(port: Int, jitterMs: Int, transport: String) {
+    private fun startStreaming(
+        port: Int,
+        jitterMs: Int,
+        transport: String,
+        key: String?,
+        accessToken: String?,
+        volumeAtSender: Boolean
+    ) {
         if (running) return
 
+        packetCipher = key?.let { text ->
+            val bytes = PacketCipher.parseKey(text)
+            if (bytes == null) {
+                Log.w(TAG, "ignoring invalid key; expected 64 hex digits or base64 of 32 bytes")
+            }
+            bytes?.let { PacketCipher(it) }
+        }
+        replayWindow = ReplayWindow()
+        accessFrame = accessToken?.let { FeedbackReport.accessFrame(it) }
+        if (volumeAtSender) {
+            startVolumeSession(RemoteVolume().also { remoteVolume = it })
+        }
+
         resetStats()
         createNotificationChannel()
-        startForeground(
-            NOTIF_ID,
-            buildNotification("Listening on ${transport.uppercase(Locale.US)} $port")
-        )
+        val listening = when (transport) {
+            TRANSPORT_BLUETOOTH -> "Listening on Bluetooth"
+            else -> "Listening on ${transport.uppercase(Locale.US)} $port"
+        }
+        startForeground(NOTIF_ID, buildNotification(listening))
 
         running = true
         receiverThread = thread(name = "udp-receiver", isDaemon = true) {
             when (transport) {
                 TRANSPORT_TCP -> receiveTcpLoop(port, jitterMs)
+                TRANSPORT_BLUETOOTH -> receiveBluetoothLoop(jitterMs)
                 else -> receiveUdpLoop(port, jitterMs)
             }
         }
@@ -119,6 +186,13 @@ class UdpAudioService : Service() {
         tcpClientSocket = null
         tcpServerSocket?.close()
         tcpServerSocket = null
+        btClientSocket?.close()
+        btClientSocket = null
+        btServerSocket?.close()
+        btServerSocket = null
+        mediaSession?.release()
+        mediaSession = null
+        remoteVolume = null
 
         receiverThread?.interrupt()
         receiverThread = null
@@ -137,6 +211,10 @@ class UdpAudioService : Service() {
         expectedFrameSamples = 0
         silenceFrame = shortArrayOf()
         frameMs = 5
+        sampleRate = 0
+        channels = 1
+        framesWritten = 0
+        resetFeedback()
         resetAdaptiveController()
 
         stopForeground(STOP_FOREGROUND_REMOVE)
@@ -148,20 +226,44 @@ class UdpAudioService : Service() {
             DatagramSocket(port).use { sock ->
                 udpSocket = sock
                 sock.soTimeout = 500
-                sock.receiveBufferSize = 256 * 1024
+                sock.receiveBufferSize = recvBufferBytes
+                Log.i(TAG, "UDP receive buffer ${sock.receiveBufferSize} bytes")
                 val packetBuf = ByteArray(8192)
+                var senderAddress: SocketAddress? = null
+                var nextFeedbackMs = System.currentTimeMillis() + FeedbackReport.INTERVAL_MS
 
                 while (running) {
                     try {
                         val datagram = DatagramPacket(packetBuf, packetBuf.size)
                         sock.receive(datagram)
-                        processPacketBytes(datagram.data, datagram.length, jitterMs, 0)
+                        if (processPacketBytes(datagram.data, datagram.length, jitterMs, 0)) {
+                            senderAddress = datagram.socketAddress
+                        }
                     } catch (_: SocketTimeoutException) {
                         // Keep service alive while waiting for packets.
                     } catch (e: Exception) {
                         Log.e(TAG, "receive error", e)
                         parseErrors.incrementAndGet()
                     }
+
+                    val nowMs = System.currentTimeMillis()
+                    if (nowMs >= nextFeedbackMs) {
+                        nextFeedbackMs = nowMs + FeedbackReport.INTERVAL_MS
+                        val target = senderAddress
+                        val report = takeFeedback()
+                        if (target != null && report != null) {
+                            try {
+                                // Reports can get lost, so the token goes along with each one.
+                                accessFrame?.let { sock.send(DatagramPacket(it, it.size, target)) }
+                                sock.send(DatagramPacket(report, report.size, target))
+                                val ping = ClockSync.ping(System.currentTimeMillis() * 1000L)
+                                sock.send(DatagramPacket(ping, ping.size, target))
+                                remoteVolume?.toBytes()?.let { sock.send(DatagramPacket(it, it.size, target)) }
+                            } catch (e: Exception) {
+                                Log.w(TAG, "feedback send failed", e)
+                            }
+                        }
+                    }
                 }
             }
         } catch (e: Exception) {
@@ -177,6 +279,8 @@ class UdpAudioService : Service() {
             ServerSocket(port).use { server ->
                 tcpServerSocket = server
                 server.reuseAddress = true
+                // Accepted connections inherit it.
+                server.receiveBufferSize = recvBufferBytes
                 server.soTimeout = 800
                 Log.i(TAG, "TCP receiver listening on $port")
 
@@ -191,7 +295,11 @@ class UdpAudioService : Service() {
                             TAG,
                             "TCP sender connected from ${client.inetAddress?.hostAddress}:${client.port}"
                         )
-                        readTcpClientLoop(client, jitterMs)
+                        readFramedLoop(
+                            BufferedInputStream(client.getInputStream()),
+                            client.getOutputStream(),
+                            jitterMs
+                        )
                     } catch (_: SocketTimeoutException) {
                         // Keep service alive while waiting for a sender.
                     } catch (e: Exception) {
@@ -218,10 +326,69 @@ class UdpAudioService : Service() {
         }
     }
 
-    private fun readTcpClientLoop(client: Socket, jitterMs: Int) {
-        val input = BufferedInputStream(client.getInputStream())
+    /**
+     * Listens for the sender's `--transport bluetooth` on an RFCOMM channel registered under
+     * [BT_SERVICE_UUID], which the sender looks up over SDP. The framing is TCP's.
+     */
+    @SuppressLint("MissingPermission")
+    private fun receiveBluetoothLoop(jitterMs: Int) {
+        try {
+            val adapter = getSystemService(BluetoothManager::class.java)?.adapter
+            if (adapter == null || !adapter.isEnabled) {
+                Log.e(TAG, "Bluetooth is off or missing on this device")
+                return
+            }
+            adapter.listenUsingRfcommWithServiceRecord(BT_SERVICE_NAME, BT_SERVICE_UUID).use { server ->
+                btServerSocket = server
+                Log.i(TAG, "Bluetooth receiver listening as $BT_SERVICE_NAME")
+
+                while (running) {
+                    var client: BluetoothSocket? = null
+                    try {
+                        // Blocks until a sender connects or stopStreaming closes the socket.
+                        client = server.accept()
+                        btClientSocket = client
+                        Log.i(TAG, "Bluetooth sender connected from ${client.remoteDevice?.address}")
+                        readFramedLoop(
+                            BufferedInputStream(client.inputStream),
+                            client.outputStream,
+                            jitterMs
+                        )
+                    } catch (e: Exception) {
+                        if (running) {
+                            Log.e(TAG, "bluetooth accept/read error", e)
+                            parseErrors.incrementAndGet()
+                        }
+                    } finally {
+                        try {
+                            client?.close()
+                        } catch (_: Exception) {
+                        }
+                        btClientSocket = null
+                    }
+                }
+            }
+        } catch (e: Exception) {
+            if (running) {
+                Log.e(TAG, "bluetooth receiver loop failed", e)
+            }
+        } finally {
+            btServerSocket = null
+            stopStreaming()
+        }
+    }
+
+    /** Reads length-prefixed packets and writes reports back, over TCP or RFCOMM. */
+    private fun readFramedLoop(input: BufferedInputStream, output: OutputStream, jitterMs: Int) {
         val lenBuf = ByteArray(2)
         var packetBuf = ByteArray(8192)
+        var nextFeedbackMs = System.currentTimeMillis() + FeedbackReport.INTERVAL_MS
+        var sentVolumeSeq: Int? = null
+        accessFrame?.let { frame ->
+            output.write(byteArrayOf(frame.size.toByte(), (frame.size shr 8).toByte()))
+            output.write(frame)
+            output.flush()
+        }
 
         while (running) {
             if (!readFully(input, lenBuf, 2)) {
@@ -242,6 +409,21 @@ class UdpAudioService : Service() {
             }
 
             processPacketBytes(packetBuf, packetLen, jitterMs, 2)
+
+            val nowMs = System.currentTimeMillis()
+            if (nowMs >= nextFeedbackMs) {
+                nextFeedbackMs = nowMs + FeedbackReport.INTERVAL_MS
+                val report = takeFeedback() ?: continue
+                val ping = ClockSync.ping(System.currentTimeMillis() * 1000L)
+                // Nothing gets lost on a stream, so the volume only goes when it changed.
+                val volume = remoteVolume?.takeIf { it.seq != sentVolumeSeq }
+                sentVolumeSeq = volume?.seq ?: sentVolumeSeq
+                for (frame in listOfNotNull(report, ping, volume?.toBytes())) {
+                    output.write(byteArrayOf(frame.size.toByte(), (frame.size shr 8).toByte()))
+                    output.write(frame)
+                }
+                output.flush()
+            }
         }
     }
 
@@ -271,29 +453,94 @@ class UdpAudioService : Service() {
         packetLen: Int,
         jitterMs: Int,
         wireOverheadBytes: Int
-    ) {
+    ): Boolean {
+        if (ClockSync.isClock(data, packetLen)) {
+            clockSync.handleReply(data, packetLen, System.currentTimeMillis() * 1000L)
+            return false
+        }
+        if (StreamStart.isStart(data, packetLen)) {
+            val startUs = StreamStart.parseStartUs(data, packetLen) ?: return false
+            if (startUs != announcedStartUs) {
+                announcedStartUs = startUs
+                val nowUs = System.currentTimeMillis() * 1000L
+                val inUs = startUs - (clockSync.offsetUs(nowUs) ?: 0L) - nowUs
+                Log.i(TAG, "stream starts in %.1fs".format(inUs / 1_000_000.0))
+            }
+            return false
+        }
+        if (StreamPause.isPause(data, packetLen)) {
+            val mediaTime = StreamPause.parseMediaTime(data, packetLen) ?: return false
+            if (!streamPaused) {
+                streamPaused = true
+                Log.i(TAG, "stream paused at media time $mediaTime")
+            }
+            jitterBuffer?.pause()
+            return false
+        }
+        if (SyncMarker.isMarker(data, packetLen)) {
+            SyncMarker.parse(data, packetLen)?.let {
+                Log.i(TAG, "sync marker ${it.name} at media time ${it.mediaTime}")
+            }
+            return false
+        }
         rxPackets.incrementAndGet()
         rxBytes.addAndGet((packetLen + wireOverheadBytes).toLong())
 
         val parseStartNs = System.nanoTime()
-        val packet = AudioPacket.parse(data, packetLen)
+        var bytes = data
+        var len = packetLen
+        val flags = if (packetLen >= AudioPacket.HEADER_SIZE) data[7].toInt() and 0xFF else 0
+        // Probes carry only padding and are never encrypted.
+        if (flags and AudioPacket.FLAG_PROBE == 0) {
+            val cipher = packetCipher
+            val sealedFlags = AudioPacket.FLAG_ENCRYPTED or AudioPacket.FLAG_AUTHENTICATED
+            val encrypted = flags and sealedFlags != 0
+            if (encrypted || cipher != null) {
+                val opened = if (encrypted) cipher?.open(data, packetLen) else null
+                if (opened == null) {
+                    authFailures.incrementAndGet()
+                    return false
+                }
+                if (!replayWindow.accept(data)) {
+                    replays.incrementAndGet()
+                    return false
+                }
+                bytes = opened
+                len = opened.size
+            }
+        }
+        val packet = AudioPacket.parse(bytes, len)
         val parseUs = (System.nanoTime() - parseStartNs) / 1000L
         if (packet == null) {
             parseErrors.incrementAndGet()
-            return
+            return false
         }
         decodeUsSum.addAndGet(parseUs)
         decodeSamples.incrementAndGet()
+        if (packet.flags and AudioPacket.FLAG_PROBE != 0) {
+            probeBytes.addAndGet(packetLen.toLong())
+            recordPacket(packet, probe = true)
+            return true
+        }
+        if (packet.flags and AudioPacket.FLAG_DISCONTINUITY != 0) {
+            Log.i(TAG, "gap in the stream before seq ${packet.seq}: dropped late audio, a pause or a switch of source")
+        }
+        streamPaused = false
         updateEstimatedNetDelay(packet.sendTimeUs)
+        synchronized(feedbackLock) {
+            sequenceTracker.record(packet.seq)
+        }
         if (audioTrack == null) {
             initAudio(packet, jitterMs)
         }
 
         if (packet.payload.size == expectedFrameSamples) {
-            jitterBuffer?.push(packet.seq, packet.payload)
-        } else {
-            payloadMismatch.incrementAndGet()
+            jitterBuffer?.push(packet.seq, packet.sendTimeUs, packet.payload)
+            recordPacket(packet, probe = false)
+            return true
         }
+        payloadMismatch.incrementAndGet()
+        return false
     }
 
     private fun initAudio(packet: AudioPacket, jitterMs: Int) {
@@ -307,8 +554,12 @@ class UdpAudioService : Service() {
         if (expectedFrameSamples <= 0) return
         silenceFrame = ShortArray(expectedFrameSamples)
 
+        sampleRate = packet.sampleRate
+        channels = packet.channels
+        framesWritten = 0
         frameMs = max(1, (packet.samplesPerChannel * 1000) / packet.sampleRate)
-        val targetFrames = max(2, jitterMs / frameMs)
+        frameUs = max(1L, packet.samplesPerChannel * 1_000_000L / packet.sampleRate)
+        val targetFrames = max(2, (jitterMs * 1000L / frameUs).toInt())
         adaptiveBaseTargetFrames = targetFrames
         adaptiveMinTargetFrames = max(2, targetFrames - 1)
         adaptiveMaxTargetFrames = max(
@@ -366,10 +617,12 @@ class UdpAudioService : Service() {
     private fun playLoop() {
         val popTimeoutMs = max(10, frameMs * 2).toLong()
         while (running) {
-            val frame = jitterBuffer?.pop(popTimeoutMs)
+            val frame = popFrame(popTimeoutMs)
             val safeFrame = if (frame == null) {
                 playoutUnderruns.incrementAndGet()
                 silenceFrame
+            } else if (frame === JitterBuffer.NOT_DUE) {
+                silenceFrame
             } else if (frame.size == expectedFrameSamples) {
                 frame
             } else {
@@ -377,14 +630,122 @@ class UdpAudioService : Service() {
                 silenceFrame
             }
             audioTrack?.write(safeFrame, 0, safeFrame.size, AudioTrack.WRITE_BLOCKING)
+            if (frame != null && frame !== JitterBuffer.NOT_DUE) {
+                recordPlayed(framesWritten)
+            }
+            framesWritten += safeFrame.size / channels
+        }
+    }
+
+    /**
+     * With a multi-room delay from the sender, the frame whose send time plus the delay is
+     * when the next write reaches the output, on the sender clock; otherwise the next frame.
+     */
+    private fun popFrame(timeoutMs: Long): ShortArray? {
+        val buffer = jitterBuffer ?: return null
+        val delayMs = clockSync.playoutDelayMs
+        val offsetUs = clockSync.offsetUs(System.currentTimeMillis() * 1000L)
+        if (delayMs <= 0 || offsetUs == null) return buffer.pop(timeoutMs)
+        buffer.ensureCapacity(adaptiveMaxTargetFrames + 4 + (delayMs * 1000L / frameUs).toInt())
+        val playoutUs = estimatePlayoutUs(framesWritten) + offsetUs - delayMs * 1000L
+        return buffer.popScheduled(timeoutMs, frameUs, playoutUs)
+    }
+
+    private fun recordPacket(packet: AudioPacket, probe: Boolean) {
+        val nowUs = System.currentTimeMillis() * 1000L
+        val nowNs = System.nanoTime()
+        synchronized(feedbackLock) {
+            // Jitter runs on the media clock and a monotonic arrival time, so a stepped wall
+            // clock on either side does not look like network jitter. Probes carry no media.
+            if (!probe && packet.mediaTime != lastFrameMediaTime) {
+                if (lastFrameMediaTime >= 0) {
+                    val mediaFrames = (packet.mediaTime - lastFrameMediaTime).toInt().toLong()
+                    val mediaUs = mediaFrames * 1_000_000L / max(1, packet.sampleRate)
+                    // A jump of more than a few seconds is a restarted stream, not jitter.
+                    if (abs(mediaUs) < MAX_MEDIA_STEP_US) {
+                        val transitChange = (nowNs - lastFrameArrivalNs) / 1000L - mediaUs
+                        interarrivalJitterUs += (abs(transitChange) - interarrivalJitterUs) / 16.0
+                    }
+                }
+                lastFrameMediaTime = packet.mediaTime
+                lastFrameArrivalNs = nowNs
+            }
+            lastPacketSendUs = packet.sendTimeUs
+            lastPacketRecvUs = nowUs
         }
     }
 
+    private fun recordPlayed(framePosition: Long) {
+        val seq = jitterBuffer?.lastPlayedSeq ?: return
+        if (seq < 0) return
+        val playoutUs = estimatePlayoutUs(framePosition)
+        synchronized(feedbackLock) {
+            if (playedLastSeq < 0) {
+                playedFirstSeq = seq
+            }
+            playedLastSeq = seq
+            playoutTimeUs = playoutUs
+        }
+    }
+
+    /** Wall-clock time at which [framePosition] reaches the output, from the track timestamp. */
+    private fun estimatePlayoutUs(framePosition: Long): Long {
+        val nowUs = System.currentTimeMillis() * 1000L
+        val track = audioTrack ?: return nowUs
+        val timestamp = AudioTimestamp()
+        if (sampleRate <= 0 || !track.getTimestamp(timestamp)) return nowUs
+        val presentNs = timestamp.nanoTime +
+            (framePosition - timestamp.framePosition) * 1_000_000_000L / sampleRate
+        return nowUs + (presentNs - System.nanoTime()) / 1000L
+    }
+
+    private fun takeFeedback(): ByteArray? {
+        synchronized(feedbackLock) {
+            if (lastPacketRecvUs == 0L) return null
+            val report = FeedbackReport(
+                playedFirstSeq = playedFirstSeq,
+                playedLastSeq = playedLastSeq,
+                playoutTimeUs = playoutTimeUs,
+                echoSendTimeUs = lastPacketSendUs,
+                echoRecvTimeUs = lastPacketRecvUs,
+                reportTimeUs = System.currentTimeMillis() * 1000L,
+                received = sequenceTracker.received,
+                lost = sequenceTracker.lost,
+                reordered = sequenceTracker.reordered,
+                duplicates = sequenceTracker.duplicates,
+                jitterUs = interarrivalJitterUs.toLong(),
+                probeBytes = probeBytes.get()
+            )
+            playedFirstSeq = -1L
+            playedLastSeq = -1L
+            return report.toBytes()
+        }
+    }
+
+    private fun resetFeedback() {
+        synchronized(feedbackLock) {
+            lastPacketSendUs = 0L
+            lastPacketRecvUs = 0L
+            lastFrameMediaTime = -1L
+            lastFrameArrivalNs = 0L
+            playedFirstSeq = -1L
+            playedLastSeq = -1L
+            playoutTimeUs = 0L
+            interarrivalJitterUs = 0.0
+            sequenceTracker.reset()
+        }
+        announcedStartUs = 0L
+        streamPaused = false
+        clockSync.reset()
+    }
+
     private fun statsLoop() {
         var lastRxPackets = 0L
         var lastRxBytes = 0L
         var lastParseErrors = 0L
         var lastPayloadMismatch = 0L
+        var lastAuthFailures = 0L
+        var lastReplays = 0L
         var lastUnderruns = 0L
         var lastJitter = JitterSnapshot(
             bufferedFrames = 0,
@@ -408,6 +769,8 @@ class UdpAudioService : Service() {
             val currRxBytes = rxBytes.get()
             val currParseErrors = parseErrors.get()
             val currPayloadMismatch = payloadMismatch.get()
+            val currAuthFailures = authFailures.get()
+            val currReplays = replays.get()
             val currUnderruns = playoutUnderruns.get()
             val jitter = jitterBuffer?.snapshot()
 
@@ -415,6 +778,8 @@ class UdpAudioService : Service() {
             val dBytes = currRxBytes - lastRxBytes
             val dParseErrors = currParseErrors - lastParseErrors
             val dPayloadMismatch = currPayloadMismatch - lastPayloadMismatch
+            val dAuthFailures = currAuthFailures - lastAuthFailures
+            val dReplays = currReplays - lastReplays
             val dUnderruns = currUnderruns - lastUnderruns
 
             val kbps = (dBytes * 8.0) / 1000.0
@@ -447,7 +812,7 @@ class UdpAudioService : Service() {
             val overflowDelta = if (jitter != null) jitter.overflowDropped - lastJitter.overflowDropped else 0L
             val bufferedFrames = jitter?.bufferedFrames ?: 0
             val targetFramesNow = jitter?.targetFrames ?: adaptiveTargetFrames
-            val bufferedMs = bufferedFrames * frameMs
+            val bufferedMs = (bufferedFrames * frameUs / 1000L).toInt()
             val (windowScore, adjustInfo, effectiveTargetFrames) = if (jitter != null && adaptiveTargetFrames > 0) {
                 val score = computeWindowScore(
                     underrunDelta = dUnderruns,
@@ -474,7 +839,7 @@ class UdpAudioService : Service() {
                 Triple(100.0, null, targetFramesNow)
             }
             val autoReason = adjustInfo?.reason ?: "hold"
-            val effectiveTargetMs = effectiveTargetFrames * frameMs
+            val effectiveTargetMs = (effectiveTargetFrames * frameUs / 1000L).toInt()
             val netForE2eMs = when {
                 netPerf.avgPathMs >= 0.0 -> netPerf.avgPathMs
                 netPerf.avgAgeMs >= 0.0 -> netPerf.avgAgeMs
@@ -495,7 +860,7 @@ class UdpAudioService : Service() {
                 TAG,
                 String.format(
                     Locale.US,
-                    "stats rx=%d pps %.1f kbps delay=%s ms buffer=%d ms loss=%d late=%d over=%d underrun=%d parseErr=%d payloadErr=%d",
+                    "stats rx=%d pps %.1f kbps delay=%s ms buffer=%d ms loss=%d late=%d over=%d underrun=%d parseErr=%d payloadErr=%d authErr=%d replay=%d",
                     dPackets,
                     kbps,
                     delayText,
@@ -505,7 +870,9 @@ class UdpAudioService : Service() {
                     overflowDelta,
                     dUnderruns,
                     dParseErrors,
-                    dPayloadMismatch
+                    dPayloadMismatch,
+                    dAuthFailures,
+                    dReplays
                 )
             )
             Log.i(
@@ -516,7 +883,7 @@ class UdpAudioService : Service() {
                     effectiveTargetFrames,
                     effectiveTargetMs,
                     adaptiveBaseTargetFrames,
-                    adaptiveBaseTargetFrames * frameMs,
+                    (adaptiveBaseTargetFrames * frameUs / 1000L).toInt(),
                     adaptiveScoreEma,
                     windowScore,
                     autoReason
@@ -535,11 +902,30 @@ class UdpAudioService : Service() {
                     e2eText
                 )
             )
+            val nowUs = System.currentTimeMillis() * 1000L
+            val clockOffsetUs = clockSync.offsetUs(nowUs)
+            if (clockOffsetUs != null) {
+                val drift = clockSync.driftPpb()?.let {
+                    String.format(Locale.US, "%+.1f", it / 1000.0)
+                } ?: "n/a"
+                Log.i(
+                    TAG,
+                    String.format(
+                        Locale.US,
+                        "clock offset=%+.1fms drift=%sppm rtt=%.1fms",
+                        clockOffsetUs / 1000.0,
+                        drift,
+                        (clockSync.rttUs() ?: 0L) / 1000.0
+                    )
+                )
+            }
 
             lastRxPackets = currRxPackets
             lastRxBytes = currRxBytes
             lastParseErrors = currParseErrors
             lastPayloadMismatch = currPayloadMismatch
+            lastAuthFailures = currAuthFailures
+            lastReplays = currReplays
             lastUnderruns = currUnderruns
             if (jitter != null) {
                 lastJitter = jitter
@@ -552,6 +938,8 @@ class UdpAudioService : Service() {
         rxBytes.set(0)
         parseErrors.set(0)
         payloadMismatch.set(0)
+        authFailures.set(0)
+        replays.set(0)
         playoutUnderruns.set(0)
         netDelayUsSum.set(0)
         netDelaySamples.set(0)
@@ -582,7 +970,8 @@ class UdpAudioService : Service() {
     private fun updateEstimatedNetDelay(sendTimeUs: Long) {
         if (sendTimeUs <= 0) return
         val nowUs = System.currentTimeMillis() * 1000L
-        val ageUs = nowUs - sendTimeUs
+        // Until the first clock answer this assumes the sender clock matches ours.
+        val ageUs = nowUs - sendTimeUs + (clockSync.offsetUs(nowUs) ?: 0L)
         if (ageUs in 0L..5_000_000L) {
             netDelayUsSum.addAndGet(ageUs)
             netDelaySamples.incrementAndGet()
@@ -745,9 +1134,33 @@ class UdpAudioService : Service() {
         return AutoAdjustInfo(targetFrames = applied, reason = adaptiveLastReason)
     }
 
+    /**
+     * Takes the volume buttons while streaming: for a media session playing remotely the
+     * system hands them, and its volume slider, to [RemoteVolume] instead of the music stream.
+     */
+    private fun startVolumeSession(volume: RemoteVolume) {
+        val provider = object : VolumeProvider(VOLUME_CONTROL_ABSOLUTE, RemoteVolume.STEPS, volume.step) {
+            override fun onSetVolumeTo(value: Int) {
+                volume.setStep(value)
+                setCurrentVolume(volume.step)
+            }
+
+            override fun onAdjustVolume(direction: Int) {
+                volume.setStep(volume.step + direction)
+                setCurrentVolume(volume.step)
+            }
+        }
+        mediaSession = MediaSession(this, TAG).apply {
+            setPlaybackToRemote(provider)
+            setPlaybackState(PlaybackState.Builder().setState(PlaybackState.STATE_PLAYING, 0, 1f).build())
+            isActive = true
+        }
+    }
+
     private fun normalizeTransport(raw: String?): String {
         return when (raw?.lowercase(Locale.US)) {
             TRANSPORT_TCP -> TRANSPORT_TCP
+            TRANSPORT_BLUETOOTH -> TRANSPORT_BLUETOOTH
             else -> TRANSPORT_UDP
         }
     }
@@ -776,14 +1189,25 @@ class UdpAudioService : Service() {
         private const val TAG = "UdpAudioService"
         private const val CHANNEL_ID = "audio_rx"
         private const val NOTIF_ID = 1001
+        private const val MAX_MEDIA_STEP_US = 5_000_000L
 
         const val ACTION_START = "com.audiolink.receiver.action.START"
         const val ACTION_STOP = "com.audiolink.receiver.action.STOP"
         const val EXTRA_PORT = "extra_port"
         const val EXTRA_JITTER_MS = "extra_jitter_ms"
         const val EXTRA_TRANSPORT = "extra_transport"
+        const val EXTRA_KEY = "extra_key"
+        const val EXTRA_ACCESS_TOKEN = "extra_access_token"
+        const val EXTRA_RECV_BUFFER_KB = "extra_recv_buffer_kb"
+        const val EXTRA_REMOTE_VOLUME = "extra_remote_volume"
+        const val DEFAULT_RECV_BUFFER_KB = 256
         const val TRANSPORT_UDP = "udp"
         const val TRANSPORT_TCP = "tcp"
+        const val TRANSPORT_BLUETOOTH = "bluetooth"
+
+        /** The sender's `bluetooth::SERVICE_UUID`. */
+        val BT_SERVICE_UUID: UUID = UUID.fromString("5b2f9a64-3c1e-4d8a-9f0b-6a7e2c1d4b58")
+        private const val BT_SERVICE_NAME = "AudioLink"
     }
 
     private data class NetworkPerf(
@@ -142,6 +142,8 @@ if (-not $SkipWindows) {
         throw "No se encontro binario sender: $senderExe"
     }
     Copy-Item $senderExe (Join-Path $windowsOutDir "windows-sender.exe") -Force
+    & $senderExe completions powershell |
+        Out-File -Encoding utf8 (Join-Path $windowsOutDir "windows-sender-completion.ps1")
 }
 
 if (-not $SkipAndroid) {
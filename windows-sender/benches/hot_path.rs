@@ -0,0 +1,165 @@
+//! The per-frame work of the send path, at the default 5 ms, 48 kHz stereo frame.
+//!
+//! `cargo bench --features bench`; compare runs with `-- --save-baseline` and
+//! `-- --baseline`.
+
+use std::hint::black_box;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use crossbeam_channel::unbounded;
+use windows_sender::capture::{sample_from_f32, sample_from_u16, CaptureQueue, QueueOverflow};
+use windows_sender::crypto::{Cipher, Key, PacketSealer};
+use windows_sender::protocol::{encode_pcm16, PacketTemplate, CODEC_PCM16, MAX_PACKET};
+use windows_sender::stats::SenderStats;
+
+const SAMPLE_RATE: u32 = 48_000;
+const CHANNELS: u8 = 2;
+const SAMPLES_PER_CHANNEL: u16 = 240;
+const SAMPLES: usize = SAMPLES_PER_CHANNEL as usize * CHANNELS as usize;
+/// Capture queue depth; kept short so filling it up takes little setup.
+const DEPTH: Duration = Duration::from_millis(100);
+
+/// A sine sweep, so nothing is constant enough for the compiler to fold.
+fn frame() -> Vec<i16> {
+    (0..SAMPLES)
+        .map(|i| ((i as f32 * 0.05).sin() * 20_000.0) as i16)
+        .collect()
+}
+
+fn conversion(c: &mut Criterion) {
+    let floats: Vec<f32> = frame().iter().map(|&s| s as f32 / 32_768.0).collect();
+    let unsigned: Vec<u16> = frame()
+        .iter()
+        .map(|&s| (s as i32 + 32_768) as u16)
+        .collect();
+    let mut group = c.benchmark_group("convert");
+    group.throughput(Throughput::Elements(SAMPLES as u64));
+    group.bench_function("f32", |b| {
+        b.iter(|| {
+            black_box(&floats)
+                .iter()
+                .map(|&s| sample_from_f32(s))
+                .collect::<Vec<i16>>()
+        })
+    });
+    group.bench_function("u16", |b| {
+        b.iter(|| {
+            black_box(&unsigned)
+                .iter()
+                .map(|&s| sample_from_u16(s))
+                .collect::<Vec<i16>>()
+        })
+    });
+    group.finish();
+}
+
+fn packet(c: &mut Criterion) {
+    let samples = frame();
+    let template = PacketTemplate::new(CODEC_PCM16, SAMPLE_RATE, CHANNELS, SAMPLES_PER_CHANNEL);
+    let mut packet = Vec::new();
+    let mut seq = 0u32;
+    let mut group = c.benchmark_group("packet");
+    group.throughput(Throughput::Elements(SAMPLES as u64));
+    group.bench_function("pcm16", |b| {
+        b.iter(|| {
+            seq = seq.wrapping_add(1);
+            template
+                .begin(&mut packet, seq, seq.wrapping_mul(240), SAMPLES * 2)
+                .unwrap();
+            encode_pcm16(black_box(&samples).iter().copied(), &mut packet);
+            black_box(&packet);
+        })
+    });
+    let mut payload = Vec::new();
+    encode_pcm16(samples.iter().copied(), &mut payload);
+    let mut buf = [0; MAX_PACKET];
+    group.bench_function("pcm16_into", |b| {
+        b.iter(|| {
+            seq = seq.wrapping_add(1);
+            let len = template
+                .build_into(&mut buf, seq, seq.wrapping_mul(240), black_box(&payload))
+                .unwrap();
+            black_box(&buf[..len]);
+        })
+    });
+    group.finish();
+}
+
+fn seal(c: &mut Criterion) {
+    let samples = frame();
+    let template = PacketTemplate::new(CODEC_PCM16, SAMPLE_RATE, CHANNELS, SAMPLES_PER_CHANNEL);
+    let key = Key::from_bytes([7; 32]);
+    let mut group = c.benchmark_group("seal");
+    group.throughput(Throughput::Bytes(SAMPLES as u64 * 2));
+    for (name, cipher) in [
+        ("aes-256-gcm", Cipher::Aes256Gcm),
+        ("chacha20-poly1305", Cipher::ChaCha20Poly1305),
+        ("hmac-sha256", Cipher::HmacSha256),
+    ] {
+        let mut sealer = PacketSealer::new(cipher, &key).unwrap();
+        let mut seq = 0u32;
+        group.bench_function(name, |b| {
+            b.iter_batched_ref(
+                || {
+                    let mut packet = Vec::new();
+                    template.begin(&mut packet, 0, 0, SAMPLES * 2).unwrap();
+                    encode_pcm16(samples.iter().copied(), &mut packet);
+                    packet
+                },
+                |packet| {
+                    seq = seq.wrapping_add(1);
+                    sealer.seal(packet, seq).unwrap();
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn queue(c: &mut Criterion) {
+    let samples = frame();
+    let stats = SenderStats::default();
+    let (tx, rx) = unbounded();
+    let queue = CaptureQueue::new(tx, rx.clone(), QueueOverflow::DropNewest, DEPTH);
+    queue.fit(SAMPLE_RATE, CHANNELS as usize);
+    c.bench_function("queue/push_recv", |b| {
+        b.iter_batched(
+            || samples.clone(),
+            |samples| {
+                queue.push(&stats, samples, Instant::now());
+                let chunk = rx.try_recv().unwrap();
+                stats
+                    .queued_samples
+                    .fetch_sub(chunk.samples.len() as u64, Ordering::Relaxed);
+                black_box(chunk);
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    // Full queue: what the capture callback pays while the send loop is stalled.
+    for (name, overflow) in [
+        ("drop_newest", QueueOverflow::DropNewest),
+        ("overwrite_oldest", QueueOverflow::OverwriteOldest),
+    ] {
+        let stats = SenderStats::default();
+        let (tx, rx) = unbounded();
+        let queue = CaptureQueue::new(tx, rx, overflow, DEPTH);
+        queue.fit(SAMPLE_RATE, CHANNELS as usize);
+        for _ in 0..DEPTH.as_millis() / 5 {
+            queue.push(&stats, samples.clone(), Instant::now());
+        }
+        c.bench_function(&format!("queue/full_{name}"), |b| {
+            b.iter_batched(
+                || samples.clone(),
+                |samples| queue.push(&stats, samples, Instant::now()),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+}
+
+criterion_group!(benches, conversion, packet, seal, queue);
+criterion_main!(benches);
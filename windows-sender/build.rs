@@ -0,0 +1,59 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    backend_cfgs();
+
+    #[cfg(feature = "ffi")]
+    generate_header();
+}
+
+/// Turns the feature matrix from Cargo.toml into cfgs that also account for the target:
+///
+/// | cfg               | feature    | target          |
+/// |-------------------|------------|-----------------|
+/// | `mic_backend`     | `mic`      | not wasm32      |
+/// | `desktop_backend` | `desktop`  | Windows         |
+/// | `plugin_loader`   | `plugins`  | not wasm32      |
+/// | `uring_backend`   | `io-uring` | Linux           |
+/// | `ndi_backend`     | `ndi`      | not wasm32      |
+fn backend_cfgs() {
+    println!(
+        "cargo:rustc-check-cfg=cfg(mic_backend, desktop_backend, plugin_loader, uring_backend, ndi_backend)"
+    );
+    let feature = |name: &str| std::env::var_os(format!("CARGO_FEATURE_{name}")).is_some();
+    let target_os = std::env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let wasm = std::env::var("CARGO_CFG_TARGET_ARCH").is_ok_and(|arch| arch == "wasm32");
+
+    if feature("MIC") && !wasm {
+        println!("cargo:rustc-cfg=mic_backend");
+    }
+    if feature("DESKTOP") && target_os == "windows" {
+        println!("cargo:rustc-cfg=desktop_backend");
+    }
+    if feature("PLUGINS") && !wasm {
+        println!("cargo:rustc-cfg=plugin_loader");
+    }
+    if feature("IO_URING") && target_os == "linux" {
+        println!("cargo:rustc-cfg=uring_backend");
+    }
+    if feature("NDI") && !wasm {
+        println!("cargo:rustc-cfg=ndi_backend");
+    }
+}
+
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=src/stats.rs");
+
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set");
+    let config = cbindgen::Config::from_file(format!("{crate_dir}/cbindgen.toml"))
+        .expect("failed to read cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("failed to generate C bindings")
+        .write_to_file(format!("{crate_dir}/include/audio_link.h"));
+}
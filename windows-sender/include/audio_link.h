@@ -0,0 +1,427 @@
+#ifndef AUDIO_LINK_H
+#define AUDIO_LINK_H
+
+/* Generated by cbindgen from src/ffi.rs. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define STRESS_SAMPLE_RATE 48000
+
+#define STRESS_CHANNELS 8
+
+/**
+ * Most workers `--encode-workers` accepts.
+ */
+#define MAX_ENCODE_WORKERS 16
+
+#define AUDIO_OK 0
+
+#define AUDIO_ERR_INVALID_ARGUMENT -1
+
+#define AUDIO_ERR_CAPTURE -2
+
+#define AUDIO_ERR_TRANSPORT -3
+
+#define AUDIO_ERR_CODEC -4
+
+#define AUDIO_ERR_PROTOCOL -5
+
+#define AUDIO_ERR_INTERNAL -6
+
+/**
+ * A thread of the engine panicked; the message says where.
+ */
+#define AUDIO_ERR_PANICKED -7
+
+#define AUDIO_SOURCE_DESKTOP 0
+
+#define AUDIO_SOURCE_MIC 1
+
+/**
+ * Synthetic 8-channel audio in 1 ms periods, for soak tests without devices.
+ */
+#define AUDIO_SOURCE_STRESS 2
+
+#define AUDIO_TRANSPORT_UDP 0
+
+#define AUDIO_TRANSPORT_TCP 1
+
+#define AUDIO_CIPHER_AES_256_GCM 0
+
+#define AUDIO_CIPHER_CHACHA20_POLY1305 1
+
+#define AUDIO_CIPHER_HMAC_SHA256 2
+
+#define AUDIO_QUEUE_DROP_NEWEST 0
+
+#define AUDIO_QUEUE_OVERWRITE_OLDEST 1
+
+#define AUDIO_QUEUE_COLLAPSE_TO_LATEST 2
+
+/**
+ * Largest audio packet: the header and the most payload its length field can describe.
+ */
+#define MAX_PACKET (HEADER_SIZE + (size_t)UINT16_MAX)
+
+typedef struct AudioReceiverHandle AudioReceiverHandle;
+
+typedef struct AudioSenderHandle AudioSenderHandle;
+
+typedef struct {
+  const char *target_host;
+  uint16_t port;
+  /**
+   * May be fractional, such as 2.5, as long as it is a whole number of samples.
+   */
+  float frame_ms;
+  uint32_t source;
+  uint32_t transport;
+  const char *desktop_device;
+  /**
+   * 32-byte pre-shared key to encrypt with, or null to send plaintext.
+   */
+  const uint8_t *key;
+  /**
+   * `AUDIO_CIPHER_*`; ignored without a key or `noise_dir`.
+   */
+  uint32_t cipher;
+  /**
+   * Seconds between key rotations, or 0 to keep one key for the whole stream.
+   */
+  uint32_t key_rotation_secs;
+  /**
+   * Directory with this sender's Noise identity and pinned receivers, or null to skip
+   * pairing. Excludes `key`.
+   */
+  const char *noise_dir;
+  /**
+   * `sha256:` fingerprint the receiver's Noise key must match, or null to pin it on
+   * first pairing.
+   */
+  const char *noise_pin;
+  /**
+   * Token receivers must present before their reports count, or null to hear anyone.
+   */
+  const char *access_token;
+  /**
+   * `system` or a PTP hardware clock device to stamp packets with, or null for the
+   * system clock without the PTP flag.
+   */
+  const char *ptp_clock;
+  /**
+   * Send packets on the cadence of the audio they carry rather than in capture bursts.
+   */
+  bool pace;
+  /**
+   * Multi-room delay from send time to playout on every receiver, or 0 to play on
+   * arrival.
+   */
+  uint32_t playout_delay_ms;
+  /**
+   * Milliseconds since the Unix epoch to hold the stream until, or 0 to start at once.
+   */
+  uint64_t start_at_unix_ms;
+  /**
+   * Drop audio older than this when sending falls behind, flagging the gap to the
+   * receivers, or 0 to send all of it.
+   */
+  uint32_t max_audio_age_ms;
+  /**
+   * `AUDIO_QUEUE_*`: what the capture drops while the queue to the send loop is full.
+   */
+  uint32_t queue_overflow;
+  /**
+   * Send UDP through io_uring; fails to start unless built for Linux with `io-uring`.
+   */
+  bool io_uring;
+  /**
+   * Let the kernel split batches of UDP packets (`UDP_SEGMENT`); Linux only.
+   */
+  bool gso;
+  /**
+   * Run the capture and send threads at real-time priority where permitted.
+   */
+  bool realtime;
+  /**
+   * Bit N lets the capture and send threads run on core N, or 0 for any core.
+   */
+  uint64_t affinity_mask;
+  /**
+   * `SO_SNDBUF` of the sockets to the receivers in bytes, or 0 for the OS default.
+   */
+  uint32_t so_sndbuf;
+  /**
+   * Write TCP without blocking, dropping the oldest packets to a receiver this many
+   * milliseconds behind, or 0 to block.
+   */
+  uint32_t tcp_backlog_ms;
+  /**
+   * Most audio held between capture and the sockets in milliseconds, the oldest
+   * dropped past it, or 0 for no cap.
+   */
+  uint32_t max_buffered_ms;
+  /**
+   * Threads encoding with the codec plugin, or 0 to encode on the send thread.
+   */
+  uint32_t encode_workers;
+  /**
+   * Most kilobits per second sent to each receiver, headers included, or 0 for no cap.
+   */
+  uint32_t max_kbps;
+} AudioSenderConfig;
+
+typedef struct {
+  uint64_t captured_chunks;
+  uint64_t captured_samples;
+  uint64_t captured_nonzero_samples;
+  uint64_t captured_abs_sum;
+  uint64_t capture_drops;
+  uint64_t capture_discontinuities;
+  uint64_t capture_silent;
+  uint64_t capture_timestamp_errors;
+  uint64_t capture_queue_us_sum;
+  uint64_t capture_queue_count;
+  uint64_t capture_to_send_us_sum;
+  uint64_t capture_to_send_count;
+  uint64_t packet_build_us_sum;
+  uint64_t packet_build_count;
+  uint64_t socket_send_us_sum;
+  uint64_t socket_send_count;
+  uint64_t sent_packets;
+  uint64_t sent_bytes;
+  uint64_t restarts;
+  uint64_t feedback_reports;
+  uint64_t rtt_us;
+  int64_t clock_offset_us;
+  uint64_t remote_received;
+  uint64_t remote_lost;
+  uint64_t remote_reordered;
+  uint64_t remote_duplicates;
+  uint64_t remote_jitter_us;
+  uint64_t remote_probe_bytes;
+  uint64_t unauthorized_feedback;
+  int64_t clock_drift_ppb;
+  int64_t device_drift_ppb;
+  uint64_t late_dropped_us;
+  uint64_t capped_us;
+  uint64_t buffered_audio_us;
+  uint64_t buffer_bytes;
+} SenderStatsSnapshot;
+
+typedef struct {
+  uint16_t port;
+  uint32_t transport;
+  uint32_t jitter_ms;
+  /**
+   * 32-byte pre-shared key of an encrypted stream, or null for plaintext.
+   */
+  const uint8_t *key;
+  /**
+   * Directory with this receiver's Noise identity and pinned senders, or null to skip
+   * pairing. Excludes `key`.
+   */
+  const char *noise_dir;
+  /**
+   * Token to present to the sender, or null.
+   */
+  const char *access_token;
+  /**
+   * `system` or a PTP hardware clock device to schedule PTP-stamped frames against, or
+   * null to play them as they come.
+   */
+  const char *ptp_clock;
+  /**
+   * Send time to playout of PTP-stamped frames.
+   */
+  uint32_t ptp_delay_ms;
+  /**
+   * Receive UDP through io_uring; fails to start unless built for Linux with `io-uring`.
+   */
+  bool io_uring;
+  /**
+   * `SO_RCVBUF` of the receiving socket in bytes, or 0 for the OS default.
+   */
+  uint32_t so_rcvbuf;
+} AudioReceiverConfig;
+
+typedef struct {
+  uint64_t rx_packets;
+  uint64_t rx_bytes;
+  uint64_t parse_errors;
+  uint64_t payload_mismatch;
+  uint64_t buffered_frames;
+  uint64_t target_frames;
+  uint64_t pushed;
+  uint64_t played;
+  uint64_t missing;
+  uint64_t overflow_dropped;
+  uint64_t lost;
+  uint64_t reordered;
+  uint64_t duplicates;
+  /**
+   * Interarrival jitter; only `AudioReceiver` knows arrival times, so 0 elsewhere.
+   */
+  uint64_t jitter_us;
+  uint64_t probe_bytes;
+  /**
+   * Packets dropped for a wrong or missing key, or for failing authentication.
+   */
+  uint64_t auth_failures;
+  /**
+   * Authentic packets dropped because they were already played or are too old to tell;
+   * network duplicates of a sealed stream land here too.
+   */
+  uint64_t replays;
+  /**
+   * Clock exchanges with the sender; only `AudioReceiver` pings it, so 0 elsewhere.
+   */
+  uint64_t rtt_us;
+  /**
+   * Sender clock minus receiver clock.
+   */
+  int64_t clock_offset_us;
+  /**
+   * How many nanoseconds per second the sender clock gains; 0 until a few seconds of
+   * exchanges.
+   */
+  int64_t clock_drift_ppb;
+  /**
+   * `send_time_us` of the newest packet to its arrival, corrected for the offset.
+   */
+  uint64_t transit_us;
+  /**
+   * Frames dropped for missing their slot on the PTP domain clock.
+   */
+  uint64_t late;
+  /**
+   * Packets flagged with `FLAG_DISCONTINUITY`: the sender dropped late audio before each.
+   */
+  uint64_t discontinuities;
+} ReceiverStatsSnapshot;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Starts a sender and stores an owned handle in `out_handle`.
+ *
+ * # Safety
+ *
+ * `config` must point to a valid `AudioSenderConfig` whose string fields are
+ * NUL-terminated (or null for `desktop_device`, `noise_dir`, `noise_pin`,
+ * `access_token` and `ptp_clock`), `key` must be null or point to 32 readable bytes,
+ * and `out_handle` must be writable.
+ */
+int32_t audio_sender_start(const AudioSenderConfig *config, AudioSenderHandle **out_handle);
+
+/**
+ * Stops the sender and frees the handle.
+ *
+ * # Safety
+ *
+ * `handle` must come from `audio_sender_start` and must not be used afterwards.
+ */
+int32_t audio_sender_stop(AudioSenderHandle *handle);
+
+/**
+ * Copies the cumulative sender counters into `out_stats`.
+ *
+ * # Safety
+ *
+ * `handle` must be a live sender handle and `out_stats` must be writable.
+ */
+int32_t audio_sender_get_stats(const AudioSenderHandle *handle, SenderStatsSnapshot *out_stats);
+
+/**
+ * Sends a sync marker named `name` at the position of the audio being captured now.
+ *
+ * # Safety
+ *
+ * `handle` must be a live sender handle and `name` must be NUL-terminated.
+ */
+int32_t audio_sender_mark(const AudioSenderHandle *handle, const char *name);
+
+/**
+ * Starts a receiver and stores an owned handle in `out_handle`.
+ *
+ * # Safety
+ *
+ * `config` must point to a valid `AudioReceiverConfig` whose `key` is null or points to 32
+ * readable bytes, whose `noise_dir`, `access_token` and `ptp_clock` are null or
+ * NUL-terminated, and `out_handle` must be writable.
+ */
+int32_t audio_receiver_start(const AudioReceiverConfig *config, AudioReceiverHandle **out_handle);
+
+/**
+ * Stops the receiver and frees the handle.
+ *
+ * # Safety
+ *
+ * `handle` must come from `audio_receiver_start` and must not be used afterwards.
+ */
+int32_t audio_receiver_stop(AudioReceiverHandle *handle);
+
+/**
+ * Copies the cumulative receiver counters into `out_stats`.
+ *
+ * # Safety
+ *
+ * `handle` must be a live receiver handle and `out_stats` must be writable.
+ */
+int32_t audio_receiver_get_stats(const AudioReceiverHandle *handle,
+                                 ReceiverStatsSnapshot *out_stats);
+
+/**
+ * Pops the next playout frame into `out_samples` (interleaved PCM16).
+ *
+ * Returns the number of samples written, 0 on underrun, or a negative error code. A frame
+ * larger than `capacity` fails with `AUDIO_ERR_INVALID_ARGUMENT`, naming its size in the
+ * last error, and is kept for the next call.
+ *
+ * # Safety
+ *
+ * `handle` must be a live receiver handle and `out_samples` must be valid for
+ * `capacity` writes.
+ */
+int32_t audio_receiver_read_frame(const AudioReceiverHandle *handle,
+                                  int16_t *out_samples,
+                                  size_t capacity,
+                                  uint32_t timeout_ms);
+
+/**
+ * Pops the oldest sync marker not read yet, storing its media time in `out_media_time`
+ * and its name, truncated to `len - 1` bytes and NUL-terminated, in `name_buf`.
+ *
+ * Returns 1 when a marker was read, 0 when none is waiting, or a negative error code.
+ *
+ * # Safety
+ *
+ * `handle` must be a live receiver handle, `out_media_time` must be writable and
+ * `name_buf` must be valid for `len` writes, or null to skip the name.
+ */
+int32_t audio_receiver_next_marker(const AudioReceiverHandle *handle,
+                                   uint32_t *out_media_time,
+                                   char *name_buf,
+                                   size_t len);
+
+/**
+ * Copies the last error message of the calling thread into `buf`.
+ *
+ * Returns the full message length in bytes (excluding the NUL terminator).
+ *
+ * # Safety
+ *
+ * `buf` must be valid for `len` writes, or null to query the length.
+ */
+size_t audio_last_error_message(char *buf, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* AUDIO_LINK_H */
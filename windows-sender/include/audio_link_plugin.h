@@ -0,0 +1,50 @@
+#ifndef AUDIO_LINK_PLUGIN_H
+#define AUDIO_LINK_PLUGIN_H
+
+/* Plugin ABI for `windows-sender --plugin <library>`. Mirrors src/plugin.rs. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define AUDIO_PLUGIN_ABI_VERSION 1
+
+#define AUDIO_PLUGIN_EFFECT 0
+
+#define AUDIO_PLUGIN_CODEC 1
+
+typedef struct AudioPluginDescriptor {
+  /* Must be AUDIO_PLUGIN_ABI_VERSION. */
+  uint32_t abi_version;
+  /* AUDIO_PLUGIN_EFFECT or AUDIO_PLUGIN_CODEC. */
+  uint32_t kind;
+  const char *name;
+  /* Codec plugins: packet header codec id, must not be 0 (PCM16) or 255
+   * (IEC 61937 passthrough); Opus codecs take 1, one Opus packet per frame. */
+  uint8_t codec_id;
+  /* Optional. Returns per-stream state, or NULL on failure. */
+  void *(*create)(uint32_t sample_rate, uint32_t channels);
+  /* Optional. Frees the state returned by `create`. */
+  void (*destroy)(void *state);
+  /* Effect plugins: processes interleaved PCM16 in place; returns 0 on success. */
+  int32_t (*process)(void *state, int16_t *samples, size_t len);
+  /* Codec plugins: encodes one frame; returns bytes written to `out` or < 0 on error.
+   * With --encode-workers it runs on several threads at once, each on its own state. */
+  intptr_t (*encode)(void *state,
+                     const int16_t *samples,
+                     size_t len,
+                     uint8_t *out,
+                     size_t out_capacity);
+} AudioPluginDescriptor;
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Every plugin exports this symbol; the returned descriptor must stay valid while loaded. */
+const AudioPluginDescriptor *audio_link_plugin_descriptor(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif
+
+#endif  /* AUDIO_LINK_PLUGIN_H */
@@ -0,0 +1,177 @@
+//! `--transport adb`: TCP to the Android receiver over the USB cable. adb forwards the
+//! stream port from loopback to the phone, and reverses the control port so the phone
+//! reaches `--control-http`; both are removed again on exit.
+
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use tracing::{info, warn};
+
+use crate::config::SessionArgs;
+use crate::{Args, Transport};
+
+/// The port forwards set up for this run.
+#[derive(Debug)]
+pub struct Forwards {
+    adb: PathBuf,
+    serial: String,
+    forwarded: Vec<u16>,
+    reversed: Vec<u16>,
+}
+
+impl Forwards {
+    /// Forwards the port of every session on `--transport adb` to the phone; `None` when no
+    /// session uses it.
+    pub fn setup(sessions: &[SessionArgs], args: &Args) -> Result<Option<Self>> {
+        let mut ports: Vec<u16> = sessions
+            .iter()
+            .filter(|session| session.args.transport == Transport::Adb)
+            .map(|session| session.args.port)
+            .collect();
+        if ports.is_empty() {
+            return Ok(None);
+        }
+        ports.sort_unstable();
+        ports.dedup();
+        let adb = locate();
+        let serial = device(&adb, args.adb_serial.as_deref())?;
+        let mut forwards = Self {
+            adb,
+            serial,
+            forwarded: Vec::new(),
+            reversed: Vec::new(),
+        };
+        for port in ports {
+            forwards.run(&["forward", &format!("tcp:{port}"), &format!("tcp:{port}")])?;
+            forwards.forwarded.push(port);
+            info!(
+                "ADB: 127.0.0.1:{port} forwarded to port {port} on {}",
+                forwards.serial
+            );
+        }
+        if let Some(addr) = args.control_http {
+            let port = addr.port();
+            forwards.run(&["reverse", &format!("tcp:{port}"), &format!("tcp:{port}")])?;
+            forwards.reversed.push(port);
+            info!(
+                "ADB: port {port} on {} reversed to the control API",
+                forwards.serial
+            );
+        }
+        Ok(Some(forwards))
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String> {
+        run_adb(&self.adb, Some(&self.serial), args)
+    }
+}
+
+impl Drop for Forwards {
+    fn drop(&mut self) {
+        for port in &self.forwarded {
+            if let Err(err) = self.run(&["forward", "--remove", &format!("tcp:{port}")]) {
+                warn!("ADB: failed to remove the forward of port {port}: {err:#}");
+            }
+        }
+        for port in &self.reversed {
+            if let Err(err) = self.run(&["reverse", "--remove", &format!("tcp:{port}")]) {
+                warn!("ADB: failed to remove the reverse of port {port}: {err:#}");
+            }
+        }
+    }
+}
+
+/// Where adb is: `ADB`, the platform-tools of the Android SDK (`ANDROID_HOME`,
+/// `ANDROID_SDK_ROOT` or where Android Studio installs it), then the `PATH`.
+fn locate() -> PathBuf {
+    if let Some(path) = env::var_os("ADB").filter(|path| !path.is_empty()) {
+        return PathBuf::from(path);
+    }
+    let exe = if cfg!(windows) { "adb.exe" } else { "adb" };
+    let studio = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(|dir| Path::new(&dir).join("Android/Sdk"))
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| Path::new(&home).join("Library/Android/sdk"))
+    } else {
+        env::var_os("HOME").map(|home| Path::new(&home).join("Android/Sdk"))
+    };
+    ["ANDROID_HOME", "ANDROID_SDK_ROOT"]
+        .iter()
+        .filter_map(env::var_os)
+        .map(PathBuf::from)
+        .chain(studio)
+        .map(|sdk| sdk.join("platform-tools").join(exe))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(exe))
+}
+
+/// The serial of the phone to use: `serial` if it is attached, or the only one attached.
+fn device(adb: &Path, serial: Option<&str>) -> Result<String> {
+    let listing = run_adb(adb, None, &["devices"])?;
+    // After the header, one `SERIAL<TAB>STATE` line per device.
+    let devices: Vec<(&str, &str)> = listing
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.trim_end().split_once('\t'))
+        .collect();
+    let ready = |state: &str| state == "device";
+    if let Some(serial) = serial {
+        return match devices.iter().find(|(found, _)| *found == serial) {
+            Some((_, state)) if ready(state) => Ok(serial.to_string()),
+            Some((_, state)) => bail!("Android device {serial} is {state}; {}", advice(state)),
+            None => bail!("no Android device {serial} attached; `adb devices` lists them"),
+        };
+    }
+    let usable: Vec<&str> = devices
+        .iter()
+        .filter(|(_, state)| ready(state))
+        .map(|(serial, _)| *serial)
+        .collect();
+    match usable.as_slice() {
+        [serial] => Ok(serial.to_string()),
+        [] => match devices.first() {
+            Some((serial, state)) => bail!("Android device {serial} is {state}; {}", advice(state)),
+            None => {
+                bail!("no Android device attached; connect the phone by USB with USB debugging on")
+            }
+        },
+        several => bail!(
+            "several Android devices attached ({}); pick one with --adb-serial",
+            several.join(", ")
+        ),
+    }
+}
+
+fn advice(state: &str) -> &'static str {
+    match state {
+        "unauthorized" => "allow USB debugging in the prompt on the phone",
+        _ => "reconnect the cable, or run `adb kill-server`",
+    }
+}
+
+fn run_adb(adb: &Path, serial: Option<&str>, args: &[&str]) -> Result<String> {
+    let mut command = Command::new(adb);
+    if let Some(serial) = serial {
+        command.args(["-s", serial]);
+    }
+    let output = match command.args(args).output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            bail!("adb not found; install the Android SDK platform-tools, or set ADB to its path")
+        }
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to run {}", adb.display()));
+        }
+    };
+    if !output.status.success() {
+        bail!(
+            "adb {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
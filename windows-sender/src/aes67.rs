@@ -0,0 +1,340 @@
+//! AES67, RTP audio timed by PTP: the stream as AES67 devices, and Dante devices in AES67
+//! mode, subscribe to it. Sessions are announced with SAP (RFC 2974), so devices list the
+//! stream by name without an SDP file being handed around.
+
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, ProtocolError};
+use crate::protocol::pcm_audio;
+use crate::ptp::TAI_UTC_OFFSET_US;
+use crate::transport::Repacketizer;
+
+pub const SAMPLE_RATE: u32 = 48_000;
+/// Samples per channel in one packet: 1 ms, the packet time every AES67 device takes.
+pub const PACKET_SAMPLES: usize = 48;
+/// Most channels in one stream that every AES67 device takes.
+pub const MAX_CHANNELS: u8 = 8;
+pub const RTP_HEADER_SIZE: usize = 12;
+/// The dynamic RTP payload type the SDP maps to the encoding.
+pub const PAYLOAD_TYPE: u8 = 96;
+/// Where AES67 devices listen for session announcements.
+pub const SAP_GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 255), 9875);
+/// Hops multicast audio and announcements may take.
+pub const MULTICAST_TTL: u32 = 32;
+/// Longest session name.
+pub const MAX_NAME: usize = 64;
+
+const RTP_VERSION: u8 = 0x80;
+const RTP_MARKER: u8 = 0x80;
+const SAP_VERSION: u8 = 0x20;
+const SAP_DELETE: u8 = 0x04;
+const SAP_PAYLOAD_TYPE: &[u8] = b"application/sdp\0";
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(30);
+/// How far RTP timestamps may stray from the PTP clock, as the capture clock drifts
+/// from it, before they are set again: 20 ms.
+const MAX_SLIP: u32 = SAMPLE_RATE / 50;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// 24-bit samples, which every AES67 device takes.
+    #[default]
+    L24,
+    L16,
+}
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::L24 => "L24",
+            Encoding::L16 => "L16",
+        }
+    }
+}
+
+/// The name devices list a session by.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SessionName {
+    bytes: [u8; MAX_NAME],
+    len: u8,
+}
+
+impl SessionName {
+    pub fn new(name: &str) -> Result<Self, Error> {
+        if name.is_empty() || name.len() > MAX_NAME || name.chars().any(char::is_control) {
+            return Err(Error::Config(format!(
+                "AES67 session name {name:?} must be 1 to {MAX_NAME} bytes without control characters"
+            )));
+        }
+        let mut bytes = [0; MAX_NAME];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(Self {
+            bytes,
+            len: name.len() as u8,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+}
+
+impl Default for SessionName {
+    fn default() -> Self {
+        Self::new("windows-sender").unwrap()
+    }
+}
+
+/// What the SDP of a stream describes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub name: SessionName,
+    pub encoding: Encoding,
+    pub channels: u8,
+    /// This host's address on the way to `target`.
+    pub source: Ipv4Addr,
+    pub target: SocketAddrV4,
+    /// The `o=` session id, kept across runs so devices find the stream again.
+    pub id: u64,
+    /// Raised whenever the description changes.
+    pub version: u64,
+}
+
+impl Session {
+    pub fn new(
+        name: SessionName,
+        encoding: Encoding,
+        source: Ipv4Addr,
+        target: SocketAddrV4,
+    ) -> Self {
+        let id = fnv1a(&[name.as_str().as_bytes(), &target.ip().octets()].concat()) >> 1;
+        let version = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        Self {
+            name,
+            encoding,
+            channels: 0,
+            source,
+            target,
+            id,
+            version,
+        }
+    }
+
+    /// The SDP (RFC 4566) devices subscribe with, as AES67 lays it out.
+    pub fn sdp(&self) -> String {
+        let ttl = if self.target.ip().is_multicast() {
+            format!("/{MULTICAST_TTL}")
+        } else {
+            String::new()
+        };
+        format!(
+            "v=0\r\n\
+             o=- {id} {version} IN IP4 {source}\r\n\
+             s={name}\r\n\
+             c=IN IP4 {ip}{ttl}\r\n\
+             t=0 0\r\n\
+             a=clock-domain:PTPv2 0\r\n\
+             m=audio {port} RTP/AVP {PAYLOAD_TYPE}\r\n\
+             i={channels} channels\r\n\
+             a=rtpmap:{PAYLOAD_TYPE} {encoding}/{SAMPLE_RATE}/{channels}\r\n\
+             a=sendonly\r\n\
+             a=ptime:1\r\n\
+             a=ts-refclk:ptp=IEEE1588-2008:traceable\r\n\
+             a=mediaclk:direct=0\r\n",
+            id = self.id,
+            version = self.version,
+            source = self.source,
+            name = self.name.as_str(),
+            ip = self.target.ip(),
+            port = self.target.port(),
+            channels = self.channels,
+            encoding = self.encoding.as_str(),
+        )
+    }
+
+    /// The SAP announcement of the session, or with `delete` the one that withdraws it.
+    fn sap_packet(&self, delete: bool) -> Vec<u8> {
+        let sdp = self.sdp();
+        // A deletion only needs the origin line, which names the session.
+        let payload = if delete {
+            sdp.lines().take(2).collect::<Vec<_>>().join("\r\n") + "\r\n"
+        } else {
+            sdp
+        };
+        let hash =
+            (fnv1a(&[self.id.to_be_bytes(), self.version.to_be_bytes()].concat()) as u16).max(1);
+        let mut packet = Vec::with_capacity(8 + SAP_PAYLOAD_TYPE.len() + payload.len());
+        packet.push(SAP_VERSION | if delete { SAP_DELETE } else { 0 });
+        packet.push(0);
+        packet.extend_from_slice(&hash.to_be_bytes());
+        packet.extend_from_slice(&self.source.octets());
+        packet.extend_from_slice(SAP_PAYLOAD_TYPE);
+        packet.extend_from_slice(payload.as_bytes());
+        packet
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// `send_time_us` as an RTP timestamp: samples since the PTP epoch, which counts TAI.
+fn ptp_timestamp(send_time_us: u64) -> u32 {
+    let tai_us = send_time_us + TAI_UTC_OFFSET_US;
+    let samples = tai_us / 1_000_000 * SAMPLE_RATE as u64
+        + tai_us % 1_000_000 * SAMPLE_RATE as u64 / 1_000_000;
+    samples as u32
+}
+
+/// Turns the audio packets of a stream into 1 ms RTP packets, holding back what does not
+/// fill one until the next, and announces the session.
+#[derive(Debug)]
+pub struct Packetizer {
+    session: Session,
+    ssrc: u32,
+    seq: u16,
+    /// RTP timestamp minus media time, once set from the PTP clock.
+    offset: Option<u32>,
+    /// The next packet starts a run of timestamps.
+    marker: bool,
+    /// Media time of the first frame in `pending`.
+    pending_time: u32,
+    pending: Vec<u8>,
+    packets: Vec<Vec<u8>>,
+    sap: Vec<u8>,
+    announced: Option<Instant>,
+}
+
+impl Packetizer {
+    pub fn new(session: Session) -> Self {
+        let ssrc = getrandom::u32().unwrap_or(session.version as u32);
+        Self {
+            session,
+            ssrc,
+            seq: 0,
+            offset: None,
+            marker: true,
+            pending_time: 0,
+            pending: Vec::new(),
+            packets: Vec::new(),
+            sap: Vec::new(),
+            announced: None,
+        }
+    }
+}
+
+impl Repacketizer for Packetizer {
+    fn packet_name(&self) -> &'static str {
+        "RTP packet"
+    }
+
+    fn packetize(&mut self, packet: &[u8]) -> Result<&[Vec<u8>], ProtocolError> {
+        let Some(audio) = pcm_audio(packet)? else {
+            return Ok(&[]);
+        };
+        if audio.sample_rate != SAMPLE_RATE {
+            return Err(ProtocolError::UnsupportedSampleRate(audio.sample_rate));
+        }
+        if audio.channels > MAX_CHANNELS {
+            return Err(ProtocolError::InvalidChannels(audio.channels));
+        }
+        if audio.channels != self.session.channels {
+            self.session.channels = audio.channels;
+            self.session.version += 1;
+            self.announced = None;
+            self.offset = None;
+        }
+
+        let frame_bytes = 2 * audio.channels as usize;
+        let next_time = self
+            .pending_time
+            .wrapping_add((self.pending.len() / frame_bytes) as u32);
+        if audio.media_time != next_time {
+            self.pending.clear();
+            self.pending_time = audio.media_time;
+        }
+        // Timestamps run on with the media time, which skips over gaps, as long as they
+        // stay near the PTP clock.
+        let now = ptp_timestamp(audio.send_time_us);
+        let offset = match self.offset {
+            Some(offset)
+                if (audio.media_time.wrapping_add(offset).wrapping_sub(now) as i32)
+                    .unsigned_abs()
+                    <= MAX_SLIP =>
+            {
+                offset
+            }
+            _ => {
+                self.pending.clear();
+                self.pending_time = audio.media_time;
+                self.marker = true;
+                *self.offset.insert(now.wrapping_sub(audio.media_time))
+            }
+        };
+
+        self.pending.extend_from_slice(audio.samples);
+        let packet_bytes = PACKET_SAMPLES * frame_bytes;
+        let count = self.pending.len() / packet_bytes;
+        if self.packets.len() < count {
+            self.packets.resize_with(count, Vec::new);
+        }
+        for (chunk, out) in self
+            .pending
+            .chunks_exact(packet_bytes)
+            .zip(&mut self.packets)
+        {
+            out.clear();
+            out.push(RTP_VERSION);
+            out.push(
+                PAYLOAD_TYPE
+                    | if std::mem::take(&mut self.marker) {
+                        RTP_MARKER
+                    } else {
+                        0
+                    },
+            );
+            out.extend_from_slice(&self.seq.to_be_bytes());
+            out.extend_from_slice(&self.pending_time.wrapping_add(offset).to_be_bytes());
+            out.extend_from_slice(&self.ssrc.to_be_bytes());
+            // Network byte order; L24 pads each sample with a low zero byte.
+            for sample in chunk.chunks_exact(2) {
+                out.push(sample[1]);
+                out.push(sample[0]);
+                if self.session.encoding == Encoding::L24 {
+                    out.push(0);
+                }
+            }
+            self.seq = self.seq.wrapping_add(1);
+            self.pending_time = self.pending_time.wrapping_add(PACKET_SAMPLES as u32);
+        }
+        self.pending.drain(..count * packet_bytes);
+        Ok(&self.packets[..count])
+    }
+
+    fn announcement(&mut self) -> Option<(SocketAddr, &[u8])> {
+        if self.session.channels == 0
+            || self
+                .announced
+                .is_some_and(|at| at.elapsed() < ANNOUNCE_INTERVAL)
+        {
+            return None;
+        }
+        self.announced = Some(Instant::now());
+        self.sap = self.session.sap_packet(false);
+        Some((SAP_GROUP.into(), &self.sap))
+    }
+
+    fn withdrawal(&mut self) -> Option<(SocketAddr, &[u8])> {
+        self.announced?;
+        self.sap = self.session.sap_packet(true);
+        Some((SAP_GROUP.into(), &self.sap))
+    }
+
+    fn sdp(&self) -> Option<String> {
+        (self.session.channels > 0).then(|| self.session.sdp())
+    }
+}
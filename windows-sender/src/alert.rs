@@ -0,0 +1,110 @@
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+use windows_sender::journal::{EventJournal, EventKind};
+use windows_sender::sender::AudioSender;
+
+use crate::Args;
+
+/// Drops are counted over this window; backlog is sampled at its end.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// The alert that stopped a session under `--alert-exit`.
+#[derive(Debug)]
+pub struct AlertExit(pub String);
+
+impl fmt::Display for AlertExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "alert: {}", self.0)
+    }
+}
+
+impl std::error::Error for AlertExit {}
+
+/// Watches one running sender against the `--alert-*` thresholds.
+pub struct AlertMonitor {
+    drops_per_sec: Option<f64>,
+    backlog: Option<usize>,
+    hold: Duration,
+    exit: bool,
+    journal: Arc<EventJournal>,
+    last: Option<(Instant, u64)>,
+    breached_since: Option<Instant>,
+    firing: bool,
+    tripped: Option<String>,
+}
+
+impl AlertMonitor {
+    /// `None` unless at least one threshold is set.
+    pub fn new(args: &Args, journal: Arc<EventJournal>) -> Option<Self> {
+        if args.alert_drops.is_none() && args.alert_backlog.is_none() {
+            return None;
+        }
+        Some(Self {
+            drops_per_sec: args.alert_drops,
+            backlog: args.alert_backlog,
+            hold: Duration::from_secs(args.alert_secs),
+            exit: args.alert_exit,
+            journal,
+            last: None,
+            breached_since: None,
+            firing: false,
+            tripped: None,
+        })
+    }
+
+    /// Called a few times a second. Fires once a threshold has been exceeded for the whole
+    /// hold time, and again only after it cleared.
+    pub fn check(&mut self, sender: &AudioSender) {
+        let now = Instant::now();
+        let drops = sender.stats().capture_drops.load(Ordering::Relaxed);
+        let Some((at, last_drops)) = self.last else {
+            self.last = Some((now, drops));
+            return;
+        };
+        if now - at < RATE_WINDOW {
+            return;
+        }
+        self.last = Some((now, drops));
+
+        let rate = drops.saturating_sub(last_drops) as f64 / (now - at).as_secs_f64();
+        let backlog = sender.backlog();
+        let mut breaches = Vec::new();
+        if let Some(limit) = self.drops_per_sec.filter(|&limit| rate > limit) {
+            breaches.push(format!("{rate:.1} drops/s > {limit}"));
+        }
+        if let Some(limit) = self.backlog.filter(|&limit| backlog > limit) {
+            breaches.push(format!("backlog {backlog} > {limit}"));
+        }
+        if breaches.is_empty() {
+            if self.firing {
+                info!("alert cleared");
+                self.journal.record(EventKind::Alert, "cleared");
+            }
+            self.breached_since = None;
+            self.firing = false;
+            return;
+        }
+
+        let since = *self.breached_since.get_or_insert(at);
+        if self.firing || now - since < self.hold {
+            return;
+        }
+        self.firing = true;
+        let message = format!("{} for {} s", breaches.join(", "), self.hold.as_secs());
+        warn!("ALERT: {message}");
+        self.journal.record(EventKind::Alert, message.clone());
+        if self.exit {
+            sender.control().request_stop();
+            self.tripped = Some(message);
+        }
+    }
+
+    /// The alert that stopped the sender under `--alert-exit`.
+    pub fn tripped(&self) -> Option<&str> {
+        self.tripped.as_deref()
+    }
+}
@@ -0,0 +1,318 @@
+//! `--output`: the packets of an Opus codec plugin written to an Ogg Opus file (RFC 7845)
+//! from a thread of its own, so a session is kept compressed and seekable.
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender, TrySendError};
+use tracing::{info, warn};
+
+use crate::error::Error;
+use crate::sender::StreamFormat;
+use crate::tee::numbered;
+use crate::tee::ogg::OggWriter;
+
+/// Opus decodes at 48 kHz whatever rate it was fed, and granule positions count those
+/// samples.
+const OPUS_RATE: u64 = 48_000;
+/// Packets waiting for the disk; past this they are dropped rather than held in memory.
+const QUEUE_PACKETS: usize = 1000;
+/// Gaps in the stream up to this long are filled with packets that decode to silence;
+/// longer ones are pauses and left out.
+const MAX_GAP: Duration = Duration::from_secs(5);
+/// How often the page being built is written out, so the file stays playable, and
+/// seekable up to there, if the sender is killed.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const VENDOR: &str = "windows-sender";
+/// TOC configurations of CELT-only frames of 20 ms and 2.5 ms, fullband; a packet of
+/// just the TOC byte carries no data, and decodes to silence.
+const CELT_20MS: (u8, u64) = (31, 960);
+const CELT_2_5MS: (u8, u64) = (28, 120);
+
+#[derive(Debug)]
+struct Packet {
+    format: StreamFormat,
+    media_time: u32,
+    data: Vec<u8>,
+}
+
+/// The file `--output` writes. Shared by the restarts of a sender, so one session makes
+/// one file; it is finished when the last handle is dropped.
+#[derive(Debug)]
+pub struct OpusArchive {
+    path: PathBuf,
+    tx: Option<Sender<Packet>>,
+    dropped: Arc<AtomicU64>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl OpusArchive {
+    /// Starts the writer for `path`, a `.opus` or `.ogg` file. The file is created once the
+    /// first packet arrives.
+    pub fn new(path: &Path) -> Result<Self, Error> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        if !matches!(extension.as_deref(), Some("opus" | "ogg" | "oga")) {
+            return Err(Error::Config(format!(
+                "--output {} needs a .opus or .ogg file",
+                path.display()
+            )));
+        }
+        let (tx, rx) = bounded(QUEUE_PACKETS);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer = Writer {
+            path: path.to_path_buf(),
+            dropped: Arc::clone(&dropped),
+        };
+        let writer = thread::Builder::new()
+            .name("output".to_string())
+            .spawn(move || writer.run(&rx))
+            .map_err(|source| Error::Spawn {
+                name: "output",
+                source,
+            })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            tx: Some(tx),
+            dropped,
+            writer: Some(writer),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Queues the Opus packet of the frame starting at `media_time`; it is dropped, and
+    /// counted, when the disk falls behind.
+    pub fn write(&self, format: StreamFormat, media_time: u32, data: Vec<u8>) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let packet = Packet {
+            format,
+            media_time,
+            data,
+        };
+        if let Err(TrySendError::Full(_)) = tx.try_send(packet) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for OpusArchive {
+    fn drop(&mut self) {
+        self.tx = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+struct Writer {
+    path: PathBuf,
+    dropped: Arc<AtomicU64>,
+}
+
+/// The file being written, with the format of the stream in it.
+struct Open {
+    ogg: OggWriter<BufWriter<File>>,
+    format: StreamFormat,
+    /// Media time right after the last packet written.
+    next_time: u32,
+    /// Samples at 48 kHz written so far.
+    granule: u64,
+}
+
+impl Writer {
+    fn run(self, rx: &Receiver<Packet>) {
+        if let Err(err) = self.write_all(rx) {
+            warn!(
+                "output: failed to write {}: {err}; no longer archiving",
+                self.path.display()
+            );
+        }
+    }
+
+    fn write_all(&self, rx: &Receiver<Packet>) -> io::Result<()> {
+        let mut open: Option<Open> = None;
+        let mut part = 1;
+        let mut flushed = Instant::now();
+        let mut reported = 0;
+        loop {
+            match rx.recv_timeout(FLUSH_INTERVAL) {
+                Ok(packet) => {
+                    if open
+                        .as_ref()
+                        .is_some_and(|open| open.format != packet.format)
+                    {
+                        if let Some(mut open) = open.take() {
+                            open.ogg.finish()?;
+                        }
+                        part += 1;
+                    }
+                    let open = match &mut open {
+                        Some(open) => open,
+                        None => open.insert(self.open(part, packet.format, packet.media_time)?),
+                    };
+                    write_packet(open, &packet)?;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if flushed.elapsed() >= FLUSH_INTERVAL {
+                flushed = Instant::now();
+                if let Some(open) = &mut open {
+                    open.ogg.flush()?;
+                }
+                let dropped = self.dropped.load(Ordering::Relaxed);
+                if dropped > reported {
+                    warn!(
+                        "output: {} packets dropped; the disk cannot keep up",
+                        dropped - reported
+                    );
+                    reported = dropped;
+                }
+            }
+        }
+        match open {
+            Some(mut open) => open.ogg.finish(),
+            None => Ok(()),
+        }
+    }
+
+    fn open(&self, part: usize, format: StreamFormat, media_time: u32) -> io::Result<Open> {
+        // Without a channel mapping table, which depends on how the plugin lays out its
+        // streams, Ogg Opus holds mono or stereo only.
+        if format.channels > 2 {
+            return Err(io::Error::other(format!(
+                "{} channels need an Opus channel mapping; only mono and stereo are archived",
+                format.channels
+            )));
+        }
+        let path = match part {
+            1 => self.path.clone(),
+            part => numbered(&self.path, part),
+        };
+        let mut ogg = OggWriter::new(BufWriter::new(File::create(&path)?));
+        // Each header packet on a page of its own.
+        ogg.packet(&opus_head(format), 0)?;
+        ogg.flush()?;
+        ogg.packet(&opus_tags(SystemTime::now()), 0)?;
+        ogg.flush()?;
+        info!(
+            "output: writing Opus, {} ch from {} Hz, to {}",
+            format.channels,
+            format.sample_rate,
+            path.display()
+        );
+        Ok(Open {
+            ogg,
+            format,
+            next_time: media_time,
+            granule: 0,
+        })
+    }
+}
+
+fn write_packet(open: &mut Open, packet: &Packet) -> io::Result<()> {
+    let rate = open.format.sample_rate.max(1) as u64;
+    let gap = packet.media_time.wrapping_sub(open.next_time) as u64;
+    if gap > 0 && gap <= MAX_GAP.as_secs() * rate {
+        let stereo = (open.format.channels == 2) as u8;
+        let mut left = gap * OPUS_RATE / rate;
+        for (config, samples) in [CELT_20MS, CELT_2_5MS] {
+            while left >= samples {
+                open.granule += samples;
+                open.ogg
+                    .packet(&[config << 3 | stereo << 2], open.granule)?;
+                left -= samples;
+            }
+        }
+    }
+    let frame = open.format.samples_per_channel as u64;
+    open.granule += packet_samples(&packet.data).unwrap_or(frame * OPUS_RATE / rate);
+    open.ogg.packet(&packet.data, open.granule)?;
+    open.next_time = packet.media_time.wrapping_add(frame as u32);
+    Ok(())
+}
+
+/// Samples at 48 kHz an Opus packet decodes to, from its TOC byte (RFC 6716, 3.1).
+fn packet_samples(packet: &[u8]) -> Option<u64> {
+    let toc = *packet.first()?;
+    let config = (toc >> 3) as usize;
+    let frame = match config {
+        // SILK: 10, 20, 40 and 60 ms.
+        0..=11 => [480, 960, 1920, 2880][config % 4],
+        // Hybrid: 10 and 20 ms.
+        12..=15 => [480, 960][config % 2],
+        // CELT: 2.5, 5, 10 and 20 ms.
+        _ => [120, 240, 480, 960][config % 4],
+    };
+    let frames = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => (*packet.get(1)? & 0x3F) as u64,
+    };
+    Some(frame * frames)
+}
+
+fn opus_head(format: StreamFormat) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(format.channels);
+    // No pre-skip: how far the encoder looks ahead is up to the plugin.
+    head.extend_from_slice(&0u16.to_le_bytes());
+    head.extend_from_slice(&format.sample_rate.to_le_bytes());
+    // No output gain, and channel mapping family 0: mono or stereo.
+    head.extend_from_slice(&0i16.to_le_bytes());
+    head.push(0);
+    head
+}
+
+/// The comment header, with the time the file was started as its `DATE`.
+fn opus_tags(started: SystemTime) -> Vec<u8> {
+    let date = format!("DATE={}", utc_timestamp(started));
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+    tags.extend_from_slice(VENDOR.as_bytes());
+    tags.extend_from_slice(&1u32.to_le_bytes());
+    tags.extend_from_slice(&(date.len() as u32).to_le_bytes());
+    tags.extend_from_slice(date.as_bytes());
+    tags
+}
+
+/// `time` in ISO 8601, UTC, such as `2024-05-01T18:30:00Z`.
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    // Days since 1970-01-01 to a civil date, counting in 400-year eras from March 2000.
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = match month_from_march {
+        0..=9 => month_from_march + 3,
+        _ => month_from_march - 9,
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    let time_of_day = secs % 86_400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60
+    )
+}
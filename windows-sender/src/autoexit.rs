@@ -0,0 +1,122 @@
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use tracing::info;
+use windows_sender::sender::AudioSender;
+
+use crate::Args;
+
+/// Captured audio below this average level counts as silence for `--exit-on-silence`.
+const SILENCE_DBFS: f64 = -60.0;
+/// The capture level is judged over windows of this length.
+const LEVEL_WINDOW: Duration = Duration::from_secs(1);
+
+/// Stops a session after `--duration`, or once `--exit-on-silence` worth of quiet capture
+/// went by.
+pub struct AutoExit {
+    deadline: Option<(Instant, Duration)>,
+    silence: Option<Duration>,
+    last: Option<(Instant, u64, u64)>,
+    silent_since: Option<Instant>,
+    stopped: bool,
+}
+
+impl AutoExit {
+    /// `None` unless one of the options is set. `started` is when the session first
+    /// started, so restarts do not extend `--duration`.
+    pub fn new(args: &Args, started: Instant) -> Option<Self> {
+        if args.duration.is_none() && args.exit_on_silence.is_none() {
+            return None;
+        }
+        Some(Self {
+            deadline: args.duration.map(|duration| (started + duration, duration)),
+            silence: args.exit_on_silence,
+            last: None,
+            silent_since: None,
+            stopped: false,
+        })
+    }
+
+    /// Called a few times a second; asks the sender to stop once a limit is reached.
+    pub fn check(&mut self, sender: &AudioSender) {
+        let now = Instant::now();
+        if self.stopped {
+            return;
+        }
+        if let Some((deadline, duration)) = self.deadline {
+            if now >= deadline {
+                info!("Ran for {}; stopping", format_duration(duration));
+                self.stop(sender);
+                return;
+            }
+        }
+        let Some(silence) = self.silence else {
+            return;
+        };
+        let stats = sender.stats();
+        let samples = stats.captured_samples.load(Ordering::Relaxed);
+        let abs_sum = stats.captured_abs_sum.load(Ordering::Relaxed);
+        let Some((at, last_samples, last_abs_sum)) = self.last else {
+            self.last = Some((now, samples, abs_sum));
+            return;
+        };
+        if now - at < LEVEL_WINDOW {
+            return;
+        }
+        self.last = Some((now, samples, abs_sum));
+
+        // Loopback capture delivers nothing at all while the desktop is quiet.
+        let count = samples.saturating_sub(last_samples);
+        let average = abs_sum.saturating_sub(last_abs_sum) as f64 / count.max(1) as f64;
+        if count > 0 && 20.0 * (average.max(1.0) / 32768.0).log10() >= SILENCE_DBFS {
+            self.silent_since = None;
+            return;
+        }
+        let since = *self.silent_since.get_or_insert(at);
+        if now - since >= silence {
+            info!("Silent for {}; stopping", format_duration(silence));
+            self.stop(sender);
+        }
+    }
+
+    /// The sender takes a moment to wind down, so this only asks once.
+    fn stop(&mut self, sender: &AudioSender) {
+        sender.control().request_stop();
+        self.stopped = true;
+    }
+}
+
+/// `duration` in the largest unit `parse_duration` takes that keeps it whole.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if duration.subsec_nanos() != 0 || secs == 0 {
+        format!("{}ms", duration.as_millis())
+    } else if secs.is_multiple_of(3600) {
+        format!("{}h", secs / 3600)
+    } else if secs.is_multiple_of(60) {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+/// `500ms`, `30s`, `10m` or `2h`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration {value:?}; expected 500ms, 30s, 10m or 2h");
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(digits);
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let duration = match unit {
+        "ms" => Duration::from_millis(number),
+        "s" => Duration::from_secs(number),
+        "m" => Duration::from_secs(number * 60),
+        "h" => Duration::from_secs(number * 3600),
+        _ => return Err(invalid()),
+    };
+    if duration.is_zero() {
+        return Err(invalid());
+    }
+    Ok(duration)
+}
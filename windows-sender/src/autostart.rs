@@ -0,0 +1,181 @@
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use clap::{Subcommand, ValueEnum};
+
+use crate::Args;
+
+/// Name of the Run value and of the scheduled task.
+const ENTRY: &str = "AudioLinkSender";
+const RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+/// Longest command line `schtasks /TR` takes.
+const MAX_TASK_COMMAND: usize = 261;
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum Action {
+    /// Start the sender with `--config` or `--resume` at every login.
+    Enable {
+        #[arg(long, value_enum, default_value_t = Method::RunKey)]
+        method: Method,
+    },
+    /// Remove the login entry, wherever `enable` put it.
+    Disable,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Method {
+    /// The per-user Run key; no administrator rights needed.
+    RunKey,
+    /// A Task Scheduler task with a logon trigger; needs an administrator console.
+    Task,
+}
+
+pub fn run(action: &Action, args: &Args) -> Result<()> {
+    if !cfg!(windows) {
+        bail!("autostart registers with the Windows Run key or Task Scheduler; elsewhere use the desktop's autostart or a user service");
+    }
+    match action {
+        Action::Enable { method } => enable(*method, args),
+        Action::Disable => disable(),
+    }
+}
+
+fn enable(method: Method, args: &Args) -> Result<()> {
+    let command = login_command(args)?;
+    match method {
+        Method::RunKey => {
+            // The task would start a second sender next to the Run entry.
+            let _ = delete_task();
+            exec(
+                "reg",
+                [
+                    "add", RUN_KEY, "/v", ENTRY, "/t", "REG_SZ", "/d", &command, "/f",
+                ],
+            )?;
+        }
+        Method::Task => {
+            if command.len() > MAX_TASK_COMMAND {
+                bail!(
+                    "the command is {} characters, over the {MAX_TASK_COMMAND} Task Scheduler takes; use --method run-key or a shorter path",
+                    command.len()
+                );
+            }
+            let _ = delete_run_value();
+            exec(
+                "schtasks",
+                [
+                    "/Create", "/F", "/SC", "ONLOGON", "/IT", "/RL", "LIMITED", "/TN", ENTRY,
+                    "/TR", &command,
+                ],
+            )?;
+        }
+    }
+    println!("The sender now starts at login: {command}");
+    Ok(())
+}
+
+fn disable() -> Result<()> {
+    let run_key = delete_run_value().is_ok();
+    let task = delete_task().is_ok();
+    if !run_key && !task {
+        bail!("no autostart entry {ENTRY} found");
+    }
+    println!("Removed the autostart entry {ENTRY}");
+    Ok(())
+}
+
+fn delete_run_value() -> Result<()> {
+    exec("reg", ["delete", RUN_KEY, "/v", ENTRY, "/f"])
+}
+
+fn delete_task() -> Result<()> {
+    exec("schtasks", ["/Delete", "/TN", ENTRY, "/F"])
+}
+
+/// This executable with the profile to stream, started minimized. The profile path is made
+/// absolute since the login entry does not run from the current directory.
+fn login_command(args: &Args) -> Result<String> {
+    let exe = std::env::current_exe().context("failed to locate the sender executable")?;
+    let mut command = quote(exe.as_os_str());
+    match (&args.config, args.resume) {
+        (Some(config), _) => {
+            let config = std::fs::canonicalize(config)
+                .with_context(|| format!("config file {} not found", config.display()))?;
+            command.push_str(" --config ");
+            command.push_str(&quote(strip_verbatim(&config).as_os_str()));
+        }
+        (None, true) => command.push_str(" --resume"),
+        (None, false) => {
+            bail!("pass --config <profile> or --resume so the login entry knows what to stream")
+        }
+    }
+    command.push_str(" --minimized");
+    Ok(command)
+}
+
+/// `canonicalize` returns `\\?\C:\...` on Windows, which some programs cannot open.
+fn strip_verbatim(path: &Path) -> &Path {
+    path.to_str()
+        .and_then(|path| path.strip_prefix(r"\\?\"))
+        .filter(|path| !path.starts_with("UNC"))
+        .map_or(path, Path::new)
+}
+
+/// Quotes `arg` the way `CommandLineToArgvW` splits it back: backslashes are only special
+/// before a quote, so those runs are doubled.
+fn quote(arg: &OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        let escapes = match c {
+            '"' => backslashes * 2 + 1,
+            _ => backslashes,
+        };
+        quoted.extend(std::iter::repeat_n('\\', escapes));
+        quoted.push(c);
+        backslashes = 0;
+    }
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+fn exec<const N: usize>(program: &str, args: [&str; N]) -> Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("failed to run {program}"))?;
+    if !output.status.success() {
+        bail!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// `--minimized`: sends the console window to the taskbar so a login start stays out of
+/// the way.
+#[cfg(windows)]
+pub fn minimize_console() {
+    use windows_sys::Win32::System::Console::GetConsoleWindow;
+    use windows_sys::Win32::UI::WindowsAndMessaging::{ShowWindow, SW_SHOWMINNOACTIVE};
+
+    // SAFETY: both calls take no pointers; a null window (no console) is skipped.
+    unsafe {
+        let window = GetConsoleWindow();
+        if !window.is_null() {
+            ShowWindow(window, SW_SHOWMINNOACTIVE);
+        }
+    }
+}
+
+#[cfg(not(windows))]
+pub fn minimize_console() {}
@@ -0,0 +1,180 @@
+//! `--max-kbps`: a leaner wire format when the capture format would not fit the cap, and
+//! a token bucket that holds the stream to it whatever the codec does.
+
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+use crate::probe::UDP_IPV4_OVERHEAD;
+use crate::protocol::HEADER_SIZE;
+use crate::sender::StreamFormat;
+
+/// Sample rate divisors tried for PCM16, richest first.
+const DECIMATIONS: [u32; 5] = [1, 2, 3, 4, 6];
+/// Below this, speech stops being intelligible; the cap is left to the bucket instead.
+const MIN_SAMPLE_RATE: u32 = 8000;
+/// Filter taps per unit of decimation, on each side of the centre tap.
+const TAPS_PER_FACTOR: usize = 8;
+/// How far ahead of the cap the stream may run, to ride out capture bursts.
+const BURST: Duration = Duration::from_millis(250);
+
+/// Bitrate of `format` as PCM16 on the wire, with packet, sealing and UDP/IPv4 headers.
+pub fn pcm16_kbps(format: StreamFormat, overhead: usize) -> f64 {
+    let packet = HEADER_SIZE + format.samples_per_packet() * 2 + overhead + UDP_IPV4_OVERHEAD;
+    let packets_per_sec = format.sample_rate as f64 / format.samples_per_channel.max(1) as f64;
+    packet as f64 * 8.0 * packets_per_sec / 1000.0
+}
+
+/// The richest PCM16 format within `max_kbps` that `format` reduces to: itself, then mono,
+/// then lower sample rates that keep frames whole. The leanest of them when none fits.
+pub fn capped_format(format: StreamFormat, overhead: usize, max_kbps: u32) -> StreamFormat {
+    let mut candidates = Vec::new();
+    for factor in DECIMATIONS {
+        let sample_rate = format.sample_rate / factor;
+        if factor > 1
+            && (sample_rate < MIN_SAMPLE_RATE
+                || !format.sample_rate.is_multiple_of(factor)
+                || !(format.samples_per_channel as u32).is_multiple_of(factor))
+        {
+            continue;
+        }
+        for channels in [format.channels, 1] {
+            let candidate = StreamFormat {
+                sample_rate,
+                channels,
+                samples_per_channel: (format.samples_per_channel as u32 / factor) as u16,
+            };
+            if !candidates.contains(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+    }
+    candidates.sort_by(|a, b| pcm16_kbps(*b, overhead).total_cmp(&pcm16_kbps(*a, overhead)));
+    let leanest = candidates[candidates.len() - 1];
+    candidates
+        .into_iter()
+        .find(|candidate| pcm16_kbps(*candidate, overhead) <= max_kbps as f64)
+        .unwrap_or(leanest)
+}
+
+/// Turns captured audio into a `capped_format`: averages the channels down to mono if asked,
+/// then low-passes and keeps every `factor`th frame.
+pub struct Reducer {
+    in_channels: usize,
+    out_channels: usize,
+    factor: usize,
+    taps: Vec<f32>,
+    /// The last `taps.len()` frames at the output channel count, newest last.
+    history: VecDeque<f32>,
+    /// Input frames until the next output frame.
+    skip: usize,
+}
+
+impl Reducer {
+    pub fn new(from: StreamFormat, to: StreamFormat) -> Self {
+        let factor = (from.sample_rate / to.sample_rate.max(1)).max(1) as usize;
+        let taps = low_pass(factor);
+        let out_channels = to.channels as usize;
+        Self {
+            in_channels: from.channels as usize,
+            out_channels,
+            factor,
+            history: VecDeque::from(vec![0.0; taps.len() * out_channels]),
+            taps,
+            skip: 0,
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut Vec<i16>) {
+        let mut out = 0;
+        for frame in 0..samples.len() / self.in_channels {
+            let input = &samples[frame * self.in_channels..(frame + 1) * self.in_channels];
+            for channel in 0..self.out_channels {
+                let value = if self.out_channels == self.in_channels {
+                    input[channel] as f32
+                } else {
+                    input.iter().map(|&s| s as f32).sum::<f32>() / self.in_channels as f32
+                };
+                self.history.pop_front();
+                self.history.push_back(value);
+            }
+            if self.skip > 0 {
+                self.skip -= 1;
+                continue;
+            }
+            self.skip = self.factor - 1;
+            for channel in 0..self.out_channels {
+                let value: f32 = self
+                    .taps
+                    .iter()
+                    .enumerate()
+                    .map(|(i, tap)| tap * self.history[i * self.out_channels + channel])
+                    .sum();
+                samples[out] = value.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+                out += 1;
+            }
+        }
+        samples.truncate(out);
+    }
+}
+
+/// Hann-windowed sinc passing up to 90% of the decimated Nyquist frequency, with unity
+/// gain; a single tap when nothing is decimated.
+fn low_pass(factor: usize) -> Vec<f32> {
+    if factor == 1 {
+        return vec![1.0];
+    }
+    let len = 2 * TAPS_PER_FACTOR * factor + 1;
+    let centre = (len / 2) as f32;
+    let cutoff = 0.9 * 0.5 / factor as f32;
+    let mut taps: Vec<f32> = (0..len)
+        .map(|i| {
+            let x = i as f32 - centre;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (2.0 * PI * cutoff * x).sin() / (2.0 * PI * cutoff * x)
+            };
+            let window = 0.5 - 0.5 * (2.0 * PI * i as f32 / (len - 1) as f32).cos();
+            sinc * window
+        })
+        .collect();
+    let sum: f32 = taps.iter().sum();
+    taps.iter_mut().for_each(|tap| *tap /= sum);
+    taps
+}
+
+/// Lets packets through at up to `max_kbps`, with `BURST` of slack.
+pub struct TokenBucket {
+    bytes_per_sec: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(max_kbps: u32) -> Self {
+        let bytes_per_sec = max_kbps as f64 * 1000.0 / 8.0;
+        let capacity = bytes_per_sec * BURST.as_secs_f64();
+        Self {
+            bytes_per_sec,
+            capacity,
+            tokens: capacity,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Takes a packet of `bytes` (UDP/IPv4 headers added here) if the cap has room for it.
+    pub fn admit(&mut self, bytes: usize) -> bool {
+        let now = Instant::now();
+        let elapsed = (now - self.refilled).as_secs_f64();
+        self.refilled = now;
+        self.tokens = (self.tokens + elapsed * self.bytes_per_sec).min(self.capacity);
+        let cost = (bytes + UDP_IPV4_OVERHEAD) as f64;
+        if self.tokens < cost {
+            return false;
+        }
+        self.tokens -= cost;
+        true
+    }
+}
@@ -0,0 +1,476 @@
+//! `--transport bluetooth`: the stream over RFCOMM to a paired phone, for when Wi-Fi is
+//! congested or missing. The link is bridged to a loopback TCP port, so the sender runs its
+//! TCP transport over it unchanged: framing, reports and pairing.
+
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use tracing::{info, warn};
+
+use crate::error::{error_chain, BluetoothError, Error, TransportError};
+
+/// The service the receiver registers over SDP, by which Windows finds its RFCOMM
+/// channel. The Android receiver listens with the same UUID.
+pub const SERVICE_UUID: u128 = 0x5b2f9a64_3c1e_4d8a_9f0b_6a7e2c1d4b58;
+/// How long `--bt-pair` searches for devices that are not paired yet, in 1.28 s steps.
+#[cfg_attr(not(windows), allow(dead_code))]
+const INQUIRY_MULTIPLIER: u8 = 8;
+
+/// A Bluetooth device address, most significant byte first as it is written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BtAddr([u8; 6]);
+
+impl BtAddr {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    fn from_u64(value: u64) -> Self {
+        let bytes = value.to_be_bytes();
+        Self([bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]])
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    fn to_u64(self) -> u64 {
+        self.0
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as u64)
+    }
+}
+
+impl FromStr for BtAddr {
+    type Err = BluetoothError;
+
+    /// Six hex pairs separated by `:` or `-`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BluetoothError::InvalidAddress(s.to_string());
+        let mut bytes = [0; 6];
+        let mut parts = s.split([':', '-']);
+        for byte in &mut bytes {
+            let part = parts
+                .next()
+                .filter(|part| part.len() == 2)
+                .ok_or_else(invalid)?;
+            *byte = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+        }
+        match parts.next() {
+            Some(_) => Err(invalid()),
+            None => Ok(Self(bytes)),
+        }
+    }
+}
+
+impl fmt::Display for BtAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02X}:{b:02X}:{c:02X}:{d:02X}:{e:02X}:{g:02X}")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Device {
+    pub addr: BtAddr,
+    /// Empty where the system cannot list devices.
+    pub name: String,
+    pub paired: bool,
+    pub connected: bool,
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{} ({})", self.name, self.addr)
+        }
+    }
+}
+
+/// The devices this machine knows: paired, remembered or connected.
+pub fn devices() -> Result<Vec<Device>, BluetoothError> {
+    sys::devices(false)
+}
+
+/// The known device with address or name `selector`; names match without regard to case.
+pub fn find(selector: &str) -> Result<Device, BluetoothError> {
+    let addr = selector.parse::<BtAddr>().ok();
+    match sys::devices(false) {
+        Ok(devices) => devices
+            .into_iter()
+            .find(|device| Some(device.addr) == addr || device.name.eq_ignore_ascii_case(selector))
+            .ok_or_else(|| BluetoothError::NotFound(selector.to_string())),
+        // An address is enough to connect where devices cannot be listed.
+        Err(BluetoothError::Unsupported(_)) if addr.is_some() => Ok(Device {
+            addr: addr.unwrap(),
+            name: String::new(),
+            paired: true,
+            connected: false,
+        }),
+        Err(err) => Err(err),
+    }
+}
+
+/// Searches for the device with address or name `selector` and pairs with it, with
+/// Windows asking the user to confirm the code on both ends. A device paired already is
+/// left as it is.
+pub fn pair(selector: &str) -> Result<Device, BluetoothError> {
+    let addr = selector.parse::<BtAddr>().ok();
+    let mut device = sys::devices(true)?
+        .into_iter()
+        .find(|device| Some(device.addr) == addr || device.name.eq_ignore_ascii_case(selector))
+        .ok_or_else(|| BluetoothError::NotFound(selector.to_string()))?;
+    if !device.paired {
+        sys::pair(&device)?;
+        device.paired = true;
+    }
+    Ok(device)
+}
+
+/// Bridges connections to a loopback port to RFCOMM links to one device, a new link for
+/// each connection, so that reconnects of the TCP transport reconnect the link too.
+#[derive(Debug)]
+pub struct Relay {
+    addr: SocketAddr,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Relay {
+    /// Connects to `device` once up front, so that a phone out of reach fails the start
+    /// rather than every reconnect; `channel` skips the SDP lookup of the receiver.
+    pub fn start(device: Device, channel: Option<u8>) -> Result<Self, Error> {
+        let link = sys::connect(&device, channel)?;
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).map_err(TransportError::Bind)?;
+        let addr = listener.local_addr().map_err(TransportError::Bind)?;
+        info!("Bluetooth: connected to {device}, relayed through {addr}");
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = Arc::clone(&running);
+        let thread = thread::Builder::new()
+            .name("bluetooth".to_string())
+            .spawn(move || relay(&listener, link, &device, channel, &thread_running))
+            .map_err(|source| Error::Spawn {
+                name: "bluetooth",
+                source,
+            })?;
+        Ok(Self {
+            addr,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// Where the TCP transport connects.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        // Wakes the accept.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn relay(
+    listener: &TcpListener,
+    link: TcpStream,
+    device: &Device,
+    channel: Option<u8>,
+    running: &AtomicBool,
+) {
+    let mut first = Some(link);
+    for client in listener.incoming() {
+        if !running.load(Ordering::Relaxed) {
+            break;
+        }
+        let Ok(client) = client else {
+            continue;
+        };
+        let link = match first.take() {
+            Some(link) => link,
+            None => match sys::connect(device, channel) {
+                Ok(link) => link,
+                // Dropping the client has the transport retry with its backoff.
+                Err(err) => {
+                    warn!("Bluetooth: {}", error_chain(&err));
+                    continue;
+                }
+            },
+        };
+        if let Err(err) = bridge(client, link) {
+            warn!("Bluetooth: failed to relay the link: {err}");
+        }
+    }
+}
+
+/// Copies both ways until either side ends, then ends the other.
+fn bridge(client: TcpStream, link: TcpStream) -> io::Result<()> {
+    client.set_nodelay(true)?;
+    for (mut from, mut to) in [(client.try_clone()?, link.try_clone()?), (link, client)] {
+        thread::Builder::new()
+            .name("bluetooth".to_string())
+            .spawn(move || {
+                let _ = io::copy(&mut from, &mut to);
+                let _ = from.shutdown(Shutdown::Both);
+                let _ = to.shutdown(Shutdown::Both);
+            })?;
+    }
+    Ok(())
+}
+
+/// Winsock's Bluetooth sockets and the Bluetooth APIs. The RFCOMM socket is handed out
+/// as a `TcpStream`, which only wraps the socket: reads, writes and shutdown work the
+/// same on it.
+#[cfg(windows)]
+mod sys {
+    use std::io;
+    use std::mem::{size_of, zeroed};
+    use std::net::TcpStream;
+    use std::os::windows::io::FromRawSocket;
+    use std::ptr;
+    use std::sync::Once;
+
+    use windows_sys::core::GUID;
+    use windows_sys::Win32::Devices::Bluetooth::{
+        BluetoothAuthenticateDeviceEx, BluetoothFindDeviceClose, BluetoothFindFirstDevice,
+        BluetoothFindNextDevice, MITMProtectionRequiredGeneralBonding, AF_BTH,
+        BLUETOOTH_DEVICE_INFO, BLUETOOTH_DEVICE_SEARCH_PARAMS, BTHPROTO_RFCOMM, SOCKADDR_BTH,
+    };
+    use windows_sys::Win32::Foundation::{ERROR_NO_MORE_ITEMS, ERROR_SUCCESS};
+    use windows_sys::Win32::Networking::WinSock::{
+        closesocket, connect as connect_socket, socket, WSAGetLastError, WSAStartup,
+        INVALID_SOCKET, SOCKADDR, SOCK_STREAM, WSADATA,
+    };
+
+    use super::{BtAddr, Device, INQUIRY_MULTIPLIER, SERVICE_UUID};
+    use crate::error::BluetoothError;
+
+    pub(super) fn devices(inquiry: bool) -> Result<Vec<Device>, BluetoothError> {
+        let params = BLUETOOTH_DEVICE_SEARCH_PARAMS {
+            dwSize: size_of::<BLUETOOTH_DEVICE_SEARCH_PARAMS>() as u32,
+            fReturnAuthenticated: 1,
+            fReturnRemembered: 1,
+            fReturnUnknown: inquiry as i32,
+            fReturnConnected: 1,
+            fIssueInquiry: inquiry as i32,
+            cTimeoutMultiplier: if inquiry { INQUIRY_MULTIPLIER } else { 0 },
+            // Every radio.
+            hRadio: ptr::null_mut(),
+        };
+        // SAFETY: all zeros is a valid device info.
+        let mut info: BLUETOOTH_DEVICE_INFO = unsafe { zeroed() };
+        info.dwSize = size_of::<BLUETOOTH_DEVICE_INFO>() as u32;
+        // SAFETY: both structs are sized and outlive the call.
+        let find = unsafe { BluetoothFindFirstDevice(&params, &mut info) };
+        if find.is_null() {
+            let err = io::Error::last_os_error();
+            return if err.raw_os_error() == Some(ERROR_NO_MORE_ITEMS as i32) {
+                Ok(Vec::new())
+            } else {
+                Err(BluetoothError::List(err))
+            };
+        }
+        let mut devices = vec![device(&info)];
+        // SAFETY: `find` stays open until closed below, and `info` outlives each call.
+        while unsafe { BluetoothFindNextDevice(find, &mut info) } != 0 {
+            devices.push(device(&info));
+        }
+        unsafe { BluetoothFindDeviceClose(find) };
+        Ok(devices)
+    }
+
+    fn device(info: &BLUETOOTH_DEVICE_INFO) -> Device {
+        let len = info
+            .szName
+            .iter()
+            .position(|&unit| unit == 0)
+            .unwrap_or(info.szName.len());
+        Device {
+            // SAFETY: the address is filled in as a whole, and every bit pattern is a u64.
+            addr: BtAddr::from_u64(unsafe { info.Address.Anonymous.ullLong }),
+            name: String::from_utf16_lossy(&info.szName[..len]),
+            paired: info.fAuthenticated != 0,
+            connected: info.fConnected != 0,
+        }
+    }
+
+    pub(super) fn pair(device: &Device) -> Result<(), BluetoothError> {
+        // SAFETY: all zeros is a valid device info.
+        let mut info: BLUETOOTH_DEVICE_INFO = unsafe { zeroed() };
+        info.dwSize = size_of::<BLUETOOTH_DEVICE_INFO>() as u32;
+        info.Address.Anonymous.ullLong = device.addr.to_u64();
+        // SAFETY: `info` outlives the call; without a window or radio Windows picks the
+        // radio and shows its own prompt.
+        let rc = unsafe {
+            BluetoothAuthenticateDeviceEx(
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut info,
+                ptr::null(),
+                MITMProtectionRequiredGeneralBonding,
+            )
+        };
+        if rc != ERROR_SUCCESS {
+            return Err(BluetoothError::Pair {
+                device: device.to_string(),
+                source: io::Error::from_raw_os_error(rc as i32),
+            });
+        }
+        Ok(())
+    }
+
+    pub(super) fn connect(
+        device: &Device,
+        channel: Option<u8>,
+    ) -> Result<TcpStream, BluetoothError> {
+        // std starts Winsock on its first socket, which may not have been made yet.
+        static STARTUP: Once = Once::new();
+        STARTUP.call_once(|| {
+            // SAFETY: `data` outlives the call.
+            let mut data: WSADATA = unsafe { zeroed() };
+            unsafe { WSAStartup(0x202, &mut data) };
+        });
+        let error = |source| BluetoothError::Connect {
+            device: device.to_string(),
+            source,
+        };
+        // SAFETY: plain socket creation.
+        let socket = unsafe { socket(AF_BTH as i32, SOCK_STREAM, BTHPROTO_RFCOMM as i32) };
+        if socket == INVALID_SOCKET {
+            return Err(error(io::Error::from_raw_os_error(unsafe {
+                WSAGetLastError()
+            })));
+        }
+        // Port 0 has Windows look the channel up by the service UUID.
+        let addr = SOCKADDR_BTH {
+            addressFamily: AF_BTH,
+            btAddr: device.addr.to_u64(),
+            serviceClassId: GUID::from_u128(SERVICE_UUID),
+            port: channel.map_or(0, u32::from),
+        };
+        // SAFETY: `addr` outlives the call and the length matches it.
+        let rc = unsafe {
+            connect_socket(
+                socket,
+                (&addr as *const SOCKADDR_BTH).cast::<SOCKADDR>(),
+                size_of::<SOCKADDR_BTH>() as i32,
+            )
+        };
+        if rc != 0 {
+            let err = io::Error::from_raw_os_error(unsafe { WSAGetLastError() });
+            unsafe { closesocket(socket) };
+            return Err(error(err));
+        }
+        // SAFETY: the socket is open and owned by nothing else.
+        Ok(unsafe { TcpStream::from_raw_socket(socket as u64) })
+    }
+}
+
+/// BlueZ's RFCOMM sockets. Listing and pairing are left to `bluetoothctl`, and without
+/// an SDP lookup the channel has to be given.
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use std::mem::size_of;
+    use std::net::TcpStream;
+    use std::os::fd::FromRawFd;
+
+    use super::Device;
+    use crate::error::BluetoothError;
+
+    const BTPROTO_RFCOMM: libc::c_int = 3;
+
+    #[repr(C)]
+    struct SockaddrRc {
+        rc_family: libc::sa_family_t,
+        /// Least significant byte first.
+        rc_bdaddr: [u8; 6],
+        rc_channel: u8,
+    }
+
+    pub(super) fn devices(_inquiry: bool) -> Result<Vec<Device>, BluetoothError> {
+        Err(BluetoothError::Unsupported("listing Bluetooth devices"))
+    }
+
+    pub(super) fn pair(_device: &Device) -> Result<(), BluetoothError> {
+        Err(BluetoothError::Unsupported("pairing Bluetooth devices"))
+    }
+
+    pub(super) fn connect(
+        device: &Device,
+        channel: Option<u8>,
+    ) -> Result<TcpStream, BluetoothError> {
+        let Some(channel) = channel else {
+            return Err(BluetoothError::NoChannel {
+                device: device.to_string(),
+            });
+        };
+        let error = |source| BluetoothError::Connect {
+            device: device.to_string(),
+            source,
+        };
+        // SAFETY: plain socket creation.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_BLUETOOTH,
+                libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+                BTPROTO_RFCOMM,
+            )
+        };
+        if fd < 0 {
+            return Err(error(io::Error::last_os_error()));
+        }
+        let mut bdaddr = device.addr.0;
+        bdaddr.reverse();
+        let addr = SockaddrRc {
+            rc_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+            rc_bdaddr: bdaddr,
+            rc_channel: channel,
+        };
+        // SAFETY: `addr` outlives the call and the length matches it.
+        let rc = unsafe {
+            libc::connect(
+                fd,
+                (&addr as *const SockaddrRc).cast(),
+                size_of::<SockaddrRc>() as libc::socklen_t,
+            )
+        };
+        if rc < 0 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(error(err));
+        }
+        // SAFETY: the fd is open and owned by nothing else.
+        Ok(unsafe { TcpStream::from_raw_fd(fd) })
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+mod sys {
+    use std::net::TcpStream;
+
+    use super::Device;
+    use crate::error::BluetoothError;
+
+    pub(super) fn devices(_inquiry: bool) -> Result<Vec<Device>, BluetoothError> {
+        Err(BluetoothError::Unsupported("listing Bluetooth devices"))
+    }
+
+    pub(super) fn pair(_device: &Device) -> Result<(), BluetoothError> {
+        Err(BluetoothError::Unsupported("pairing Bluetooth devices"))
+    }
+
+    pub(super) fn connect(
+        _device: &Device,
+        _channel: Option<u8>,
+    ) -> Result<TcpStream, BluetoothError> {
+        Err(BluetoothError::Unsupported("streaming over Bluetooth"))
+    }
+}
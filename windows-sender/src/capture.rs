@@ -0,0 +1,386 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, Sender};
+use tracing::warn;
+
+use crate::error::CaptureError;
+use crate::sched::Scheduling;
+use crate::stats::{SenderStats, Stage};
+use crate::timer::{Resolution, Sleeper};
+
+#[cfg(desktop_backend)]
+mod desktop;
+mod file;
+#[cfg(mic_backend)]
+mod mic;
+mod stress;
+mod tone;
+
+#[cfg(desktop_backend)]
+pub use desktop::{desktop_devices, start_desktop_capture};
+pub use file::start_file_capture;
+#[cfg(mic_backend)]
+pub use mic::start_mic_capture;
+pub use stress::{start_stress_capture, STRESS_CHANNELS, STRESS_SAMPLE_RATE};
+pub use tone::start_tone_capture;
+
+pub const DESKTOP_SAMPLE_RATE: u32 = 48_000;
+pub const DESKTOP_CHANNELS: usize = 2;
+/// How much audio the queue to the send loop holds without `--max-buffered-ms`.
+pub const DEFAULT_QUEUE_DEPTH: Duration = Duration::from_secs(5);
+
+/// A render device desktop capture can record from, by the name `desktop_device` takes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DesktopDevice {
+    pub name: String,
+    pub is_default: bool,
+}
+
+impl DesktopDevice {
+    pub fn virtual_cable(&self) -> Option<VirtualCable> {
+        VirtualCable::detect(&self.name)
+    }
+}
+
+/// A virtual render device: apps set to play into it are heard nowhere else, so
+/// capturing it streams those apps alone.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VirtualCable {
+    VbCable,
+    VoiceMeeter,
+    SteamStreaming,
+}
+
+impl VirtualCable {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VirtualCable::VbCable => "VB-Cable",
+            VirtualCable::VoiceMeeter => "VoiceMeeter",
+            VirtualCable::SteamStreaming => "Steam Streaming Speakers",
+        }
+    }
+
+    /// Recognizes a render device by the name its driver gives it, such as
+    /// `CABLE Input (VB-Audio Virtual Cable)`.
+    pub fn detect(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        // VoiceMeeter's devices are VB-Audio's too.
+        if name.contains("voicemeeter") {
+            Some(VirtualCable::VoiceMeeter)
+        } else if name.contains("vb-audio") || name.starts_with("cable") {
+            Some(VirtualCable::VbCable)
+        } else if name.contains("steam streaming speakers") {
+            Some(VirtualCable::SteamStreaming)
+        } else {
+            None
+        }
+    }
+}
+
+/// Without the desktop backend there is nothing to list.
+#[cfg(not(desktop_backend))]
+pub fn desktop_devices() -> Result<Vec<DesktopDevice>, CaptureError> {
+    Err(CaptureError::Unsupported("desktop"))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SourceKind {
+    Desktop,
+    Mic,
+    /// Synthetic audio at a high packet rate, for soak tests without devices.
+    Stress,
+    /// A steady test tone.
+    Tone,
+    /// A WAV file played in a loop.
+    File,
+}
+
+impl SourceKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SourceKind::Desktop => "desktop",
+            SourceKind::Mic => "mic",
+            SourceKind::Stress => "stress",
+            SourceKind::Tone => "tone",
+            SourceKind::File => "file",
+        }
+    }
+}
+
+pub enum CaptureGuard {
+    #[cfg(mic_backend)]
+    Mic(cpal::Stream),
+    /// A capture thread, which stops once `running` is cleared.
+    Thread(thread::JoinHandle<()>),
+}
+
+impl CaptureGuard {
+    pub fn keepalive_ref(&self) {
+        match self {
+            #[cfg(mic_backend)]
+            CaptureGuard::Mic(stream) => {
+                let _ = stream;
+            }
+            CaptureGuard::Thread(handle) => {
+                let _ = handle;
+            }
+        }
+    }
+}
+
+pub struct CaptureSetup {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub source_name: String,
+    pub guard: CaptureGuard,
+}
+
+pub struct CaptureChunk {
+    pub samples: Vec<i16>,
+    pub captured_at: Instant,
+}
+
+/// What the capture does with a new chunk when the queue to the send loop is full.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum QueueOverflow {
+    /// Drops the new chunk and keeps the older audio already waiting.
+    #[default]
+    DropNewest,
+    /// Drops the oldest waiting chunk to make room for the new one.
+    OverwriteOldest,
+    /// Drops everything waiting, so the send loop picks up from the new chunk.
+    CollapseToLatest,
+}
+
+/// The capture end of the queue to the send loop.
+///
+/// The queue holds up to `depth` of audio, whatever the chunk size, rate and channel
+/// count of the capture; the samples waiting are the ones `SenderStats::queued_samples`
+/// counts, which the send loop takes off as it receives.
+#[derive(Clone)]
+pub struct CaptureQueue {
+    tx: Sender<CaptureChunk>,
+    /// Takes chunks back out to make room; see `QueueOverflow`.
+    rx: Receiver<CaptureChunk>,
+    overflow: QueueOverflow,
+    depth: Duration,
+    /// `depth` in samples once the capture format is known; unbounded until then.
+    capacity: Arc<AtomicUsize>,
+}
+
+impl CaptureQueue {
+    pub fn new(
+        tx: Sender<CaptureChunk>,
+        rx: Receiver<CaptureChunk>,
+        overflow: QueueOverflow,
+        depth: Duration,
+    ) -> Self {
+        Self {
+            tx,
+            rx,
+            overflow,
+            depth,
+            capacity: Arc::new(AtomicUsize::new(usize::MAX)),
+        }
+    }
+
+    /// Sizes the queue for audio at `sample_rate` with `channels` interleaved channels.
+    pub fn fit(&self, sample_rate: u32, channels: usize) {
+        let frames = self.depth.as_micros() as u64 * sample_rate as u64 / 1_000_000;
+        self.capacity
+            .store(frames as usize * channels, Ordering::Relaxed);
+    }
+
+    /// Whether `len` more samples fit; a chunk always fits an empty queue.
+    fn has_room(&self, stats: &SenderStats, len: usize) -> bool {
+        let queued = stats.queued_samples.load(Ordering::Relaxed) as usize;
+        queued == 0 || queued + len <= self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Counts `samples` into `stats` and hands them to the send loop without blocking the
+    /// capture callback, which began at `called_at` and spent the time since converting
+    /// them.
+    pub fn push(&self, stats: &SenderStats, samples: Vec<i16>, called_at: Instant) {
+        stats.stages.record(Stage::Convert, called_at.elapsed());
+        self.enqueue(stats, samples);
+        stats.stages.record(Stage::Capture, called_at.elapsed());
+    }
+
+    fn enqueue(&self, stats: &SenderStats, samples: Vec<i16>) {
+        if samples.is_empty() {
+            return;
+        }
+
+        let mut abs_sum = 0_u64;
+        let mut nonzero = 0_u64;
+        for s in &samples {
+            let v = *s as i32;
+            abs_sum += v.unsigned_abs() as u64;
+            if *s != 0 {
+                nonzero += 1;
+            }
+        }
+        stats
+            .captured_samples
+            .fetch_add(samples.len() as u64, Ordering::Relaxed);
+        stats.captured_abs_sum.fetch_add(abs_sum, Ordering::Relaxed);
+        stats
+            .captured_nonzero_samples
+            .fetch_add(nonzero, Ordering::Relaxed);
+        let len = samples.len();
+        let discard = |chunk: CaptureChunk| {
+            stats
+                .queued_samples
+                .fetch_sub(chunk.samples.len() as u64, Ordering::Relaxed);
+        };
+        // Makes room until the chunk fits, taking out the oldest first.
+        let mut discarded = 0;
+        while !self.has_room(stats, len) {
+            let made = match self.overflow {
+                QueueOverflow::DropNewest => 0,
+                QueueOverflow::OverwriteOldest => {
+                    self.rx.try_recv().into_iter().map(discard).count()
+                }
+                QueueOverflow::CollapseToLatest => self.rx.try_iter().map(discard).count(),
+            };
+            if made == 0 {
+                break;
+            }
+            discarded += made;
+        }
+        let fits = self.has_room(stats, len);
+        let dropped = discarded + usize::from(!fits);
+        if dropped > 0 {
+            stats
+                .capture_drops
+                .fetch_add(dropped as u64, Ordering::Relaxed);
+            stats.drop_bursts.record();
+        }
+        if !fits {
+            return;
+        }
+
+        // Counted before it can be received, so the send loop never takes off more than
+        // was added.
+        stats
+            .queued_samples
+            .fetch_add(len as u64, Ordering::Relaxed);
+        let chunk = CaptureChunk {
+            samples,
+            captured_at: Instant::now(),
+        };
+        if self.tx.send(chunk).is_err() {
+            stats
+                .queued_samples
+                .fetch_sub(len as u64, Ordering::Relaxed);
+            return;
+        }
+        stats.captured_chunks.fetch_add(1, Ordering::Relaxed);
+        stats
+            .backlog_high_water
+            .fetch_max(self.tx.len() as u64, Ordering::Relaxed);
+    }
+}
+
+/// A float sample from a capture device as 16-bit PCM, clipped to full scale. Scaled as
+/// Windows scales 16-bit audio to float, so that it comes back bit for bit, as IEC 61937
+/// bitstreams have to.
+pub fn sample_from_f32(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * 32768.0).round() as i16
+}
+
+/// An unsigned (offset binary) 16-bit sample as signed PCM.
+pub fn sample_from_u16(sample: u16) -> i16 {
+    (sample as i32 - 32768) as i16
+}
+
+/// Further behind than this (a suspended machine, a debugger) a clocked source skips ahead
+/// rather than delivering the backlog in one burst.
+const MAX_CATCH_UP: Duration = Duration::from_secs(1);
+
+/// Delivers audio from a source with no device behind it on the system clock, `fill`
+/// writing the frames of each `period_frames` period until `running` is cleared.
+fn run_clocked(
+    queue: &CaptureQueue,
+    stats: &SenderStats,
+    running: &AtomicBool,
+    sample_rate: u32,
+    channels: usize,
+    period_frames: usize,
+    mut fill: impl FnMut(&mut Vec<i16>, usize),
+) {
+    let _resolution = Resolution::fine();
+    let sleeper = Sleeper::new();
+    let period = Duration::from_secs_f64(period_frames as f64 / sample_rate as f64);
+    let mut next = Instant::now();
+
+    while running.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if next > now {
+            sleeper.sleep(next - now);
+            continue;
+        }
+        if now - next > MAX_CATCH_UP {
+            warn!(
+                "generator fell {} ms behind; skipping ahead",
+                (now - next).as_millis()
+            );
+            next = now;
+        }
+        let mut periods = 0;
+        while next <= now {
+            periods += 1;
+            next += period;
+        }
+        let mut chunk = Vec::with_capacity(periods * period_frames * channels);
+        fill(&mut chunk, periods * period_frames);
+        queue.push(stats, chunk, now);
+    }
+}
+
+/// Starts capturing from `source`, or fails if its backend is not part of this build.
+///
+/// `running` stops the desktop and generated sources' threads; the microphone stops when
+/// the guard drops.
+/// The thread that delivers the audio runs with `scheduling`.
+#[allow(clippy::too_many_arguments)]
+pub fn start_capture(
+    source: SourceKind,
+    queue: CaptureQueue,
+    stats: Arc<SenderStats>,
+    desktop_device: Option<&str>,
+    source_file: Option<&Path>,
+    running: Arc<AtomicBool>,
+    scheduling: &Scheduling,
+) -> Result<CaptureSetup, CaptureError> {
+    match source {
+        #[cfg(mic_backend)]
+        SourceKind::Mic => start_mic_capture(queue, stats, scheduling),
+        SourceKind::Stress => start_stress_capture(queue, stats, running, scheduling),
+        SourceKind::Tone => start_tone_capture(queue, stats, running, scheduling),
+        SourceKind::File => {
+            let path = source_file.ok_or(CaptureError::NoSourceFile)?;
+            start_file_capture(path, queue, stats, running, scheduling)
+        }
+        #[cfg(desktop_backend)]
+        SourceKind::Desktop => {
+            start_desktop_capture(queue, stats, desktop_device, running, scheduling)
+        }
+        #[allow(unreachable_patterns)]
+        other => {
+            let _ = (
+                queue,
+                stats,
+                desktop_device,
+                source_file,
+                running,
+                scheduling,
+            );
+            Err(CaptureError::Unsupported(other.as_str()))
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc as std_mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, error, info_span, warn};
+use wasapi::{
+    BufferFlags, DeviceEnumerator, Direction as WasapiDirection, SampleType, StreamMode, WaveFormat,
+};
+
+use super::{
+    sample_from_f32, CaptureGuard, CaptureQueue, CaptureSetup, DesktopDevice, DESKTOP_CHANNELS,
+    DESKTOP_SAMPLE_RATE,
+};
+use crate::error::{error_chain, CaptureError};
+use crate::sched::Scheduling;
+use crate::stats::SenderStats;
+
+/// Consecutive read failures after which the loopback device is considered gone.
+const MAX_READ_ERRORS: u32 = 50;
+
+/// The render devices of this machine, skipping any whose name cannot be read.
+pub fn desktop_devices() -> Result<Vec<DesktopDevice>, CaptureError> {
+    wasapi::initialize_mta()
+        .ok()
+        .map_err(|e| CaptureError::wasapi("failed to initialize COM MTA for WASAPI", e))?;
+    let enumerator = DeviceEnumerator::new()
+        .map_err(|e| CaptureError::wasapi("failed to create WASAPI device enumerator", e))?;
+    let default = enumerator
+        .get_default_device(&WasapiDirection::Render)
+        .ok()
+        .and_then(|device| device.get_friendlyname().ok());
+    let collection = enumerator
+        .get_device_collection(&WasapiDirection::Render)
+        .map_err(|e| CaptureError::wasapi("failed to get render device collection", e))?;
+    let mut devices = Vec::new();
+    for device in &collection {
+        let name = match device.and_then(|device| device.get_friendlyname()) {
+            Ok(name) => name,
+            Err(err) => {
+                warn!("skipping unreadable render device: {err}");
+                continue;
+            }
+        };
+        devices.push(DesktopDevice {
+            is_default: default.as_ref() == Some(&name),
+            name,
+        });
+    }
+    Ok(devices)
+}
+
+pub fn start_desktop_capture(
+    queue: CaptureQueue,
+    stats: Arc<SenderStats>,
+    desktop_device_name: Option<&str>,
+    running: Arc<AtomicBool>,
+    scheduling: &Scheduling,
+) -> Result<CaptureSetup, CaptureError> {
+    let sample_rate = DESKTOP_SAMPLE_RATE;
+    let channels = DESKTOP_CHANNELS;
+    let (ready_tx, ready_rx) = std_mpsc::sync_channel::<Result<String, String>>(1);
+    let desktop_device_name_owned = desktop_device_name.map(|s| s.to_string());
+
+    let span = info_span!("capture", source = "desktop");
+    let scheduling = scheduling.clone();
+    let handle = thread::Builder::new()
+        .name("wasapi-loopback".to_string())
+        .spawn(move || {
+            let _span = span.entered();
+            scheduling.apply("wasapi-loopback");
+            desktop_capture_loop(
+                queue,
+                stats,
+                sample_rate,
+                channels,
+                desktop_device_name_owned,
+                ready_tx,
+                running,
+            );
+        })
+        .map_err(CaptureError::SpawnThread)?;
+
+    let source_name = ready_rx
+        .recv_timeout(Duration::from_secs(5))
+        .map_err(|_| CaptureError::InitTimeout)?
+        .map_err(CaptureError::Init)?;
+
+    Ok(CaptureSetup {
+        sample_rate,
+        channels,
+        source_name,
+        guard: CaptureGuard::Thread(handle),
+    })
+}
+
+fn desktop_capture_loop(
+    queue: CaptureQueue,
+    stats: Arc<SenderStats>,
+    sample_rate: u32,
+    channels: usize,
+    desktop_device_name: Option<String>,
+    ready_tx: std_mpsc::SyncSender<Result<String, String>>,
+    running: Arc<AtomicBool>,
+) {
+    if let Err(err) = desktop_capture_inner(
+        queue,
+        stats,
+        sample_rate,
+        channels,
+        desktop_device_name.as_deref(),
+        &ready_tx,
+        &running,
+    ) {
+        let msg = error_chain(&err);
+        let _ = ready_tx.send(Err(msg.clone()));
+        error!("desktop loopback stopped: {msg}");
+    }
+}
+
+fn desktop_capture_inner(
+    queue: CaptureQueue,
+    stats: Arc<SenderStats>,
+    sample_rate: u32,
+    channels: usize,
+    desktop_device_name: Option<&str>,
+    ready_tx: &std_mpsc::SyncSender<Result<String, String>>,
+    running: &AtomicBool,
+) -> Result<(), CaptureError> {
+    wasapi::initialize_mta()
+        .ok()
+        .map_err(|e| CaptureError::wasapi("failed to initialize COM MTA for WASAPI", e))?;
+
+    let enumerator = DeviceEnumerator::new()
+        .map_err(|e| CaptureError::wasapi("failed to create WASAPI device enumerator", e))?;
+    let device = if let Some(name) = desktop_device_name {
+        let collection = enumerator
+            .get_device_collection(&WasapiDirection::Render)
+            .map_err(|e| CaptureError::wasapi("failed to get render device collection", e))?;
+        collection.get_device_with_name(name).map_err(|e| {
+            CaptureError::wasapi(
+                format!("failed to find render device with name '{name}'"),
+                e,
+            )
+        })?
+    } else {
+        enumerator
+            .get_default_device(&WasapiDirection::Render)
+            .map_err(|e| CaptureError::wasapi("failed to get default render device", e))?
+    };
+    let device_name = device
+        .get_friendlyname()
+        .unwrap_or_else(|_| "default render device".to_string());
+
+    let mut audio_client = device
+        .get_iaudioclient()
+        .map_err(|e| CaptureError::wasapi("failed to get IAudioClient", e))?;
+    let desired_format = WaveFormat::new(
+        32,
+        32,
+        &SampleType::Float,
+        sample_rate as usize,
+        channels,
+        None,
+    );
+    let mode = StreamMode::EventsShared {
+        autoconvert: true,
+        buffer_duration_hns: 0,
+    };
+    audio_client
+        .initialize_client(&desired_format, &WasapiDirection::Capture, &mode)
+        .map_err(|e| CaptureError::wasapi("failed to initialize desktop loopback client", e))?;
+    let event = audio_client
+        .set_get_eventhandle()
+        .map_err(|e| CaptureError::wasapi("failed to set WASAPI event handle", e))?;
+    let capture_client = audio_client
+        .get_audiocaptureclient()
+        .map_err(|e| CaptureError::wasapi("failed to get WASAPI capture client", e))?;
+    audio_client
+        .start_stream()
+        .map_err(|e| CaptureError::wasapi("failed to start desktop loopback stream", e))?;
+
+    let _ = ready_tx.send(Ok(device_name));
+
+    let mut byte_queue = VecDeque::<u8>::with_capacity(32 * 1024);
+    let frame_bytes = channels * 4;
+    let mut read_errors = 0;
+
+    while running.load(Ordering::Relaxed) {
+        if let Err(err) = event.wait_for_event(1000) {
+            debug!("desktop loopback event wait timeout/error: {err}");
+            continue;
+        }
+        let called_at = Instant::now();
+
+        let info = match capture_client.read_from_device_to_deque(&mut byte_queue) {
+            Ok(info) => info,
+            Err(err) => {
+                read_errors += 1;
+                if read_errors >= MAX_READ_ERRORS {
+                    let _ = audio_client.stop_stream();
+                    return Err(CaptureError::wasapi(
+                        "desktop loopback read failed repeatedly",
+                        err,
+                    ));
+                }
+                warn!("desktop loopback read error: {err}");
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+        };
+        read_errors = 0;
+        count_buffer_flags(&stats, &info.flags);
+
+        if byte_queue.len() < frame_bytes {
+            continue;
+        }
+
+        let available_frames = byte_queue.len() / frame_bytes;
+        let mut chunk = Vec::<i16>::with_capacity(available_frames * channels);
+        for _ in 0..available_frames {
+            for _ in 0..channels {
+                let sample = pop_f32_le(&mut byte_queue).unwrap_or(0.0);
+                chunk.push(sample_from_f32(sample));
+            }
+        }
+        queue.push(&stats, chunk, called_at);
+    }
+
+    let _ = audio_client.stop_stream();
+    Ok(())
+}
+
+/// Driver-level glitches, kept apart from network trouble. Loopback also flags buffers
+/// silent whenever nothing is playing.
+fn count_buffer_flags(stats: &SenderStats, flags: &BufferFlags) {
+    let count = |counter: &AtomicU64, set: bool| {
+        if set {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    };
+    count(&stats.capture_discontinuities, flags.data_discontinuity);
+    count(&stats.capture_silent, flags.silent);
+    count(&stats.capture_timestamp_errors, flags.timestamp_error);
+}
+
+fn pop_f32_le(queue: &mut VecDeque<u8>) -> Option<f32> {
+    if queue.len() < 4 {
+        return None;
+    }
+    let b0 = queue.pop_front()?;
+    let b1 = queue.pop_front()?;
+    let b2 = queue.pop_front()?;
+    let b3 = queue.pop_front()?;
+    Some(f32::from_le_bytes([b0, b1, b2, b3]))
+}
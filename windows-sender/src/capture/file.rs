@@ -0,0 +1,151 @@
+//! A WAV file played in a loop as if a device were capturing it, for streaming a fixed
+//! recording or testing without a sound card.
+
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+
+use tracing::info_span;
+
+use super::{run_clocked, sample_from_f32, CaptureGuard, CaptureQueue, CaptureSetup};
+use crate::error::CaptureError;
+use crate::sched::Scheduling;
+use crate::stats::SenderStats;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// Periods of 10 ms, rounded down at rates that are not a multiple of 100 Hz.
+const PERIODS_PER_SECOND: usize = 100;
+
+struct Wav {
+    sample_rate: u32,
+    channels: usize,
+    samples: Vec<i16>,
+}
+
+pub fn start_file_capture(
+    path: &Path,
+    queue: CaptureQueue,
+    stats: Arc<SenderStats>,
+    running: Arc<AtomicBool>,
+    scheduling: &Scheduling,
+) -> Result<CaptureSetup, CaptureError> {
+    let bytes = fs::read(path).map_err(|source| CaptureError::ReadFile {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let Wav {
+        sample_rate,
+        channels,
+        samples,
+    } = parse_wav(&bytes).map_err(|reason| CaptureError::UnsupportedFile {
+        path: path.to_path_buf(),
+        reason,
+    })?;
+
+    let span = info_span!("capture", source = "file");
+    let scheduling = scheduling.clone();
+    let handle = thread::Builder::new()
+        .name("file-capture".to_string())
+        .spawn(move || {
+            let _span = span.entered();
+            scheduling.apply("file-capture");
+            let mut pos = 0;
+            run_clocked(
+                &queue,
+                &stats,
+                &running,
+                sample_rate,
+                channels,
+                (sample_rate as usize / PERIODS_PER_SECOND).max(1),
+                |chunk, frames| {
+                    let mut wanted = frames * channels;
+                    while wanted > 0 {
+                        let take = wanted.min(samples.len() - pos);
+                        chunk.extend_from_slice(&samples[pos..pos + take]);
+                        pos = (pos + take) % samples.len();
+                        wanted -= take;
+                    }
+                },
+            );
+        })
+        .map_err(CaptureError::SpawnThread)?;
+
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    Ok(CaptureSetup {
+        sample_rate,
+        channels,
+        source_name: format!("{} (looped)", name.to_string_lossy()),
+        guard: CaptureGuard::Thread(handle),
+    })
+}
+
+/// Reads 8, 16, 24 or 32-bit integer or 32-bit float PCM into 16-bit samples.
+fn parse_wav(bytes: &[u8]) -> Result<Wav, String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("no RIFF/WAVE header".to_string());
+    }
+    let u16_at = |data: &[u8], at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let u32_at = |data: &[u8], at: usize| {
+        u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+    };
+    let mut format = None;
+    let mut data = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let len = u32_at(bytes, pos + 4) as usize;
+        let body = &bytes[pos + 8..(pos + 8).saturating_add(len).min(bytes.len())];
+        match &bytes[pos..pos + 4] {
+            b"fmt " if body.len() >= 16 => {
+                let mut tag = u16_at(body, 0);
+                if tag == FORMAT_EXTENSIBLE && body.len() >= 26 {
+                    tag = u16_at(body, 24);
+                }
+                format = Some((tag, u16_at(body, 2), u32_at(body, 4), u16_at(body, 14)));
+            }
+            b"data" => data = Some(body),
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        pos = pos.saturating_add(8 + len + len % 2);
+    }
+    let (tag, channels, sample_rate, bits) = format.ok_or("no fmt chunk")?;
+    let data = data.ok_or("no data chunk")?;
+    if channels == 0 || channels > u8::MAX as u16 || sample_rate == 0 {
+        return Err(format!("{channels} channels at {sample_rate} Hz"));
+    }
+    let mut samples: Vec<i16> = match (tag, bits) {
+        (FORMAT_PCM, 8) => data.iter().map(|&s| (s as i16 - 128) << 8).collect(),
+        (FORMAT_PCM, 16) => data
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+            .collect(),
+        (FORMAT_PCM, 24) => data
+            .chunks_exact(3)
+            .map(|s| i16::from_le_bytes([s[1], s[2]]))
+            .collect(),
+        (FORMAT_PCM, 32) => data
+            .chunks_exact(4)
+            .map(|s| i16::from_le_bytes([s[2], s[3]]))
+            .collect(),
+        (FORMAT_FLOAT, 32) => data
+            .chunks_exact(4)
+            .map(|s| sample_from_f32(f32::from_le_bytes([s[0], s[1], s[2], s[3]])))
+            .collect(),
+        _ => return Err(format!("format {tag} with {bits}-bit samples")),
+    };
+    let channels = channels as usize;
+    if samples.len() < channels {
+        return Err("no audio".to_string());
+    }
+    // A partial frame at the end would shift the channels on every loop.
+    samples.truncate(samples.len() / channels * channels);
+    Ok(Wav {
+        sample_rate,
+        channels,
+        samples,
+    })
+}
@@ -0,0 +1,111 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{SampleFormat, StreamConfig};
+use tracing::warn;
+
+use super::{sample_from_f32, sample_from_u16, CaptureGuard, CaptureQueue, CaptureSetup};
+use crate::error::CaptureError;
+use crate::sched::Scheduling;
+use crate::stats::SenderStats;
+
+pub fn start_mic_capture(
+    queue: CaptureQueue,
+    stats: Arc<SenderStats>,
+    scheduling: &Scheduling,
+) -> Result<CaptureSetup, CaptureError> {
+    let host = cpal::default_host();
+    let device = host
+        .default_input_device()
+        .ok_or(CaptureError::NoInputDevice)?;
+    let device_name = device.name().unwrap_or_else(|_| "unknown".to_string());
+    let supported = device
+        .default_input_config()
+        .map_err(CaptureError::InputConfig)?;
+
+    let sample_format = supported.sample_format();
+    let config: StreamConfig = supported.into();
+    let sample_rate = config.sample_rate.0;
+    let channels = config.channels as usize;
+
+    let stream = build_input_stream(&device, &config, sample_format, queue, stats, scheduling)?;
+    stream.play().map_err(CaptureError::PlayStream)?;
+
+    Ok(CaptureSetup {
+        sample_rate,
+        channels,
+        source_name: device_name,
+        guard: CaptureGuard::Mic(stream),
+    })
+}
+
+fn build_input_stream(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    sample_format: SampleFormat,
+    queue: CaptureQueue,
+    stats: Arc<SenderStats>,
+    scheduling: &Scheduling,
+) -> Result<cpal::Stream, CaptureError> {
+    let err_fn = |err| warn!(source = "mic", "cpal stream error: {err}");
+
+    let stream = match sample_format {
+        SampleFormat::I16 => {
+            let queue = queue.clone();
+            let stats = Arc::clone(&stats);
+            let mut tune = scheduling.on_first_call("capture");
+            device.build_input_stream(
+                config,
+                move |data: &[i16], _| {
+                    let called_at = Instant::now();
+                    tune();
+                    queue.push(&stats, data.to_vec(), called_at);
+                },
+                err_fn,
+                None,
+            )
+        }
+        SampleFormat::U16 => {
+            let queue = queue.clone();
+            let stats = Arc::clone(&stats);
+            let mut tune = scheduling.on_first_call("capture");
+            device.build_input_stream(
+                config,
+                move |data: &[u16], _| {
+                    let called_at = Instant::now();
+                    tune();
+                    let converted = data
+                        .iter()
+                        .map(|s| sample_from_u16(*s))
+                        .collect::<Vec<i16>>();
+                    queue.push(&stats, converted, called_at);
+                },
+                err_fn,
+                None,
+            )
+        }
+        SampleFormat::F32 => {
+            let queue = queue.clone();
+            let stats = Arc::clone(&stats);
+            let mut tune = scheduling.on_first_call("capture");
+            device.build_input_stream(
+                config,
+                move |data: &[f32], _| {
+                    let called_at = Instant::now();
+                    tune();
+                    let converted = data
+                        .iter()
+                        .map(|s| sample_from_f32(*s))
+                        .collect::<Vec<i16>>();
+                    queue.push(&stats, converted, called_at);
+                },
+                err_fn,
+                None,
+            )
+        }
+        other => return Err(CaptureError::UnsupportedSampleFormat(other)),
+    };
+
+    stream.map_err(CaptureError::BuildStream)
+}
@@ -0,0 +1,76 @@
+//! Synthetic capture for soak tests: a tone on every channel, delivered in 1 ms periods on
+//! the audio clock with no device behind it, so hours of streaming exercise the queue, the
+//! packetizer and the transport at their highest rate.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use tracing::info_span;
+
+use super::{run_clocked, CaptureGuard, CaptureQueue, CaptureSetup};
+use crate::error::CaptureError;
+use crate::sched::Scheduling;
+use crate::stats::SenderStats;
+
+pub const STRESS_SAMPLE_RATE: u32 = 48_000;
+pub const STRESS_CHANNELS: usize = 8;
+
+/// The shortest period capture devices deliver in practice.
+const PERIOD: Duration = Duration::from_millis(1);
+const PERIOD_FRAMES: usize = STRESS_SAMPLE_RATE as usize / 1000;
+/// -12 dBFS.
+const AMPLITUDE: f32 = 0.25;
+
+pub fn start_stress_capture(
+    queue: CaptureQueue,
+    stats: Arc<SenderStats>,
+    running: Arc<AtomicBool>,
+    scheduling: &Scheduling,
+) -> Result<CaptureSetup, CaptureError> {
+    let span = info_span!("capture", source = "stress");
+    let scheduling = scheduling.clone();
+    let handle = thread::Builder::new()
+        .name("stress-capture".to_string())
+        .spawn(move || {
+            let _span = span.entered();
+            scheduling.apply("stress-capture");
+            generate(&queue, &stats, &running);
+        })
+        .map_err(CaptureError::SpawnThread)?;
+
+    Ok(CaptureSetup {
+        sample_rate: STRESS_SAMPLE_RATE,
+        channels: STRESS_CHANNELS,
+        source_name: format!(
+            "synthetic {STRESS_CHANNELS} ch @ {STRESS_SAMPLE_RATE} Hz, {} ms periods",
+            PERIOD.as_millis()
+        ),
+        guard: CaptureGuard::Thread(handle),
+    })
+}
+
+fn generate(queue: &CaptureQueue, stats: &SenderStats, running: &AtomicBool) {
+    // Channel N plays 220 Hz times N + 1, so a swapped or dropped channel is audible.
+    let steps: [f32; STRESS_CHANNELS] = std::array::from_fn(|ch| {
+        std::f32::consts::TAU * 220.0 * (ch + 1) as f32 / STRESS_SAMPLE_RATE as f32
+    });
+    let mut phases = [0.0f32; STRESS_CHANNELS];
+    run_clocked(
+        queue,
+        stats,
+        running,
+        STRESS_SAMPLE_RATE,
+        STRESS_CHANNELS,
+        PERIOD_FRAMES,
+        |chunk, frames| {
+            for _ in 0..frames {
+                for (phase, step) in phases.iter_mut().zip(&steps) {
+                    chunk.push((phase.sin() * AMPLITUDE * i16::MAX as f32) as i16);
+                    *phase = (*phase + step) % std::f32::consts::TAU;
+                }
+            }
+        },
+    );
+}
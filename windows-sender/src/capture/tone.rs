@@ -0,0 +1,60 @@
+//! A 440 Hz tone on both channels, to check a setup end to end without playing anything.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+
+use tracing::info_span;
+
+use super::{run_clocked, CaptureGuard, CaptureQueue, CaptureSetup};
+use crate::error::CaptureError;
+use crate::sched::Scheduling;
+use crate::stats::SenderStats;
+
+const SAMPLE_RATE: u32 = 48_000;
+const CHANNELS: usize = 2;
+const FREQUENCY_HZ: f32 = 440.0;
+const PERIOD_FRAMES: usize = SAMPLE_RATE as usize / 100;
+/// -18 dBFS, the usual alignment level.
+const AMPLITUDE: f32 = 0.125;
+
+pub fn start_tone_capture(
+    queue: CaptureQueue,
+    stats: Arc<SenderStats>,
+    running: Arc<AtomicBool>,
+    scheduling: &Scheduling,
+) -> Result<CaptureSetup, CaptureError> {
+    let span = info_span!("capture", source = "tone");
+    let scheduling = scheduling.clone();
+    let handle = thread::Builder::new()
+        .name("tone".to_string())
+        .spawn(move || {
+            let _span = span.entered();
+            scheduling.apply("tone");
+            let step = std::f32::consts::TAU * FREQUENCY_HZ / SAMPLE_RATE as f32;
+            let mut phase = 0.0f32;
+            run_clocked(
+                &queue,
+                &stats,
+                &running,
+                SAMPLE_RATE,
+                CHANNELS,
+                PERIOD_FRAMES,
+                |chunk, frames| {
+                    for _ in 0..frames {
+                        let sample = (phase.sin() * AMPLITUDE * i16::MAX as f32) as i16;
+                        chunk.extend([sample; CHANNELS]);
+                        phase = (phase + step) % std::f32::consts::TAU;
+                    }
+                },
+            );
+        })
+        .map_err(CaptureError::SpawnThread)?;
+
+    Ok(CaptureSetup {
+        sample_rate: SAMPLE_RATE,
+        channels: CHANNELS,
+        source_name: format!("{FREQUENCY_HZ} Hz tone @ {SAMPLE_RATE} Hz"),
+        guard: CaptureGuard::Thread(handle),
+    })
+}
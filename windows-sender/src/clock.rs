@@ -0,0 +1,181 @@
+//! Offset and drift between two clocks from NTP-style exchanges: a message leaves here at
+//! t1, arrives there at t2, the answer leaves there at t3 and arrives here at t4.
+//!
+//! The offset comes from the recent exchange with the shortest round trip, which queueing
+//! skewed the least. The drift is the slope through the best exchange of each stretch of
+//! the history, so the offset can be carried forward between exchanges.
+//!
+//! `DeviceDrift` does the same for an audio device against the monotonic clock, with the
+//! samples it delivers standing in for the exchanges.
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// Exchanges kept; a minute of history at four exchanges a second.
+const HISTORY: usize = 256;
+/// Exchanges the offset is picked from.
+const RECENT: usize = 16;
+/// Stretches of history the drift is fitted through.
+const DRIFT_POINTS: usize = 8;
+/// History needed before the drift is estimated; shorter spans drown in network noise.
+const MIN_DRIFT_SPAN_US: u64 = 10_000_000;
+/// Seconds of capture the device drift is fitted through, one point per second.
+const DEVICE_HISTORY: usize = 120;
+/// Seconds of capture needed before the device drift is estimated.
+const MIN_DEVICE_POINTS: usize = 10;
+/// A jump in device lag larger than this is lost or repeated audio, not drift.
+const MAX_LAG_STEP_US: f64 = 5_000.0;
+
+#[derive(Copy, Clone, Debug)]
+pub struct ClockSample {
+    /// When the answer arrived, on the local clock.
+    pub local_us: u64,
+    pub rtt_us: u64,
+    /// Remote clock minus local clock.
+    pub offset_us: i64,
+}
+
+#[derive(Default)]
+pub struct ClockSync {
+    samples: VecDeque<ClockSample>,
+}
+
+impl ClockSync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Folds in one exchange; `t1` and `t4` are local times, `t2` and `t3` remote ones.
+    pub fn add(&mut self, t1: u64, t2: u64, t3: u64, t4: u64) -> ClockSample {
+        let (t1, t2, t3, t4) = (t1 as i64, t2 as i64, t3 as i64, t4 as i64);
+        let sample = ClockSample {
+            local_us: t4 as u64,
+            rtt_us: ((t4 - t1) - (t3 - t2)).max(0) as u64,
+            offset_us: ((t2 - t1) + (t3 - t4)) / 2,
+        };
+        // A local clock that jumped back makes the history useless.
+        if self
+            .samples
+            .back()
+            .is_some_and(|last| last.local_us > sample.local_us)
+        {
+            self.samples.clear();
+        }
+        if self.samples.len() == HISTORY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        sample
+    }
+
+    pub fn latest(&self) -> Option<ClockSample> {
+        self.samples.back().copied()
+    }
+
+    /// Remote clock minus local clock at `local_us`.
+    pub fn offset_us(&self, local_us: u64) -> Option<i64> {
+        let best = self
+            .samples
+            .iter()
+            .rev()
+            .take(RECENT)
+            .min_by_key(|sample| sample.rtt_us)?;
+        let elapsed_us = local_us as f64 - best.local_us as f64;
+        let drift = self.drift_ppb().unwrap_or(0) as f64 / 1e9;
+        Some(best.offset_us + (elapsed_us * drift) as i64)
+    }
+
+    /// How many nanoseconds per second the remote clock gains on the local one.
+    pub fn drift_ppb(&self) -> Option<i64> {
+        let (first, last) = (self.samples.front()?, self.samples.back()?);
+        if last.local_us - first.local_us < MIN_DRIFT_SPAN_US {
+            return None;
+        }
+        let stretch = self.samples.len().div_ceil(DRIFT_POINTS);
+        let samples: Vec<ClockSample> = self.samples.iter().copied().collect();
+        let points: Vec<_> = samples
+            .chunks(stretch)
+            .filter_map(|chunk| chunk.iter().min_by_key(|sample| sample.rtt_us))
+            .map(|sample| {
+                (
+                    (sample.local_us - first.local_us) as f64,
+                    sample.offset_us as f64,
+                )
+            })
+            .collect();
+        slope(&points).map(|slope| (slope * 1e9) as i64)
+    }
+}
+
+/// Drift of an audio device clock against the monotonic clock.
+///
+/// Each chunk's lag, the monotonic time since the first chunk minus the duration of the
+/// samples delivered, only grows with scheduling delay, so the minimum over each second
+/// follows the two clocks and the slope through those minimums is the drift.
+#[derive(Default)]
+pub struct DeviceDrift {
+    start: Option<Instant>,
+    frames: u64,
+    /// Second since the first chunk and the smallest lag seen in it.
+    second: Option<(u64, f64)>,
+    points: Vec<(f64, f64)>,
+}
+
+impl DeviceDrift {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, captured_at: Instant, frames: usize, sample_rate: u32) {
+        let start = *self.start.get_or_insert(captured_at);
+        self.frames += frames as u64;
+        let elapsed = captured_at.saturating_duration_since(start);
+        let lag_us =
+            elapsed.as_micros() as f64 - self.frames as f64 * 1e6 / sample_rate.max(1) as f64;
+        match &mut self.second {
+            Some((second, min_lag_us)) if *second == elapsed.as_secs() => {
+                *min_lag_us = min_lag_us.min(lag_us);
+            }
+            current => {
+                if let Some((second, min_lag_us)) = current.replace((elapsed.as_secs(), lag_us)) {
+                    self.add_point(second as f64, min_lag_us);
+                }
+            }
+        }
+    }
+
+    fn add_point(&mut self, second: f64, lag_us: f64) {
+        if self
+            .points
+            .last()
+            .is_some_and(|&(_, last)| (lag_us - last).abs() > MAX_LAG_STEP_US)
+        {
+            self.points.clear();
+        }
+        if self.points.len() == DEVICE_HISTORY {
+            self.points.remove(0);
+        }
+        self.points.push((second, lag_us));
+    }
+
+    /// How many parts per million the device clock runs fast.
+    pub fn ppm(&self) -> Option<f64> {
+        if self.points.len() < MIN_DEVICE_POINTS {
+            return None;
+        }
+        // Lag in microseconds per second of capture; a fast device makes it shrink.
+        slope(&self.points).map(|slope| -slope)
+    }
+}
+
+/// Least-squares slope through `points`.
+fn slope(points: &[(f64, f64)]) -> Option<f64> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let (num, den) = points.iter().fold((0.0, 0.0), |(num, den), (x, y)| {
+        let dx = x - mean_x;
+        (num + dx * (y - mean_y), den + dx * dx)
+    });
+    (den > 0.0).then(|| num / den)
+}
@@ -0,0 +1,1238 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use serde::{Deserialize, Deserializer};
+use tracing::{info, warn};
+use windows_sender::crypto::noise::KeyPin;
+use windows_sender::crypto::Key;
+use windows_sender::dsp::EqBand;
+use windows_sender::error::error_chain;
+use windows_sender::ptp::PtpSource;
+use windows_sender::resolve::Endpoint;
+
+use crate::autoexit;
+use crate::console::ColorWhen;
+use crate::control::{ControlCommand, Router};
+use crate::hotkey::Hotkey;
+use crate::logfile::Rotation;
+use crate::logging::LogFormat;
+use crate::notify::NotifyWhen;
+use crate::stats_log::{StatsFormat, StatsGroup};
+use crate::{
+    session, Aes67Encoding, Args, AudioSource, LateAudio, PayloadCipher, PreferIp, QueueFull,
+    TeeAt, Transport, VirtualRoute,
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    target_ip: Option<String>,
+    prefer_ip: Option<PreferIp>,
+    port: Option<u16>,
+    frame_ms: Option<f32>,
+    source: Option<AudioSource>,
+    desktop_device: Option<String>,
+    route_through_virtual: Option<VirtualRoute>,
+    source_file: Option<PathBuf>,
+    transport: Option<Transport>,
+    vban_stream: Option<String>,
+    aes67_session: Option<String>,
+    aes67_encoding: Option<Aes67Encoding>,
+    write_sdp: Option<PathBuf>,
+    bt_device: Option<String>,
+    bt_channel: Option<u8>,
+    #[serde(default, deserialize_with = "parse_key")]
+    key: Option<Key>,
+    key_name: Option<String>,
+    cipher: Option<PayloadCipher>,
+    rotate_key_mins: Option<u64>,
+    noise: Option<PathBuf>,
+    #[serde(default, deserialize_with = "parse_pins")]
+    pins: Option<Vec<KeyPin>>,
+    access_token: Option<String>,
+    remote_volume: Option<bool>,
+    #[serde(default, deserialize_with = "parse_ptp")]
+    ptp: Option<PtpSource>,
+    pace: Option<bool>,
+    no_preflight: Option<bool>,
+    pause_notice: Option<bool>,
+    playout_delay_ms: Option<u32>,
+    #[serde(default, deserialize_with = "parse_start_at")]
+    start_at: Option<SystemTime>,
+    late_audio: Option<LateAudio>,
+    max_audio_age_ms: Option<u32>,
+    queue_overflow: Option<QueueFull>,
+    io_uring: Option<bool>,
+    gso: Option<bool>,
+    realtime: Option<bool>,
+    affinity: Option<Vec<usize>>,
+    so_sndbuf: Option<u32>,
+    tcp_backlog_ms: Option<u32>,
+    max_buffered_ms: Option<u32>,
+    max_kbps: Option<u32>,
+    tee: Option<PathBuf>,
+    tee_point: Option<TeeAt>,
+    ndi: Option<String>,
+    output: Option<PathBuf>,
+    passthrough: Option<bool>,
+    encode_workers: Option<usize>,
+    gain_db: Option<f32>,
+    mute: Option<bool>,
+    extra_targets: Option<Vec<String>>,
+    eq: Option<Vec<EqBandConfig>>,
+    watchdog_secs: Option<u64>,
+    plugins: Option<Vec<PathBuf>>,
+    stats_format: Option<StatsFormat>,
+    stats_file: Option<PathBuf>,
+    stats_webhook: Option<String>,
+    #[serde(default, deserialize_with = "parse_hotkeys")]
+    hotkeys: Option<Vec<Hotkey>>,
+    events_file: Option<PathBuf>,
+    perf_folded: Option<PathBuf>,
+    stats_interval_ms: Option<u64>,
+    stats_fields: Option<Vec<StatsGroup>>,
+    alert_drops: Option<f64>,
+    alert_backlog: Option<usize>,
+    alert_secs: Option<u64>,
+    alert_exit: Option<bool>,
+    #[serde(default, deserialize_with = "parse_duration")]
+    duration: Option<Duration>,
+    #[serde(default, deserialize_with = "parse_duration")]
+    exit_on_silence: Option<Duration>,
+    notify: Option<NotifyWhen>,
+    log_level: Option<String>,
+    log_format: Option<LogFormat>,
+    color: Option<ColorWhen>,
+    log_file: Option<PathBuf>,
+    log_rotate: Option<Rotation>,
+    log_keep: Option<usize>,
+    otlp_endpoint: Option<String>,
+    control: Option<String>,
+    control_http: Option<SocketAddr>,
+    adb_serial: Option<String>,
+    #[serde(default)]
+    session: Vec<SessionConfig>,
+}
+
+/// One `[[session]]` table: a pipeline whose unset fields fall back to the top level.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SessionConfig {
+    name: String,
+    target_ip: Option<String>,
+    prefer_ip: Option<PreferIp>,
+    port: Option<u16>,
+    frame_ms: Option<f32>,
+    source: Option<AudioSource>,
+    desktop_device: Option<String>,
+    route_through_virtual: Option<VirtualRoute>,
+    source_file: Option<PathBuf>,
+    transport: Option<Transport>,
+    vban_stream: Option<String>,
+    aes67_session: Option<String>,
+    aes67_encoding: Option<Aes67Encoding>,
+    write_sdp: Option<PathBuf>,
+    bt_device: Option<String>,
+    bt_channel: Option<u8>,
+    #[serde(default, deserialize_with = "parse_key")]
+    key: Option<Key>,
+    key_name: Option<String>,
+    cipher: Option<PayloadCipher>,
+    rotate_key_mins: Option<u64>,
+    noise: Option<PathBuf>,
+    #[serde(default, deserialize_with = "parse_pins")]
+    pins: Option<Vec<KeyPin>>,
+    access_token: Option<String>,
+    remote_volume: Option<bool>,
+    #[serde(default, deserialize_with = "parse_ptp")]
+    ptp: Option<PtpSource>,
+    pace: Option<bool>,
+    no_preflight: Option<bool>,
+    pause_notice: Option<bool>,
+    playout_delay_ms: Option<u32>,
+    #[serde(default, deserialize_with = "parse_start_at")]
+    start_at: Option<SystemTime>,
+    late_audio: Option<LateAudio>,
+    max_audio_age_ms: Option<u32>,
+    queue_overflow: Option<QueueFull>,
+    io_uring: Option<bool>,
+    gso: Option<bool>,
+    realtime: Option<bool>,
+    affinity: Option<Vec<usize>>,
+    so_sndbuf: Option<u32>,
+    tcp_backlog_ms: Option<u32>,
+    max_buffered_ms: Option<u32>,
+    max_kbps: Option<u32>,
+    tee: Option<PathBuf>,
+    tee_point: Option<TeeAt>,
+    ndi: Option<String>,
+    output: Option<PathBuf>,
+    passthrough: Option<bool>,
+    encode_workers: Option<usize>,
+    gain_db: Option<f32>,
+    mute: Option<bool>,
+    extra_targets: Option<Vec<String>>,
+    eq: Option<Vec<EqBandConfig>>,
+    watchdog_secs: Option<u64>,
+    plugins: Option<Vec<PathBuf>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EqBandConfig {
+    freq_hz: f32,
+    gain_db: f32,
+    #[serde(default = "default_q")]
+    q: f32,
+}
+
+fn default_q() -> f32 {
+    0.707
+}
+
+fn parse_key<'de, D>(deserializer: D) -> Result<Option<Key>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    text.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+fn parse_pins<'de, D>(deserializer: D) -> Result<Option<Vec<KeyPin>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|text| text.parse())
+        .collect::<Result<_, _>>()
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn parse_hotkeys<'de, D>(deserializer: D) -> Result<Option<Vec<Hotkey>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|text| text.parse())
+        .collect::<Result<_, _>>()
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn parse_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    autoexit::parse_duration(&text)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn parse_start_at<'de, D>(deserializer: D) -> Result<Option<SystemTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    session::parse_start_at(&text)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn parse_ptp<'de, D>(deserializer: D) -> Result<Option<PtpSource>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    Ok(Some(text.as_str().into()))
+}
+
+/// Settings of a single capture -> send pipeline after merging file and CLI values.
+#[derive(Clone, Debug)]
+pub struct SessionArgs {
+    pub name: String,
+    pub args: Args,
+}
+
+struct Pipeline {
+    target_ip: Option<String>,
+    prefer_ip: Option<PreferIp>,
+    port: Option<u16>,
+    frame_ms: Option<f32>,
+    source: Option<AudioSource>,
+    desktop_device: Option<String>,
+    route_through_virtual: Option<VirtualRoute>,
+    source_file: Option<PathBuf>,
+    transport: Option<Transport>,
+    vban_stream: Option<String>,
+    aes67_session: Option<String>,
+    aes67_encoding: Option<Aes67Encoding>,
+    write_sdp: Option<PathBuf>,
+    bt_device: Option<String>,
+    bt_channel: Option<u8>,
+    key: Option<Key>,
+    key_name: Option<String>,
+    cipher: Option<PayloadCipher>,
+    rotate_key_mins: Option<u64>,
+    noise: Option<PathBuf>,
+    pins: Option<Vec<KeyPin>>,
+    access_token: Option<String>,
+    remote_volume: Option<bool>,
+    ptp: Option<PtpSource>,
+    pace: Option<bool>,
+    no_preflight: Option<bool>,
+    pause_notice: Option<bool>,
+    playout_delay_ms: Option<u32>,
+    start_at: Option<SystemTime>,
+    late_audio: Option<LateAudio>,
+    max_audio_age_ms: Option<u32>,
+    queue_overflow: Option<QueueFull>,
+    io_uring: Option<bool>,
+    gso: Option<bool>,
+    realtime: Option<bool>,
+    affinity: Option<Vec<usize>>,
+    so_sndbuf: Option<u32>,
+    tcp_backlog_ms: Option<u32>,
+    max_buffered_ms: Option<u32>,
+    max_kbps: Option<u32>,
+    tee: Option<PathBuf>,
+    tee_point: Option<TeeAt>,
+    ndi: Option<String>,
+    output: Option<PathBuf>,
+    passthrough: Option<bool>,
+    encode_workers: Option<usize>,
+    gain_db: Option<f32>,
+    mute: Option<bool>,
+    extra_targets: Option<Vec<String>>,
+    eq: Option<Vec<EqBandConfig>>,
+    watchdog_secs: Option<u64>,
+    plugins: Option<Vec<PathBuf>>,
+}
+
+impl FileConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let mut config: Self = toml::from_str(&text)
+            .with_context(|| format!("invalid config file {}", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        let session_plugins = config
+            .session
+            .iter_mut()
+            .flat_map(|session| session.plugins.iter_mut().flatten());
+        for plugin in config.plugins.iter_mut().flatten().chain(session_plugins) {
+            *plugin = base.join(&*plugin);
+        }
+        let session_files = config.session.iter_mut().flat_map(|session| {
+            [
+                &mut session.source_file,
+                &mut session.tee,
+                &mut session.output,
+                &mut session.write_sdp,
+                &mut session.noise,
+            ]
+        });
+        for file in [
+            &mut config.source_file,
+            &mut config.tee,
+            &mut config.output,
+            &mut config.write_sdp,
+            &mut config.noise,
+        ]
+        .into_iter()
+        .chain(session_files)
+        .flatten()
+        {
+            *file = base.join(&*file);
+        }
+        for file in [
+            &mut config.stats_file,
+            &mut config.log_file,
+            &mut config.events_file,
+            &mut config.perf_folded,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *file = base.join(&*file);
+        }
+        let session_bands = config
+            .session
+            .iter()
+            .flat_map(|session| session.eq.iter().flatten());
+        for band in config.eq.iter().flatten().chain(session_bands) {
+            if band.freq_hz <= 0.0 || band.q <= 0.0 {
+                bail!(
+                    "invalid EQ band in {}: freq_hz and q must be positive",
+                    path.display()
+                );
+            }
+        }
+        let session_watchdogs = config.session.iter().map(|session| session.watchdog_secs);
+        if std::iter::once(config.watchdog_secs)
+            .chain(session_watchdogs)
+            .any(|secs| secs == Some(0))
+        {
+            bail!(
+                "invalid watchdog_secs in {}: must be at least 1",
+                path.display()
+            );
+        }
+        let session_rotations = config.session.iter().map(|session| session.rotate_key_mins);
+        if std::iter::once(config.rotate_key_mins)
+            .chain(session_rotations)
+            .any(|mins| mins == Some(0))
+        {
+            bail!(
+                "invalid rotate_key_mins in {}: must be at least 1",
+                path.display()
+            );
+        }
+        if config.stats_interval_ms.is_some_and(|ms| ms < 100) {
+            bail!(
+                "invalid stats_interval_ms in {}: must be at least 100",
+                path.display()
+            );
+        }
+        if config.alert_secs == Some(0) {
+            bail!(
+                "invalid alert_secs in {}: must be at least 1",
+                path.display()
+            );
+        }
+        for (index, session) in config.session.iter().enumerate() {
+            if session.name.trim().is_empty() {
+                bail!(
+                    "session #{} in {} has an empty name",
+                    index + 1,
+                    path.display()
+                );
+            }
+            if config.session[..index]
+                .iter()
+                .any(|other| other.name == session.name)
+            {
+                bail!(
+                    "duplicate session name {:?} in {}",
+                    session.name,
+                    path.display()
+                );
+            }
+        }
+        Ok(config)
+    }
+
+    /// Fills `args` with file values, keeping anything given explicitly on the command line.
+    ///
+    /// Returns one entry per `[[session]]`, each layered as CLI > session > top level.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) -> Vec<SessionArgs> {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+        merge(
+            &mut args.control,
+            self.control.map(Some),
+            from_cli("control"),
+        );
+        merge(
+            &mut args.control_http,
+            self.control_http.map(Some),
+            from_cli("control_http"),
+        );
+        merge(
+            &mut args.adb_serial,
+            self.adb_serial.map(Some),
+            from_cli("adb_serial"),
+        );
+        merge(
+            &mut args.stats_format,
+            self.stats_format,
+            from_cli("stats_format"),
+        );
+        merge(
+            &mut args.stats_file,
+            self.stats_file.map(Some),
+            from_cli("stats_file"),
+        );
+        merge(
+            &mut args.stats_webhook,
+            self.stats_webhook.map(Some),
+            from_cli("stats_webhook"),
+        );
+        merge(&mut args.hotkeys, self.hotkeys, from_cli("hotkeys"));
+        merge(
+            &mut args.events_file,
+            self.events_file.map(Some),
+            from_cli("events_file"),
+        );
+        merge(
+            &mut args.perf_folded,
+            self.perf_folded.map(Some),
+            from_cli("perf_folded"),
+        );
+        merge(
+            &mut args.stats_interval_ms,
+            self.stats_interval_ms,
+            from_cli("stats_interval_ms"),
+        );
+        merge(
+            &mut args.stats_fields,
+            self.stats_fields,
+            from_cli("stats_fields"),
+        );
+        merge(
+            &mut args.alert_drops,
+            self.alert_drops.map(Some),
+            from_cli("alert_drops"),
+        );
+        merge(
+            &mut args.alert_backlog,
+            self.alert_backlog.map(Some),
+            from_cli("alert_backlog"),
+        );
+        merge(
+            &mut args.alert_secs,
+            self.alert_secs,
+            from_cli("alert_secs"),
+        );
+        merge(
+            &mut args.alert_exit,
+            self.alert_exit,
+            from_cli("alert_exit"),
+        );
+        merge(
+            &mut args.duration,
+            self.duration.map(Some),
+            from_cli("duration"),
+        );
+        merge(
+            &mut args.exit_on_silence,
+            self.exit_on_silence.map(Some),
+            from_cli("exit_on_silence"),
+        );
+        merge(&mut args.notify, self.notify, from_cli("notify"));
+        merge(
+            &mut args.log_level,
+            self.log_level.map(Some),
+            from_cli("log_level"),
+        );
+        merge(
+            &mut args.log_format,
+            self.log_format,
+            from_cli("log_format"),
+        );
+        merge(&mut args.color, self.color, from_cli("color"));
+        merge(
+            &mut args.log_file,
+            self.log_file.map(Some),
+            from_cli("log_file"),
+        );
+        merge(
+            &mut args.log_rotate,
+            self.log_rotate,
+            from_cli("log_rotate"),
+        );
+        merge(&mut args.log_keep, self.log_keep, from_cli("log_keep"));
+        merge(
+            &mut args.otlp_endpoint,
+            self.otlp_endpoint.map(Some),
+            from_cli("otlp_endpoint"),
+        );
+        merge_pipeline(
+            args,
+            Pipeline {
+                target_ip: self.target_ip,
+                prefer_ip: self.prefer_ip,
+                port: self.port,
+                frame_ms: self.frame_ms,
+                source: self.source,
+                desktop_device: self.desktop_device,
+                route_through_virtual: self.route_through_virtual,
+                source_file: self.source_file,
+                transport: self.transport,
+                vban_stream: self.vban_stream,
+                aes67_session: self.aes67_session,
+                aes67_encoding: self.aes67_encoding,
+                write_sdp: self.write_sdp,
+                bt_device: self.bt_device,
+                bt_channel: self.bt_channel,
+                key: self.key,
+                key_name: self.key_name,
+                cipher: self.cipher,
+                rotate_key_mins: self.rotate_key_mins,
+                noise: self.noise,
+                pins: self.pins,
+                access_token: self.access_token,
+                remote_volume: self.remote_volume,
+                ptp: self.ptp,
+                pace: self.pace,
+                no_preflight: self.no_preflight,
+                pause_notice: self.pause_notice,
+                playout_delay_ms: self.playout_delay_ms,
+                start_at: self.start_at,
+                late_audio: self.late_audio,
+                max_audio_age_ms: self.max_audio_age_ms,
+                queue_overflow: self.queue_overflow,
+                io_uring: self.io_uring,
+                gso: self.gso,
+                realtime: self.realtime,
+                affinity: self.affinity,
+                so_sndbuf: self.so_sndbuf,
+                tcp_backlog_ms: self.tcp_backlog_ms,
+                max_buffered_ms: self.max_buffered_ms,
+                max_kbps: self.max_kbps,
+                tee: self.tee,
+                tee_point: self.tee_point,
+                ndi: self.ndi,
+                output: self.output,
+                passthrough: self.passthrough,
+                encode_workers: self.encode_workers,
+                gain_db: self.gain_db,
+                mute: self.mute,
+                extra_targets: self.extra_targets,
+                eq: self.eq,
+                watchdog_secs: self.watchdog_secs,
+                plugins: self.plugins,
+            },
+            &from_cli,
+        );
+
+        self.session
+            .into_iter()
+            .map(|session| {
+                let mut session_args = args.clone();
+                merge_pipeline(
+                    &mut session_args,
+                    Pipeline {
+                        target_ip: session.target_ip,
+                        prefer_ip: session.prefer_ip,
+                        port: session.port,
+                        frame_ms: session.frame_ms,
+                        source: session.source,
+                        desktop_device: session.desktop_device,
+                        route_through_virtual: session.route_through_virtual,
+                        source_file: session.source_file,
+                        transport: session.transport,
+                        vban_stream: session.vban_stream,
+                        aes67_session: session.aes67_session,
+                        aes67_encoding: session.aes67_encoding,
+                        write_sdp: session.write_sdp,
+                        bt_device: session.bt_device,
+                        bt_channel: session.bt_channel,
+                        key: session.key,
+                        key_name: session.key_name,
+                        cipher: session.cipher,
+                        rotate_key_mins: session.rotate_key_mins,
+                        noise: session.noise,
+                        pins: session.pins,
+                        access_token: session.access_token,
+                        remote_volume: session.remote_volume,
+                        ptp: session.ptp,
+                        pace: session.pace,
+                        no_preflight: session.no_preflight,
+                        pause_notice: session.pause_notice,
+                        playout_delay_ms: session.playout_delay_ms,
+                        start_at: session.start_at,
+                        late_audio: session.late_audio,
+                        max_audio_age_ms: session.max_audio_age_ms,
+                        queue_overflow: session.queue_overflow,
+                        io_uring: session.io_uring,
+                        gso: session.gso,
+                        realtime: session.realtime,
+                        affinity: session.affinity,
+                        so_sndbuf: session.so_sndbuf,
+                        tcp_backlog_ms: session.tcp_backlog_ms,
+                        max_buffered_ms: session.max_buffered_ms,
+                        max_kbps: session.max_kbps,
+                        tee: session.tee,
+                        tee_point: session.tee_point,
+                        ndi: session.ndi,
+                        output: session.output,
+                        passthrough: session.passthrough,
+                        encode_workers: session.encode_workers,
+                        gain_db: session.gain_db,
+                        mute: session.mute,
+                        extra_targets: session.extra_targets,
+                        eq: session.eq,
+                        watchdog_secs: session.watchdog_secs,
+                        plugins: session.plugins,
+                    },
+                    &from_cli,
+                );
+                SessionArgs {
+                    name: session.name,
+                    args: session_args,
+                }
+            })
+            .collect()
+    }
+}
+
+fn merge_pipeline(args: &mut Args, pipeline: Pipeline, from_cli: &dyn Fn(&str) -> bool) {
+    merge(
+        &mut args.target_ip,
+        pipeline.target_ip.map(Some),
+        from_cli("target_ip"),
+    );
+    merge(
+        &mut args.prefer_ip,
+        pipeline.prefer_ip,
+        from_cli("prefer_ip"),
+    );
+    merge(&mut args.port, pipeline.port, from_cli("port"));
+    merge(&mut args.frame_ms, pipeline.frame_ms, from_cli("frame_ms"));
+    merge(&mut args.source, pipeline.source, from_cli("source"));
+    merge(
+        &mut args.desktop_device,
+        pipeline.desktop_device.map(Some),
+        from_cli("desktop_device"),
+    );
+    merge(
+        &mut args.route_through_virtual,
+        pipeline.route_through_virtual.map(Some),
+        from_cli("route_through_virtual"),
+    );
+    merge(
+        &mut args.source_file,
+        pipeline.source_file.map(Some),
+        from_cli("source_file"),
+    );
+    merge(
+        &mut args.transport,
+        pipeline.transport,
+        from_cli("transport"),
+    );
+    merge(
+        &mut args.vban_stream,
+        pipeline.vban_stream.map(Some),
+        from_cli("vban_stream"),
+    );
+    merge(
+        &mut args.aes67_session,
+        pipeline.aes67_session.map(Some),
+        from_cli("aes67_session"),
+    );
+    merge(
+        &mut args.aes67_encoding,
+        pipeline.aes67_encoding.map(Some),
+        from_cli("aes67_encoding"),
+    );
+    merge(
+        &mut args.write_sdp,
+        pipeline.write_sdp.map(Some),
+        from_cli("write_sdp"),
+    );
+    merge(
+        &mut args.bt_device,
+        pipeline.bt_device.map(Some),
+        from_cli("bt_device"),
+    );
+    merge(
+        &mut args.bt_channel,
+        pipeline.bt_channel.map(Some),
+        from_cli("bt_channel"),
+    );
+    merge(&mut args.key, pipeline.key.map(Some), from_cli("key"));
+    merge(
+        &mut args.key_name,
+        pipeline.key_name.map(Some),
+        from_cli("key_name"),
+    );
+    merge(&mut args.cipher, pipeline.cipher, from_cli("cipher"));
+    merge(
+        &mut args.rotate_key_mins,
+        pipeline.rotate_key_mins.map(Some),
+        from_cli("rotate_key_mins"),
+    );
+    merge(&mut args.noise, pipeline.noise.map(Some), from_cli("noise"));
+    merge(&mut args.pins, pipeline.pins, from_cli("pins"));
+    merge(
+        &mut args.access_token,
+        pipeline.access_token.map(Some),
+        from_cli("access_token"),
+    );
+    merge(
+        &mut args.remote_volume,
+        pipeline.remote_volume,
+        from_cli("remote_volume"),
+    );
+    merge(&mut args.ptp, pipeline.ptp.map(Some), from_cli("ptp"));
+    merge(&mut args.pace, pipeline.pace, from_cli("pace"));
+    merge(
+        &mut args.no_preflight,
+        pipeline.no_preflight,
+        from_cli("no_preflight"),
+    );
+    merge(
+        &mut args.pause_notice,
+        pipeline.pause_notice,
+        from_cli("pause_notice"),
+    );
+    merge(
+        &mut args.start_at,
+        pipeline.start_at.map(Some),
+        from_cli("start_at"),
+    );
+    merge(
+        &mut args.playout_delay_ms,
+        pipeline.playout_delay_ms.map(Some),
+        from_cli("playout_delay_ms"),
+    );
+    merge(
+        &mut args.late_audio,
+        pipeline.late_audio,
+        from_cli("late_audio"),
+    );
+    merge(
+        &mut args.max_audio_age_ms,
+        pipeline.max_audio_age_ms,
+        from_cli("max_audio_age_ms"),
+    );
+    merge(
+        &mut args.queue_overflow,
+        pipeline.queue_overflow,
+        from_cli("queue_overflow"),
+    );
+    merge(&mut args.io_uring, pipeline.io_uring, from_cli("io_uring"));
+    merge(&mut args.gso, pipeline.gso, from_cli("gso"));
+    merge(&mut args.realtime, pipeline.realtime, from_cli("realtime"));
+    merge(&mut args.affinity, pipeline.affinity, from_cli("affinity"));
+    merge(
+        &mut args.so_sndbuf,
+        pipeline.so_sndbuf.map(Some),
+        from_cli("so_sndbuf"),
+    );
+    merge(
+        &mut args.tcp_backlog_ms,
+        pipeline.tcp_backlog_ms.map(Some),
+        from_cli("tcp_backlog_ms"),
+    );
+    merge(
+        &mut args.max_buffered_ms,
+        pipeline.max_buffered_ms.map(Some),
+        from_cli("max_buffered_ms"),
+    );
+    merge(
+        &mut args.max_kbps,
+        pipeline.max_kbps.map(Some),
+        from_cli("max_kbps"),
+    );
+    merge(&mut args.tee, pipeline.tee.map(Some), from_cli("tee"));
+    merge(
+        &mut args.tee_point,
+        pipeline.tee_point,
+        from_cli("tee_point"),
+    );
+    merge(&mut args.ndi, pipeline.ndi.map(Some), from_cli("ndi"));
+    merge(
+        &mut args.output,
+        pipeline.output.map(Some),
+        from_cli("output"),
+    );
+    merge(
+        &mut args.passthrough,
+        pipeline.passthrough,
+        from_cli("passthrough"),
+    );
+    merge(
+        &mut args.encode_workers,
+        pipeline.encode_workers,
+        from_cli("encode_workers"),
+    );
+    merge(&mut args.gain_db, pipeline.gain_db, from_cli("gain_db"));
+    merge(&mut args.mute, pipeline.mute, from_cli("mute"));
+    merge(
+        &mut args.extra_targets,
+        pipeline.extra_targets,
+        from_cli("extra_targets"),
+    );
+    merge(
+        &mut args.watchdog_secs,
+        pipeline.watchdog_secs.map(Some),
+        from_cli("watchdog_secs"),
+    );
+    merge(&mut args.plugins, pipeline.plugins, from_cli("plugins"));
+    if let Some(eq) = pipeline.eq {
+        args.eq = eq
+            .into_iter()
+            .map(|band| EqBand {
+                freq_hz: band.freq_hz,
+                gain_db: band.gain_db,
+                q: band.q,
+            })
+            .collect();
+    }
+}
+
+fn merge<T>(slot: &mut T, file_value: Option<T>, from_cli: bool) {
+    if from_cli {
+        return;
+    }
+    if let Some(value) = file_value {
+        *slot = value;
+    }
+}
+
+pub const DEFAULT_SESSION: &str = "default";
+
+/// Merges the config file (if any) into `args` and returns the sessions to run:
+/// the `[[session]]` tables, or a single session with the top-level settings.
+pub fn resolve(
+    path: Option<&Path>,
+    args: &mut Args,
+    matches: &ArgMatches,
+) -> Result<Vec<SessionArgs>> {
+    let sessions = match path {
+        Some(path) => FileConfig::load(path)?.apply(args, matches),
+        None => Vec::new(),
+    };
+    if sessions.is_empty() {
+        return Ok(vec![SessionArgs {
+            name: DEFAULT_SESSION.to_string(),
+            args: args.clone(),
+        }]);
+    }
+    Ok(sessions)
+}
+
+/// Polls the config file and applies changes that are safe to make while streaming.
+/// A message on `reload` forces a re-read even if the file looks unchanged.
+///
+/// `cli_args` are the arguments before the file was merged, so command-line flags keep
+/// overriding the file on every reload.
+pub fn spawn_watcher(
+    path: PathBuf,
+    cli_args: Args,
+    matches: ArgMatches,
+    mut current: Vec<SessionArgs>,
+    router: Router,
+    reload: Receiver<()>,
+) -> Result<()> {
+    let mut last_modified = modified_time(&path);
+    let mut current_top = cli_args.clone();
+    resolve(Some(&path), &mut current_top, &matches)?;
+    thread::Builder::new()
+        .name("config-watch".to_string())
+        .spawn(move || loop {
+            let forced = match reload.recv_timeout(Duration::from_millis(500)) {
+                Ok(()) => true,
+                Err(RecvTimeoutError::Timeout) => false,
+                Err(RecvTimeoutError::Disconnected) => {
+                    thread::sleep(Duration::from_millis(500));
+                    false
+                }
+            };
+            let modified = modified_time(&path);
+            if modified == last_modified && !forced {
+                continue;
+            }
+            last_modified = modified;
+
+            let mut next_top = cli_args.clone();
+            let next = match resolve(Some(&path), &mut next_top, &matches) {
+                Ok(next) => next,
+                Err(err) => {
+                    warn!("config reload skipped: {err:#}");
+                    continue;
+                }
+            };
+
+            if next_top.control != current_top.control {
+                info!("config: control changed; restart the sender to apply it");
+            }
+            if next_top.control_http != current_top.control_http {
+                info!("config: control_http changed; restart the sender to apply it");
+            }
+            if next_top.adb_serial != current_top.adb_serial {
+                info!("config: adb_serial changed; restart the sender to apply it");
+            }
+            if next_top.stats_format != current_top.stats_format
+                || next_top.stats_file != current_top.stats_file
+                || next_top.stats_webhook != current_top.stats_webhook
+                || next_top.stats_interval_ms != current_top.stats_interval_ms
+                || next_top.stats_fields != current_top.stats_fields
+            {
+                info!("config: stats output changed; restart the sender to apply it");
+            }
+            if next_top.alert_drops != current_top.alert_drops
+                || next_top.alert_backlog != current_top.alert_backlog
+                || next_top.alert_secs != current_top.alert_secs
+                || next_top.alert_exit != current_top.alert_exit
+            {
+                info!("config: alert thresholds changed; restart the sender to apply it");
+            }
+            if next_top.duration != current_top.duration
+                || next_top.exit_on_silence != current_top.exit_on_silence
+            {
+                info!(
+                    "config: duration or exit_on_silence changed; restart the sender to apply it"
+                );
+            }
+            if next_top.notify != current_top.notify {
+                info!("config: notify changed; restart the sender to apply it");
+            }
+            if next_top.hotkeys != current_top.hotkeys {
+                info!("config: hotkeys changed; restart the sender to apply it");
+            }
+            if next_top.events_file != current_top.events_file {
+                info!("config: events_file changed; restart the sender to apply it");
+            }
+            if next_top.perf_folded != current_top.perf_folded {
+                info!("config: perf_folded changed; restart the sender to apply it");
+            }
+            if next_top.log_level != current_top.log_level
+                || next_top.log_format != current_top.log_format
+                || next_top.color != current_top.color
+                || next_top.log_file != current_top.log_file
+                || next_top.log_rotate != current_top.log_rotate
+                || next_top.log_keep != current_top.log_keep
+                || next_top.otlp_endpoint != current_top.otlp_endpoint
+            {
+                info!("config: logging changed; restart the sender to apply it");
+            }
+            for session in &next {
+                let Some(old) = current.iter().find(|old| old.name == session.name) else {
+                    info!(
+                        "config: session {} added; restart the sender to start it",
+                        session.name
+                    );
+                    continue;
+                };
+                apply_live_changes(&session.name, &old.args, &session.args, &router);
+                for field in restart_only_changes(&old.args, &session.args) {
+                    info!(
+                        "config: [{}] {field} changed; restart the sender to apply it",
+                        session.name
+                    );
+                }
+            }
+            for old in current
+                .iter()
+                .filter(|old| !next.iter().any(|s| s.name == old.name))
+            {
+                info!(
+                    "config: session {} removed; restart the sender to stop it",
+                    old.name
+                );
+            }
+            current = next;
+            current_top = next_top;
+        })
+        .context("failed to spawn config watcher thread")?;
+    Ok(())
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+fn apply_live_changes(session: &str, current: &Args, next: &Args, router: &Router) {
+    let mut commands = Vec::new();
+    if next.gain_db != current.gain_db {
+        commands.push(("gain_db", ControlCommand::SetGain(next.gain_db)));
+    }
+    if next.mute != current.mute {
+        let command = if next.mute {
+            ControlCommand::Mute
+        } else {
+            ControlCommand::Unmute
+        };
+        commands.push(("mute", command));
+    }
+    if next.eq != current.eq {
+        commands.push(("eq", ControlCommand::SetEq(next.eq.clone())));
+    }
+    if next.extra_targets != current.extra_targets {
+        let old = parse_targets(&current.extra_targets, current.prefer_ip);
+        let new = parse_targets(&next.extra_targets, next.prefer_ip);
+        for addr in new.iter().filter(|addr| !old.contains(addr)) {
+            commands.push(("extra_targets", ControlCommand::AddTarget(*addr)));
+        }
+        for addr in old.iter().filter(|addr| !new.contains(addr)) {
+            commands.push(("extra_targets", ControlCommand::RemoveTarget(*addr)));
+        }
+    }
+
+    for (field, command) in commands {
+        match router.request(Some(session), command) {
+            Ok(_) => info!("config: [{session}] applied {field}"),
+            Err(err) => warn!("config: [{session}] failed to apply {field}: {err}"),
+        }
+    }
+}
+
+/// Names are looked up once here; a live-added target keeps the address it had then.
+fn parse_targets(targets: &[String], prefer: PreferIp) -> Vec<SocketAddr> {
+    targets
+        .iter()
+        .filter_map(|target| {
+            match Endpoint::parse(target, prefer.into())
+                .and_then(|endpoint| Ok(endpoint.resolve()?))
+            {
+                Ok(addr) => Some(addr),
+                Err(err) => {
+                    warn!("config: {}", error_chain(&err));
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn restart_only_changes(current: &Args, next: &Args) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if next.target_ip != current.target_ip {
+        changed.push("target_ip");
+    }
+    if next.port != current.port {
+        changed.push("port");
+    }
+    if next.prefer_ip != current.prefer_ip {
+        changed.push("prefer_ip");
+    }
+    if next.frame_ms != current.frame_ms {
+        changed.push("frame_ms");
+    }
+    if next.source != current.source {
+        changed.push("source");
+    }
+    if next.desktop_device != current.desktop_device {
+        changed.push("desktop_device");
+    }
+    if next.route_through_virtual != current.route_through_virtual {
+        changed.push("route_through_virtual");
+    }
+    if next.source_file != current.source_file {
+        changed.push("source_file");
+    }
+    if next.transport != current.transport {
+        changed.push("transport");
+    }
+    if next.vban_stream != current.vban_stream {
+        changed.push("vban_stream");
+    }
+    if next.aes67_session != current.aes67_session {
+        changed.push("aes67_session");
+    }
+    if next.aes67_encoding != current.aes67_encoding {
+        changed.push("aes67_encoding");
+    }
+    if next.write_sdp != current.write_sdp {
+        changed.push("write_sdp");
+    }
+    if next.bt_device != current.bt_device {
+        changed.push("bt_device");
+    }
+    if next.bt_channel != current.bt_channel {
+        changed.push("bt_channel");
+    }
+    if next.key != current.key {
+        changed.push("key");
+    }
+    if next.key_name != current.key_name {
+        changed.push("key_name");
+    }
+    if next.cipher != current.cipher {
+        changed.push("cipher");
+    }
+    if next.rotate_key_mins != current.rotate_key_mins {
+        changed.push("rotate_key_mins");
+    }
+    if next.noise != current.noise {
+        changed.push("noise");
+    }
+    if next.pins != current.pins {
+        changed.push("pins");
+    }
+    if next.access_token != current.access_token {
+        changed.push("access_token");
+    }
+    if next.remote_volume != current.remote_volume {
+        changed.push("remote_volume");
+    }
+    if next.ptp != current.ptp {
+        changed.push("ptp");
+    }
+    if next.pace != current.pace {
+        changed.push("pace");
+    }
+    if next.pause_notice != current.pause_notice {
+        changed.push("pause_notice");
+    }
+    if next.playout_delay_ms != current.playout_delay_ms {
+        changed.push("playout_delay_ms");
+    }
+    if next.late_audio != current.late_audio {
+        changed.push("late_audio");
+    }
+    if next.max_audio_age_ms != current.max_audio_age_ms {
+        changed.push("max_audio_age_ms");
+    }
+    if next.queue_overflow != current.queue_overflow {
+        changed.push("queue_overflow");
+    }
+    if next.io_uring != current.io_uring {
+        changed.push("io_uring");
+    }
+    if next.gso != current.gso {
+        changed.push("gso");
+    }
+    if next.realtime != current.realtime {
+        changed.push("realtime");
+    }
+    if next.affinity != current.affinity {
+        changed.push("affinity");
+    }
+    if next.so_sndbuf != current.so_sndbuf {
+        changed.push("so_sndbuf");
+    }
+    if next.tcp_backlog_ms != current.tcp_backlog_ms {
+        changed.push("tcp_backlog_ms");
+    }
+    if next.max_buffered_ms != current.max_buffered_ms {
+        changed.push("max_buffered_ms");
+    }
+    if next.max_kbps != current.max_kbps {
+        changed.push("max_kbps");
+    }
+    if next.tee != current.tee {
+        changed.push("tee");
+    }
+    if next.tee_point != current.tee_point {
+        changed.push("tee_point");
+    }
+    if next.ndi != current.ndi {
+        changed.push("ndi");
+    }
+    if next.output != current.output {
+        changed.push("output");
+    }
+    if next.passthrough != current.passthrough {
+        changed.push("passthrough");
+    }
+    if next.encode_workers != current.encode_workers {
+        changed.push("encode_workers");
+    }
+    if next.plugins != current.plugins {
+        changed.push("plugins");
+    }
+    if next.watchdog_secs != current.watchdog_secs {
+        changed.push("watchdog_secs");
+    }
+    if next.control != current.control {
+        changed.push("control");
+    }
+    if next.control_http != current.control_http {
+        changed.push("control_http");
+    }
+    changed
+}
@@ -0,0 +1,312 @@
+//! The interactive terminal: colors by severity, and the status row stats lines are redrawn
+//! on while log lines scroll above it.
+
+use std::fmt::Display;
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Mutex, OnceLock};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Columns assumed when the terminal does not say.
+const DEFAULT_WIDTH: usize = 80;
+
+static TERMINAL: OnceLock<Terminal> = OnceLock::new();
+static STATUS: Mutex<StatusRow> = Mutex::new(StatusRow {
+    blocks: Vec::new(),
+    rows: 0,
+    closed: false,
+});
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorWhen {
+    /// On a terminal, unless `NO_COLOR` is set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Foreground colors, as their SGR codes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Tint {
+    Red = 31,
+    Green = 32,
+    Yellow = 33,
+    Cyan = 36,
+}
+
+impl Tint {
+    /// `text` in this color when `on`, as it is otherwise.
+    pub fn paint(self, text: impl Display, on: bool) -> String {
+        if on {
+            format!("\x1b[{}m{text}\x1b[0m", self as u8)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// What stdout and stderr turned out to be.
+#[derive(Debug)]
+struct Terminal {
+    stdout_color: bool,
+    stderr_color: bool,
+    /// Stdout is a terminal that takes cursor movements.
+    status_row: bool,
+    /// Stderr is that same terminal, so log lines have to go above the status row.
+    stderr_shares: bool,
+}
+
+impl Terminal {
+    fn detect(when: ColorWhen) -> Self {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        let dumb = std::env::var_os("TERM").is_some_and(|term| term == "dumb");
+        let stdout = io::stdout().is_terminal() && !dumb && enable_escapes(Stream::Stdout);
+        let stderr = io::stderr().is_terminal() && !dumb && enable_escapes(Stream::Stderr);
+        let color = |terminal: bool| match when {
+            ColorWhen::Auto => terminal && !no_color,
+            ColorWhen::Always => true,
+            ColorWhen::Never => false,
+        };
+        Self {
+            stdout_color: color(stdout),
+            stderr_color: color(stderr),
+            status_row: stdout,
+            stderr_shares: stdout && stderr,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Decides what the terminal gets; before this, and without it, `--color auto` applies.
+pub fn init(when: ColorWhen) {
+    let _ = TERMINAL.set(Terminal::detect(when));
+}
+
+fn terminal() -> &'static Terminal {
+    TERMINAL.get_or_init(|| Terminal::detect(ColorWhen::Auto))
+}
+
+pub fn stdout_color() -> bool {
+    terminal().stdout_color
+}
+
+pub fn stderr_color() -> bool {
+    terminal().stderr_color
+}
+
+/// Whether stats lines printed to stdout go on the status row instead of scrolling.
+pub fn status_row() -> bool {
+    terminal().status_row
+}
+
+/// The last lines of every session, drawn at the bottom of the terminal. The cursor stays
+/// at the end of the last line, so redrawing starts by going back up `rows`.
+struct StatusRow {
+    blocks: Vec<(String, String)>,
+    rows: usize,
+    /// Set on exit, after which nothing is drawn.
+    closed: bool,
+}
+
+impl StatusRow {
+    fn erase(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        write!(out, "\r")?;
+        if self.rows > 1 {
+            write!(out, "\x1b[{}A", self.rows - 1)?;
+        }
+        write!(out, "\x1b[J")?;
+        self.rows = 0;
+        Ok(())
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let text = self
+            .blocks
+            .iter()
+            .map(|(_, lines)| lines.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if text.is_empty() {
+            return Ok(());
+        }
+        write!(out, "{text}")?;
+        // Lines longer than the terminal wrap onto more rows.
+        let width = width().max(1);
+        self.rows = text
+            .split('\n')
+            .map(|line| visible_len(line).div_ceil(width).max(1))
+            .sum();
+        Ok(())
+    }
+}
+
+/// Shows `lines` on the status row as the stats of session `key`, in place of its previous
+/// ones.
+pub fn show_status(key: &str, lines: &str) -> io::Result<()> {
+    let mut status = STATUS.lock().unwrap();
+    if status.closed {
+        return Ok(());
+    }
+    let mut out = io::stdout().lock();
+    status.erase(&mut out)?;
+    match status.blocks.iter_mut().find(|(block, _)| block == key) {
+        Some((_, shown)) => *shown = lines.to_string(),
+        None => status.blocks.push((key.to_string(), lines.to_string())),
+    }
+    status.draw(&mut out)?;
+    out.flush()
+}
+
+/// Takes the stats of session `key` off the status row, leaving its last lines in the
+/// scrollback.
+pub fn end_status(key: &str) -> io::Result<()> {
+    let mut status = STATUS.lock().unwrap();
+    let Some(index) = status.blocks.iter().position(|(block, _)| block == key) else {
+        return Ok(());
+    };
+    let (_, lines) = status.blocks.remove(index);
+    let mut out = io::stdout().lock();
+    status.erase(&mut out)?;
+    writeln!(out, "{lines}")?;
+    status.draw(&mut out)?;
+    out.flush()
+}
+
+/// Leaves whatever the status row shows in the scrollback, before the process exits.
+pub fn finish() {
+    let mut status = STATUS.lock().unwrap();
+    status.closed = true;
+    status.blocks.clear();
+    if std::mem::take(&mut status.rows) > 0 {
+        let mut out = io::stdout().lock();
+        let _ = writeln!(out).and_then(|_| out.flush());
+    }
+}
+
+/// Stderr for log events, which go above the status row when they share its terminal.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LogWriter;
+
+/// Collects one formatted event and writes it out when dropped.
+pub struct LogLine {
+    bytes: Vec<u8>,
+}
+
+impl Write for LogLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogLine {
+    fn drop(&mut self) {
+        if !terminal().stderr_shares {
+            let _ = io::stderr().write_all(&self.bytes);
+            return;
+        }
+        let mut status = STATUS.lock().unwrap();
+        if status.rows == 0 {
+            let _ = io::stderr().write_all(&self.bytes);
+            return;
+        }
+        let mut out = io::stdout().lock();
+        let _ = status.erase(&mut out).and_then(|_| out.flush());
+        let _ = io::stderr().write_all(&self.bytes);
+        let _ = status.draw(&mut out).and_then(|_| out.flush());
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogWriter {
+    type Writer = LogLine;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogLine { bytes: Vec::new() }
+    }
+}
+
+/// Characters `line` takes up on screen, leaving out color sequences.
+fn visible_len(line: &str) -> usize {
+    let mut len = 0;
+    let mut escape = false;
+    for c in line.chars() {
+        match (escape, c) {
+            (false, '\x1b') => escape = true,
+            (false, _) => len += 1,
+            (true, 'm') => escape = false,
+            (true, _) => {}
+        }
+    }
+    len
+}
+
+#[cfg(unix)]
+fn width() -> usize {
+    // SAFETY: all-zero is a valid `winsize`, which TIOCGWINSZ fills in.
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    if ok && size.ws_col > 0 {
+        size.ws_col as usize
+    } else {
+        DEFAULT_WIDTH
+    }
+}
+
+#[cfg(windows)]
+fn width() -> usize {
+    use windows_sys::Win32::System::Console::{
+        GetConsoleScreenBufferInfo, GetStdHandle, CONSOLE_SCREEN_BUFFER_INFO, STD_OUTPUT_HANDLE,
+    };
+
+    // SAFETY: all-zero is a valid `CONSOLE_SCREEN_BUFFER_INFO`, which the call fills in.
+    let mut info: CONSOLE_SCREEN_BUFFER_INFO = unsafe { std::mem::zeroed() };
+    let ok = unsafe { GetConsoleScreenBufferInfo(GetStdHandle(STD_OUTPUT_HANDLE), &mut info) };
+    if ok != 0 {
+        (info.srWindow.Right - info.srWindow.Left + 1).max(1) as usize
+    } else {
+        DEFAULT_WIDTH
+    }
+}
+
+/// Terminals elsewhere take escape sequences as they are.
+#[cfg(not(windows))]
+fn enable_escapes(_stream: Stream) -> bool {
+    true
+}
+
+/// The Windows console takes escape sequences once asked to, from Windows 10 on.
+#[cfg(windows)]
+fn enable_escapes(stream: Stream) -> bool {
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+        STD_ERROR_HANDLE, STD_OUTPUT_HANDLE,
+    };
+
+    let handle = match stream {
+        Stream::Stdout => STD_OUTPUT_HANDLE,
+        Stream::Stderr => STD_ERROR_HANDLE,
+    };
+    // SAFETY: the handle is the process's own; `mode` outlives the call.
+    unsafe {
+        let handle = GetStdHandle(handle);
+        let mut mode = 0;
+        GetConsoleMode(handle, &mut mode) != 0
+            && SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+    }
+}
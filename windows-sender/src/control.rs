@@ -0,0 +1,261 @@
+#![cfg_attr(not(any(feature = "control", feature = "http")), allow(dead_code))]
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
+use serde_json::{json, Value};
+use windows_sender::dsp::EqBand;
+use windows_sender::error::error_chain;
+use windows_sender::sender::{AudioSender, SenderConfig};
+use windows_sender::transport::TransportKind;
+
+use crate::AudioSource;
+
+#[cfg(feature = "control")]
+mod socket;
+
+#[cfg(feature = "control")]
+pub use socket::{run_client, spawn_server};
+
+#[cfg(not(feature = "control"))]
+pub fn spawn_server(name: &str, _router: Router) -> anyhow::Result<()> {
+    anyhow::bail!("--control {name} needs a build with the `control` feature")
+}
+
+#[cfg(not(feature = "control"))]
+pub fn run_client(_name: &str, _session: Option<&str>, _command: &[String]) -> anyhow::Result<()> {
+    anyhow::bail!("audioctl needs a build with the `control` feature")
+}
+
+pub const DEFAULT_NAME: &str = "audio-link";
+
+#[derive(Clone, Debug)]
+pub enum ControlCommand {
+    Mute,
+    Unmute,
+    SetGain(f32),
+    SetEq(Vec<EqBand>),
+    Pause,
+    Resume,
+    SwitchSource(AudioSource),
+    Restart,
+    AddTarget(SocketAddr),
+    RemoveTarget(SocketAddr),
+    Targets,
+    Stats,
+    Status,
+    Events,
+    Sdp,
+    Mark(String),
+    Quit,
+}
+
+pub fn parse_addr(addr: &str) -> Result<SocketAddr, String> {
+    addr.parse()
+        .map_err(|_| format!("invalid target endpoint: {addr}"))
+}
+
+/// `Ok(Value::Null)` means plain success; strings are informational messages.
+pub type ControlReply = Result<Value, String>;
+
+pub struct ControlRequest {
+    command: ControlCommand,
+    reply: Sender<ControlReply>,
+}
+
+/// What the main thread should do with the sender after serving control requests.
+pub enum Outcome {
+    Finished,
+    Restart,
+    Quit,
+}
+
+pub fn channel() -> (Sender<ControlRequest>, Receiver<ControlRequest>) {
+    bounded(8)
+}
+
+/// Hands `command` to the thread that owns the sender and waits for its answer.
+fn request(tx: &Sender<ControlRequest>, command: ControlCommand) -> ControlReply {
+    let (reply_tx, reply_rx) = bounded(1);
+    tx.send(ControlRequest {
+        command,
+        reply: reply_tx,
+    })
+    .map_err(|_| "sender is shutting down".to_string())?;
+    reply_rx
+        .recv_timeout(Duration::from_secs(5))
+        .map_err(|_| "sender did not answer".to_string())?
+}
+
+/// Routes control requests to the session they name (the first session by default).
+#[derive(Clone)]
+pub struct Router {
+    sessions: Arc<Vec<(String, Sender<ControlRequest>)>>,
+    reload: Option<Sender<()>>,
+}
+
+impl Router {
+    /// `reload` wakes the config watcher; it is `None` when no config file is in use.
+    pub fn new(
+        sessions: Vec<(String, Sender<ControlRequest>)>,
+        reload: Option<Sender<()>>,
+    ) -> Self {
+        Self {
+            sessions: Arc::new(sessions),
+            reload,
+        }
+    }
+
+    /// Re-reads the config file now instead of waiting for the next poll.
+    pub fn reload_config(&self) -> ControlReply {
+        let reload = self.reload.as_ref().ok_or("no config file to reload")?;
+        let _ = reload.try_send(());
+        Ok(Value::Null)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.sessions.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    pub fn request(&self, session: Option<&str>, command: ControlCommand) -> ControlReply {
+        let tx = match session {
+            Some(name) => self
+                .sessions
+                .iter()
+                .find(|(session, _)| session == name)
+                .map(|(_, tx)| tx)
+                .ok_or_else(|| format!("unknown session: {name}"))?,
+            None => &self.sessions.first().ok_or("no sessions are running")?.1,
+        };
+        request(tx, command)
+    }
+
+    pub fn broadcast(&self, command: ControlCommand) -> Vec<ControlReply> {
+        self.sessions
+            .iter()
+            .map(|(_, tx)| request(tx, command.clone()))
+            .collect()
+    }
+}
+
+/// Serves control requests until the sender stops or a command needs the main thread.
+/// `on_idle` runs whenever no request arrived for a while.
+pub fn serve(
+    sender: &AudioSender,
+    config: &mut SenderConfig,
+    requests: &Receiver<ControlRequest>,
+    on_idle: &mut dyn FnMut(),
+) -> Outcome {
+    let control = sender.control();
+    loop {
+        let request = match requests.recv_timeout(Duration::from_millis(200)) {
+            Ok(request) => request,
+            Err(RecvTimeoutError::Timeout) => {
+                if !sender.is_running() {
+                    return Outcome::Finished;
+                }
+                on_idle();
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => return Outcome::Finished,
+        };
+
+        let mut outcome = None;
+        let reply = match request.command {
+            ControlCommand::Mute => {
+                control.update_dsp(|dsp| dsp.muted = true);
+                Ok(Value::Null)
+            }
+            ControlCommand::Unmute => {
+                control.update_dsp(|dsp| dsp.muted = false);
+                Ok(Value::Null)
+            }
+            ControlCommand::SetGain(gain_db) => {
+                control.update_dsp(|dsp| dsp.gain_db = gain_db);
+                Ok(Value::Null)
+            }
+            ControlCommand::SetEq(bands) => {
+                control.update_dsp(|dsp| dsp.eq = bands);
+                Ok(Value::Null)
+            }
+            ControlCommand::Pause => {
+                control.set_paused(true);
+                Ok(Value::Null)
+            }
+            ControlCommand::Resume => {
+                control.set_paused(false);
+                Ok(Value::Null)
+            }
+            ControlCommand::AddTarget(addr) => match control.add_target(addr) {
+                Ok(true) => Ok(Value::Null),
+                Ok(false) => Err(format!("{addr} is already a target")),
+                Err(err) => Err(error_chain(&err)),
+            },
+            ControlCommand::RemoveTarget(addr) => match control.remove_target(addr) {
+                Ok(true) => Ok(Value::Null),
+                Ok(false) => Err(format!("{addr} is not a target")),
+                Err(err) => Err(error_chain(&err)),
+            },
+            ControlCommand::Targets => Ok(json!(control.targets())),
+            ControlCommand::Stats => Ok(json!(control.snapshot())),
+            ControlCommand::Events => Ok(json!(control.events())),
+            ControlCommand::Sdp => match control.sdp() {
+                Some(sdp) => Ok(Value::String(sdp)),
+                None if config.transport == TransportKind::Aes67 => {
+                    Err("the stream has not started yet".to_string())
+                }
+                None => Err("only --transport aes67 streams have an SDP".to_string()),
+            },
+            ControlCommand::Mark(name) => control
+                .mark(&name)
+                .map(|()| Value::Null)
+                .map_err(|err| error_chain(&err)),
+            ControlCommand::Status => {
+                let format = sender.format();
+                let dsp = control.dsp_settings();
+                Ok(json!({
+                    "running": sender.is_running(),
+                    "paused": control.is_paused(),
+                    "source": sender.source().as_str(),
+                    "source_name": sender.source_name(),
+                    "transport": config.transport.as_str(),
+                    "sample_rate": format.sample_rate,
+                    "channels": format.channels,
+                    "frame_ms": config.frame_ms,
+                    "targets": control.targets(),
+                    "gain_db": dsp.gain_db,
+                    "muted": dsp.muted,
+                    "backlog": sender.backlog(),
+                    "stats": control.snapshot(),
+                }))
+            }
+            ControlCommand::SwitchSource(source) => match control.switch_source(source.into()) {
+                Ok(name) => {
+                    // A later restart comes back on the source switched to.
+                    config.source = source.into();
+                    Ok(json!(format!("capturing from {name}")))
+                }
+                Err(err) => Err(error_chain(&err)),
+            },
+            ControlCommand::Restart => {
+                config.targets = control.endpoints();
+                config.dsp = control.dsp_settings();
+                outcome = Some(Outcome::Restart);
+                Ok(json!(format!(
+                    "restarting capture from {}",
+                    config.source.as_str()
+                )))
+            }
+            ControlCommand::Quit => {
+                outcome = Some(Outcome::Quit);
+                Ok(Value::Null)
+            }
+        };
+        let _ = request.reply.send(reply);
+        if let Some(outcome) = outcome {
+            return outcome;
+        }
+    }
+}
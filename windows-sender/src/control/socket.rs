@@ -0,0 +1,169 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use interprocess::local_socket::prelude::*;
+use interprocess::local_socket::{
+    GenericFilePath, GenericNamespaced, ListenerOptions, Name, Stream,
+};
+use serde_json::{json, Value};
+use tracing::warn;
+
+use super::{parse_addr, ControlCommand, ControlReply, Router};
+use crate::AudioSource;
+
+impl ControlCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().ok_or("empty command")?;
+        let arg = words.next();
+        let parsed = match (command, arg) {
+            ("mute", None) => Self::Mute,
+            ("unmute", None) => Self::Unmute,
+            ("set-gain", Some(db)) => Self::SetGain(
+                db.parse()
+                    .map_err(|_| format!("invalid gain value: {db}"))?,
+            ),
+            ("pause", None) => Self::Pause,
+            ("resume", None) => Self::Resume,
+            ("switch-source", Some(source)) => Self::SwitchSource(
+                AudioSource::from_str(source, true)
+                    .map_err(|_| format!("unknown source: {source}"))?,
+            ),
+            ("restart", None) => Self::Restart,
+            ("add-target", Some(addr)) => Self::AddTarget(parse_addr(addr)?),
+            ("remove-target", Some(addr)) => Self::RemoveTarget(parse_addr(addr)?),
+            ("targets", None) => Self::Targets,
+            ("stats", None) => Self::Stats,
+            ("status", None) => Self::Status,
+            ("events", None) => Self::Events,
+            ("sdp", None) => Self::Sdp,
+            ("mark", Some(name)) => Self::Mark(name.to_string()),
+            ("quit", None) => Self::Quit,
+            _ => return Err(format!("unknown command: {}", line.trim())),
+        };
+        if words.next().is_some() {
+            return Err(format!("too many arguments: {}", line.trim()));
+        }
+        Ok(parsed)
+    }
+}
+
+impl Router {
+    /// Handles one text command line, optionally prefixed with `@session`.
+    fn dispatch_line(&self, line: &str) -> ControlReply {
+        let line = line.trim();
+        let (session, command) = match line.strip_prefix('@') {
+            Some(rest) => {
+                let (name, command) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                (Some(name), command)
+            }
+            None => (None, line),
+        };
+        match command.trim() {
+            "sessions" => return Ok(json!(self.names())),
+            "reload" => return self.reload_config(),
+            _ => {}
+        }
+        let command = ControlCommand::parse(command)?;
+        self.request(session, command)
+    }
+}
+
+fn socket_name(name: &str) -> io::Result<Name<'static>> {
+    if GenericNamespaced::is_supported() {
+        format!("{name}.sock").to_ns_name::<GenericNamespaced>()
+    } else {
+        std::env::temp_dir()
+            .join(format!("{name}.sock"))
+            .to_fs_name::<GenericFilePath>()
+    }
+}
+
+/// Listens on a named pipe (Windows) or Unix socket and forwards parsed commands.
+pub fn spawn_server(name: &str, router: Router) -> Result<()> {
+    let listener = ListenerOptions::new()
+        .name(socket_name(name)?)
+        .create_sync()
+        .with_context(|| format!("failed to create control endpoint {name}"))?;
+
+    thread::Builder::new()
+        .name("control".to_string())
+        .spawn(move || {
+            for conn in listener.incoming() {
+                let conn = match conn {
+                    Ok(conn) => conn,
+                    Err(err) => {
+                        warn!("control accept error: {err}");
+                        continue;
+                    }
+                };
+                if let Err(err) = handle_client(conn, &router) {
+                    warn!("control client error: {err}");
+                }
+            }
+        })
+        .context("failed to spawn control thread")?;
+    Ok(())
+}
+
+fn handle_client(conn: Stream, router: &Router) -> io::Result<()> {
+    let mut conn = BufReader::new(conn);
+    let mut line = String::new();
+    conn.read_line(&mut line)?;
+
+    let reply = router.dispatch_line(&line);
+    conn.get_mut().write_all(render_text(&reply).as_bytes())?;
+    conn.get_mut().write_all(b"\n")?;
+    conn.get_mut().flush()
+}
+
+fn render_text(reply: &ControlReply) -> String {
+    match reply {
+        Ok(Value::Null) => "ok".to_string(),
+        Ok(Value::String(message)) => format!("ok: {message}"),
+        Ok(Value::Object(map)) => map
+            .iter()
+            .map(|(key, value)| format!("{key}={}", plain(value)))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Ok(Value::Array(items)) => items.iter().map(plain).collect::<Vec<_>>().join("\n"),
+        Ok(other) => other.to_string(),
+        Err(err) => format!("error: {err}"),
+    }
+}
+
+fn plain(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Sends one command to a running sender and prints its answer.
+pub fn run_client(name: &str, session: Option<&str>, command: &[String]) -> Result<()> {
+    let command = command.join(" ");
+    if command != "sessions" && command != "reload" {
+        ControlCommand::parse(&command).map_err(anyhow::Error::msg)?;
+    }
+    let line = match session {
+        Some(session) => format!("@{session} {command}"),
+        None => command,
+    };
+
+    let mut conn = Stream::connect(socket_name(name)?)
+        .with_context(|| format!("no sender is listening on control endpoint {name}"))?;
+    conn.write_all(line.as_bytes())?;
+    conn.write_all(b"\n")?;
+    conn.flush()?;
+
+    let mut response = String::new();
+    conn.read_to_string(&mut response)?;
+    let response = response.trim_end();
+    println!("{response}");
+    if response.starts_with("error") {
+        bail!("command failed");
+    }
+    Ok(())
+}
@@ -0,0 +1,719 @@
+//! Authenticated encryption of packet payloads with a pre-shared key.
+//!
+//! A sealed packet keeps its plaintext header, with `FLAG_ENCRYPTED` set (plus
+//! `FLAG_CHACHA20` for ChaCha20-Poly1305) and `payload_len` covering everything after it:
+//!
+//! ```text
+//! header (32) | session id (8) | ciphertext | tag (16)
+//! ```
+//!
+//! The nonce is the session id followed by the little-endian sequence number, and the
+//! header is authenticated as associated data. Every sealer picks a random session id,
+//! so a sequence number that starts over never reuses a nonce under the same key.
+//!
+//! `Cipher::HmacSha256` keeps the same layout with `FLAG_AUTHENTICATED` instead: the
+//! payload stays readable and the tag is HMAC-SHA256 over nonce, header and payload,
+//! truncated to 16 bytes.
+//!
+//! A sealer with a rotation period also sets `FLAG_KEY_EPOCH` and puts the epoch (the
+//! number of periods since the sealer started, little-endian) after the session id, which
+//! is how receivers learn of each change. Epoch 0 seals with HKDF-SHA256 of the pre-shared
+//! key and every later epoch with HKDF-SHA256 of the key before it, which the sealer then
+//! forgets, so a key that leaks opens neither earlier epochs nor the pre-shared key:
+//!
+//! ```text
+//! header (32) | session id (8) | epoch (4) | ciphertext | tag (16)
+//! ```
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod noise;
+
+use std::fmt;
+use std::str::FromStr;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::Aes256Gcm;
+use base64::Engine;
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::error::CryptoError;
+use crate::protocol::{
+    FLAG_AUTHENTICATED, FLAG_CHACHA20, FLAG_ENCRYPTED, FLAG_KEY_EPOCH, HEADER_SIZE,
+};
+
+pub const KEY_LEN: usize = 32;
+pub const SESSION_ID_LEN: usize = 8;
+pub const TAG_LEN: usize = 16;
+pub const EPOCH_LEN: usize = 4;
+/// Epochs a receiver ratchets forward for one packet once it follows a stream; more than
+/// a sender goes through while its packets are lost.
+const MAX_EPOCH_SKIP: u32 = 64;
+/// Epochs a receiver ratchets through from the pre-shared key to join a stream: over a
+/// month of one-minute periods.
+const MAX_EPOCH_JOIN: u32 = 1 << 16;
+/// Bytes an encrypted payload grows by.
+pub const OVERHEAD: usize = SESSION_ID_LEN + TAG_LEN;
+
+/// The AEAD a sender seals with; receivers tell them apart by the header flags.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Cipher {
+    #[default]
+    Aes256Gcm,
+    /// Faster than AES on CPUs without AES instructions, such as many phones.
+    ChaCha20Poly1305,
+    /// Only authenticates: rejects spoofed or modified packets but leaves audio readable.
+    HmacSha256,
+}
+
+impl Cipher {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Cipher::Aes256Gcm => "AES-256-GCM",
+            Cipher::ChaCha20Poly1305 => "ChaCha20-Poly1305",
+            Cipher::HmacSha256 => "HMAC-SHA256 (authentication only)",
+        }
+    }
+
+    fn flags(self) -> u8 {
+        match self {
+            Cipher::Aes256Gcm => FLAG_ENCRYPTED,
+            Cipher::ChaCha20Poly1305 => FLAG_ENCRYPTED | FLAG_CHACHA20,
+            Cipher::HmacSha256 => FLAG_AUTHENTICATED,
+        }
+    }
+}
+
+enum Aead {
+    Aes256Gcm(Box<Aes256Gcm>),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+    HmacSha256([u8; KEY_LEN]),
+}
+
+impl Aead {
+    fn new(cipher: Cipher, key: &Key) -> Self {
+        match cipher {
+            Cipher::Aes256Gcm => Aead::Aes256Gcm(Box::new(Aes256Gcm::new(&key.0.into()))),
+            Cipher::ChaCha20Poly1305 => {
+                Aead::ChaCha20Poly1305(ChaCha20Poly1305::new(&key.0.into()))
+            }
+            Cipher::HmacSha256 => Aead::HmacSha256(key.0),
+        }
+    }
+
+    fn seal(&self, nonce: &[u8; 12], aad: &[u8], data: &mut [u8]) -> [u8; TAG_LEN] {
+        match self {
+            Aead::Aes256Gcm(cipher) => cipher
+                .encrypt_in_place_detached(nonce.into(), aad, data)
+                .expect("packet payloads are far below the GCM limit")
+                .into(),
+            Aead::ChaCha20Poly1305(cipher) => cipher
+                .encrypt_in_place_detached(nonce.into(), aad, data)
+                .expect("packet payloads are far below the ChaCha20 limit")
+                .into(),
+            Aead::HmacSha256(key) => hmac(key, nonce, aad, data).finalize().into_bytes()[..TAG_LEN]
+                .try_into()
+                .unwrap(),
+        }
+    }
+
+    fn open(&self, nonce: &[u8; 12], aad: &[u8], data: &mut [u8], tag: &[u8; TAG_LEN]) -> bool {
+        match self {
+            Aead::Aes256Gcm(cipher) => cipher
+                .decrypt_in_place_detached(nonce.into(), aad, data, tag.into())
+                .is_ok(),
+            Aead::ChaCha20Poly1305(cipher) => cipher
+                .decrypt_in_place_detached(nonce.into(), aad, data, tag.into())
+                .is_ok(),
+            Aead::HmacSha256(key) => hmac(key, nonce, aad, data)
+                .verify_truncated_left(tag)
+                .is_ok(),
+        }
+    }
+}
+
+/// A 256-bit pre-shared key, given as 64 hex digits or as base64.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key([u8; KEY_LEN]);
+
+impl Key {
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Self {
+        Self(bytes)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn generate() -> Result<Self, CryptoError> {
+        let mut bytes = [0; KEY_LEN];
+        getrandom::fill(&mut bytes).map_err(CryptoError::Random)?;
+        Ok(Self(bytes))
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8; KEY_LEN] {
+        &self.0
+    }
+
+    /// The key as 64 hex digits, for handing it to a receiver.
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// The key of the epoch after the one this key seals; see `crypto`.
+    pub fn ratchet(&self) -> Self {
+        let mut next = [0; KEY_LEN];
+        Hkdf::<Sha256>::new(None, &self.0)
+            .expand(b"audio-link key ratchet", &mut next)
+            .expect("32 bytes is a valid HKDF-SHA256 length");
+        Self(next)
+    }
+
+    /// The start of the key's SHA-256 in groups of four hex digits, to compare both ends
+    /// without showing the key.
+    pub fn fingerprint(&self) -> String {
+        Sha256::digest(self.0)[..8]
+            .chunks(2)
+            .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl FromStr for Key {
+    type Err = CryptoError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        let bytes = if text.len() == 2 * KEY_LEN && text.chars().all(|c| c.is_ascii_hexdigit()) {
+            (0..KEY_LEN)
+                .map(|i| u8::from_str_radix(&text[2 * i..2 * i + 2], 16).unwrap())
+                .collect()
+        } else {
+            base64::engine::general_purpose::STANDARD
+                .decode(text)
+                .map_err(|_| CryptoError::InvalidKey)?
+        };
+        bytes
+            .try_into()
+            .map(Self)
+            .map_err(|_| CryptoError::InvalidKey)
+    }
+}
+
+/// Never prints the key itself.
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// What the sealers of a sender are made from. Every target seals with one of its own, so
+/// each has its own session id and sequence numbers never share a nonce.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Debug)]
+pub struct Sealing {
+    pub cipher: Cipher,
+    pub key: Key,
+    /// See `PacketSealer::rotating`.
+    pub rotation: Option<Duration>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Sealing {
+    pub fn sealer(&self) -> Result<PacketSealer, CryptoError> {
+        match self.rotation {
+            Some(period) => PacketSealer::rotating(self.cipher, &self.key, period),
+            None => PacketSealer::new(self.cipher, &self.key),
+        }
+    }
+
+    /// Bytes a payload grows by when sealed.
+    pub fn overhead(&self) -> usize {
+        match self.rotation {
+            Some(_) => OVERHEAD + EPOCH_LEN,
+            None => OVERHEAD,
+        }
+    }
+}
+
+/// Encrypts the packets of one stream.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct PacketSealer {
+    cipher: Cipher,
+    aead: Aead,
+    session_id: [u8; SESSION_ID_LEN],
+    last_seq: Option<u32>,
+    rotation: Option<Rotation>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct Rotation {
+    /// The current epoch's key, dropped as soon as the next one is derived from it.
+    key: Key,
+    period: Duration,
+    epoch: u32,
+    next_at: Instant,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PacketSealer {
+    pub fn new(cipher: Cipher, key: &Key) -> Result<Self, CryptoError> {
+        Ok(Self {
+            cipher,
+            aead: Aead::new(cipher, key),
+            session_id: random_session_id()?,
+            last_seq: None,
+            rotation: None,
+        })
+    }
+
+    /// Seals with a key ratcheted from `key` that moves on every `period`.
+    pub fn rotating(cipher: Cipher, key: &Key, period: Duration) -> Result<Self, CryptoError> {
+        let period = period.max(Duration::from_secs(1));
+        let key = key.ratchet();
+        Ok(Self {
+            cipher,
+            aead: Aead::new(cipher, &key),
+            session_id: random_session_id()?,
+            last_seq: None,
+            rotation: Some(Rotation {
+                key,
+                period,
+                epoch: 0,
+                next_at: Instant::now() + period,
+            }),
+        })
+    }
+
+    /// Bytes a payload grows by when sealed.
+    pub fn overhead(&self) -> usize {
+        match self.rotation {
+            Some(_) => OVERHEAD + EPOCH_LEN,
+            None => OVERHEAD,
+        }
+    }
+
+    /// Encrypts the payload of a freshly built `packet` in place.
+    pub fn seal(&mut self, packet: &mut Vec<u8>, seq: u32) -> Result<(), CryptoError> {
+        let payload_len = packet.len() - HEADER_SIZE;
+        let overhead = self.overhead();
+        if payload_len + overhead > u16::MAX as usize {
+            return Err(CryptoError::PayloadTooLarge { len: payload_len });
+        }
+        // A wrapped sequence would repeat nonces under the old session id.
+        if self.last_seq.is_some_and(|last| seq <= last) {
+            self.session_id = random_session_id()?;
+        }
+        self.last_seq = Some(seq);
+        if let Some(rotation) = self.rotation.as_mut() {
+            let now = Instant::now();
+            if now >= rotation.next_at {
+                while now >= rotation.next_at {
+                    rotation.key = rotation.key.ratchet();
+                    rotation.epoch = rotation.epoch.wrapping_add(1);
+                    rotation.next_at += rotation.period;
+                }
+                self.aead = Aead::new(self.cipher, &rotation.key);
+                self.session_id = random_session_id()?;
+            }
+        }
+
+        packet[7] |= self.cipher.flags();
+        packet[26..28].copy_from_slice(&((payload_len + overhead) as u16).to_le_bytes());
+        let mut prefix = self.session_id.to_vec();
+        if let Some(rotation) = &self.rotation {
+            packet[7] |= FLAG_KEY_EPOCH;
+            prefix.extend_from_slice(&rotation.epoch.to_le_bytes());
+        }
+        let prefix_len = prefix.len();
+        packet.splice(HEADER_SIZE..HEADER_SIZE, prefix);
+        let (header, body) = packet.split_at_mut(HEADER_SIZE);
+        let tag = self.aead.seal(
+            &nonce(&self.session_id, seq),
+            header,
+            &mut body[prefix_len..],
+        );
+        packet.extend_from_slice(&tag);
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn random_session_id() -> Result<[u8; SESSION_ID_LEN], CryptoError> {
+    let mut session_id = [0; SESSION_ID_LEN];
+    getrandom::fill(&mut session_id).map_err(CryptoError::Random)?;
+    Ok(session_id)
+}
+
+/// Authenticates and decrypts packets sealed with the same key, in any cipher.
+pub struct PacketOpener {
+    key: Key,
+    aeads: Aeads,
+    /// The newest epoch seen and its key, which later epochs are ratcheted from.
+    chain: Option<(u32, Key)>,
+    /// The newest epoch seen and the one before, for packets still in flight across a
+    /// rotation.
+    epochs: Vec<(u32, Aeads)>,
+}
+
+struct Aeads {
+    aes: Aead,
+    chacha: Aead,
+    hmac: Aead,
+}
+
+impl Aeads {
+    fn new(key: &Key) -> Self {
+        Self {
+            aes: Aead::new(Cipher::Aes256Gcm, key),
+            chacha: Aead::new(Cipher::ChaCha20Poly1305, key),
+            hmac: Aead::new(Cipher::HmacSha256, key),
+        }
+    }
+
+    fn for_flags(&self, flags: u8) -> &Aead {
+        if flags & FLAG_AUTHENTICATED != 0 {
+            &self.hmac
+        } else if flags & FLAG_CHACHA20 != 0 {
+            &self.chacha
+        } else {
+            &self.aes
+        }
+    }
+}
+
+impl PacketOpener {
+    pub fn new(key: &Key) -> Self {
+        Self {
+            key: key.clone(),
+            aeads: Aeads::new(key),
+            chain: None,
+            epochs: Vec::new(),
+        }
+    }
+
+    /// The plaintext payload, or `None` when the packet was not sealed with this key or
+    /// was modified on the way.
+    pub fn open(&mut self, header: &[u8], payload: &[u8], seq: u32) -> Option<Vec<u8>> {
+        let flags = header[7];
+        let prefix_len = if flags & FLAG_KEY_EPOCH != 0 {
+            SESSION_ID_LEN + EPOCH_LEN
+        } else {
+            SESSION_ID_LEN
+        };
+        if payload.len() < prefix_len + TAG_LEN {
+            return None;
+        }
+        let (prefix, rest) = payload.split_at(prefix_len);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        let nonce = nonce(prefix[..SESSION_ID_LEN].try_into().unwrap(), seq);
+        let mut plaintext = ciphertext.to_vec();
+        let open = |aeads: &Aeads, plaintext: &mut [u8]| {
+            aeads.for_flags(flags).open(
+                &nonce,
+                &header[..HEADER_SIZE],
+                plaintext,
+                tag.try_into().unwrap(),
+            )
+        };
+        if flags & FLAG_KEY_EPOCH == 0 {
+            return open(&self.aeads, &mut plaintext).then_some(plaintext);
+        }
+
+        let epoch = u32::from_le_bytes(prefix[SESSION_ID_LEN..].try_into().unwrap());
+        if let Some((_, aeads)) = self.epochs.iter().find(|(known, _)| *known == epoch) {
+            return open(aeads, &mut plaintext).then_some(plaintext);
+        }
+        // Later epochs ratchet on from the newest key. Earlier ones only come from a sender
+        // that started over, at a low epoch, and ratchet from the pre-shared key again.
+        let (from, key) = match &self.chain {
+            Some((newest, key)) if epoch > *newest => {
+                if epoch - newest > MAX_EPOCH_SKIP {
+                    return None;
+                }
+                (*newest, key.clone())
+            }
+            Some(_) if epoch >= MAX_EPOCH_SKIP => return None,
+            _ if epoch >= MAX_EPOCH_JOIN => return None,
+            _ => (0, self.key.ratchet()),
+        };
+        let key = (from..epoch).fold(key, |key, _| key.ratchet());
+        // Only a packet that authenticates under the epoch's key moves the window.
+        let aeads = Aeads::new(&key);
+        if !open(&aeads, &mut plaintext) {
+            return None;
+        }
+        // A late packet of the epoch before leaves the chain where it is.
+        if self
+            .chain
+            .as_ref()
+            .is_none_or(|(newest, _)| epoch > *newest || epoch.saturating_add(1) < *newest)
+        {
+            self.chain = Some((epoch, key));
+        }
+        let newest = self.chain.as_ref().map_or(epoch, |(newest, _)| *newest);
+        self.epochs
+            .retain(|(known, _)| *known <= newest && known.saturating_add(1) >= newest);
+        self.epochs.push((epoch, aeads));
+        Some(plaintext)
+    }
+}
+
+/// Sequence numbers a session may fall behind its newest packet and still be accepted.
+pub const REPLAY_WINDOW: u32 = 1024;
+
+/// Rejects packets of a stream that were already accepted once, so a recorded packet
+/// cannot be played again: a sliding window over the sequence numbers of the current
+/// session, as in RFC 4303. A new session id only takes over when its (authenticated)
+/// send time is newer than the current session's, which stops whole old sessions too.
+#[derive(Default)]
+pub struct ReplayWindow {
+    session: Option<ReplaySession>,
+}
+
+struct ReplaySession {
+    id: [u8; SESSION_ID_LEN],
+    send_time_us: u64,
+    highest: u32,
+    seen: [u64; REPLAY_WINDOW as usize / 64],
+}
+
+impl ReplayWindow {
+    /// Records the packet and returns whether it is new.
+    pub fn accept(&mut self, session_id: &[u8], seq: u32, send_time_us: u64) -> bool {
+        match &mut self.session {
+            Some(session) if session.id == session_id => {
+                if !session.accept(seq) {
+                    return false;
+                }
+                session.send_time_us = session.send_time_us.max(send_time_us);
+                true
+            }
+            Some(session) if send_time_us <= session.send_time_us => false,
+            _ => {
+                let mut session = ReplaySession {
+                    id: session_id.try_into().unwrap(),
+                    send_time_us,
+                    highest: seq,
+                    seen: [0; REPLAY_WINDOW as usize / 64],
+                };
+                session.accept(seq);
+                self.session = Some(session);
+                true
+            }
+        }
+    }
+}
+
+impl ReplaySession {
+    fn accept(&mut self, seq: u32) -> bool {
+        // Sealers switch session ids before the sequence wraps, so plain comparison works.
+        if seq > self.highest {
+            if seq - self.highest >= REPLAY_WINDOW {
+                self.seen = [0; REPLAY_WINDOW as usize / 64];
+            } else {
+                for newer in self.highest + 1..=seq {
+                    let (word, bit) = Self::bit(newer);
+                    self.seen[word] &= !bit;
+                }
+            }
+            self.highest = seq;
+        } else if self.highest - seq >= REPLAY_WINDOW {
+            return false;
+        }
+        let (word, bit) = Self::bit(seq);
+        if self.seen[word] & bit != 0 {
+            return false;
+        }
+        self.seen[word] |= bit;
+        true
+    }
+
+    fn bit(seq: u32) -> (usize, u64) {
+        let index = (seq % REPLAY_WINDOW) as usize;
+        (index / 64, 1 << (index % 64))
+    }
+}
+
+/// HMAC-SHA256 over nonce, header and payload; tags keep its first `TAG_LEN` bytes.
+fn hmac(key: &[u8; KEY_LEN], nonce: &[u8; 12], aad: &[u8], data: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(nonce);
+    mac.update(aad);
+    mac.update(data);
+    mac
+}
+
+fn nonce(session_id: &[u8; SESSION_ID_LEN], seq: u32) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[..SESSION_ID_LEN].copy_from_slice(session_id);
+    nonce[SESSION_ID_LEN..].copy_from_slice(&seq.to_le_bytes());
+    nonce
+}
+
+/// Compares without an early exit, so timing does not reveal how much of a tag matched.
+pub(crate) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Test case 16 of the GCM specification submitted to NIST (McGrew and Viega).
+    #[test]
+    fn aes_256_gcm_matches_nist_vector() {
+        let key: Key = "feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308"
+            .parse()
+            .unwrap();
+        let nonce: [u8; 12] = hex("cafebabefacedbaddecaf888").try_into().unwrap();
+        let aad = hex("feedfacedeadbeeffeedfacedeadbeefabaddad2");
+        let mut data = hex(
+            "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+             1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+        );
+        let aead = Aead::new(Cipher::Aes256Gcm, &key);
+        let tag = aead.seal(&nonce, &aad, &mut data);
+        assert_eq!(
+            data,
+            hex(
+                "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+                 8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662"
+            )
+        );
+        assert_eq!(tag.to_vec(), hex("76fc6ece0f4e1768cddf8853bb2d551b"));
+        assert!(aead.open(&nonce, &aad, &mut data, &tag));
+        data[0] ^= 1;
+        assert!(!aead.open(&nonce, &aad, &mut data, &tag));
+    }
+
+    /// RFC 8439, section 2.8.2.
+    #[test]
+    fn chacha20_poly1305_matches_rfc_8439_vector() {
+        let key = Key::from_bytes(std::array::from_fn(|i| 0x80 + i as u8));
+        let nonce: [u8; 12] = hex("070000004041424344454647").try_into().unwrap();
+        let aad = hex("50515253c0c1c2c3c4c5c6c7");
+        let mut data = b"Ladies and Gentlemen of the class of '99: If I could offer you only one \
+                         tip for the future, sunscreen would be it."
+            .to_vec();
+        let aead = Aead::new(Cipher::ChaCha20Poly1305, &key);
+        let tag = aead.seal(&nonce, &aad, &mut data);
+        assert_eq!(
+            data,
+            hex(
+                "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+                 3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+                 92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+                 3ff4def08e4b7a9de576d26586cec64b6116"
+            )
+        );
+        assert_eq!(tag.to_vec(), hex("1ae10b594f09e26a7e902ecbd0600691"));
+        assert!(aead.open(&nonce, &aad, &mut data, &tag));
+        data[0] ^= 1;
+        assert!(!aead.open(&nonce, &aad, &mut data, &tag));
+    }
+
+    /// RFC 4231, test case 2; HMAC pads the short key with zeros to the same effect.
+    #[test]
+    fn hmac_sha256_matches_rfc_4231_vector() {
+        let mut key = [0; KEY_LEN];
+        key[..4].copy_from_slice(b"Jefe");
+        let message = b"what do ya want for nothing?";
+        let (nonce, rest) = message.split_at(12);
+        let (aad, data) = rest.split_at(6);
+        let aead = Aead::new(Cipher::HmacSha256, &Key::from_bytes(key));
+        let mut data = data.to_vec();
+        let tag = aead.seal(nonce.try_into().unwrap(), aad, &mut data);
+        assert_eq!(tag.to_vec(), hex("5bdcc146bf60754e6a042426089575c7"));
+        assert_eq!(data, &rest[6..]);
+        assert!(aead.open(nonce.try_into().unwrap(), aad, &mut data, &tag));
+        data[0] ^= 1;
+        assert!(!aead.open(nonce.try_into().unwrap(), aad, &mut data, &tag));
+    }
+
+    #[test]
+    fn key_derivations_match_the_receivers() {
+        let key = Key::from_bytes([7; KEY_LEN]);
+        assert_eq!(key.fingerprint(), "4bb0 6f8e 4e3a 7715");
+        assert_eq!(
+            key.ratchet().to_hex(),
+            "5e763963a2319568d1e6953e197a7088adb8aae33976447aa65961954c0a8e3a"
+        );
+        assert_eq!(
+            key.ratchet().ratchet().to_hex(),
+            "4648e34b9203fcc0e18ac6ac20a7553ffd1ecf37702ce9fafc827409860b80a5"
+        );
+    }
+
+    /// Seals a packet in `epochs` more periods than the last one.
+    fn seal_later(sealer: &mut PacketSealer, epochs: u32, seq: u32) -> Vec<u8> {
+        let rotation = sealer.rotation.as_mut().unwrap();
+        rotation.next_at = Instant::now() - rotation.period * epochs.saturating_sub(1);
+        if epochs == 0 {
+            rotation.next_at += rotation.period * 2;
+        }
+        let mut packet = vec![0; HEADER_SIZE];
+        packet[7] = 0;
+        packet.extend_from_slice(&seq.to_le_bytes());
+        sealer.seal(&mut packet, seq).unwrap();
+        packet
+    }
+
+    fn opens(opener: &mut PacketOpener, packet: &[u8], seq: u32) -> bool {
+        let (header, payload) = packet.split_at(HEADER_SIZE);
+        opener.open(header, payload, seq).is_some()
+    }
+
+    #[test]
+    fn receivers_follow_the_ratchet() {
+        let key = Key::from_bytes([7; KEY_LEN]);
+        let period = Duration::from_secs(60);
+        let mut sealer = PacketSealer::rotating(Cipher::Aes256Gcm, &key, period).unwrap();
+        let mut opener = PacketOpener::new(&key);
+
+        let first = seal_later(&mut sealer, 0, 1);
+        assert!(opens(&mut opener, &first, 1));
+        let third = seal_later(&mut sealer, 2, 2);
+        assert_eq!(sealer.rotation.as_ref().unwrap().epoch, 2);
+        assert!(opens(&mut opener, &third, 2));
+        let far = seal_later(&mut sealer, MAX_EPOCH_SKIP + 1, 3);
+        assert!(!opens(&mut opener, &far, 3));
+
+        let mut restarted = PacketSealer::rotating(Cipher::Aes256Gcm, &key, period).unwrap();
+        assert!(opens(&mut opener, &seal_later(&mut restarted, 0, 1), 1));
+        assert!(opens(&mut opener, &seal_later(&mut restarted, 1, 2), 2));
+    }
+
+    #[test]
+    fn sealed_packets_use_the_session_id_and_seq_as_nonce() {
+        let key = Key::from_bytes([7; KEY_LEN]);
+        let mut sealer = PacketSealer::new(Cipher::Aes256Gcm, &key).unwrap();
+        let mut packet = vec![0; HEADER_SIZE];
+        packet.extend_from_slice(b"payload");
+        sealer.seal(&mut packet, 42).unwrap();
+
+        let (header, payload) = packet.split_at(HEADER_SIZE);
+        let session_id: [u8; SESSION_ID_LEN] = payload[..SESSION_ID_LEN].try_into().unwrap();
+        assert_eq!(session_id, sealer.session_id);
+        let (ciphertext, tag) = payload[SESSION_ID_LEN..].split_at(payload.len() - OVERHEAD);
+        let mut plaintext = ciphertext.to_vec();
+        let aead = Aead::new(Cipher::Aes256Gcm, &key);
+        let nonce = nonce(&session_id, 42);
+        assert!(aead.open(&nonce, header, &mut plaintext, tag.try_into().unwrap()));
+        assert_eq!(plaintext, b"payload");
+        assert_eq!(
+            PacketOpener::new(&key).open(header, payload, 42).as_deref(),
+            Some(&b"payload"[..])
+        );
+    }
+}
@@ -0,0 +1,457 @@
+//! The `Noise_XX_25519_ChaChaPoly_SHA256` handshake, run by `snow`:
+//!
+//! ```text
+//! -> e
+//! <- e, ee, s, es
+//! -> s, se
+//! ```
+//!
+//! Both sides learn the other's static public key; the last message carries a payload
+//! only the responder can read, which is how the sender hands over its stream key.
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+use snow::params::{CipherChoice, DHChoice, HashChoice};
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::types::{Cipher, Dh, Hash, Random};
+use snow::{Builder, HandshakeState};
+use x25519_dalek::StaticSecret;
+
+use crate::error::CryptoError;
+
+const HASH_LEN: usize = 32;
+const PARAMS: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+/// Largest Noise message.
+const MAX_MESSAGE: usize = 65535;
+const PROLOGUE: &[u8] = b"audio-link";
+pub const DH_LEN: usize = 32;
+
+/// An X25519 public key, printed as 64 hex digits.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct PublicKey(pub [u8; DH_LEN]);
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Hex(&self.0).fmt(f)
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PublicKey({self})")
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = CryptoError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        parse_hex(text).map(Self).ok_or(CryptoError::InvalidKey)
+    }
+}
+
+/// The SHA-256 of a peer's public key, written `sha256:` and 64 hex digits, to trust that
+/// peer before it ever pairs.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct KeyPin([u8; HASH_LEN]);
+
+impl KeyPin {
+    pub fn of(key: &PublicKey) -> Self {
+        Self(Sha256::digest(key.0).into())
+    }
+}
+
+impl fmt::Display for KeyPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sha256:{}", Hex(&self.0))
+    }
+}
+
+impl fmt::Debug for KeyPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "KeyPin({self})")
+    }
+}
+
+impl FromStr for KeyPin {
+    type Err = CryptoError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        text.trim()
+            .strip_prefix("sha256:")
+            .and_then(parse_hex)
+            .map(Self)
+            .ok_or(CryptoError::InvalidPin)
+    }
+}
+
+/// A long-lived static key pair that peers pin on first pairing.
+#[derive(Clone)]
+pub struct Identity {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl Identity {
+    pub fn generate() -> Result<Self, CryptoError> {
+        Ok(Self::from_secret(random_secret()?))
+    }
+
+    fn from_secret(secret: [u8; DH_LEN]) -> Self {
+        let secret = StaticSecret::from(secret);
+        Self {
+            public: PublicKey(x25519_dalek::PublicKey::from(&secret).to_bytes()),
+            secret,
+        }
+    }
+
+    /// Reads the secret key from `path`, creating it there on first use.
+    pub fn load_or_create(path: &Path) -> Result<Self, CryptoError> {
+        let io_error = |source| CryptoError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                let secret =
+                    parse_hex(text.trim()).ok_or_else(|| CryptoError::InvalidIdentity {
+                        path: path.to_path_buf(),
+                    })?;
+                Ok(Self::from_secret(secret))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let identity = Self::generate()?;
+                let mut options = OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                let mut file = options.open(path).map_err(io_error)?;
+                writeln!(file, "{}", Hex(identity.secret.as_bytes())).map_err(io_error)?;
+                Ok(identity)
+            }
+            Err(err) => Err(io_error(err)),
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.public
+    }
+}
+
+/// Never prints the secret key.
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity")
+            .field("public", &self.public)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Public keys pinned on first pairing, one `label hexkey` line each.
+#[derive(Debug)]
+pub struct KnownPeers {
+    path: PathBuf,
+    peers: Vec<(String, PublicKey)>,
+}
+
+impl KnownPeers {
+    /// Reads `path`; a missing file means nobody has paired yet.
+    pub fn load(path: &Path) -> Result<Self, CryptoError> {
+        let mut peers = Vec::new();
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                for line in text.lines().map(str::trim) {
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    let (label, key) = line.rsplit_once(char::is_whitespace).ok_or_else(|| {
+                        CryptoError::InvalidPeers {
+                            path: path.to_path_buf(),
+                        }
+                    })?;
+                    let key = key.parse().map_err(|_| CryptoError::InvalidPeers {
+                        path: path.to_path_buf(),
+                    })?;
+                    peers.push((label.trim().to_string(), key));
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(source) => {
+                return Err(CryptoError::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            peers,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    pub fn contains(&self, key: &PublicKey) -> bool {
+        self.peers.iter().any(|(_, known)| known == key)
+    }
+
+    /// The key pinned for `label`, if any.
+    pub fn get(&self, label: &str) -> Option<PublicKey> {
+        self.peers
+            .iter()
+            .find(|(known, _)| known == label)
+            .map(|(_, key)| *key)
+    }
+
+    /// Pins `key` under `label` and appends it to the file.
+    pub fn pin(&mut self, label: &str, key: PublicKey) -> Result<(), CryptoError> {
+        let io_error = |source| CryptoError::Io {
+            path: self.path.clone(),
+            source,
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(io_error)?;
+        writeln!(file, "{label} {key}").map_err(io_error)?;
+        self.peers.push((label.to_string(), key));
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// The sender's side: writes the first and third messages.
+pub struct Initiator {
+    state: HandshakeState,
+}
+
+impl Initiator {
+    /// Starts a handshake as `identity`; returns the first message.
+    pub fn start(identity: &Identity) -> Result<(Self, Vec<u8>), CryptoError> {
+        let mut state = handshake(identity, true)?;
+        let hello = write(&mut state, &[])?;
+        Ok((Self { state }, hello))
+    }
+
+    /// Reads the responder's reply and returns its static key with the last message,
+    /// which carries `payload`. Check the key before sending the message.
+    pub fn finish(
+        mut self,
+        reply: &[u8],
+        payload: &[u8],
+    ) -> Result<(PublicKey, Vec<u8>), CryptoError> {
+        read(&mut self.state, reply)?;
+        let remote = remote_static(&self.state)?;
+        let message = write(&mut self.state, payload)?;
+        Ok((remote, message))
+    }
+}
+
+/// The receiver's side: answers the first message and reads the third.
+pub struct Responder {
+    state: HandshakeState,
+}
+
+impl Responder {
+    /// Reads the first message; returns the reply.
+    pub fn accept(identity: &Identity, hello: &[u8]) -> Result<(Self, Vec<u8>), CryptoError> {
+        let mut state = handshake(identity, false)?;
+        read(&mut state, hello)?;
+        let reply = write(&mut state, &[])?;
+        Ok((Self { state }, reply))
+    }
+
+    /// Reads the last message; returns the initiator's static key and the payload.
+    pub fn finish(mut self, message: &[u8]) -> Result<(PublicKey, Vec<u8>), CryptoError> {
+        let payload = read(&mut self.state, message)?;
+        Ok((remote_static(&self.state)?, payload))
+    }
+}
+
+fn handshake(identity: &Identity, initiator: bool) -> Result<HandshakeState, CryptoError> {
+    let params = PARAMS.parse().expect("a valid Noise protocol name");
+    let builder = Builder::with_resolver(params, Box::new(Resolver))
+        .prologue(PROLOGUE)
+        .local_private_key(identity.secret.as_bytes());
+    let state = if initiator {
+        builder.build_initiator()
+    } else {
+        builder.build_responder()
+    };
+    state.map_err(|_| CryptoError::Handshake)
+}
+
+fn write(state: &mut HandshakeState, payload: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let mut message = vec![0; MAX_MESSAGE];
+    let len = state
+        .write_message(payload, &mut message)
+        .map_err(|_| CryptoError::Handshake)?;
+    message.truncate(len);
+    Ok(message)
+}
+
+fn read(state: &mut HandshakeState, message: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let mut payload = vec![0; MAX_MESSAGE];
+    let len = state
+        .read_message(message, &mut payload)
+        .map_err(|_| CryptoError::Handshake)?;
+    payload.truncate(len);
+    Ok(payload)
+}
+
+fn remote_static(state: &HandshakeState) -> Result<PublicKey, CryptoError> {
+    state
+        .get_remote_static()
+        .and_then(|key| key.try_into().ok())
+        .map(PublicKey)
+        .ok_or(CryptoError::Handshake)
+}
+
+/// Snow's own primitives, except X25519 from `x25519-dalek`.
+struct Resolver;
+
+impl CryptoResolver for Resolver {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        DefaultResolver.resolve_rng()
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        match choice {
+            DHChoice::Curve25519 => Some(Box::new(X25519::default())),
+            _ => None,
+        }
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<dyn Hash>> {
+        DefaultResolver.resolve_hash(choice)
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+        DefaultResolver.resolve_cipher(choice)
+    }
+}
+
+struct X25519 {
+    secret: StaticSecret,
+    public: x25519_dalek::PublicKey,
+}
+
+impl Default for X25519 {
+    fn default() -> Self {
+        let secret = StaticSecret::from([0; DH_LEN]);
+        Self {
+            public: x25519_dalek::PublicKey::from(&secret),
+            secret,
+        }
+    }
+}
+
+impl Dh for X25519 {
+    fn name(&self) -> &'static str {
+        "25519"
+    }
+
+    fn pub_len(&self) -> usize {
+        DH_LEN
+    }
+
+    fn priv_len(&self) -> usize {
+        DH_LEN
+    }
+
+    fn set(&mut self, privkey: &[u8]) {
+        let secret: [u8; DH_LEN] = privkey.try_into().expect("a 32-byte X25519 key");
+        self.secret = StaticSecret::from(secret);
+        self.public = x25519_dalek::PublicKey::from(&self.secret);
+    }
+
+    fn generate(&mut self, rng: &mut dyn Random) {
+        self.secret = StaticSecret::random_from_rng(rng);
+        self.public = x25519_dalek::PublicKey::from(&self.secret);
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        self.public.as_bytes()
+    }
+
+    fn privkey(&self) -> &[u8] {
+        self.secret.as_bytes()
+    }
+
+    /// Refuses low-order points, whose shared secret is all zeros whatever our key is.
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), snow::Error> {
+        // Snow may pass its whole key buffer, longer than the key.
+        let public: [u8; DH_LEN] = pubkey
+            .get(..DH_LEN)
+            .and_then(|key| key.try_into().ok())
+            .ok_or(snow::Error::Dh)?;
+        let shared = self
+            .secret
+            .diffie_hellman(&x25519_dalek::PublicKey::from(public));
+        if !shared.was_contributory() {
+            return Err(snow::Error::Dh);
+        }
+        out[..DH_LEN].copy_from_slice(shared.as_bytes());
+        Ok(())
+    }
+}
+
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+fn parse_hex(text: &str) -> Option<[u8; DH_LEN]> {
+    if text.len() != 2 * DH_LEN || !text.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0; DH_LEN];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16).unwrap();
+    }
+    Some(bytes)
+}
+
+fn random_secret() -> Result<[u8; DH_LEN], CryptoError> {
+    let mut secret = [0; DH_LEN];
+    getrandom::fill(&mut secret).map_err(CryptoError::Random)?;
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake_hands_over_the_payload_and_both_keys() {
+        let sender = Identity::generate().unwrap();
+        let receiver = Identity::generate().unwrap();
+        let (initiator, hello) = Initiator::start(&sender).unwrap();
+        let (responder, reply) = Responder::accept(&receiver, &hello).unwrap();
+        let (receiver_key, finish) = initiator.finish(&reply, b"stream key").unwrap();
+        let (sender_key, payload) = responder.finish(&finish).unwrap();
+        assert_eq!(receiver_key, receiver.public_key());
+        assert_eq!(sender_key, sender.public_key());
+        assert_eq!(payload, b"stream key");
+    }
+
+    #[test]
+    fn low_order_ephemeral_is_refused() {
+        let receiver = Identity::generate().unwrap();
+        assert!(Responder::accept(&receiver, &[0; DH_LEN]).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use anyhow::{Context, Result};
+use daemonize::Daemonize;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use tracing::{info, warn};
+
+use crate::control::{ControlCommand, Router};
+use crate::exit;
+use crate::Args;
+
+/// Forks into the background and writes the PID file. Output is dropped from then on, so
+/// logs go to `--log-file`.
+///
+/// Paths in `args` are made absolute first because the daemon runs from `/`.
+pub fn daemonize(args: &mut Args) -> Result<()> {
+    if let Some(config) = &args.config {
+        args.config = Some(
+            fs::canonicalize(config)
+                .with_context(|| format!("config file {} not found", config.display()))?,
+        );
+    }
+    args.pid_file = args.pid_file.as_deref().map(absolute).transpose()?;
+    args.log_file = args.log_file.as_deref().map(absolute).transpose()?;
+    args.stats_file = args.stats_file.as_deref().map(absolute).transpose()?;
+    args.events_file = args.events_file.as_deref().map(absolute).transpose()?;
+    args.perf_folded = args.perf_folded.as_deref().map(absolute).transpose()?;
+    args.tee = args.tee.as_deref().map(absolute).transpose()?;
+    args.output = args.output.as_deref().map(absolute).transpose()?;
+    args.write_sdp = args.write_sdp.as_deref().map(absolute).transpose()?;
+    args.source_file = args.source_file.as_deref().map(absolute).transpose()?;
+    args.noise = args.noise.as_deref().map(absolute).transpose()?;
+    args.plugins = args
+        .plugins
+        .iter()
+        .map(|plugin| absolute(plugin))
+        .collect::<Result<_>>()?;
+
+    let mut daemon = Daemonize::new().working_directory("/");
+    if let Some(pid_file) = &args.pid_file {
+        daemon = daemon.pid_file(pid_file);
+    }
+    daemon.start().context("failed to daemonize")
+}
+
+pub fn remove_pid_file(pid_file: Option<&Path>) {
+    if let Some(pid_file) = pid_file {
+        let _ = fs::remove_file(pid_file);
+    }
+}
+
+/// SIGHUP re-reads the config file; SIGTERM/SIGINT stop every session cleanly.
+pub fn spawn_signal_handler(router: Router) -> Result<()> {
+    let mut signals =
+        Signals::new([SIGHUP, SIGINT, SIGTERM]).context("failed to install signal handlers")?;
+    thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            for signal in signals.forever() {
+                if signal == SIGHUP {
+                    match router.reload_config() {
+                        Ok(_) => info!("SIGHUP: reloading config"),
+                        Err(err) => warn!("SIGHUP ignored: {err}"),
+                    }
+                    continue;
+                }
+                info!("signal {signal}: stopping");
+                exit::interrupt();
+                router.broadcast(ControlCommand::Quit);
+                break;
+            }
+        })
+        .context("failed to spawn signal thread")?;
+    Ok(())
+}
+
+fn absolute(path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+    Ok(std::env::current_dir()
+        .context("failed to read current directory")?
+        .join(path))
+}
@@ -0,0 +1,256 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::crypto::{Key, PacketOpener, ReplayWindow, SESSION_ID_LEN};
+use crate::jitter::JitterBuffer;
+use crate::protocol::{
+    decode_pcm16, parse_packet, PacketHeader, FLAG_AUTHENTICATED, FLAG_DISCONTINUITY,
+    FLAG_ENCRYPTED, FLAG_PROBE, FLAG_PTP_TIME,
+};
+use crate::stats::{ReceiverStats, ReceiverStatsSnapshot, SequenceCounts, SequenceTracker};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReceiverFormat {
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub samples_per_channel: u16,
+}
+
+/// Frames played at a fixed delay after their send time instead of on arrival.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Schedule {
+    delay: Duration,
+    /// Also schedule frames without a PTP stamp, by the sender's own clock.
+    unstamped: bool,
+}
+
+pub struct StreamDecoder {
+    jitter_ms: u32,
+    schedule: Mutex<Option<Schedule>>,
+    stats: ReceiverStats,
+    buffer: JitterBuffer,
+    format: Mutex<Option<ReceiverFormat>>,
+    last_send_time_us: AtomicU64,
+    last_media_time: Mutex<Option<u32>>,
+    sequence: Mutex<SequenceTracker>,
+    opener: Mutex<Option<PacketOpener>>,
+    replay: Mutex<ReplayWindow>,
+}
+
+impl StreamDecoder {
+    pub fn new(jitter_ms: u32) -> Self {
+        Self {
+            jitter_ms,
+            schedule: Mutex::new(None),
+            stats: ReceiverStats::default(),
+            buffer: JitterBuffer::new(2, 18),
+            format: Mutex::new(None),
+            last_send_time_us: AtomicU64::new(0),
+            last_media_time: Mutex::new(None),
+            sequence: Mutex::new(SequenceTracker::default()),
+            opener: Mutex::new(None),
+            replay: Mutex::new(ReplayWindow::default()),
+        }
+    }
+
+    /// With a key, only packets sealed with it are played; without one, only plaintext.
+    pub fn set_key(&self, key: Option<&Key>) {
+        *self.opener.lock().unwrap() = key.map(PacketOpener::new);
+        *self.replay.lock().unwrap() = ReplayWindow::default();
+    }
+
+    /// Keeps the send time of PTP-stamped frames, and of every frame with `unstamped`, for
+    /// `pop_frame_scheduled`, and makes room in the buffer for `delay` worth of them.
+    pub fn schedule_playout(&self, delay: Duration, unstamped: bool) {
+        let schedule = Some(Schedule { delay, unstamped });
+        let previous = std::mem::replace(&mut *self.schedule.lock().unwrap(), schedule);
+        if previous != schedule {
+            // Sized again on the next packet.
+            *self.format.lock().unwrap() = None;
+        }
+    }
+
+    pub fn push_packet(&self, data: &[u8], wire_overhead_bytes: usize) -> bool {
+        let stats = &self.stats;
+        stats.rx_packets.fetch_add(1, Ordering::Relaxed);
+        stats
+            .rx_bytes
+            .fetch_add((data.len() + wire_overhead_bytes) as u64, Ordering::Relaxed);
+
+        let (header, payload) = match parse_packet(data) {
+            Ok(parsed) => parsed,
+            Err(_) => {
+                stats.parse_errors.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        };
+
+        // Probes carry only padding and are never encrypted.
+        if header.flags & FLAG_PROBE != 0 {
+            self.last_send_time_us
+                .store(header.send_time_us, Ordering::Relaxed);
+            stats
+                .probe_bytes
+                .fetch_add(data.len() as u64, Ordering::Relaxed);
+            return true;
+        }
+        let decrypted;
+        let mut opener = self.opener.lock().unwrap();
+        let sealed = header.flags & (FLAG_ENCRYPTED | FLAG_AUTHENTICATED) != 0;
+        let payload = match (&mut *opener, sealed) {
+            (None, false) => payload,
+            (Some(opener), true) => match opener.open(data, payload, header.seq) {
+                Some(_)
+                    if !self.replay.lock().unwrap().accept(
+                        &payload[..SESSION_ID_LEN],
+                        header.seq,
+                        header.send_time_us,
+                    ) =>
+                {
+                    stats.replays.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                Some(plaintext) => {
+                    decrypted = plaintext;
+                    &decrypted
+                }
+                None => {
+                    stats.auth_failures.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+            },
+            _ => {
+                stats.auth_failures.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        };
+        drop(opener);
+        self.last_send_time_us
+            .store(header.send_time_us, Ordering::Relaxed);
+        self.sequence.lock().unwrap().record(header.seq);
+        let expected_samples = self.ensure_format(&header);
+        let mut frame = vec![0i16; payload.len() / 2];
+        decode_pcm16(payload, &mut frame);
+        if frame.len() != expected_samples {
+            stats.payload_mismatch.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        if header.flags & FLAG_DISCONTINUITY != 0 {
+            stats.discontinuities.fetch_add(1, Ordering::Relaxed);
+        }
+        *self.last_media_time.lock().unwrap() = Some(header.media_time);
+        let schedule = *self.schedule.lock().unwrap();
+        let scheduled = schedule
+            .is_some_and(|schedule| schedule.unstamped || header.flags & FLAG_PTP_TIME != 0);
+        self.buffer
+            .push(header.seq, scheduled.then_some(header.send_time_us), frame);
+        true
+    }
+
+    pub fn pop_frame(&self, timeout: Duration) -> Option<Vec<i16>> {
+        self.buffer.pop(timeout)
+    }
+
+    /// The next frame once `playout_us()` reaches its send time; see
+    /// `JitterBuffer::pop_scheduled`.
+    pub fn pop_frame_scheduled<F>(&self, timeout: Duration, playout_us: F) -> Option<Vec<i16>>
+    where
+        F: Fn() -> Option<u64>,
+    {
+        self.buffer.pop_scheduled(timeout, playout_us)
+    }
+
+    pub fn try_pop_frame(&self) -> Option<Vec<i16>> {
+        self.buffer.try_pop()
+    }
+
+    /// The sender paused the stream; see `JitterBuffer::pause`.
+    pub fn pause(&self) {
+        self.buffer.pause();
+    }
+
+    pub fn format(&self) -> Option<ReceiverFormat> {
+        *self.format.lock().unwrap()
+    }
+
+    /// `send_time_us` of the newest valid packet, or 0 before the first one.
+    pub fn last_send_time_us(&self) -> u64 {
+        self.last_send_time_us.load(Ordering::Relaxed)
+    }
+
+    /// `media_time` of the newest audio frame, or `None` before the first one.
+    pub fn last_media_time(&self) -> Option<u32> {
+        *self.last_media_time.lock().unwrap()
+    }
+
+    pub fn sequence_counts(&self) -> SequenceCounts {
+        self.sequence.lock().unwrap().counts()
+    }
+
+    pub fn last_played_seq(&self) -> Option<u32> {
+        self.buffer.snapshot().last_played_seq
+    }
+
+    pub fn record_parse_error(&self) {
+        self.stats.parse_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> ReceiverStatsSnapshot {
+        let stats = &self.stats;
+        let jitter = self.buffer.snapshot();
+        let sequence = self.sequence_counts();
+        ReceiverStatsSnapshot {
+            rx_packets: stats.rx_packets.load(Ordering::Relaxed),
+            rx_bytes: stats.rx_bytes.load(Ordering::Relaxed),
+            parse_errors: stats.parse_errors.load(Ordering::Relaxed),
+            payload_mismatch: stats.payload_mismatch.load(Ordering::Relaxed),
+            buffered_frames: jitter.buffered_frames as u64,
+            target_frames: jitter.target_frames as u64,
+            pushed: jitter.pushed,
+            played: jitter.played,
+            missing: jitter.missing,
+            overflow_dropped: jitter.overflow_dropped,
+            late: jitter.late,
+            lost: sequence.lost,
+            reordered: sequence.reordered,
+            duplicates: sequence.duplicates,
+            jitter_us: 0,
+            probe_bytes: stats.probe_bytes.load(Ordering::Relaxed),
+            auth_failures: stats.auth_failures.load(Ordering::Relaxed),
+            replays: stats.replays.load(Ordering::Relaxed),
+            rtt_us: 0,
+            clock_offset_us: 0,
+            clock_drift_ppb: 0,
+            transit_us: 0,
+            discontinuities: stats.discontinuities.load(Ordering::Relaxed),
+        }
+    }
+
+    fn ensure_format(&self, header: &PacketHeader) -> usize {
+        let incoming = ReceiverFormat {
+            sample_rate: header.sample_rate,
+            channels: header.channels,
+            samples_per_channel: header.samples_per_channel,
+        };
+        let mut format = self.format.lock().unwrap();
+        if *format != Some(incoming) {
+            let frame_us = (incoming.samples_per_channel as u64 * 1_000_000
+                / incoming.sample_rate.max(1) as u64)
+                .max(1);
+            let target_frames = (self.jitter_ms as u64 * 1000 / frame_us).max(2) as usize;
+            let delay_frames = self
+                .schedule
+                .lock()
+                .unwrap()
+                .map_or(0, |schedule| schedule.delay.as_micros() as u64 / frame_us);
+            self.buffer.reset(
+                target_frames,
+                target_frames + 16 + delay_frames as usize,
+                frame_us,
+            );
+            *format = Some(incoming);
+        }
+        incoming.samples_per_channel as usize * incoming.channels as usize
+    }
+}
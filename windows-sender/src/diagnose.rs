@@ -0,0 +1,429 @@
+use std::net::TcpStream;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+#[cfg(mic_backend)]
+use cpal::traits::{DeviceTrait, HostTrait};
+#[cfg(desktop_backend)]
+use wasapi::{DeviceEnumerator, Direction as WasapiDirection};
+use windows_sender::bluetooth::{self, Relay};
+use windows_sender::capture::{SourceKind, DESKTOP_CHANNELS, DESKTOP_SAMPLE_RATE};
+use windows_sender::error::{error_chain, Error};
+use windows_sender::ndi::NdiSource;
+use windows_sender::probe::{self, ProbeConfig};
+use windows_sender::protocol::{now_us, HEADER_SIZE};
+use windows_sender::transport::TransportKind;
+use windows_sender::{aes67, scream, vban};
+
+use crate::session;
+use crate::Args;
+
+const PROBE_MAX_KBPS: u64 = 102_400;
+/// Below this much spare capacity a compressed codec plugin is worth it.
+const LOW_HEADROOM: f64 = 2.0;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const CLOCK_SAMPLES: usize = 20;
+/// Coarser than this and sub-millisecond latency stages vanish.
+const COARSE_CLOCK_US: u64 = 1000;
+
+/// `--probe-bandwidth`: compares what the link to the first target sustains with what
+/// the stream needs.
+pub fn probe_bandwidth(args: &Args) -> Result<()> {
+    let config = session::sender_config(args)?;
+    if !config.transport.is_native() {
+        bail!(
+            "{} receivers send no reports to measure with; probe with --transport udp",
+            config.transport.as_str()
+        );
+    }
+    // Bluetooth is probed through its relay, as the stream goes.
+    let relay = match &config.bt_device {
+        Some(device) => Some(Relay::start(bluetooth::find(device)?, config.bt_channel)?),
+        None => None,
+    };
+    let target = match &relay {
+        Some(relay) => relay.local_addr(),
+        None => config.targets[0].resolve()?,
+    };
+    println!(
+        "Probing bandwidth to {target} ({})...",
+        config.transport.as_str()
+    );
+    let result = probe::probe_bandwidth(
+        &ProbeConfig {
+            target,
+            transport: config.transport,
+            max_kbps: PROBE_MAX_KBPS,
+        },
+        |step| {
+            println!(
+                "  offered {:>8.0} kbps -> delivered {:>8.0} kbps",
+                step.offered_kbps, step.delivered_kbps
+            );
+        },
+    )?;
+
+    let achievable = result.achievable_kbps();
+    let needed = stream_kbps(args.frame_ms, config.transport);
+    let headroom = achievable / needed;
+    println!(
+        "Achievable: ~{achievable:.0} kbps; the PCM stream needs {needed:.0} kbps at frame={}ms \
+         ({} kHz, {} ch): headroom {headroom:.1}x",
+        args.frame_ms,
+        DESKTOP_SAMPLE_RATE / 1000,
+        DESKTOP_CHANNELS
+    );
+    if headroom < LOW_HEADROOM {
+        println!("Little headroom on this link; consider a compressed codec plugin (e.g. Opus).");
+    }
+    Ok(())
+}
+
+/// `--probe-mtu`: finds the largest packet that reaches the first target unfragmented and
+/// checks the stream's packets against it.
+pub fn probe_mtu(args: &Args) -> Result<()> {
+    let config = session::sender_config(args)?;
+    if config.transport == TransportKind::Tcp {
+        println!("TCP segments packets itself; path MTU only matters for --transport udp.");
+        return Ok(());
+    }
+    if config.transport == TransportKind::Bluetooth {
+        println!("RFCOMM segments packets itself; path MTU only matters for --transport udp.");
+        return Ok(());
+    }
+    if config.transport == TransportKind::Vban {
+        println!(
+            "VBAN packets are at most {} bytes, which fits any Ethernet path.",
+            vban::HEADER_SIZE + vban::MAX_PAYLOAD
+        );
+        return Ok(());
+    }
+    if config.transport == TransportKind::Scream {
+        println!(
+            "Scream packets are always {} bytes, which fits any Ethernet path.",
+            scream::HEADER_SIZE + scream::PAYLOAD
+        );
+        return Ok(());
+    }
+    if config.transport == TransportKind::Aes67 {
+        println!(
+            "AES67 packets are at most {} bytes (1 ms of 8 channels of L24), which fits any Ethernet path.",
+            aes67::RTP_HEADER_SIZE + aes67::PACKET_SAMPLES * aes67::MAX_CHANNELS as usize * 3
+        );
+        return Ok(());
+    }
+    let target = config.targets[0].resolve()?;
+    println!("Probing path MTU to {target}...");
+    let Some(largest) = probe::probe_path_mtu(target)? else {
+        println!("Not even 576-byte datagrams arrive with DF set; the path drops them.");
+        return Ok(());
+    };
+    let needed = packet_bytes(args.frame_ms);
+    let more = if largest >= probe::MAX_PROBE_PACKET {
+        " or more"
+    } else {
+        ""
+    };
+    println!(
+        "Path MTU: ~{}{more} bytes (largest unfragmented packet {largest} bytes); packets at \
+         frame={}ms are {needed} bytes",
+        largest + probe::UDP_IPV4_OVERHEAD,
+        args.frame_ms
+    );
+    if needed > largest {
+        println!("Warning: packets will fragment, and fragmented UDP is lost far more on Wi-Fi.");
+        // Half-millisecond steps keep every suggestion a whole number of samples.
+        match (2..(args.frame_ms * 2.0) as u32)
+            .rev()
+            .map(|halves| halves as f32 / 2.0)
+            .find(|&ms| packet_bytes(ms) <= largest)
+        {
+            Some(ms) => println!("Use --frame-ms {ms} or lower to stay under the MTU."),
+            None => println!("Even 1 ms frames do not fit; consider a codec plugin."),
+        }
+    }
+    Ok(())
+}
+
+/// Size of one uncompressed PCM16 packet at the desktop capture format.
+fn packet_bytes(frame_ms: f32) -> usize {
+    let samples = (DESKTOP_SAMPLE_RATE as f32 * frame_ms / 1000.0) as usize * DESKTOP_CHANNELS;
+    HEADER_SIZE + samples * 2
+}
+
+/// Wire bitrate of uncompressed PCM16 at the desktop capture format.
+fn stream_kbps(frame_ms: f32, transport: TransportKind) -> f64 {
+    let framing = match transport {
+        TransportKind::Udp | TransportKind::Vban | TransportKind::Scream | TransportKind::Aes67 => {
+            0
+        }
+        TransportKind::Tcp | TransportKind::Bluetooth => 2,
+    };
+    let packet_bytes = packet_bytes(frame_ms) + framing;
+    packet_bytes as f64 * 8.0 * (1000.0 / frame_ms as f64) / 1000.0
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Verdict {
+    Pass,
+    Warn,
+    Fail,
+    Skip,
+}
+
+impl Verdict {
+    fn as_str(self) -> &'static str {
+        match self {
+            Verdict::Pass => "PASS",
+            Verdict::Warn => "WARN",
+            Verdict::Fail => "FAIL",
+            Verdict::Skip => "SKIP",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    verdicts: Vec<Verdict>,
+}
+
+impl Report {
+    fn check(&mut self, verdict: Verdict, message: impl AsRef<str>) {
+        println!("  [{}] {}", verdict.as_str(), message.as_ref());
+        self.verdicts.push(verdict);
+    }
+
+    fn count(&self, verdict: Verdict) -> usize {
+        self.verdicts.iter().filter(|&&v| v == verdict).count()
+    }
+}
+
+/// `doctor`: checks what most setups get wrong, from audio devices to the path to the
+/// receiver, and fails when anything the configured stream needs is broken.
+pub fn doctor(args: &Args) -> Result<()> {
+    // The NDI runtime gets a check of its own below rather than failing the whole config.
+    let config = session::sender_config(&Args {
+        ndi: None,
+        ..args.clone()
+    })
+    .ok();
+    let source = config
+        .as_ref()
+        .map_or(SourceKind::from(args.source), |config| config.source);
+    let mut report = Report::default();
+
+    println!("Audio devices:");
+    // A broken backend only fails the check when it is the configured source.
+    let needed = |kind| {
+        if kind == source {
+            Verdict::Fail
+        } else {
+            Verdict::Warn
+        }
+    };
+    check_desktop(&mut report, args, needed(SourceKind::Desktop));
+    check_mic(&mut report, needed(SourceKind::Mic));
+
+    println!("Network:");
+    let target = config
+        .as_ref()
+        .and_then(|config| config.targets.first())
+        .map(|target| target.resolve());
+    match (&config, target) {
+        (Some(config), Some(Ok(target))) => {
+            let needed = |kind| {
+                if kind == config.transport {
+                    Verdict::Fail
+                } else {
+                    Verdict::Warn
+                }
+            };
+            match probe::ping(target) {
+                Ok(rtt) => report.check(
+                    Verdict::Pass,
+                    format!(
+                        "UDP: receiver at {target} answers, round trip {:.1} ms",
+                        rtt.as_secs_f64() * 1000.0
+                    ),
+                ),
+                Err(Error::NoFeedback { .. }) => report.check(
+                    needed(TransportKind::Udp),
+                    format!(
+                        "UDP: no answer from {target}; is the receiver running in UDP mode, \
+                         and does a firewall allow UDP port {} both ways?",
+                        target.port()
+                    ),
+                ),
+                Err(err) => report.check(
+                    needed(TransportKind::Udp),
+                    format!("UDP: {}", error_chain(&err)),
+                ),
+            }
+            match TcpStream::connect_timeout(&target, CONNECT_TIMEOUT) {
+                Ok(_) => report.check(Verdict::Pass, format!("TCP: connected to {target}")),
+                Err(err) => report.check(
+                    needed(TransportKind::Tcp),
+                    format!("TCP: cannot connect to {target} ({err}); only a receiver in TCP mode accepts"),
+                ),
+            }
+        }
+        (_, Some(Err(err))) => report.check(Verdict::Fail, error_chain(&err)),
+        _ => report.check(Verdict::Skip, "no --target-ip; reachability not checked"),
+    }
+
+    if let Some(selector) = &args.bt_device {
+        println!("Bluetooth:");
+        match bluetooth::find(selector) {
+            Ok(device) => report.check(Verdict::Pass, format!("Bluetooth: {device} is known")),
+            Err(err) => report.check(Verdict::Fail, format!("Bluetooth: {}", error_chain(&err))),
+        }
+    }
+
+    if let Some(name) = &args.ndi {
+        println!("NDI:");
+        match NdiSource::new(name) {
+            Ok(_) => report.check(
+                Verdict::Pass,
+                format!("NDI: runtime loaded, {name:?} can be published"),
+            ),
+            Err(err) => report.check(Verdict::Fail, format!("NDI: {}", error_chain(&err))),
+        }
+    }
+
+    println!("Clock:");
+    check_clock(&mut report);
+
+    let failed = report.count(Verdict::Fail);
+    println!(
+        "passed: {}, warnings: {}, failed: {failed}",
+        report.count(Verdict::Pass),
+        report.count(Verdict::Warn)
+    );
+    if failed > 0 {
+        bail!("doctor found {failed} problem(s)");
+    }
+    Ok(())
+}
+
+#[cfg(desktop_backend)]
+fn check_desktop(report: &mut Report, args: &Args, failure: Verdict) {
+    if let Err(err) = wasapi::initialize_mta().ok() {
+        report.check(Verdict::Fail, format!("COM initialization failed: {err}"));
+        return;
+    }
+    report.check(Verdict::Pass, "COM initialized (MTA)");
+
+    let device = DeviceEnumerator::new().and_then(|enumerator| match &args.desktop_device {
+        Some(name) => enumerator
+            .get_device_collection(&WasapiDirection::Render)?
+            .get_device_with_name(name),
+        None => enumerator.get_default_device(&WasapiDirection::Render),
+    });
+    let wanted = args
+        .desktop_device
+        .as_deref()
+        .unwrap_or("default render device");
+    let device = match device {
+        Ok(device) => device,
+        Err(err) => {
+            report.check(failure, format!("desktop: cannot open {wanted}: {err}"));
+            return;
+        }
+    };
+    let name = device
+        .get_friendlyname()
+        .unwrap_or_else(|_| wanted.to_string());
+    match device
+        .get_iaudioclient()
+        .and_then(|client| client.get_mixformat())
+    {
+        Ok(format) => report.check(
+            Verdict::Pass,
+            format!(
+                "desktop: {name} ({} Hz, {} ch mix)",
+                format.get_samplespersec(),
+                format.get_nchannels()
+            ),
+        ),
+        Err(err) => report.check(
+            failure,
+            format!("desktop: {name} has no usable audio client: {err}"),
+        ),
+    }
+}
+
+#[cfg(not(desktop_backend))]
+fn check_desktop(report: &mut Report, _args: &Args, failure: Verdict) {
+    report.check(
+        missing_backend(failure),
+        "desktop capture is not available in this build",
+    );
+}
+
+#[cfg(mic_backend)]
+fn check_mic(report: &mut Report, failure: Verdict) {
+    let Some(device) = cpal::default_host().default_input_device() else {
+        report.check(failure, "mic: no default input device");
+        return;
+    };
+    let name = device.name().unwrap_or_else(|_| "unknown".to_string());
+    match device.default_input_config() {
+        Ok(config) => report.check(
+            Verdict::Pass,
+            format!(
+                "mic: {name} ({} Hz, {} ch)",
+                config.sample_rate().0,
+                config.channels()
+            ),
+        ),
+        Err(err) => report.check(failure, format!("mic: {name} has no usable config: {err}")),
+    }
+}
+
+#[cfg(not(mic_backend))]
+fn check_mic(report: &mut Report, failure: Verdict) {
+    report.check(
+        missing_backend(failure),
+        "mic capture is not available in this build",
+    );
+}
+
+/// Only the configured source has to be part of the build.
+#[cfg(any(not(desktop_backend), not(mic_backend)))]
+fn missing_backend(failure: Verdict) -> Verdict {
+    match failure {
+        Verdict::Fail => Verdict::Fail,
+        _ => Verdict::Skip,
+    }
+}
+
+/// Packet timestamps and every latency figure come from the wall clock, so its step
+/// bounds their accuracy.
+fn check_clock(report: &mut Report) {
+    let mut step_us = 0;
+    for _ in 0..CLOCK_SAMPLES {
+        let Ok(first) = now_us() else {
+            report.check(Verdict::Fail, "clock: system time is before the Unix epoch");
+            return;
+        };
+        let mut next = first;
+        while next == first {
+            next = now_us().unwrap_or(first + 1);
+        }
+        step_us = step_us.max(next - first);
+    }
+    if step_us <= COARSE_CLOCK_US {
+        report.check(
+            Verdict::Pass,
+            format!("clock: wall clock ticks every {step_us} us"),
+        );
+    } else {
+        report.check(
+            Verdict::Warn,
+            format!(
+                "clock: wall clock ticks every {:.1} ms; latency figures are only that accurate",
+                step_us as f64 / 1000.0
+            ),
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EqBand {
+    pub freq_hz: f32,
+    pub gain_db: f32,
+    pub q: f32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DspSettings {
+    pub gain_db: f32,
+    pub muted: bool,
+    pub eq: Vec<EqBand>,
+}
+
+impl DspSettings {
+    pub fn is_passthrough(&self) -> bool {
+        self.gain_db == 0.0 && !self.muted && self.eq.is_empty()
+    }
+}
+
+/// DSP settings shared between a control thread and the send loop.
+#[derive(Debug, Default)]
+pub struct SharedDspSettings {
+    settings: Mutex<DspSettings>,
+    generation: AtomicU64,
+}
+
+impl SharedDspSettings {
+    pub fn new(settings: DspSettings) -> Self {
+        Self {
+            settings: Mutex::new(settings),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self) -> DspSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    pub fn update<F>(&self, apply: F)
+    where
+        F: FnOnce(&mut DspSettings),
+    {
+        let mut settings = self.settings.lock().unwrap();
+        apply(&mut settings);
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    fn peaking(band: &EqBand, sample_rate: u32) -> Self {
+        let a = 10f32.powf(band.gain_db / 40.0);
+        let w0 = 2.0 * PI * band.freq_hz / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * band.q.max(0.05));
+        let cos_w0 = w0.cos();
+        let a0 = 1.0 + alpha / a;
+        Self {
+            b0: (1.0 + alpha * a) / a0,
+            b1: (-2.0 * cos_w0) / a0,
+            b2: (1.0 - alpha * a) / a0,
+            a1: (-2.0 * cos_w0) / a0,
+            a2: (1.0 - alpha / a) / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+pub struct DspChain {
+    settings: DspSettings,
+    sample_rate: u32,
+    channels: usize,
+    gain: f32,
+    filters: Vec<Vec<Biquad>>,
+    next_channel: usize,
+    generation: u64,
+}
+
+impl DspChain {
+    pub fn new(settings: DspSettings, sample_rate: u32, channels: usize) -> Self {
+        let mut chain = Self {
+            settings: DspSettings::default(),
+            sample_rate,
+            channels: channels.max(1),
+            gain: 1.0,
+            filters: Vec::new(),
+            next_channel: 0,
+            generation: 0,
+        };
+        chain.update(settings);
+        chain
+    }
+
+    pub fn settings(&self) -> &DspSettings {
+        &self.settings
+    }
+
+    pub fn update(&mut self, settings: DspSettings) {
+        if settings.eq != self.settings.eq {
+            self.filters = (0..self.channels)
+                .map(|_| {
+                    settings
+                        .eq
+                        .iter()
+                        .map(|band| Biquad::peaking(band, self.sample_rate))
+                        .collect()
+                })
+                .collect();
+        }
+        self.gain = 10f32.powf(settings.gain_db / 20.0);
+        self.settings = settings;
+    }
+
+    /// Picks up changes made through `shared` since the last call.
+    pub fn sync(&mut self, shared: &SharedDspSettings) {
+        let generation = shared.generation();
+        if generation != self.generation {
+            self.update(shared.get());
+            self.generation = generation;
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [i16]) {
+        if self.settings.muted {
+            samples.fill(0);
+            return;
+        }
+        if self.settings.is_passthrough() {
+            return;
+        }
+
+        for sample in samples.iter_mut() {
+            let mut value = *sample as f32 / i16::MAX as f32;
+            if let Some(filters) = self.filters.get_mut(self.next_channel) {
+                for filter in filters.iter_mut() {
+                    value = filter.process(value);
+                }
+            }
+            value = (value * self.gain).clamp(-1.0, 1.0);
+            *sample = (value * i16::MAX as f32) as i16;
+            self.next_channel = (self.next_channel + 1) % self.channels;
+        }
+    }
+}
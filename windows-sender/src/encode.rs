@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::thread::{self, JoinHandle};
+
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+
+use crate::error::{CodecError, Error, PluginError};
+use crate::plugin::Plugin;
+
+/// Most workers `--encode-workers` accepts.
+pub const MAX_ENCODE_WORKERS: usize = 16;
+
+struct Job {
+    index: u64,
+    frame: Vec<i16>,
+    out: Vec<u8>,
+}
+
+struct Done {
+    index: u64,
+    frame: Vec<i16>,
+    out: Vec<u8>,
+    result: Result<(), PluginError>,
+}
+
+/// Encodes frames with a codec plugin on worker threads, each with its own codec
+/// instance, and hands the payloads back in the order the frames went in.
+///
+/// The frames in flight are always the oldest complete frames of the send loop's
+/// accumulator: `fill` submits them without taking them out, and `next` returns the
+/// payload of the oldest once the caller drains it.
+pub struct EncodePool {
+    jobs: Option<Sender<Job>>,
+    done: Receiver<Done>,
+    workers: Vec<JoinHandle<()>>,
+    depth: usize,
+    submitted: u64,
+    returned: u64,
+    /// Finished ahead of a frame submitted before them.
+    parked: BTreeMap<u64, Done>,
+    frames: Vec<Vec<i16>>,
+    outs: Vec<Vec<u8>>,
+}
+
+impl EncodePool {
+    pub fn new(
+        codec: &Plugin,
+        sample_rate: u32,
+        channels: u32,
+        workers: usize,
+    ) -> Result<Self, Error> {
+        let depth = workers * 2;
+        let (jobs, job_rx) = bounded::<Job>(depth);
+        let (done_tx, done) = unbounded();
+        let mut pool = Self {
+            jobs: Some(jobs),
+            done,
+            workers: Vec::with_capacity(workers),
+            depth,
+            submitted: 0,
+            returned: 0,
+            parked: BTreeMap::new(),
+            frames: Vec::new(),
+            outs: Vec::new(),
+        };
+        for i in 0..workers {
+            let mut instance = codec.instantiate(sample_rate, channels)?;
+            let job_rx = job_rx.clone();
+            let done_tx = done_tx.clone();
+            let handle = thread::Builder::new()
+                .name(format!("encode-{i}"))
+                .spawn(move || {
+                    for mut job in job_rx {
+                        let result = instance.encode(&job.frame, &mut job.out);
+                        let done = Done {
+                            index: job.index,
+                            frame: job.frame,
+                            out: job.out,
+                            result,
+                        };
+                        if done_tx.send(done).is_err() {
+                            break;
+                        }
+                    }
+                })
+                .map_err(|source| Error::Spawn {
+                    name: "encode",
+                    source,
+                })?;
+            pool.workers.push(handle);
+        }
+        Ok(pool)
+    }
+
+    /// Most frames in flight at once.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn in_flight(&self) -> usize {
+        (self.submitted - self.returned) as usize
+    }
+
+    /// Submits the complete frames of `acc` past the ones already in flight, as many as
+    /// the pool has room for.
+    pub fn fill(&mut self, acc: &VecDeque<i16>, samples_per_frame: usize) -> Result<(), Error> {
+        while self.in_flight() < self.depth {
+            let start = self.in_flight() * samples_per_frame;
+            if start + samples_per_frame > acc.len() {
+                break;
+            }
+            let mut frame = self.frames.pop().unwrap_or_default();
+            frame.clear();
+            frame.extend(acc.range(start..start + samples_per_frame));
+            let job = Job {
+                index: self.submitted,
+                frame,
+                out: self.outs.pop().unwrap_or_default(),
+            };
+            self.jobs
+                .as_ref()
+                .and_then(|jobs| jobs.send(job).ok())
+                .ok_or(CodecError::WorkerLost)?;
+            self.submitted += 1;
+        }
+        Ok(())
+    }
+
+    /// Swaps the payload of the oldest frame in flight into `payload`, waiting for it.
+    pub fn next(&mut self, payload: &mut Vec<u8>) -> Result<(), Error> {
+        let index = self.returned;
+        let mut done = loop {
+            if let Some(done) = self.parked.remove(&index) {
+                break done;
+            }
+            let done = self.done.recv().map_err(|_| CodecError::WorkerLost)?;
+            if done.index == index {
+                break done;
+            }
+            self.parked.insert(done.index, done);
+        };
+        self.returned += 1;
+        std::mem::swap(payload, &mut done.out);
+        self.frames.push(done.frame);
+        self.outs.push(done.out);
+        done.result?;
+        Ok(())
+    }
+}
+
+impl Drop for EncodePool {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for handle in self.workers.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
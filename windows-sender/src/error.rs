@@ -0,0 +1,359 @@
+use std::any::Any;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+pub fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut msg = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        msg.push_str(": ");
+        msg.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    msg
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Capture(#[from] CaptureError),
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+    #[error(transparent)]
+    Codec(#[from] CodecError),
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+    #[error(transparent)]
+    Plugin(#[from] PluginError),
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+    #[error(transparent)]
+    Ndi(#[from] NdiError),
+    #[error(transparent)]
+    Bluetooth(#[from] BluetoothError),
+    #[error("{0}")]
+    Config(String),
+    #[error("failed to spawn {name} thread")]
+    Spawn {
+        name: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    #[error("no reports from the receiver at {target}; it may predate receiver feedback")]
+    NoFeedback { target: SocketAddr },
+    #[error("{stage} stage failed {restarts} times within a minute; giving up")]
+    Stalled {
+        stage: &'static str,
+        restarts: usize,
+    },
+    #[error("{name} thread panicked: {message}")]
+    Panicked { name: &'static str, message: String },
+}
+
+impl Error {
+    /// The error for a thread that panicked, from the payload `join` returned.
+    pub fn panicked(name: &'static str, payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "no message".to_string(),
+            },
+        };
+        Error::Panicked { name, message }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CaptureError {
+    #[error("no default input device found")]
+    NoInputDevice,
+    #[cfg(mic_backend)]
+    #[error("failed to read default input config")]
+    InputConfig(#[source] cpal::DefaultStreamConfigError),
+    #[cfg(mic_backend)]
+    #[error("failed to build input stream")]
+    BuildStream(#[source] cpal::BuildStreamError),
+    #[cfg(mic_backend)]
+    #[error("failed to start input stream")]
+    PlayStream(#[source] cpal::PlayStreamError),
+    #[cfg(mic_backend)]
+    #[error("unsupported sample format: {0:?}")]
+    UnsupportedSampleFormat(cpal::SampleFormat),
+    #[error("{context}")]
+    Wasapi {
+        context: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error("failed to spawn desktop capture thread")]
+    SpawnThread(#[source] io::Error),
+    #[error("desktop capture thread did not initialize in time")]
+    InitTimeout,
+    #[error("{0}")]
+    Init(String),
+    #[error("{0} capture is not available in this build")]
+    Unsupported(&'static str),
+    #[error("audio capture channel closed")]
+    ChannelClosed,
+    #[error("capture format changed to {sample_rate} Hz, {channels} ch after restart")]
+    FormatChanged { sample_rate: u32, channels: usize },
+    #[error("the file source needs a WAV file (--source-file)")]
+    NoSourceFile,
+    #[error("failed to read {}", path.display())]
+    ReadFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{} is not a WAV file the sender can play: {reason}", path.display())]
+    UnsupportedFile { path: PathBuf, reason: String },
+}
+
+impl CaptureError {
+    #[cfg(desktop_backend)]
+    pub(crate) fn wasapi<E>(context: impl Into<String>, source: E) -> Self
+    where
+        E: Into<BoxError>,
+    {
+        CaptureError::Wasapi {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransportError {
+    #[error("failed to bind UDP sender socket")]
+    Bind(#[source] io::Error),
+    #[error("failed to configure {what}")]
+    Configure {
+        what: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to listen on {addr}")]
+    Listen {
+        addr: SocketAddr,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to receive packet")]
+    Receive(#[source] io::Error),
+    #[error("failed to connect TCP stream to {addr}")]
+    Connect {
+        addr: SocketAddr,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to send {what} seq={seq}")]
+    Send {
+        what: &'static str,
+        seq: u32,
+        #[source]
+        source: io::Error,
+    },
+    #[error("packet too large for TCP length prefix ({len} bytes)")]
+    PacketTooLarge { len: usize },
+    #[error("failed to pair with {addr}")]
+    Handshake {
+        addr: SocketAddr,
+        #[source]
+        source: CryptoError,
+    },
+    #[error("receiver {addr} did not present the access token")]
+    Unauthorized { addr: SocketAddr },
+    #[error("failed to seal packet for {addr}")]
+    Seal {
+        addr: SocketAddr,
+        #[source]
+        source: CryptoError,
+    },
+    #[error("failed to build {what} for {addr}")]
+    Repacketize {
+        what: &'static str,
+        addr: SocketAddr,
+        #[source]
+        source: ProtocolError,
+    },
+    #[error("failed to resolve {host}")]
+    Resolve {
+        host: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl TransportError {
+    pub fn io_kind(&self) -> Option<io::ErrorKind> {
+        match self {
+            TransportError::Bind(source)
+            | TransportError::Configure { source, .. }
+            | TransportError::Listen { source, .. }
+            | TransportError::Receive(source)
+            | TransportError::Connect { source, .. }
+            | TransportError::Send { source, .. }
+            | TransportError::Resolve { source, .. } => Some(source.kind()),
+            TransportError::PacketTooLarge { .. }
+            | TransportError::Handshake { .. }
+            | TransportError::Unauthorized { .. }
+            | TransportError::Seal { .. }
+            | TransportError::Repacketize { .. } => None,
+        }
+    }
+
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self.io_kind(),
+            Some(
+                io::ErrorKind::WouldBlock
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+            )
+        )
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PluginError {
+    #[cfg(plugin_loader)]
+    #[error("failed to load plugin {}", path.display())]
+    Load {
+        path: PathBuf,
+        #[source]
+        source: libloading::Error,
+    },
+    #[error("invalid plugin {}: {reason}", path.display())]
+    Invalid { path: PathBuf, reason: String },
+    #[error("plugin support is not available in this build")]
+    Disabled,
+    #[error("plugin {name} failed to initialize")]
+    Create { name: String },
+    #[error("plugin {name} failed with code {code}")]
+    Failed { name: String, code: i64 },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NdiError {
+    #[error("NDI output is not available in this build")]
+    Disabled,
+    #[cfg(ndi_backend)]
+    #[error("failed to load the NDI runtime ({library}); install NDI Tools or the NDI runtime")]
+    Load {
+        library: String,
+        #[source]
+        source: libloading::Error,
+    },
+    #[cfg(ndi_backend)]
+    #[error("the NDI runtime lacks {0}; it may be too old")]
+    Symbol(&'static str),
+    #[error("the NDI runtime does not support this CPU")]
+    Unsupported,
+    #[error("failed to create NDI source {name:?}")]
+    Create { name: String },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BluetoothError {
+    #[error("{0} is not available on this system")]
+    Unsupported(&'static str),
+    #[error("invalid Bluetooth address {0:?}; expected six hex pairs like 00:1A:7D:DA:71:13")]
+    InvalidAddress(String),
+    #[error("no paired Bluetooth device matches {0:?}; --list-bt-devices shows them")]
+    NotFound(String),
+    #[error("failed to list Bluetooth devices")]
+    List(#[source] io::Error),
+    #[error("failed to pair with {device}")]
+    Pair {
+        device: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("the RFCOMM channel of {device} has to be given with --bt-channel on this system")]
+    NoChannel { device: String },
+    #[error("failed to connect to {device} over Bluetooth")]
+    Connect {
+        device: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CodecError {
+    #[error("frame of {samples} samples does not fit in a packet payload")]
+    FrameTooLarge { samples: usize },
+    #[error("a {frame_ms} ms frame is not a whole number of samples at {sample_rate} Hz")]
+    FractionalFrame { frame_ms: f32, sample_rate: u32 },
+    #[error("an encode worker stopped")]
+    WorkerLost,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    #[error("payload too large ({len} bytes)")]
+    PayloadTooLarge { len: usize },
+    #[error("system clock before unix epoch")]
+    ClockBeforeEpoch,
+    #[error("packet too short ({len} bytes)")]
+    Truncated { len: usize },
+    #[error("bad packet magic")]
+    BadMagic,
+    #[error("unsupported protocol version {0}")]
+    UnsupportedVersion(u8),
+    #[error("unsupported codec id {0}")]
+    UnsupportedCodec(u8),
+    #[error("invalid channel count {0}")]
+    InvalidChannels(u8),
+    #[error("unsupported sample rate {0} Hz")]
+    UnsupportedSampleRate(u32),
+    #[error("invalid payload length {declared} ({available} bytes available)")]
+    PayloadLength { declared: usize, available: usize },
+    #[error("failed to read PTP clock {}", path.display())]
+    PtpClock {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CryptoError {
+    #[error("key must be 32 bytes, written as 64 hex digits or base64")]
+    InvalidKey,
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("failed to get random bytes from the OS")]
+    Random(#[source] getrandom::Error),
+    #[error("payload too large to encrypt ({len} bytes)")]
+    PayloadTooLarge { len: usize },
+    #[error("failed to access {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{} does not hold a 64-hex-digit secret key", path.display())]
+    InvalidIdentity { path: PathBuf },
+    #[error("{} has a line that is not a label and a 64-hex-digit public key", path.display())]
+    InvalidPeers { path: PathBuf },
+    #[error("malformed or forged handshake message")]
+    Handshake,
+    #[error("the public key of {peer} changed since pairing; remove its line from {} to pair again", path.display())]
+    PeerKeyChanged { peer: String, path: PathBuf },
+    #[error("pin must be sha256: followed by 64 hex digits")]
+    InvalidPin,
+    #[error("the receiver's public key {key} matches no pinned fingerprint")]
+    PinMismatch { key: String },
+    #[error("the receiver does not trust this sender's public key")]
+    Rejected,
+    #[error("no handshake reply; the receiver may not have pairing enabled")]
+    NoReply,
+}
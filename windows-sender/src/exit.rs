@@ -0,0 +1,127 @@
+//! Process exit codes, so wrapper scripts and service managers can tell why the sender
+//! stopped and pick a restart policy.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use windows_sender::error::{BluetoothError, CaptureError, Error, TransportError};
+
+use crate::alert::AlertExit;
+
+/// Anything else.
+pub const FAILURE: u8 = 1;
+/// Flags, the config file, a key or another setting kept the sender from starting.
+pub const CONFIG: u8 = 2;
+/// `--alert-exit` stopped the stream.
+pub const ALERT: u8 = 3;
+/// The capture device was missing or would not open at start.
+pub const NO_DEVICE: u8 = 4;
+/// A target could not be resolved or connected to at start.
+pub const UNREACHABLE: u8 = 5;
+/// Capture failed while streaming and could not be brought back.
+pub const CAPTURE_FAILED: u8 = 6;
+/// Sending failed while streaming and could not be brought back.
+pub const NETWORK_FAILED: u8 = 7;
+/// Ctrl+C or a stop signal ended a run that was otherwise fine; 128 + SIGINT, as shells do.
+pub const INTERRUPTED: u8 = 130;
+
+static INTERRUPTED_BY_USER: AtomicBool = AtomicBool::new(false);
+
+/// Marks an error that ended a stream that had been running, rather than one that kept it
+/// from starting.
+#[derive(Debug)]
+pub struct Streaming;
+
+impl fmt::Display for Streaming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("stopped while streaming")
+    }
+}
+
+pub fn streaming(err: impl Into<anyhow::Error>) -> anyhow::Error {
+    err.into().context(Streaming)
+}
+
+/// Called when Ctrl+C or a stop signal asks every session to stop.
+pub fn interrupt() {
+    INTERRUPTED_BY_USER.store(true, Ordering::Relaxed);
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED_BY_USER.load(Ordering::Relaxed)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Capture,
+    Network,
+}
+
+/// The exit code for `err`. Anything that is neither the capture nor the network and did
+/// not happen while streaming is down to the settings.
+pub fn code(err: &anyhow::Error) -> u8 {
+    if err.chain().any(|cause| cause.is::<AlertExit>()) {
+        return ALERT;
+    }
+    let streaming = err.downcast_ref::<Streaming>().is_some();
+    match (err.chain().find_map(part), streaming) {
+        (Some(Part::Capture), false) => NO_DEVICE,
+        (Some(Part::Network), false) => UNREACHABLE,
+        (Some(Part::Capture), true) => CAPTURE_FAILED,
+        (Some(Part::Network), true) => NETWORK_FAILED,
+        (None, false) => CONFIG,
+        (None, true) => FAILURE,
+    }
+}
+
+fn part(cause: &(dyn std::error::Error + 'static)) -> Option<Part> {
+    if cause.is::<CaptureError>() {
+        return Some(Part::Capture);
+    }
+    if cause.is::<TransportError>() {
+        return Some(Part::Network);
+    }
+    match cause.downcast_ref::<Error>()? {
+        Error::Capture(_) => Some(Part::Capture),
+        Error::Transport(_)
+        | Error::Bluetooth(BluetoothError::Connect { .. })
+        | Error::NoFeedback { .. } => Some(Part::Network),
+        Error::Stalled { stage, .. } if *stage == "capture" => Some(Part::Capture),
+        Error::Stalled { .. } => Some(Part::Network),
+        _ => None,
+    }
+}
+
+/// Ctrl+C, Ctrl+Break and closing the console window stop every session the way `quit`
+/// does; a second one ends the process right away.
+#[cfg(windows)]
+pub fn catch_ctrl_c(router: crate::control::Router) -> anyhow::Result<()> {
+    use std::sync::OnceLock;
+
+    use anyhow::Context;
+    use windows_sys::Win32::Foundation::BOOL;
+    use windows_sys::Win32::System::Console::SetConsoleCtrlHandler;
+
+    use crate::control::{ControlCommand, Router};
+
+    static ROUTER: OnceLock<Router> = OnceLock::new();
+
+    unsafe extern "system" fn handler(_event: u32) -> BOOL {
+        if interrupted() {
+            return 0;
+        }
+        interrupt();
+        if let Some(router) = ROUTER.get() {
+            tracing::info!("Ctrl+C: stopping");
+            router.broadcast(ControlCommand::Quit);
+        }
+        1
+    }
+
+    let _ = ROUTER.set(router);
+    // SAFETY: `handler` lives as long as the process.
+    if unsafe { SetConsoleCtrlHandler(Some(handler), 1) } == 0 {
+        return Err(std::io::Error::last_os_error()).context("failed to catch Ctrl+C");
+    }
+    Ok(())
+}
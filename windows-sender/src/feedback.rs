@@ -0,0 +1,497 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tracing::{debug, info};
+
+use crate::clock::ClockSync;
+use crate::crypto::ct_eq;
+use crate::dsp::SharedDspSettings;
+use crate::error::TransportError;
+use crate::protocol::{
+    build_clock, is_access_token, is_clock, is_volume, now_us, parse_access_token, parse_clock,
+    parse_feedback, parse_volume, ClockMessage, FeedbackReport, VolumeRequest,
+};
+use crate::stats::{SenderStats, SequenceCounts, TargetStats};
+use crate::transport::{is_timeout, TcpWriter};
+
+/// How long a reader blocks before checking whether its transport is gone.
+const READ_TIMEOUT: Duration = Duration::from_millis(200);
+/// Capture times kept for matching reports; about four seconds of 1 ms frames.
+const SENT_HISTORY: usize = 4096;
+/// How long a TCP receiver has to present the access token after the connect.
+const ACCESS_TIMEOUT: Duration = Duration::from_secs(3);
+/// Gain a receiver may ask for: attenuation only, down to where it is as good as muted.
+const REMOTE_GAIN_DB: (f32, f32) = (-60.0, 0.0);
+/// Volume requests this close behind the last one applied arrived late and are ignored;
+/// further behind, the receiver started counting over.
+const VOLUME_REORDER: u32 = 64;
+
+/// What has been learned from one receiver's reports so far.
+#[derive(Default)]
+struct Peer {
+    clock: ClockSync,
+    /// Counts already added to `SenderStats`.
+    counted: Option<SequenceCounts>,
+    /// The send loop's sequence number minus this receiver's; see `TargetSet`.
+    seq_offset: u32,
+    /// `seq` of the last volume request applied.
+    volume_seq: Option<u32>,
+}
+
+/// Turns receiver reports into round-trip, clock-offset, capture-to-playout and delivery
+/// figures in `SenderStats`.
+pub struct FeedbackTracker {
+    stats: Arc<SenderStats>,
+    access_token: Option<String>,
+    /// Advertised in every clock answer; 0 without multi-room playout.
+    playout_delay_ms: u16,
+    sent: Mutex<VecDeque<(u32, u64)>>,
+    peers: Mutex<HashMap<SocketAddr, Peer>>,
+    /// Where volume requests from receivers go; `None` ignores them.
+    remote_volume: Option<Arc<SharedDspSettings>>,
+}
+
+impl FeedbackTracker {
+    /// With `access_token`, only receivers that present it are heard, and TCP targets
+    /// must present it before the stream starts. `playout_delay` goes out with every clock
+    /// answer so receivers play in step.
+    pub fn new(
+        stats: Arc<SenderStats>,
+        access_token: Option<String>,
+        playout_delay: Option<Duration>,
+    ) -> Self {
+        Self {
+            stats,
+            access_token,
+            playout_delay_ms: playout_delay.map_or(0, |delay| {
+                delay.as_millis().clamp(1, u16::MAX.into()) as u16
+            }),
+            sent: Mutex::new(VecDeque::with_capacity(SENT_HISTORY)),
+            peers: Mutex::new(HashMap::new()),
+            remote_volume: None,
+        }
+    }
+
+    /// Lets admitted receivers set the gain and mute of `dsp`, as if through the control
+    /// API.
+    pub fn with_remote_volume(mut self, dsp: Arc<SharedDspSettings>) -> Self {
+        self.remote_volume = Some(dsp);
+        self
+    }
+
+    fn admits(&self, presented: &[u8]) -> bool {
+        self.access_token
+            .as_ref()
+            .is_none_or(|token| ct_eq(token.as_bytes(), presented))
+    }
+
+    fn reject(&self, from: SocketAddr, why: &str) {
+        self.stats
+            .unauthorized_feedback
+            .fetch_add(1, Ordering::Relaxed);
+        debug!("ignoring feedback from {from}: {why}");
+    }
+
+    /// Remembers when the audio in packet `seq` was captured, on the sender clock.
+    pub fn record_sent(&self, seq: u32, capture_time_us: u64) {
+        let mut sent = self.sent.lock().unwrap();
+        // A restarted send loop starts over at seq 0.
+        if sent
+            .back()
+            .is_some_and(|(last, _)| last.wrapping_add(1) != seq)
+        {
+            sent.clear();
+        }
+        if sent.len() == SENT_HISTORY {
+            sent.pop_front();
+        }
+        sent.push_back((seq, capture_time_us));
+    }
+
+    /// The stats kept for target `addr`, started if it has none yet.
+    pub(crate) fn target_stats(&self, addr: SocketAddr) -> Arc<TargetStats> {
+        self.stats.targets.register(addr)
+    }
+
+    pub(crate) fn forget_target(&self, addr: SocketAddr) {
+        self.stats.targets.remove(addr);
+        self.peers.lock().unwrap().remove(&addr);
+    }
+
+    /// Tells how the sequence numbers `addr` reports map onto the ones `record_sent` got.
+    pub(crate) fn set_seq_offset(&self, addr: SocketAddr, offset: u32) {
+        self.peers
+            .lock()
+            .unwrap()
+            .entry(addr)
+            .or_default()
+            .seq_offset = offset;
+    }
+
+    /// Folds in a report from `from` that arrived at `arrival_us` on the sender clock.
+    pub fn handle(&self, from: SocketAddr, report: &FeedbackReport, arrival_us: u64) {
+        self.stats.feedback_reports.fetch_add(1, Ordering::Relaxed);
+        let target = self.stats.targets.get(from);
+        self.count_delivery(from, report.counts, target.as_deref());
+        self.stats
+            .remote_jitter_us
+            .store(report.jitter_us, Ordering::Relaxed);
+        if let Some(target) = &target {
+            target
+                .remote_jitter_us
+                .store(report.jitter_us, Ordering::Relaxed);
+        }
+        self.stats
+            .remote_probe_bytes
+            .store(report.probe_bytes, Ordering::Relaxed);
+        let Some(offset_us) = self.update_clock(from, report, arrival_us, target.as_deref()) else {
+            return;
+        };
+        let Some(played) = report.played else {
+            return;
+        };
+        let seq_offset = self
+            .peers
+            .lock()
+            .unwrap()
+            .get(&from)
+            .map_or(0, |peer| peer.seq_offset);
+        let Some(captured_us) = self.capture_time(played.last_seq.wrapping_add(seq_offset)) else {
+            return;
+        };
+        let playout_us = played.playout_time_us as i64 - offset_us;
+        if let Ok(latency_us) = u64::try_from(playout_us - captured_us as i64) {
+            self.stats.capture_to_playout_hist.record(latency_us);
+        }
+    }
+
+    fn apply_volume(&self, from: SocketAddr, request: &VolumeRequest) {
+        let Some(dsp) = &self.remote_volume else {
+            debug!("ignoring volume request from {from}: --remote-volume is off");
+            return;
+        };
+        {
+            let mut peers = self.peers.lock().unwrap();
+            let peer = peers.entry(from).or_default();
+            if peer
+                .volume_seq
+                .is_some_and(|last| last.wrapping_sub(request.seq) < VOLUME_REORDER)
+            {
+                return;
+            }
+            peer.volume_seq = Some(request.seq);
+        }
+        let (min, max) = REMOTE_GAIN_DB;
+        let gain_db = if request.gain_db.is_finite() {
+            request.gain_db.clamp(min, max)
+        } else {
+            max
+        };
+        let current = dsp.get();
+        if current.gain_db == gain_db && current.muted == request.muted {
+            return;
+        }
+        dsp.update(|dsp| {
+            dsp.gain_db = gain_db;
+            dsp.muted = request.muted;
+        });
+        if request.muted {
+            info!("{from} muted the stream");
+        } else {
+            info!("{from} set the gain to {gain_db:.1} dB");
+        }
+    }
+
+    fn capture_time(&self, seq: u32) -> Option<u64> {
+        let sent = self.sent.lock().unwrap();
+        let (first, _) = *sent.front()?;
+        let (found, captured_us) = *sent.get(seq.wrapping_sub(first) as usize)?;
+        (found == seq).then_some(captured_us)
+    }
+
+    /// NTP-style estimate from the echoed send time, its arrival at the receiver, the
+    /// report time and the report's arrival here (`arrival_us`); see `clock`.
+    fn update_clock(
+        &self,
+        from: SocketAddr,
+        report: &FeedbackReport,
+        arrival_us: u64,
+        target: Option<&TargetStats>,
+    ) -> Option<i64> {
+        if report.echo_send_time_us == 0 || report.echo_recv_time_us == 0 {
+            return None;
+        }
+        let mut peers = self.peers.lock().unwrap();
+        let clock = &mut peers.entry(from).or_default().clock;
+        let sample = clock.add(
+            report.echo_send_time_us,
+            report.echo_recv_time_us,
+            report.report_time_us,
+            arrival_us,
+        );
+        let offset_us = clock.offset_us(arrival_us)?;
+        self.stats.rtt_us.store(sample.rtt_us, Ordering::Relaxed);
+        if let Some(target) = target {
+            target.rtt_us.store(sample.rtt_us, Ordering::Relaxed);
+        }
+        self.stats
+            .clock_offset_us
+            .store(offset_us, Ordering::Relaxed);
+        self.stats
+            .clock_drift_ppb
+            .store(clock.drift_ppb().unwrap_or(0), Ordering::Relaxed);
+        Some(offset_us)
+    }
+
+    /// Adds what changed since the previous report from `from`. The first report, and
+    /// any after the receiver restarted, only sets the baseline.
+    fn count_delivery(
+        &self,
+        from: SocketAddr,
+        counts: SequenceCounts,
+        target: Option<&TargetStats>,
+    ) {
+        let mut peers = self.peers.lock().unwrap();
+        let counted = &mut peers.entry(from).or_default().counted;
+        let Some(last) = counted.filter(|last| counts.received >= last.received) else {
+            *counted = Some(counts);
+            return;
+        };
+        let stats = &self.stats;
+        let add = |total: &AtomicU64, now: u64, last: u64| {
+            total.fetch_add(now.saturating_sub(last), Ordering::Relaxed);
+        };
+        add(&stats.remote_received, counts.received, last.received);
+        add(&stats.remote_lost, counts.lost, last.lost);
+        add(&stats.remote_reordered, counts.reordered, last.reordered);
+        add(&stats.remote_duplicates, counts.duplicates, last.duplicates);
+        if let Some(target) = target {
+            add(&target.remote_received, counts.received, last.received);
+            add(&target.remote_lost, counts.lost, last.lost);
+        }
+        // Loss shrinks when a late packet fills a gap; keep the high mark so the gap is
+        // not counted twice if it opens again.
+        *counted = Some(SequenceCounts {
+            lost: counts.lost.max(last.lost),
+            ..counts
+        });
+    }
+}
+
+/// Reads reports coming back from one target; the thread stops shortly after this is
+/// dropped.
+pub(crate) struct FeedbackReader {
+    running: Arc<AtomicBool>,
+}
+
+impl FeedbackReader {
+    pub(crate) fn udp(
+        socket: &UdpSocket,
+        target: SocketAddr,
+        tracker: Arc<FeedbackTracker>,
+    ) -> Result<Self, TransportError> {
+        let socket = socket.try_clone().map_err(configure_error)?;
+        socket
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .map_err(configure_error)?;
+        Self::spawn(move |running| {
+            let mut buf = [0u8; 512];
+            let mut admitted = tracker.access_token.is_none();
+            while running.load(Ordering::Relaxed) {
+                match socket.recv_from(&mut buf) {
+                    Ok((len, from)) if from == target => {
+                        let mut answer = |frame: &[u8]| socket.send_to(frame, target).map(drop);
+                        handle_packet(&tracker, target, &buf[..len], &mut admitted, &mut answer);
+                    }
+                    Ok(_) => {}
+                    Err(err) if is_timeout(&err) => {}
+                    // Windows reports ICMP port unreachable here while nobody listens.
+                    Err(err) => {
+                        debug!("feedback from {target}: {err}");
+                        thread::sleep(READ_TIMEOUT);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Waits for the access token a TCP receiver sends first, if the tracker needs one.
+    pub(crate) fn admit_tcp(
+        stream: &mut TcpStream,
+        target: SocketAddr,
+        tracker: &FeedbackTracker,
+    ) -> Result<(), TransportError> {
+        if tracker.access_token.is_none() {
+            return Ok(());
+        }
+        stream
+            .set_read_timeout(Some(ACCESS_TIMEOUT))
+            .map_err(configure_error)?;
+        let unauthorized = |err: io::Error| match err.kind() {
+            io::ErrorKind::UnexpectedEof => TransportError::Unauthorized { addr: target },
+            _ if is_timeout(&err) => TransportError::Unauthorized { addr: target },
+            _ => TransportError::Receive(err),
+        };
+        let mut len_buf = [0u8; 2];
+        stream.read_exact(&mut len_buf).map_err(unauthorized)?;
+        let mut buf = vec![0u8; u16::from_le_bytes(len_buf) as usize];
+        stream.read_exact(&mut buf).map_err(unauthorized)?;
+        match parse_access_token(&buf) {
+            Ok(presented) if tracker.admits(presented) => Ok(()),
+            _ => {
+                tracker.reject(target, "missing or wrong access token");
+                Err(TransportError::Unauthorized { addr: target })
+            }
+        }
+    }
+
+    /// Reads from a stream whose receiver was already admitted; clock pings are answered
+    /// through `writer`, which the send loop shares.
+    pub(crate) fn tcp(
+        writer: Arc<Mutex<TcpWriter>>,
+        target: SocketAddr,
+        tracker: Arc<FeedbackTracker>,
+    ) -> Result<Self, TransportError> {
+        let mut stream = writer
+            .lock()
+            .unwrap()
+            .stream()
+            .try_clone()
+            .map_err(configure_error)?;
+        stream
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .map_err(configure_error)?;
+        Self::spawn(move |running| {
+            let mut len_buf = [0u8; 2];
+            let mut buf = vec![0u8; 512];
+            loop {
+                match read_fully(&mut stream, &mut len_buf, running) {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(err) => {
+                        debug!("feedback from {target}: {err}");
+                        break;
+                    }
+                }
+                let len = u16::from_le_bytes(len_buf) as usize;
+                buf.resize(len, 0);
+                match read_fully(&mut stream, &mut buf, running) {
+                    Ok(true) => {
+                        let mut answer = |frame: &[u8]| writer.lock().unwrap().write_frame(frame);
+                        handle_packet(&tracker, target, &buf, &mut true, &mut answer);
+                    }
+                    Ok(false) => break,
+                    Err(err) => {
+                        debug!("feedback from {target}: {err}");
+                        break;
+                    }
+                }
+            }
+        })
+    }
+
+    fn spawn<F>(read: F) -> Result<Self, TransportError>
+    where
+        F: FnOnce(&AtomicBool) + Send + 'static,
+    {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = Arc::clone(&running);
+        thread::Builder::new()
+            .name("feedback-reader".to_string())
+            .spawn(move || read(&thread_running))
+            .map_err(configure_error)?;
+        Ok(Self { running })
+    }
+}
+
+impl Drop for FeedbackReader {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+fn configure_error(source: io::Error) -> TransportError {
+    TransportError::Configure {
+        what: "feedback reader",
+        source,
+    }
+}
+
+/// Folds in a report from an admitted receiver, applies its volume request, or answers its
+/// clock ping through `answer`; an access frame decides whether the packets after it are admitted.
+fn handle_packet(
+    tracker: &FeedbackTracker,
+    from: SocketAddr,
+    packet: &[u8],
+    admitted: &mut bool,
+    answer: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+) {
+    if is_access_token(packet) {
+        *admitted = parse_access_token(packet).is_ok_and(|presented| tracker.admits(presented));
+        if !*admitted {
+            tracker.reject(from, "wrong access token");
+        }
+        return;
+    }
+    if !*admitted {
+        tracker.reject(from, "no access token yet");
+        return;
+    }
+    let Ok(arrival_us) = now_us() else {
+        return;
+    };
+    if is_clock(packet) {
+        match parse_clock(packet) {
+            Ok(ping) if !ping.reply => {
+                let Ok(transmit_us) = now_us() else {
+                    return;
+                };
+                let reply = build_clock(&ClockMessage {
+                    reply: true,
+                    playout_delay_ms: tracker.playout_delay_ms,
+                    origin_us: ping.origin_us,
+                    receive_us: arrival_us,
+                    transmit_us,
+                });
+                if let Err(err) = answer(&reply) {
+                    debug!("failed to answer clock ping from {from}: {err}");
+                }
+            }
+            Ok(_) => {}
+            Err(err) => debug!("ignoring clock ping from {from}: {err}"),
+        }
+        return;
+    }
+    if is_volume(packet) {
+        match parse_volume(packet) {
+            Ok(request) => tracker.apply_volume(from, &request),
+            Err(err) => debug!("ignoring volume request from {from}: {err}"),
+        }
+        return;
+    }
+    match parse_feedback(packet) {
+        Ok(report) => tracker.handle(from, &report, arrival_us),
+        Err(err) => debug!("ignoring feedback from {from}: {err}"),
+    }
+}
+
+fn read_fully(stream: &mut TcpStream, out: &mut [u8], running: &AtomicBool) -> io::Result<bool> {
+    let mut offset = 0;
+    while offset < out.len() {
+        if !running.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        match stream.read(&mut out[offset..]) {
+            Ok(0) => return Ok(false),
+            Ok(n) => offset += n,
+            Err(err) if is_timeout(&err) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,560 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::capture::{QueueOverflow, SourceKind};
+use crate::crypto::noise::KeyPin;
+use crate::crypto::{Cipher, Key, KEY_LEN};
+use crate::dsp::DspSettings;
+use crate::error::{error_chain, CryptoError, Error};
+use crate::pairing::NoiseConfig;
+use crate::ptp::PtpSource;
+use crate::receiver::{AudioReceiver, PtpPlayout, ReceiverConfig};
+use crate::resolve::{Endpoint, IpPreference};
+use crate::sched::Scheduling;
+use crate::sender::{AudioSender, LatePolicy, SenderConfig};
+use crate::stats::{ReceiverStatsSnapshot, SenderStatsSnapshot};
+use crate::transport::TransportKind;
+
+pub const AUDIO_OK: i32 = 0;
+pub const AUDIO_ERR_INVALID_ARGUMENT: i32 = -1;
+pub const AUDIO_ERR_CAPTURE: i32 = -2;
+pub const AUDIO_ERR_TRANSPORT: i32 = -3;
+pub const AUDIO_ERR_CODEC: i32 = -4;
+pub const AUDIO_ERR_PROTOCOL: i32 = -5;
+pub const AUDIO_ERR_INTERNAL: i32 = -6;
+/// A thread of the engine panicked; the message says where.
+pub const AUDIO_ERR_PANICKED: i32 = -7;
+
+pub const AUDIO_SOURCE_DESKTOP: u32 = 0;
+pub const AUDIO_SOURCE_MIC: u32 = 1;
+/// Synthetic 8-channel audio in 1 ms periods, for soak tests without devices.
+pub const AUDIO_SOURCE_STRESS: u32 = 2;
+pub const AUDIO_TRANSPORT_UDP: u32 = 0;
+pub const AUDIO_TRANSPORT_TCP: u32 = 1;
+pub const AUDIO_CIPHER_AES_256_GCM: u32 = 0;
+pub const AUDIO_CIPHER_CHACHA20_POLY1305: u32 = 1;
+pub const AUDIO_CIPHER_HMAC_SHA256: u32 = 2;
+pub const AUDIO_QUEUE_DROP_NEWEST: u32 = 0;
+pub const AUDIO_QUEUE_OVERWRITE_OLDEST: u32 = 1;
+pub const AUDIO_QUEUE_COLLAPSE_TO_LATEST: u32 = 2;
+
+#[repr(C)]
+pub struct AudioSenderConfig {
+    pub target_host: *const c_char,
+    pub port: u16,
+    /// May be fractional, such as 2.5, as long as it is a whole number of samples.
+    pub frame_ms: f32,
+    pub source: u32,
+    pub transport: u32,
+    pub desktop_device: *const c_char,
+    /// 32-byte pre-shared key to encrypt with, or null to send plaintext.
+    pub key: *const u8,
+    /// `AUDIO_CIPHER_*`; ignored without a key or `noise_dir`.
+    pub cipher: u32,
+    /// Seconds between key rotations, or 0 to keep one key for the whole stream.
+    pub key_rotation_secs: u32,
+    /// Directory with this sender's Noise identity and pinned receivers, or null to skip
+    /// pairing. Excludes `key`.
+    pub noise_dir: *const c_char,
+    /// `sha256:` fingerprint the receiver's Noise key must match, or null to pin it on
+    /// first pairing.
+    pub noise_pin: *const c_char,
+    /// Token receivers must present before their reports count, or null to hear anyone.
+    pub access_token: *const c_char,
+    /// `system` or a PTP hardware clock device to stamp packets with, or null for the
+    /// system clock without the PTP flag.
+    pub ptp_clock: *const c_char,
+    /// Send packets on the cadence of the audio they carry rather than in capture bursts.
+    pub pace: bool,
+    /// Multi-room delay from send time to playout on every receiver, or 0 to play on
+    /// arrival.
+    pub playout_delay_ms: u32,
+    /// Milliseconds since the Unix epoch to hold the stream until, or 0 to start at once.
+    pub start_at_unix_ms: u64,
+    /// Drop audio older than this when sending falls behind, flagging the gap to the
+    /// receivers, or 0 to send all of it.
+    pub max_audio_age_ms: u32,
+    /// `AUDIO_QUEUE_*`: what the capture drops while the queue to the send loop is full.
+    pub queue_overflow: u32,
+    /// Send UDP through io_uring; fails to start unless built for Linux with `io-uring`.
+    pub io_uring: bool,
+    /// Let the kernel split batches of UDP packets (`UDP_SEGMENT`); Linux only.
+    pub gso: bool,
+    /// Run the capture and send threads at real-time priority where permitted.
+    pub realtime: bool,
+    /// Bit N lets the capture and send threads run on core N, or 0 for any core.
+    pub affinity_mask: u64,
+    /// `SO_SNDBUF` of the sockets to the receivers in bytes, or 0 for the OS default.
+    pub so_sndbuf: u32,
+    /// Write TCP without blocking, dropping the oldest packets to a receiver this many
+    /// milliseconds behind, or 0 to block.
+    pub tcp_backlog_ms: u32,
+    /// Most audio held between capture and the sockets in milliseconds, the oldest
+    /// dropped past it, or 0 for no cap.
+    pub max_buffered_ms: u32,
+    /// Threads encoding with the codec plugin, or 0 to encode on the send thread.
+    pub encode_workers: u32,
+    /// Most kilobits per second sent to each receiver, headers included, or 0 for no cap.
+    pub max_kbps: u32,
+}
+
+#[repr(C)]
+pub struct AudioReceiverConfig {
+    pub port: u16,
+    pub transport: u32,
+    pub jitter_ms: u32,
+    /// 32-byte pre-shared key of an encrypted stream, or null for plaintext.
+    pub key: *const u8,
+    /// Directory with this receiver's Noise identity and pinned senders, or null to skip
+    /// pairing. Excludes `key`.
+    pub noise_dir: *const c_char,
+    /// Token to present to the sender, or null.
+    pub access_token: *const c_char,
+    /// `system` or a PTP hardware clock device to schedule PTP-stamped frames against, or
+    /// null to play them as they come.
+    pub ptp_clock: *const c_char,
+    /// Send time to playout of PTP-stamped frames.
+    pub ptp_delay_ms: u32,
+    /// Receive UDP through io_uring; fails to start unless built for Linux with `io-uring`.
+    pub io_uring: bool,
+    /// `SO_RCVBUF` of the receiving socket in bytes, or 0 for the OS default.
+    pub so_rcvbuf: u32,
+}
+
+pub struct AudioSenderHandle {
+    inner: AudioSender,
+}
+
+pub struct AudioReceiverHandle {
+    inner: AudioReceiver,
+    /// A frame that did not fit the caller's buffer, returned by the next read.
+    held: Mutex<Option<Vec<i16>>>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Starts a sender and stores an owned handle in `out_handle`.
+///
+/// # Safety
+///
+/// `config` must point to a valid `AudioSenderConfig` whose string fields are
+/// NUL-terminated (or null for `desktop_device`, `noise_dir`, `noise_pin`,
+/// `access_token` and `ptp_clock`), `key` must be null or point to 32 readable bytes,
+/// and `out_handle` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn audio_sender_start(
+    config: *const AudioSenderConfig,
+    out_handle: *mut *mut AudioSenderHandle,
+) -> i32 {
+    guard(|| {
+        if config.is_null() || out_handle.is_null() {
+            return Err(invalid("config and out_handle must not be null"));
+        }
+        let config = &*config;
+        let host = c_str(config.target_host)?.ok_or_else(|| invalid("target_host is required"))?;
+        let target = Endpoint::new(&host, config.port, IpPreference::default());
+        let source = match config.source {
+            AUDIO_SOURCE_DESKTOP => SourceKind::Desktop,
+            AUDIO_SOURCE_MIC => SourceKind::Mic,
+            AUDIO_SOURCE_STRESS => SourceKind::Stress,
+            other => return Err(invalid(&format!("unknown source {other}"))),
+        };
+        let mut noise = noise(config.noise_dir, NoiseConfig::sender)?;
+        if let (Some(noise), Some(pin)) = (noise.as_mut(), c_str(config.noise_pin)?) {
+            noise.pins = vec![pin.parse::<KeyPin>().map_err(Error::from)?];
+        }
+        let sender = AudioSender::start(SenderConfig {
+            targets: vec![target],
+            source,
+            desktop_device: c_str(config.desktop_device)?,
+            source_file: None,
+            transport: transport_kind(config.transport)?,
+            vban_stream: None,
+            aes67_session: None,
+            aes67_encoding: None,
+            bt_device: None,
+            bt_channel: None,
+            frame_ms: config.frame_ms,
+            dsp: DspSettings::default(),
+            watchdog: None,
+            plugins: Vec::new(),
+            journal: Default::default(),
+            key: key(config.key),
+            cipher: cipher(config.cipher)?,
+            key_rotation: (config.key_rotation_secs > 0)
+                .then(|| Duration::from_secs(config.key_rotation_secs.into())),
+            noise,
+            access_token: c_str(config.access_token)?,
+            remote_volume: false,
+            ptp: c_str(config.ptp_clock)?.map(|source| PtpSource::from(source.as_str())),
+            pace: config.pace,
+            pause_notice: false,
+            playout_delay: (config.playout_delay_ms > 0)
+                .then(|| Duration::from_millis(config.playout_delay_ms.into())),
+            start_at: (config.start_at_unix_ms > 0)
+                .then(|| UNIX_EPOCH + Duration::from_millis(config.start_at_unix_ms)),
+            late_policy: match config.max_audio_age_ms {
+                0 => LatePolicy::Never,
+                ms => LatePolicy::DropOldest(Duration::from_millis(ms.into())),
+            },
+            queue_overflow: queue_overflow(config.queue_overflow)?,
+            io_uring: config.io_uring,
+            gso: config.gso,
+            send_buffer: (config.so_sndbuf > 0).then_some(config.so_sndbuf),
+            max_kbps: (config.max_kbps > 0).then_some(config.max_kbps),
+            tee: None,
+            ndi: None,
+            output: None,
+            passthrough: false,
+            tcp_backlog: (config.tcp_backlog_ms > 0)
+                .then(|| Duration::from_millis(config.tcp_backlog_ms.into())),
+            max_buffered: (config.max_buffered_ms > 0)
+                .then(|| Duration::from_millis(config.max_buffered_ms.into())),
+            encode_workers: config.encode_workers as usize,
+            scheduling: Scheduling {
+                realtime: config.realtime,
+                cores: (0..64)
+                    .filter(|core| config.affinity_mask & 1 << core != 0)
+                    .collect(),
+            },
+        })?;
+        *out_handle = Box::into_raw(Box::new(AudioSenderHandle { inner: sender }));
+        Ok(AUDIO_OK)
+    })
+}
+
+/// Stops the sender and frees the handle.
+///
+/// # Safety
+///
+/// `handle` must come from `audio_sender_start` and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn audio_sender_stop(handle: *mut AudioSenderHandle) -> i32 {
+    guard(|| {
+        if handle.is_null() {
+            return Err(invalid("handle must not be null"));
+        }
+        let handle = Box::from_raw(handle);
+        handle.inner.stop()?;
+        Ok(AUDIO_OK)
+    })
+}
+
+/// Copies the cumulative sender counters into `out_stats`.
+///
+/// # Safety
+///
+/// `handle` must be a live sender handle and `out_stats` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn audio_sender_get_stats(
+    handle: *const AudioSenderHandle,
+    out_stats: *mut SenderStatsSnapshot,
+) -> i32 {
+    guard(|| {
+        if handle.is_null() || out_stats.is_null() {
+            return Err(invalid("handle and out_stats must not be null"));
+        }
+        *out_stats = (*handle).inner.snapshot();
+        Ok(AUDIO_OK)
+    })
+}
+
+/// Sends a sync marker named `name` at the position of the audio being captured now.
+///
+/// # Safety
+///
+/// `handle` must be a live sender handle and `name` must be NUL-terminated.
+#[no_mangle]
+pub unsafe extern "C" fn audio_sender_mark(
+    handle: *const AudioSenderHandle,
+    name: *const c_char,
+) -> i32 {
+    guard(|| {
+        if handle.is_null() || name.is_null() {
+            return Err(invalid("handle and name must not be null"));
+        }
+        let name = c_str(name)?.unwrap_or_default();
+        (*handle).inner.control().mark(&name)?;
+        Ok(AUDIO_OK)
+    })
+}
+
+/// Starts a receiver and stores an owned handle in `out_handle`.
+///
+/// # Safety
+///
+/// `config` must point to a valid `AudioReceiverConfig` whose `key` is null or points to 32
+/// readable bytes, whose `noise_dir`, `access_token` and `ptp_clock` are null or
+/// NUL-terminated, and `out_handle` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn audio_receiver_start(
+    config: *const AudioReceiverConfig,
+    out_handle: *mut *mut AudioReceiverHandle,
+) -> i32 {
+    guard(|| {
+        if config.is_null() || out_handle.is_null() {
+            return Err(invalid("config and out_handle must not be null"));
+        }
+        let config = &*config;
+        let receiver = AudioReceiver::start(ReceiverConfig {
+            port: config.port,
+            transport: transport_kind(config.transport)?,
+            jitter_ms: config.jitter_ms,
+            key: key(config.key),
+            noise: noise(config.noise_dir, NoiseConfig::receiver)?,
+            access_token: c_str(config.access_token)?,
+            ptp: c_str(config.ptp_clock)?.map(|source| PtpPlayout {
+                source: PtpSource::from(source.as_str()),
+                delay: Duration::from_millis(config.ptp_delay_ms.into()),
+            }),
+            io_uring: config.io_uring,
+            recv_buffer: (config.so_rcvbuf > 0).then_some(config.so_rcvbuf),
+        })?;
+        *out_handle = Box::into_raw(Box::new(AudioReceiverHandle {
+            inner: receiver,
+            held: Mutex::new(None),
+        }));
+        Ok(AUDIO_OK)
+    })
+}
+
+/// Stops the receiver and frees the handle.
+///
+/// # Safety
+///
+/// `handle` must come from `audio_receiver_start` and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn audio_receiver_stop(handle: *mut AudioReceiverHandle) -> i32 {
+    guard(|| {
+        if handle.is_null() {
+            return Err(invalid("handle must not be null"));
+        }
+        let handle = Box::from_raw(handle);
+        handle.inner.stop()?;
+        Ok(AUDIO_OK)
+    })
+}
+
+/// Copies the cumulative receiver counters into `out_stats`.
+///
+/// # Safety
+///
+/// `handle` must be a live receiver handle and `out_stats` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn audio_receiver_get_stats(
+    handle: *const AudioReceiverHandle,
+    out_stats: *mut ReceiverStatsSnapshot,
+) -> i32 {
+    guard(|| {
+        if handle.is_null() || out_stats.is_null() {
+            return Err(invalid("handle and out_stats must not be null"));
+        }
+        *out_stats = (*handle).inner.stats();
+        Ok(AUDIO_OK)
+    })
+}
+
+/// Pops the next playout frame into `out_samples` (interleaved PCM16).
+///
+/// Returns the number of samples written, 0 on underrun, or a negative error code. A frame
+/// larger than `capacity` fails with `AUDIO_ERR_INVALID_ARGUMENT`, naming its size in the
+/// last error, and is kept for the next call.
+///
+/// # Safety
+///
+/// `handle` must be a live receiver handle and `out_samples` must be valid for
+/// `capacity` writes.
+#[no_mangle]
+pub unsafe extern "C" fn audio_receiver_read_frame(
+    handle: *const AudioReceiverHandle,
+    out_samples: *mut i16,
+    capacity: usize,
+    timeout_ms: u32,
+) -> i32 {
+    guard(|| {
+        if handle.is_null() || out_samples.is_null() {
+            return Err(invalid("handle and out_samples must not be null"));
+        }
+        let handle = &*handle;
+        let mut held = handle.held.lock().unwrap_or_else(|err| err.into_inner());
+        let Some(frame) = held.take().or_else(|| {
+            handle
+                .inner
+                .read_frame(Duration::from_millis(timeout_ms as u64))
+        }) else {
+            return Ok(0);
+        };
+        if frame.len() > capacity {
+            let message = format!(
+                "frame of {} samples exceeds capacity {capacity}; it is kept for the next call",
+                frame.len()
+            );
+            *held = Some(frame);
+            return Err(invalid(&message));
+        }
+        ptr::copy_nonoverlapping(frame.as_ptr(), out_samples, frame.len());
+        Ok(frame.len() as i32)
+    })
+}
+
+/// Pops the oldest sync marker not read yet, storing its media time in `out_media_time`
+/// and its name, truncated to `len - 1` bytes and NUL-terminated, in `name_buf`.
+///
+/// Returns 1 when a marker was read, 0 when none is waiting, or a negative error code.
+///
+/// # Safety
+///
+/// `handle` must be a live receiver handle, `out_media_time` must be writable and
+/// `name_buf` must be valid for `len` writes, or null to skip the name.
+#[no_mangle]
+pub unsafe extern "C" fn audio_receiver_next_marker(
+    handle: *const AudioReceiverHandle,
+    out_media_time: *mut u32,
+    name_buf: *mut c_char,
+    len: usize,
+) -> i32 {
+    guard(|| {
+        if handle.is_null() || out_media_time.is_null() {
+            return Err(invalid("handle and out_media_time must not be null"));
+        }
+        let Some(marker) = (*handle).inner.next_marker() else {
+            return Ok(0);
+        };
+        *out_media_time = marker.media_time;
+        if !name_buf.is_null() && len > 0 {
+            let copy_len = marker.name.len().min(len - 1);
+            ptr::copy_nonoverlapping(marker.name.as_ptr() as *const c_char, name_buf, copy_len);
+            *name_buf.add(copy_len) = 0;
+        }
+        Ok(1)
+    })
+}
+
+/// Copies the last error message of the calling thread into `buf`.
+///
+/// Returns the full message length in bytes (excluding the NUL terminator).
+///
+/// # Safety
+///
+/// `buf` must be valid for `len` writes, or null to query the length.
+#[no_mangle]
+pub unsafe extern "C" fn audio_last_error_message(buf: *mut c_char, len: usize) -> usize {
+    LAST_ERROR.with(|last| {
+        let last = last.borrow();
+        if !buf.is_null() && len > 0 {
+            let copy_len = last.len().min(len - 1);
+            ptr::copy_nonoverlapping(last.as_ptr() as *const c_char, buf, copy_len);
+            *buf.add(copy_len) = 0;
+        }
+        last.len()
+    })
+}
+
+struct FfiError {
+    code: i32,
+    message: String,
+}
+
+impl From<Error> for FfiError {
+    fn from(err: Error) -> Self {
+        let code = match &err {
+            Error::Capture(_) => AUDIO_ERR_CAPTURE,
+            Error::Transport(_) | Error::Bluetooth(_) | Error::NoFeedback { .. } => {
+                AUDIO_ERR_TRANSPORT
+            }
+            Error::Codec(_) => AUDIO_ERR_CODEC,
+            Error::Protocol(_) | Error::Crypto(_) => AUDIO_ERR_PROTOCOL,
+            Error::Config(_) => AUDIO_ERR_INVALID_ARGUMENT,
+            Error::Plugin(_) | Error::Ndi(_) | Error::Spawn { .. } | Error::Stalled { .. } => {
+                AUDIO_ERR_INTERNAL
+            }
+            Error::Panicked { .. } => AUDIO_ERR_PANICKED,
+        };
+        FfiError {
+            code,
+            message: error_chain(&err),
+        }
+    }
+}
+
+fn guard<F>(f: F) -> i32
+where
+    F: FnOnce() -> Result<i32, FfiError>,
+{
+    let result = catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|payload| Err(Error::panicked("calling", payload).into()));
+    match result {
+        Ok(code) => code,
+        Err(err) => {
+            LAST_ERROR.with(|last| *last.borrow_mut() = err.message);
+            err.code
+        }
+    }
+}
+
+fn invalid(message: &str) -> FfiError {
+    FfiError {
+        code: AUDIO_ERR_INVALID_ARGUMENT,
+        message: message.to_string(),
+    }
+}
+
+unsafe fn c_str(value: *const c_char) -> Result<Option<String>, FfiError> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map(|s| Some(s.to_string()))
+        .map_err(|_| invalid("string arguments must be valid UTF-8"))
+}
+
+unsafe fn key(value: *const u8) -> Option<Key> {
+    if value.is_null() {
+        return None;
+    }
+    Some(Key::from_bytes(*value.cast::<[u8; KEY_LEN]>()))
+}
+
+unsafe fn noise(
+    dir: *const c_char,
+    load: fn(&Path) -> Result<NoiseConfig, CryptoError>,
+) -> Result<Option<NoiseConfig>, FfiError> {
+    let Some(dir) = c_str(dir)? else {
+        return Ok(None);
+    };
+    Ok(Some(load(Path::new(&dir)).map_err(Error::from)?))
+}
+
+fn cipher(value: u32) -> Result<Cipher, FfiError> {
+    match value {
+        AUDIO_CIPHER_AES_256_GCM => Ok(Cipher::Aes256Gcm),
+        AUDIO_CIPHER_CHACHA20_POLY1305 => Ok(Cipher::ChaCha20Poly1305),
+        AUDIO_CIPHER_HMAC_SHA256 => Ok(Cipher::HmacSha256),
+        other => Err(invalid(&format!("unknown cipher {other}"))),
+    }
+}
+
+fn queue_overflow(value: u32) -> Result<QueueOverflow, FfiError> {
+    match value {
+        AUDIO_QUEUE_DROP_NEWEST => Ok(QueueOverflow::DropNewest),
+        AUDIO_QUEUE_OVERWRITE_OLDEST => Ok(QueueOverflow::OverwriteOldest),
+        AUDIO_QUEUE_COLLAPSE_TO_LATEST => Ok(QueueOverflow::CollapseToLatest),
+        other => Err(invalid(&format!("unknown queue overflow policy {other}"))),
+    }
+}
+
+fn transport_kind(value: u32) -> Result<TransportKind, FfiError> {
+    match value {
+        AUDIO_TRANSPORT_UDP => Ok(TransportKind::Udp),
+        AUDIO_TRANSPORT_TCP => Ok(TransportKind::Tcp),
+        other => Err(invalid(&format!("unknown transport {other}"))),
+    }
+}
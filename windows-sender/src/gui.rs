@@ -0,0 +1,493 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use eframe::egui::{self, Color32, ComboBox, ProgressBar, RichText, Slider};
+use egui_plot::{Legend, Line, Plot};
+use windows_sender::capture::{self, DesktopDevice, SourceKind};
+use windows_sender::journal::EventJournal;
+use windows_sender::resolve::Endpoint;
+use windows_sender::sender::AudioSender;
+use windows_sender::stats::{ChannelLevel, SenderStatsSnapshot};
+
+use crate::session;
+use crate::Args;
+
+const HISTORY: usize = 120;
+/// Fast enough to follow speech without keeping a core busy redrawing.
+const REDRAW: Duration = Duration::from_millis(100);
+const GAIN_RANGE_DB: RangeInclusive<f32> = -40.0..=20.0;
+/// Level meter range; quieter signals show as an empty bar.
+const METER_FLOOR_DB: f32 = -60.0;
+/// Peaks this close to full scale have most likely been clipped.
+const CLIP_PEAK: f32 = 0.999;
+const SHOWN_EVENTS: usize = 8;
+
+/// `--gui`: opens the window and blocks until it is closed, stopping the sender started
+/// from it.
+///
+/// Everything goes through `AudioSender` and `SenderControl`, as any embedding program
+/// would; the other settings on the command line or in `--config` apply to each start.
+pub fn run(args: Args) -> Result<()> {
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([760.0, 720.0]),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "windows-sender",
+        options,
+        Box::new(|cc| {
+            if args.minimized {
+                cc.egui_ctx
+                    .send_viewport_cmd(egui::ViewportCommand::Minimized(true));
+            }
+            Ok(Box::new(App::new(args)))
+        }),
+    )
+    .map_err(|err| anyhow!("failed to open the --gui window: {err}"))
+}
+
+/// Per-interval values derived from the stats of the running sender.
+#[derive(Default)]
+struct History {
+    last: Option<(Instant, SenderStatsSnapshot)>,
+    kbps: VecDeque<[f64; 2]>,
+    drops: VecDeque<[f64; 2]>,
+    buffered_ms: VecDeque<[f64; 2]>,
+    rtt_ms: VecDeque<[f64; 2]>,
+}
+
+impl History {
+    fn update(&mut self, started: Instant, stats: SenderStatsSnapshot) {
+        let now = Instant::now();
+        let x = (now - started).as_secs_f64();
+        if let Some((at, last)) = &self.last {
+            let secs = (now - *at).as_secs_f64().max(f64::EPSILON);
+            let kbps = stats.sent_bytes.saturating_sub(last.sent_bytes) as f64 * 8.0 / 1000.0;
+            let drops = stats.capture_drops.saturating_sub(last.capture_drops) as f64;
+            push(&mut self.kbps, [x, kbps / secs]);
+            push(&mut self.drops, [x, drops / secs]);
+        }
+        push(
+            &mut self.buffered_ms,
+            [x, stats.buffered_audio_us as f64 / 1000.0],
+        );
+        push(&mut self.rtt_ms, [x, stats.rtt_us as f64 / 1000.0]);
+        self.last = Some((now, stats));
+    }
+}
+
+fn push(history: &mut VecDeque<[f64; 2]>, point: [f64; 2]) {
+    if history.len() == HISTORY {
+        history.pop_front();
+    }
+    history.push_back(point);
+}
+
+/// A sender started from the window.
+struct Running {
+    sender: AudioSender,
+    started: Instant,
+    last_sample: Instant,
+    last_levels: Instant,
+    levels: Vec<ChannelLevel>,
+    history: History,
+}
+
+struct App {
+    args: Args,
+    devices: Vec<DesktopDevice>,
+    source: SourceKind,
+    desktop_device: Option<String>,
+    targets: Vec<String>,
+    new_target: String,
+    gain_db: f32,
+    muted: bool,
+    paused: bool,
+    interval: Duration,
+    journal: Arc<EventJournal>,
+    running: Option<Running>,
+    message: Option<String>,
+}
+
+impl App {
+    fn new(args: Args) -> Self {
+        let mut targets: Vec<String> = args
+            .target_ip
+            .iter()
+            .map(|ip| format!("{ip}:{}", args.port))
+            .collect();
+        targets.extend(args.extra_targets.iter().cloned());
+        let mut app = Self {
+            devices: Vec::new(),
+            source: if args.stress {
+                SourceKind::Stress
+            } else {
+                args.source.into()
+            },
+            desktop_device: args.desktop_device.clone(),
+            targets,
+            new_target: String::new(),
+            gain_db: args.gain_db,
+            muted: args.mute,
+            paused: false,
+            interval: Duration::from_millis(args.stats_interval_ms),
+            journal: Arc::default(),
+            running: None,
+            message: None,
+            args,
+        };
+        app.refresh_devices();
+        app
+    }
+
+    fn refresh_devices(&mut self) {
+        match capture::desktop_devices() {
+            Ok(devices) => self.devices = devices,
+            Err(_) => self.devices.clear(),
+        }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let (first, extra) = self.targets.split_first().context("add a target first")?;
+        let (host, port) = first
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+            .with_context(|| format!("invalid target {first}"))?;
+        let mut args = self.args.clone();
+        args.target_ip = Some(host.to_string());
+        args.port = port;
+        args.extra_targets = extra.to_vec();
+        let mut config = session::sender_config(&args)?;
+        config.source = self.source;
+        config.desktop_device = self.desktop_device.clone();
+        config.dsp.gain_db = self.gain_db;
+        config.dsp.muted = self.muted;
+        config.journal = Arc::clone(&self.journal);
+        let sender = AudioSender::start(config).context("failed to start the sender")?;
+        sender.control().set_paused(self.paused);
+        let now = Instant::now();
+        self.running = Some(Running {
+            sender,
+            started: now,
+            last_sample: now,
+            last_levels: now,
+            levels: Vec::new(),
+            history: History::default(),
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        match self.running.take() {
+            Some(running) => Ok(running.sender.stop()?),
+            None => Ok(()),
+        }
+    }
+
+    /// Samples the stats and levels when due, and notices a sender that stopped by itself.
+    fn poll(&mut self) {
+        let Some(running) = self.running.as_mut() else {
+            return;
+        };
+        if !running.sender.is_running() {
+            let running = self.running.take().unwrap();
+            self.message = Some(match running.sender.wait() {
+                Ok(()) => "the sender stopped".to_string(),
+                Err(err) => format!("the sender stopped: {:#}", anyhow::Error::from(err)),
+            });
+            return;
+        }
+        let now = Instant::now();
+        if now - running.last_levels >= REDRAW {
+            running.levels = running.sender.stats().levels.take();
+            running.last_levels = now;
+        }
+        if now - running.last_sample >= self.interval || running.history.last.is_none() {
+            running
+                .history
+                .update(running.started, running.sender.snapshot());
+            running.last_sample = now;
+        }
+    }
+
+    fn source_ui(&mut self, ui: &mut egui::Ui) {
+        let idle = self.running.is_none();
+        ui.add_enabled_ui(idle, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Source");
+                for source in [
+                    SourceKind::Desktop,
+                    SourceKind::Mic,
+                    SourceKind::Tone,
+                    SourceKind::Stress,
+                ] {
+                    ui.radio_value(&mut self.source, source, source.as_str());
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Desktop device");
+                let selected = self.desktop_device.as_deref().unwrap_or("default");
+                ComboBox::from_id_salt("desktop-device")
+                    .selected_text(selected)
+                    .width(320.0)
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.desktop_device, None, "default");
+                        for device in &self.devices {
+                            let label = if device.is_default {
+                                format!("{} [default]", device.name)
+                            } else {
+                                device.name.clone()
+                            };
+                            ui.selectable_value(
+                                &mut self.desktop_device,
+                                Some(device.name.clone()),
+                                label,
+                            );
+                        }
+                    });
+                if ui.button("Refresh").clicked() {
+                    self.refresh_devices();
+                }
+            });
+        });
+    }
+
+    /// Targets added while streaming are looked up once, when they are added.
+    fn resolve(&self, target: &str) -> Result<SocketAddr> {
+        Ok(Endpoint::parse(target, self.args.prefer_ip.into())?.resolve()?)
+    }
+
+    fn targets_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Targets");
+        let control = self
+            .running
+            .as_ref()
+            .map(|running| running.sender.control());
+        let shown = match &control {
+            Some(control) => control
+                .endpoints()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            None => self.targets.clone(),
+        };
+        for target in shown {
+            ui.horizontal(|ui| {
+                ui.monospace(&target);
+                if ui.small_button("Remove").clicked() {
+                    let removed = match &control {
+                        Some(control) => self
+                            .resolve(&target)
+                            .and_then(|addr| Ok(control.remove_target(addr)?))
+                            .map(drop),
+                        None => Ok(()),
+                    };
+                    match removed {
+                        Ok(()) => self.targets.retain(|kept| kept != &target),
+                        Err(err) => self.message = Some(format!("{err:#}")),
+                    }
+                }
+            });
+        }
+        ui.horizontal(|ui| {
+            let entry = ui.add(
+                egui::TextEdit::singleline(&mut self.new_target)
+                    .hint_text("host:port")
+                    .desired_width(200.0),
+            );
+            let submitted = entry.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if (ui.button("Add").clicked() || submitted) && !self.new_target.is_empty() {
+                let target = self.new_target.trim().to_string();
+                let added = match &control {
+                    Some(control) => self
+                        .resolve(&target)
+                        .and_then(|addr| Ok(control.add_target(addr)?)),
+                    None => Ok(true),
+                };
+                match added {
+                    Ok(_) => {
+                        if !self.targets.contains(&target) {
+                            self.targets.push(target);
+                        }
+                        self.new_target.clear();
+                    }
+                    Err(err) => self.message = Some(format!("{err:#}")),
+                }
+            }
+        });
+    }
+
+    fn controls_ui(&mut self, ui: &mut egui::Ui) {
+        let control = self
+            .running
+            .as_ref()
+            .map(|running| running.sender.control());
+        ui.horizontal(|ui| {
+            let gain = ui.add(
+                Slider::new(&mut self.gain_db, GAIN_RANGE_DB)
+                    .text("gain dB")
+                    .step_by(0.5),
+            );
+            let mute = ui.checkbox(&mut self.muted, "Mute");
+            if gain.changed() || mute.changed() {
+                if let Some(control) = &control {
+                    let (gain_db, muted) = (self.gain_db, self.muted);
+                    control.update_dsp(|dsp| {
+                        dsp.gain_db = gain_db;
+                        dsp.muted = muted;
+                    });
+                }
+            }
+            if ui.checkbox(&mut self.paused, "Pause").changed() {
+                if let Some(control) = &control {
+                    control.set_paused(self.paused);
+                }
+            }
+        });
+    }
+
+    fn meters_ui(&self, ui: &mut egui::Ui) {
+        let Some(running) = &self.running else {
+            return;
+        };
+        if running.levels.is_empty() {
+            ui.label("no audio");
+            return;
+        }
+        let count = running.levels.len();
+        for (channel, level) in running.levels.iter().enumerate() {
+            let name = match (count, channel) {
+                (1, _) => "M".to_string(),
+                (2, 0) => "L".to_string(),
+                (2, _) => "R".to_string(),
+                (_, channel) => (channel + 1).to_string(),
+            };
+            let rms_db = to_db(level.rms);
+            let fill = 1.0 - rms_db / METER_FLOOR_DB;
+            let color = if level.peak >= CLIP_PEAK {
+                Color32::RED
+            } else {
+                Color32::from_rgb(70, 170, 90)
+            };
+            ui.horizontal(|ui| {
+                ui.monospace(format!("{name:>2}"));
+                ui.add(
+                    ProgressBar::new(fill)
+                        .fill(color)
+                        .desired_width(360.0)
+                        .text(format!("{rms_db:.0} dB")),
+                );
+                if level.peak >= CLIP_PEAK {
+                    ui.label(RichText::new("CLIP").color(Color32::RED));
+                }
+            });
+        }
+    }
+
+    fn graphs_ui(&self, ui: &mut egui::Ui) {
+        let Some(running) = &self.running else {
+            return;
+        };
+        let format = running.sender.format();
+        ui.label(format!(
+            "{} | {} Hz, {} ch | {} samples per packet",
+            running.sender.source_name(),
+            format.sample_rate,
+            format.channels,
+            format.samples_per_channel,
+        ));
+        let history = &running.history;
+        let plot = |ui: &mut egui::Ui, id: &str, series: &[(&str, &VecDeque<[f64; 2]>)]| {
+            Plot::new(id)
+                .height(110.0)
+                .include_y(0.0)
+                .allow_drag(false)
+                .allow_zoom(false)
+                .allow_scroll(false)
+                .legend(Legend::default())
+                .show(ui, |plot| {
+                    for (name, points) in series {
+                        let points: Vec<[f64; 2]> = points.iter().copied().collect();
+                        plot.line(Line::new(*name, points));
+                    }
+                });
+        };
+        plot(ui, "throughput", &[("kbit/s", &history.kbps)]);
+        plot(
+            ui,
+            "latency",
+            &[
+                ("buffered ms", &history.buffered_ms),
+                ("rtt ms", &history.rtt_ms),
+            ],
+        );
+        plot(ui, "drops", &[("drops/s", &history.drops)]);
+    }
+
+    fn events_ui(&self, ui: &mut egui::Ui) {
+        let events = self.journal.events();
+        egui::CollapsingHeader::new(format!("Events ({})", events.len())).show(ui, |ui| {
+            let now_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_millis() as u64)
+                .unwrap_or(0);
+            for event in events.iter().rev().take(SHOWN_EVENTS) {
+                let ago = now_ms.saturating_sub(event.time_ms) / 1000;
+                ui.label(format!(
+                    "{ago:>4}s ago  {:?}: {}",
+                    event.kind, event.message
+                ));
+            }
+        });
+    }
+}
+
+fn to_db(level: f32) -> f32 {
+    (20.0 * level.max(f32::MIN_POSITIVE).log10()).max(METER_FLOOR_DB)
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll();
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                self.source_ui(ui);
+                ui.separator();
+                self.targets_ui(ui);
+                ui.separator();
+                self.controls_ui(ui);
+                ui.horizontal(|ui| {
+                    let label = match self.running {
+                        Some(_) => "Stop",
+                        None => "Start",
+                    };
+                    if ui.button(label).clicked() {
+                        let result = match self.running {
+                            Some(_) => self.stop(),
+                            None => self.start(),
+                        };
+                        self.message = result.err().map(|err| format!("{err:#}"));
+                    }
+                    if let Some(message) = &self.message {
+                        ui.label(RichText::new(message).color(Color32::YELLOW));
+                    }
+                });
+                ui.separator();
+                self.meters_ui(ui);
+                self.graphs_ui(ui);
+                self.events_ui(ui);
+            });
+        });
+        ctx.request_repaint_after(REDRAW);
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if let Err(err) = self.stop() {
+            tracing::error!("{err:#}");
+        }
+    }
+}
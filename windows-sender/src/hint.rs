@@ -0,0 +1,210 @@
+use std::io;
+
+use windows_sender::error::{error_chain, CaptureError, CodecError, Error, TransportError};
+
+/// HRESULTs behind the WASAPI failures users actually hit, as they appear in the message.
+const DEVICE_IN_USE: &str = "0x8889000a";
+const UNSUPPORTED_FORMAT: &str = "0x88890008";
+const DEVICE_INVALIDATED: &str = "0x88890004";
+const SERVICE_NOT_RUNNING: &str = "0x88890010";
+const ACCESS_DENIED: &str = "0x80070005";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Category {
+    Device,
+    Format,
+    Network,
+    Firewall,
+    Security,
+}
+
+impl Category {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Category::Device => "audio device",
+            Category::Format => "audio format",
+            Category::Network => "network",
+            Category::Firewall => "firewall",
+            Category::Security => "security",
+        }
+    }
+}
+
+/// What a failure most likely means and what to try, for the failures seen most often.
+pub struct Hint {
+    pub category: Category,
+    pub reason: String,
+    pub fix: String,
+}
+
+impl Hint {
+    fn new(category: Category, reason: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            category,
+            reason: reason.into(),
+            fix: fix.into(),
+        }
+    }
+}
+
+/// The hint for the first error in the chain of `err` that has one.
+pub fn for_error(err: &anyhow::Error) -> Option<Hint> {
+    err.chain().find_map(|cause| {
+        if let Some(err) = cause.downcast_ref::<Error>() {
+            return sender(err);
+        }
+        if let Some(err) = cause.downcast_ref::<CaptureError>() {
+            return capture(err);
+        }
+        cause.downcast_ref::<TransportError>().and_then(transport)
+    })
+}
+
+fn sender(err: &Error) -> Option<Hint> {
+    match err {
+        Error::Capture(err) => capture(err),
+        Error::Transport(err) => transport(err),
+        Error::Codec(CodecError::FractionalFrame {
+            frame_ms,
+            sample_rate,
+        }) => Some(Hint::new(
+            Category::Format,
+            format!("{frame_ms} ms at the device's {sample_rate} Hz is not a whole number of samples"),
+            format!("pick a --frame-ms that gives whole samples at {sample_rate} Hz; 10 and 20 work at 44100 and 48000"),
+        )),
+        Error::NoFeedback { target } => Some(Hint::new(
+            Category::Firewall,
+            format!("nothing came back from {target}: the receiver is not running, or a firewall drops UDP on the way"),
+            format!(
+                "start the receiver on port {}, and allow windows-sender through Windows Defender Firewall (Allow an app through firewall, Private networks)",
+                target.port()
+            ),
+        )),
+        _ => None,
+    }
+}
+
+fn capture(err: &CaptureError) -> Option<Hint> {
+    match err {
+        CaptureError::Wasapi { .. } => wasapi(&error_chain(err).to_ascii_lowercase()),
+        CaptureError::NoInputDevice => Some(Hint::new(
+            Category::Device,
+            "Windows reports no recording device",
+            "plug in a microphone or enable one under Sound settings > Input, or use --source desktop",
+        )),
+        #[cfg(mic_backend)]
+        CaptureError::BuildStream(_) | CaptureError::PlayStream(_) => Some(Hint::new(
+            Category::Device,
+            "the microphone is held exclusively by another app, or access to it is turned off",
+            "close apps using it in exclusive mode, and check Settings > Privacy > Microphone",
+        )),
+        #[cfg(mic_backend)]
+        CaptureError::UnsupportedSampleFormat(format) => Some(Hint::new(
+            Category::Format,
+            format!("the microphone delivers {format:?} samples, which the sender cannot convert"),
+            "set the device to 16 or 24 bit in Sound settings > device properties > Advanced",
+        )),
+        CaptureError::FormatChanged { .. } => Some(Hint::new(
+            Category::Format,
+            "the device format was changed while streaming",
+            "restart the sender so the receiver gets the new format",
+        )),
+        _ => None,
+    }
+}
+
+fn wasapi(chain: &str) -> Option<Hint> {
+    let hint = if chain.contains(DEVICE_IN_USE) {
+        Hint::new(
+            Category::Device,
+            "another app holds the output device in exclusive mode",
+            "close it, or untick \"Allow applications to take exclusive control\" in the device's Advanced properties",
+        )
+    } else if chain.contains(UNSUPPORTED_FORMAT) {
+        Hint::new(
+            Category::Format,
+            "the device cannot be captured in the format the sender asks for",
+            "set the device to 48000 Hz, 16 or 24 bit in its Advanced properties",
+        )
+    } else if chain.contains(DEVICE_INVALIDATED) {
+        Hint::new(
+            Category::Device,
+            "the output device was unplugged or disabled",
+            "reconnect it, or pick another with --desktop-device (see --list-desktop-devices)",
+        )
+    } else if chain.contains(SERVICE_NOT_RUNNING) {
+        Hint::new(
+            Category::Device,
+            "the Windows Audio service is not running",
+            "start it from services.msc (Windows Audio) or run `net start audiosrv` as administrator",
+        )
+    } else if chain.contains(ACCESS_DENIED) {
+        Hint::new(
+            Category::Device,
+            "Windows denied access to the audio device",
+            "turn on microphone access for desktop apps under Settings > Privacy",
+        )
+    } else {
+        return None;
+    };
+    Some(hint)
+}
+
+fn transport(err: &TransportError) -> Option<Hint> {
+    if let TransportError::Resolve { host, .. } = err {
+        return Some(Hint::new(
+            Category::Network,
+            format!("{host} is not known to DNS or mDNS"),
+            "check the spelling and that the phone is on the same network, or pass its IP to --target-ip",
+        ));
+    }
+    if let TransportError::Unauthorized { .. } = err {
+        return Some(Hint::new(
+            Category::Security,
+            "the receiver answered without the access token",
+            "set the same --access-token on the receiver",
+        ));
+    }
+    if let TransportError::Handshake { .. } = err {
+        return Some(Hint::new(
+            Category::Security,
+            "the receiver does not hold the key or identity this sender expects",
+            "check --key / --key-name or the --pin, or pair again with --pair",
+        ));
+    }
+    let (target, kind) = match err {
+        TransportError::Connect { addr, source } => (Some(*addr), source.kind()),
+        TransportError::Bind(source) | TransportError::Send { source, .. } => (None, source.kind()),
+        _ => return None,
+    };
+    let target = target.map_or("the receiver".to_string(), |addr| addr.to_string());
+    let hint = match kind {
+        io::ErrorKind::ConnectionRefused => Hint::new(
+            Category::Network,
+            format!("{target} is reachable but nothing listens on that port"),
+            "start the receiver, and check that its port and transport match --port and --transport",
+        ),
+        io::ErrorKind::TimedOut => Hint::new(
+            Category::Firewall,
+            format!("{target} did not answer; a firewall drops the connection or the address is wrong"),
+            "check the phone's IP in the receiver app, and allow the port in the firewall",
+        ),
+        io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => Hint::new(
+            Category::Network,
+            format!("no route to {target}"),
+            "connect both devices to the same network (guest Wi-Fi often isolates clients), or use USB with adb forward",
+        ),
+        io::ErrorKind::PermissionDenied => Hint::new(
+            Category::Firewall,
+            "the OS refused to send; a firewall or security policy blocks the sender",
+            "allow windows-sender through the firewall for Private networks",
+        ),
+        io::ErrorKind::AddrInUse => Hint::new(
+            Category::Network,
+            "the port is already taken by another program",
+            "stop the other sender or pick another --port",
+        ),
+        _ => return None,
+    };
+    Some(hint)
+}
@@ -0,0 +1,249 @@
+use std::fmt;
+#[cfg(windows)]
+use std::io;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::thread;
+
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::Sender;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::control::{ControlCommand, Router};
+use crate::AudioSource;
+
+const GAIN_STEP_DB: f32 = 2.0;
+const GAIN_RANGE_DB: RangeInclusive<f32> = -40.0..=20.0;
+
+const MOD_ALT: u32 = 0x1;
+const MOD_CONTROL: u32 = 0x2;
+const MOD_SHIFT: u32 = 0x4;
+const MOD_WIN: u32 = 0x8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Action {
+    Mute,
+    GainUp,
+    GainDown,
+    SwitchSource,
+    Pause,
+}
+
+impl Action {
+    const ALL: [Action; 5] = [
+        Action::Mute,
+        Action::GainUp,
+        Action::GainDown,
+        Action::SwitchSource,
+        Action::Pause,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Action::Mute => "mute",
+            Action::GainUp => "gain-up",
+            Action::GainDown => "gain-down",
+            Action::SwitchSource => "switch-source",
+            Action::Pause => "pause",
+        }
+    }
+}
+
+/// `action=keys`, such as `mute=ctrl+alt+m`: a global key combination and what it does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(not(windows), allow(dead_code))]
+pub struct Hotkey {
+    action: Action,
+    /// `MOD_*` flags of `RegisterHotKey`.
+    modifiers: u32,
+    /// Windows virtual-key code.
+    key: u32,
+    keys: String,
+}
+
+impl FromStr for Hotkey {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let (action, keys) = text.split_once('=').ok_or_else(|| {
+            format!("invalid hotkey {text:?}; expected action=keys, such as mute=ctrl+alt+m")
+        })?;
+        let action = Action::ALL
+            .into_iter()
+            .find(|known| known.as_str() == action.trim())
+            .ok_or_else(|| {
+                let known: Vec<_> = Action::ALL.iter().map(|action| action.as_str()).collect();
+                format!(
+                    "unknown hotkey action {action:?}; expected one of {}",
+                    known.join(", ")
+                )
+            })?;
+        let mut modifiers = 0;
+        let mut key = None;
+        for part in keys.split('+').map(|part| part.trim().to_ascii_lowercase()) {
+            let modifier = match part.as_str() {
+                "ctrl" | "control" => MOD_CONTROL,
+                "alt" => MOD_ALT,
+                "shift" => MOD_SHIFT,
+                "win" | "super" => MOD_WIN,
+                _ => 0,
+            };
+            if modifier != 0 {
+                modifiers |= modifier;
+                continue;
+            }
+            if key.is_some() {
+                return Err(format!("hotkey {keys:?} has more than one key"));
+            }
+            key = Some(
+                virtual_key(&part)
+                    .ok_or_else(|| format!("unknown key {part:?} in hotkey {keys:?}"))?,
+            );
+        }
+        let key = key.ok_or_else(|| format!("hotkey {keys:?} has no key besides modifiers"))?;
+        if modifiers == 0 {
+            return Err(format!("hotkey {keys:?} needs a modifier such as ctrl or alt; a bare key would be taken from every app"));
+        }
+        Ok(Self {
+            action,
+            modifiers,
+            key,
+            keys: keys.trim().to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.action.as_str(), self.keys)
+    }
+}
+
+fn virtual_key(name: &str) -> Option<u32> {
+    let code = match name {
+        "up" => 0x26,
+        "down" => 0x28,
+        "left" => 0x25,
+        "right" => 0x27,
+        "space" => 0x20,
+        "pageup" => 0x21,
+        "pagedown" => 0x22,
+        "end" => 0x23,
+        "home" => 0x24,
+        "insert" => 0x2D,
+        "delete" => 0x2E,
+        "pause" => 0x13,
+        _ => {
+            if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse::<u32>().ok()) {
+                return (1..=24).contains(&n).then_some(0x70 + n - 1);
+            }
+            let mut chars = name.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphanumeric() => Some(c.to_ascii_uppercase() as u32),
+                _ => None,
+            };
+        }
+    };
+    Some(code)
+}
+
+/// Carries out `action` on every session, reading the current state first for the
+/// toggles.
+fn trigger(router: &Router, action: Action) {
+    for session in router.names() {
+        let result = router
+            .request(Some(&session), ControlCommand::Status)
+            .and_then(|status| match command(action, &status) {
+                Some(command) => router.request(Some(&session), command).map(|_| ()),
+                None => Ok(()),
+            });
+        match result {
+            Ok(()) => info!("hotkey: {} [{session}]", action.as_str()),
+            Err(err) => warn!("hotkey: {} [{session}] failed: {err}", action.as_str()),
+        }
+    }
+}
+
+fn command(action: Action, status: &Value) -> Option<ControlCommand> {
+    let flag = |name: &str| status[name].as_bool().unwrap_or(false);
+    let gain_db = status["gain_db"].as_f64().unwrap_or(0.0) as f32;
+    Some(match action {
+        Action::Mute if flag("muted") => ControlCommand::Unmute,
+        Action::Mute => ControlCommand::Mute,
+        Action::Pause if flag("paused") => ControlCommand::Resume,
+        Action::Pause => ControlCommand::Pause,
+        Action::GainUp => {
+            ControlCommand::SetGain((gain_db + GAIN_STEP_DB).min(*GAIN_RANGE_DB.end()))
+        }
+        Action::GainDown => {
+            ControlCommand::SetGain((gain_db - GAIN_STEP_DB).max(*GAIN_RANGE_DB.start()))
+        }
+        Action::SwitchSource => ControlCommand::SwitchSource(match status["source"].as_str()? {
+            "desktop" => AudioSource::Mic,
+            _ => AudioSource::Desktop,
+        }),
+    })
+}
+
+/// Registers `hotkeys` system-wide and forwards them to `router` from a thread of their
+/// own. Fails if another app already holds one of the combinations.
+pub fn spawn(hotkeys: &[Hotkey], router: Router) -> Result<()> {
+    let registered = hotkeys.to_vec();
+    let (ready_tx, ready_rx) = crossbeam_channel::bounded(1);
+    thread::Builder::new()
+        .name("hotkeys".to_string())
+        .spawn(move || listen(&registered, &ready_tx, |action| trigger(&router, action)))
+        .context("failed to spawn hotkey thread")?;
+    ready_rx
+        .recv()
+        .unwrap_or_else(|_| Err(anyhow!("hotkey thread exited")))?;
+    for hotkey in hotkeys {
+        info!("Hotkey: {hotkey}");
+    }
+    Ok(())
+}
+
+/// Hotkeys post to the thread that registered them, so this registers them and then waits
+/// for presses on the same thread, reporting on `ready` in between.
+#[cfg(windows)]
+fn listen(hotkeys: &[Hotkey], ready: &Sender<Result<()>>, mut on_press: impl FnMut(Action)) {
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::{RegisterHotKey, MOD_NOREPEAT};
+    use windows_sys::Win32::UI::WindowsAndMessaging::{GetMessageW, MSG, WM_HOTKEY};
+
+    for (id, hotkey) in hotkeys.iter().enumerate() {
+        // SAFETY: a null window ties the hotkey to this thread's message queue.
+        let ok = unsafe {
+            RegisterHotKey(
+                std::ptr::null_mut(),
+                id as i32,
+                hotkey.modifiers | MOD_NOREPEAT,
+                hotkey.key,
+            )
+        };
+        if ok == 0 {
+            let err = io::Error::last_os_error();
+            let _ = ready.send(Err(anyhow!(
+                "failed to register hotkey {hotkey}: {err}; another app may hold it"
+            )));
+            return;
+        }
+    }
+    let _ = ready.send(Ok(()));
+    // SAFETY: all-zero is a valid `MSG`, and it outlives every call.
+    let mut msg: MSG = unsafe { std::mem::zeroed() };
+    while unsafe { GetMessageW(&mut msg, std::ptr::null_mut(), 0, 0) } > 0 {
+        if msg.message == WM_HOTKEY {
+            if let Some(hotkey) = hotkeys.get(msg.wParam) {
+                on_press(hotkey.action);
+            }
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn listen(_hotkeys: &[Hotkey], ready: &Sender<Result<()>>, _on_press: impl FnMut(Action)) {
+    let _ = ready.send(Err(anyhow!(
+        "--hotkey registers global hotkeys with Windows; elsewhere bind keys to `audioctl` in the desktop's shortcut settings"
+    )));
+}
@@ -0,0 +1,150 @@
+use std::net::SocketAddr;
+use std::thread;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::warn;
+
+use crate::control::{self, ControlCommand, ControlReply, Router};
+use crate::AudioSource;
+
+#[derive(Deserialize)]
+struct GainBody {
+    gain_db: f32,
+}
+
+#[derive(Deserialize)]
+struct MarkerBody {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct TargetBody {
+    addr: String,
+}
+
+#[derive(Deserialize)]
+struct SourceBody {
+    source: AudioSource,
+}
+
+/// Serves the JSON control API on `addr` and forwards commands to the sender.
+pub fn spawn_server(addr: SocketAddr, router: Router) -> Result<()> {
+    let server = Server::http(addr)
+        .map_err(|err| anyhow!(err))
+        .with_context(|| format!("failed to bind control HTTP server on {addr}"))?;
+
+    thread::Builder::new()
+        .name("control-http".to_string())
+        .spawn(move || {
+            for request in server.incoming_requests() {
+                if let Err(err) = handle_request(request, &router) {
+                    warn!("control http error: {err}");
+                }
+            }
+        })
+        .context("failed to spawn control HTTP thread")?;
+    Ok(())
+}
+
+fn handle_request(mut request: Request, router: &Router) -> std::io::Result<()> {
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body)?;
+
+    let reply = dispatch(router, request.method(), request.url(), &body);
+    // The SDP goes out as the file players open rather than in JSON.
+    if request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .ends_with("/sdp")
+    {
+        if let Ok(Ok(Value::String(sdp))) = reply {
+            let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/sdp"[..])
+                .expect("static header is valid");
+            return request.respond(Response::from_string(sdp).with_header(content_type));
+        }
+    }
+    let (status, value) = match reply {
+        Ok(reply) => match reply {
+            Ok(Value::Null) => (200, json!({ "ok": true })),
+            Ok(Value::String(message)) => (200, json!({ "ok": true, "message": message })),
+            Ok(value) => (200, value),
+            Err(err) => (409, json!({ "error": err })),
+        },
+        Err((status, err)) => (status, json!({ "error": err })),
+    };
+
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("static header is valid");
+    request.respond(
+        Response::from_string(value.to_string())
+            .with_status_code(status)
+            .with_header(content_type),
+    )
+}
+
+/// `/sessions/<name>/...` addresses one session; bare paths go to the first one.
+fn dispatch(
+    router: &Router,
+    method: &Method,
+    url: &str,
+    body: &str,
+) -> Result<ControlReply, (u16, String)> {
+    let path = url.split('?').next().unwrap_or(url);
+    match (method, path) {
+        (Method::Get, "/sessions") => return Ok(Ok(json!(router.names()))),
+        (Method::Post, "/reload") => return Ok(router.reload_config()),
+        _ => {}
+    }
+    let (session, path) = match path.strip_prefix("/sessions/") {
+        Some(rest) => match rest.split_once('/') {
+            Some((name, rest)) => (Some(name), &path[path.len() - rest.len() - 1..]),
+            None => return Err((404, format!("no route for {method} {path}"))),
+        },
+        None => (None, path),
+    };
+    let command = route(method, path, body)?;
+    Ok(router.request(session, command))
+}
+
+fn route(method: &Method, path: &str, body: &str) -> Result<ControlCommand, (u16, String)> {
+    let command = match (method, path) {
+        (Method::Get, "/status") => ControlCommand::Status,
+        (Method::Get, "/stats") => ControlCommand::Stats,
+        (Method::Get, "/targets") => ControlCommand::Targets,
+        (Method::Get, "/events") => ControlCommand::Events,
+        (Method::Get, "/sdp") => ControlCommand::Sdp,
+        (Method::Post, "/targets") => {
+            let body: TargetBody = parse_body(body)?;
+            ControlCommand::AddTarget(control::parse_addr(&body.addr).map_err(bad_request)?)
+        }
+        (Method::Delete, path) if path.starts_with("/targets/") => ControlCommand::RemoveTarget(
+            control::parse_addr(&path["/targets/".len()..]).map_err(bad_request)?,
+        ),
+        (Method::Post, "/pause") => ControlCommand::Pause,
+        (Method::Post, "/resume") => ControlCommand::Resume,
+        (Method::Post, "/mute") => ControlCommand::Mute,
+        (Method::Post, "/unmute") => ControlCommand::Unmute,
+        (Method::Put, "/gain") => ControlCommand::SetGain(parse_body::<GainBody>(body)?.gain_db),
+        (Method::Put, "/source") => {
+            ControlCommand::SwitchSource(parse_body::<SourceBody>(body)?.source)
+        }
+        (Method::Post, "/markers") => ControlCommand::Mark(parse_body::<MarkerBody>(body)?.name),
+        (Method::Post, "/restart") => ControlCommand::Restart,
+        (Method::Post, "/quit") => ControlCommand::Quit,
+        _ => return Err((404, format!("no route for {method} {path}"))),
+    };
+    Ok(command)
+}
+
+fn parse_body<'a, T: Deserialize<'a>>(body: &'a str) -> Result<T, (u16, String)> {
+    serde_json::from_str(body).map_err(|err| bad_request(format!("invalid JSON body: {err}")))
+}
+
+fn bad_request(err: String) -> (u16, String) {
+    (400, err)
+}
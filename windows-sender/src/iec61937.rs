@@ -0,0 +1,101 @@
+//! IEC 61937: compressed audio (AC-3, DTS, E-AC-3) carried in 16-bit stereo PCM, as
+//! players send it to S/PDIF and HDMI for a receiver to decode. Each burst of coded data
+//! starts with a preamble that PCM audio practically never holds, so the capture can be
+//! told apart from music and sent on untouched.
+
+use std::time::Duration;
+
+/// First two words of every burst preamble, in the left and right samples of one frame.
+const PA: i16 = 0xF872_u16 as i16;
+const PB: i16 = 0x4E1F;
+/// Burst data types that carry no audio: null data, and the pause between bursts.
+const TYPE_NULL: u8 = 0;
+const TYPE_PAUSE: u8 = 3;
+/// How long the stream stays a bitstream after its last burst: longer than the longest
+/// burst period, 6144 frames (128 ms at 48 kHz) of E-AC-3.
+const HOLD: Duration = Duration::from_millis(200);
+
+/// What a burst carries, from the low bits of its `Pc` word.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DataType {
+    Ac3,
+    Dts,
+    Eac3,
+    TrueHd,
+    Other(u8),
+}
+
+impl DataType {
+    fn from_pc(pc: i16) -> Option<Self> {
+        match pc as u8 & 0x1f {
+            TYPE_NULL | TYPE_PAUSE => None,
+            1 => Some(DataType::Ac3),
+            11..=13 | 17 => Some(DataType::Dts),
+            21 => Some(DataType::Eac3),
+            22 => Some(DataType::TrueHd),
+            other => Some(DataType::Other(other)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DataType::Ac3 => "AC-3",
+            DataType::Dts => "DTS",
+            DataType::Eac3 => "E-AC-3",
+            DataType::TrueHd => "Dolby TrueHD",
+            DataType::Other(_) => "IEC 61937",
+        }
+    }
+}
+
+/// Watches interleaved stereo capture for bursts, holding on to the stream as a bitstream
+/// across the gaps between them.
+#[derive(Debug)]
+pub struct Detector {
+    /// Frames without a burst after which the stream counts as PCM again.
+    hold_frames: usize,
+    since_burst: usize,
+    current: Option<DataType>,
+    /// The last frame held `Pa` and `Pb`, so this one starts with `Pc`.
+    expect_pc: bool,
+}
+
+impl Detector {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            hold_frames: (HOLD.as_micros() as u64 * sample_rate as u64 / 1_000_000) as usize,
+            since_burst: 0,
+            current: None,
+            expect_pc: false,
+        }
+    }
+
+    /// Scans the next stereo `samples` and returns what the stream carries now: a
+    /// bitstream of some type, or `None` for PCM.
+    pub fn scan(&mut self, samples: &[i16]) -> Option<DataType> {
+        let frames = samples.len() / 2;
+        let mut last_burst = None;
+        for (index, frame) in samples.chunks_exact(2).enumerate() {
+            if std::mem::take(&mut self.expect_pc) {
+                match DataType::from_pc(frame[0]) {
+                    Some(found) => {
+                        self.current = Some(found);
+                        last_burst = Some(index);
+                    }
+                    // Pauses keep a bitstream going, but do not start one.
+                    None if self.current.is_some() => last_burst = Some(index),
+                    None => {}
+                }
+            }
+            self.expect_pc = frame[0] == PA && frame[1] == PB;
+        }
+        self.since_burst = match last_burst {
+            Some(index) => frames - index,
+            None => self.since_burst.saturating_add(frames),
+        };
+        if self.since_burst > self.hold_frames {
+            self.current = None;
+        }
+        self.current
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct JitterSnapshot {
+    pub buffered_frames: usize,
+    pub target_frames: usize,
+    pub max_frames: usize,
+    pub pushed: u64,
+    pub played: u64,
+    pub missing: u64,
+    pub late: u64,
+    pub overflow_dropped: u64,
+    /// Sequence number of the frame most recently handed out for playout.
+    pub last_played_seq: Option<u32>,
+}
+
+struct JitterState {
+    /// Sequence number, send time if scheduled against a clock shared with the sender, and
+    /// samples of each frame.
+    queue: VecDeque<(u32, Option<u64>, Vec<i16>)>,
+    primed: bool,
+    /// The sender paused the stream: what is queued plays out faded, then silence.
+    paused: bool,
+    frame_us: u64,
+    target_frames: usize,
+    max_frames: usize,
+    pushed: u64,
+    played: u64,
+    missing: u64,
+    late: u64,
+    overflow_dropped: u64,
+    last_played_seq: Option<u32>,
+}
+
+pub struct JitterBuffer {
+    state: Mutex<JitterState>,
+    cond: Condvar,
+}
+
+impl JitterBuffer {
+    pub fn new(initial_target_frames: usize, max_frames: usize) -> Self {
+        Self {
+            state: Mutex::new(JitterState {
+                queue: VecDeque::with_capacity(max_frames),
+                primed: false,
+                paused: false,
+                frame_us: 0,
+                target_frames: clamp_target(initial_target_frames, max_frames),
+                max_frames,
+                pushed: 0,
+                played: 0,
+                missing: 0,
+                late: 0,
+                overflow_dropped: 0,
+                last_played_seq: None,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, seq: u32, send_time_us: Option<u64>, frame: Vec<i16>) {
+        let mut state = self.state.lock().unwrap();
+        state.pushed += 1;
+        state.paused = false;
+        if state.queue.len() >= state.max_frames {
+            state.queue.pop_front();
+            state.overflow_dropped += 1;
+        }
+        state.queue.push_back((seq, send_time_us, frame));
+        if !state.primed && state.queue.len() >= state.target_frames {
+            state.primed = true;
+        }
+        self.cond.notify_all();
+    }
+
+    pub fn pop(&self, timeout: Duration) -> Option<Vec<i16>> {
+        let deadline = Instant::now() + timeout.max(Duration::from_millis(1));
+        let mut state = self.state.lock().unwrap();
+
+        state = self.wait_while(state, deadline, |s| !s.primed);
+        if !state.primed {
+            return None;
+        }
+
+        let low_water_frames = (state.target_frames / 2).max(1);
+        state = self.wait_while(state, deadline, |s| {
+            !s.paused && s.queue.len() <= low_water_frames
+        });
+        state = self.wait_while(state, deadline, |s| s.queue.is_empty());
+        state.take_frame()
+    }
+
+    /// Like `pop` for frames scheduled on a clock shared with the sender: waits until
+    /// `playout_us()` reaches the oldest frame's send time and drops frames already a
+    /// whole frame past it. Returns `None` at the deadline while the next frame is still
+    /// early, so the caller plays silence instead of starting ahead of time. Frames
+    /// without a send time play as soon as the buffer is primed.
+    pub fn pop_scheduled<F>(&self, timeout: Duration, playout_us: F) -> Option<Vec<i16>>
+    where
+        F: Fn() -> Option<u64>,
+    {
+        let deadline = Instant::now() + timeout.max(Duration::from_millis(1));
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let playout_us = playout_us()?;
+            while let Some(&(_, Some(send_time_us), _)) = state.queue.front() {
+                if send_time_us + state.frame_us > playout_us {
+                    break;
+                }
+                state.queue.pop_front();
+                state.late += 1;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let wait = match state.queue.front() {
+                Some(&(_, Some(send_time_us), _)) if send_time_us <= playout_us => {
+                    return state.take_frame()
+                }
+                Some(&(_, Some(send_time_us), _)) => {
+                    Duration::from_micros(send_time_us - playout_us)
+                }
+                Some(&(_, None, _)) if state.primed => return state.take_frame(),
+                _ => remaining,
+            };
+            if remaining.is_zero() {
+                return if state.queue.is_empty() {
+                    state.take_frame()
+                } else {
+                    None
+                };
+            }
+            state = self
+                .cond
+                .wait_timeout(state, wait.min(remaining))
+                .unwrap()
+                .0;
+        }
+    }
+
+    pub fn try_pop(&self) -> Option<Vec<i16>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.primed {
+            return None;
+        }
+        state.take_frame()
+    }
+
+    /// Fades out the queued frames of a stream the sender paused. Once they have played,
+    /// the buffer primes again for the resumed stream, without counting frames missing.
+    pub fn pause(&self) {
+        let mut state = self.state.lock().unwrap();
+        if std::mem::replace(&mut state.paused, true) {
+            return;
+        }
+        let total = state
+            .queue
+            .iter()
+            .map(|(_, _, frame)| frame.len())
+            .sum::<usize>()
+            .max(1) as f32;
+        let mut at = 0;
+        for (_, _, frame) in state.queue.iter_mut() {
+            for sample in frame.iter_mut() {
+                *sample = (*sample as f32 * (1.0 - at as f32 / total)) as i16;
+                at += 1;
+            }
+        }
+        self.cond.notify_all();
+    }
+
+    pub fn set_target_frames(&self, target_frames: usize) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.target_frames = clamp_target(target_frames, state.max_frames);
+        if !state.primed && state.queue.len() >= state.target_frames {
+            state.primed = true;
+        }
+        self.cond.notify_all();
+        state.target_frames
+    }
+
+    pub fn reset(&self, target_frames: usize, max_frames: usize, frame_us: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.clear();
+        state.primed = false;
+        state.paused = false;
+        state.frame_us = frame_us;
+        state.max_frames = max_frames;
+        state.target_frames = clamp_target(target_frames, max_frames);
+        self.cond.notify_all();
+    }
+
+    pub fn snapshot(&self) -> JitterSnapshot {
+        let state = self.state.lock().unwrap();
+        JitterSnapshot {
+            buffered_frames: state.queue.len(),
+            target_frames: state.target_frames,
+            max_frames: state.max_frames,
+            pushed: state.pushed,
+            played: state.played,
+            missing: state.missing,
+            late: state.late,
+            overflow_dropped: state.overflow_dropped,
+            last_played_seq: state.last_played_seq,
+        }
+    }
+
+    fn wait_while<'a, F>(
+        &self,
+        mut state: std::sync::MutexGuard<'a, JitterState>,
+        deadline: Instant,
+        condition: F,
+    ) -> std::sync::MutexGuard<'a, JitterState>
+    where
+        F: Fn(&JitterState) -> bool,
+    {
+        while condition(&state) {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self.cond.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        state
+    }
+}
+
+impl JitterState {
+    fn take_frame(&mut self) -> Option<Vec<i16>> {
+        match self.queue.pop_front() {
+            Some((seq, _, frame)) => {
+                self.played += 1;
+                self.last_played_seq = Some(seq);
+                Some(frame)
+            }
+            None if self.paused => {
+                self.primed = false;
+                None
+            }
+            None => {
+                self.played += 1;
+                self.missing += 1;
+                None
+            }
+        }
+    }
+}
+
+fn clamp_target(target_frames: usize, max_frames: usize) -> usize {
+    target_frames.clamp(2, max_frames.saturating_sub(1).max(2))
+}
@@ -0,0 +1,80 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use serde::Serialize;
+
+/// Events kept before the oldest are forgotten; a night of trouble rarely needs more.
+const CAPACITY: usize = 512;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Start,
+    /// Capture chunks dropped because the send loop fell behind.
+    Drops,
+    SendError,
+    Reconnect,
+    /// The capture stage stopped, stalled or could not be restarted.
+    Capture,
+    /// Capture came back after it stopped, on the same device or another one.
+    Device,
+    /// The stream moved to another source at runtime.
+    Source,
+    Targets,
+    /// A configured threshold was crossed, or cleared again.
+    Alert,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Event {
+    /// Milliseconds since the Unix epoch.
+    pub time_ms: u64,
+    pub kind: EventKind,
+    pub message: String,
+}
+
+/// The most recent notable events of a sender, for diagnosing glitches after the fact.
+#[derive(Debug, Default)]
+pub struct EventJournal {
+    events: Mutex<VecDeque<Event>>,
+    subscribers: Mutex<Vec<Sender<Event>>>,
+}
+
+impl EventJournal {
+    pub fn record(&self, kind: EventKind, message: impl Into<String>) {
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or(0);
+        let event = Event {
+            time_ms,
+            kind,
+            message: message.into(),
+        };
+        self.subscribers.lock().unwrap().retain(|tx| {
+            !matches!(
+                tx.try_send(event.clone()),
+                Err(TrySendError::Disconnected(_))
+            )
+        });
+        let mut events = self.events.lock().unwrap();
+        if events.len() == CAPACITY {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Events recorded from now on; a subscriber that falls behind misses the overflow.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (tx, rx) = crossbeam_channel::bounded(CAPACITY);
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Oldest first.
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+}
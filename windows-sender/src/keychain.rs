@@ -0,0 +1,59 @@
+#[cfg(not(feature = "keychain"))]
+use anyhow::bail;
+#[cfg(feature = "keychain")]
+use anyhow::Context;
+use anyhow::Result;
+use windows_sender::crypto::Key;
+
+use crate::Args;
+
+/// Keychain entries live under this service, one per `--key-name`.
+#[cfg(feature = "keychain")]
+const SERVICE: &str = "audio-link";
+
+/// The key a session seals with: the one stored under `--key-name`, else `--key`.
+pub fn session_key(args: &Args) -> Result<Option<Key>> {
+    match &args.key_name {
+        Some(name) => load(name).map(Some),
+        None => Ok(args.key.clone()),
+    }
+}
+
+#[cfg(feature = "keychain")]
+fn load(name: &str) -> Result<Key> {
+    let secret = keyring::Entry::new(SERVICE, name)
+        .and_then(|entry| entry.get_password())
+        .with_context(|| format!("failed to read key {name} from the OS keychain"))?;
+    secret
+        .parse()
+        .with_context(|| format!("keychain entry {name} does not hold a key"))
+}
+
+#[cfg(not(feature = "keychain"))]
+fn load(_name: &str) -> Result<Key> {
+    bail!("--key-name needs a build with the `keychain` feature")
+}
+
+/// Saves `key`, or a fresh one, under `name` and prints what the receiver needs.
+#[cfg(feature = "keychain")]
+pub fn store(name: &str, key: Option<Key>) -> Result<()> {
+    let generated = key.is_none();
+    let key = match key {
+        Some(key) => key,
+        None => Key::generate()?,
+    };
+    keyring::Entry::new(SERVICE, name)
+        .and_then(|entry| entry.set_password(&key.to_hex()))
+        .with_context(|| format!("failed to save key {name} in the OS keychain"))?;
+    println!("Saved key {name}; send with --key-name {name}");
+    if generated {
+        println!("Key for the receiver: {}", key.to_hex());
+    }
+    println!("Key fingerprint: {}", key.fingerprint());
+    Ok(())
+}
+
+#[cfg(not(feature = "keychain"))]
+pub fn store(_name: &str, _key: Option<Key>) -> Result<()> {
+    bail!("store-key needs a build with the `keychain` feature")
+}
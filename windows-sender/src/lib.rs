@@ -0,0 +1,72 @@
+#[cfg(not(target_arch = "wasm32"))]
+pub mod aes67;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod archive;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bandwidth;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bluetooth;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod capture;
+pub mod clock;
+pub mod crypto;
+pub mod decoder;
+pub mod dsp;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod encode;
+pub mod error;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod feedback;
+#[cfg(all(feature = "ffi", not(target_arch = "wasm32")))]
+pub mod ffi;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod iec61937;
+pub mod jitter;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod journal;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ndi;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pairing;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod plugin;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod probe;
+pub mod protocol;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ptp;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod receiver;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod resolve;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sched;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod scream;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sender;
+pub mod stats;
+#[cfg(not(target_arch = "wasm32"))]
+mod supervisor;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tee;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod timer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod transport;
+#[cfg(uring_backend)]
+mod uring;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod vban;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+
+pub use decoder::StreamDecoder;
+pub use error::{
+    BluetoothError, CaptureError, CodecError, CryptoError, Error, NdiError, PluginError,
+    ProtocolError, TransportError,
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use receiver::{AudioReceiver, ReceiverConfig};
+#[cfg(not(target_arch = "wasm32"))]
+pub use sender::{AudioSender, SenderConfig};
@@ -0,0 +1,223 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// The file of `--log-file`, for the stats lines to go next to the log events.
+static SHARED: OnceLock<RotatingFile> = OnceLock::new();
+
+/// When `--log-file` moves on to a fresh file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Rotation {
+    /// Once the file reaches this many bytes.
+    Size(u64),
+    /// At the start of every hour, UTC.
+    Hourly,
+    /// At midnight UTC.
+    Daily,
+}
+
+impl Rotation {
+    /// The hour or day `time` falls in, or 0 when rotating by size.
+    fn period(self, time: SystemTime) -> u64 {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        match self {
+            Rotation::Size(_) => 0,
+            Rotation::Hourly => secs / 3600,
+            Rotation::Daily => secs / 86_400,
+        }
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let text = text.trim().to_ascii_lowercase();
+        match text.as_str() {
+            "hourly" => return Ok(Rotation::Hourly),
+            "daily" => return Ok(Rotation::Daily),
+            _ => {}
+        }
+        let split = text
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len());
+        let (number, unit) = text.split_at(split);
+        let scale = match unit.trim() {
+            "" | "b" => 1,
+            "kb" | "k" => 1 << 10,
+            "mb" | "m" => 1 << 20,
+            "gb" | "g" => 1 << 30,
+            _ => 0,
+        };
+        match number.parse::<u64>() {
+            Ok(number) if number > 0 && scale > 0 => Ok(Rotation::Size(number * scale)),
+            _ => Err(format!(
+                "invalid log rotation {text:?}; expected a size such as 10MB, hourly or daily"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Rotation {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        text.parse()
+    }
+}
+
+impl fmt::Display for Rotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rotation::Size(bytes) if bytes % (1 << 20) == 0 => write!(f, "{}MB", bytes >> 20),
+            Rotation::Size(bytes) if bytes % (1 << 10) == 0 => write!(f, "{}KB", bytes >> 10),
+            Rotation::Size(bytes) => write!(f, "{bytes}B"),
+            Rotation::Hourly => f.write_str("hourly"),
+            Rotation::Daily => f.write_str("daily"),
+        }
+    }
+}
+
+/// A log file that is renamed to `<file>.1` when `Rotation` says so, shifting older ones
+/// up to `<file>.<keep>` and deleting the one past it.
+#[derive(Clone)]
+pub struct RotatingFile {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    path: PathBuf,
+    rotation: Rotation,
+    keep: usize,
+    file: File,
+    size: u64,
+    period: u64,
+}
+
+impl RotatingFile {
+    /// Opens `path` for appending and makes it the file stats lines are copied to.
+    pub fn open(path: &Path, rotation: Rotation, keep: usize) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let file =
+            open(path).with_context(|| format!("failed to open log file {}", path.display()))?;
+        let metadata = file
+            .metadata()
+            .with_context(|| format!("failed to open log file {}", path.display()))?;
+        let modified = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+        let log = Self {
+            inner: Arc::new(Mutex::new(Inner {
+                path: path.to_path_buf(),
+                rotation,
+                keep: keep.max(1),
+                file,
+                size: metadata.len(),
+                period: rotation.period(modified),
+            })),
+        };
+        let _ = SHARED.set(log.clone());
+        Ok(log)
+    }
+
+    /// The file opened by `open`, if there is one.
+    pub fn shared() -> Option<&'static RotatingFile> {
+        SHARED.get()
+    }
+
+    pub fn write_line(&self, line: &str) {
+        self.write(format!("{line}\n").as_bytes());
+    }
+
+    /// Write failures are dropped: the logger has nowhere left to report them.
+    fn write(&self, bytes: &[u8]) {
+        let mut inner = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+        let _ = inner.write(bytes);
+    }
+}
+
+impl Inner {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let due = match self.rotation {
+            Rotation::Size(max) => self.size > 0 && self.size + bytes.len() as u64 > max,
+            rotation => rotation.period(SystemTime::now()) != self.period,
+        };
+        if due {
+            self.rotate()?;
+        }
+        self.file.write_all(bytes)?;
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let _ = fs::remove_file(numbered(&self.path, self.keep));
+        for n in (1..self.keep).rev() {
+            let _ = fs::rename(numbered(&self.path, n), numbered(&self.path, n + 1));
+        }
+        fs::rename(&self.path, numbered(&self.path, 1))?;
+        self.file = open(&self.path)?;
+        self.size = 0;
+        self.period = self.rotation.period(SystemTime::now());
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// `path` with `.n` appended, such as `sender.log.1`.
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+/// Collects one formatted event and writes it in one piece when dropped, so rotation
+/// never splits an event across two files.
+pub struct LogFileEvent {
+    file: RotatingFile,
+    bytes: Vec<u8>,
+}
+
+impl io::Write for LogFileEvent {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogFileEvent {
+    fn drop(&mut self) {
+        self.file.write(&self.bytes);
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = LogFileEvent;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogFileEvent {
+            file: self.clone(),
+            bytes: Vec::new(),
+        }
+    }
+}
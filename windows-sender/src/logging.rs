@@ -0,0 +1,194 @@
+#[cfg(feature = "tui")]
+use std::collections::VecDeque;
+#[cfg(feature = "tui")]
+use std::io;
+#[cfg(feature = "tui")]
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+use crate::console::{self, LogWriter};
+use crate::logfile::RotatingFile;
+#[cfg(feature = "otel")]
+use crate::otel::Telemetry;
+use crate::Args;
+
+#[cfg(feature = "tui")]
+const LOG_BUFFER_LINES: usize = 200;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Keeps the OTLP exporters alive; dropping it flushes them.
+pub struct LogGuard {
+    /// Log lines captured for the dashboard instead of going to stderr.
+    #[cfg(feature = "tui")]
+    pub logs: Option<LogBuffer>,
+    #[cfg(feature = "otel")]
+    _telemetry: Option<Telemetry>,
+}
+
+/// Installs the global subscriber that writes log events to stderr, above the stats status
+/// row (or to the dashboard with `--tui`), and also to `--log-file` if given, and with
+/// `--otlp-endpoint` exports spans and stream metrics to an OpenTelemetry collector.
+///
+/// `--log-level` takes `RUST_LOG`-style directives (`debug`, `windows_sender=trace,info`);
+/// without it `RUST_LOG` is used, then `info`.
+pub fn init(args: &Args) -> Result<LogGuard> {
+    console::init(args.color);
+    let filter = match args.log_level.as_deref() {
+        Some(level) => {
+            EnvFilter::try_new(level).with_context(|| format!("invalid --log-level {level:?}"))?
+        }
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+
+    #[cfg(feature = "tui")]
+    let logs = args.tui.then(LogBuffer::default);
+    #[cfg(feature = "tui")]
+    let output = match &logs {
+        Some(logs) => output_layer(args.log_format, logs.clone(), false),
+        None => output_layer(args.log_format, LogWriter, console::stderr_color()),
+    };
+    #[cfg(not(feature = "tui"))]
+    let output = output_layer(args.log_format, LogWriter, console::stderr_color());
+
+    let file_output = args
+        .log_file
+        .as_deref()
+        .map(|path| RotatingFile::open(path, args.log_rotate, args.log_keep))
+        .transpose()?
+        .map(|file| output_layer(args.log_format, file, false));
+
+    #[cfg(feature = "otel")]
+    let telemetry = args
+        .otlp_endpoint
+        .as_deref()
+        .map(Telemetry::init)
+        .transpose()?;
+    #[cfg(feature = "otel")]
+    let otel = telemetry
+        .as_ref()
+        .map(|telemetry| tracing_opentelemetry::layer().with_tracer(telemetry.tracer()));
+    #[cfg(not(feature = "otel"))]
+    let otel: Option<tracing_subscriber::layer::Identity> = match &args.otlp_endpoint {
+        Some(endpoint) => {
+            anyhow::bail!("--otlp-endpoint {endpoint} needs a build with the `otel` feature")
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .with(file_output)
+        .with(otel)
+        .try_init()
+        .map_err(|err| anyhow!("failed to install logger: {err}"))?;
+    Ok(LogGuard {
+        #[cfg(feature = "tui")]
+        logs,
+        #[cfg(feature = "otel")]
+        _telemetry: telemetry,
+    })
+}
+
+fn output_layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => fmt::layer()
+            .with_target(false)
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
+/// The most recent log lines, kept in memory while the dashboard owns the terminal.
+#[cfg(feature = "tui")]
+#[derive(Clone, Default)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+#[cfg(feature = "tui")]
+impl LogBuffer {
+    /// Up to `count` lines, oldest first.
+    pub fn tail(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap_or_else(|err| err.into_inner());
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+
+    fn push(&self, text: &str) {
+        let mut lines = self.lines.lock().unwrap_or_else(|err| err.into_inner());
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            if lines.len() == LOG_BUFFER_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+        }
+    }
+}
+
+/// Collects one formatted event and appends it to the buffer when dropped.
+#[cfg(feature = "tui")]
+pub struct LogEvent {
+    buffer: LogBuffer,
+    bytes: Vec<u8>,
+}
+
+#[cfg(feature = "tui")]
+impl io::Write for LogEvent {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tui")]
+impl Drop for LogEvent {
+    fn drop(&mut self) {
+        self.buffer.push(&String::from_utf8_lossy(&self.bytes));
+    }
+}
+
+#[cfg(feature = "tui")]
+impl<'a> MakeWriter<'a> for LogBuffer {
+    type Writer = LogEvent;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogEvent {
+            buffer: self.clone(),
+            bytes: Vec::new(),
+        }
+    }
+}
@@ -1,789 +1,943 @@
-use std::collections::VecDeque;
-use std::io::Write;
-use std::net::{SocketAddr, TcpStream, UdpSocket};
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::mpsc as std_mpsc;
-use std::sync::Arc;
+mod adb;
+mod alert;
+mod autoexit;
+mod autostart;
+mod config;
+mod console;
+mod control;
+#[cfg(unix)]
+mod daemon;
+mod diagnose;
+mod exit;
+#[cfg(feature = "gui")]
+mod gui;
+mod hint;
+mod hotkey;
+#[cfg(feature = "http")]
+mod http;
+mod keychain;
+mod logfile;
+mod logging;
+mod meter;
+mod notify;
+#[cfg(feature = "otel")]
+mod otel;
+mod pair;
+mod qr;
+mod resume;
+mod sdp;
+#[cfg(windows)]
+mod service;
+mod session;
+mod spectrum;
+mod stats_log;
+#[cfg(feature = "tui")]
+mod tui;
+mod webhook;
+#[cfg(feature = "tui")]
+mod wizard;
+
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::thread;
-use std::time::Duration;
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime};
 
 use anyhow::{bail, Context, Result};
-use clap::{Parser, ValueEnum};
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{SampleFormat, StreamConfig};
-use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
-use wasapi::{DeviceEnumerator, Direction as WasapiDirection, SampleType, StreamMode, WaveFormat};
-
-const MAGIC: [u8; 4] = *b"AUD0";
-const VERSION: u8 = 1;
-const CODEC_PCM16: u8 = 0;
-const HEADER_SIZE: usize = 28;
-
-const DESKTOP_SAMPLE_RATE: u32 = 48_000;
-const DESKTOP_CHANNELS: usize = 2;
-
-#[derive(Default)]
-struct SenderStats {
-    captured_chunks: AtomicU64,
-    captured_samples: AtomicU64,
-    captured_nonzero_samples: AtomicU64,
-    captured_abs_sum: AtomicU64,
-    capture_drops: AtomicU64,
-    capture_queue_us_sum: AtomicU64,
-    capture_queue_count: AtomicU64,
-    capture_to_send_us_sum: AtomicU64,
-    capture_to_send_count: AtomicU64,
-    packet_build_us_sum: AtomicU64,
-    packet_build_count: AtomicU64,
-    socket_send_us_sum: AtomicU64,
-    socket_send_count: AtomicU64,
-    sent_packets: AtomicU64,
-    sent_bytes: AtomicU64,
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use windows_sender::aes67::Encoding;
+use windows_sender::bluetooth;
+use windows_sender::capture::{self, QueueOverflow, SourceKind, VirtualCable};
+use windows_sender::crypto::noise::KeyPin;
+use windows_sender::crypto::{Cipher, Key};
+use windows_sender::dsp::EqBand;
+use windows_sender::ptp::PtpSource;
+use windows_sender::resolve::IpPreference;
+use windows_sender::tee::TeePoint;
+use windows_sender::transport::TransportKind;
+
+use crate::console::{ColorWhen, Tint};
+use crate::hotkey::Hotkey;
+use crate::logfile::Rotation;
+use crate::logging::LogFormat;
+use crate::notify::NotifyWhen;
+use crate::stats_log::{StatsFormat, StatsGroup};
+
+/// Which virtual devices `--route-through-virtual` may capture.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum VirtualRoute {
+    /// Any installed one, VB-Cable first, then VoiceMeeter, then Steam.
+    Any,
+    VbCable,
+    Voicemeeter,
+    Steam,
 }
 
-#[derive(Copy, Clone, Debug, ValueEnum)]
+impl VirtualRoute {
+    fn matches(self, cable: VirtualCable) -> bool {
+        match self {
+            VirtualRoute::Any => true,
+            VirtualRoute::VbCable => cable == VirtualCable::VbCable,
+            VirtualRoute::Voicemeeter => cable == VirtualCable::VoiceMeeter,
+            VirtualRoute::Steam => cable == VirtualCable::SteamStreaming,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 enum AudioSource {
     Desktop,
     Mic,
+    /// A 440 Hz test tone.
+    Tone,
+    /// The WAV file given with `--source-file`, looped.
+    File,
 }
 
-impl AudioSource {
-    fn as_str(self) -> &'static str {
-        match self {
-            AudioSource::Desktop => "desktop",
-            AudioSource::Mic => "mic",
+impl From<AudioSource> for SourceKind {
+    fn from(source: AudioSource) -> Self {
+        match source {
+            AudioSource::Desktop => SourceKind::Desktop,
+            AudioSource::Mic => SourceKind::Mic,
+            AudioSource::Tone => SourceKind::Tone,
+            AudioSource::File => SourceKind::File,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+enum PayloadCipher {
+    #[value(name = "aes-256-gcm")]
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    #[value(name = "chacha20-poly1305")]
+    #[serde(rename = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+    #[value(name = "hmac-sha256")]
+    #[serde(rename = "hmac-sha256")]
+    HmacSha256,
+}
+
+impl From<PayloadCipher> for Cipher {
+    fn from(cipher: PayloadCipher) -> Self {
+        match cipher {
+            PayloadCipher::Aes256Gcm => Cipher::Aes256Gcm,
+            PayloadCipher::ChaCha20Poly1305 => Cipher::ChaCha20Poly1305,
+            PayloadCipher::HmacSha256 => Cipher::HmacSha256,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum LateAudio {
+    Never,
+    DropOldest,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum QueueFull {
+    DropNewest,
+    OverwriteOldest,
+    CollapseToLatest,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum TeeAt {
+    PreCodec,
+    PostCodec,
+}
+
+impl From<TeeAt> for TeePoint {
+    fn from(point: TeeAt) -> Self {
+        match point {
+            TeeAt::PreCodec => TeePoint::PreCodec,
+            TeeAt::PostCodec => TeePoint::PostCodec,
         }
     }
 }
 
-#[derive(Copy, Clone, Debug, ValueEnum)]
+impl From<QueueFull> for QueueOverflow {
+    fn from(policy: QueueFull) -> Self {
+        match policy {
+            QueueFull::DropNewest => QueueOverflow::DropNewest,
+            QueueFull::OverwriteOldest => QueueOverflow::OverwriteOldest,
+            QueueFull::CollapseToLatest => QueueOverflow::CollapseToLatest,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 enum Transport {
     Udp,
     Tcp,
+    /// VBAN, for VBAN Receptor, Voicemeeter and other VBAN receivers (port 6980).
+    Vban,
+    /// Scream, for Scream receivers; its multicast group is 239.255.77.77, port 4010.
+    Scream,
+    /// AES67 RTP, for AES67 devices and Dante in AES67 mode; usually to a multicast group
+    /// in 239.69.0.0/16, port 5004.
+    Aes67,
+    /// RFCOMM to a paired phone running the Android receiver, chosen with `--bt-device`.
+    Bluetooth,
+    /// TCP to the Android receiver over USB, through port forwards adb sets up.
+    Adb,
 }
 
-impl Transport {
-    fn as_str(self) -> &'static str {
-        match self {
-            Transport::Udp => "udp",
-            Transport::Tcp => "tcp",
+impl From<Transport> for TransportKind {
+    fn from(transport: Transport) -> Self {
+        match transport {
+            Transport::Udp => TransportKind::Udp,
+            Transport::Tcp => TransportKind::Tcp,
+            Transport::Vban => TransportKind::Vban,
+            Transport::Scream => TransportKind::Scream,
+            Transport::Aes67 => TransportKind::Aes67,
+            Transport::Bluetooth => TransportKind::Bluetooth,
+            // The receiver listens on TCP; adb carries it.
+            Transport::Adb => TransportKind::Tcp,
         }
     }
 }
 
-enum CaptureGuard {
-    Mic(cpal::Stream),
-    Desktop(thread::JoinHandle<()>),
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Aes67Encoding {
+    L24,
+    L16,
 }
 
-impl CaptureGuard {
-    fn keepalive_ref(&self) {
-        match self {
-            CaptureGuard::Mic(stream) => {
-                let _ = stream;
-            }
-            CaptureGuard::Desktop(handle) => {
-                let _ = handle;
-            }
+impl From<Aes67Encoding> for Encoding {
+    fn from(encoding: Aes67Encoding) -> Self {
+        match encoding {
+            Aes67Encoding::L24 => Encoding::L24,
+            Aes67Encoding::L16 => Encoding::L16,
         }
     }
 }
 
-struct CaptureSetup {
-    sample_rate: u32,
-    channels: usize,
-    source_name: String,
-    guard: CaptureGuard,
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum PreferIp {
+    V4,
+    V6,
 }
 
-struct CaptureChunk {
-    samples: Vec<i16>,
-    captured_at: Instant,
+impl From<PreferIp> for IpPreference {
+    fn from(prefer: PreferIp) -> Self {
+        match prefer {
+            PreferIp::V4 => IpPreference::V4,
+            PreferIp::V6 => IpPreference::V6,
+        }
+    }
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Clone, Debug)]
 #[command(
     author,
     version,
     about = "Low-latency UDP audio sender (Windows -> Android)"
 )]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML file with default settings; flags given on the command line take precedence.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Start with the settings recorded on the last clean start, as if they were given
+    /// with `--config`; flags on the command line still take precedence.
+    #[arg(long, conflicts_with = "config")]
+    resume: bool,
+    /// Receiver IP address or host name; `.local` names are looked up by mDNS too, and
+    /// names are looked up again whenever the sender reconnects.
     #[arg(long)]
     target_ip: Option<String>,
+    /// Address family used when a host name resolves to both.
+    #[arg(long, value_enum, default_value_t = PreferIp::V4)]
+    prefer_ip: PreferIp,
     #[arg(long, default_value_t = 50000)]
     port: u16,
-    #[arg(long, default_value_t = 5)]
-    frame_ms: u32,
+    /// Audio per packet, 1 to 20 ms; fractions such as 2.5 work when they come to a whole
+    /// number of samples at the capture rate.
+    #[arg(long, default_value_t = 5.0)]
+    frame_ms: f32,
     #[arg(long, value_enum, default_value_t = AudioSource::Desktop)]
     source: AudioSource,
+    /// Stream synthetic audio instead of capturing: 8 channels at 48 kHz in 1 ms periods,
+    /// to soak-test the queue, packetizer and transport for hours without devices. Pair
+    /// with `--frame-ms 1` for the highest packet rate.
+    #[arg(long, default_value_t = false, conflicts_with = "source")]
+    stress: bool,
     #[arg(long)]
     desktop_device: Option<String>,
+    /// Capture the virtual device (VB-Cable, VoiceMeeter, Steam Streaming Speakers) the
+    /// apps to stream are set to play into, rather than `--desktop-device`; any installed
+    /// one without a value.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "KIND",
+        num_args = 0..=1,
+        default_missing_value = "any",
+        conflicts_with = "desktop_device"
+    )]
+    route_through_virtual: Option<VirtualRoute>,
+    /// WAV file for `--source file`, or for switching to it at runtime.
+    #[arg(long)]
+    source_file: Option<PathBuf>,
     #[arg(long, default_value_t = false)]
     list_desktop_devices: bool,
+    /// Measure the throughput the link to the target sustains, then exit.
+    #[arg(long, default_value_t = false)]
+    probe_bandwidth: bool,
+    /// Find the largest packet that reaches the target unfragmented, then exit.
+    #[arg(long, default_value_t = false)]
+    probe_mtu: bool,
     #[arg(long, value_enum, default_value_t = Transport::Udp)]
     transport: Transport,
+    /// Stream name in the VBAN packets, which VBAN receivers pick the stream by (default
+    /// Stream1).
+    #[arg(long, value_name = "NAME")]
+    vban_stream: Option<String>,
+    /// Session name AES67 devices list the stream by (default windows-sender).
+    #[arg(long, value_name = "NAME")]
+    aes67_session: Option<String>,
+    /// Sample encoding of the AES67 stream (default l24).
+    #[arg(long, value_enum)]
+    aes67_encoding: Option<Aes67Encoding>,
+    /// Write the SDP of the AES67 stream to this file, for VLC, ffplay or GStreamer to join
+    /// it; the control HTTP API serves it at `/sdp` as well.
+    #[arg(long, value_name = "FILE")]
+    write_sdp: Option<PathBuf>,
+    /// Address (00:1A:7D:DA:71:13) or name of the paired phone `--transport bluetooth`
+    /// streams to.
+    #[arg(long, value_name = "DEVICE")]
+    bt_device: Option<String>,
+    /// RFCOMM channel of the receiver on the phone; by default Windows looks it up. Needed
+    /// on Linux.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=30))]
+    bt_channel: Option<u8>,
+    /// List the Bluetooth devices this machine knows, then exit.
+    #[arg(long, default_value_t = false)]
+    list_bt_devices: bool,
+    /// Search for a phone by address or name and pair with it, then exit.
+    #[arg(long, value_name = "DEVICE")]
+    bt_pair: Option<String>,
+    /// Serial of the phone `--transport adb` goes to, as `adb devices` lists it; needed
+    /// when several are attached.
+    #[arg(long, value_name = "SERIAL")]
+    adb_serial: Option<String>,
+    /// Skip asking UDP receivers for a report before streaming, which warns when nothing
+    /// answers within 2 s.
+    #[arg(long, default_value_t = false)]
+    no_preflight: bool,
+    /// Encrypt every payload with this pre-shared 256-bit key (64 hex digits or base64).
+    #[arg(long)]
+    key: Option<Key>,
+    /// Encrypt with the key saved in the OS keychain under this name (see `store-key`)
+    /// instead of passing it on the command line.
+    #[arg(long, conflicts_with = "key")]
+    key_name: Option<String>,
+    /// Cipher for `--key` and `--noise`; ChaCha20-Poly1305 is faster on phones without AES
+    /// instructions, and hmac-sha256 only authenticates, leaving the audio readable.
+    #[arg(long, value_enum, default_value_t = PayloadCipher::Aes256Gcm)]
+    cipher: PayloadCipher,
+    /// Move to a fresh key derived from `--key` (or the Noise key) every this many
+    /// minutes; receivers follow the epoch in each packet.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    rotate_key_mins: Option<u64>,
+    /// Pair with each receiver by Noise handshake instead of `--key`; the directory holds
+    /// this sender's identity and the receiver keys pinned on first pairing.
+    #[arg(long, conflicts_with = "key")]
+    noise: Option<PathBuf>,
+    /// Trust only receivers whose Noise public key has this fingerprint (`sha256:` and 64
+    /// hex digits, repeatable), instead of whichever answers first.
+    #[arg(long = "pin")]
+    pins: Vec<KeyPin>,
+    /// Only hear receivers that present this token; over TCP a receiver that does not is
+    /// refused before any audio goes out.
+    #[arg(long)]
+    access_token: Option<String>,
+    /// Let the receiver set the gain and mute, as the phone's volume buttons do with the
+    /// Android receiver, so quiet listening is attenuated before encoding.
+    #[arg(long, default_value_t = false)]
+    remote_volume: bool,
+    /// Stamp packets with the PTP domain clock: `system` when the OS PTP client keeps the
+    /// system clock on the domain, or a PTP hardware clock such as /dev/ptp0 (Linux).
+    #[arg(long, value_name = "SOURCE")]
+    ptp: Option<PtpSource>,
+    /// Send packets on an even frame-ms cadence derived from the sample count instead of in
+    /// bursts as capture periods arrive; adds up to one capture period of latency.
+    #[arg(long, default_value_t = false)]
+    pace: bool,
+    /// Tell the receiver when the stream is paused, so it fades out instead of running
+    /// dry; receivers older than the notice count it as a parse error.
+    #[arg(long, default_value_t = false)]
+    pause_notice: bool,
+    /// Multi-room playback: every receiver plays each frame this long after it was sent,
+    /// on the sender clock, so speakers in different rooms stay in step.
+    #[arg(long, value_name = "MS")]
+    playout_delay_ms: Option<u32>,
+    /// Hold the stream until this time, `+5s`/`+500ms`/`+2m` from now or Unix seconds, and
+    /// announce it to the receivers meanwhile.
+    #[arg(long, value_name = "TIME", value_parser = session::parse_start_at)]
+    start_at: Option<SystemTime>,
+    /// What to do with audio older than `--max-audio-age-ms` when sending falls behind:
+    /// send it anyway, or drop the oldest and flag the gap to the receivers.
+    #[arg(long, value_enum, default_value_t = LateAudio::Never)]
+    late_audio: LateAudio,
+    #[arg(long, value_name = "MS", default_value_t = 100)]
+    max_audio_age_ms: u32,
+    /// What the capture drops when the send loop falls behind and its queue fills: the new
+    /// audio, the oldest queued chunk, or everything queued.
+    #[arg(long, value_enum, default_value_t = QueueFull::DropNewest)]
+    queue_overflow: QueueFull,
+    /// Send UDP through io_uring, one system call per batch of packets (Linux, needs the
+    /// `io-uring` feature).
+    #[arg(long, default_value_t = false)]
+    io_uring: bool,
+    /// Let the kernel split each batch of UDP packets from a few large buffers
+    /// (UDP_SEGMENT, Linux 4.18+), saving CPU with many targets or small frames.
+    #[arg(long, default_value_t = false)]
+    gso: bool,
+    /// Run the capture and send threads at real-time priority: TIME_CRITICAL on Windows,
+    /// SCHED_FIFO on Unix where permitted (CAP_SYS_NICE or an rtprio limit).
+    #[arg(long, default_value_t = false)]
+    realtime: bool,
+    /// Keep the capture and send threads on these CPU cores, e.g. `2` or `2,3`.
+    #[arg(long, value_name = "CORES", value_delimiter = ',')]
+    affinity: Vec<usize>,
+    /// Kernel send buffer of each socket to a receiver (SO_SNDBUF), in bytes. Smaller
+    /// buffers drop audio during a brief network stall instead of queueing it up as delay.
+    #[arg(long, value_name = "BYTES")]
+    so_sndbuf: Option<u32>,
+    /// Write TCP without blocking: once a receiver falls this far behind, drop the oldest
+    /// audio queued for it and flag the gap, instead of stalling every target.
+    #[arg(long, value_name = "MS")]
+    tcp_backlog_ms: Option<u32>,
+    /// Cap the audio held between capture and the sockets (capture queue, frame assembly
+    /// and the TCP backlog); past it the oldest is dropped and the gap flagged.
+    #[arg(long, value_name = "MS")]
+    max_buffered_ms: Option<u32>,
+    /// Cap what is sent to each receiver, headers included, e.g. on a metered connection:
+    /// PCM goes out mono or at a lower sample rate when it would not fit, and frames over
+    /// the cap are dropped.
+    #[arg(long, value_name = "KBPS")]
+    max_kbps: Option<u32>,
+    /// Also write the audio being sent to this `.wav`, `.flac` or `.ogg` (FLAC) file.
+    #[arg(long, value_name = "FILE")]
+    tee: Option<PathBuf>,
+    /// Tee every frame before encoding, or only what went out, with silence for dropped frames.
+    #[arg(long, value_enum, default_value_t = TeeAt::PreCodec)]
+    tee_point: TeeAt,
+    /// Also publish the audio being sent as an NDI source with this name, for OBS, vMix and
+    /// other NDI receivers on the LAN. Needs the `ndi` feature and the NDI runtime.
+    #[arg(long, value_name = "NAME")]
+    ndi: Option<String>,
+    /// Also archive the stream to this `.opus` file (Ogg Opus), from the Opus codec plugin;
+    /// without `--target-ip` only the file is written.
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Send AC-3, DTS and E-AC-3 bitstreams (IEC 61937) found in the capture untouched, for
+    /// a receiver that hands them to S/PDIF or HDMI, instead of processing them as PCM.
+    #[arg(long, default_value_t = false)]
+    passthrough: bool,
+    /// Encode frames with the codec plugin on this many threads, each with its own codec
+    /// instance, for codecs too slow for the send thread at small frames.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    encode_workers: usize,
+    /// Print a QR code that sets up the Android receiver for this run: a fresh key (unless
+    /// `--key` is given), the port, the transport and the access token.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["noise", "tui"])]
+    pair: bool,
+    /// Additional receivers (ip:port or host:port) that get a copy of every packet.
+    #[arg(long = "extra-target")]
+    extra_targets: Vec<String>,
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    gain_db: f32,
+    #[arg(long, default_value_t = false)]
+    mute: bool,
+    #[arg(skip)]
+    eq: Vec<EqBand>,
+    /// Restart the capture, send or stats stage when it stalls for this many seconds.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    watchdog_secs: Option<u64>,
+    /// Load an effect or codec plugin library (repeatable; effects run in order).
+    #[arg(long = "plugin")]
+    plugins: Vec<PathBuf>,
+    /// Print stats as human-readable text or as one JSON object per line.
+    #[arg(long, value_enum, default_value_t = StatsFormat::Text)]
+    stats_format: StatsFormat,
+    /// Append stats lines to this file instead of stdout.
+    #[arg(long)]
+    stats_file: Option<PathBuf>,
+    /// POST every stats interval, and error events as they happen, as JSON to this URL.
+    #[arg(long)]
+    stats_webhook: Option<String>,
+    /// Append the event journal here, one JSON object per line, when the sender exits.
+    #[arg(long)]
+    events_file: Option<PathBuf>,
+    /// Append the time spent in each pipeline stage here, as folded stacks for flame graph
+    /// tools, whenever the sender stops.
+    #[arg(long, value_name = "FILE")]
+    perf_folded: Option<PathBuf>,
+    /// How often a stats line is printed, in milliseconds.
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(100..))]
+    stats_interval_ms: u64,
+    /// Metric groups shown in stats lines, comma-separated.
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = StatsGroup::ALL)]
+    stats_fields: Vec<StatsGroup>,
+    /// Alert when more than this many capture chunks per second are dropped for `--alert-secs`.
+    #[arg(long)]
+    alert_drops: Option<f64>,
+    /// Alert when more than this many chunks wait to be sent for `--alert-secs`.
+    #[arg(long)]
+    alert_backlog: Option<usize>,
+    /// How long a threshold must stay exceeded before the alert fires.
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    alert_secs: u64,
+    /// Stop with exit code 3 when an alert fires, so a supervisor can restart the sender.
+    #[arg(long, default_value_t = false)]
+    alert_exit: bool,
+    /// Stop after running this long (`30s`, `10m`, `2h`), for scripted measurements.
+    #[arg(long, value_name = "TIME", value_parser = autoexit::parse_duration)]
+    duration: Option<Duration>,
+    /// Stop once the captured audio stayed below -60 dBFS this long (`10m`).
+    #[arg(long, value_name = "TIME", value_parser = autoexit::parse_duration)]
+    exit_on_silence: Option<Duration>,
+    /// Show a desktop notification when the connection or the capture is lost or comes
+    /// back, or audio keeps dropping out. `auto` does for the Windows service,
+    /// `--daemonize` and `--minimized`.
+    #[arg(long, value_enum, default_value_t = NotifyWhen::Auto)]
+    notify: NotifyWhen,
+    /// Log filter such as `debug` or `windows_sender=trace` (default: `RUST_LOG`, then `info`).
+    #[arg(long)]
+    log_level: Option<String>,
+    /// Write log events to stderr as text or as one JSON object per line.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Color log levels, errors and stats: `auto` does on a terminal unless `NO_COLOR` is
+    /// set.
+    #[arg(long, value_enum, default_value_t = ColorWhen::Auto)]
+    color: ColorWhen,
+    /// Also write log events and stats lines to this file, for services and tray starts
+    /// that nobody watches the console of.
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+    /// Start a fresh log file at a size such as `10MB`, or `hourly` or `daily` (UTC).
+    #[arg(long, value_name = "SIZE|hourly|daily", default_value = "10MB")]
+    log_rotate: Rotation,
+    /// Rotated log files kept, `<file>.1` being the newest.
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    log_keep: usize,
+    /// Export spans and stream metrics over OTLP/HTTP to this collector (e.g. `http://host:4318`).
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+    /// Show a live terminal dashboard instead of stats lines.
+    #[arg(long, default_value_t = false)]
+    tui: bool,
+    /// Open a window to pick the source and targets, start and stop the stream, adjust the
+    /// gain and watch levels and stats, instead of streaming right away.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["tui", "meter", "pair"])]
+    gui: bool,
+    /// Global hotkey as action=keys (repeatable), such as `mute=ctrl+alt+m`; actions are
+    /// mute, pause, gain-up, gain-down and switch-source (Windows).
+    #[arg(long = "hotkey", value_name = "ACTION=KEYS")]
+    hotkeys: Vec<Hotkey>,
+    /// Start with the console window (or the `--gui` window) minimized to the taskbar, as
+    /// `autostart enable` does. Only Windows has a console window to minimize.
+    #[arg(long, default_value_t = false)]
+    minimized: bool,
+    /// Log the dominant frequency and band energies of each channel every second, to track
+    /// down pitch, sample-rate or channel-order problems.
+    #[arg(long, default_value_t = false)]
+    spectrum: bool,
+    /// Show a live per-channel level meter on one console line instead of stats lines.
+    #[arg(long, default_value_t = false, conflicts_with = "tui")]
+    meter: bool,
+    /// Accept runtime commands on a named pipe / Unix socket (see `audioctl`).
+    #[arg(long, num_args = 0..=1, default_missing_value = control::DEFAULT_NAME)]
+    control: Option<String>,
+    /// Serve a JSON control API (status, pause, gain, targets) on this address.
+    #[arg(long)]
+    control_http: Option<SocketAddr>,
+    /// Register the sender as an auto-start Windows service using `--config`.
+    #[cfg(windows)]
+    #[arg(long, requires = "config")]
+    install_service: bool,
+    #[cfg(windows)]
+    #[arg(long)]
+    uninstall_service: bool,
+    #[cfg(windows)]
+    #[arg(long, hide = true)]
+    run_as_service: bool,
+    /// Detach from the terminal and run in the background.
+    #[cfg(unix)]
+    #[arg(long, default_value_t = false)]
+    daemonize: bool,
+    #[cfg(unix)]
+    #[arg(long, requires = "daemonize")]
+    pid_file: Option<PathBuf>,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-    if args.list_desktop_devices {
-        list_desktop_devices()?;
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Send a command to a running sender: mute, unmute, set-gain <db>, pause, resume,
+    /// switch-source <desktop|mic|tone|file>, restart, add-target/remove-target
+    /// <ip:port>, targets, stats, status, events, mark <name>, sessions, quit.
+    Audioctl {
+        #[arg(long, default_value = control::DEFAULT_NAME)]
+        name: String,
+        /// Session to address when the sender runs several (see `sessions`).
+        #[arg(long)]
+        session: Option<String>,
+        #[arg(required = true, num_args = 1.., allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Check audio devices, COM, reachability of the target and clock resolution.
+    Doctor,
+    /// Save `--key`, or a freshly generated key, in the OS keychain for `--key-name`.
+    StoreKey { name: String },
+    /// Print a completion script for `shell` to stdout.
+    Completions { shell: Shell },
+    /// Print the man page, in roff, to stdout.
+    Man,
+    /// Start the sender at login with the profile from `--config` or `--resume` (Windows).
+    Autostart {
+        #[command(subcommand)]
+        action: autostart::Action,
+    },
+}
+
+fn main() -> ExitCode {
+    let result = try_main();
+    console::finish();
+    match result {
+        Ok(()) if exit::interrupted() => ExitCode::from(exit::INTERRUPTED),
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            let color = console::stderr_color();
+            let error = Tint::Red.paint("Error:", color);
+            match hint::for_error(&err) {
+                Some(hint) => {
+                    eprintln!("{error} {err:#}");
+                    eprintln!(
+                        "  {} {}",
+                        Tint::Yellow.paint(
+                            format_args!("Likely {} problem:", hint.category.as_str()),
+                            color
+                        ),
+                        hint.reason
+                    );
+                    eprintln!("  {} {}", Tint::Green.paint("Try:", color), hint.fix);
+                }
+                None => eprintln!("{error} {err:?}"),
+            }
+            ExitCode::from(exit::code(&err))
+        }
+    }
+}
+
+fn try_main() -> Result<()> {
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    if let Some(Command::Audioctl {
+        name,
+        session,
+        command,
+    }) = &args.command
+    {
+        return control::run_client(name, session.as_deref(), command);
+    }
+    if let Some(Command::StoreKey { name }) = &args.command {
+        return keychain::store(name, args.key.clone());
+    }
+    if let Some(Command::Completions { shell }) = &args.command {
+        let mut command = Args::command();
+        let name = command.get_name().to_string();
+        clap_complete::generate(*shell, &mut command, name, &mut io::stdout());
         return Ok(());
     }
-    if !(1..=20).contains(&args.frame_ms) {
-        bail!("--frame-ms must be in range [1, 20]");
+    if let Some(Command::Autostart { action }) = &args.command {
+        return autostart::run(action, &args);
     }
-
-    let target_ip = args
-        .target_ip
-        .as_deref()
-        .context("--target-ip is required unless --list-desktop-devices is used")?;
-
-    let target: SocketAddr = format!("{}:{}", target_ip, args.port)
-        .parse()
-        .context("invalid target endpoint")?;
-
-    let (tx, rx) = bounded::<CaptureChunk>(512);
-    let stats = Arc::new(SenderStats::default());
-
-    let capture = match args.source {
-        AudioSource::Mic => start_mic_capture(tx, Arc::clone(&stats))?,
-        AudioSource::Desktop => {
-            start_desktop_capture(tx, Arc::clone(&stats), args.desktop_device.as_deref())?
+    if let Some(Command::Man) = &args.command {
+        return clap_mangen::Man::new(Args::command())
+            .render(&mut io::stdout())
+            .context("failed to write the man page");
+    }
+    #[cfg(windows)]
+    {
+        if args.install_service {
+            return service::install(args.config.as_deref());
         }
-    };
-
-    let sample_rate = capture.sample_rate;
-    let channels = capture.channels;
-    let _capture_guard = capture.guard;
-    _capture_guard.keepalive_ref();
-
-    let samples_per_channel = ((sample_rate as u64 * args.frame_ms as u64) / 1000) as usize;
-    let samples_per_packet = samples_per_channel * channels;
-
-    println!("Source: {} ({})", args.source.as_str(), capture.source_name);
-    println!(
-        "Config: {} Hz, {} ch, frame={} ms ({} samples/ch)",
-        sample_rate, channels, args.frame_ms, samples_per_channel
-    );
-    println!("Target: {target}");
-    println!("Transport: {}", args.transport.as_str());
-    println!("Stats: one line per second (pps/kbps/drops/backlog)");
-
-    let _stats_thread = spawn_stats_logger(Arc::clone(&stats), rx.clone(), args.frame_ms);
-
-    match args.transport {
-        Transport::Udp => {
-            let socket = UdpSocket::bind("0.0.0.0:0").context("failed to bind UDP sender socket")?;
-            socket
-                .set_nonblocking(false)
-                .context("failed to configure UDP socket")?;
-
-            send_loop(
-                rx,
-                sample_rate,
-                channels as u8,
-                samples_per_channel as u16,
-                samples_per_packet,
-                0,
-                stats,
-                move |packet: &[u8], seq| {
-                    socket
-                        .send_to(packet, target)
-                        .with_context(|| format!("failed to send UDP packet seq={seq}"))?;
-                    Ok(())
-                },
-            )
+        if args.uninstall_service {
+            return service::uninstall();
         }
-        Transport::Tcp => {
-            let mut stream = TcpStream::connect(target)
-                .with_context(|| format!("failed to connect TCP stream to {target}"))?;
-            stream
-                .set_nodelay(true)
-                .context("failed to set TCP_NODELAY on sender socket")?;
-
-            send_loop(
-                rx,
-                sample_rate,
-                channels as u8,
-                samples_per_channel as u16,
-                samples_per_packet,
-                2,
-                stats,
-                move |packet: &[u8], seq| {
-                    let len = u16::try_from(packet.len())
-                        .context("packet too large for TCP length prefix")?;
-                    stream
-                        .write_all(&len.to_le_bytes())
-                        .with_context(|| format!("failed to send TCP packet length seq={seq}"))?;
-                    stream
-                        .write_all(packet)
-                        .with_context(|| format!("failed to send TCP packet payload seq={seq}"))?;
-                    Ok(())
-                },
-            )
+        if args.run_as_service {
+            return service::run_dispatcher();
         }
     }
-}
-
-fn start_mic_capture(tx: Sender<CaptureChunk>, stats: Arc<SenderStats>) -> Result<CaptureSetup> {
-    let host = cpal::default_host();
-    let device = host
-        .default_input_device()
-        .context("no default input device found")?;
-    let device_name = device.name().unwrap_or_else(|_| "unknown".to_string());
-    let supported = device
-        .default_input_config()
-        .context("failed to read default input config")?;
-
-    let sample_format = supported.sample_format();
-    let config: StreamConfig = supported.into();
-    let sample_rate = config.sample_rate.0;
-    let channels = config.channels as usize;
-
-    let stream = build_input_stream(&device, &config, sample_format, tx, stats)?;
-    stream.play().context("failed to start input stream")?;
-
-    Ok(CaptureSetup {
-        sample_rate,
-        channels,
-        source_name: device_name,
-        guard: CaptureGuard::Mic(stream),
-    })
-}
-
-fn start_desktop_capture(
-    tx: Sender<CaptureChunk>,
-    stats: Arc<SenderStats>,
-    desktop_device_name: Option<&str>,
-) -> Result<CaptureSetup> {
-    let sample_rate = DESKTOP_SAMPLE_RATE;
-    let channels = DESKTOP_CHANNELS;
-    let (ready_tx, ready_rx) = std_mpsc::sync_channel::<Result<String, String>>(1);
-    let desktop_device_name_owned = desktop_device_name.map(|s| s.to_string());
-
-    let handle = thread::Builder::new()
-        .name("wasapi-loopback".to_string())
-        .spawn(move || {
-            desktop_capture_loop(
-                tx,
-                stats,
-                sample_rate,
-                channels,
-                desktop_device_name_owned,
-                ready_tx,
-            );
-        })
-        .context("failed to spawn desktop capture thread")?;
-
-    let source_name = ready_rx
-        .recv_timeout(Duration::from_secs(5))
-        .context("desktop capture thread did not initialize in time")?
-        .map_err(|msg| anyhow::anyhow!(msg))?;
-
-    Ok(CaptureSetup {
-        sample_rate,
-        channels,
-        source_name,
-        guard: CaptureGuard::Desktop(handle),
+    #[cfg(unix)]
+    {
+        let mut args = args;
+        if args.daemonize {
+            daemon::daemonize(&mut args)?;
+        }
+        let pid_file = args.pid_file.clone();
+        let result = run(args, &matches, |router| {
+            if let Err(err) = daemon::spawn_signal_handler(router.clone()) {
+                error!("{err:#}");
+            }
+        });
+        daemon::remove_pid_file(pid_file.as_deref());
+        result
+    }
+    #[cfg(windows)]
+    run(args, &matches, |router| {
+        if let Err(err) = exit::catch_ctrl_c(router.clone()) {
+            error!("{err:#}");
+        }
     })
 }
 
-fn desktop_capture_loop(
-    tx: Sender<CaptureChunk>,
-    stats: Arc<SenderStats>,
-    sample_rate: u32,
-    channels: usize,
-    desktop_device_name: Option<String>,
-    ready_tx: std_mpsc::SyncSender<Result<String, String>>,
-) {
-    if let Err(err) = desktop_capture_inner(
-        tx,
-        stats,
-        sample_rate,
-        channels,
-        desktop_device_name.as_deref(),
-        &ready_tx,
-    ) {
-        let _ = ready_tx.send(Err(format!("{err:#}")));
-        eprintln!("desktop loopback stopped: {err:#}");
+/// Starts every configured session and blocks until all of them finish.
+///
+/// `on_ready` receives the control router once the sessions are about to start.
+pub(crate) fn run<F>(mut args: Args, matches: &ArgMatches, on_ready: F) -> Result<()>
+where
+    F: FnOnce(&control::Router),
+{
+    if args.pair && args.key.is_none() && args.key_name.is_none() {
+        args.key = Some(Key::generate()?);
+    }
+    if args.resume {
+        args.config = Some(resume::saved()?);
+    }
+    let cli_args = args.clone();
+    let config_path = args.config.clone();
+    let sessions = config::resolve(config_path.as_deref(), &mut args, matches)?;
+    #[cfg(not(feature = "tui"))]
+    if args.tui {
+        bail!("--tui needs a build with the `tui` feature");
+    }
+    #[cfg(not(feature = "gui"))]
+    if args.gui {
+        bail!("--gui needs a build with the `gui` feature");
+    }
+    let _log_guard = logging::init(&args)?;
+    if args.minimized && !args.gui {
+        autostart::minimize_console();
+    }
+    if args.list_desktop_devices {
+        list_desktop_devices()?;
+        return Ok(());
+    }
+    if args.list_bt_devices {
+        list_bt_devices()?;
+        return Ok(());
+    }
+    if let Some(selector) = &args.bt_pair {
+        println!("Searching for {selector}; confirm the code on both devices when asked...");
+        let device = bluetooth::pair(selector)?;
+        println!("Paired with {device}");
+        return Ok(());
+    }
+    let _forwards = adb::Forwards::setup(&sessions, &args)?;
+    if matches!(args.command, Some(Command::Doctor)) {
+        return diagnose::doctor(&sessions[0].args);
+    }
+    if args.probe_bandwidth {
+        return diagnose::probe_bandwidth(&sessions[0].args);
+    }
+    if args.probe_mtu {
+        return diagnose::probe_mtu(&sessions[0].args);
     }
-}
-
-fn desktop_capture_inner(
-    tx: Sender<CaptureChunk>,
-    stats: Arc<SenderStats>,
-    sample_rate: u32,
-    channels: usize,
-    desktop_device_name: Option<&str>,
-    ready_tx: &std_mpsc::SyncSender<Result<String, String>>,
-) -> Result<()> {
-    wasapi::initialize_mta()
-        .ok()
-        .context("failed to initialize COM MTA for WASAPI")?;
-
-    let enumerator =
-        DeviceEnumerator::new().context("failed to create WASAPI device enumerator")?;
-    let device = if let Some(name) = desktop_device_name {
-        let collection = enumerator
-            .get_device_collection(&WasapiDirection::Render)
-            .context("failed to get render device collection")?;
-        collection
-            .get_device_with_name(name)
-            .with_context(|| format!("failed to find render device with name '{name}'"))?
-    } else {
-        enumerator
-            .get_default_device(&WasapiDirection::Render)
-            .context("failed to get default render device")?
-    };
-    let device_name = device
-        .get_friendlyname()
-        .unwrap_or_else(|_| "default render device".to_string());
-
-    let mut audio_client = device
-        .get_iaudioclient()
-        .context("failed to get IAudioClient")?;
-    let desired_format = WaveFormat::new(
-        32,
-        32,
-        &SampleType::Float,
-        sample_rate as usize,
-        channels,
-        None,
-    );
-    let mode = StreamMode::EventsShared {
-        autoconvert: true,
-        buffer_duration_hns: 0,
-    };
-    audio_client
-        .initialize_client(&desired_format, &WasapiDirection::Capture, &mode)
-        .context("failed to initialize desktop loopback client")?;
-    let event = audio_client
-        .set_get_eventhandle()
-        .context("failed to set WASAPI event handle")?;
-    let capture_client = audio_client
-        .get_audiocaptureclient()
-        .context("failed to get WASAPI capture client")?;
-    audio_client
-        .start_stream()
-        .context("failed to start desktop loopback stream")?;
-
-    let _ = ready_tx.send(Ok(device_name));
-
-    let mut byte_queue = VecDeque::<u8>::with_capacity(32 * 1024);
-    let frame_bytes = channels * 4;
-
-    loop {
-        if let Err(err) = event.wait_for_event(1000) {
-            eprintln!("desktop loopback event wait timeout/error: {err}");
-            continue;
-        }
 
-        if let Err(err) = capture_client.read_from_device_to_deque(&mut byte_queue) {
-            eprintln!("desktop loopback read error: {err}");
-            thread::sleep(Duration::from_millis(10));
-            continue;
+    #[cfg(feature = "gui")]
+    if args.gui {
+        if sessions.len() > 1 {
+            bail!("--gui drives a single sender; it cannot run several sessions");
         }
+        return gui::run(sessions[0].args.clone());
+    }
 
-        if byte_queue.len() < frame_bytes {
-            continue;
+    #[cfg(feature = "tui")]
+    let sessions = {
+        let mut sessions = sessions;
+        if sessions.len() == 1
+            && sessions[0].args.target_ip.is_none()
+            && sessions[0].args.output.is_none()
+            && !matches!(
+                sessions[0].args.transport,
+                Transport::Bluetooth | Transport::Adb
+            )
+            && wizard::available()
+        {
+            wizard::run(&mut sessions[0].args)?;
         }
+        sessions
+    };
 
-        let available_frames = byte_queue.len() / frame_bytes;
-        let mut chunk = Vec::<i16>::with_capacity(available_frames * channels);
-        for _ in 0..available_frames {
-            for _ in 0..channels {
-                let sample = pop_f32_le(&mut byte_queue).unwrap_or(0.0);
-                let clamped = sample.clamp(-1.0, 1.0);
-                chunk.push((clamped * i16::MAX as f32) as i16);
-            }
-        }
-        enqueue_audio_chunk(&tx, &stats, chunk);
+    if args.pair {
+        pair::print(&sessions)?;
     }
-}
 
-fn pop_f32_le(queue: &mut VecDeque<u8>) -> Option<f32> {
-    if queue.len() < 4 {
-        return None;
+    if args.meter && sessions.len() > 1 {
+        bail!("--meter draws a single console line; it cannot follow several sessions");
     }
-    let b0 = queue.pop_front()?;
-    let b1 = queue.pop_front()?;
-    let b2 = queue.pop_front()?;
-    let b3 = queue.pop_front()?;
-    Some(f32::from_le_bytes([b0, b1, b2, b3]))
-}
 
-fn enqueue_audio_chunk(tx: &Sender<CaptureChunk>, stats: &Arc<SenderStats>, chunk: Vec<i16>) {
-    if chunk.is_empty() {
-        return;
+    let configs = sessions
+        .iter()
+        .map(|session| match sessions.len() {
+            1 => session::sender_config(&session.args),
+            _ => session::sender_config(&session.args)
+                .with_context(|| format!("invalid settings for session {}", session.name)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut routes = Vec::with_capacity(sessions.len());
+    let mut receivers = Vec::with_capacity(sessions.len());
+    for session in &sessions {
+        let (tx, rx) = control::channel();
+        routes.push((session.name.clone(), tx));
+        receivers.push(rx);
     }
-
-    let mut abs_sum = 0_u64;
-    let mut nonzero = 0_u64;
-    for s in &chunk {
-        let v = *s as i32;
-        abs_sum += v.unsigned_abs() as u64;
-        if *s != 0 {
-            nonzero += 1;
-        }
+    let (reload_tx, reload_rx) = crossbeam_channel::bounded(1);
+    let router = control::Router::new(routes, config_path.as_ref().map(|_| reload_tx));
+    if let Some(name) = &args.control {
+        control::spawn_server(name, router.clone())?;
+        info!("Control: {name}");
     }
-    stats
-        .captured_samples
-        .fetch_add(chunk.len() as u64, Ordering::Relaxed);
-    stats.captured_abs_sum.fetch_add(abs_sum, Ordering::Relaxed);
-    stats
-        .captured_nonzero_samples
-        .fetch_add(nonzero, Ordering::Relaxed);
-    match tx.try_send(CaptureChunk {
-        samples: chunk,
-        captured_at: Instant::now(),
-    }) {
-        Ok(_) => {
-            stats.captured_chunks.fetch_add(1, Ordering::Relaxed);
-        }
-        Err(TrySendError::Full(_)) => {
-            stats.capture_drops.fetch_add(1, Ordering::Relaxed);
-        }
-        Err(TrySendError::Disconnected(_)) => {}
+    #[cfg(feature = "http")]
+    if let Some(addr) = args.control_http {
+        http::spawn_server(addr, router.clone())?;
+        info!("Control HTTP: http://{addr}");
     }
-}
-
-fn send_loop<F>(
-    rx: Receiver<CaptureChunk>,
-    sample_rate: u32,
-    channels: u8,
-    samples_per_channel: u16,
-    samples_per_packet: usize,
-    per_packet_overhead_bytes: usize,
-    stats: Arc<SenderStats>,
-    mut send_packet: F,
-) -> Result<()>
-where
-    F: FnMut(&[u8], u32) -> Result<()>,
-{
-    let mut seq: u32 = 0;
-    let mut acc = VecDeque::<i16>::with_capacity(samples_per_packet * 4);
-    let mut acc_capture = VecDeque::<(usize, Instant)>::with_capacity(64);
-
-    loop {
-        let chunk = rx.recv().context("audio capture channel closed")?;
-        let chunk_queue_us = chunk.captured_at.elapsed().as_micros() as u64;
-        stats
-            .capture_queue_us_sum
-            .fetch_add(chunk_queue_us, Ordering::Relaxed);
-        stats.capture_queue_count.fetch_add(1, Ordering::Relaxed);
-
-        let chunk_samples = chunk.samples.len();
-        for s in chunk.samples {
-            acc.push_back(s);
-        }
-        if chunk_samples > 0 {
-            acc_capture.push_back((chunk_samples, chunk.captured_at));
-        }
-
-        while acc.len() >= samples_per_packet {
-            let packet_capture_time = consume_capture_time(&mut acc_capture, samples_per_packet);
-            let mut payload = vec![0u8; samples_per_packet * 2];
-            for i in 0..samples_per_packet {
-                let sample = acc.pop_front().unwrap_or(0);
-                let bytes = sample.to_le_bytes();
-                payload[i * 2] = bytes[0];
-                payload[i * 2 + 1] = bytes[1];
-            }
+    #[cfg(not(feature = "http"))]
+    if let Some(addr) = args.control_http {
+        bail!("--control-http {addr} needs a build with the `http` feature");
+    }
+    if let Some(path) = config_path {
+        config::spawn_watcher(
+            path,
+            cli_args,
+            matches.clone(),
+            sessions.clone(),
+            router.clone(),
+            reload_rx,
+        )?;
+    }
+    if !args.hotkeys.is_empty() {
+        hotkey::spawn(&args.hotkeys, router.clone())?;
+    }
+    on_ready(&router);
+    let stats_to = args
+        .stats_file
+        .as_ref()
+        .map(|path| format!(" -> {}", path.display()))
+        .unwrap_or_default();
+    let every = args.stats_interval_ms;
+    match args.stats_format {
+        StatsFormat::Text => info!("Stats: one line every {every} ms{stats_to}"),
+        StatsFormat::Json => info!("Stats: one JSON object every {every} ms{stats_to}"),
+    }
+    if let Some(url) = &args.stats_webhook {
+        info!("Stats webhook: {url}");
+    }
+    #[cfg(feature = "tui")]
+    let _dashboard = match &_log_guard.logs {
+        Some(logs) => Some(tui::Dashboard::spawn(
+            router.clone(),
+            logs.clone(),
+            std::time::Duration::from_millis(args.stats_interval_ms),
+        )?),
+        None => None,
+    };
+    drop(router);
 
-            let packet_build_start = Instant::now();
-            let packet = build_packet(seq, sample_rate, channels, samples_per_channel, &payload)?;
-            let packet_build_us = packet_build_start.elapsed().as_micros() as u64;
-            stats
-                .packet_build_us_sum
-                .fetch_add(packet_build_us, Ordering::Relaxed);
-            stats.packet_build_count.fetch_add(1, Ordering::Relaxed);
-
-            let send_start = Instant::now();
-            send_packet(&packet, seq)?;
-            let socket_send_us = send_start.elapsed().as_micros() as u64;
-            stats
-                .socket_send_us_sum
-                .fetch_add(socket_send_us, Ordering::Relaxed);
-            stats.socket_send_count.fetch_add(1, Ordering::Relaxed);
-            stats.sent_packets.fetch_add(1, Ordering::Relaxed);
-            stats
-                .sent_bytes
-                .fetch_add((packet.len() + per_packet_overhead_bytes) as u64, Ordering::Relaxed);
-            if let Some(captured_at) = packet_capture_time {
-                let capture_to_send_us = captured_at.elapsed().as_micros() as u64;
-                stats
-                    .capture_to_send_us_sum
-                    .fetch_add(capture_to_send_us, Ordering::Relaxed);
-                stats.capture_to_send_count.fetch_add(1, Ordering::Relaxed);
-            }
-            seq = seq.wrapping_add(1);
-        }
+    if sessions.len() == 1 {
+        return session::run(&sessions[0], configs[0].clone(), &receivers[0], "");
     }
-}
 
-fn consume_capture_time(
-    acc_capture: &mut VecDeque<(usize, Instant)>,
-    mut samples_to_consume: usize,
-) -> Option<Instant> {
-    let oldest = acc_capture.front().map(|(_, captured_at)| *captured_at);
-    while samples_to_consume > 0 {
-        let Some((count, captured_at)) = acc_capture.pop_front() else {
-            break;
-        };
-        if count > samples_to_consume {
-            acc_capture.push_front((count - samples_to_consume, captured_at));
-            break;
+    let handles = sessions
+        .into_iter()
+        .zip(configs)
+        .zip(receivers)
+        .map(|((session, config), requests)| {
+            let label = format!("[{}] ", session.name);
+            thread::Builder::new()
+                .name(format!("session-{}", session.name))
+                .spawn(move || session::run(&session, config, &requests, &label))
+                .context("failed to spawn session thread")
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut result = Ok(());
+    for handle in handles {
+        let session_result = handle
+            .join()
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("session thread panicked")));
+        if let Err(err) = session_result {
+            error!("{err:#}");
+            if result.is_ok() {
+                result = Err(err);
+            }
         }
-        samples_to_consume -= count;
     }
-    oldest
-}
-
-fn build_packet(
-    seq: u32,
-    sample_rate: u32,
-    channels: u8,
-    samples_per_channel: u16,
-    payload: &[u8],
-) -> Result<Vec<u8>> {
-    if payload.len() > u16::MAX as usize {
-        bail!("payload too large");
-    }
-
-    let send_time_us = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .context("system clock before unix epoch")?
-        .as_micros() as u64;
-
-    let mut packet = Vec::with_capacity(HEADER_SIZE + payload.len());
-    packet.extend_from_slice(&MAGIC);
-    packet.push(VERSION);
-    packet.push(CODEC_PCM16);
-    packet.push(channels);
-    packet.push(0);
-    packet.extend_from_slice(&sample_rate.to_le_bytes());
-    packet.extend_from_slice(&seq.to_le_bytes());
-    packet.extend_from_slice(&send_time_us.to_le_bytes());
-    packet.extend_from_slice(&samples_per_channel.to_le_bytes());
-    packet.extend_from_slice(&(payload.len() as u16).to_le_bytes());
-    packet.extend_from_slice(payload);
-    Ok(packet)
+    result
 }
 
-fn build_input_stream(
-    device: &cpal::Device,
-    config: &StreamConfig,
-    sample_format: SampleFormat,
-    tx: Sender<CaptureChunk>,
-    stats: Arc<SenderStats>,
-) -> Result<cpal::Stream> {
-    let err_fn = |err| eprintln!("cpal stream error: {err}");
-
-    let stream = match sample_format {
-        SampleFormat::I16 => {
-            let tx = tx.clone();
-            let stats = Arc::clone(&stats);
-            device.build_input_stream(
-                config,
-                move |data: &[i16], _| {
-                    enqueue_audio_chunk(&tx, &stats, data.to_vec());
-                },
-                err_fn,
-                None,
-            )?
-        }
-        SampleFormat::U16 => {
-            let tx = tx.clone();
-            let stats = Arc::clone(&stats);
-            device.build_input_stream(
-                config,
-                move |data: &[u16], _| {
-                    let converted = data
-                        .iter()
-                        .map(|s| (*s as i32 - 32768) as i16)
-                        .collect::<Vec<i16>>();
-                    enqueue_audio_chunk(&tx, &stats, converted);
-                },
-                err_fn,
-                None,
-            )?
+fn list_desktop_devices() -> Result<()> {
+    let devices = capture::desktop_devices()?;
+    println!("Desktop render devices:");
+    let mut virtual_found = false;
+    for device in &devices {
+        let mut labels = Vec::new();
+        if device.is_default {
+            labels.push("default".to_string());
         }
-        SampleFormat::F32 => {
-            let tx = tx.clone();
-            let stats = Arc::clone(&stats);
-            device.build_input_stream(
-                config,
-                move |data: &[f32], _| {
-                    let converted = data
-                        .iter()
-                        .map(|s| {
-                            let clamped = s.clamp(-1.0, 1.0);
-                            (clamped * i16::MAX as f32) as i16
-                        })
-                        .collect::<Vec<i16>>();
-                    enqueue_audio_chunk(&tx, &stats, converted);
-                },
-                err_fn,
-                None,
-            )?
+        if let Some(cable) = device.virtual_cable() {
+            labels.push(format!("virtual: {}", cable.as_str()));
+            virtual_found = true;
         }
-        other => bail!("unsupported sample format: {other:?}"),
-    };
-
-    Ok(stream)
-}
-
-fn spawn_stats_logger(
-    stats: Arc<SenderStats>,
-    rx: Receiver<CaptureChunk>,
-    frame_ms: u32,
-) -> thread::JoinHandle<()> {
-    thread::spawn(move || {
-        let mut last_chunks = 0_u64;
-        let mut last_samples = 0_u64;
-        let mut last_nonzero_samples = 0_u64;
-        let mut last_abs_sum = 0_u64;
-        let mut last_drops = 0_u64;
-        let mut last_capture_queue_us_sum = 0_u64;
-        let mut last_capture_queue_count = 0_u64;
-        let mut last_capture_to_send_us_sum = 0_u64;
-        let mut last_capture_to_send_count = 0_u64;
-        let mut last_packet_build_us_sum = 0_u64;
-        let mut last_packet_build_count = 0_u64;
-        let mut last_socket_send_us_sum = 0_u64;
-        let mut last_socket_send_count = 0_u64;
-        let mut last_packets = 0_u64;
-        let mut last_bytes = 0_u64;
-
-        loop {
-            thread::sleep(Duration::from_secs(1));
-            let chunks = stats.captured_chunks.load(Ordering::Relaxed);
-            let samples = stats.captured_samples.load(Ordering::Relaxed);
-            let nonzero_samples = stats.captured_nonzero_samples.load(Ordering::Relaxed);
-            let abs_sum = stats.captured_abs_sum.load(Ordering::Relaxed);
-            let drops = stats.capture_drops.load(Ordering::Relaxed);
-            let capture_queue_us_sum = stats.capture_queue_us_sum.load(Ordering::Relaxed);
-            let capture_queue_count = stats.capture_queue_count.load(Ordering::Relaxed);
-            let capture_to_send_us_sum = stats.capture_to_send_us_sum.load(Ordering::Relaxed);
-            let capture_to_send_count = stats.capture_to_send_count.load(Ordering::Relaxed);
-            let packet_build_us_sum = stats.packet_build_us_sum.load(Ordering::Relaxed);
-            let packet_build_count = stats.packet_build_count.load(Ordering::Relaxed);
-            let socket_send_us_sum = stats.socket_send_us_sum.load(Ordering::Relaxed);
-            let socket_send_count = stats.socket_send_count.load(Ordering::Relaxed);
-            let packets = stats.sent_packets.load(Ordering::Relaxed);
-            let bytes = stats.sent_bytes.load(Ordering::Relaxed);
-
-            let d_chunks = chunks.saturating_sub(last_chunks);
-            let d_samples = samples.saturating_sub(last_samples);
-            let d_nonzero_samples = nonzero_samples.saturating_sub(last_nonzero_samples);
-            let d_abs_sum = abs_sum.saturating_sub(last_abs_sum);
-            let d_drops = drops.saturating_sub(last_drops);
-            let d_capture_queue_us_sum =
-                capture_queue_us_sum.saturating_sub(last_capture_queue_us_sum);
-            let d_capture_queue_count =
-                capture_queue_count.saturating_sub(last_capture_queue_count);
-            let d_capture_to_send_us_sum =
-                capture_to_send_us_sum.saturating_sub(last_capture_to_send_us_sum);
-            let d_capture_to_send_count =
-                capture_to_send_count.saturating_sub(last_capture_to_send_count);
-            let d_packet_build_us_sum =
-                packet_build_us_sum.saturating_sub(last_packet_build_us_sum);
-            let d_packet_build_count = packet_build_count.saturating_sub(last_packet_build_count);
-            let d_socket_send_us_sum = socket_send_us_sum.saturating_sub(last_socket_send_us_sum);
-            let d_socket_send_count = socket_send_count.saturating_sub(last_socket_send_count);
-            let d_packets = packets.saturating_sub(last_packets);
-            let d_bytes = bytes.saturating_sub(last_bytes);
-            let kbps = (d_bytes as f64 * 8.0) / 1000.0;
-            let queue_backlog = rx.len();
-            let avg_abs = if d_samples > 0 {
-                d_abs_sum as f64 / d_samples as f64
-            } else {
-                0.0
-            };
-            let active_pct = if d_samples > 0 {
-                (d_nonzero_samples as f64 * 100.0) / d_samples as f64
-            } else {
-                0.0
-            };
-            let capq_ms = if d_capture_queue_count > 0 {
-                (d_capture_queue_us_sum as f64 / d_capture_queue_count as f64) / 1000.0
-            } else {
-                0.0
-            };
-            let capsend_ms = if d_capture_to_send_count > 0 {
-                (d_capture_to_send_us_sum as f64 / d_capture_to_send_count as f64) / 1000.0
-            } else {
-                0.0
-            };
-            let pkt_ms = if d_packet_build_count > 0 {
-                (d_packet_build_us_sum as f64 / d_packet_build_count as f64) / 1000.0
-            } else {
-                0.0
-            };
-            let sock_ms = if d_socket_send_count > 0 {
-                (d_socket_send_us_sum as f64 / d_socket_send_count as f64) / 1000.0
-            } else {
-                0.0
-            };
-
-            println!(
-                "stats frame={}ms tx={}pps {:.1}kbps cap={}chunks/s {}samples/s drop={} q={} avgAbs={:.1} active={:.1}% perf capQ={:.3}ms capSend={:.3}ms pkt={:.3}ms sock={:.3}ms",
-                frame_ms, d_packets, kbps, d_chunks, d_samples, d_drops, queue_backlog, avg_abs, active_pct, capq_ms, capsend_ms, pkt_ms, sock_ms
-            );
-
-            last_chunks = chunks;
-            last_samples = samples;
-            last_nonzero_samples = nonzero_samples;
-            last_abs_sum = abs_sum;
-            last_drops = drops;
-            last_capture_queue_us_sum = capture_queue_us_sum;
-            last_capture_queue_count = capture_queue_count;
-            last_capture_to_send_us_sum = capture_to_send_us_sum;
-            last_capture_to_send_count = capture_to_send_count;
-            last_packet_build_us_sum = packet_build_us_sum;
-            last_packet_build_count = packet_build_count;
-            last_socket_send_us_sum = socket_send_us_sum;
-            last_socket_send_count = socket_send_count;
-            last_packets = packets;
-            last_bytes = bytes;
+        if labels.is_empty() {
+            println!("* {}", device.name);
+        } else {
+            println!("* {} [{}]", device.name, labels.join(", "));
         }
-    })
+    }
+    if virtual_found {
+        println!(
+            "To stream some apps only, set their output to a virtual device in Settings > \
+             System > Sound > Volume mixer and start with --route-through-virtual."
+        );
+    }
+    Ok(())
 }
 
-fn list_desktop_devices() -> Result<()> {
-    wasapi::initialize_mta()
-        .ok()
-        .context("failed to initialize COM MTA for WASAPI")?;
-    let enumerator =
-        DeviceEnumerator::new().context("failed to create WASAPI device enumerator")?;
-    let default = enumerator
-        .get_default_device(&WasapiDirection::Render)
-        .ok()
-        .and_then(|d| d.get_friendlyname().ok());
-    let collection = enumerator
-        .get_device_collection(&WasapiDirection::Render)
-        .context("failed to get render device collection")?;
-
-    println!("Desktop render devices:");
-    for device_result in &collection {
-        let device = match device_result {
-            Ok(device) => device,
-            Err(err) => {
-                println!("* <error reading device: {err}>");
-                continue;
-            }
+fn list_bt_devices() -> Result<()> {
+    let devices = bluetooth::devices()?;
+    if devices.is_empty() {
+        println!("No Bluetooth devices known; pair one with --bt-pair.");
+        return Ok(());
+    }
+    println!("Bluetooth devices:");
+    for device in devices {
+        let state = match (device.connected, device.paired) {
+            (true, _) => " [connected]",
+            (false, true) => "",
+            (false, false) => " [not paired]",
         };
-        let name = device
-            .get_friendlyname()
-            .unwrap_or_else(|_| "<unknown>".to_string());
-        let is_default = default.as_ref().map(|d| d == &name).unwrap_or(false);
-        if is_default {
-            println!("* {name} [default]");
-        } else {
-            println!("* {name}");
-        }
+        println!("* {device}{state}");
     }
     Ok(())
 }
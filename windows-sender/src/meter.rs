@@ -0,0 +1,77 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use windows_sender::stats::{ChannelLevel, SenderStats};
+
+/// Fast enough to follow speech without flickering the console.
+const REDRAW: Duration = Duration::from_millis(100);
+const FLOOR_DB: f32 = -60.0;
+const BAR_WIDTH: usize = 20;
+/// Peaks this close to full scale have most likely been clipped.
+const CLIP_PEAK: f32 = 0.999;
+
+/// `--meter`: redraws one console line with a level bar per channel until `running`
+/// clears, then blanks it.
+pub fn spawn(stats: Arc<SenderStats>, running: Arc<AtomicBool>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut out = io::stdout();
+        let mut width = 0;
+        while running.load(Ordering::Relaxed) {
+            thread::sleep(REDRAW);
+            let line = render(&stats.levels.take());
+            // Pad over whatever the previous, possibly longer, line left behind.
+            let _ = write!(out, "\r{line:width$}");
+            let _ = out.flush();
+            width = line.chars().count();
+        }
+        let _ = write!(out, "\r{:width$}\r", "");
+        let _ = out.flush();
+    })
+}
+
+fn render(levels: &[ChannelLevel]) -> String {
+    if levels.is_empty() {
+        return "no audio".to_string();
+    }
+    levels
+        .iter()
+        .enumerate()
+        .map(|(channel, level)| {
+            let name = match (levels.len(), channel) {
+                (1, _) => "M".to_string(),
+                (2, 0) => "L".to_string(),
+                (2, _) => "R".to_string(),
+                (_, channel) => (channel + 1).to_string(),
+            };
+            let rms_db = to_db(level.rms);
+            let clip = if level.peak >= CLIP_PEAK { " CLIP" } else { "" };
+            format!(
+                "{name} [{}] {rms_db:>5.1} dB{clip}",
+                bar(rms_db, to_db(level.peak))
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("  ")
+}
+
+/// RMS fills the bar; the peak is a `|` past it.
+fn bar(rms_db: f32, peak_db: f32) -> String {
+    let cells =
+        |db: f32| (((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0) * BAR_WIDTH as f32) as usize;
+    let filled = cells(rms_db);
+    let peak = cells(peak_db);
+    (0..BAR_WIDTH)
+        .map(|cell| match cell {
+            cell if cell < filled => '=',
+            cell if cell + 1 == peak && peak > filled => '|',
+            _ => ' ',
+        })
+        .collect()
+}
+
+fn to_db(level: f32) -> f32 {
+    (20.0 * level.max(1e-6).log10()).max(FLOOR_DB)
+}
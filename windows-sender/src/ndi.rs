@@ -0,0 +1,314 @@
+//! `--ndi`: the audio being sent, published as an NDI source that OBS, vMix and other NDI
+//! receivers on the LAN pick up by name. The NDI runtime is loaded when the source is
+//! made, so it is only needed on machines that use it.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+#[cfg(ndi_backend)]
+use std::time::{Duration, Instant};
+
+#[cfg(ndi_backend)]
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError};
+use crossbeam_channel::{Sender, TrySendError};
+
+use crate::error::Error;
+#[cfg(not(ndi_backend))]
+use crate::error::NdiError;
+use crate::sender::StreamFormat;
+
+/// Frames waiting for the NDI runtime; past this they are dropped rather than held.
+#[cfg(ndi_backend)]
+const QUEUE_FRAMES: usize = 100;
+/// How often receivers are counted, and drops reported.
+#[cfg(ndi_backend)]
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+#[cfg_attr(not(ndi_backend), allow(dead_code))]
+struct Frame {
+    format: StreamFormat,
+    samples: Vec<i16>,
+}
+
+/// The NDI source `--ndi` publishes. Shared by the restarts of a sender, so receivers keep
+/// the same source for the whole session; it goes off the network when the last handle is
+/// dropped.
+#[derive(Debug)]
+#[cfg_attr(not(ndi_backend), allow(dead_code))]
+pub struct NdiSource {
+    name: String,
+    tx: Option<Sender<Frame>>,
+    dropped: Arc<AtomicU64>,
+    publisher: Option<JoinHandle<()>>,
+}
+
+impl NdiSource {
+    #[cfg(not(ndi_backend))]
+    pub fn new(_name: &str) -> Result<Self, Error> {
+        Err(NdiError::Disabled.into())
+    }
+
+    /// Loads the NDI runtime and announces a source called `name`, which receivers list as
+    /// `MACHINE (name)`.
+    #[cfg(ndi_backend)]
+    pub fn new(name: &str) -> Result<Self, Error> {
+        if name.is_empty() || name.contains('\0') {
+            return Err(Error::Config(format!(
+                "NDI source name {name:?} is empty or invalid"
+            )));
+        }
+        let runtime = runtime::Runtime::load()?;
+        let instance = runtime.create(name)?;
+        let (tx, rx) = bounded(QUEUE_FRAMES);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let publisher = Publisher {
+            instance,
+            runtime,
+            dropped: Arc::clone(&dropped),
+        };
+        let publisher = std::thread::Builder::new()
+            .name("ndi".to_string())
+            .spawn(move || publisher.run(&rx))
+            .map_err(|source| Error::Spawn {
+                name: "ndi",
+                source,
+            })?;
+        Ok(Self {
+            name: name.to_string(),
+            tx: Some(tx),
+            dropped,
+            publisher: Some(publisher),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Queues one frame of interleaved samples; it is dropped, and counted, when the NDI
+    /// runtime falls behind.
+    pub fn write(&self, format: StreamFormat, samples: Vec<i16>) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        if let Err(TrySendError::Full(_)) = tx.try_send(Frame { format, samples }) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for NdiSource {
+    fn drop(&mut self) {
+        self.tx = None;
+        if let Some(publisher) = self.publisher.take() {
+            let _ = publisher.join();
+        }
+    }
+}
+
+#[cfg(ndi_backend)]
+struct Publisher {
+    instance: runtime::SendInstance,
+    runtime: runtime::Runtime,
+    dropped: Arc<AtomicU64>,
+}
+
+#[cfg(ndi_backend)]
+impl Publisher {
+    fn run(self, rx: &Receiver<Frame>) {
+        let mut polled = Instant::now();
+        let mut receivers = 0;
+        let mut reported = 0;
+        loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(mut frame) => self.runtime.send_audio(&self.instance, &mut frame),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if polled.elapsed() >= POLL_INTERVAL {
+                polled = Instant::now();
+                let connected = self.runtime.connections(&self.instance);
+                if connected != receivers {
+                    tracing::info!("NDI: {connected} receiver(s) connected");
+                    receivers = connected;
+                }
+                let dropped = self.dropped.load(Ordering::Relaxed);
+                if dropped > reported {
+                    tracing::warn!(
+                        "NDI: {} frames dropped; the NDI runtime cannot keep up",
+                        dropped - reported
+                    );
+                    reported = dropped;
+                }
+            }
+        }
+        self.runtime.destroy(self.instance);
+    }
+}
+
+/// The parts of the NDI SDK's C API the source uses, resolved from the runtime library.
+#[cfg(ndi_backend)]
+mod runtime {
+    use std::ffi::{c_char, c_void, CString, OsString};
+    use std::path::PathBuf;
+    use std::ptr;
+
+    use libloading::Library;
+
+    use super::Frame;
+    use crate::error::NdiError;
+
+    #[repr(C)]
+    struct SendCreate {
+        p_ndi_name: *const c_char,
+        p_groups: *const c_char,
+        clock_video: bool,
+        clock_audio: bool,
+    }
+
+    #[repr(C)]
+    struct AudioFrameInterleaved16s {
+        sample_rate: i32,
+        no_channels: i32,
+        no_samples: i32,
+        timecode: i64,
+        reference_level: i32,
+        p_data: *mut i16,
+    }
+
+    /// Asks the runtime to timestamp frames as they arrive.
+    const TIMECODE_SYNTHESIZE: i64 = i64::MAX;
+
+    type InitializeFn = unsafe extern "C" fn() -> bool;
+    type DestroyFn = unsafe extern "C" fn();
+    type SendCreateFn = unsafe extern "C" fn(*const SendCreate) -> *mut c_void;
+    type SendDestroyFn = unsafe extern "C" fn(*mut c_void);
+    type SendAudioFn = unsafe extern "C" fn(*mut c_void, *const AudioFrameInterleaved16s);
+    type ConnectionsFn = unsafe extern "C" fn(*mut c_void, u32) -> i32;
+
+    pub(super) struct Runtime {
+        destroy: DestroyFn,
+        send_create: SendCreateFn,
+        send_destroy: SendDestroyFn,
+        send_audio: SendAudioFn,
+        connections: ConnectionsFn,
+        _library: Library,
+    }
+
+    /// An NDI sender, destroyed through the runtime that made it.
+    pub(super) struct SendInstance(*mut c_void);
+
+    // The NDI SDK lets a sender be used from any thread, one call at a time.
+    unsafe impl Send for SendInstance {}
+
+    impl Runtime {
+        pub(super) fn load() -> Result<Self, NdiError> {
+            let mut last = None;
+            for path in candidates() {
+                match unsafe { Library::new(&path) } {
+                    Ok(library) => return Self::resolve(library),
+                    Err(source) => last = Some((path, source)),
+                }
+            }
+            let (path, source) = last.expect("at least one library name");
+            Err(NdiError::Load {
+                library: path.to_string_lossy().into_owned(),
+                source,
+            })
+        }
+
+        fn resolve(library: Library) -> Result<Self, NdiError> {
+            fn symbol<T: Copy>(library: &Library, name: &'static str) -> Result<T, NdiError> {
+                let bytes = format!("{name}\0");
+                unsafe { library.get::<T>(bytes.as_bytes()) }
+                    .map(|symbol| *symbol)
+                    .map_err(|_| NdiError::Symbol(name))
+            }
+
+            let initialize: InitializeFn = symbol(&library, "NDIlib_initialize")?;
+            let destroy = symbol(&library, "NDIlib_destroy")?;
+            if !unsafe { initialize() } {
+                return Err(NdiError::Unsupported);
+            }
+            Ok(Self {
+                destroy,
+                send_create: symbol(&library, "NDIlib_send_create")?,
+                send_destroy: symbol(&library, "NDIlib_send_destroy")?,
+                send_audio: symbol(&library, "NDIlib_util_send_send_audio_interleaved_16s")?,
+                connections: symbol(&library, "NDIlib_send_get_no_connections")?,
+                _library: library,
+            })
+        }
+
+        pub(super) fn create(&self, name: &str) -> Result<SendInstance, NdiError> {
+            let create_error = || NdiError::Create {
+                name: name.to_string(),
+            };
+            let ndi_name = CString::new(name).map_err(|_| create_error())?;
+            // Frames go out as they are captured; the capture clock already paces them.
+            let settings = SendCreate {
+                p_ndi_name: ndi_name.as_ptr(),
+                p_groups: ptr::null(),
+                clock_video: false,
+                clock_audio: false,
+            };
+            let instance = unsafe { (self.send_create)(&settings) };
+            if instance.is_null() {
+                return Err(create_error());
+            }
+            Ok(SendInstance(instance))
+        }
+
+        pub(super) fn send_audio(&self, instance: &SendInstance, frame: &mut Frame) {
+            let channels = frame.format.channels.max(1) as usize;
+            let audio = AudioFrameInterleaved16s {
+                sample_rate: frame.format.sample_rate as i32,
+                no_channels: channels as i32,
+                no_samples: (frame.samples.len() / channels) as i32,
+                timecode: TIMECODE_SYNTHESIZE,
+                // Full scale maps to NDI's +4 dBu reference, as 1.0 in its float audio.
+                reference_level: 0,
+                p_data: frame.samples.as_mut_ptr(),
+            };
+            unsafe { (self.send_audio)(instance.0, &audio) };
+        }
+
+        /// Receivers connected right now.
+        pub(super) fn connections(&self, instance: &SendInstance) -> i32 {
+            unsafe { (self.connections)(instance.0, 0) }.max(0)
+        }
+
+        pub(super) fn destroy(&self, instance: SendInstance) {
+            unsafe { (self.send_destroy)(instance.0) };
+        }
+    }
+
+    impl Drop for Runtime {
+        fn drop(&mut self) {
+            unsafe { (self.destroy)() };
+        }
+    }
+
+    /// Where the runtime may be: the folder the NDI installer names in the environment,
+    /// then the library search path.
+    fn candidates() -> Vec<PathBuf> {
+        #[cfg(windows)]
+        const NAMES: &[&str] = &["Processing.NDI.Lib.x64.dll"];
+        #[cfg(target_os = "macos")]
+        const NAMES: &[&str] = &["libndi.dylib", "/usr/local/lib/libndi.dylib"];
+        #[cfg(not(any(windows, target_os = "macos")))]
+        const NAMES: &[&str] = &["libndi.so.6", "libndi.so.5", "libndi.so"];
+
+        let dirs: Vec<OsString> = ["NDI_RUNTIME_DIR_V6", "NDI_RUNTIME_DIR_V5"]
+            .iter()
+            .filter_map(std::env::var_os)
+            .collect();
+        let mut candidates = Vec::new();
+        for dir in &dirs {
+            candidates.extend(NAMES.iter().map(|name| PathBuf::from(dir).join(name)));
+        }
+        candidates.extend(NAMES.iter().map(PathBuf::from));
+        candidates
+    }
+}
@@ -0,0 +1,415 @@
+//! `--notify`: desktop notifications for the trouble a sender running in the background
+//! would otherwise only log.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use crossbeam_channel::{Receiver, Sender};
+use serde::Deserialize;
+use tracing::warn;
+use windows_sender::journal::{Event, EventJournal, EventKind};
+
+use crate::Args;
+
+/// Drop bursts within `DROP_WINDOW` that make audio dropping out worth a notification.
+const DROP_BURSTS: usize = 3;
+const DROP_WINDOW: Duration = Duration::from_secs(60);
+/// Notifications waiting to be shown; past this a burst of them is cut short.
+const QUEUE: usize = 8;
+
+/// The display shared by every session, so they all show through one tray icon.
+static DISPLAY: Mutex<Option<Sender<Notification>>> = Mutex::new(None);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotifyWhen {
+    /// When nobody watches a console: the Windows service, `--daemonize` and `--minimized`.
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug)]
+struct Notification {
+    title: String,
+    body: String,
+    /// Something went wrong, rather than came back.
+    warning: bool,
+}
+
+/// Shows notifications for the events of one session until its journal goes away. `label`
+/// goes in front of the titles when several sessions run.
+pub fn watch(args: &Args, label: &str, journal: &EventJournal) -> Result<()> {
+    if !enabled(args) {
+        return Ok(());
+    }
+    let display = display(in_service(args))?;
+    let events = journal.subscribe();
+    let label = label.to_string();
+    thread::Builder::new()
+        .name("notify".to_string())
+        .spawn(move || {
+            let mut state = State::default();
+            for event in events {
+                if let Some(mut notification) = state.on_event(&event, Instant::now()) {
+                    notification.title.insert_str(0, &label);
+                    let _ = display.try_send(notification);
+                }
+            }
+        })
+        .context("failed to spawn notify thread")?;
+    Ok(())
+}
+
+fn enabled(args: &Args) -> bool {
+    #[cfg(windows)]
+    let background = args.run_as_service;
+    #[cfg(unix)]
+    let background = args.daemonize;
+    match args.notify {
+        NotifyWhen::Auto => background || args.minimized,
+        NotifyWhen::Always => true,
+        NotifyWhen::Never => false,
+    }
+}
+
+#[cfg(windows)]
+fn in_service(args: &Args) -> bool {
+    args.run_as_service
+}
+
+#[cfg(not(windows))]
+fn in_service(_args: &Args) -> bool {
+    false
+}
+
+fn display(service: bool) -> Result<Sender<Notification>> {
+    let mut display = DISPLAY.lock().unwrap();
+    if let Some(tx) = &*display {
+        return Ok(tx.clone());
+    }
+    let (tx, rx) = crossbeam_channel::bounded(QUEUE);
+    thread::Builder::new()
+        .name("notify-display".to_string())
+        .spawn(move || show_all(&rx, service))
+        .context("failed to spawn notify thread")?;
+    *display = Some(tx.clone());
+    Ok(tx)
+}
+
+/// What has been notified, so a loss is told once and its recovery only after it.
+#[derive(Debug, Default)]
+struct State {
+    connection_lost: bool,
+    capture_lost: bool,
+    drop_bursts: VecDeque<Instant>,
+    drops_notified: Option<Instant>,
+}
+
+impl State {
+    fn on_event(&mut self, event: &Event, now: Instant) -> Option<Notification> {
+        let message = event.message.clone();
+        match event.kind {
+            EventKind::SendError if !std::mem::replace(&mut self.connection_lost, true) => {
+                Some(warning("Connection lost", message))
+            }
+            EventKind::Reconnect if std::mem::take(&mut self.connection_lost) => {
+                Some(recovery("Connection restored", message))
+            }
+            EventKind::Capture if !std::mem::replace(&mut self.capture_lost, true) => {
+                Some(warning("Capture lost", message))
+            }
+            EventKind::Device if std::mem::take(&mut self.capture_lost) => {
+                Some(recovery("Capture restored", message))
+            }
+            EventKind::Drops => {
+                self.drop_bursts.push_back(now);
+                while self
+                    .drop_bursts
+                    .front()
+                    .is_some_and(|&at| now - at > DROP_WINDOW)
+                {
+                    self.drop_bursts.pop_front();
+                }
+                let quiet = self.drops_notified.is_none_or(|at| now - at >= DROP_WINDOW);
+                if self.drop_bursts.len() < DROP_BURSTS || !quiet {
+                    return None;
+                }
+                self.drops_notified = Some(now);
+                let body = format!(
+                    "{} drop bursts in the last minute; the last one {message}",
+                    self.drop_bursts.len()
+                );
+                Some(warning("Audio dropping out", body))
+            }
+            EventKind::Alert if message == "cleared" => Some(recovery(
+                "Alert cleared",
+                "back under the alert thresholds".to_string(),
+            )),
+            EventKind::Alert => Some(warning("Alert", message)),
+            _ => None,
+        }
+    }
+}
+
+fn warning(title: &str, body: String) -> Notification {
+    Notification {
+        title: title.to_string(),
+        body,
+        warning: true,
+    }
+}
+
+fn recovery(title: &str, body: String) -> Notification {
+    Notification {
+        title: title.to_string(),
+        body,
+        warning: false,
+    }
+}
+
+/// A balloon from a tray icon in the user's session, which Windows 10 and later show as a
+/// toast. A service has no desktop of its own, so it sends a message box that closes by
+/// itself to whoever sits at the console instead.
+#[cfg(windows)]
+fn show_all(rx: &Receiver<Notification>, service: bool) {
+    use crossbeam_channel::RecvTimeoutError;
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        DispatchMessageW, PeekMessageW, MSG, PM_REMOVE,
+    };
+
+    /// The icon goes away this long after the last notification, so it does not outlive a
+    /// crash for long.
+    const ICON_LINGER: Duration = Duration::from_secs(30);
+    const PUMP_INTERVAL: Duration = Duration::from_millis(250);
+
+    if service {
+        for notification in rx {
+            tray::message_box(&notification);
+        }
+        return;
+    }
+    let mut icon: Option<tray::TrayIcon> = None;
+    let mut shown = Instant::now();
+    loop {
+        match rx.recv_timeout(PUMP_INTERVAL) {
+            Ok(notification) => {
+                if icon.is_none() {
+                    match tray::TrayIcon::add() {
+                        Ok(added) => icon = Some(added),
+                        Err(err) => warn!("failed to add the notification icon: {err}"),
+                    }
+                }
+                if let Some(icon) = &mut icon {
+                    icon.balloon(&notification);
+                    shown = Instant::now();
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if icon.is_some() && shown.elapsed() >= ICON_LINGER {
+                    icon = None;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        // SAFETY: all-zero is a valid `MSG`, and it outlives every call.
+        let mut msg: MSG = unsafe { std::mem::zeroed() };
+        while unsafe { PeekMessageW(&mut msg, std::ptr::null_mut(), 0, 0, PM_REMOVE) } != 0 {
+            unsafe { DispatchMessageW(&msg) };
+        }
+    }
+}
+
+#[cfg(windows)]
+mod tray {
+    use std::io;
+    use std::time::Duration;
+
+    use tracing::debug;
+    use windows_sys::Win32::System::RemoteDesktop::{
+        WTSGetActiveConsoleSessionId, WTSSendMessageW, WTS_CURRENT_SERVER_HANDLE,
+    };
+    use windows_sys::Win32::UI::Shell::{
+        Shell_NotifyIconW, NIF_ICON, NIF_INFO, NIF_TIP, NIIF_INFO, NIIF_WARNING, NIM_ADD,
+        NIM_DELETE, NIM_MODIFY, NOTIFYICONDATAW,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        CreateWindowExW, DestroyWindow, LoadIconW, HWND_MESSAGE, IDI_APPLICATION,
+        MB_ICONINFORMATION, MB_ICONWARNING, MB_OK,
+    };
+
+    use super::Notification;
+
+    /// How long a message box from the service stays up unanswered.
+    const MESSAGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// A tray icon on a message-only window, there only to carry balloons.
+    pub struct TrayIcon {
+        data: NOTIFYICONDATAW,
+    }
+
+    impl TrayIcon {
+        pub fn add() -> io::Result<Self> {
+            let class = wide("STATIC");
+            let name = wide("windows-sender");
+            // SAFETY: both strings are NUL-terminated and outlive the call; the predefined
+            // STATIC class needs no registration.
+            let window = unsafe {
+                CreateWindowExW(
+                    0,
+                    class.as_ptr(),
+                    name.as_ptr(),
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    HWND_MESSAGE,
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                )
+            };
+            if window.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: all-zero is a valid `NOTIFYICONDATAW`.
+            let mut data: NOTIFYICONDATAW = unsafe { std::mem::zeroed() };
+            data.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+            data.hWnd = window;
+            data.uID = 1;
+            data.uFlags = NIF_ICON | NIF_TIP;
+            // SAFETY: a stock icon, loaded without a module.
+            data.hIcon = unsafe { LoadIconW(std::ptr::null_mut(), IDI_APPLICATION) };
+            copy_wide(&mut data.szTip, "windows-sender");
+            // SAFETY: `data` is fully initialized and outlives the call.
+            if unsafe { Shell_NotifyIconW(NIM_ADD, &data) } == 0 {
+                let err = io::Error::last_os_error();
+                unsafe { DestroyWindow(window) };
+                return Err(err);
+            }
+            Ok(Self { data })
+        }
+
+        pub fn balloon(&mut self, notification: &Notification) {
+            self.data.uFlags = NIF_INFO;
+            copy_wide(&mut self.data.szInfoTitle, &notification.title);
+            copy_wide(&mut self.data.szInfo, &notification.body);
+            self.data.dwInfoFlags = if notification.warning {
+                NIIF_WARNING
+            } else {
+                NIIF_INFO
+            };
+            // SAFETY: as in `add`.
+            if unsafe { Shell_NotifyIconW(NIM_MODIFY, &self.data) } == 0 {
+                debug!(
+                    "failed to show notification: {}",
+                    io::Error::last_os_error()
+                );
+            }
+        }
+    }
+
+    impl Drop for TrayIcon {
+        fn drop(&mut self) {
+            // SAFETY: the icon and window were created by `add`.
+            unsafe {
+                Shell_NotifyIconW(NIM_DELETE, &self.data);
+                DestroyWindow(self.data.hWnd);
+            }
+        }
+    }
+
+    pub fn message_box(notification: &Notification) {
+        // SAFETY: takes no pointers.
+        let session = unsafe { WTSGetActiveConsoleSessionId() };
+        if session == u32::MAX {
+            return;
+        }
+        let title: Vec<u16> = notification.title.encode_utf16().collect();
+        let body: Vec<u16> = notification.body.encode_utf16().collect();
+        let icon = if notification.warning {
+            MB_ICONWARNING
+        } else {
+            MB_ICONINFORMATION
+        };
+        let mut response = 0;
+        // SAFETY: the lengths are those of the buffers, in bytes; without waiting the call
+        // returns as soon as the box is up.
+        let ok = unsafe {
+            WTSSendMessageW(
+                WTS_CURRENT_SERVER_HANDLE,
+                session,
+                title.as_ptr(),
+                (title.len() * 2) as u32,
+                body.as_ptr(),
+                (body.len() * 2) as u32,
+                MB_OK | icon,
+                MESSAGE_TIMEOUT.as_secs() as u32,
+                &mut response,
+                0,
+            )
+        };
+        if ok == 0 {
+            debug!(
+                "failed to show notification: {}",
+                io::Error::last_os_error()
+            );
+        }
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain([0]).collect()
+    }
+
+    /// Copies as much of `text` as fits into a fixed, NUL-terminated buffer.
+    fn copy_wide(buffer: &mut [u16], text: &str) {
+        let len = buffer.len() - 1;
+        let mut end = 0;
+        for (slot, unit) in buffer[..len].iter_mut().zip(text.encode_utf16()) {
+            *slot = unit;
+            end += 1;
+        }
+        buffer[end] = 0;
+    }
+}
+
+/// `notify-send`, which most Linux desktops answer; without it notifications are only
+/// logged as missing once.
+#[cfg(not(windows))]
+fn show_all(rx: &Receiver<Notification>, _service: bool) {
+    use std::process::Command;
+
+    use tracing::debug;
+
+    let mut missing = false;
+    for notification in rx {
+        let (urgency, icon) = if notification.warning {
+            ("normal", "dialog-warning")
+        } else {
+            ("low", "dialog-information")
+        };
+        let result = Command::new("notify-send")
+            .arg("--app-name=windows-sender")
+            .arg(format!("--urgency={urgency}"))
+            .arg(format!("--icon={icon}"))
+            .arg(&notification.title)
+            .arg(&notification.body)
+            .status();
+        match result {
+            Ok(status) if !status.success() => {
+                debug!("notify-send exited with {status}");
+            }
+            Ok(_) => {}
+            Err(err) if !missing => {
+                warn!("notify-send failed: {err}; no desktop notifications");
+                missing = true;
+            }
+            Err(_) => {}
+        }
+    }
+}
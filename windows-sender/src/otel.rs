@@ -0,0 +1,234 @@
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use opentelemetry::metrics::{AsyncInstrument, Meter, MeterProvider as _};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use tracing::warn;
+use windows_sender::stats::{HistogramSnapshot, SenderStats, SenderStatsSnapshot};
+
+const SERVICE_NAME: &str = "windows-sender";
+const EXPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A running session whose counters are reported on every metrics export.
+struct Watched {
+    session: String,
+    stats: Arc<SenderStats>,
+    backlog: Box<dyn Fn() -> usize + Send>,
+    capsend: HistogramSnapshot,
+    sock: HistogramSnapshot,
+    playout: HistogramSnapshot,
+    send_jitter: HistogramSnapshot,
+}
+
+static WATCHED: Mutex<Vec<Watched>> = Mutex::new(Vec::new());
+
+/// OTLP/HTTP exporters for spans and metrics; flushed when dropped.
+pub struct Telemetry {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+}
+
+impl Telemetry {
+    /// Connects to a collector at `endpoint` (e.g. `http://collector:4318`).
+    pub fn init(endpoint: &str) -> Result<Self> {
+        let endpoint = endpoint.trim_end_matches('/');
+        let resource = Resource::builder().with_service_name(SERVICE_NAME).build();
+
+        let spans = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{endpoint}/v1/traces"))
+            .build()
+            .context("failed to create OTLP span exporter")?;
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_batch_exporter(spans)
+            .with_resource(resource.clone())
+            .build();
+
+        let metrics = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{endpoint}/v1/metrics"))
+            .build()
+            .context("failed to create OTLP metric exporter")?;
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(
+                PeriodicReader::builder(metrics)
+                    .with_interval(EXPORT_INTERVAL)
+                    .build(),
+            )
+            .with_resource(resource)
+            .build();
+        register_instruments(&meter_provider.meter(SERVICE_NAME));
+
+        Ok(Self {
+            tracer_provider,
+            meter_provider,
+        })
+    }
+
+    pub fn tracer(&self) -> SdkTracer {
+        self.tracer_provider.tracer(SERVICE_NAME)
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(err) = self.meter_provider.shutdown() {
+            warn!("failed to flush OTLP metrics: {err}");
+        }
+        if let Err(err) = self.tracer_provider.shutdown() {
+            warn!("failed to flush OTLP spans: {err}");
+        }
+    }
+}
+
+/// Reports `stats` under `session`, replacing whatever a previous start of it registered.
+pub fn watch<B>(session: &str, stats: &Arc<SenderStats>, backlog: B)
+where
+    B: Fn() -> usize + Send + 'static,
+{
+    let mut watched = WATCHED.lock().unwrap_or_else(|err| err.into_inner());
+    watched.retain(|entry| entry.session != session);
+    watched.push(Watched {
+        session: session.to_string(),
+        stats: Arc::clone(stats),
+        backlog: Box::new(backlog),
+        capsend: stats.capture_to_send_hist.snapshot(),
+        sock: stats.socket_send_hist.snapshot(),
+        playout: stats.capture_to_playout_hist.snapshot(),
+        send_jitter: stats.send_jitter_hist.snapshot(),
+    });
+}
+
+fn for_each_session(mut f: impl FnMut(&mut Watched, KeyValue)) {
+    let mut watched = WATCHED.lock().unwrap_or_else(|err| err.into_inner());
+    for entry in watched.iter_mut() {
+        let session = KeyValue::new("session", entry.session.clone());
+        f(entry, session);
+    }
+}
+
+fn counter(
+    meter: &Meter,
+    name: &'static str,
+    unit: &'static str,
+    read: fn(&SenderStatsSnapshot) -> u64,
+) {
+    meter
+        .u64_observable_counter(name)
+        .with_unit(unit)
+        .with_callback(move |observer: &dyn AsyncInstrument<u64>| {
+            for_each_session(|entry, session| {
+                observer.observe(read(&entry.stats.snapshot()), &[session]);
+            });
+        })
+        .build();
+}
+
+fn register_instruments(meter: &Meter) {
+    counter(meter, "audio.capture.samples", "{sample}", |s| {
+        s.captured_samples
+    });
+    counter(meter, "audio.capture.drops", "{chunk}", |s| s.capture_drops);
+    counter(meter, "audio.sender.late_dropped", "us", |s| {
+        s.late_dropped_us
+    });
+    counter(meter, "audio.sender.capped", "us", |s| s.capped_us);
+    counter(meter, "audio.capture.discontinuities", "{buffer}", |s| {
+        s.capture_discontinuities
+    });
+    counter(meter, "audio.capture.timestamp_errors", "{buffer}", |s| {
+        s.capture_timestamp_errors
+    });
+    counter(meter, "audio.sender.packets", "{packet}", |s| {
+        s.sent_packets
+    });
+    counter(meter, "audio.sender.bytes", "By", |s| s.sent_bytes);
+    counter(meter, "audio.sender.restarts", "{restart}", |s| s.restarts);
+
+    meter
+        .u64_observable_gauge("audio.sender.backlog")
+        .with_description("Capture chunks waiting for the send loop")
+        .with_unit("{chunk}")
+        .with_callback(|observer| {
+            for_each_session(|entry, session| {
+                observer.observe((entry.backlog)() as u64, &[session]);
+            });
+        })
+        .build();
+
+    meter
+        .u64_observable_gauge("audio.sender.buffered")
+        .with_description("Audio held between capture and the sockets")
+        .with_unit("us")
+        .with_callback(|observer| {
+            for_each_session(|entry, session| {
+                let buffered = entry.stats.buffered_audio_us.load(Ordering::Relaxed);
+                observer.observe(buffered, &[session]);
+            });
+        })
+        .build();
+
+    meter
+        .u64_observable_gauge("audio.sender.buffer_memory")
+        .with_description("Approximate heap taken by the buffers holding audio")
+        .with_unit("By")
+        .with_callback(|observer| {
+            for_each_session(|entry, session| {
+                let bytes = entry.stats.buffer_bytes.load(Ordering::Relaxed);
+                observer.observe(bytes, &[session]);
+            });
+        })
+        .build();
+
+    meter
+        .f64_observable_gauge("audio.sender.latency")
+        .with_description("Latency percentiles since the previous export")
+        .with_unit("ms")
+        .with_callback(|observer| {
+            for_each_session(|entry, session| {
+                let capsend = entry.stats.capture_to_send_hist.snapshot();
+                let sock = entry.stats.socket_send_hist.snapshot();
+                let playout = entry.stats.capture_to_playout_hist.snapshot();
+                let send_jitter = entry.stats.send_jitter_hist.snapshot();
+                let stages = [
+                    ("capture_to_send", capsend.since(&entry.capsend)),
+                    ("socket_send", sock.since(&entry.sock)),
+                    ("capture_to_playout", playout.since(&entry.playout)),
+                    ("send_jitter", send_jitter.since(&entry.send_jitter)),
+                ];
+                for (stage, interval) in stages {
+                    if interval.count() == 0 && stage == "capture_to_playout" {
+                        continue;
+                    }
+                    let p = interval.percentiles();
+                    for (quantile, value) in [
+                        ("p50", p.p50_ms),
+                        ("p95", p.p95_ms),
+                        ("p99", p.p99_ms),
+                        ("max", p.max_ms),
+                    ] {
+                        observer.observe(
+                            value,
+                            &[
+                                session.clone(),
+                                KeyValue::new("stage", stage),
+                                KeyValue::new("quantile", quantile),
+                            ],
+                        );
+                    }
+                }
+                entry.capsend = capsend;
+                entry.sock = sock;
+                entry.playout = playout;
+                entry.send_jitter = send_jitter;
+            });
+        })
+        .build();
+}
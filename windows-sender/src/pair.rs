@@ -0,0 +1,85 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use anyhow::{bail, Context, Result};
+use windows_sender::crypto::Key;
+use windows_sender::resolve::Endpoint;
+use windows_sender::transport::{self, TransportKind};
+
+use crate::config::SessionArgs;
+use crate::keychain;
+use crate::qr::QrCode;
+use crate::{Args, Transport};
+
+/// Prints the `--pair` QR code: an `audiolink://pair` link with this machine's address and
+/// everything the receiver needs to play the stream.
+pub fn print(sessions: &[SessionArgs]) -> Result<()> {
+    let [session] = sessions else {
+        bail!("--pair sets up one receiver; it cannot be used with [[session]] tables");
+    };
+    let args = &session.args;
+    if !TransportKind::from(args.transport).is_native() {
+        bail!(
+            "--pair sets up the Android receiver; VBAN, Scream and AES67 receivers need no setup"
+        );
+    }
+    if args.noise.is_some() {
+        bail!("--pair hands over a pre-shared key; it cannot be combined with noise");
+    }
+    let key = keychain::session_key(args)?.context("--pair needs a key")?;
+    // Over adb the phone reaches this machine on its own loopback.
+    let host = match args.transport {
+        Transport::Adb => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        _ => {
+            let target_ip = args
+                .target_ip
+                .as_deref()
+                .context("--target-ip is required for --pair")?;
+            local_ip(Endpoint::new(target_ip, args.port, args.prefer_ip.into()).resolve()?)?
+        }
+    };
+
+    let link = link(host, args, &key);
+    let code = QrCode::encode(link.as_bytes()).context("pairing link too long for a QR code")?;
+    println!("{}", code.render());
+    println!("Scan with the phone to set up the receiver, or open {link}");
+    println!("Key fingerprint: {}", key.fingerprint());
+    Ok(())
+}
+
+fn link(host: IpAddr, args: &Args, key: &Key) -> String {
+    let mut link = format!(
+        "audiolink://pair?host={host}&port={}&transport={}&key={}&fp={}",
+        args.port,
+        TransportKind::from(args.transport).as_str(),
+        key.to_hex(),
+        key.fingerprint().replace(' ', ""),
+    );
+    if let Some(token) = &args.access_token {
+        link.push_str("&token=");
+        link.push_str(&percent_encode(token));
+    }
+    if args.remote_volume {
+        link.push_str("&volume=1");
+    }
+    link
+}
+
+/// The address the route to `target` leaves from; connecting a UDP socket sends nothing.
+fn local_ip(target: SocketAddr) -> Result<IpAddr> {
+    let socket = transport::bind_for(target).context("failed to bind a UDP socket")?;
+    socket
+        .connect(target)
+        .with_context(|| format!("no route to {target}"))?;
+    Ok(socket.local_addr()?.ip())
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,402 @@
+//! Pairing over the audio socket: a Noise XX handshake (see `crypto::noise`) that runs
+//! before the first packet, pins the peer's static key on first contact, and hands the
+//! receiver the key this run of the sender seals its stream with.
+//!
+//! Handshake frames share the socket with audio and feedback; their magic tells them
+//! apart. Over TCP they carry the same 2-byte length prefix as packets.
+//!
+//! ```text
+//! "AUDH" | version (1) | kind (1) | reserved (2) | Noise message
+//! ```
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info, warn};
+
+use crate::crypto::noise::{Identity, Initiator, KeyPin, KnownPeers, PublicKey, Responder};
+use crate::crypto::{Key, KEY_LEN};
+use crate::error::{CryptoError, TransportError};
+use crate::transport::is_timeout;
+
+pub const HANDSHAKE_MAGIC: [u8; 4] = *b"AUDH";
+const VERSION: u8 = 1;
+const FRAME_HEADER: usize = 8;
+
+const HELLO: u8 = 1;
+const REPLY: u8 = 2;
+const FINISH: u8 = 3;
+const ACCEPTED: u8 = 4;
+const REJECTED: u8 = 5;
+
+/// How long a UDP sender waits for an answer before sending its message again.
+const UDP_RETRY: Duration = Duration::from_millis(500);
+const UDP_ATTEMPTS: u32 = 6;
+const TCP_TIMEOUT: Duration = Duration::from_secs(3);
+/// Half-finished handshakes a receiver keeps; more than this and it starts over.
+const MAX_PENDING: usize = 16;
+
+/// A static identity plus the file where the keys of paired peers are pinned.
+#[derive(Clone, Debug)]
+pub struct NoiseConfig {
+    pub identity: Identity,
+    pub known_peers: PathBuf,
+    /// Receivers a sender trusts from the start; when set, `known_peers` is not used.
+    pub pins: Vec<KeyPin>,
+}
+
+impl NoiseConfig {
+    /// `dir/identity`, created on first use, and the receivers pinned in
+    /// `dir/known_receivers`.
+    pub fn sender(dir: &Path) -> Result<Self, CryptoError> {
+        Self::load(dir, "known_receivers")
+    }
+
+    /// `dir/identity`, created on first use, and the senders pinned in `dir/known_senders`.
+    pub fn receiver(dir: &Path) -> Result<Self, CryptoError> {
+        Self::load(dir, "known_senders")
+    }
+
+    fn load(dir: &Path, peers: &str) -> Result<Self, CryptoError> {
+        fs::create_dir_all(dir).map_err(|source| CryptoError::Io {
+            path: dir.to_path_buf(),
+            source,
+        })?;
+        Ok(Self {
+            identity: Identity::load_or_create(&dir.join("identity"))?,
+            known_peers: dir.join(peers),
+            pins: Vec::new(),
+        })
+    }
+}
+
+pub fn is_handshake(packet: &[u8]) -> bool {
+    packet.starts_with(&HANDSHAKE_MAGIC)
+}
+
+fn frame(kind: u8, message: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER + message.len());
+    frame.extend_from_slice(&HANDSHAKE_MAGIC);
+    frame.extend_from_slice(&[VERSION, kind, 0, 0]);
+    frame.extend_from_slice(message);
+    frame
+}
+
+fn parse_frame(packet: &[u8]) -> Option<(u8, &[u8])> {
+    if packet.len() < FRAME_HEADER || !is_handshake(packet) || packet[4] != VERSION {
+        return None;
+    }
+    Some((packet[5], &packet[FRAME_HEADER..]))
+}
+
+/// The sender's half, shared by every target of one `AudioSender`.
+pub struct SenderPairing {
+    identity: Identity,
+    peers: Mutex<KnownPeers>,
+    pins: Vec<KeyPin>,
+    stream_key: Key,
+}
+
+impl SenderPairing {
+    pub fn new(config: NoiseConfig, stream_key: Key) -> Result<Self, CryptoError> {
+        Ok(Self {
+            peers: Mutex::new(KnownPeers::load(&config.known_peers)?),
+            identity: config.identity,
+            pins: config.pins,
+            stream_key,
+        })
+    }
+
+    /// Pairs before anything else reads from `socket`, so no answer goes missing.
+    pub(crate) fn pair_udp(
+        &self,
+        socket: &UdpSocket,
+        target: SocketAddr,
+    ) -> Result<(), TransportError> {
+        let mut link = UdpLink {
+            socket,
+            target,
+            deadline: Instant::now(),
+        };
+        self.pair(target, &mut link)
+    }
+
+    pub(crate) fn pair_tcp(
+        &self,
+        stream: &mut TcpStream,
+        target: SocketAddr,
+    ) -> Result<(), TransportError> {
+        stream
+            .set_read_timeout(Some(TCP_TIMEOUT))
+            .map_err(|source| TransportError::Configure {
+                what: "handshake timeout on sender socket",
+                source,
+            })?;
+        self.pair(target, &mut TcpLink { stream })
+    }
+
+    fn pair(&self, target: SocketAddr, link: &mut dyn Link) -> Result<(), TransportError> {
+        let failed = |source| TransportError::Handshake {
+            addr: target,
+            source,
+        };
+        let (initiator, hello) = Initiator::start(&self.identity).map_err(failed)?;
+        let (_, reply) = exchange(link, target, &frame(HELLO, &hello), &[REPLY])?;
+        let (receiver_key, finish) = initiator
+            .finish(&reply, self.stream_key.as_bytes())
+            .map_err(failed)?;
+        self.trust(target, receiver_key).map_err(failed)?;
+        match exchange(link, target, &frame(FINISH, &finish), &[ACCEPTED, REJECTED])? {
+            (ACCEPTED, _) => Ok(()),
+            _ => Err(failed(CryptoError::Rejected)),
+        }
+    }
+
+    /// Pins an unknown receiver; refuses one whose key changed. With fingerprints given,
+    /// only a receiver matching one of them is trusted, on first contact too.
+    fn trust(&self, target: SocketAddr, key: PublicKey) -> Result<(), CryptoError> {
+        let label = target.to_string();
+        if !self.pins.is_empty() {
+            return if self.pins.contains(&KeyPin::of(&key)) {
+                Ok(())
+            } else {
+                Err(CryptoError::PinMismatch {
+                    key: key.to_string(),
+                })
+            };
+        }
+        let mut peers = self.peers.lock().unwrap();
+        match peers.get(&label) {
+            Some(known) if known == key => Ok(()),
+            Some(_) => Err(CryptoError::PeerKeyChanged {
+                peer: label,
+                path: peers.path().to_path_buf(),
+            }),
+            None => {
+                info!("paired with receiver {target}, key {key}");
+                peers.pin(&label, key)
+            }
+        }
+    }
+}
+
+/// Sends `frame` until an answer of one of the `expected` kinds arrives.
+fn exchange(
+    link: &mut dyn Link,
+    target: SocketAddr,
+    frame: &[u8],
+    expected: &[u8],
+) -> Result<(u8, Vec<u8>), TransportError> {
+    for _ in 0..link.attempts() {
+        link.send(frame).map_err(|source| TransportError::Send {
+            what: "handshake message",
+            seq: 0,
+            source,
+        })?;
+        while let Some(packet) = link.recv().map_err(TransportError::Receive)? {
+            match parse_frame(&packet) {
+                Some((kind, message)) if expected.contains(&kind) => {
+                    return Ok((kind, message.to_vec()))
+                }
+                _ => {}
+            }
+        }
+    }
+    Err(TransportError::Handshake {
+        addr: target,
+        source: CryptoError::NoReply,
+    })
+}
+
+trait Link {
+    fn send(&mut self, frame: &[u8]) -> io::Result<()>;
+    /// The next packet from the peer, or `None` once it is time to send again.
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>>;
+    fn attempts(&self) -> u32;
+}
+
+struct UdpLink<'a> {
+    socket: &'a UdpSocket,
+    target: SocketAddr,
+    deadline: Instant,
+}
+
+impl Link for UdpLink<'_> {
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.deadline = Instant::now() + UDP_RETRY;
+        self.socket.send_to(frame, self.target).map(drop)
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = [0u8; 512];
+        loop {
+            let left = self.deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(None);
+            }
+            self.socket.set_read_timeout(Some(left))?;
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, from)) if from == self.target => return Ok(Some(buf[..len].to_vec())),
+                Ok(_) => {}
+                Err(err) if is_timeout(&err) => return Ok(None),
+                // Windows reports ICMP port unreachable here while nobody listens.
+                Err(err) if err.kind() == io::ErrorKind::ConnectionReset => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn attempts(&self) -> u32 {
+        UDP_ATTEMPTS
+    }
+}
+
+struct TcpLink<'a> {
+    stream: &'a mut TcpStream,
+}
+
+impl Link for TcpLink<'_> {
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.stream.write_all(&(frame.len() as u16).to_le_bytes())?;
+        self.stream.write_all(frame)
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut len = [0u8; 2];
+        match self.stream.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if is_timeout(&err) => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let mut packet = vec![0u8; u16::from_le_bytes(len) as usize];
+        self.stream.read_exact(&mut packet)?;
+        Ok(Some(packet))
+    }
+
+    /// TCP already retransmits.
+    fn attempts(&self) -> u32 {
+        1
+    }
+}
+
+struct Pending {
+    hello: Vec<u8>,
+    reply: Vec<u8>,
+    responder: Responder,
+}
+
+/// The receiver's half: answers handshakes from any sender. While no sender is pinned,
+/// the first one to finish is; after that only pinned senders are accepted.
+pub(crate) struct ReceiverPairing {
+    identity: Identity,
+    peers: KnownPeers,
+    pending: HashMap<SocketAddr, Pending>,
+    /// The last finish message and its answer, resent if the answer got lost.
+    last_finish: Option<(SocketAddr, Vec<u8>, Vec<u8>)>,
+}
+
+impl ReceiverPairing {
+    pub(crate) fn new(config: NoiseConfig) -> Result<Self, CryptoError> {
+        let key = config.identity.public_key();
+        info!(
+            "pairing as {key}; senders can pin it as {}",
+            KeyPin::of(&key)
+        );
+        Ok(Self {
+            peers: KnownPeers::load(&config.known_peers)?,
+            identity: config.identity,
+            pending: HashMap::new(),
+            last_finish: None,
+        })
+    }
+
+    /// The frame to answer `packet` with, and the stream key once a sender is accepted.
+    pub(crate) fn handle(
+        &mut self,
+        from: SocketAddr,
+        packet: &[u8],
+    ) -> Option<(Vec<u8>, Option<Key>)> {
+        let (kind, message) = parse_frame(packet)?;
+        match kind {
+            HELLO => {
+                if let Some(pending) = self.pending.get(&from) {
+                    if pending.hello == message {
+                        return Some((frame(REPLY, &pending.reply), None));
+                    }
+                }
+                let (responder, reply) = Responder::accept(&self.identity, message)
+                    .map_err(|err| debug!("handshake from {from}: {err}"))
+                    .ok()?;
+                if self.pending.len() >= MAX_PENDING {
+                    self.pending.clear();
+                }
+                let answer = frame(REPLY, &reply);
+                self.pending.insert(
+                    from,
+                    Pending {
+                        hello: message.to_vec(),
+                        reply,
+                        responder,
+                    },
+                );
+                Some((answer, None))
+            }
+            FINISH => {
+                if let Some((addr, finish, answer)) = &self.last_finish {
+                    if *addr == from && finish == message {
+                        return Some((answer.clone(), None));
+                    }
+                }
+                let pending = self.pending.remove(&from)?;
+                let (sender_key, payload) = pending
+                    .responder
+                    .finish(message)
+                    .map_err(|err| debug!("handshake from {from}: {err}"))
+                    .ok()?;
+                let stream_key = <[u8; KEY_LEN]>::try_from(payload)
+                    .ok()
+                    .filter(|_| self.trust(from, sender_key))
+                    .map(Key::from_bytes);
+                let answer = frame(
+                    if stream_key.is_some() {
+                        ACCEPTED
+                    } else {
+                        REJECTED
+                    },
+                    &[],
+                );
+                self.last_finish = Some((from, message.to_vec(), answer.clone()));
+                Some((answer, stream_key))
+            }
+            _ => None,
+        }
+    }
+
+    fn trust(&mut self, from: SocketAddr, key: PublicKey) -> bool {
+        if self.peers.contains(&key) {
+            return true;
+        }
+        if !self.peers.is_empty() {
+            warn!(
+                "rejecting sender {from}: its key {key} is not in {}",
+                self.peers.path().display()
+            );
+            return false;
+        }
+        match self.peers.pin(&from.ip().to_string(), key) {
+            Ok(()) => {
+                info!("paired with sender {from}, key {key}");
+                true
+            }
+            Err(err) => {
+                warn!("failed to pin sender {from}: {err}");
+                false
+            }
+        }
+    }
+}
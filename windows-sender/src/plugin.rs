@@ -0,0 +1,280 @@
+#[cfg(plugin_loader)]
+use std::ffi::CStr;
+use std::ffi::{c_char, c_void};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Arc;
+
+#[cfg(plugin_loader)]
+use libloading::Library;
+
+use crate::error::PluginError;
+#[cfg(plugin_loader)]
+use crate::protocol::{CODEC_IEC61937, CODEC_PCM16};
+
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+pub const AUDIO_PLUGIN_EFFECT: u32 = 0;
+pub const AUDIO_PLUGIN_CODEC: u32 = 1;
+
+/// Symbol every plugin library exports; see `include/audio_link_plugin.h`.
+pub const PLUGIN_ENTRY: &[u8] = b"audio_link_plugin_descriptor\0";
+
+/// Function table a plugin hands to the sender.
+///
+/// Effects get `process` (interleaved PCM16, in place, 0 on success). Codecs get
+/// `encode` (bytes written to `out`, negative on error) and a `codec_id` that goes
+/// into the packet header, other than 0 (PCM16) and 255 (IEC 61937); Opus codecs take 1
+/// (`CODEC_OPUS`).
+/// `create`/`destroy` are optional.
+#[repr(C)]
+pub struct AudioPluginDescriptor {
+    pub abi_version: u32,
+    pub kind: u32,
+    pub name: *const c_char,
+    pub codec_id: u8,
+    pub create: Option<unsafe extern "C" fn(sample_rate: u32, channels: u32) -> *mut c_void>,
+    pub destroy: Option<unsafe extern "C" fn(state: *mut c_void)>,
+    pub process:
+        Option<unsafe extern "C" fn(state: *mut c_void, samples: *mut i16, len: usize) -> i32>,
+    pub encode: Option<
+        unsafe extern "C" fn(
+            state: *mut c_void,
+            samples: *const i16,
+            len: usize,
+            out: *mut u8,
+            out_capacity: usize,
+        ) -> isize,
+    >,
+}
+
+#[cfg(plugin_loader)]
+type EntryFn = unsafe extern "C" fn() -> *const AudioPluginDescriptor;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PluginKind {
+    Effect,
+    Codec,
+}
+
+impl PluginKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PluginKind::Effect => "effect",
+            PluginKind::Codec => "codec",
+        }
+    }
+}
+
+#[cfg_attr(not(plugin_loader), allow(dead_code))]
+struct Loaded {
+    descriptor: *const AudioPluginDescriptor,
+    name: String,
+    path: PathBuf,
+    kind: PluginKind,
+    #[cfg(plugin_loader)]
+    _library: Library,
+}
+
+// The descriptor is static data inside the library, which lives as long as `Loaded`.
+unsafe impl Send for Loaded {}
+unsafe impl Sync for Loaded {}
+
+/// A loaded plugin library. Clones share the library, which stays loaded until
+/// the last clone and every instance created from it are dropped.
+#[derive(Clone)]
+pub struct Plugin {
+    inner: Arc<Loaded>,
+}
+
+impl fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Plugin")
+            .field("name", &self.inner.name)
+            .field("kind", &self.inner.kind)
+            .field("path", &self.inner.path)
+            .finish()
+    }
+}
+
+impl Plugin {
+    /// Loads a plugin library. Plugins run arbitrary native code inside the sender,
+    /// so only load libraries you trust.
+    #[cfg(not(plugin_loader))]
+    pub fn load(_path: &Path) -> Result<Self, PluginError> {
+        Err(PluginError::Disabled)
+    }
+
+    /// Loads a plugin library. Plugins run arbitrary native code inside the sender,
+    /// so only load libraries you trust.
+    #[cfg(plugin_loader)]
+    pub fn load(path: &Path) -> Result<Self, PluginError> {
+        let load_error = |source| PluginError::Load {
+            path: path.to_path_buf(),
+            source,
+        };
+        let invalid = |reason: &str| PluginError::Invalid {
+            path: path.to_path_buf(),
+            reason: reason.to_string(),
+        };
+
+        let library = unsafe { Library::new(path) }.map_err(load_error)?;
+        let entry: EntryFn =
+            *unsafe { library.get::<EntryFn>(PLUGIN_ENTRY) }.map_err(load_error)?;
+        let descriptor = unsafe { entry() };
+        if descriptor.is_null() {
+            return Err(invalid("descriptor is null"));
+        }
+        let desc = unsafe { &*descriptor };
+        if desc.abi_version != PLUGIN_ABI_VERSION {
+            return Err(invalid(&format!(
+                "ABI version {} (expected {PLUGIN_ABI_VERSION})",
+                desc.abi_version
+            )));
+        }
+        let kind = match desc.kind {
+            AUDIO_PLUGIN_EFFECT if desc.process.is_some() => PluginKind::Effect,
+            AUDIO_PLUGIN_EFFECT => return Err(invalid("effect plugin without process")),
+            AUDIO_PLUGIN_CODEC if desc.encode.is_none() => {
+                return Err(invalid("codec plugin without encode"))
+            }
+            AUDIO_PLUGIN_CODEC if desc.codec_id == CODEC_PCM16 => {
+                return Err(invalid("codec id 0 is reserved for PCM16"))
+            }
+            AUDIO_PLUGIN_CODEC if desc.codec_id == CODEC_IEC61937 => {
+                return Err(invalid(
+                    "codec id 255 is reserved for IEC 61937 passthrough",
+                ))
+            }
+            AUDIO_PLUGIN_CODEC => PluginKind::Codec,
+            other => return Err(invalid(&format!("unknown plugin kind {other}"))),
+        };
+        if desc.name.is_null() {
+            return Err(invalid("name is null"));
+        }
+        let name = unsafe { CStr::from_ptr(desc.name) }
+            .to_string_lossy()
+            .into_owned();
+
+        Ok(Self {
+            inner: Arc::new(Loaded {
+                descriptor,
+                name,
+                path: path.to_path_buf(),
+                kind,
+                _library: library,
+            }),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    pub fn kind(&self) -> PluginKind {
+        self.inner.kind
+    }
+
+    /// Packet header codec id; only meaningful for codec plugins.
+    pub fn codec_id(&self) -> u8 {
+        self.descriptor().codec_id
+    }
+
+    /// Creates per-stream plugin state for the given capture format.
+    pub fn instantiate(
+        &self,
+        sample_rate: u32,
+        channels: u32,
+    ) -> Result<PluginInstance, PluginError> {
+        let state = match self.descriptor().create {
+            Some(create) => {
+                let state = unsafe { create(sample_rate, channels) };
+                if state.is_null() {
+                    return Err(PluginError::Create {
+                        name: self.inner.name.clone(),
+                    });
+                }
+                state
+            }
+            None => ptr::null_mut(),
+        };
+        Ok(PluginInstance {
+            plugin: self.clone(),
+            state,
+        })
+    }
+
+    fn descriptor(&self) -> &AudioPluginDescriptor {
+        unsafe { &*self.inner.descriptor }
+    }
+}
+
+/// Plugin state for one stream; used from the send loop only.
+pub struct PluginInstance {
+    plugin: Plugin,
+    state: *mut c_void,
+}
+
+unsafe impl Send for PluginInstance {}
+
+impl PluginInstance {
+    pub fn plugin(&self) -> &Plugin {
+        &self.plugin
+    }
+
+    /// Runs an effect plugin over interleaved samples in place.
+    pub fn process(&mut self, samples: &mut [i16]) -> Result<(), PluginError> {
+        let Some(process) = self.plugin.descriptor().process else {
+            return Ok(());
+        };
+        let code = unsafe { process(self.state, samples.as_mut_ptr(), samples.len()) };
+        if code != 0 {
+            return Err(self.failed(code as i64));
+        }
+        Ok(())
+    }
+
+    /// Encodes one frame with a codec plugin, replacing the contents of `out`.
+    pub fn encode(&mut self, samples: &[i16], out: &mut Vec<u8>) -> Result<(), PluginError> {
+        let Some(encode) = self.plugin.descriptor().encode else {
+            return Err(self.failed(-1));
+        };
+        out.clear();
+        out.resize(u16::MAX as usize, 0);
+        let written = unsafe {
+            encode(
+                self.state,
+                samples.as_ptr(),
+                samples.len(),
+                out.as_mut_ptr(),
+                out.len(),
+            )
+        };
+        if written < 0 || written as usize > out.len() {
+            return Err(self.failed(written as i64));
+        }
+        out.truncate(written as usize);
+        Ok(())
+    }
+
+    fn failed(&self, code: i64) -> PluginError {
+        PluginError::Failed {
+            name: self.plugin.inner.name.clone(),
+            code,
+        }
+    }
+}
+
+impl Drop for PluginInstance {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.plugin.descriptor().destroy {
+            if !self.state.is_null() {
+                unsafe { destroy(self.state) };
+            }
+        }
+    }
+}
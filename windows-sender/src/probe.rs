@@ -0,0 +1,396 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::error::{Error, TransportError};
+use crate::feedback::{FeedbackReader, FeedbackTracker};
+use crate::protocol::{
+    build_probe_packet, build_probe_packet_into, parse_feedback, HEADER_SIZE, MAX_PACKET,
+};
+use crate::resolve::Endpoint;
+use crate::stats::SenderStats;
+use crate::transport::{bind_for, is_timeout, SendOptions, TargetSet, Transport, TransportKind};
+
+/// Stays below a typical 1500-byte MTU once IP and UDP headers are added.
+const PROBE_PACKET_SIZE: usize = 1200;
+const FIRST_RATE_KBPS: u64 = 512;
+const BURST: Duration = Duration::from_millis(500);
+/// Long enough for the receiver's next report to cover the whole burst.
+const SETTLE: Duration = Duration::from_millis(600);
+/// TCP receivers only report after reading a packet, so the settle period trickles these.
+const KEEPALIVE_SIZE: usize = 0;
+const KEEPALIVE_INTERVAL: Duration = Duration::from_millis(50);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+/// A step that delivers less than this share of what was offered ends the probe.
+const SATURATED: f64 = 0.9;
+
+#[derive(Clone, Debug)]
+pub struct ProbeConfig {
+    pub target: SocketAddr,
+    pub transport: TransportKind,
+    /// The rate doubles from 512 kbps until delivery falls behind or this is reached.
+    pub max_kbps: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ProbeStep {
+    pub offered_kbps: f64,
+    pub delivered_kbps: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProbeResult {
+    pub steps: Vec<ProbeStep>,
+}
+
+impl ProbeResult {
+    /// The best delivered rate of any step.
+    pub fn achievable_kbps(&self) -> f64 {
+        self.steps
+            .iter()
+            .map(|step| step.delivered_kbps)
+            .fold(0.0, f64::max)
+    }
+}
+
+/// Sends bursts of padding at increasing rates and reads back, from receiver reports,
+/// how much of each arrived. Blocks for roughly a second per step; `on_step` sees each
+/// step as it completes.
+pub fn probe_bandwidth<F>(config: &ProbeConfig, mut on_step: F) -> Result<ProbeResult, Error>
+where
+    F: FnMut(&ProbeStep),
+{
+    let stats = Arc::new(SenderStats::default());
+    let feedback = Arc::new(FeedbackTracker::new(Arc::clone(&stats), None, None));
+    let mut targets = TargetSet::connect(
+        config.transport,
+        &[Endpoint::Addr(config.target)],
+        feedback,
+        None,
+        None,
+        SendOptions::default(),
+    )?;
+    let mut seq = 0u32;
+    await_feedback(&stats, config.target, || {
+        targets.send_packet(&build_probe_packet(seq, KEEPALIVE_SIZE)?, seq)?;
+        seq = seq.wrapping_add(1);
+        Ok(())
+    })?;
+    settle(&mut targets, &mut seq)?;
+
+    let mut steps = Vec::new();
+    let mut rate_kbps = FIRST_RATE_KBPS;
+    while rate_kbps <= config.max_kbps {
+        let before = stats.remote_probe_bytes.load(Ordering::Relaxed);
+        let (sent, elapsed) = send_burst(&mut targets, rate_kbps, &mut seq)?;
+        let keepalive = settle(&mut targets, &mut seq)?;
+        let delivered = stats
+            .remote_probe_bytes
+            .load(Ordering::Relaxed)
+            .saturating_sub(before)
+            .saturating_sub(keepalive)
+            .min(sent);
+
+        let kbps = |bytes: u64| bytes as f64 * 8.0 / elapsed.as_secs_f64() / 1000.0;
+        let step = ProbeStep {
+            offered_kbps: kbps(sent),
+            delivered_kbps: kbps(delivered),
+        };
+        on_step(&step);
+        steps.push(step);
+        if (delivered as f64) < sent as f64 * SATURATED {
+            break;
+        }
+        rate_kbps *= 2;
+    }
+    Ok(ProbeResult { steps })
+}
+
+/// Round trip to the receiver over UDP, from its first report. Getting one at all shows
+/// the receiver listens and nothing in between blocks either direction.
+pub fn ping(target: SocketAddr) -> Result<Duration, Error> {
+    let stats = Arc::new(SenderStats::default());
+    let feedback = Arc::new(FeedbackTracker::new(Arc::clone(&stats), None, None));
+    let mut targets = TargetSet::connect(
+        TransportKind::Udp,
+        &[Endpoint::Addr(target)],
+        feedback,
+        None,
+        None,
+        SendOptions::default(),
+    )?;
+    let mut seq = 0u32;
+    await_feedback(&stats, target, || {
+        targets.send_packet(&build_probe_packet(seq, KEEPALIVE_SIZE)?, seq)?;
+        seq = seq.wrapping_add(1);
+        Ok(())
+    })?;
+    Ok(Duration::from_micros(stats.rtt_us.load(Ordering::Relaxed)))
+}
+
+/// Receivers on the local network listening on UDP `port`, found by broadcasting probes
+/// for `wait` and collecting who answers with a report, in the order they answered.
+///
+/// A receiver playing another stream answers too; its next report goes to us instead of
+/// its sender, which costs that sender one report.
+pub fn discover(port: u16, wait: Duration) -> Result<Vec<SocketAddr>, Error> {
+    let broadcast = SocketAddr::from((Ipv4Addr::BROADCAST, port));
+    let socket = bind_for(broadcast).map_err(TransportError::Bind)?;
+    socket
+        .set_broadcast(true)
+        .map_err(|source| TransportError::Configure {
+            what: "broadcast",
+            source,
+        })?;
+    socket
+        .set_read_timeout(Some(KEEPALIVE_INTERVAL))
+        .map_err(|source| TransportError::Configure {
+            what: "read timeout",
+            source,
+        })?;
+    let mut found = Vec::new();
+    let mut buf = [0u8; MAX_PACKET];
+    let mut seq = 0u32;
+    let start = Instant::now();
+    let mut next_probe = start;
+    while start.elapsed() < wait {
+        if Instant::now() >= next_probe {
+            let packet = build_probe_packet(seq, KEEPALIVE_SIZE)?;
+            socket
+                .send_to(&packet, broadcast)
+                .map_err(|source| TransportError::Send {
+                    what: "discovery probe",
+                    seq,
+                    source,
+                })?;
+            seq = seq.wrapping_add(1);
+            next_probe += KEEPALIVE_INTERVAL;
+        }
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                if parse_feedback(&buf[..len]).is_ok() && !found.contains(&from) {
+                    found.push(from);
+                }
+            }
+            Err(err) if is_timeout(&err) => {}
+            Err(err) => return Err(TransportError::Receive(err).into()),
+        }
+    }
+    Ok(found)
+}
+
+/// Keeps calling `send` until a report arrives; receivers only report once they have
+/// heard from us.
+fn await_feedback<F>(stats: &SenderStats, target: SocketAddr, mut send: F) -> Result<(), Error>
+where
+    F: FnMut() -> Result<(), Error>,
+{
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    while stats.feedback_reports.load(Ordering::Relaxed) == 0 {
+        if Instant::now() >= deadline {
+            return Err(Error::NoFeedback { target });
+        }
+        send()?;
+        thread::sleep(KEEPALIVE_INTERVAL);
+    }
+    Ok(())
+}
+
+/// Paces probe packets at `rate_kbps` for one burst. Returns the bytes sent and how long
+/// that took; TCP can take longer than planned when the link cannot keep up.
+fn send_burst(
+    targets: &mut TargetSet,
+    rate_kbps: u64,
+    seq: &mut u32,
+) -> Result<(u64, Duration), Error> {
+    let packet_bits = (PROBE_PACKET_SIZE * 8) as f64;
+    let packets_per_sec = rate_kbps as f64 * 1000.0 / packet_bits;
+    let start = Instant::now();
+    let mut sent_packets = 0u64;
+    let mut sent = 0u64;
+    let mut packet = [0; MAX_PACKET];
+    while start.elapsed() < BURST {
+        let due = (start.elapsed().as_secs_f64() * packets_per_sec) as u64;
+        while sent_packets < due {
+            let len = build_probe_packet_into(&mut packet, *seq, PROBE_PACKET_SIZE)?;
+            sent += targets.send_packet(&packet[..len], *seq)? as u64;
+            sent_packets += 1;
+            *seq = seq.wrapping_add(1);
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    Ok((sent, start.elapsed()))
+}
+
+/// Waits out `SETTLE` while keeping the receiver reporting. Returns the bytes sent.
+fn settle(targets: &mut TargetSet, seq: &mut u32) -> Result<u64, Error> {
+    let start = Instant::now();
+    let mut sent = 0u64;
+    while start.elapsed() < SETTLE {
+        let packet = build_probe_packet(*seq, KEEPALIVE_SIZE)?;
+        sent += targets.send_packet(&packet, *seq)? as u64;
+        *seq = seq.wrapping_add(1);
+        thread::sleep(KEEPALIVE_INTERVAL);
+    }
+    Ok(sent)
+}
+
+/// IPv4 and UDP headers on top of each packet.
+pub const UDP_IPV4_OVERHEAD: usize = 28;
+/// Every IPv4 link carries 576-byte datagrams.
+const MTU_FLOOR: usize = 576 - UDP_IPV4_OVERHEAD;
+/// The receivers read into 8 KiB buffers, so the MTU probe stops there.
+pub const MAX_PROBE_PACKET: usize = 8192;
+const MTU_ATTEMPTS: usize = 3;
+/// Two reports, so at least one was taken after the attempts arrived.
+const MTU_WAIT: Duration = Duration::from_millis(1200);
+
+/// Largest UDP packet that reaches `target` unfragmented, found by bisecting sizes sent
+/// with the don't-fragment bit set. `None` when not even `MTU_FLOOR` bytes get through.
+pub fn probe_path_mtu(target: SocketAddr) -> Result<Option<usize>, Error> {
+    let stats = Arc::new(SenderStats::default());
+    let feedback = Arc::new(FeedbackTracker::new(Arc::clone(&stats), None, None));
+    let socket = bind_for(target).map_err(TransportError::Bind)?;
+    set_dont_fragment(&socket, target.is_ipv6()).map_err(|source| TransportError::Configure {
+        what: "don't-fragment bit",
+        source,
+    })?;
+    let _reader = FeedbackReader::udp(&socket, target, feedback)?;
+    let mut seq = 0u32;
+
+    await_feedback(&stats, target, || {
+        send_df(&socket, target, &mut seq, KEEPALIVE_SIZE).map(|_| ())
+    })?;
+
+    let mut fits = |len: usize| -> Result<bool, Error> {
+        let before = stats.remote_probe_bytes.load(Ordering::Relaxed);
+        let reports = stats.feedback_reports.load(Ordering::Relaxed);
+        for _ in 0..MTU_ATTEMPTS {
+            if !send_df(&socket, target, &mut seq, len)? {
+                return Ok(false);
+            }
+        }
+        let deadline = Instant::now() + MTU_WAIT;
+        while stats.feedback_reports.load(Ordering::Relaxed) < reports + 2
+            && Instant::now() < deadline
+        {
+            thread::sleep(KEEPALIVE_INTERVAL);
+        }
+        let delivered = stats
+            .remote_probe_bytes
+            .load(Ordering::Relaxed)
+            .saturating_sub(before);
+        Ok(delivered >= len as u64)
+    };
+
+    if !fits(MTU_FLOOR)? {
+        return Ok(None);
+    }
+    // Probe packets carry 16-bit samples, so sizes past the header stay even.
+    let (mut good, mut bad) = (MTU_FLOOR, MAX_PROBE_PACKET + 2);
+    while bad - good > 2 {
+        let mid = good + (bad - good) / 4 * 2;
+        if fits(mid)? {
+            good = mid;
+        } else {
+            bad = mid;
+        }
+    }
+    Ok(Some(good))
+}
+
+/// Sends one `len`-byte probe. Returns false when the local stack refuses it as too big.
+fn send_df(
+    socket: &UdpSocket,
+    target: SocketAddr,
+    seq: &mut u32,
+    len: usize,
+) -> Result<bool, Error> {
+    let packet = build_probe_packet(*seq, len.max(HEADER_SIZE))?;
+    *seq = seq.wrapping_add(1);
+    match socket.send_to(&packet, target) {
+        Ok(_) => Ok(true),
+        Err(err) => {
+            debug!("{len}-byte probe not sent: {err}");
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_dont_fragment(socket: &UdpSocket, ipv6: bool) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // PROBE sets DF without clamping sends to the kernel's cached path MTU.
+    let (level, name, value) = if ipv6 {
+        (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_PROBE,
+        )
+    } else {
+        (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_PROBE,
+        )
+    };
+    // SAFETY: the fd is open for the socket's lifetime and `value` outlives the call.
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+fn set_dont_fragment(socket: &UdpSocket, ipv6: bool) -> io::Result<()> {
+    use std::os::windows::io::AsRawSocket;
+    use windows_sys::Win32::Networking::WinSock::{
+        setsockopt, WSAGetLastError, IPPROTO_IP, IPPROTO_IPV6, IPV6_DONTFRAG, IP_DONTFRAGMENT,
+    };
+
+    let (level, name) = if ipv6 {
+        (IPPROTO_IPV6, IPV6_DONTFRAG)
+    } else {
+        (IPPROTO_IP, IP_DONTFRAGMENT)
+    };
+    let value: u32 = 1;
+    // SAFETY: the socket is open for its lifetime and `value` outlives the call.
+    let rc = unsafe {
+        setsockopt(
+            socket.as_raw_socket() as usize,
+            level,
+            name,
+            (&value as *const u32).cast(),
+            std::mem::size_of::<u32>() as i32,
+        )
+    };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(unsafe { WSAGetLastError() }))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn set_dont_fragment(_socket: &UdpSocket, _ipv6: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "not supported on this platform",
+    ))
+}
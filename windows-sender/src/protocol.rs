@@ -0,0 +1,744 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::error::ProtocolError;
+use crate::stats::SequenceCounts;
+
+pub const MAGIC: [u8; 4] = *b"AUD0";
+/// Version of reports and the other control frames.
+pub const VERSION: u8 = 1;
+/// Audio packets moved to version 2 when the media timestamp joined the header.
+pub const PACKET_VERSION: u8 = 2;
+pub const CODEC_PCM16: u8 = 0;
+/// Opus (RFC 6716), one packet per frame, from a codec plugin taking this id.
+pub const CODEC_OPUS: u8 = 1;
+/// An IEC 61937 bitstream (AC-3, DTS, E-AC-3) passed through untouched: the payload is laid
+/// out as PCM16, but must reach an S/PDIF or HDMI output bit for bit; see `iec61937`.
+pub const CODEC_IEC61937: u8 = 255;
+pub const HEADER_SIZE: usize = 32;
+/// Largest audio packet: the header and the most payload its length field can describe.
+pub const MAX_PACKET: usize = HEADER_SIZE + u16::MAX as usize;
+/// Header flag for bandwidth-probe padding: counted by the receiver, never played.
+pub const FLAG_PROBE: u8 = 1;
+/// Header flag for a payload sealed with the pre-shared key; see `crypto`.
+pub const FLAG_ENCRYPTED: u8 = 2;
+/// With `FLAG_ENCRYPTED`: sealed with ChaCha20-Poly1305 instead of AES-256-GCM.
+pub const FLAG_CHACHA20: u8 = 4;
+/// Header flag for a payload left readable but authenticated with the pre-shared key.
+pub const FLAG_AUTHENTICATED: u8 = 8;
+/// With a sealed payload: sealed with a rotating key whose epoch follows the session id.
+pub const FLAG_KEY_EPOCH: u8 = 16;
+/// Header flag for a `send_time_us` read from the PTP domain clock; see `ptp`.
+pub const FLAG_PTP_TIME: u8 = 32;
+/// Header flag for the first packet after the sender dropped audio that was too late to
+/// send; `media_time` skips over the gap.
+pub const FLAG_DISCONTINUITY: u8 = 64;
+
+/// Receiver -> sender reports travel back over the same socket as the audio.
+pub const FEEDBACK_MAGIC: [u8; 4] = *b"AUDF";
+pub const FEEDBACK_SIZE: usize = 96;
+const FEEDBACK_PLAYED: u8 = 1;
+/// Receiver -> sender frame presenting the access token; see `build_access_token`.
+pub const ACCESS_MAGIC: [u8; 4] = *b"AUDA";
+const ACCESS_HEADER: usize = 8;
+/// Receiver -> sender clock ping and the sender's answer; see `ClockMessage`.
+pub const CLOCK_MAGIC: [u8; 4] = *b"AUDT";
+pub const CLOCK_SIZE: usize = 32;
+const CLOCK_REPLY: u8 = 1;
+/// Receiver -> sender request for gain and mute at the source; see `VolumeRequest`.
+pub const VOLUME_MAGIC: [u8; 4] = *b"AUDV";
+pub const VOLUME_SIZE: usize = 12;
+const VOLUME_MUTED: u8 = 1;
+/// Sender -> receiver named position in the stream; see `SyncMarker`.
+pub const MARKER_MAGIC: [u8; 4] = *b"AUDM";
+const MARKER_HEADER: usize = 20;
+pub const MAX_MARKER_NAME: usize = 255;
+/// Sender -> receiver notice of a deferred start; see `build_start`.
+pub const START_MAGIC: [u8; 4] = *b"AUDS";
+pub const START_SIZE: usize = 16;
+/// Sender -> receiver notice that the stream is paused; see `build_pause`.
+pub const PAUSE_MAGIC: [u8; 4] = *b"AUDP";
+pub const PAUSE_SIZE: usize = 12;
+
+/// Microseconds since the Unix epoch; every timestamp in the protocol uses this clock. It
+/// reads the system clock once and counts on with the monotonic clock, so latencies and
+/// clock offsets do not jump when NTP steps the system clock.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_us() -> Result<u64, ProtocolError> {
+    static ANCHOR: OnceLock<(Instant, u64)> = OnceLock::new();
+    let (at, unix_us) = match ANCHOR.get() {
+        Some(anchor) => *anchor,
+        None => {
+            let unix_us = wall_us()?;
+            *ANCHOR.get_or_init(|| (Instant::now(), unix_us))
+        }
+    };
+    Ok(unix_us + at.elapsed().as_micros() as u64)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn now_us() -> Result<u64, ProtocolError> {
+    wall_us()
+}
+
+/// Microseconds since the Unix epoch on the system clock itself, which NTP may step.
+pub fn wall_us() -> Result<u64, ProtocolError> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| ProtocolError::ClockBeforeEpoch)?
+        .as_micros() as u64)
+}
+
+/// `at` on the clock of `now_us`, for times of day given by the user.
+pub fn to_clock_us(at: SystemTime) -> Result<u64, ProtocolError> {
+    let ahead_us = match at.duration_since(SystemTime::now()) {
+        Ok(ahead) => ahead.as_micros() as i64,
+        Err(behind) => -(behind.duration().as_micros() as i64),
+    };
+    u64::try_from(now_us()? as i64 + ahead_us).map_err(|_| ProtocolError::ClockBeforeEpoch)
+}
+
+/// The time of day `clock_us` on the clock of `now_us` falls on.
+pub fn from_clock_us(clock_us: u64) -> Result<SystemTime, ProtocolError> {
+    let ahead_us = clock_us as i64 - now_us()? as i64;
+    let now = SystemTime::now();
+    Ok(match u64::try_from(ahead_us) {
+        Ok(ahead) => now + Duration::from_micros(ahead),
+        Err(_) => now - Duration::from_micros(ahead_us.unsigned_abs()),
+    })
+}
+
+pub fn build_packet(
+    seq: u32,
+    media_time: u32,
+    sample_rate: u32,
+    channels: u8,
+    samples_per_channel: u16,
+    payload: &[u8],
+) -> Result<Vec<u8>, ProtocolError> {
+    build_encoded_packet(
+        CODEC_PCM16,
+        seq,
+        media_time,
+        sample_rate,
+        channels,
+        samples_per_channel,
+        payload,
+    )
+}
+
+/// Like `build_packet`, written into `buf` without allocating. Returns the packet length.
+pub fn build_packet_into(
+    buf: &mut [u8; MAX_PACKET],
+    seq: u32,
+    media_time: u32,
+    sample_rate: u32,
+    channels: u8,
+    samples_per_channel: u16,
+    payload: &[u8],
+) -> Result<usize, ProtocolError> {
+    PacketTemplate::new(CODEC_PCM16, sample_rate, channels, samples_per_channel)
+        .build_into(buf, seq, media_time, payload)
+}
+
+/// Like `build_packet`, for a payload produced by the codec with id `codec`.
+pub fn build_encoded_packet(
+    codec: u8,
+    seq: u32,
+    media_time: u32,
+    sample_rate: u32,
+    channels: u8,
+    samples_per_channel: u16,
+    payload: &[u8],
+) -> Result<Vec<u8>, ProtocolError> {
+    let mut packet = Vec::with_capacity(HEADER_SIZE + payload.len());
+    PacketTemplate::new(codec, sample_rate, channels, samples_per_channel).begin(
+        &mut packet,
+        seq,
+        media_time,
+        payload.len(),
+    )?;
+    packet.extend_from_slice(payload);
+    Ok(packet)
+}
+
+/// The header fields every audio packet of a stream shares, laid out once so each packet
+/// only fills in its own and the payload can be written straight after.
+#[derive(Copy, Clone, Debug)]
+pub struct PacketTemplate {
+    header: [u8; HEADER_SIZE],
+}
+
+impl PacketTemplate {
+    pub fn new(codec: u8, sample_rate: u32, channels: u8, samples_per_channel: u16) -> Self {
+        let mut header = [0; HEADER_SIZE];
+        header[0..4].copy_from_slice(&MAGIC);
+        header[4] = PACKET_VERSION;
+        header[5] = codec;
+        header[6] = channels;
+        header[8..12].copy_from_slice(&sample_rate.to_le_bytes());
+        header[24..26].copy_from_slice(&samples_per_channel.to_le_bytes());
+        Self { header }
+    }
+
+    /// Replaces `packet` with the header of packet `seq`, stamped now, for a
+    /// `payload_len`-byte payload the caller appends.
+    pub fn begin(
+        &self,
+        packet: &mut Vec<u8>,
+        seq: u32,
+        media_time: u32,
+        payload_len: usize,
+    ) -> Result<(), ProtocolError> {
+        packet.clear();
+        packet.resize(HEADER_SIZE, 0);
+        self.write_header(packet, seq, media_time, payload_len)
+    }
+
+    /// Writes packet `seq`, stamped now, into `buf` without allocating. Returns the packet
+    /// length.
+    pub fn build_into(
+        &self,
+        buf: &mut [u8; MAX_PACKET],
+        seq: u32,
+        media_time: u32,
+        payload: &[u8],
+    ) -> Result<usize, ProtocolError> {
+        self.write_header(buf, seq, media_time, payload.len())?;
+        let len = HEADER_SIZE + payload.len();
+        buf[HEADER_SIZE..len].copy_from_slice(payload);
+        Ok(len)
+    }
+
+    fn write_header(
+        &self,
+        out: &mut [u8],
+        seq: u32,
+        media_time: u32,
+        payload_len: usize,
+    ) -> Result<(), ProtocolError> {
+        if payload_len > u16::MAX as usize {
+            return Err(ProtocolError::PayloadTooLarge { len: payload_len });
+        }
+        out[..HEADER_SIZE].copy_from_slice(&self.header);
+        out[12..16].copy_from_slice(&seq.to_le_bytes());
+        out[16..24].copy_from_slice(&now_us()?.to_le_bytes());
+        out[26..28].copy_from_slice(&(payload_len as u16).to_le_bytes());
+        out[28..32].copy_from_slice(&media_time.to_le_bytes());
+        Ok(())
+    }
+}
+
+/// Restamps a built packet with a time from the PTP domain clock.
+pub fn stamp_ptp_time(packet: &mut [u8], send_time_us: u64) {
+    packet[16..24].copy_from_slice(&send_time_us.to_le_bytes());
+    packet[7] |= FLAG_PTP_TIME;
+}
+
+/// Renumbers a built packet, for a target that counts its packets on its own.
+pub fn set_seq(packet: &mut [u8], seq: u32) {
+    packet[12..16].copy_from_slice(&seq.to_le_bytes());
+}
+
+/// Flags a built packet as the first after a gap; see `FLAG_DISCONTINUITY`.
+pub fn mark_discontinuity(packet: &mut [u8]) {
+    packet[7] |= FLAG_DISCONTINUITY;
+}
+
+/// The audio of a packet the sender built, for passing the stream on in another protocol.
+#[derive(Copy, Clone, Debug)]
+pub struct PcmAudio<'a> {
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub media_time: u32,
+    /// When the packet was built, on the PTP domain clock with `--ptp`.
+    pub send_time_us: u64,
+    /// First packet after a gap; see `FLAG_DISCONTINUITY`.
+    pub discontinuity: bool,
+    /// Interleaved little-endian PCM16, whole frames only.
+    pub samples: &'a [u8],
+}
+
+/// Reads a packet the sender built back as PCM16; `None` for control and probe packets,
+/// which carry no audio. Unlike `parse_packet` it takes any number of channels.
+pub fn pcm_audio(packet: &[u8]) -> Result<Option<PcmAudio<'_>>, ProtocolError> {
+    if packet.len() < HEADER_SIZE || packet[0..4] != MAGIC || packet[7] & FLAG_PROBE != 0 {
+        return Ok(None);
+    }
+    let codec = packet[5];
+    if codec != CODEC_PCM16 {
+        return Err(ProtocolError::UnsupportedCodec(codec));
+    }
+    let channels = packet[6];
+    if channels == 0 {
+        return Err(ProtocolError::InvalidChannels(channels));
+    }
+    let payload_len = u16::from_le_bytes([packet[26], packet[27]]) as usize;
+    let available = packet.len() - HEADER_SIZE;
+    if payload_len > available {
+        return Err(ProtocolError::PayloadLength {
+            declared: payload_len,
+            available,
+        });
+    }
+    let frame_bytes = 2 * channels as usize;
+    Ok(Some(PcmAudio {
+        sample_rate: u32::from_le_bytes([packet[8], packet[9], packet[10], packet[11]]),
+        channels,
+        media_time: u32::from_le_bytes([packet[28], packet[29], packet[30], packet[31]]),
+        send_time_us: u64::from_le_bytes([
+            packet[16], packet[17], packet[18], packet[19], packet[20], packet[21], packet[22],
+            packet[23],
+        ]),
+        discontinuity: packet[7] & FLAG_DISCONTINUITY != 0,
+        samples: &packet[HEADER_SIZE..][..payload_len - payload_len % frame_bytes],
+    }))
+}
+
+/// A `len`-byte probe packet of silent padding.
+pub fn build_probe_packet(seq: u32, len: usize) -> Result<Vec<u8>, ProtocolError> {
+    let samples = (len.saturating_sub(HEADER_SIZE) / 2).max(1);
+    let mut packet = build_packet(seq, 0, 48_000, 1, samples as u16, &vec![0; samples * 2])?;
+    packet[7] = FLAG_PROBE;
+    Ok(packet)
+}
+
+/// Like `build_probe_packet`, written into `buf` without allocating. Returns the packet
+/// length.
+pub fn build_probe_packet_into(
+    buf: &mut [u8; MAX_PACKET],
+    seq: u32,
+    len: usize,
+) -> Result<usize, ProtocolError> {
+    let samples = (len.saturating_sub(HEADER_SIZE) / 2).max(1);
+    let payload_len = samples * 2;
+    PacketTemplate::new(CODEC_PCM16, 48_000, 1, samples as u16).write_header(
+        buf,
+        seq,
+        0,
+        payload_len,
+    )?;
+    buf[7] = FLAG_PROBE;
+    buf[HEADER_SIZE..HEADER_SIZE + payload_len].fill(0);
+    Ok(HEADER_SIZE + payload_len)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PacketHeader {
+    pub version: u8,
+    pub codec: u8,
+    pub channels: u8,
+    pub flags: u8,
+    pub sample_rate: u32,
+    pub seq: u32,
+    /// When the packet was built, on the sender's clock; see `now_us`.
+    pub send_time_us: u64,
+    pub samples_per_channel: u16,
+    pub payload_len: u16,
+    /// Sample frames since the stream started, wrapping like an RTP timestamp; unlike
+    /// `send_time_us` it never jumps when the system clock is stepped.
+    pub media_time: u32,
+}
+
+pub fn parse_packet(packet: &[u8]) -> Result<(PacketHeader, &[u8]), ProtocolError> {
+    if packet.len() < HEADER_SIZE {
+        return Err(ProtocolError::Truncated { len: packet.len() });
+    }
+    if packet[0..4] != MAGIC {
+        return Err(ProtocolError::BadMagic);
+    }
+
+    let header = PacketHeader {
+        version: packet[4],
+        codec: packet[5],
+        channels: packet[6],
+        flags: packet[7],
+        sample_rate: u32::from_le_bytes([packet[8], packet[9], packet[10], packet[11]]),
+        seq: u32::from_le_bytes([packet[12], packet[13], packet[14], packet[15]]),
+        send_time_us: u64::from_le_bytes([
+            packet[16], packet[17], packet[18], packet[19], packet[20], packet[21], packet[22],
+            packet[23],
+        ]),
+        samples_per_channel: u16::from_le_bytes([packet[24], packet[25]]),
+        payload_len: u16::from_le_bytes([packet[26], packet[27]]),
+        media_time: u32::from_le_bytes([packet[28], packet[29], packet[30], packet[31]]),
+    };
+    if header.version != PACKET_VERSION {
+        return Err(ProtocolError::UnsupportedVersion(header.version));
+    }
+    if header.codec != CODEC_PCM16 {
+        return Err(ProtocolError::UnsupportedCodec(header.codec));
+    }
+    if !(1..=2).contains(&header.channels) {
+        return Err(ProtocolError::InvalidChannels(header.channels));
+    }
+
+    let payload_len = header.payload_len as usize;
+    if payload_len == 0
+        || !payload_len.is_multiple_of(2)
+        || HEADER_SIZE + payload_len > packet.len()
+    {
+        return Err(ProtocolError::PayloadLength {
+            declared: payload_len,
+            available: packet.len() - HEADER_SIZE,
+        });
+    }
+    Ok((header, &packet[HEADER_SIZE..HEADER_SIZE + payload_len]))
+}
+
+/// Frames `first_seq..=last_seq` were played since the previous report; the last one
+/// reached the output at `playout_time_us` on the receiver clock.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PlayedRange {
+    pub first_seq: u32,
+    pub last_seq: u32,
+    pub playout_time_us: u64,
+}
+
+/// Periodic report from a receiver.
+///
+/// It echoes the `send_time_us` of the newest packet together with the receiver clock
+/// when that packet arrived and when the report left, which is enough for an NTP-style
+/// round-trip and clock-offset estimate at the sender. `counts` are cumulative.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FeedbackReport {
+    pub played: Option<PlayedRange>,
+    pub echo_send_time_us: u64,
+    pub echo_recv_time_us: u64,
+    pub report_time_us: u64,
+    pub counts: SequenceCounts,
+    /// RFC 3550 interarrival jitter.
+    pub jitter_us: u64,
+    /// Cumulative bytes of probe packets received.
+    pub probe_bytes: u64,
+}
+
+pub fn build_feedback(report: &FeedbackReport) -> Vec<u8> {
+    let played = report.played.unwrap_or(PlayedRange {
+        first_seq: 0,
+        last_seq: 0,
+        playout_time_us: 0,
+    });
+    let mut packet = Vec::with_capacity(FEEDBACK_SIZE);
+    packet.extend_from_slice(&FEEDBACK_MAGIC);
+    packet.push(VERSION);
+    packet.push(if report.played.is_some() {
+        FEEDBACK_PLAYED
+    } else {
+        0
+    });
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(&played.first_seq.to_le_bytes());
+    packet.extend_from_slice(&played.last_seq.to_le_bytes());
+    packet.extend_from_slice(&played.playout_time_us.to_le_bytes());
+    packet.extend_from_slice(&report.echo_send_time_us.to_le_bytes());
+    packet.extend_from_slice(&report.echo_recv_time_us.to_le_bytes());
+    packet.extend_from_slice(&report.report_time_us.to_le_bytes());
+    let counts = &report.counts;
+    for count in [
+        counts.received,
+        counts.lost,
+        counts.reordered,
+        counts.duplicates,
+    ] {
+        packet.extend_from_slice(&count.to_le_bytes());
+    }
+    packet.extend_from_slice(&report.jitter_us.to_le_bytes());
+    packet.extend_from_slice(&report.probe_bytes.to_le_bytes());
+    packet
+}
+
+pub fn parse_feedback(packet: &[u8]) -> Result<FeedbackReport, ProtocolError> {
+    if packet.len() < FEEDBACK_SIZE {
+        return Err(ProtocolError::Truncated { len: packet.len() });
+    }
+    if packet[0..4] != FEEDBACK_MAGIC {
+        return Err(ProtocolError::BadMagic);
+    }
+    if packet[4] != VERSION {
+        return Err(ProtocolError::UnsupportedVersion(packet[4]));
+    }
+    let u32_at = |at: usize| u32::from_le_bytes(packet[at..at + 4].try_into().unwrap());
+    let u64_at = |at: usize| u64::from_le_bytes(packet[at..at + 8].try_into().unwrap());
+    Ok(FeedbackReport {
+        played: (packet[5] & FEEDBACK_PLAYED != 0).then(|| PlayedRange {
+            first_seq: u32_at(8),
+            last_seq: u32_at(12),
+            playout_time_us: u64_at(16),
+        }),
+        echo_send_time_us: u64_at(24),
+        echo_recv_time_us: u64_at(32),
+        report_time_us: u64_at(40),
+        counts: SequenceCounts {
+            received: u64_at(48),
+            lost: u64_at(56),
+            reordered: u64_at(64),
+            duplicates: u64_at(72),
+        },
+        jitter_us: u64_at(80),
+        probe_bytes: u64_at(88),
+    })
+}
+
+/// Magic, version, three reserved bytes and the token as UTF-8. Receivers send it first
+/// on a TCP stream, and ahead of every report over UDP.
+pub fn build_access_token(token: &str) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(ACCESS_HEADER + token.len());
+    packet.extend_from_slice(&ACCESS_MAGIC);
+    packet.extend_from_slice(&[VERSION, 0, 0, 0]);
+    packet.extend_from_slice(token.as_bytes());
+    packet
+}
+
+pub fn is_access_token(packet: &[u8]) -> bool {
+    packet.starts_with(&ACCESS_MAGIC)
+}
+
+/// The token bytes an access frame presents.
+pub fn parse_access_token(packet: &[u8]) -> Result<&[u8], ProtocolError> {
+    if packet.len() < ACCESS_HEADER {
+        return Err(ProtocolError::Truncated { len: packet.len() });
+    }
+    if !is_access_token(packet) {
+        return Err(ProtocolError::BadMagic);
+    }
+    if packet[4] != VERSION {
+        return Err(ProtocolError::UnsupportedVersion(packet[4]));
+    }
+    Ok(&packet[ACCESS_HEADER..])
+}
+
+/// One half of a four-timestamp clock exchange. The receiver pings with `origin_us` on its
+/// own clock; the sender answers with it, when the ping arrived and when the answer left,
+/// on the sender clock. Answers also carry the sender's multi-room playout delay.
+///
+/// ```text
+/// "AUDT" | version (1) | reply (1) | playout delay ms (2) | origin (8) | receive (8) | transmit (8)
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ClockMessage {
+    pub reply: bool,
+    /// Send time to playout every receiver should keep, or 0 to let each play on arrival.
+    pub playout_delay_ms: u16,
+    pub origin_us: u64,
+    pub receive_us: u64,
+    pub transmit_us: u64,
+}
+
+impl ClockMessage {
+    pub fn ping(origin_us: u64) -> Self {
+        Self {
+            reply: false,
+            playout_delay_ms: 0,
+            origin_us,
+            receive_us: 0,
+            transmit_us: 0,
+        }
+    }
+}
+
+pub fn build_clock(message: &ClockMessage) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(CLOCK_SIZE);
+    packet.extend_from_slice(&CLOCK_MAGIC);
+    packet.extend_from_slice(&[VERSION, if message.reply { CLOCK_REPLY } else { 0 }]);
+    packet.extend_from_slice(&message.playout_delay_ms.to_le_bytes());
+    packet.extend_from_slice(&message.origin_us.to_le_bytes());
+    packet.extend_from_slice(&message.receive_us.to_le_bytes());
+    packet.extend_from_slice(&message.transmit_us.to_le_bytes());
+    packet
+}
+
+pub fn is_clock(packet: &[u8]) -> bool {
+    packet.starts_with(&CLOCK_MAGIC)
+}
+
+pub fn parse_clock(packet: &[u8]) -> Result<ClockMessage, ProtocolError> {
+    if packet.len() < CLOCK_SIZE {
+        return Err(ProtocolError::Truncated { len: packet.len() });
+    }
+    if !is_clock(packet) {
+        return Err(ProtocolError::BadMagic);
+    }
+    if packet[4] != VERSION {
+        return Err(ProtocolError::UnsupportedVersion(packet[4]));
+    }
+    let u64_at = |at: usize| u64::from_le_bytes(packet[at..at + 8].try_into().unwrap());
+    Ok(ClockMessage {
+        reply: packet[5] & CLOCK_REPLY != 0,
+        playout_delay_ms: u16::from_le_bytes([packet[6], packet[7]]),
+        origin_us: u64_at(8),
+        receive_us: u64_at(16),
+        transmit_us: u64_at(24),
+    })
+}
+
+/// Gain and mute a receiver asks the sender to apply before encoding, so turning the volume
+/// down at the phone keeps the codec's whole dynamic range. `seq` counts up with every
+/// request, so a late one over UDP does not undo a newer one.
+///
+/// ```text
+/// "AUDV" | version (1) | muted (1) | gain centi-dB (2, signed) | seq (4)
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VolumeRequest {
+    pub muted: bool,
+    pub gain_db: f32,
+    pub seq: u32,
+}
+
+pub fn build_volume(request: &VolumeRequest) -> Vec<u8> {
+    let centi_db = (request.gain_db * 100.0)
+        .round()
+        .clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+    let mut packet = Vec::with_capacity(VOLUME_SIZE);
+    packet.extend_from_slice(&VOLUME_MAGIC);
+    packet.extend_from_slice(&[VERSION, if request.muted { VOLUME_MUTED } else { 0 }]);
+    packet.extend_from_slice(&centi_db.to_le_bytes());
+    packet.extend_from_slice(&request.seq.to_le_bytes());
+    packet
+}
+
+pub fn is_volume(packet: &[u8]) -> bool {
+    packet.starts_with(&VOLUME_MAGIC)
+}
+
+pub fn parse_volume(packet: &[u8]) -> Result<VolumeRequest, ProtocolError> {
+    if packet.len() < VOLUME_SIZE {
+        return Err(ProtocolError::Truncated { len: packet.len() });
+    }
+    if !is_volume(packet) {
+        return Err(ProtocolError::BadMagic);
+    }
+    if packet[4] != VERSION {
+        return Err(ProtocolError::UnsupportedVersion(packet[4]));
+    }
+    Ok(VolumeRequest {
+        muted: packet[5] & VOLUME_MUTED != 0,
+        gain_db: i16::from_le_bytes([packet[6], packet[7]]) as f32 / 100.0,
+        seq: u32::from_le_bytes(packet[8..12].try_into().unwrap()),
+    })
+}
+
+/// A named position in the stream, for aligning a separately streamed video to the audio.
+/// `media_time` is on the clock of the audio packet headers.
+///
+/// ```text
+/// "AUDM" | version (1) | reserved (3) | send time (8) | media time (4) | name (UTF-8)
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SyncMarker {
+    pub name: String,
+    pub media_time: u32,
+    pub send_time_us: u64,
+}
+
+pub fn build_marker(marker: &SyncMarker) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(MARKER_HEADER + marker.name.len());
+    packet.extend_from_slice(&MARKER_MAGIC);
+    packet.extend_from_slice(&[VERSION, 0, 0, 0]);
+    packet.extend_from_slice(&marker.send_time_us.to_le_bytes());
+    packet.extend_from_slice(&marker.media_time.to_le_bytes());
+    packet.extend_from_slice(marker.name.as_bytes());
+    packet
+}
+
+pub fn is_marker(packet: &[u8]) -> bool {
+    packet.starts_with(&MARKER_MAGIC)
+}
+
+pub fn parse_marker(packet: &[u8]) -> Result<SyncMarker, ProtocolError> {
+    if packet.len() < MARKER_HEADER {
+        return Err(ProtocolError::Truncated { len: packet.len() });
+    }
+    if !is_marker(packet) {
+        return Err(ProtocolError::BadMagic);
+    }
+    if packet[4] != VERSION {
+        return Err(ProtocolError::UnsupportedVersion(packet[4]));
+    }
+    Ok(SyncMarker {
+        name: String::from_utf8_lossy(&packet[MARKER_HEADER..]).into_owned(),
+        media_time: u32::from_le_bytes(packet[16..20].try_into().unwrap()),
+        send_time_us: u64::from_le_bytes(packet[8..16].try_into().unwrap()),
+    })
+}
+
+/// Magic, version, three reserved bytes and the time the stream starts, on the sender
+/// clock. Repeated while the sender waits for it, so late receivers learn it too.
+pub fn build_start(start_time_us: u64) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(START_SIZE);
+    packet.extend_from_slice(&START_MAGIC);
+    packet.extend_from_slice(&[VERSION, 0, 0, 0]);
+    packet.extend_from_slice(&start_time_us.to_le_bytes());
+    packet
+}
+
+pub fn is_start(packet: &[u8]) -> bool {
+    packet.starts_with(&START_MAGIC)
+}
+
+/// The announced start time on the sender clock.
+pub fn parse_start(packet: &[u8]) -> Result<u64, ProtocolError> {
+    if packet.len() < START_SIZE {
+        return Err(ProtocolError::Truncated { len: packet.len() });
+    }
+    if !is_start(packet) {
+        return Err(ProtocolError::BadMagic);
+    }
+    if packet[4] != VERSION {
+        return Err(ProtocolError::UnsupportedVersion(packet[4]));
+    }
+    Ok(u64::from_le_bytes(packet[8..16].try_into().unwrap()))
+}
+
+/// Magic, version, three reserved bytes and the media time the audio stopped at. Repeated
+/// while the stream is paused, so the receiver fades out instead of running dry; the first
+/// packet after it carries `FLAG_DISCONTINUITY`.
+pub fn build_pause(media_time: u32) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(PAUSE_SIZE);
+    packet.extend_from_slice(&PAUSE_MAGIC);
+    packet.extend_from_slice(&[VERSION, 0, 0, 0]);
+    packet.extend_from_slice(&media_time.to_le_bytes());
+    packet
+}
+
+pub fn is_pause(packet: &[u8]) -> bool {
+    packet.starts_with(&PAUSE_MAGIC)
+}
+
+/// The media time the audio stopped at.
+pub fn parse_pause(packet: &[u8]) -> Result<u32, ProtocolError> {
+    if packet.len() < PAUSE_SIZE {
+        return Err(ProtocolError::Truncated { len: packet.len() });
+    }
+    if !is_pause(packet) {
+        return Err(ProtocolError::BadMagic);
+    }
+    if packet[4] != VERSION {
+        return Err(ProtocolError::UnsupportedVersion(packet[4]));
+    }
+    Ok(u32::from_le_bytes(packet[8..12].try_into().unwrap()))
+}
+
+/// Appends `samples` to `out` as little-endian PCM16, the payload of `CODEC_PCM16`.
+pub fn encode_pcm16<I>(samples: I, out: &mut Vec<u8>)
+where
+    I: IntoIterator<Item = i16>,
+{
+    for sample in samples {
+        out.extend_from_slice(&sample.to_le_bytes());
+    }
+}
+
+pub fn decode_pcm16(payload: &[u8], out: &mut [i16]) -> usize {
+    let count = (payload.len() / 2).min(out.len());
+    for (i, sample) in out.iter_mut().take(count).enumerate() {
+        *sample = i16::from_le_bytes([payload[i * 2], payload[i * 2 + 1]]);
+    }
+    count
+}
@@ -0,0 +1,123 @@
+//! Timestamps on the PTP (IEEE 1588) domain clock, so devices on a PTP-synced LAN can
+//! play the same frame at the same instant.
+//!
+//! The clock is either the system clock, for hosts whose OS PTP client disciplines it
+//! (the Windows Time PTP provider, ptp4l with phc2sys), or a Linux PTP hardware clock such
+//! as `/dev/ptp0` read directly. Hardware clocks count TAI; the UTC offset is taken off so
+//! stamps stay comparable with `protocol::now_us`.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::fs::File;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::error::ProtocolError;
+use crate::protocol::now_us;
+
+/// TAI minus UTC, unchanged since the leap second at the end of 2016.
+pub const TAI_UTC_OFFSET_US: u64 = 37_000_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PtpSource {
+    /// The system clock, kept on the domain by the OS PTP client.
+    System,
+    /// A PTP hardware clock device.
+    Device(PathBuf),
+}
+
+impl From<&str> for PtpSource {
+    fn from(s: &str) -> Self {
+        match s {
+            "system" => Self::System,
+            path => Self::Device(path.into()),
+        }
+    }
+}
+
+impl FromStr for PtpSource {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.into())
+    }
+}
+
+impl fmt::Display for PtpSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::System => f.write_str("system"),
+            Self::Device(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+pub struct PtpClock {
+    /// The hardware clock, if any; the descriptor keeps its dynamic clock id valid.
+    device: Option<(PathBuf, File)>,
+}
+
+impl PtpClock {
+    pub fn open(source: &PtpSource) -> Result<Self, ProtocolError> {
+        let PtpSource::Device(path) = source else {
+            return Ok(Self { device: None });
+        };
+        let file = open_device(path).map_err(|source| ProtocolError::PtpClock {
+            path: path.clone(),
+            source,
+        })?;
+        let clock = Self {
+            device: Some((path.clone(), file)),
+        };
+        clock.now_us()?;
+        Ok(clock)
+    }
+
+    /// Microseconds since the Unix epoch on the domain clock.
+    pub fn now_us(&self) -> Result<u64, ProtocolError> {
+        match &self.device {
+            Some((path, file)) => read_device(file).map_err(|source| ProtocolError::PtpClock {
+                path: path.clone(),
+                source,
+            }),
+            None => now_us(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_device(path: &std::path::Path) -> std::io::Result<File> {
+    File::open(path)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_device(_path: &std::path::Path) -> std::io::Result<File> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "PTP hardware clocks can only be read on Linux; use `system` with the OS PTP client",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn read_device(device: &File) -> std::io::Result<u64> {
+    use std::os::fd::AsRawFd;
+
+    // FD_TO_CLOCKID from the kernel's posix-timers.
+    let clock_id = ((!device.as_raw_fd()) << 3) | 3;
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `time` is a valid timespec and `clock_id` names a clock whose descriptor
+    // `device` keeps open.
+    if unsafe { libc::clock_gettime(clock_id, &mut time) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let tai_us = time.tv_sec as u64 * 1_000_000 + time.tv_nsec as u64 / 1_000;
+    Ok(tai_us.saturating_sub(TAI_UTC_OFFSET_US))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_device(_device: &File) -> std::io::Result<u64> {
+    unreachable!("no device is opened outside Linux")
+}
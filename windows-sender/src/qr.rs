@@ -0,0 +1,440 @@
+//! QR codes (ISO/IEC 18004) for `--pair`: byte mode, error correction level M, versions 1
+//! to 10, which is plenty for a pairing link.
+
+/// Error correction codewords per block and `(blocks, data codewords per block)` groups.
+struct Version {
+    ec_len: usize,
+    groups: [(usize, usize); 2],
+    alignment: &'static [usize],
+}
+
+const VERSIONS: [Version; 10] = [
+    Version {
+        ec_len: 10,
+        groups: [(1, 16), (0, 0)],
+        alignment: &[],
+    },
+    Version {
+        ec_len: 16,
+        groups: [(1, 28), (0, 0)],
+        alignment: &[6, 18],
+    },
+    Version {
+        ec_len: 26,
+        groups: [(1, 44), (0, 0)],
+        alignment: &[6, 22],
+    },
+    Version {
+        ec_len: 18,
+        groups: [(2, 32), (0, 0)],
+        alignment: &[6, 26],
+    },
+    Version {
+        ec_len: 24,
+        groups: [(2, 43), (0, 0)],
+        alignment: &[6, 30],
+    },
+    Version {
+        ec_len: 16,
+        groups: [(4, 27), (0, 0)],
+        alignment: &[6, 34],
+    },
+    Version {
+        ec_len: 18,
+        groups: [(4, 31), (0, 0)],
+        alignment: &[6, 22, 38],
+    },
+    Version {
+        ec_len: 22,
+        groups: [(2, 38), (2, 39)],
+        alignment: &[6, 24, 42],
+    },
+    Version {
+        ec_len: 22,
+        groups: [(3, 36), (2, 37)],
+        alignment: &[6, 26, 46],
+    },
+    Version {
+        ec_len: 26,
+        groups: [(4, 43), (1, 44)],
+        alignment: &[6, 28, 50],
+    },
+];
+
+/// Format bits of error correction level M.
+const LEVEL_M: u32 = 0;
+const QUIET_ZONE: i32 = 4;
+
+impl Version {
+    fn data_len(&self) -> usize {
+        self.groups.iter().map(|(blocks, len)| blocks * len).sum()
+    }
+}
+
+pub struct QrCode {
+    size: usize,
+    dark: Vec<bool>,
+}
+
+impl QrCode {
+    /// The smallest code holding `data`, or `None` if it does not fit in version 10.
+    pub fn encode(data: &[u8]) -> Option<Self> {
+        let (number, version) = VERSIONS.iter().enumerate().find_map(|(index, version)| {
+            let count_bits = if index < 9 { 8 } else { 16 };
+            (4 + count_bits + 8 * data.len() <= 8 * version.data_len())
+                .then_some((index + 1, version))
+        })?;
+        let codewords = codewords(data, number, version);
+        let mut code = Matrix::new(number);
+        code.draw_function_patterns(number, version);
+        code.draw_codewords(&codewords);
+        let best = (0..8)
+            .min_by_key(|&mask| {
+                let mut candidate = code.clone();
+                candidate.apply_mask(mask);
+                candidate.draw_format(mask);
+                candidate.penalty()
+            })
+            .unwrap();
+        code.apply_mask(best);
+        code.draw_format(best);
+        Some(Self {
+            size: code.size,
+            dark: code.dark,
+        })
+    }
+
+    /// Two rows per line with half blocks, light modules drawn, for dark terminals.
+    pub fn render(&self) -> String {
+        let dark = |x: i32, y: i32| {
+            (0..self.size as i32).contains(&x)
+                && (0..self.size as i32).contains(&y)
+                && self.dark[y as usize * self.size + x as usize]
+        };
+        let edge = self.size as i32 + QUIET_ZONE;
+        let mut out = String::new();
+        for y in (-QUIET_ZONE..edge).step_by(2) {
+            for x in -QUIET_ZONE..edge {
+                out.push(match (dark(x, y), dark(x, y + 1)) {
+                    (false, false) => '█',
+                    (false, true) => '▀',
+                    (true, false) => '▄',
+                    (true, true) => ' ',
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Data and error correction codewords, interleaved across blocks.
+fn codewords(data: &[u8], number: usize, version: &Version) -> Vec<u8> {
+    let mut bits = Bits::default();
+    bits.push(0b0100, 4);
+    bits.push(data.len() as u32, if number < 10 { 8 } else { 16 });
+    for &byte in data {
+        bits.push(u32::from(byte), 8);
+    }
+    let capacity = 8 * version.data_len();
+    let terminator = (capacity - bits.len).min(4);
+    bits.push(0, terminator);
+    bits.push(0, (8 - bits.len % 8) % 8);
+    let mut stream = bits.bytes;
+    for pad in [0xec, 0x11].into_iter().cycle() {
+        if stream.len() == version.data_len() {
+            break;
+        }
+        stream.push(pad);
+    }
+
+    let divisor = rs_divisor(version.ec_len);
+    let mut blocks = Vec::new();
+    let mut rest = &stream[..];
+    for &(count, len) in &version.groups {
+        for _ in 0..count {
+            let (block, tail) = rest.split_at(len);
+            blocks.push((block, rs_remainder(block, &divisor)));
+            rest = tail;
+        }
+    }
+    let longest = version.groups.iter().map(|&(_, len)| len).max().unwrap();
+    let mut out = Vec::new();
+    for i in 0..longest {
+        out.extend(blocks.iter().filter_map(|(block, _)| block.get(i)));
+    }
+    for i in 0..version.ec_len {
+        out.extend(blocks.iter().map(|(_, ec)| ec[i]));
+    }
+    out
+}
+
+#[derive(Default)]
+struct Bits {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Bits {
+    fn push(&mut self, value: u32, count: usize) {
+        for i in (0..count).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            let bit = (value >> i) as u8 & 1;
+            *self.bytes.last_mut().unwrap() |= bit << (7 - self.len % 8);
+            self.len += 1;
+        }
+    }
+}
+
+/// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1.
+fn gf_mul(x: u8, y: u8) -> u8 {
+    let mut z = 0_u32;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11d);
+        z ^= ((u32::from(y) >> i) & 1) * u32::from(x);
+    }
+    z as u8
+}
+
+/// The generator polynomial (x - a^0)(x - a^1)...(x - a^(degree-1)), leading term dropped.
+fn rs_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0; degree];
+    result[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_mul(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_mul(root, 2);
+    }
+    result
+}
+
+fn rs_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (out, &coef) in result.iter_mut().zip(divisor) {
+            *out ^= gf_mul(coef, factor);
+        }
+    }
+    result
+}
+
+#[derive(Clone)]
+struct Matrix {
+    size: usize,
+    dark: Vec<bool>,
+    function: Vec<bool>,
+}
+
+impl Matrix {
+    fn new(number: usize) -> Self {
+        let size = 17 + 4 * number;
+        Self {
+            size,
+            dark: vec![false; size * size],
+            function: vec![false; size * size],
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        self.dark[y * self.size + x]
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.dark[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, number: usize, version: &Version) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            self.draw_finder(x, y);
+        }
+        let positions = version.alignment;
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                let corner = (i == 0 && (j == 0 || j == last)) || (i == last && j == 0);
+                if !corner {
+                    self.draw_alignment(x, y);
+                }
+            }
+        }
+        // Reserve the format areas; `draw_format` fills them in per mask.
+        self.draw_format(0);
+        if number >= 7 {
+            let mut rem = number as u32;
+            for _ in 0..12 {
+                rem = (rem << 1) ^ ((rem >> 11) * 0x1f25);
+            }
+            let bits = (number as u32) << 12 | rem;
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 == 1;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function(a, b, dark);
+                self.set_function(b, a, dark);
+            }
+        }
+    }
+
+    fn draw_finder(&mut self, cx: usize, cy: usize) {
+        for dy in -4_i32..=4 {
+            for dx in -4_i32..=4 {
+                let (x, y) = (cx as i32 + dx, cy as i32 + dy);
+                if (0..self.size as i32).contains(&x) && (0..self.size as i32).contains(&y) {
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function(x as usize, y as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment(&mut self, cx: usize, cy: usize) {
+        for dy in -2_i32..=2 {
+            for dx in -2_i32..=2 {
+                let (x, y) = ((cx as i32 + dx) as usize, (cy as i32 + dy) as usize);
+                self.set_function(x, y, dx.abs().max(dy.abs()) != 1);
+            }
+        }
+    }
+
+    fn draw_format(&mut self, mask: u32) {
+        let data = LEVEL_M << 3 | mask;
+        let mut rem = data;
+        for _ in 0..10 {
+            rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+        }
+        let bits = (data << 10 | rem) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 == 1;
+        let size = self.size;
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Fills the non-function modules in the standard zigzag, two columns at a time.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vertical in 0..size {
+                for x in [right, right - 1] {
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward {
+                        size - 1 - vertical
+                    } else {
+                        vertical
+                    };
+                    if !self.function[y * size + x] && i < codewords.len() * 8 {
+                        self.dark[y * size + x] = (codewords[i / 8] >> (7 - i % 8)) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let index = y * self.size + x;
+                self.dark[index] ^= invert && !self.function[index];
+            }
+        }
+    }
+
+    /// The standard's score for a masked symbol; lower scans more reliably.
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut penalty = 0;
+        for transpose in [false, true] {
+            let at = |a: usize, b: usize| {
+                if transpose {
+                    self.get(b, a)
+                } else {
+                    self.get(a, b)
+                }
+            };
+            for b in 0..size {
+                let line: Vec<bool> = (0..size).map(|a| at(a, b)).collect();
+                let mut run = 1;
+                for a in 1..=size {
+                    if a < size && line[a] == line[a - 1] {
+                        run += 1;
+                        continue;
+                    }
+                    if run >= 5 {
+                        penalty += run - 2;
+                    }
+                    run = 1;
+                }
+                for window in line.windows(11) {
+                    let finder = [true, false, true, true, true, false, true];
+                    if window[..7] == finder && window[7..].iter().all(|dark| !dark)
+                        || window[4..] == finder && window[..4].iter().all(|dark| !dark)
+                    {
+                        penalty += 40;
+                    }
+                }
+            }
+        }
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let color = self.get(x, y);
+                if color == self.get(x + 1, y)
+                    && color == self.get(x, y + 1)
+                    && color == self.get(x + 1, y + 1)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+        let dark = self.dark.iter().filter(|dark| **dark).count();
+        let total = size * size;
+        let deviation = (dark * 20).abs_diff(total * 10);
+        penalty + 10 * (deviation.div_ceil(total).saturating_sub(1))
+    }
+}
@@ -0,0 +1,632 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::{debug, warn};
+
+use crate::clock::ClockSync;
+use crate::crypto::Key;
+use crate::decoder::{ReceiverFormat, StreamDecoder};
+use crate::error::{Error, TransportError};
+use crate::pairing::{is_handshake, NoiseConfig, ReceiverPairing};
+use crate::protocol::{
+    build_access_token, build_clock, build_feedback, from_clock_us, is_clock, is_marker, is_pause,
+    is_start, now_us, parse_clock, parse_marker, parse_pause, parse_start, ClockMessage,
+    FeedbackReport, PlayedRange, SyncMarker,
+};
+use crate::ptp::{PtpClock, PtpSource};
+use crate::stats::ReceiverStatsSnapshot;
+#[cfg(not(uring_backend))]
+use crate::transport::io_uring_unsupported;
+use crate::transport::{is_timeout, set_buffer_size, SocketBuffer, TransportKind};
+#[cfg(uring_backend)]
+use crate::uring::RecvRing;
+
+#[derive(Clone, Debug)]
+pub struct ReceiverConfig {
+    pub port: u16,
+    pub transport: TransportKind,
+    pub jitter_ms: u32,
+    /// Pre-shared key the sender encrypts with; `None` accepts only plaintext streams.
+    pub key: Option<Key>,
+    /// Pairs with senders instead and plays only streams sealed with the key a pinned
+    /// sender handed over.
+    pub noise: Option<NoiseConfig>,
+    /// Presented to the sender so it accepts this receiver's reports.
+    pub access_token: Option<String>,
+    /// Plays frames stamped on the PTP domain clock at a fixed delay after their send time.
+    pub ptp: Option<PtpPlayout>,
+    /// Receives UDP through an io_uring multishot receive; needs Linux and the `io-uring`
+    /// feature.
+    pub io_uring: bool,
+    /// `SO_RCVBUF` of the socket, in bytes; `None` keeps the OS default.
+    pub recv_buffer: Option<u32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PtpPlayout {
+    pub source: PtpSource,
+    /// Send time to playout; must cover the network and the sender's own jitter, and be
+    /// the same on every receiver that should play in step.
+    pub delay: Duration,
+}
+
+/// How often the sender gets a report on what arrived and what was played, and a clock
+/// ping.
+const FEEDBACK_INTERVAL: Duration = Duration::from_millis(250);
+const MAX_MEDIA_STEP_US: i64 = 5_000_000;
+/// Sync markers kept for `next_marker`; older ones are dropped first.
+const MAX_MARKERS: usize = 64;
+/// How long a UDP receive waits before the loop checks whether it should stop.
+const UDP_READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+struct ReceiverShared {
+    running: AtomicBool,
+    decoder: StreamDecoder,
+    feedback: Mutex<FeedbackState>,
+    pairing: Option<Mutex<ReceiverPairing>>,
+    access_frame: Option<Vec<u8>>,
+    /// Sender clock against ours, from the answers to clock pings.
+    clock: Mutex<ClockSync>,
+    /// Multi-room playout delay the sender asks for, in microseconds; 0 plays on arrival.
+    /// Stays 0 with `ReceiverConfig::ptp`, which sets its own delay.
+    playout_delay_us: AtomicU64,
+    follows_sender_delay: bool,
+    /// Deferred start the sender announced, on its clock; 0 when none is pending.
+    start_us: AtomicU64,
+    markers: Mutex<VecDeque<SyncMarker>>,
+}
+
+/// What the next report to the sender will contain.
+#[derive(Default)]
+struct FeedbackState {
+    played: Option<PlayedRange>,
+    /// `send_time_us` of the newest packet and when it arrived, on the local clock.
+    last_packet: Option<(u64, u64)>,
+    /// `media_time` of the newest audio frame and when it arrived; jitter is measured on
+    /// these so stepping either wall clock does not show up as network jitter.
+    last_frame: Option<(u32, Instant)>,
+    jitter_us: f64,
+    /// One-way delay of the newest packet, once the sender clock is known.
+    transit_us: u64,
+}
+
+impl ReceiverShared {
+    /// The answer to a handshake frame from `from`, if it needs one.
+    fn pair(&self, from: SocketAddr, packet: &[u8]) -> Option<Vec<u8>> {
+        let mut pairing = self.pairing.as_ref()?.lock().unwrap();
+        let (answer, key) = pairing.handle(from, packet)?;
+        if let Some(key) = key {
+            self.decoder.set_key(Some(&key));
+        }
+        Some(answer)
+    }
+
+    fn record_packet(&self) {
+        self.start_us.store(0, Ordering::Relaxed);
+        let mut feedback = self.feedback.lock().unwrap();
+        if let (Some(media_time), Some(format)) =
+            (self.decoder.last_media_time(), self.decoder.format())
+        {
+            let arrived = Instant::now();
+            // Probes and duplicates bring no new frame.
+            if feedback
+                .last_frame
+                .is_none_or(|(last, _)| last != media_time)
+            {
+                if let Some((last_media, last_arrived)) = feedback.last_frame {
+                    let media_us = media_time.wrapping_sub(last_media) as i32 as i64 * 1_000_000
+                        / format.sample_rate.max(1) as i64;
+                    // A jump of more than a few seconds is a restarted stream, not jitter.
+                    if media_us.abs() < MAX_MEDIA_STEP_US {
+                        // RFC 3550: smoothed change in transit time between frames.
+                        let arrival_us = (arrived - last_arrived).as_micros() as i64;
+                        let transit_change = arrival_us - media_us;
+                        feedback.jitter_us +=
+                            (transit_change.unsigned_abs() as f64 - feedback.jitter_us) / 16.0;
+                    }
+                }
+                feedback.last_frame = Some((media_time, arrived));
+            }
+        }
+        if let Ok(now) = now_us() {
+            let sent = self.decoder.last_send_time_us();
+            feedback.last_packet = Some((sent, now));
+            if let Some(offset_us) = self.clock.lock().unwrap().offset_us(now) {
+                feedback.transit_us = (now as i64 - sent as i64 + offset_us).max(0) as u64;
+            }
+        }
+    }
+
+    fn record_clock(&self, packet: &[u8]) {
+        let (Ok(reply), Ok(now)) = (parse_clock(packet), now_us()) else {
+            return;
+        };
+        if reply.reply && reply.origin_us <= now {
+            self.clock.lock().unwrap().add(
+                reply.origin_us,
+                reply.receive_us,
+                reply.transmit_us,
+                now,
+            );
+            if self.follows_sender_delay {
+                let delay = Duration::from_millis(reply.playout_delay_ms.into());
+                if !delay.is_zero() {
+                    self.decoder.schedule_playout(delay, true);
+                }
+                self.playout_delay_us
+                    .store(delay.as_micros() as u64, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Sender clock time whose frames should be playing now, in multi-room playback.
+    fn sender_playout_us(&self) -> Option<u64> {
+        let delay_us = self.playout_delay_us.load(Ordering::Relaxed);
+        let now = now_us().ok()?;
+        let offset_us = self.clock.lock().unwrap().offset_us(now)?;
+        (now as i64 + offset_us - delay_us as i64).try_into().ok()
+    }
+
+    fn record_start(&self, packet: &[u8]) {
+        let Ok(start_us) = parse_start(packet) else {
+            return;
+        };
+        if self.start_us.swap(start_us, Ordering::Relaxed) != start_us {
+            debug!("stream announced to start at {start_us} us on the sender clock");
+        }
+    }
+
+    fn record_pause(&self, packet: &[u8]) {
+        let Ok(media_time) = parse_pause(packet) else {
+            return;
+        };
+        debug!("stream paused at media time {media_time}");
+        self.decoder.pause();
+    }
+
+    fn record_marker(&self, packet: &[u8]) {
+        let Ok(marker) = parse_marker(packet) else {
+            return;
+        };
+        debug!(
+            "sync marker {:?} at media time {}",
+            marker.name, marker.media_time
+        );
+        let mut markers = self.markers.lock().unwrap();
+        if markers.len() == MAX_MARKERS {
+            markers.pop_front();
+        }
+        markers.push_back(marker);
+    }
+
+    fn record_played(&self) {
+        let (Some(seq), Ok(now)) = (self.decoder.last_played_seq(), now_us()) else {
+            return;
+        };
+        let mut feedback = self.feedback.lock().unwrap();
+        feedback.played = Some(PlayedRange {
+            first_seq: feedback.played.map_or(seq, |played| played.first_seq),
+            last_seq: seq,
+            playout_time_us: now,
+        });
+    }
+
+    /// The report for the sender, once a packet has arrived.
+    fn take_report(&self) -> Option<Vec<u8>> {
+        let mut feedback = self.feedback.lock().unwrap();
+        let (echo_send_time_us, echo_recv_time_us) = feedback.last_packet?;
+        let report = FeedbackReport {
+            played: feedback.played.take(),
+            echo_send_time_us,
+            echo_recv_time_us,
+            report_time_us: now_us().ok()?,
+            counts: self.decoder.sequence_counts(),
+            jitter_us: feedback.jitter_us as u64,
+            probe_bytes: self.decoder.stats().probe_bytes,
+        };
+        Some(build_feedback(&report))
+    }
+
+    fn clock_ping(&self) -> Option<Vec<u8>> {
+        Some(build_clock(&ClockMessage::ping(now_us().ok()?)))
+    }
+}
+
+pub struct AudioReceiver {
+    shared: Arc<ReceiverShared>,
+    /// The domain clock and the playout delay in microseconds.
+    ptp: Option<(PtpClock, u64)>,
+    thread: Option<thread::JoinHandle<Result<(), Error>>>,
+}
+
+impl AudioReceiver {
+    pub fn start(config: ReceiverConfig) -> Result<Self, Error> {
+        if config.io_uring && config.transport != TransportKind::Udp {
+            return Err(Error::Config(
+                "--io-uring only applies to the UDP transport".to_string(),
+            ));
+        }
+        let ptp = match &config.ptp {
+            Some(ptp) => Some((PtpClock::open(&ptp.source)?, ptp.delay.as_micros() as u64)),
+            None => None,
+        };
+        let decoder = StreamDecoder::new(config.jitter_ms);
+        if let Some(ptp) = &config.ptp {
+            decoder.schedule_playout(ptp.delay, false);
+        }
+        let pairing = match config.noise {
+            Some(_) if config.key.is_some() => {
+                return Err(Error::Config(
+                    "a pre-shared key and Noise pairing cannot be combined".to_string(),
+                ))
+            }
+            Some(noise) => {
+                // Until a sender pairs, a key nobody has rejects every stream.
+                decoder.set_key(Some(&Key::generate()?));
+                Some(Mutex::new(ReceiverPairing::new(noise)?))
+            }
+            None => {
+                decoder.set_key(config.key.as_ref());
+                None
+            }
+        };
+        let shared = Arc::new(ReceiverShared {
+            running: AtomicBool::new(true),
+            decoder,
+            feedback: Mutex::new(FeedbackState::default()),
+            pairing,
+            access_frame: config.access_token.as_deref().map(build_access_token),
+            clock: Mutex::new(ClockSync::new()),
+            playout_delay_us: AtomicU64::new(0),
+            follows_sender_delay: config.ptp.is_none(),
+            start_us: AtomicU64::new(0),
+            markers: Mutex::new(VecDeque::new()),
+        });
+        let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
+
+        let thread_shared = Arc::clone(&shared);
+        let thread =
+            match config.transport {
+                TransportKind::Udp => {
+                    let socket = UdpSocket::bind(addr)
+                        .map_err(|source| TransportError::Listen { addr, source })?;
+                    socket
+                        .set_read_timeout(Some(UDP_READ_TIMEOUT))
+                        .map_err(|source| TransportError::Configure {
+                            what: "UDP receiver socket",
+                            source,
+                        })?;
+                    if let Some(bytes) = config.recv_buffer {
+                        set_buffer_size(&socket, SocketBuffer::Receive, bytes)?;
+                    }
+                    let source = UdpSource::open(&socket, config.io_uring)?;
+                    thread::Builder::new()
+                        .name("audio-receiver".to_string())
+                        .spawn(move || receive_udp_loop(socket, source, &thread_shared))
+                }
+                TransportKind::Tcp => {
+                    let listener = TcpListener::bind(addr)
+                        .map_err(|source| TransportError::Listen { addr, source })?;
+                    listener
+                        .set_nonblocking(true)
+                        .map_err(|source| TransportError::Configure {
+                            what: "TCP receiver socket",
+                            source,
+                        })?;
+                    // Accepted connections inherit it.
+                    if let Some(bytes) = config.recv_buffer {
+                        set_buffer_size(&listener, SocketBuffer::Receive, bytes)?;
+                    }
+                    thread::Builder::new()
+                        .name("audio-receiver".to_string())
+                        .spawn(move || receive_tcp_loop(listener, &thread_shared))
+                }
+                kind @ (TransportKind::Vban | TransportKind::Scream | TransportKind::Aes67) => {
+                    return Err(Error::Config(format!(
+                        "the receiver takes udp or tcp; a {} stream plays in a {0} receiver",
+                        kind.as_str()
+                    )))
+                }
+                TransportKind::Bluetooth => return Err(Error::Config(
+                    "the receiver takes udp or tcp; Bluetooth streams play on the Android receiver"
+                        .to_string(),
+                )),
+            }
+            .map_err(|source| Error::Spawn {
+                name: "audio-receiver",
+                source,
+            })?;
+
+        Ok(Self {
+            shared,
+            ptp,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn format(&self) -> Option<ReceiverFormat> {
+        self.shared.decoder.format()
+    }
+
+    /// The next frame to play. Callers should hand it to the output right away: the
+    /// time of this call is reported to the sender as the frame's playout time.
+    ///
+    /// With `ReceiverConfig::ptp` and a PTP-stamped stream, or a sender that sets a
+    /// multi-room playout delay, `None` also means the next frame is not due yet; play
+    /// silence for it as for a missing one.
+    pub fn read_frame(&self, timeout: Duration) -> Option<Vec<i16>> {
+        let shared = &self.shared;
+        let decoder = &shared.decoder;
+        let frame = match &self.ptp {
+            Some((clock, delay_us)) => decoder.pop_frame_scheduled(timeout, || {
+                Some(clock.now_us().ok()?.saturating_sub(*delay_us))
+            })?,
+            None if shared.playout_delay_us.load(Ordering::Relaxed) > 0 => {
+                decoder.pop_frame_scheduled(timeout, || shared.sender_playout_us())?
+            }
+            None => decoder.pop_frame(timeout)?,
+        };
+        self.shared.record_played();
+        Some(frame)
+    }
+
+    /// When the stream starts if the sender deferred it with `SenderConfig::start_at`,
+    /// moved to this clock by the offset from clock pings once one is known. Cleared as the
+    /// audio begins.
+    pub fn scheduled_start(&self) -> Option<SystemTime> {
+        let start_us = self.shared.start_us.load(Ordering::Relaxed);
+        if start_us == 0 {
+            return None;
+        }
+        let now = now_us().ok()?;
+        let offset_us = self
+            .shared
+            .clock
+            .lock()
+            .unwrap()
+            .offset_us(now)
+            .unwrap_or(0);
+        let local_us = u64::try_from(start_us as i64 - offset_us).ok()?;
+        from_clock_us(local_us).ok()
+    }
+
+    /// The oldest sync marker not read yet. Its `media_time` is on the clock of the
+    /// `media_time` in the audio packet headers.
+    pub fn next_marker(&self) -> Option<SyncMarker> {
+        self.shared.markers.lock().unwrap().pop_front()
+    }
+
+    pub fn stats(&self) -> ReceiverStatsSnapshot {
+        let feedback = self.shared.feedback.lock().unwrap();
+        let clock = self.shared.clock.lock().unwrap();
+        let now = now_us().unwrap_or_default();
+        ReceiverStatsSnapshot {
+            jitter_us: feedback.jitter_us as u64,
+            rtt_us: clock.latest().map_or(0, |sample| sample.rtt_us),
+            clock_offset_us: clock.offset_us(now).unwrap_or(0),
+            clock_drift_ppb: clock.drift_ppb().unwrap_or(0),
+            transit_us: feedback.transit_us,
+            ..self.shared.decoder.stats()
+        }
+    }
+
+    pub fn stop(mut self) -> Result<(), Error> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        self.shared.running.store(false, Ordering::Relaxed);
+        match self.thread.take() {
+            Some(handle) => handle
+                .join()
+                .unwrap_or_else(|payload| Err(Error::panicked("audio-receiver", payload))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for AudioReceiver {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+/// Where the UDP loop reads datagrams from; answers always go out on the socket.
+enum UdpSource {
+    Socket,
+    #[cfg(uring_backend)]
+    Ring(RecvRing),
+}
+
+impl UdpSource {
+    #[cfg(not(uring_backend))]
+    fn open(_socket: &UdpSocket, io_uring: bool) -> Result<Self, TransportError> {
+        if io_uring {
+            return Err(io_uring_unsupported());
+        }
+        Ok(UdpSource::Socket)
+    }
+
+    #[cfg(uring_backend)]
+    fn open(socket: &UdpSocket, io_uring: bool) -> Result<Self, TransportError> {
+        if !io_uring {
+            return Ok(UdpSource::Socket);
+        }
+        let ring = RecvRing::new(socket).map_err(|source| TransportError::Configure {
+            what: "io_uring",
+            source,
+        })?;
+        Ok(UdpSource::Ring(ring))
+    }
+
+    fn recv_from(&mut self, socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self {
+            UdpSource::Socket => socket.recv_from(buf),
+            #[cfg(uring_backend)]
+            UdpSource::Ring(ring) => ring.recv_from(buf, UDP_READ_TIMEOUT),
+        }
+    }
+}
+
+fn receive_udp_loop(
+    socket: UdpSocket,
+    mut source: UdpSource,
+    shared: &ReceiverShared,
+) -> Result<(), Error> {
+    let mut packet_buf = vec![0u8; 8192];
+    let mut sender = None;
+    let mut next_report = Instant::now() + FEEDBACK_INTERVAL;
+    while shared.running.load(Ordering::Relaxed) {
+        match source.recv_from(&socket, &mut packet_buf) {
+            Ok((len, from)) if Some(from) == sender && is_clock(&packet_buf[..len]) => {
+                shared.record_clock(&packet_buf[..len]);
+            }
+            Ok((len, _)) if is_start(&packet_buf[..len]) => {
+                shared.record_start(&packet_buf[..len]);
+            }
+            Ok((len, from)) if is_pause(&packet_buf[..len]) => {
+                if Some(from) == sender {
+                    shared.record_pause(&packet_buf[..len]);
+                }
+            }
+            Ok((len, from)) if is_marker(&packet_buf[..len]) => {
+                // Only the stream being played can place markers in it.
+                if Some(from) == sender {
+                    shared.record_marker(&packet_buf[..len]);
+                }
+            }
+            Ok((len, from)) if is_handshake(&packet_buf[..len]) => {
+                if let Some(answer) = shared.pair(from, &packet_buf[..len]) {
+                    if let Err(err) = socket.send_to(&answer, from) {
+                        debug!("failed to answer handshake from {from}: {err}");
+                    }
+                }
+            }
+            Ok((len, from)) => {
+                if shared.decoder.push_packet(&packet_buf[..len], 0) {
+                    shared.record_packet();
+                    sender = Some(from);
+                }
+            }
+            Err(err) if is_timeout(&err) => {}
+            Err(err) => return Err(TransportError::Receive(err).into()),
+        }
+        if Instant::now() >= next_report {
+            next_report = Instant::now() + FEEDBACK_INTERVAL;
+            if let (Some(sender), Some(report)) = (sender, shared.take_report()) {
+                // Reports can get lost, so the token goes along with each one.
+                let ping = shared.clock_ping();
+                let frames = shared
+                    .access_frame
+                    .iter()
+                    .chain([&report])
+                    .chain(ping.as_ref());
+                for frame in frames {
+                    if let Err(err) = socket.send_to(frame, sender) {
+                        debug!("failed to send feedback to {sender}: {err}");
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn receive_tcp_loop(listener: TcpListener, shared: &ReceiverShared) -> Result<(), Error> {
+    while shared.running.load(Ordering::Relaxed) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if is_timeout(&err) => {
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            Err(err) => return Err(TransportError::Receive(err).into()),
+        };
+        if let Err(err) = read_tcp_client_loop(stream, shared) {
+            if shared.running.load(Ordering::Relaxed) {
+                warn!("tcp receiver client error: {err}");
+                shared.decoder.record_parse_error();
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_tcp_client_loop(mut stream: TcpStream, shared: &ReceiverShared) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(Duration::from_millis(800)))?;
+    let peer = stream.peer_addr()?;
+    let mut len_buf = [0u8; 2];
+    let mut packet_buf = vec![0u8; 8192];
+    let mut next_report = Instant::now() + FEEDBACK_INTERVAL;
+    if let Some(frame) = &shared.access_frame {
+        stream.write_all(&(frame.len() as u16).to_le_bytes())?;
+        stream.write_all(frame)?;
+    }
+
+    while shared.running.load(Ordering::Relaxed) {
+        if !read_fully(&mut stream, &mut len_buf, shared)? {
+            break;
+        }
+        let packet_len = u16::from_le_bytes(len_buf) as usize;
+        if packet_len == 0 {
+            shared.decoder.record_parse_error();
+            break;
+        }
+        if packet_len > packet_buf.len() {
+            packet_buf.resize(packet_len, 0);
+        }
+        if !read_fully(&mut stream, &mut packet_buf[..packet_len], shared)? {
+            break;
+        }
+        let packet = &packet_buf[..packet_len];
+        if is_handshake(packet) {
+            if let Some(answer) = shared.pair(peer, packet) {
+                stream.write_all(&(answer.len() as u16).to_le_bytes())?;
+                stream.write_all(&answer)?;
+            }
+        } else if is_clock(packet) {
+            shared.record_clock(packet);
+        } else if is_start(packet) {
+            shared.record_start(packet);
+        } else if is_pause(packet) {
+            shared.record_pause(packet);
+        } else if is_marker(packet) {
+            shared.record_marker(packet);
+        } else if shared.decoder.push_packet(packet, 2) {
+            shared.record_packet();
+        }
+        if Instant::now() >= next_report {
+            next_report = Instant::now() + FEEDBACK_INTERVAL;
+            if let Some(report) = shared.take_report() {
+                for frame in [Some(report), shared.clock_ping()].into_iter().flatten() {
+                    stream.write_all(&(frame.len() as u16).to_le_bytes())?;
+                    stream.write_all(&frame)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_fully(stream: &mut TcpStream, out: &mut [u8], shared: &ReceiverShared) -> io::Result<bool> {
+    let mut offset = 0;
+    while offset < out.len() {
+        if !shared.running.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        match stream.read(&mut out[offset..]) {
+            Ok(0) => return Ok(false),
+            Ok(n) => offset += n,
+            Err(err) if is_timeout(&err) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,269 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::error::{Error, TransportError};
+use crate::transport::is_timeout;
+
+const MDNS_GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353);
+/// Responders answer within a few hundred milliseconds; a name still silent after this is
+/// not on the link.
+const MDNS_WAIT: Duration = Duration::from_secs(1);
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+/// Class IN, with the top bit asking for the answer by unicast.
+const CLASS_IN_UNICAST: u16 = 0x8001;
+/// More labels than a name can have; stops compression pointers that loop.
+const MAX_LABELS: usize = 128;
+
+/// Which address a name that has both IPv4 and IPv6 addresses is sent to.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum IpPreference {
+    #[default]
+    V4,
+    V6,
+}
+
+/// A receiver given by host name, resolved again whenever the sender reconnects so a
+/// phone renumbered by DHCP is found at its new address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TargetName {
+    pub host: String,
+    pub port: u16,
+    pub prefer: IpPreference,
+}
+
+impl TargetName {
+    /// Names under `.local` are asked for by mDNS first, then like any other name through
+    /// the system resolver.
+    pub fn resolve(&self) -> Result<SocketAddr, TransportError> {
+        let mut addrs = Vec::new();
+        if is_mdns_name(&self.host) {
+            match query_mdns(&self.host) {
+                Ok(found) => addrs = found,
+                Err(err) => debug!("mDNS query for {} failed: {err}", self.host),
+            }
+        }
+        if addrs.is_empty() {
+            addrs = (self.host.as_str(), 0)
+                .to_socket_addrs()
+                .map_err(|source| TransportError::Resolve {
+                    host: self.host.clone(),
+                    source,
+                })?
+                .map(|addr| addr.ip())
+                .collect();
+        }
+        let preferred = |ip: &&IpAddr| match self.prefer {
+            IpPreference::V4 => ip.is_ipv4(),
+            IpPreference::V6 => ip.is_ipv6(),
+        };
+        let ip = addrs
+            .iter()
+            .find(preferred)
+            .or(addrs.first())
+            .ok_or_else(|| TransportError::Resolve {
+                host: self.host.clone(),
+                source: io::Error::new(io::ErrorKind::NotFound, "no addresses"),
+            })?;
+        Ok(SocketAddr::new(*ip, self.port))
+    }
+}
+
+impl fmt::Display for TargetName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+/// A receiver as the settings give it: an address, or a name to resolve.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Addr(SocketAddr),
+    Name(TargetName),
+}
+
+impl Endpoint {
+    /// `host` is an IPv4 address, an IPv6 address with or without brackets, or a name.
+    pub fn new(host: &str, port: u16, prefer: IpPreference) -> Self {
+        let bare = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+        match bare.parse::<IpAddr>() {
+            Ok(ip) => Endpoint::Addr(SocketAddr::new(ip, port)),
+            Err(_) => Endpoint::Name(TargetName {
+                host: host.to_string(),
+                port,
+                prefer,
+            }),
+        }
+    }
+
+    /// `host:port`, with IPv6 addresses in brackets.
+    pub fn parse(endpoint: &str, prefer: IpPreference) -> Result<Self, Error> {
+        let invalid = || Error::Config(format!("invalid target endpoint {endpoint}"));
+        let (host, port) = endpoint.rsplit_once(':').ok_or_else(invalid)?;
+        let port = port.parse().map_err(|_| invalid())?;
+        if host.is_empty() || (host.contains(':') && !host.starts_with('[')) {
+            return Err(invalid());
+        }
+        Ok(Self::new(host, port, prefer))
+    }
+
+    pub fn resolve(&self) -> Result<SocketAddr, TransportError> {
+        match self {
+            Endpoint::Addr(addr) => Ok(*addr),
+            Endpoint::Name(name) => name.resolve(),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Addr(addr) => addr.fmt(f),
+            Endpoint::Name(name) => name.fmt(f),
+        }
+    }
+}
+
+fn is_mdns_name(host: &str) -> bool {
+    host.trim_end_matches('.')
+        .to_ascii_lowercase()
+        .ends_with(".local")
+}
+
+/// Asks the link for the addresses of `host` with a one-shot mDNS query (RFC 6762 5.1),
+/// which responders answer by unicast since it does not come from port 5353.
+fn query_mdns(host: &str) -> io::Result<Vec<IpAddr>> {
+    let name = host.trim_end_matches('.');
+    let query = build_query(name)?;
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.send_to(&query, MDNS_GROUP)?;
+    let deadline = Instant::now() + MDNS_WAIT;
+    let mut buf = [0u8; 9000];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "no mDNS answer"));
+        }
+        socket.set_read_timeout(Some(left))?;
+        match socket.recv_from(&mut buf) {
+            Ok((len, _)) => {
+                let addrs = parse_answers(&buf[..len], name);
+                if !addrs.is_empty() {
+                    return Ok(addrs);
+                }
+            }
+            Err(err) if is_timeout(&err) => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// A query for the A and AAAA records of `name`.
+fn build_query(name: &str) -> io::Result<Vec<u8>> {
+    let mut packet = vec![0u8; 12];
+    packet[5] = 2;
+    for record_type in [TYPE_A, TYPE_AAAA] {
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{name:?} is not a valid host name"),
+                ));
+            }
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+        packet.push(0);
+        packet.extend_from_slice(&record_type.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN_UNICAST.to_be_bytes());
+    }
+    Ok(packet)
+}
+
+/// The addresses a response gives for `name`. Link-local IPv6 addresses are left out,
+/// since they are useless without the interface they were seen on.
+fn parse_answers(packet: &[u8], name: &str) -> Vec<IpAddr> {
+    let mut addrs = Vec::new();
+    let field = |pos: usize| {
+        packet
+            .get(pos..pos + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+    let (Some(flags), Some(questions)) = (field(2), field(4)) else {
+        return addrs;
+    };
+    if flags & 0x8000 == 0 {
+        return addrs;
+    }
+    let records: usize = [6, 8, 10]
+        .into_iter()
+        .filter_map(field)
+        .map(usize::from)
+        .sum();
+    let mut pos = 12;
+    for _ in 0..questions {
+        let Some((_, next)) = read_name(packet, pos) else {
+            return addrs;
+        };
+        pos = next + 4;
+    }
+    for _ in 0..records {
+        let Some((owner, next)) = read_name(packet, pos) else {
+            break;
+        };
+        let (Some(record_type), Some(len)) = (field(next), field(next + 8)) else {
+            break;
+        };
+        let start = next + 10;
+        let Some(data) = packet.get(start..start + usize::from(len)) else {
+            break;
+        };
+        if owner.eq_ignore_ascii_case(name) {
+            match (record_type, data.len()) {
+                (TYPE_A, 4) => addrs.push(IpAddr::from(<[u8; 4]>::try_from(data).unwrap())),
+                (TYPE_AAAA, 16) => {
+                    let ip = Ipv6Addr::from(<[u8; 16]>::try_from(data).unwrap());
+                    if ip.segments()[0] & 0xffc0 != 0xfe80 {
+                        addrs.push(IpAddr::V6(ip));
+                    }
+                }
+                _ => {}
+            }
+        }
+        pos = start + usize::from(len);
+    }
+    addrs
+}
+
+/// Reads the possibly compressed name at `pos`. Returns it dotted, and where the record
+/// goes on after it.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    for _ in 0..MAX_LABELS {
+        let len = usize::from(*packet.get(pos)?);
+        if len == 0 {
+            return Some((name, end.unwrap_or(pos + 1)));
+        }
+        if len & 0xC0 == 0xC0 {
+            end.get_or_insert(pos + 2);
+            pos = (len & 0x3F) << 8 | usize::from(*packet.get(pos + 1)?);
+            continue;
+        }
+        let label = packet.get(pos + 1..pos + 1 + len)?;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.push_str(&String::from_utf8_lossy(label));
+        pos += 1 + len;
+    }
+    None
+}
@@ -0,0 +1,137 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{Args, AudioSource, PayloadCipher, PreferIp, Transport};
+
+const APP_DIR: &str = "windows-sender";
+const FILE_NAME: &str = "last-session.toml";
+const HEADER: &str =
+    "# Written by windows-sender on every clean start; --resume starts with these.\n\n";
+
+/// The settings that make up a session, in `--config` form.
+#[derive(Serialize)]
+struct LastSession<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_ip: Option<&'a str>,
+    prefer_ip: PreferIp,
+    port: u16,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    extra_targets: &'a [String],
+    source: AudioSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    desktop_device: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_file: Option<PathBuf>,
+    transport: Transport,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bt_device: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bt_channel: Option<u8>,
+    frame_ms: f32,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    plugins: Vec<PathBuf>,
+    encode_workers: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key_name: Option<&'a str>,
+    cipher: PayloadCipher,
+    gain_db: f32,
+}
+
+/// Where the last session is kept: the platform's per-user config directory.
+pub fn path() -> Result<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| Path::new(&home).join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    let base = base.context("no per-user config directory (APPDATA or HOME is not set)")?;
+    Ok(base.join(APP_DIR).join(FILE_NAME))
+}
+
+/// The file `--resume` loads as its `--config`.
+pub fn saved() -> Result<PathBuf> {
+    let path = path()?;
+    if !path.exists() {
+        bail!(
+            "no saved session at {}; start once with --target-ip to record one",
+            path.display()
+        );
+    }
+    Ok(path)
+}
+
+/// Records `args` for the next `--resume`. A failure only costs the next resume, so it is
+/// logged rather than returned.
+pub fn record(args: &Args) {
+    if args.stress {
+        return;
+    }
+    if (args.key.is_some() && args.key_name.is_none())
+        || args.noise.is_some()
+        || args.access_token.is_some()
+    {
+        info!("Session not recorded for --resume: its key, Noise identity or token stays off disk");
+        return;
+    }
+    if let Err(err) = write(args) {
+        warn!("failed to record the session for --resume: {err:#}");
+    }
+}
+
+fn write(args: &Args) -> Result<()> {
+    if args.target_ip.is_none() && args.bt_device.is_none() && args.transport != Transport::Adb {
+        return Ok(());
+    }
+    let plugins = args
+        .plugins
+        .iter()
+        .map(|plugin| {
+            fs::canonicalize(plugin)
+                .with_context(|| format!("failed to locate plugin {}", plugin.display()))
+        })
+        .collect::<Result<_>>()?;
+    let source_file = args
+        .source_file
+        .as_ref()
+        .map(|file| {
+            fs::canonicalize(file).with_context(|| format!("failed to locate {}", file.display()))
+        })
+        .transpose()?;
+    let session = LastSession {
+        target_ip: args.target_ip.as_deref(),
+        prefer_ip: args.prefer_ip,
+        port: args.port,
+        extra_targets: &args.extra_targets,
+        source: args.source,
+        desktop_device: args.desktop_device.as_deref(),
+        source_file,
+        transport: args.transport,
+        bt_device: args.bt_device.as_deref(),
+        bt_channel: args.bt_channel,
+        frame_ms: args.frame_ms,
+        plugins,
+        encode_workers: args.encode_workers,
+        key_name: args.key_name.as_deref(),
+        cipher: args.cipher,
+        gain_db: args.gain_db,
+    };
+    let text = toml::to_string(&session).context("failed to serialize the session")?;
+    let path = path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    fs::write(&path, format!("{HEADER}{text}"))
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    info!("Session recorded for --resume in {}", path.display());
+    Ok(())
+}
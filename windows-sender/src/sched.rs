@@ -0,0 +1,119 @@
+//! Priority and CPU pinning for the capture and send threads, so a busy machine (a game,
+//! a build) does not delay the audio.
+
+use std::io;
+
+use tracing::{debug, warn};
+
+/// `SCHED_FIFO` priority of tuned threads on Unix; mid-range, so the kernel's own
+/// real-time threads still come first.
+#[cfg(unix)]
+const FIFO_PRIORITY: i32 = 50;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Scheduling {
+    /// Runs at real-time priority: `THREAD_PRIORITY_TIME_CRITICAL` on Windows, `SCHED_FIFO`
+    /// on Unix where the process is allowed (`CAP_SYS_NICE` or an `rtprio` limit).
+    pub realtime: bool,
+    /// CPU cores the threads may run on; empty leaves it to the OS.
+    pub cores: Vec<usize>,
+}
+
+impl Scheduling {
+    /// Applies to the calling thread. Failures are logged and otherwise ignored, since the
+    /// stream works without them.
+    pub fn apply(&self, thread: &str) {
+        if self.realtime {
+            match raise_priority() {
+                Ok(()) => debug!("{thread}: real-time priority"),
+                Err(err) => warn!("{thread}: could not raise the priority: {err}"),
+            }
+        }
+        if !self.cores.is_empty() {
+            match pin(&self.cores) {
+                Ok(()) => debug!("{thread}: pinned to cores {:?}", self.cores),
+                Err(err) => warn!("{thread}: could not pin to cores {:?}: {err}", self.cores),
+            }
+        }
+    }
+
+    /// `apply` on the first call of the returned closure, for threads someone else
+    /// spawns, such as audio callbacks.
+    #[cfg(mic_backend)]
+    pub(crate) fn on_first_call(&self, thread: &'static str) -> impl FnMut() + Send + 'static {
+        let mut pending = Some(self.clone());
+        move || {
+            if let Some(scheduling) = pending.take() {
+                scheduling.apply(thread);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+fn raise_priority() -> io::Result<()> {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_TIME_CRITICAL,
+    };
+
+    // SAFETY: the pseudo handle of the calling thread needs no closing.
+    if unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn raise_priority() -> io::Result<()> {
+    // SAFETY: all-zero is a valid `sched_param`; only the priority matters.
+    let mut param: libc::sched_param = unsafe { std::mem::zeroed() };
+    param.sched_priority = FIFO_PRIORITY;
+    // SAFETY: `param` outlives the call.
+    match unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) } {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+#[cfg(not(any(windows, unix)))]
+fn raise_priority() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "thread priorities need Windows or Unix",
+    ))
+}
+
+#[cfg(windows)]
+fn pin(cores: &[usize]) -> io::Result<()> {
+    use windows_sys::Win32::System::Threading::{GetCurrentThread, SetThreadAffinityMask};
+
+    let mask = cores.iter().fold(0usize, |mask, core| mask | 1 << core);
+    // SAFETY: as in `raise_priority`.
+    if unsafe { SetThreadAffinityMask(GetCurrentThread(), mask) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn pin(cores: &[usize]) -> io::Result<()> {
+    // SAFETY: all-zero is an empty `cpu_set_t`.
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &core in cores {
+        // SAFETY: `CPU_SET` ignores cores past the end of the set.
+        unsafe { libc::CPU_SET(core, &mut set) };
+    }
+    // SAFETY: `set` outlives the call; pid 0 is the calling thread.
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn pin(_cores: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "thread affinity needs Windows or Linux",
+    ))
+}
@@ -0,0 +1,89 @@
+//! Scream, the network sound card protocol: the stream as Scream receivers on Linux,
+//! Raspberry Pi and Windows play it, sent to its multicast group or to one receiver.
+
+use crate::error::ProtocolError;
+use crate::protocol::pcm_audio;
+use crate::transport::Repacketizer;
+
+pub const HEADER_SIZE: usize = 5;
+/// Every packet carries exactly this much audio, as the Scream driver sends it; frames
+/// run on from one packet into the next.
+pub const PAYLOAD: usize = 1152;
+
+/// Sample rate, sample size, channels and channel mask, as the header gives them.
+fn header_for(sample_rate: u32, channels: u8) -> Result<[u8; HEADER_SIZE], ProtocolError> {
+    // A multiple of 44.1 kHz, flagged by the top bit, or of 48 kHz.
+    let (base, multiple) = if sample_rate.is_multiple_of(44_100) {
+        (0x80, sample_rate / 44_100)
+    } else if sample_rate.is_multiple_of(48_000) {
+        (0, sample_rate / 48_000)
+    } else {
+        (0, 0)
+    };
+    if !(1..=0x7f).contains(&multiple) {
+        return Err(ProtocolError::UnsupportedSampleRate(sample_rate));
+    }
+    // The speaker layouts Windows uses for these counts, otherwise the first speakers.
+    let mask: u16 = match channels {
+        1 => 0x0004,
+        2 => 0x0003,
+        4 => 0x0033,
+        6 => 0x003f,
+        8 => 0x063f,
+        n => (1u32 << n.min(16)).wrapping_sub(1) as u16,
+    };
+    let [mask_lo, mask_hi] = mask.to_le_bytes();
+    Ok([base | multiple as u8, 16, channels, mask_lo, mask_hi])
+}
+
+/// Turns the audio packets of a stream into Scream packets, holding back what does not
+/// fill one until the next.
+#[derive(Debug, Default)]
+pub struct Packetizer {
+    header: Option<[u8; HEADER_SIZE]>,
+    /// Sample rate and channels `header` was made for.
+    format: (u32, u8),
+    pending: Vec<u8>,
+    packets: Vec<Vec<u8>>,
+}
+
+impl Packetizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Repacketizer for Packetizer {
+    fn packet_name(&self) -> &'static str {
+        "Scream packet"
+    }
+
+    fn packetize(&mut self, packet: &[u8]) -> Result<&[Vec<u8>], ProtocolError> {
+        let Some(audio) = pcm_audio(packet)? else {
+            return Ok(&[]);
+        };
+        let format = (audio.sample_rate, audio.channels);
+        let header = match self.header {
+            Some(header) if self.format == format => header,
+            // Audio held back in the old format would play in the new one.
+            _ => {
+                let header = header_for(audio.sample_rate, audio.channels)?;
+                self.pending.clear();
+                self.format = format;
+                *self.header.insert(header)
+            }
+        };
+        self.pending.extend_from_slice(audio.samples);
+        let count = self.pending.len() / PAYLOAD;
+        if self.packets.len() < count {
+            self.packets.resize_with(count, Vec::new);
+        }
+        for (chunk, out) in self.pending.chunks_exact(PAYLOAD).zip(&mut self.packets) {
+            out.clear();
+            out.extend_from_slice(&header);
+            out.extend_from_slice(chunk);
+        }
+        self.pending.drain(..count * PAYLOAD);
+        Ok(&self.packets[..count])
+    }
+}
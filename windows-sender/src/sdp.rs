@@ -0,0 +1,39 @@
+use std::fs;
+use std::path::PathBuf;
+
+use tracing::{info, warn};
+use windows_sender::sender::AudioSender;
+
+use crate::Args;
+
+/// `--write-sdp`: the SDP of the stream in a file VLC, ffplay or GStreamer can open,
+/// written once the stream is known and again whenever its description changes.
+pub struct SdpFile {
+    path: PathBuf,
+    written: Option<String>,
+}
+
+impl SdpFile {
+    /// `None` unless `--write-sdp` is set.
+    pub fn new(args: &Args) -> Option<Self> {
+        Some(Self {
+            path: args.write_sdp.clone()?,
+            written: None,
+        })
+    }
+
+    /// Called a few times a second; writes the file when the description changed.
+    pub fn check(&mut self, sender: &AudioSender) {
+        let Some(sdp) = sender.control().sdp() else {
+            return;
+        };
+        if self.written.as_ref() == Some(&sdp) {
+            return;
+        }
+        match fs::write(&self.path, &sdp) {
+            Ok(()) => info!("SDP: wrote {}", self.path.display()),
+            Err(err) => warn!("failed to write the SDP to {}: {err}", self.path.display()),
+        }
+        self.written = Some(sdp);
+    }
+}
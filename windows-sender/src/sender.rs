@@ -0,0 +1,1352 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc as std_mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender, TrySendError};
+use tracing::{info, trace_span, warn};
+
+use crate::aes67::{Encoding, SessionName};
+use crate::archive::OpusArchive;
+use crate::bandwidth::{capped_format, pcm16_kbps, Reducer, TokenBucket};
+use crate::bluetooth::{self, Relay};
+use crate::capture::{CaptureChunk, QueueOverflow, SourceKind};
+use crate::clock::DeviceDrift;
+use crate::crypto::{Cipher, Key, Sealing};
+use crate::dsp::{DspChain, DspSettings, SharedDspSettings};
+use crate::encode::{EncodePool, MAX_ENCODE_WORKERS};
+use crate::error::{CaptureError, CodecError, Error};
+use crate::feedback::FeedbackTracker;
+use crate::iec61937::Detector;
+use crate::journal::{Event, EventJournal, EventKind};
+use crate::ndi::NdiSource;
+use crate::pairing::{NoiseConfig, SenderPairing};
+use crate::plugin::{Plugin, PluginKind};
+use crate::protocol::{
+    build_marker, build_pause, build_start, encode_pcm16, mark_discontinuity, now_us,
+    stamp_ptp_time, to_clock_us, PacketTemplate, SyncMarker, CODEC_IEC61937, CODEC_OPUS,
+    CODEC_PCM16, MAX_MARKER_NAME,
+};
+use crate::ptp::{PtpClock, PtpSource};
+use crate::resolve::Endpoint;
+use crate::sched::Scheduling;
+use crate::stats::{SenderStats, SenderStatsSnapshot, Stage};
+use crate::supervisor::{SourceSwitch, Started, Supervisor};
+use crate::tee::{Tee, TeePoint};
+use crate::timer::{Resolution, Sleeper};
+use crate::transport::{SendOptions, TargetSet, Transport, TransportKind};
+use crate::vban::StreamName;
+
+#[derive(Clone, Debug)]
+pub struct SenderConfig {
+    /// Targets given by name are resolved at start and again whenever the sender reconnects.
+    pub targets: Vec<Endpoint>,
+    pub source: SourceKind,
+    pub desktop_device: Option<String>,
+    /// The WAV file `SourceKind::File` plays, here or after a switch to it.
+    pub source_file: Option<PathBuf>,
+    pub transport: TransportKind,
+    /// The stream name in `TransportKind::Vban` packets; `None` is VBAN's default, Stream1.
+    pub vban_stream: Option<String>,
+    /// The session name AES67 devices list a `TransportKind::Aes67` stream by; `None` is
+    /// windows-sender.
+    pub aes67_session: Option<String>,
+    /// The sample encoding of a `TransportKind::Aes67` stream; `None` is L24.
+    pub aes67_encoding: Option<Encoding>,
+    /// Address or name of the paired device a `TransportKind::Bluetooth` stream goes to,
+    /// which then takes no `targets`.
+    pub bt_device: Option<String>,
+    /// Its RFCOMM channel; `None` looks it up by `bluetooth::SERVICE_UUID`.
+    pub bt_channel: Option<u8>,
+    /// May be fractional, such as 2.5, as long as it is a whole number of samples.
+    pub frame_ms: f32,
+    pub dsp: DspSettings,
+    /// Restart a stage that stops making progress for this long; `None` disables it.
+    pub watchdog: Option<Duration>,
+    /// Effect plugins run in order after the built-in DSP; at most one codec plugin.
+    pub plugins: Vec<Plugin>,
+    /// Shared with the caller so the history survives restarts of the sender.
+    pub journal: Arc<EventJournal>,
+    /// Encrypts every payload with this pre-shared key.
+    pub key: Option<Key>,
+    pub cipher: Cipher,
+    /// Moves to a key derived from the session key every this long; see `crypto`.
+    pub key_rotation: Option<Duration>,
+    /// Pairs with every target instead, sealing the stream with a fresh key per start.
+    pub noise: Option<NoiseConfig>,
+    /// Ignores reports from receivers that do not present this token, and refuses TCP
+    /// targets that do not present it before the stream starts.
+    pub access_token: Option<String>,
+    /// Lets receivers set the gain and mute with `VOLUME_MAGIC` requests over the report
+    /// channel, so a phone turned down attenuates before encoding.
+    pub remote_volume: bool,
+    /// Stamps packets with the PTP domain clock read from here instead of the system clock.
+    pub ptp: Option<PtpSource>,
+    /// Sends packets on the cadence of the audio they carry instead of as capture periods
+    /// arrive, at the cost of up to one capture period of latency.
+    pub pace: bool,
+    /// Tells the receivers when the stream is paused, so they fade out instead of running
+    /// dry. Receivers that predate the notice count it as a parse error.
+    pub pause_notice: bool,
+    /// Asks every receiver to play each frame this long after it was sent, on the sender
+    /// clock, so speakers in different rooms stay in step.
+    pub playout_delay: Option<Duration>,
+    /// Holds the stream back until this time, announcing it to the receivers meanwhile.
+    pub start_at: Option<SystemTime>,
+    pub late_policy: LatePolicy,
+    /// Most audio held on the way to the sockets (capture queue, accumulator and TCP
+    /// backlog); the oldest is dropped past it, flagging the gap. It also sizes the capture
+    /// queue, `DEFAULT_QUEUE_DEPTH` without it, and with TCP bounds the backlog unless
+    /// `tcp_backlog` is set.
+    pub max_buffered: Option<Duration>,
+    /// Caps the stream to each target, headers included: PCM16 goes out mono or at a lower
+    /// sample rate when the capture format is over it, and frames past it are dropped,
+    /// flagging the gap.
+    pub max_kbps: Option<u32>,
+    /// Writes the audio being sent to a file as well; see `tee`.
+    pub tee: Option<Arc<Tee>>,
+    /// Publishes the audio being sent as an NDI source as well; see `ndi`.
+    pub ndi: Option<Arc<NdiSource>>,
+    /// Archives the packets of the Opus codec plugin to an Ogg Opus file as well, or
+    /// instead of sending with no `targets`; see `archive`.
+    pub output: Option<Arc<OpusArchive>>,
+    /// Sends IEC 61937 bitstreams (AC-3, DTS, E-AC-3) found in stereo capture untouched,
+    /// as `CODEC_IEC61937` packets, while they last; see `iec61937`.
+    pub passthrough: bool,
+    /// Threads encoding frames with the codec plugin, each with its own instance, or 0
+    /// to encode on the send thread.
+    pub encode_workers: usize,
+    /// What the capture does with new audio while the queue to the send loop is full.
+    pub queue_overflow: QueueOverflow,
+    /// Sends UDP through io_uring, one system call per batch; needs Linux and the
+    /// `io-uring` feature.
+    pub io_uring: bool,
+    /// Hands each batch of UDP packets to the kernel to split (`UDP_SEGMENT`); Linux only.
+    pub gso: bool,
+    /// `SO_SNDBUF` of the sockets to the targets, in bytes; `None` keeps the OS default.
+    pub send_buffer: Option<u32>,
+    /// Writes TCP without blocking and drops the oldest packets to a target that falls this
+    /// far behind, flagging the gap, instead of holding up the send loop.
+    pub tcp_backlog: Option<Duration>,
+    /// Priority and cores of the capture and send threads.
+    pub scheduling: Scheduling,
+}
+
+/// What the sender does with audio that waited too long to go out, e.g. after a stall.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LatePolicy {
+    /// Sends all of it, however late, leaving the receiver to catch up.
+    #[default]
+    Never,
+    /// Drops the oldest audio until the rest is younger than this, and flags the next
+    /// packet with `FLAG_DISCONTINUITY`.
+    DropOldest(Duration),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StreamFormat {
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub samples_per_channel: u16,
+}
+
+impl StreamFormat {
+    /// The format of `frame_ms` frames, refused unless they hold a whole number of samples
+    /// at `sample_rate`.
+    pub fn new(sample_rate: u32, channels: u8, frame_ms: f32) -> Result<Self, CodecError> {
+        let samples = sample_rate as f64 * frame_ms as f64 / 1000.0;
+        if (samples - samples.round()).abs() > 1e-3 || samples < 1.0 {
+            return Err(CodecError::FractionalFrame {
+                frame_ms,
+                sample_rate,
+            });
+        }
+        Ok(Self {
+            sample_rate,
+            channels,
+            samples_per_channel: samples.round() as u16,
+        })
+    }
+
+    pub fn samples_per_packet(&self) -> usize {
+        self.samples_per_channel as usize * self.channels as usize
+    }
+}
+
+/// Cloneable handle for adjusting a running sender from other threads.
+#[derive(Clone)]
+pub struct SenderControl {
+    running: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    stats: Arc<SenderStats>,
+    dsp: Arc<SharedDspSettings>,
+    targets: TargetSet,
+    journal: Arc<EventJournal>,
+    markers: Sender<String>,
+    switches: Sender<SourceSwitch>,
+}
+
+impl SenderControl {
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// While paused, captured audio is discarded instead of being sent.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn targets(&self) -> Vec<SocketAddr> {
+        self.targets.addrs()
+    }
+
+    /// The targets as given, for restarting with the same set; names stay names.
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        self.targets.endpoints()
+    }
+
+    pub fn add_target(&self, addr: SocketAddr) -> Result<bool, Error> {
+        let added = self.targets.add(addr)?;
+        if added {
+            self.journal
+                .record(EventKind::Targets, format!("added target {addr}"));
+        }
+        Ok(added)
+    }
+
+    pub fn remove_target(&self, addr: SocketAddr) -> Result<bool, Error> {
+        if self.targets.len() == 1 && self.targets.contains(addr) {
+            return Err(Error::Config("cannot remove the last target".to_string()));
+        }
+        let removed = self.targets.remove(addr);
+        if removed {
+            self.journal
+                .record(EventKind::Targets, format!("removed target {addr}"));
+        }
+        Ok(removed)
+    }
+
+    pub fn dsp_settings(&self) -> DspSettings {
+        self.dsp.get()
+    }
+
+    pub fn update_dsp<F>(&self, apply: F)
+    where
+        F: FnOnce(&mut DspSettings),
+    {
+        self.dsp.update(apply);
+    }
+
+    pub fn snapshot(&self) -> SenderStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.journal.events()
+    }
+
+    /// The SDP of an AES67 stream, for the first target, once the first packet set its
+    /// channels; see `Transport::sdp`.
+    pub fn sdp(&self) -> Option<String> {
+        self.targets.sdp()
+    }
+
+    /// Sends a sync marker at the position of the audio being captured now.
+    pub fn mark(&self, name: &str) -> Result<(), Error> {
+        if name.is_empty() || name.len() > MAX_MARKER_NAME {
+            return Err(Error::Config(format!(
+                "marker names must be 1 to {MAX_MARKER_NAME} bytes long"
+            )));
+        }
+        match self.markers.try_send(name.to_string()) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(Error::Config(
+                "too many markers waiting to be sent".to_string(),
+            )),
+            Err(TrySendError::Disconnected(_)) => Err(CaptureError::ChannelClosed.into()),
+        }
+    }
+
+    /// Moves the stream to `source` without restarting it: the receivers keep the same
+    /// sockets and sequence numbers, and hear the old source crossfade into the new one.
+    /// Returns the name of the new source; on failure the old one carries on.
+    pub fn switch_source(&self, source: SourceKind) -> Result<String, Error> {
+        let (reply, result) = std_mpsc::sync_channel(1);
+        self.switches
+            .send(SourceSwitch { source, reply })
+            .map_err(|_| CaptureError::ChannelClosed)?;
+        result.recv().map_err(|_| CaptureError::ChannelClosed)?
+    }
+
+    pub fn request_stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+pub struct AudioSender {
+    running: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    stats: Arc<SenderStats>,
+    dsp: Arc<SharedDspSettings>,
+    targets: TargetSet,
+    journal: Arc<EventJournal>,
+    markers: Sender<String>,
+    switches: Sender<SourceSwitch>,
+    rx: Receiver<CaptureChunk>,
+    active: Arc<Mutex<Started>>,
+    supervisor: Option<thread::JoinHandle<Result<(), Error>>>,
+    /// Outlives the transports to it, which are dropped with `targets`.
+    _relay: Option<Relay>,
+}
+
+impl AudioSender {
+    pub fn start(config: SenderConfig) -> Result<Self, Error> {
+        if !(1.0..=20.0).contains(&config.frame_ms) {
+            return Err(Error::Config(
+                "--frame-ms must be in range [1, 20]".to_string(),
+            ));
+        }
+        if (config.bt_device.is_some() || config.bt_channel.is_some())
+            && config.transport != TransportKind::Bluetooth
+        {
+            return Err(Error::Config(
+                "--bt-device and --bt-channel only apply to the Bluetooth transport".to_string(),
+            ));
+        }
+        if config.transport == TransportKind::Bluetooth {
+            if config.bt_device.is_none() {
+                return Err(Error::Config(
+                    "--transport bluetooth needs --bt-device".to_string(),
+                ));
+            }
+            if !config.targets.is_empty() {
+                return Err(Error::Config(
+                    "--transport bluetooth streams to --bt-device; it takes no other targets"
+                        .to_string(),
+                ));
+            }
+        } else if config.targets.is_empty() && config.output.is_none() {
+            return Err(Error::Config("at least one target is required".to_string()));
+        }
+        if config
+            .playout_delay
+            .is_some_and(|delay| !(1..=10_000).contains(&delay.as_millis()))
+        {
+            return Err(Error::Config(
+                "--playout-delay-ms must be in range [1, 10000]".to_string(),
+            ));
+        }
+        if let LatePolicy::DropOldest(max_age) = config.late_policy {
+            if !(10..=10_000).contains(&max_age.as_millis()) {
+                return Err(Error::Config(
+                    "--max-audio-age-ms must be in range [10, 10000]".to_string(),
+                ));
+            }
+        }
+        if config.io_uring && config.transport != TransportKind::Udp {
+            return Err(Error::Config(
+                "--io-uring only applies to the UDP transport".to_string(),
+            ));
+        }
+        if config
+            .max_buffered
+            .is_some_and(|max| !(10..=10_000).contains(&max.as_millis()))
+        {
+            return Err(Error::Config(
+                "--max-buffered-ms must be in range [10, 10000]".to_string(),
+            ));
+        }
+        if config
+            .max_kbps
+            .is_some_and(|max| !(16..=100_000).contains(&max))
+        {
+            return Err(Error::Config(
+                "--max-kbps must be in range [16, 100000]".to_string(),
+            ));
+        }
+        if let Some(backlog) = config.tcp_backlog {
+            if !matches!(
+                config.transport,
+                TransportKind::Tcp | TransportKind::Bluetooth
+            ) {
+                return Err(Error::Config(
+                    "--tcp-backlog-ms only applies to the TCP and Bluetooth transports".to_string(),
+                ));
+            }
+            if !(10..=10_000).contains(&backlog.as_millis()) {
+                return Err(Error::Config(
+                    "--tcp-backlog-ms must be in range [10, 10000]".to_string(),
+                ));
+            }
+        }
+        if config.gso && config.transport != TransportKind::Udp {
+            return Err(Error::Config(
+                "--gso only applies to the UDP transport".to_string(),
+            ));
+        }
+        if config.vban_stream.is_some() && config.transport != TransportKind::Vban {
+            return Err(Error::Config(
+                "--vban-stream only applies to the VBAN transport".to_string(),
+            ));
+        }
+        let vban_stream = config
+            .vban_stream
+            .as_deref()
+            .map(StreamName::new)
+            .transpose()?
+            .unwrap_or_default();
+        if (config.aes67_session.is_some() || config.aes67_encoding.is_some())
+            && config.transport != TransportKind::Aes67
+        {
+            return Err(Error::Config(
+                "--aes67-session and --aes67-encoding only apply to the AES67 transport"
+                    .to_string(),
+            ));
+        }
+        if config.max_kbps.is_some() && config.transport == TransportKind::Aes67 {
+            return Err(Error::Config(
+                "AES67 streams are 48 kHz at the capture's channels; --max-kbps cannot cap them"
+                    .to_string(),
+            ));
+        }
+        let aes67_session = config
+            .aes67_session
+            .as_deref()
+            .map(SessionName::new)
+            .transpose()?
+            .unwrap_or_default();
+        let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+        if let Some(core) = config
+            .scheduling
+            .cores
+            .iter()
+            .find(|&&core| core >= cores.min(usize::BITS as usize))
+        {
+            return Err(Error::Config(format!(
+                "--affinity core {core} does not exist; this machine has {cores}"
+            )));
+        }
+        if config.gso && config.io_uring {
+            return Err(Error::Config(
+                "--gso and --io-uring cannot be combined".to_string(),
+            ));
+        }
+        let codecs = config
+            .plugins
+            .iter()
+            .filter(|plugin| plugin.kind() == PluginKind::Codec)
+            .count();
+        if codecs > 1 {
+            return Err(Error::Config(
+                "at most one codec plugin can be loaded".to_string(),
+            ));
+        }
+        if config.output.is_some()
+            && !config
+                .plugins
+                .iter()
+                .any(|plugin| plugin.kind() == PluginKind::Codec && plugin.codec_id() == CODEC_OPUS)
+        {
+            return Err(Error::Config(format!(
+                "--output archives the Opus stream; it needs an Opus codec plugin (codec id {CODEC_OPUS})"
+            )));
+        }
+        if !config.transport.is_native() {
+            let transport = config.transport.as_str();
+            if codecs > 0 {
+                return Err(Error::Config(format!(
+                    "--transport {transport} carries PCM16; it cannot be combined with a codec plugin"
+                )));
+            }
+            if config.key.is_some() || config.noise.is_some() {
+                return Err(Error::Config(format!(
+                    "{transport} receivers cannot decrypt; --transport {transport} cannot be combined with a key or --noise"
+                )));
+            }
+        }
+        if config.passthrough {
+            if !config.transport.is_native() {
+                return Err(Error::Config(format!(
+                    "--transport {} carries PCM16; it cannot pass a bitstream through",
+                    config.transport.as_str()
+                )));
+            }
+            if config.max_kbps.is_some() {
+                return Err(Error::Config(
+                    "--passthrough cannot be combined with --max-kbps; a bitstream cannot be resampled or dropped".to_string(),
+                ));
+            }
+            if config.encode_workers > 0 {
+                return Err(Error::Config(
+                    "--passthrough cannot be combined with --encode-workers".to_string(),
+                ));
+            }
+        }
+        if config.encode_workers > 0 && codecs == 0 {
+            return Err(Error::Config(
+                "--encode-workers needs a codec plugin".to_string(),
+            ));
+        }
+        if codecs > 0
+            && config
+                .tee
+                .as_ref()
+                .is_some_and(|tee| tee.point() == TeePoint::PostCodec)
+        {
+            return Err(Error::Config(
+                "--tee-point post-codec needs PCM on the wire; a codec plugin's packets cannot be decoded here, so tee them pre-codec".to_string(),
+            ));
+        }
+        if config.encode_workers > MAX_ENCODE_WORKERS {
+            return Err(Error::Config(format!(
+                "--encode-workers must be at most {MAX_ENCODE_WORKERS}"
+            )));
+        }
+
+        let (key, pairing) = match config.noise {
+            Some(_) if config.key.is_some() => {
+                return Err(Error::Config(
+                    "a pre-shared key and Noise pairing cannot be combined".to_string(),
+                ))
+            }
+            Some(noise) => {
+                let key = Key::generate()?;
+                let pairing = SenderPairing::new(noise, key.clone())?;
+                (Some(key), Some(Arc::new(pairing)))
+            }
+            None => (config.key, None),
+        };
+        let sealing = key.map(|key| Sealing {
+            cipher: config.cipher,
+            key,
+            rotation: config.key_rotation,
+        });
+
+        let (relay, targets) = match &config.bt_device {
+            Some(device) => {
+                let relay = Relay::start(bluetooth::find(device)?, config.bt_channel)?;
+                let targets = vec![Endpoint::Addr(relay.local_addr())];
+                (Some(relay), targets)
+            }
+            None => (None, config.targets),
+        };
+
+        let (tx, rx) = unbounded::<CaptureChunk>();
+        let (marker_tx, marker_rx) = bounded(64);
+        let (switch_tx, switch_rx) = unbounded();
+        let stats = Arc::new(SenderStats::default());
+        let running = Arc::new(AtomicBool::new(true));
+        let dsp = Arc::new(SharedDspSettings::new(config.dsp));
+        let mut feedback = FeedbackTracker::new(
+            Arc::clone(&stats),
+            config.access_token.clone(),
+            config.playout_delay,
+        );
+        if config.remote_volume {
+            feedback = feedback.with_remote_volume(Arc::clone(&dsp));
+        }
+        let feedback = Arc::new(feedback);
+        let targets = TargetSet::connect(
+            config.transport,
+            &targets,
+            Arc::clone(&feedback),
+            pairing,
+            sealing.clone(),
+            SendOptions {
+                io_uring: config.io_uring,
+                gso: config.gso,
+                send_buffer: config.send_buffer,
+                tcp_backlog: config
+                    .tcp_backlog
+                    .or(config.max_buffered)
+                    .filter(|_| config.transport.is_stream())
+                    .map(|backlog| {
+                        (backlog.as_secs_f32() * 1000.0 / config.frame_ms).ceil() as usize
+                    }),
+                vban_stream,
+                aes67_session,
+                aes67_encoding: config.aes67_encoding.unwrap_or_default(),
+            },
+        )?
+        .with_journal(Arc::clone(&config.journal));
+        let paused = Arc::new(AtomicBool::new(false));
+
+        let (ready_tx, ready_rx) = std_mpsc::sync_channel(1);
+        let supervisor = Supervisor {
+            source: config.source,
+            desktop_device: config.desktop_device,
+            source_file: config.source_file,
+            frame_ms: config.frame_ms,
+            watchdog: config.watchdog,
+            plugins: config.plugins,
+            tx,
+            rx: rx.clone(),
+            stats: Arc::clone(&stats),
+            feedback,
+            journal: Arc::clone(&config.journal),
+            targets: targets.clone(),
+            dsp: Arc::clone(&dsp),
+            running: Arc::clone(&running),
+            paused: Arc::clone(&paused),
+            seal_overhead: sealing.as_ref().map_or(0, Sealing::overhead),
+            ptp: config.ptp,
+            pace: config.pace,
+            pause_notice: config.pause_notice,
+            markers: marker_rx,
+            switches: switch_rx,
+            start_at: config.start_at,
+            late_policy: config.late_policy,
+            max_buffered: config.max_buffered,
+            max_kbps: config.max_kbps,
+            tee: config.tee,
+            ndi: config.ndi,
+            output: config.output,
+            passthrough: config.passthrough,
+            encode_workers: config.encode_workers,
+            queue_overflow: config.queue_overflow,
+            scheduling: config.scheduling,
+        }
+        .spawn(ready_tx)?;
+        let active = ready_rx
+            .recv()
+            .map_err(|_| Error::from(CaptureError::ChannelClosed))??;
+
+        Ok(Self {
+            running,
+            paused,
+            stats,
+            dsp,
+            targets,
+            journal: config.journal,
+            markers: marker_tx,
+            switches: switch_tx,
+            rx,
+            active,
+            supervisor: Some(supervisor),
+            _relay: relay,
+        })
+    }
+
+    /// The format of the source streaming now, which a switch of source may change.
+    pub fn format(&self) -> StreamFormat {
+        self.active.lock().unwrap().format
+    }
+
+    pub fn source(&self) -> SourceKind {
+        self.active.lock().unwrap().source
+    }
+
+    pub fn source_name(&self) -> String {
+        self.active.lock().unwrap().source_name.clone()
+    }
+
+    pub fn stats(&self) -> &Arc<SenderStats> {
+        &self.stats
+    }
+
+    pub fn snapshot(&self) -> SenderStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    pub fn control(&self) -> SenderControl {
+        SenderControl {
+            running: Arc::clone(&self.running),
+            paused: Arc::clone(&self.paused),
+            stats: Arc::clone(&self.stats),
+            dsp: Arc::clone(&self.dsp),
+            targets: self.targets.clone(),
+            journal: Arc::clone(&self.journal),
+            markers: self.markers.clone(),
+            switches: self.switches.clone(),
+        }
+    }
+
+    pub fn backlog(&self) -> usize {
+        self.rx.len()
+    }
+
+    pub fn backlog_gauge(&self) -> impl Fn() -> usize + Send + 'static {
+        let rx = self.rx.clone();
+        move || rx.len()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.supervisor
+            .as_ref()
+            .map(|handle| !handle.is_finished())
+            .unwrap_or(false)
+    }
+
+    pub fn wait(mut self) -> Result<(), Error> {
+        match self.supervisor.take() {
+            Some(handle) => handle
+                .join()
+                .unwrap_or_else(|payload| Err(Error::panicked("supervisor", payload))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn stop(mut self) -> Result<(), Error> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        self.running.store(false, Ordering::Relaxed);
+        match self.supervisor.take() {
+            Some(handle) => handle
+                .join()
+                .unwrap_or_else(|payload| Err(Error::panicked("supervisor", payload))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for AudioSender {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+/// How often receivers hear about a deferred start, or a pause, while it lasts.
+const ANNOUNCE_INTERVAL: Duration = Duration::from_millis(250);
+/// How long a new source takes to fade in after a switch.
+const CROSSFADE: Duration = Duration::from_millis(20);
+/// The sync marker sent where a switch of source changed the stream format.
+pub const FORMAT_CHANGE_MARKER: &str = "format-change";
+
+/// Where a send loop left the stream, for the next one to carry it on from after a switch
+/// of source.
+#[derive(Clone, Debug)]
+pub struct StreamPosition {
+    pub seq: u32,
+    pub media_time: u32,
+    pub format: StreamFormat,
+    /// Processed audio that was not sent yet, which the next source crossfades from.
+    pub tail: Vec<i16>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn send_loop<T>(
+    rx: Receiver<CaptureChunk>,
+    mut format: StreamFormat,
+    stats: Arc<SenderStats>,
+    feedback: &FeedbackTracker,
+    transport: &mut T,
+    dsp_settings: &SharedDspSettings,
+    plugins: &[Plugin],
+    running: &AtomicBool,
+    paused: &AtomicBool,
+    overhead: usize,
+    ptp: Option<&PtpClock>,
+    pace: bool,
+    pause_notice: bool,
+    markers: &Receiver<String>,
+    start_at: Option<SystemTime>,
+    late_policy: LatePolicy,
+    max_buffered: Option<Duration>,
+    max_kbps: Option<u32>,
+    tee: Option<&Tee>,
+    ndi: Option<&NdiSource>,
+    output: Option<&OpusArchive>,
+    passthrough: bool,
+    encode_workers: usize,
+    resume: Option<StreamPosition>,
+) -> Result<StreamPosition, Error>
+where
+    T: Transport + ?Sized,
+{
+    let mut reducer = None;
+    let has_codec = plugins
+        .iter()
+        .any(|plugin| plugin.kind() == PluginKind::Codec);
+    if let Some(max_kbps) = max_kbps.filter(|_| !has_codec) {
+        let capped = capped_format(format, overhead, max_kbps);
+        let kbps = pcm16_kbps(capped, overhead);
+        if capped != format {
+            info!(
+                "--max-kbps {max_kbps}: sending {} Hz {} ch at {kbps:.0} kbps instead of {} Hz {} ch at {:.0} kbps",
+                capped.sample_rate,
+                capped.channels,
+                format.sample_rate,
+                format.channels,
+                pcm16_kbps(format, overhead)
+            );
+            reducer = Some(Reducer::new(format, capped));
+            format = capped;
+        }
+        if kbps > max_kbps as f64 {
+            warn!("--max-kbps {max_kbps}: even {kbps:.0} kbps is over the cap; frames past it are dropped");
+        }
+    }
+    let mut bucket = max_kbps.map(TokenBucket::new);
+    let samples_per_packet = format.samples_per_packet();
+    if samples_per_packet * 2 + overhead > u16::MAX as usize {
+        return Err(CodecError::FrameTooLarge {
+            samples: samples_per_packet,
+        }
+        .into());
+    }
+
+    let mut dsp = DspChain::new(
+        dsp_settings.get(),
+        format.sample_rate,
+        format.channels as usize,
+    );
+    let mut effects = Vec::new();
+    let mut codec = None;
+    let mut encode_pool = None;
+    let mut codec_id = CODEC_PCM16;
+    for plugin in plugins {
+        let (sample_rate, channels) = (format.sample_rate, format.channels as u32);
+        match plugin.kind() {
+            PluginKind::Effect => effects.push(plugin.instantiate(sample_rate, channels)?),
+            PluginKind::Codec => {
+                codec_id = plugin.codec_id();
+                match encode_workers {
+                    0 => codec = Some(plugin.instantiate(sample_rate, channels)?),
+                    workers => {
+                        encode_pool = Some(EncodePool::new(plugin, sample_rate, channels, workers)?)
+                    }
+                }
+            }
+        }
+    }
+    let template = PacketTemplate::new(
+        codec_id,
+        format.sample_rate,
+        format.channels,
+        format.samples_per_channel,
+    );
+    let bitstream_template = PacketTemplate::new(
+        CODEC_IEC61937,
+        format.sample_rate,
+        format.channels,
+        format.samples_per_channel,
+    );
+    if passthrough && format.channels != 2 {
+        warn!(
+            "--passthrough: the capture is {} ch; bitstreams only come in stereo",
+            format.channels
+        );
+    }
+    let mut detector =
+        (passthrough && format.channels == 2).then(|| Detector::new(format.sample_rate));
+    // What the capture carries while it is a bitstream rather than PCM.
+    let mut bitstream = None;
+    // Only codecs need the frame and the payload apart; PCM goes straight into the packet.
+    let mut frame = Vec::<i16>::new();
+    let mut payload = Vec::<u8>::new();
+    let mut batch = Vec::<Vec<u8>>::new();
+    let mut batch_capture = Vec::<Option<Instant>>::new();
+    // Packet buffers of sent batches, reused for the next ones.
+    let mut spare = Vec::<Vec<u8>>::new();
+    let mut seq: u32 = 0;
+    let mut media_time: u32 = 0;
+    let channels = format.channels as usize;
+    let mut acc = VecDeque::<i16>::with_capacity(samples_per_packet * 4);
+    let mut acc_capture = VecDeque::<(usize, Instant)>::with_capacity(64);
+    let frame_us = format.samples_per_channel as u64 * 1_000_000 / format.sample_rate.max(1) as u64;
+    let mut last_send: Option<Instant> = None;
+    let mut device_drift = DeviceDrift::new();
+    let start_us = start_at.map(to_clock_us).transpose()?;
+    let mut last_announce: Option<Instant> = None;
+    let mut last_pause_notice: Option<Instant> = None;
+    let mut was_paused = false;
+    let mut discontinuity = false;
+    let max_buffered_samples = max_buffered.map(|max| {
+        (max.as_micros() as u64 * format.sample_rate as u64 / 1_000_000) as usize * channels
+    });
+    let mut pacer = pace.then(|| Pacer::new(format.sample_rate, Duration::from_micros(frame_us)));
+    let mut crossfade = None;
+    if let Some(resume) = resume {
+        seq = resume.seq;
+        media_time = resume.media_time;
+        let same_format = resume.format.sample_rate == format.sample_rate
+            && resume.format.channels == format.channels;
+        let tail = if same_format { resume.tail } else { Vec::new() };
+        crossfade = Some(Crossfade::new(tail, format));
+        if !same_format {
+            // Receivers reset on the new format by themselves; the marker tells apps where.
+            let marker = SyncMarker {
+                name: FORMAT_CHANGE_MARKER.to_string(),
+                media_time,
+                send_time_us: now_us()?,
+            };
+            transport.send_packet(&build_marker(&marker), seq)?;
+            discontinuity = true;
+        }
+    }
+    // Capture waits and pacing sleeps would otherwise snap to the 15.6 ms Windows tick.
+    let _resolution = Resolution::fine();
+    let sleeper = Sleeper::new();
+
+    while running.load(Ordering::Relaxed) {
+        let mut chunk = match rx.recv_timeout(Duration::from_millis(200)) {
+            Ok(chunk) => chunk,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Err(CaptureError::ChannelClosed.into()),
+        };
+        stats
+            .queued_samples
+            .fetch_sub(chunk.samples.len() as u64, Ordering::Relaxed);
+        if let Some(reducer) = reducer.as_mut() {
+            reducer.process(&mut chunk.samples);
+        }
+        let chunk_queue_us = chunk.captured_at.elapsed().as_micros() as u64;
+        stats
+            .capture_queue_us_sum
+            .fetch_add(chunk_queue_us, Ordering::Relaxed);
+        stats.capture_queue_count.fetch_add(1, Ordering::Relaxed);
+        device_drift.record(
+            chunk.captured_at,
+            chunk.samples.len() / channels,
+            format.sample_rate,
+        );
+        if let Some(ppm) = device_drift.ppm() {
+            stats
+                .device_drift_ppb
+                .store((ppm * 1000.0) as i64, Ordering::Relaxed);
+        }
+
+        // Markers land at the end of the newest audio, which was captured about when they
+        // were asked for.
+        let captured = (acc.len() + chunk.samples.len()) / channels;
+        for name in markers.try_iter() {
+            let marker = SyncMarker {
+                name,
+                media_time: media_time.wrapping_add(captured as u32),
+                send_time_us: now_us()?,
+            };
+            transport.send_packet(&build_marker(&marker), seq)?;
+        }
+
+        let waiting = match start_us {
+            Some(start_us) if now_us()? < start_us => {
+                if last_announce.is_none_or(|at| at.elapsed() >= ANNOUNCE_INTERVAL) {
+                    transport.send_packet(&build_start(start_us), seq)?;
+                    last_announce = Some(Instant::now());
+                }
+                true
+            }
+            _ => false,
+        };
+        if waiting || paused.load(Ordering::Relaxed) {
+            if !waiting {
+                was_paused = true;
+                if pause_notice
+                    && last_pause_notice.is_none_or(|at| at.elapsed() >= ANNOUNCE_INTERVAL)
+                {
+                    transport.send_packet(&build_pause(media_time), seq)?;
+                    last_pause_notice = Some(Instant::now());
+                }
+            }
+            // The media clock runs on over discarded audio, as RTP does over silence.
+            let discarded = (acc.len() + chunk.samples.len()) / channels;
+            media_time = media_time.wrapping_add(discarded as u32);
+            acc.clear();
+            acc_capture.clear();
+            last_send = None;
+            if let Some(pacer) = pacer.as_mut() {
+                pacer.reset();
+            }
+            continue;
+        }
+        if std::mem::take(&mut was_paused) {
+            // The resumed audio fades in after the gap rather than starting with a click.
+            last_pause_notice = None;
+            discontinuity = true;
+            crossfade = Some(Crossfade::new(Vec::new(), format));
+        }
+        if let Some(found) = detector
+            .as_mut()
+            .map(|detector| detector.scan(&chunk.samples))
+            .filter(|&found| found != bitstream)
+        {
+            match found {
+                Some(kind) => info!("{} bitstream: passing it through untouched", kind.as_str()),
+                None => info!("bitstream ended; back to PCM"),
+            }
+            // Neither side of the switch plays as the other, so what is held of the old one
+            // goes, and the receiver is told where.
+            media_time = media_time.wrapping_add((acc.len() / channels) as u32);
+            acc.clear();
+            acc_capture.clear();
+            discontinuity = true;
+            crossfade = found.is_none().then(|| Crossfade::new(Vec::new(), format));
+            bitstream = found;
+        }
+        // A bitstream has to reach the receiver bit for bit: no DSP, effects or fades.
+        if bitstream.is_none() {
+            let dsp_start = Instant::now();
+            dsp.sync(dsp_settings);
+            dsp.process(&mut chunk.samples);
+            for effect in effects.iter_mut() {
+                effect.process(&mut chunk.samples)?;
+            }
+            if crossfade
+                .as_mut()
+                .is_some_and(|fade| fade.apply(&mut chunk.samples))
+            {
+                crossfade = None;
+            }
+            stats.stages.record(Stage::Dsp, dsp_start.elapsed());
+            stats
+                .levels
+                .record(&chunk.samples, format.channels as usize);
+            stats.tap.record(&chunk.samples);
+        }
+        let chunk_samples = chunk.samples.len();
+        if let Some(pacer) = pacer.as_mut() {
+            pacer.observe_period(chunk_samples / channels);
+        }
+        for s in chunk.samples {
+            acc.push_back(s);
+        }
+        if chunk_samples > 0 {
+            acc_capture.push_back((chunk_samples, chunk.captured_at));
+        }
+        let mut dropped = 0;
+        if let LatePolicy::DropOldest(max_age) = late_policy {
+            dropped += drop_stale(&mut acc, &mut acc_capture, max_age) / channels;
+        }
+        if let Some(max_samples) = max_buffered_samples {
+            // Only the accumulator can be trimmed from here; the queue behind it and the
+            // TCP backlog ahead of it still count towards the cap.
+            let (tcp_packets, _) = transport.backlog();
+            let held = acc.len()
+                + stats.queued_samples.load(Ordering::Relaxed) as usize
+                + tcp_packets * samples_per_packet;
+            let excess = held.saturating_sub(max_samples).min(acc.len()) / channels * channels;
+            acc.drain(..excess);
+            consume_capture_time(&mut acc_capture, excess);
+            dropped += excess / channels;
+        }
+        if dropped > 0 {
+            media_time = media_time.wrapping_add(dropped as u32);
+            stats.late_dropped_us.fetch_add(
+                dropped as u64 * 1_000_000 / format.sample_rate.max(1) as u64,
+                Ordering::Relaxed,
+            );
+            discontinuity = true;
+            last_send = None;
+            if let Some(pacer) = pacer.as_mut() {
+                pacer.reset();
+            }
+        }
+
+        // Take in what capture already queued before encoding, so the pool gets several
+        // frames to encode side by side.
+        if encode_pool
+            .as_ref()
+            .is_some_and(|pool| !rx.is_empty() && acc.len() < pool.depth() * samples_per_packet)
+        {
+            continue;
+        }
+        while acc.len() >= samples_per_packet {
+            if let Some(pool) = encode_pool.as_mut() {
+                pool.fill(&acc, samples_per_packet)?;
+            }
+            if let Some(pacer) = pacer.as_mut() {
+                let due = pacer.due(media_time, Instant::now());
+                let wait = due.saturating_duration_since(Instant::now());
+                if !wait.is_zero() {
+                    sleeper.sleep(wait);
+                }
+            }
+            let packet_capture_time = consume_capture_time(&mut acc_capture, samples_per_packet);
+
+            let teed = tee.map(|tee| (tee, acc.range(..samples_per_packet).copied().collect()));
+            if let Some(ndi) = ndi.filter(|_| bitstream.is_none()) {
+                ndi.write(format, acc.range(..samples_per_packet).copied().collect());
+            }
+
+            let encode_span = trace_span!("encode", seq).entered();
+            let packet_build_start = Instant::now();
+            let mut packet = spare.pop().unwrap_or_default();
+            let pcm_template = match bitstream {
+                Some(_) => &bitstream_template,
+                None => &template,
+            };
+            match (
+                encode_pool.as_mut(),
+                codec.as_mut().filter(|_| bitstream.is_none()),
+            ) {
+                (Some(pool), _) => {
+                    acc.drain(..samples_per_packet);
+                    pool.next(&mut payload)?;
+                    template.begin(&mut packet, seq, media_time, payload.len())?;
+                    packet.extend_from_slice(&payload);
+                    if let Some(output) = output {
+                        output.write(format, media_time, payload.clone());
+                    }
+                }
+                (None, Some(codec)) => {
+                    frame.clear();
+                    frame.extend(acc.drain(..samples_per_packet));
+                    codec.encode(&frame, &mut payload)?;
+                    template.begin(&mut packet, seq, media_time, payload.len())?;
+                    packet.extend_from_slice(&payload);
+                    if let Some(output) = output {
+                        output.write(format, media_time, payload.clone());
+                    }
+                }
+                (None, None) => {
+                    pcm_template.begin(&mut packet, seq, media_time, samples_per_packet * 2)?;
+                    packet.reserve(samples_per_packet * 2);
+                    encode_pcm16(acc.drain(..samples_per_packet), &mut packet);
+                }
+            }
+            let packetize_start = Instant::now();
+            stats
+                .stages
+                .record(Stage::Encode, packetize_start - packet_build_start);
+            if let Some(ptp) = ptp {
+                stamp_ptp_time(&mut packet, ptp.now_us()?);
+            }
+            if std::mem::take(&mut discontinuity) {
+                mark_discontinuity(&mut packet);
+            }
+            stats
+                .stages
+                .record(Stage::Packetize, packetize_start.elapsed());
+            let packet_build_us = packet_build_start.elapsed().as_micros() as u64;
+            stats
+                .packet_build_us_sum
+                .fetch_add(packet_build_us, Ordering::Relaxed);
+            stats.packet_build_count.fetch_add(1, Ordering::Relaxed);
+            drop(encode_span);
+            let admitted = bucket
+                .as_mut()
+                .is_none_or(|bucket| bucket.admit(packet.len() + overhead));
+            if let Some((tee, samples)) = teed {
+                if admitted || tee.point() == TeePoint::PreCodec {
+                    tee.write(format, media_time, samples);
+                }
+            }
+            if admitted {
+                batch.push(packet);
+                batch_capture.push(packet_capture_time);
+                seq = seq.wrapping_add(1);
+            } else {
+                // Over --max-kbps: the frame is dropped like late audio, flagging the gap.
+                spare.push(packet);
+                stats.capped_us.fetch_add(frame_us, Ordering::Relaxed);
+                discontinuity = true;
+            }
+            media_time = media_time.wrapping_add(format.samples_per_channel as u32);
+            // Unpaced, every frame already waiting goes out in one batch.
+            if batch.is_empty() || pacer.is_none() && acc.len() >= samples_per_packet {
+                continue;
+            }
+
+            let first_seq = seq.wrapping_sub(batch.len() as u32);
+            let bytes: usize = batch.iter().map(Vec::len).sum();
+            let _send_span =
+                trace_span!("send", seq = first_seq, packets = batch.len(), bytes).entered();
+            let send_start = Instant::now();
+            if let Some(last_send) = last_send {
+                let interval_us = (send_start - last_send).as_micros() as u64;
+                stats
+                    .send_jitter_hist
+                    .record(interval_us.abs_diff(frame_us));
+            }
+            last_send = Some(send_start);
+            let wire_bytes = transport.send_batch(&batch, first_seq)?;
+            // The target that dropped them flags the gap in its own stream.
+            let dropped = transport.take_dropped();
+            if dropped > 0 {
+                stats
+                    .late_dropped_us
+                    .fetch_add(dropped as u64 * frame_us, Ordering::Relaxed);
+            }
+            let send_elapsed = send_start.elapsed();
+            stats.stages.record(Stage::Send, send_elapsed);
+            let socket_send_us = send_elapsed.as_micros() as u64;
+            stats
+                .socket_send_us_sum
+                .fetch_add(socket_send_us, Ordering::Relaxed);
+            stats.socket_send_count.fetch_add(1, Ordering::Relaxed);
+            stats.socket_send_hist.record(socket_send_us);
+            stats
+                .sent_packets
+                .fetch_add(batch.len() as u64, Ordering::Relaxed);
+            stats
+                .sent_bytes
+                .fetch_add(wire_bytes as u64, Ordering::Relaxed);
+            for (i, captured_at) in batch_capture.drain(..).enumerate() {
+                let Some(captured_at) = captured_at else {
+                    continue;
+                };
+                let capture_to_send_us = captured_at.elapsed().as_micros() as u64;
+                stats
+                    .capture_to_send_us_sum
+                    .fetch_add(capture_to_send_us, Ordering::Relaxed);
+                stats.capture_to_send_count.fetch_add(1, Ordering::Relaxed);
+                stats.capture_to_send_hist.record(capture_to_send_us);
+                if let Ok(now) = now_us() {
+                    feedback.record_sent(
+                        first_seq.wrapping_add(i as u32),
+                        now.saturating_sub(capture_to_send_us),
+                    );
+                }
+            }
+            spare.append(&mut batch);
+        }
+
+        let queued = stats.queued_samples.load(Ordering::Relaxed) as usize;
+        let (tcp_packets, tcp_bytes) = transport.backlog();
+        let held = acc.len() + queued + tcp_packets * samples_per_packet;
+        stats.buffered_audio_us.store(
+            (held / channels) as u64 * 1_000_000 / format.sample_rate.max(1) as u64,
+            Ordering::Relaxed,
+        );
+        let packet_bytes: usize = spare.iter().map(Vec::capacity).sum();
+        stats.buffer_bytes.store(
+            ((queued + acc.capacity() + frame.capacity()) * 2
+                + payload.capacity()
+                + packet_bytes
+                + tcp_bytes) as u64,
+            Ordering::Relaxed,
+        );
+    }
+    Ok(StreamPosition {
+        seq,
+        media_time,
+        format,
+        // A bitstream cut short is not audio to fade out of.
+        tail: match bitstream {
+            Some(_) => Vec::new(),
+            None => acc.into(),
+        },
+    })
+}
+
+/// Fades the audio the previous source left unsent out over the start of the next source,
+/// which fades in; with nothing left over, the new source only fades in.
+struct Crossfade {
+    tail: Vec<i16>,
+    channels: usize,
+    fade_in: usize,
+    /// Samples of the new source mixed so far.
+    pos: usize,
+}
+
+impl Crossfade {
+    fn new(tail: Vec<i16>, format: StreamFormat) -> Self {
+        let channels = format.channels as usize;
+        let fade_in = (CROSSFADE.as_micros() as u64 * format.sample_rate as u64 / 1_000_000)
+            as usize
+            * channels;
+        Self {
+            tail,
+            channels,
+            fade_in,
+            pos: 0,
+        }
+    }
+
+    /// Mixes the next `samples` of the new source; true once the crossfade is over.
+    fn apply(&mut self, samples: &mut [i16]) -> bool {
+        let len = self.fade_in.max(self.tail.len());
+        let tail_frames = (self.tail.len() / self.channels).max(1) as f32;
+        let fade_in_frames = (self.fade_in / self.channels).max(1) as f32;
+        for (at, sample) in (self.pos..len).zip(samples.iter_mut()) {
+            let frame = (at / self.channels) as f32;
+            let old = self
+                .tail
+                .get(at)
+                .map_or(0.0, |&old| old as f32 * (1.0 - frame / tail_frames));
+            let new = *sample as f32 * (frame / fade_in_frames).min(1.0);
+            *sample = (old + new).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        }
+        self.pos += samples.len();
+        self.pos >= len
+    }
+}
+
+/// Spreads the packets of each capture period over the period, timing every packet by
+/// the samples sent since an anchor rather than by when its audio arrived.
+struct Pacer {
+    sample_rate: u64,
+    frame: Duration,
+    /// Longest capture period seen, the most a packet may wait behind the one before it.
+    period: Duration,
+    anchor: Option<(Instant, u32)>,
+}
+
+impl Pacer {
+    fn new(sample_rate: u32, frame: Duration) -> Self {
+        Self {
+            sample_rate: sample_rate.max(1) as u64,
+            frame,
+            period: Duration::ZERO,
+            anchor: None,
+        }
+    }
+
+    fn observe_period(&mut self, frames: usize) {
+        let period = Duration::from_micros(frames as u64 * 1_000_000 / self.sample_rate);
+        self.period = self.period.max(period);
+    }
+
+    /// When the packet starting at `media_time` should go out. Re-anchors on it when the
+    /// capture clock has drifted a frame behind the schedule or a period ahead of it, or
+    /// after a gap in the stream.
+    fn due(&mut self, media_time: u32, now: Instant) -> Instant {
+        if let Some((at, anchor_media)) = self.anchor {
+            let samples = media_time.wrapping_sub(anchor_media) as u64;
+            let due = at + Duration::from_micros(samples * 1_000_000 / self.sample_rate);
+            if due + self.frame >= now && due <= now + self.period + self.frame {
+                return due;
+            }
+        }
+        self.anchor = Some((now, media_time));
+        now
+    }
+
+    fn reset(&mut self) {
+        self.anchor = None;
+    }
+}
+
+/// Drops whole captured chunks off the front of the queue while they are older than
+/// `max_age`, returning how many samples went.
+fn drop_stale(
+    acc: &mut VecDeque<i16>,
+    acc_capture: &mut VecDeque<(usize, Instant)>,
+    max_age: Duration,
+) -> usize {
+    let mut dropped = 0;
+    while let Some(&(count, captured_at)) = acc_capture.front() {
+        if captured_at.elapsed() <= max_age {
+            break;
+        }
+        acc_capture.pop_front();
+        let count = count.min(acc.len());
+        acc.drain(..count);
+        dropped += count;
+    }
+    dropped
+}
+
+fn consume_capture_time(
+    acc_capture: &mut VecDeque<(usize, Instant)>,
+    mut samples_to_consume: usize,
+) -> Option<Instant> {
+    let oldest = acc_capture.front().map(|(_, captured_at)| *captured_at);
+    while samples_to_consume > 0 {
+        let Some((count, captured_at)) = acc_capture.pop_front() else {
+            break;
+        };
+        if count > samples_to_consume {
+            acc_capture.push_front((count - samples_to_consume, captured_at));
+            break;
+        }
+        samples_to_consume -= count;
+    }
+    oldest
+}
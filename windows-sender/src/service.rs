@@ -0,0 +1,195 @@
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use clap::{CommandFactory, FromArgMatches};
+use tracing::error;
+use windows_service::define_windows_service;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType, SessionChangeReason,
+};
+use windows_service::service_control_handler::{
+    self, ServiceControlHandlerResult, ServiceStatusHandle,
+};
+use windows_service::service_dispatcher;
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+use crate::control::{ControlCommand, Router};
+use crate::exit;
+use crate::Args;
+
+pub const SERVICE_NAME: &str = "AudioLinkSender";
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+pub fn install(config: Option<&Path>) -> Result<()> {
+    let config = config.context("--install-service needs --config")?;
+    let config = std::fs::canonicalize(config)
+        .with_context(|| format!("config file {} not found", config.display()))?;
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .context("failed to open the service manager (run as administrator)")?;
+
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from("Audio Link Sender"),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()
+            .context("failed to locate the sender executable")?,
+        launch_arguments: vec![
+            OsString::from("--run-as-service"),
+            OsString::from("--config"),
+            config.into_os_string(),
+        ],
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .with_context(|| format!("failed to create service {SERVICE_NAME}"))?;
+    service
+        .set_description("Streams desktop or microphone audio to Audio Link receivers")
+        .context("failed to set service description")?;
+    println!(
+        "Installed service {SERVICE_NAME} (starts on boot; `sc start {SERVICE_NAME}` to start now)"
+    );
+    Ok(())
+}
+
+pub fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .context("failed to open the service manager (run as administrator)")?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .with_context(|| format!("service {SERVICE_NAME} is not installed"))?;
+    let status = service
+        .query_status()
+        .context("failed to query service status")?;
+    if status.current_state != ServiceState::Stopped {
+        service.stop().context("failed to stop service")?;
+    }
+    service.delete().context("failed to delete service")?;
+    println!("Removed service {SERVICE_NAME}");
+    Ok(())
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Hands the current thread to the service control manager; only valid when started by it.
+pub fn run_dispatcher() -> Result<()> {
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .context("failed to connect to the service control manager")
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(err) = run_service() {
+        error!("service error: {err:#}");
+    }
+}
+
+fn run_service() -> Result<()> {
+    let router = Arc::new(OnceLock::<Router>::new());
+    let status = Arc::new(OnceLock::<ServiceStatusHandle>::new());
+
+    let handler_router = Arc::clone(&router);
+    let handler_status = Arc::clone(&status);
+    let event_handler = move |event: ServiceControl| -> ServiceControlHandlerResult {
+        let Some(router) = handler_router.get() else {
+            return match event {
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                _ => ServiceControlHandlerResult::NotImplemented,
+            };
+        };
+        match event {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                report(handler_status.get(), ServiceState::StopPending, 0);
+                router.broadcast(ControlCommand::Quit);
+            }
+            ServiceControl::Pause => {
+                router.broadcast(ControlCommand::Pause);
+                report(handler_status.get(), ServiceState::Paused, 0);
+            }
+            ServiceControl::Continue => {
+                router.broadcast(ControlCommand::Resume);
+                report(handler_status.get(), ServiceState::Running, 0);
+            }
+            // A new interactive session can change the default render device, so
+            // restart capture to follow it.
+            ServiceControl::SessionChange(change) => match change.reason {
+                SessionChangeReason::SessionLogon
+                | SessionChangeReason::ConsoleConnect
+                | SessionChangeReason::RemoteConnect => {
+                    router.broadcast(ControlCommand::Restart);
+                }
+                _ => {}
+            },
+            ServiceControl::Interrogate => {}
+            _ => return ServiceControlHandlerResult::NotImplemented,
+        }
+        ServiceControlHandlerResult::NoError
+    };
+
+    let handle = service_control_handler::register(SERVICE_NAME, event_handler)
+        .context("failed to register service control handler")?;
+    let _ = status.set(handle);
+    report(status.get(), ServiceState::StartPending, 0);
+
+    let matches = Args::command().get_matches_from(std::env::args_os());
+    let args = Args::from_arg_matches(&matches)?;
+    let result = crate::run(args, &matches, |ready| {
+        let _ = router.set(ready.clone());
+        report(status.get(), ServiceState::Running, 0);
+    });
+    report(
+        status.get(),
+        ServiceState::Stopped,
+        result
+            .as_ref()
+            .map_or_else(|err| exit::code(err).into(), |_| 0),
+    );
+    result
+}
+
+fn report(handle: Option<&ServiceStatusHandle>, state: ServiceState, exit_code: u32) {
+    let Some(handle) = handle else {
+        return;
+    };
+    let pending = matches!(
+        state,
+        ServiceState::StartPending | ServiceState::StopPending
+    );
+    let controls_accepted = if pending || state == ServiceState::Stopped {
+        ServiceControlAccept::empty()
+    } else {
+        ServiceControlAccept::STOP
+            | ServiceControlAccept::SHUTDOWN
+            | ServiceControlAccept::PAUSE_CONTINUE
+            | ServiceControlAccept::SESSION_CHANGE
+    };
+    let _ = handle.set_service_status(ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: state,
+        controls_accepted,
+        exit_code: match exit_code {
+            0 => ServiceExitCode::Win32(0),
+            code => ServiceExitCode::ServiceSpecific(code),
+        },
+        checkpoint: 0,
+        wait_hint: if pending {
+            Duration::from_secs(10)
+        } else {
+            Duration::default()
+        },
+        process_id: None,
+    });
+}
@@ -0,0 +1,626 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use crossbeam_channel::Receiver;
+use serde_json::json;
+use tracing::{info, info_span, warn, Span};
+use windows_sender::archive::OpusArchive;
+use windows_sender::capture::{self, DesktopDevice, SourceKind, VirtualCable};
+use windows_sender::dsp::DspSettings;
+use windows_sender::error::Error;
+use windows_sender::journal::EventJournal;
+use windows_sender::ndi::NdiSource;
+use windows_sender::pairing::NoiseConfig;
+use windows_sender::plugin::Plugin;
+use windows_sender::probe;
+use windows_sender::resolve::Endpoint;
+use windows_sender::sched::Scheduling;
+use windows_sender::sender::{AudioSender, LatePolicy, SenderConfig};
+use windows_sender::stats::SenderStats;
+use windows_sender::tee::Tee;
+use windows_sender::transport::TransportKind;
+
+use crate::alert::{AlertExit, AlertMonitor};
+use crate::autoexit::{self, AutoExit};
+use crate::config::SessionArgs;
+use crate::control::{self, ControlRequest, Outcome};
+use crate::exit;
+use crate::hint;
+use crate::keychain;
+use crate::meter;
+use crate::notify;
+use crate::resume;
+use crate::sdp::SdpFile;
+use crate::spectrum;
+use crate::stats_log::StatsLogger;
+use crate::webhook::Webhook;
+use crate::{Args, AudioSource, LateAudio, Transport, VirtualRoute};
+
+/// `+5s`, `+500ms` or `+2m` from now, or seconds since the Unix epoch as `date +%s`
+/// prints them.
+pub fn parse_start_at(value: &str) -> Result<SystemTime, String> {
+    let invalid =
+        || format!("invalid start time {value:?}; expected +5s, +500ms, +2m or Unix seconds");
+    if let Some(offset) = value.strip_prefix('+') {
+        let digits = offset
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(offset.len());
+        let (number, unit) = offset.split_at(digits);
+        let number: u64 = number.parse().map_err(|_| invalid())?;
+        let delay = match unit {
+            "ms" => Duration::from_millis(number),
+            "s" => Duration::from_secs(number),
+            "m" => Duration::from_secs(number * 60),
+            _ => return Err(invalid()),
+        };
+        return Ok(SystemTime::now() + delay);
+    }
+    let secs: f64 = value.parse().map_err(|_| invalid())?;
+    let since_epoch = Duration::try_from_secs_f64(secs).map_err(|_| invalid())?;
+    Ok(UNIX_EPOCH + since_epoch)
+}
+
+pub fn sender_config(args: &Args) -> Result<SenderConfig> {
+    let prefer = args.prefer_ip.into();
+    let mut targets = Vec::new();
+    if args.transport == Transport::Adb {
+        if args.target_ip.is_some() || !args.extra_targets.is_empty() {
+            bail!("--transport adb streams to the phone on the USB cable; it takes no --target-ip or --extra-target");
+        }
+        targets.push(Endpoint::Addr(SocketAddr::from((
+            Ipv4Addr::LOCALHOST,
+            args.port,
+        ))));
+    // The Bluetooth transport streams to --bt-device alone, and --output on its own
+    // archives without sending.
+    } else if args.target_ip.is_some()
+        || args.transport != Transport::Bluetooth && args.output.is_none()
+    {
+        let target_ip = args
+            .target_ip
+            .as_deref()
+            .context("--target-ip is required unless --list-desktop-devices or --output is used")?;
+        targets.push(Endpoint::new(target_ip, args.port, prefer));
+    }
+    for extra in &args.extra_targets {
+        targets.push(Endpoint::parse(extra, prefer)?);
+    }
+
+    let plugins = args
+        .plugins
+        .iter()
+        .map(|path| Plugin::load(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    if args.write_sdp.is_some() && args.transport != Transport::Aes67 {
+        bail!("--write-sdp describes an RTP stream; it needs --transport aes67");
+    }
+    let transport = TransportKind::from(args.transport);
+    if args.remote_volume && !transport.is_native() {
+        bail!(
+            "--remote-volume listens on the receiver's report channel, which --transport {} does not have",
+            transport.as_str()
+        );
+    }
+    if !args.pins.is_empty() && args.noise.is_none() {
+        bail!("--pin checks the receivers' Noise keys; it needs --noise");
+    }
+
+    Ok(SenderConfig {
+        targets,
+        source: if args.stress {
+            SourceKind::Stress
+        } else {
+            args.source.into()
+        },
+        desktop_device: match args.route_through_virtual {
+            Some(route) => Some(virtual_device(args, route)?),
+            None => args.desktop_device.clone(),
+        },
+        source_file: args.source_file.clone(),
+        transport: args.transport.into(),
+        vban_stream: args.vban_stream.clone(),
+        aes67_session: args.aes67_session.clone(),
+        aes67_encoding: args.aes67_encoding.map(Into::into),
+        bt_device: args.bt_device.clone(),
+        bt_channel: args.bt_channel,
+        frame_ms: args.frame_ms,
+        dsp: DspSettings {
+            gain_db: args.gain_db,
+            muted: args.mute,
+            eq: args.eq.clone(),
+        },
+        watchdog: args.watchdog_secs.map(Duration::from_secs),
+        plugins,
+        journal: Arc::default(),
+        key: keychain::session_key(args)?,
+        cipher: args.cipher.into(),
+        key_rotation: args
+            .rotate_key_mins
+            .map(|mins| Duration::from_secs(mins * 60)),
+        noise: args
+            .noise
+            .as_deref()
+            .map(NoiseConfig::sender)
+            .transpose()?
+            .map(|noise| NoiseConfig {
+                pins: args.pins.clone(),
+                ..noise
+            }),
+        access_token: args.access_token.clone(),
+        remote_volume: args.remote_volume,
+        ptp: args.ptp.clone(),
+        pace: args.pace,
+        pause_notice: args.pause_notice,
+        playout_delay: args
+            .playout_delay_ms
+            .map(|ms| Duration::from_millis(ms.into())),
+        start_at: args.start_at,
+        late_policy: match args.late_audio {
+            LateAudio::Never => LatePolicy::Never,
+            LateAudio::DropOldest => {
+                LatePolicy::DropOldest(Duration::from_millis(args.max_audio_age_ms.into()))
+            }
+        },
+        queue_overflow: args.queue_overflow.into(),
+        io_uring: args.io_uring,
+        gso: args.gso,
+        send_buffer: args.so_sndbuf,
+        tcp_backlog: args
+            .tcp_backlog_ms
+            .map(|ms| Duration::from_millis(ms.into())),
+        max_kbps: args.max_kbps,
+        tee: args
+            .tee
+            .as_deref()
+            .map(|path| Tee::new(path, args.tee_point.into()))
+            .transpose()?
+            .map(Arc::new),
+        ndi: args
+            .ndi
+            .as_deref()
+            .map(NdiSource::new)
+            .transpose()?
+            .map(Arc::new),
+        output: args
+            .output
+            .as_deref()
+            .map(OpusArchive::new)
+            .transpose()?
+            .map(Arc::new),
+        passthrough: args.passthrough,
+        max_buffered: args
+            .max_buffered_ms
+            .map(|ms| Duration::from_millis(ms.into())),
+        encode_workers: args.encode_workers,
+        scheduling: Scheduling {
+            realtime: args.realtime,
+            cores: args.affinity.clone(),
+        },
+    })
+}
+
+/// The virtual device `--route-through-virtual` captures, which the apps to stream are
+/// set to play into while everything else keeps playing on the speakers.
+fn virtual_device(args: &Args, route: VirtualRoute) -> Result<String> {
+    if args.source != AudioSource::Desktop || args.stress {
+        bail!("--route-through-virtual captures a playback device; it needs --source desktop");
+    }
+    let mut found: Vec<(VirtualCable, DesktopDevice)> = capture::desktop_devices()
+        .context("--route-through-virtual looks for the virtual device among the playback devices")?
+        .into_iter()
+        .filter_map(|device| Some((device.virtual_cable()?, device)))
+        .filter(|(cable, _)| route.matches(*cable))
+        .collect();
+    found.sort_by_key(|(cable, _)| *cable as u8);
+    let Some((cable, device)) = found.into_iter().next() else {
+        bail!(
+            "no matching virtual device installed; --route-through-virtual takes VB-Cable, \
+             VoiceMeeter or Steam Streaming Speakers, and --list-desktop-devices labels them"
+        );
+    };
+    if device.is_default {
+        warn!(
+            "{} is the default playback device, so every app plays into it; keep the \
+             speakers as default to stream only some apps",
+            device.name
+        );
+    }
+    info!(
+        "Capturing {} ({}): set the output of the apps to stream to it in Settings > \
+         System > Sound > Volume mixer",
+        device.name,
+        cable.as_str()
+    );
+    Ok(device.name)
+}
+
+/// Runs one capture -> send pipeline until it fails or is told to quit.
+///
+/// `label` prefixes every stats line; it is empty when only one session runs. Log events
+/// of a session carry a `session` span when several run.
+pub fn run(
+    session: &SessionArgs,
+    config: SenderConfig,
+    requests: &Receiver<ControlRequest>,
+    label: &str,
+) -> Result<()> {
+    let _span = if label.is_empty() {
+        Span::none()
+    } else {
+        info_span!("session", name = %session.name)
+    }
+    .entered();
+    let journal = Arc::clone(&config.journal);
+    let webhook = session
+        .args
+        .stats_webhook
+        .as_deref()
+        .map(|url| Webhook::spawn(url, &session.name, &journal))
+        .transpose()?;
+    notify::watch(&session.args, label, &journal)?;
+    let result = run_sender(session, config, requests, label, webhook);
+    if let Some(path) = &session.args.events_file {
+        if let Err(err) = dump_events(path, &session.name, &journal) {
+            warn!("failed to write events to {}: {err}", path.display());
+        }
+    }
+    result
+}
+
+fn run_sender(
+    session: &SessionArgs,
+    mut config: SenderConfig,
+    requests: &Receiver<ControlRequest>,
+    label: &str,
+    webhook: Option<Webhook>,
+) -> Result<()> {
+    if config.transport == TransportKind::Udp && !session.args.no_preflight {
+        preflight(&config.targets);
+    }
+    let frame_ms = session.args.frame_ms;
+    let watchdog = config.watchdog.is_some();
+    // Only a lone session is recorded; several come from a config file already.
+    let mut record = label.is_empty();
+    let started = Instant::now();
+    let mut summary = Summary::default();
+    let mut restarted = false;
+    let result = loop {
+        let sender = AudioSender::start(config.clone())
+            .with_context(|| format!("failed to start session {}", session.name))
+            // A restart that fails has the stream stop, the same as a failure while sending.
+            .map_err(|err| if restarted { exit::streaming(err) } else { err })?;
+        restarted = true;
+        if std::mem::take(&mut record) {
+            resume::record(&session.args);
+        }
+        let format = sender.format();
+
+        info!(
+            "Source: {} ({})",
+            config.source.as_str(),
+            sender.source_name()
+        );
+        info!(
+            "Config: {} Hz, {} ch, frame={} ms ({} samples/ch)",
+            format.sample_rate, format.channels, frame_ms, format.samples_per_channel
+        );
+        for target in &config.targets {
+            info!("Target: {target}");
+        }
+        info!("Transport: {}", config.transport.as_str());
+        if config.transport == TransportKind::Vban {
+            info!(
+                "VBAN stream: {}",
+                config.vban_stream.as_deref().unwrap_or("Stream1")
+            );
+        }
+        if config.transport == TransportKind::Aes67 {
+            info!(
+                "AES67 session: {} ({}), announced by SAP",
+                config.aes67_session.as_deref().unwrap_or("windows-sender"),
+                config.aes67_encoding.unwrap_or_default().as_str()
+            );
+            if config.ptp.is_none() {
+                warn!("AES67 without --ptp: RTP timestamps follow the system clock, which AES67 devices only lock to if it is on their PTP domain");
+            }
+        }
+        if config.key.is_some() {
+            info!(
+                "Encryption: {} with the pre-shared key",
+                config.cipher.as_str()
+            );
+        }
+        if let Some(period) = config.key_rotation {
+            info!("Key rotation: every {} min", period.as_secs() / 60);
+        }
+        if let Some(ptp) = &config.ptp {
+            info!("Timestamps: PTP domain clock from {ptp}");
+        }
+        if config.io_uring {
+            info!("Sending: through io_uring");
+        }
+        if config.gso {
+            info!("Sending: batches segmented by the kernel (UDP GSO)");
+        }
+        if config.scheduling.realtime {
+            info!("Threads: real-time priority for capture and send");
+        }
+        if let Some(bytes) = config.send_buffer {
+            info!("Socket send buffer: {bytes} bytes requested");
+        }
+        if let Some(backlog) = config.tcp_backlog {
+            info!(
+                "TCP backlog: oldest audio dropped past {} ms behind",
+                backlog.as_millis()
+            );
+        }
+        if let Some(max) = config.max_kbps {
+            info!("Bandwidth cap: {max} kbps per target");
+        }
+        if let Some(tee) = &config.tee {
+            info!("Tee: {} ({})", tee.path().display(), tee.point().as_str());
+        }
+        if let Some(ndi) = &config.ndi {
+            info!("NDI: publishing as {:?}", ndi.name());
+        }
+        if let Some(output) = &config.output {
+            info!("Output: {} (Ogg Opus)", output.path().display());
+        }
+        if config.remote_volume {
+            info!("Volume: receivers may set the gain and mute");
+        }
+        if let Some(duration) = session.args.duration {
+            info!("Stops after {}", autoexit::format_duration(duration));
+        }
+        if let Some(silence) = session.args.exit_on_silence {
+            info!(
+                "Stops after {} of silence",
+                autoexit::format_duration(silence)
+            );
+        }
+        if let Some(max) = config.max_buffered {
+            info!(
+                "Buffered audio: oldest dropped past {} ms held",
+                max.as_millis()
+            );
+        }
+        if !config.scheduling.cores.is_empty() {
+            info!(
+                "Threads: capture and send pinned to cores {:?}",
+                config.scheduling.cores
+            );
+        }
+        if config.pace {
+            info!("Pacing: one packet every {} ms", config.frame_ms);
+        }
+        if let Some(wait) = config
+            .start_at
+            .and_then(|at| at.duration_since(SystemTime::now()).ok())
+        {
+            info!("Start: deferred by {:.1} s", wait.as_secs_f64());
+        }
+        if let Some(delay) = config.playout_delay {
+            info!(
+                "Multi-room: receivers play {} ms after the send time",
+                delay.as_millis()
+            );
+        }
+        if let LatePolicy::DropOldest(max_age) = config.late_policy {
+            info!(
+                "Late audio: dropped once older than {} ms",
+                max_age.as_millis()
+            );
+        }
+        if let Some(noise) = &config.noise {
+            info!(
+                "Encryption: {} with a key handed over by Noise pairing (identity {})",
+                config.cipher.as_str(),
+                noise.identity.public_key()
+            );
+        }
+        for plugin in &config.plugins {
+            info!("Plugin: {} ({})", plugin.name(), plugin.kind().as_str());
+        }
+        if config.encode_workers > 0 {
+            info!("Encoding: {} codec worker threads", config.encode_workers);
+        }
+
+        #[cfg(feature = "otel")]
+        crate::otel::watch(&session.name, sender.stats(), sender.backlog_gauge());
+
+        // The dashboard and the meter own the terminal, so stats lines only go to a file there.
+        let print_stats =
+            !(session.args.tui || session.args.meter) || session.args.stats_file.is_some();
+        let logger = StatsLogger {
+            session: session.name.clone(),
+            label: label.to_string(),
+            frame_ms,
+            format: session.args.stats_format,
+            file: session.args.stats_file.clone(),
+            print: print_stats,
+            webhook: webhook.clone(),
+            interval: Duration::from_millis(session.args.stats_interval_ms),
+            groups: session.args.stats_fields.clone(),
+        };
+        let logger_running = Arc::new(AtomicBool::new(true));
+        let spawn_logger = || {
+            logger.spawn(
+                Arc::clone(sender.stats()),
+                sender.backlog_gauge(),
+                Arc::clone(&logger_running),
+            )
+        };
+        let mut stats_thread = (print_stats || webhook.is_some()).then(spawn_logger);
+        let mut alerts = AlertMonitor::new(&session.args, Arc::clone(&config.journal));
+        let mut auto_exit = AutoExit::new(&session.args, started);
+        let mut sdp_file = SdpFile::new(&session.args);
+        let mut on_idle = || {
+            if let Some(alerts) = &mut alerts {
+                alerts.check(&sender);
+            }
+            if let Some(auto_exit) = &mut auto_exit {
+                auto_exit.check(&sender);
+            }
+            if let Some(sdp_file) = &mut sdp_file {
+                sdp_file.check(&sender);
+            }
+            if watchdog
+                && stats_thread
+                    .as_ref()
+                    .is_some_and(|thread| thread.is_finished())
+            {
+                warn!("stats logger stopped; restarting it");
+                stats_thread = Some(spawn_logger());
+                sender.stats().restarts.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+
+        let meter = session
+            .args
+            .meter
+            .then(|| meter::spawn(Arc::clone(sender.stats()), Arc::clone(&logger_running)));
+
+        let spectrum = session.args.spectrum.then(|| {
+            spectrum::spawn(
+                Arc::clone(sender.stats()),
+                format,
+                Arc::clone(&logger_running),
+            )
+        });
+
+        let outcome = control::serve(&sender, &mut config, requests, &mut on_idle);
+        logger_running.store(false, Ordering::Relaxed);
+        if let Some(path) = &session.args.perf_folded {
+            let prefix = if label.is_empty() { "" } else { &session.name };
+            if let Err(err) = dump_stages(path, prefix, sender.stats()) {
+                warn!("failed to write stage timings to {}: {err}", path.display());
+            }
+        }
+        for thread in [meter, spectrum].into_iter().flatten() {
+            let _ = thread.join();
+        }
+        summary.add(sender.stats());
+        match outcome {
+            Outcome::Finished => {
+                if let Err(err) = sender.wait() {
+                    break Err(exit::streaming(err));
+                }
+                if let Some(alert) = alerts.as_ref().and_then(AlertMonitor::tripped) {
+                    break Err(AlertExit(alert.to_string()).into());
+                }
+                break Ok(());
+            }
+            Outcome::Quit => break sender.stop().map_err(exit::streaming),
+            Outcome::Restart => sender.stop().map_err(exit::streaming)?,
+        }
+    };
+    summary.log(started.elapsed());
+    result
+}
+
+/// What a session sent over all of its restarts, logged when it ends.
+#[derive(Default)]
+struct Summary {
+    packets: u64,
+    bytes: u64,
+    drops: u64,
+    restarts: u64,
+}
+
+impl Summary {
+    fn add(&mut self, stats: &SenderStats) {
+        let snapshot = stats.snapshot();
+        self.packets += snapshot.sent_packets;
+        self.bytes += snapshot.sent_bytes;
+        self.drops += snapshot.capture_drops;
+        self.restarts += snapshot.restarts;
+    }
+
+    fn log(&self, elapsed: Duration) {
+        info!(
+            "Session summary: {:.1} s, {} packets ({:.1} MB) sent, {} capture drops, {} stage restarts",
+            elapsed.as_secs_f64(),
+            self.packets,
+            self.bytes as f64 / 1e6,
+            self.drops,
+            self.restarts
+        );
+    }
+}
+
+/// Asks every UDP target for a report before capture starts, all at once, so a receiver
+/// that is not there shows up now rather than after hours of audio sent into the void.
+/// Receivers that predate reports never answer, so silence only warns.
+fn preflight(targets: &[Endpoint]) {
+    let checks: Vec<_> = targets
+        .iter()
+        .cloned()
+        .map(|target| {
+            thread::spawn(move || {
+                let rtt = target.resolve().map_err(Error::from).and_then(probe::ping);
+                (target, rtt)
+            })
+        })
+        .collect();
+    for check in checks {
+        let Ok((target, rtt)) = check.join() else {
+            continue;
+        };
+        match rtt {
+            Ok(rtt) => info!(
+                "Preflight: receiver at {target} answered in {:.1} ms",
+                rtt.as_secs_f64() * 1000.0
+            ),
+            Err(err) => {
+                match &err {
+                    Error::NoFeedback { .. } => {
+                        warn!("NO RECEIVER ANSWERED at {target}; streaming anyway, maybe to no one")
+                    }
+                    err => warn!("Preflight to {target} failed: {err}; streaming anyway"),
+                }
+                if let Some(hint) = hint::for_error(&err.into()) {
+                    warn!(
+                        "  Likely {} problem: {}",
+                        hint.category.as_str(),
+                        hint.reason
+                    );
+                    warn!("  Try: {}", hint.fix);
+                }
+            }
+        }
+    }
+}
+
+/// Appends the stage totals to `path` as folded stacks, under `prefix` when not empty.
+fn dump_stages(path: &Path, prefix: &str, stats: &SenderStats) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for line in stats.stages.snapshot().folded().lines() {
+        match prefix {
+            "" => writeln!(file, "{line}")?,
+            prefix => writeln!(file, "{prefix};{line}")?,
+        }
+    }
+    Ok(())
+}
+
+/// Appends the journal to `path`, one JSON object per event.
+fn dump_events(path: &Path, session: &str, journal: &EventJournal) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for event in journal.events() {
+        let line = json!({
+            "session": session,
+            "time_ms": event.time_ms,
+            "kind": event.kind,
+            "message": event.message,
+        });
+        writeln!(file, "{line}")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use tracing::{info, Span};
+use windows_sender::sender::StreamFormat;
+use windows_sender::stats::SenderStats;
+
+const INTERVAL: Duration = Duration::from_secs(1);
+/// About 12 Hz per bin at 48 kHz: coarse, but enough to spot a pitch shift.
+const FFT_SIZE: usize = 4096;
+const FLOOR_DB: f32 = -90.0;
+/// Below this a channel's loudest bin is noise, not a tone worth naming.
+const SILENT_DB: f32 = -70.0;
+const BANDS: [(&str, f32, f32); 7] = [
+    ("sub", 20.0, 60.0),
+    ("bass", 60.0, 250.0),
+    ("low-mid", 250.0, 500.0),
+    ("mid", 500.0, 2000.0),
+    ("high-mid", 2000.0, 4000.0),
+    ("presence", 4000.0, 6000.0),
+    ("air", 6000.0, 20000.0),
+];
+
+/// `--spectrum`: logs the dominant frequency and band energies of every channel once a
+/// second, plus how the first two channels relate, until `running` clears.
+pub fn spawn(
+    stats: Arc<SenderStats>,
+    format: StreamFormat,
+    running: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    let channels = format.channels as usize;
+    stats.tap.enable(FFT_SIZE * channels);
+    let span = Span::current();
+    thread::spawn(move || {
+        let _span = span.entered();
+        info!(
+            "Spectrum: {FFT_SIZE}-point FFT at {} Hz, {:.1} Hz per bin",
+            format.sample_rate,
+            format.sample_rate as f32 / FFT_SIZE as f32
+        );
+        while running.load(Ordering::Relaxed) {
+            thread::sleep(INTERVAL);
+            let samples = stats.tap.take();
+            if samples.len() < FFT_SIZE * channels {
+                info!("spectrum: no audio");
+                continue;
+            }
+            let channel = |index: usize| -> Vec<f32> {
+                samples
+                    .iter()
+                    .skip(index)
+                    .step_by(channels)
+                    .map(|&sample| sample as f32 / 32768.0)
+                    .collect()
+            };
+            let names = channel_names(channels);
+            for (index, name) in names.iter().enumerate() {
+                info!(
+                    "spectrum {name}: {}",
+                    describe(&channel(index), format.sample_rate)
+                );
+            }
+            if channels >= 2 {
+                info!(
+                    "spectrum {}/{}: {}",
+                    names[0],
+                    names[1],
+                    compare(&channel(0), &channel(1))
+                );
+            }
+        }
+    })
+}
+
+fn channel_names(channels: usize) -> Vec<String> {
+    match channels {
+        1 => vec!["M".to_string()],
+        2 => vec!["L".to_string(), "R".to_string()],
+        _ => (1..=channels).map(|channel| channel.to_string()).collect(),
+    }
+}
+
+/// Dominant frequency and RMS level per band of one channel.
+fn describe(samples: &[f32], sample_rate: u32) -> String {
+    let power = power_spectrum(samples);
+    let bin_hz = sample_rate as f32 / FFT_SIZE as f32;
+    // Parseval with the Hann window's energy, so a band reads as the RMS level it carries.
+    let window_energy = (0..FFT_SIZE).map(|n| hann(n).powi(2)).sum::<f32>();
+    let scale = 2.0 / (FFT_SIZE as f32 * window_energy);
+    let to_db = |power: f32| (10.0 * (power * scale).max(1e-12).log10()).max(FLOOR_DB);
+
+    let (peak_bin, &peak_power) = power
+        .iter()
+        .enumerate()
+        .skip(1)
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .expect("spectrum has bins");
+    let dominant = if to_db(peak_power) < SILENT_DB {
+        "silent".to_string()
+    } else {
+        format!(
+            "dominant {:.0} Hz ({:.1} dB)",
+            refine(&power, peak_bin) * bin_hz,
+            to_db(peak_power)
+        )
+    };
+    let nyquist = sample_rate as f32 / 2.0;
+    let bands = BANDS
+        .iter()
+        .filter(|(_, low, _)| *low < nyquist)
+        .map(|(name, low, high)| {
+            let bins = (low / bin_hz).ceil() as usize..=((high.min(nyquist) / bin_hz) as usize);
+            let band_power = power[bins].iter().sum::<f32>();
+            format!("{name} {:.0}", to_db(band_power))
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("{dominant} | {bands}")
+}
+
+/// Correlation near -1 means one channel is inverted; a large balance points at a
+/// swapped or dead channel.
+fn compare(left: &[f32], right: &[f32]) -> String {
+    let dot = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    let (left_energy, right_energy) = (dot(left, left), dot(right, right));
+    if left_energy == 0.0 || right_energy == 0.0 {
+        return "one channel is silent".to_string();
+    }
+    format!(
+        "correlation {:+.2}, balance {:+.1} dB",
+        dot(left, right) / (left_energy * right_energy).sqrt(),
+        10.0 * (left_energy / right_energy).log10()
+    )
+}
+
+/// One-sided power of the Hann-windowed samples, bins 0..=N/2.
+fn power_spectrum(samples: &[f32]) -> Vec<f32> {
+    let mut re = samples
+        .iter()
+        .enumerate()
+        .map(|(n, sample)| sample * hann(n))
+        .collect::<Vec<_>>();
+    let mut im = vec![0.0; FFT_SIZE];
+    fft(&mut re, &mut im);
+    (0..=FFT_SIZE / 2)
+        .map(|bin| re[bin].powi(2) + im[bin].powi(2))
+        .collect()
+}
+
+fn hann(n: usize) -> f32 {
+    0.5 - 0.5 * (2.0 * PI * n as f32 / FFT_SIZE as f32).cos()
+}
+
+/// Fractional bin of a peak from a parabola through its neighbours.
+fn refine(power: &[f32], bin: usize) -> f32 {
+    let Some(&right) = power.get(bin + 1) else {
+        return bin as f32;
+    };
+    let db = |power: f32| power.max(1e-20).ln();
+    let (left, center, right) = (db(power[bin - 1]), db(power[bin]), db(right));
+    let curve = left - 2.0 * center + right;
+    if curve >= 0.0 {
+        return bin as f32;
+    }
+    bin as f32 + 0.5 * (left - right) / curve
+}
+
+/// In-place iterative radix-2 FFT; the length must be a power of two.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let len = re.len();
+    let mut j = 0;
+    for i in 1..len {
+        let mut bit = len >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut size = 2;
+    while size <= len {
+        let angle = -2.0 * PI / size as f32;
+        for start in (0..len).step_by(size) {
+            for k in 0..size / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + size / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        size <<= 1;
+    }
+}
@@ -0,0 +1,802 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+#[derive(Default)]
+pub struct SenderStats {
+    pub captured_chunks: AtomicU64,
+    pub captured_samples: AtomicU64,
+    pub captured_nonzero_samples: AtomicU64,
+    pub captured_abs_sum: AtomicU64,
+    pub capture_drops: AtomicU64,
+    pub drop_bursts: DropBursts,
+    /// Most chunks waiting to be sent at once since this was last reset to 0.
+    pub backlog_high_water: AtomicU64,
+    /// Capture buffers the device flagged; only WASAPI reports these.
+    pub capture_discontinuities: AtomicU64,
+    pub capture_silent: AtomicU64,
+    pub capture_timestamp_errors: AtomicU64,
+    pub capture_queue_us_sum: AtomicU64,
+    pub capture_queue_count: AtomicU64,
+    pub capture_to_send_us_sum: AtomicU64,
+    pub capture_to_send_count: AtomicU64,
+    pub packet_build_us_sum: AtomicU64,
+    pub packet_build_count: AtomicU64,
+    pub socket_send_us_sum: AtomicU64,
+    pub socket_send_count: AtomicU64,
+    pub sent_packets: AtomicU64,
+    pub sent_bytes: AtomicU64,
+    pub restarts: AtomicU64,
+    /// Receiver reports; the round trip and offset are the latest estimates.
+    pub feedback_reports: AtomicU64,
+    pub rtt_us: AtomicU64,
+    /// Receiver clock minus sender clock.
+    pub clock_offset_us: AtomicI64,
+    /// How many nanoseconds per second the receiver clock gains; 0 until a few seconds of
+    /// reports arrived.
+    pub clock_drift_ppb: AtomicI64,
+    /// How many nanoseconds per second the capture device clock runs fast against the
+    /// monotonic clock; 0 until ten seconds of capture.
+    pub device_drift_ppb: AtomicI64,
+    /// Delivery counts from receiver reports, summed over targets.
+    pub remote_received: AtomicU64,
+    pub remote_lost: AtomicU64,
+    pub remote_reordered: AtomicU64,
+    pub remote_duplicates: AtomicU64,
+    /// Interarrival jitter from the latest receiver report.
+    pub remote_jitter_us: AtomicU64,
+    /// Probe bytes received, from the latest receiver report.
+    pub remote_probe_bytes: AtomicU64,
+    /// Reports and access frames ignored for a missing or wrong access token.
+    pub unauthorized_feedback: AtomicU64,
+    /// Audio dropped for being older than `LatePolicy::DropOldest` allows, or for a TCP
+    /// target falling further behind than its backlog.
+    pub late_dropped_us: AtomicU64,
+    /// Audio dropped for going over `SenderConfig::max_kbps`.
+    pub capped_us: AtomicU64,
+    /// Samples waiting in the queue from the capture to the send loop.
+    pub queued_samples: AtomicU64,
+    /// Audio held on the way to the sockets: the capture queue, the send loop's
+    /// accumulator and any TCP backlog.
+    pub buffered_audio_us: AtomicU64,
+    /// Approximate heap those buffers take up.
+    pub buffer_bytes: AtomicU64,
+    pub capture_to_send_hist: LatencyHistogram,
+    pub socket_send_hist: LatencyHistogram,
+    pub capture_to_playout_hist: LatencyHistogram,
+    /// How far each send interval strays from the frame duration.
+    pub send_jitter_hist: LatencyHistogram,
+    /// Levels of the audio as sent, after gain and effects.
+    pub levels: LevelMeter,
+    pub tap: AudioTap,
+    pub stages: StageTimes,
+    pub targets: TargetStatsTable,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub struct SenderStatsSnapshot {
+    pub captured_chunks: u64,
+    pub captured_samples: u64,
+    pub captured_nonzero_samples: u64,
+    pub captured_abs_sum: u64,
+    pub capture_drops: u64,
+    pub capture_discontinuities: u64,
+    pub capture_silent: u64,
+    pub capture_timestamp_errors: u64,
+    pub capture_queue_us_sum: u64,
+    pub capture_queue_count: u64,
+    pub capture_to_send_us_sum: u64,
+    pub capture_to_send_count: u64,
+    pub packet_build_us_sum: u64,
+    pub packet_build_count: u64,
+    pub socket_send_us_sum: u64,
+    pub socket_send_count: u64,
+    pub sent_packets: u64,
+    pub sent_bytes: u64,
+    pub restarts: u64,
+    pub feedback_reports: u64,
+    pub rtt_us: u64,
+    pub clock_offset_us: i64,
+    pub remote_received: u64,
+    pub remote_lost: u64,
+    pub remote_reordered: u64,
+    pub remote_duplicates: u64,
+    pub remote_jitter_us: u64,
+    pub remote_probe_bytes: u64,
+    pub unauthorized_feedback: u64,
+    pub clock_drift_ppb: i64,
+    pub device_drift_ppb: i64,
+    pub late_dropped_us: u64,
+    pub capped_us: u64,
+    pub buffered_audio_us: u64,
+    pub buffer_bytes: u64,
+}
+
+impl SenderStats {
+    pub fn snapshot(&self) -> SenderStatsSnapshot {
+        SenderStatsSnapshot {
+            captured_chunks: self.captured_chunks.load(Ordering::Relaxed),
+            captured_samples: self.captured_samples.load(Ordering::Relaxed),
+            captured_nonzero_samples: self.captured_nonzero_samples.load(Ordering::Relaxed),
+            captured_abs_sum: self.captured_abs_sum.load(Ordering::Relaxed),
+            capture_drops: self.capture_drops.load(Ordering::Relaxed),
+            capture_discontinuities: self.capture_discontinuities.load(Ordering::Relaxed),
+            capture_silent: self.capture_silent.load(Ordering::Relaxed),
+            capture_timestamp_errors: self.capture_timestamp_errors.load(Ordering::Relaxed),
+            capture_queue_us_sum: self.capture_queue_us_sum.load(Ordering::Relaxed),
+            capture_queue_count: self.capture_queue_count.load(Ordering::Relaxed),
+            capture_to_send_us_sum: self.capture_to_send_us_sum.load(Ordering::Relaxed),
+            capture_to_send_count: self.capture_to_send_count.load(Ordering::Relaxed),
+            packet_build_us_sum: self.packet_build_us_sum.load(Ordering::Relaxed),
+            packet_build_count: self.packet_build_count.load(Ordering::Relaxed),
+            socket_send_us_sum: self.socket_send_us_sum.load(Ordering::Relaxed),
+            socket_send_count: self.socket_send_count.load(Ordering::Relaxed),
+            sent_packets: self.sent_packets.load(Ordering::Relaxed),
+            sent_bytes: self.sent_bytes.load(Ordering::Relaxed),
+            restarts: self.restarts.load(Ordering::Relaxed),
+            feedback_reports: self.feedback_reports.load(Ordering::Relaxed),
+            rtt_us: self.rtt_us.load(Ordering::Relaxed),
+            clock_offset_us: self.clock_offset_us.load(Ordering::Relaxed),
+            remote_received: self.remote_received.load(Ordering::Relaxed),
+            remote_lost: self.remote_lost.load(Ordering::Relaxed),
+            remote_reordered: self.remote_reordered.load(Ordering::Relaxed),
+            remote_duplicates: self.remote_duplicates.load(Ordering::Relaxed),
+            remote_jitter_us: self.remote_jitter_us.load(Ordering::Relaxed),
+            remote_probe_bytes: self.remote_probe_bytes.load(Ordering::Relaxed),
+            unauthorized_feedback: self.unauthorized_feedback.load(Ordering::Relaxed),
+            clock_drift_ppb: self.clock_drift_ppb.load(Ordering::Relaxed),
+            device_drift_ppb: self.device_drift_ppb.load(Ordering::Relaxed),
+            late_dropped_us: self.late_dropped_us.load(Ordering::Relaxed),
+            capped_us: self.capped_us.load(Ordering::Relaxed),
+            buffered_audio_us: self.buffered_audio_us.load(Ordering::Relaxed),
+            buffer_bytes: self.buffer_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Drops further apart than this start a new burst.
+pub const DROP_BURST_GAP: Duration = Duration::from_millis(100);
+/// Finished bursts kept until someone takes them.
+const MAX_DROP_BURSTS: usize = 64;
+
+/// A run of capture drops, from the first dropped chunk to the last.
+#[derive(Copy, Clone, Debug)]
+pub struct DropBurst {
+    pub chunks: u64,
+    pub started: Instant,
+    pub ended: Instant,
+}
+
+impl DropBurst {
+    pub fn duration(&self) -> Duration {
+        self.ended - self.started
+    }
+}
+
+/// Groups capture drops into bursts, since an average drop rate hides a single stall.
+#[derive(Default)]
+pub struct DropBursts {
+    state: Mutex<DropBurstState>,
+}
+
+#[derive(Default)]
+struct DropBurstState {
+    open: Option<DropBurst>,
+    finished: VecDeque<DropBurst>,
+}
+
+impl DropBursts {
+    /// Counts one dropped chunk.
+    pub fn record(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match &mut state.open {
+            Some(burst) if now - burst.ended <= DROP_BURST_GAP => {
+                burst.chunks += 1;
+                burst.ended = now;
+            }
+            open => {
+                let finished = open.replace(DropBurst {
+                    chunks: 1,
+                    started: now,
+                    ended: now,
+                });
+                state.finish(finished);
+            }
+        }
+    }
+
+    /// Bursts that are over, oldest first: no drop followed them for `DROP_BURST_GAP`.
+    pub fn take_finished(&self) -> Vec<DropBurst> {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .open
+            .is_some_and(|burst| burst.ended.elapsed() > DROP_BURST_GAP)
+        {
+            let finished = state.open.take();
+            state.finish(finished);
+        }
+        state.finished.drain(..).collect()
+    }
+}
+
+impl DropBurstState {
+    fn finish(&mut self, burst: Option<DropBurst>) {
+        let Some(burst) = burst else {
+            return;
+        };
+        if self.finished.len() == MAX_DROP_BURSTS {
+            self.finished.pop_front();
+        }
+        self.finished.push_back(burst);
+    }
+}
+
+/// What happened to one target, kept apart from the others so the trouble of one receiver
+/// shows against it alone.
+#[derive(Default)]
+pub struct TargetStats {
+    pub sent_packets: AtomicU64,
+    pub sent_bytes: AtomicU64,
+    pub send_errors: AtomicU64,
+    /// Audio packets it missed while set aside after a send error.
+    pub skipped_packets: AtomicU64,
+    /// Times it came back on its own after being set aside.
+    pub reconnects: AtomicU64,
+    /// Set aside after a send error and waiting for a reconnect.
+    pub set_aside: AtomicBool,
+    pub remote_received: AtomicU64,
+    pub remote_lost: AtomicU64,
+    /// From this target's latest report.
+    pub rtt_us: AtomicU64,
+    pub remote_jitter_us: AtomicU64,
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub struct TargetStatsSnapshot {
+    pub sent_packets: u64,
+    pub sent_bytes: u64,
+    pub send_errors: u64,
+    pub skipped_packets: u64,
+    pub reconnects: u64,
+    pub set_aside: bool,
+    pub remote_received: u64,
+    pub remote_lost: u64,
+    pub rtt_us: u64,
+    pub remote_jitter_us: u64,
+}
+
+impl TargetStats {
+    pub fn snapshot(&self) -> TargetStatsSnapshot {
+        let load = |value: &AtomicU64| value.load(Ordering::Relaxed);
+        TargetStatsSnapshot {
+            sent_packets: load(&self.sent_packets),
+            sent_bytes: load(&self.sent_bytes),
+            send_errors: load(&self.send_errors),
+            skipped_packets: load(&self.skipped_packets),
+            reconnects: load(&self.reconnects),
+            set_aside: self.set_aside.load(Ordering::Relaxed),
+            remote_received: load(&self.remote_received),
+            remote_lost: load(&self.remote_lost),
+            rtt_us: load(&self.rtt_us),
+            remote_jitter_us: load(&self.remote_jitter_us),
+        }
+    }
+}
+
+/// The `TargetStats` of every current target, in the order they were added.
+#[derive(Default)]
+pub struct TargetStatsTable {
+    targets: Mutex<Vec<(SocketAddr, Arc<TargetStats>)>>,
+}
+
+impl TargetStatsTable {
+    /// The stats of `addr`, starting fresh ones if it has none yet.
+    pub fn register(&self, addr: SocketAddr) -> Arc<TargetStats> {
+        let mut targets = self.targets.lock().unwrap();
+        if let Some((_, stats)) = targets.iter().find(|(known, _)| *known == addr) {
+            return Arc::clone(stats);
+        }
+        let stats = Arc::new(TargetStats::default());
+        targets.push((addr, Arc::clone(&stats)));
+        stats
+    }
+
+    pub fn get(&self, addr: SocketAddr) -> Option<Arc<TargetStats>> {
+        self.targets
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(known, _)| *known == addr)
+            .map(|(_, stats)| Arc::clone(stats))
+    }
+
+    pub fn remove(&self, addr: SocketAddr) {
+        self.targets
+            .lock()
+            .unwrap()
+            .retain(|(known, _)| *known != addr);
+    }
+
+    pub fn snapshot(&self) -> Vec<(SocketAddr, TargetStatsSnapshot)> {
+        self.targets
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(addr, stats)| (*addr, stats.snapshot()))
+            .collect()
+    }
+}
+
+/// Channels the level meter tracks; any beyond these are ignored.
+pub const METER_CHANNELS: usize = 8;
+
+/// Per-channel RMS and peak since the last `take`, for a single reader.
+#[derive(Default)]
+pub struct LevelMeter {
+    channels: AtomicUsize,
+    frames: AtomicU64,
+    square_sums: [AtomicU64; METER_CHANNELS],
+    peaks: [AtomicU32; METER_CHANNELS],
+}
+
+/// Linear levels where 1.0 is full scale.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ChannelLevel {
+    pub rms: f32,
+    pub peak: f32,
+}
+
+impl LevelMeter {
+    pub fn record(&self, samples: &[i16], channels: usize) {
+        let metered = channels.min(METER_CHANNELS);
+        if metered == 0 {
+            return;
+        }
+        let mut square_sums = [0_u64; METER_CHANNELS];
+        let mut peaks = [0_u32; METER_CHANNELS];
+        for frame in samples.chunks_exact(channels) {
+            for (channel, &sample) in frame.iter().take(metered).enumerate() {
+                let abs = (sample as i32).unsigned_abs();
+                square_sums[channel] += (abs * abs) as u64;
+                peaks[channel] = peaks[channel].max(abs);
+            }
+        }
+        self.channels.store(metered, Ordering::Relaxed);
+        self.frames
+            .fetch_add((samples.len() / channels) as u64, Ordering::Relaxed);
+        for channel in 0..metered {
+            self.square_sums[channel].fetch_add(square_sums[channel], Ordering::Relaxed);
+            self.peaks[channel].fetch_max(peaks[channel], Ordering::Relaxed);
+        }
+    }
+
+    /// Levels since the previous call, then starts a new window; empty when nothing was
+    /// sent in between.
+    pub fn take(&self) -> Vec<ChannelLevel> {
+        let frames = self.frames.swap(0, Ordering::Relaxed);
+        let channels = self.channels.load(Ordering::Relaxed);
+        let levels = (0..channels)
+            .map(|channel| {
+                let square_sum = self.square_sums[channel].swap(0, Ordering::Relaxed);
+                let peak = self.peaks[channel].swap(0, Ordering::Relaxed);
+                ChannelLevel {
+                    rms: (square_sum as f64 / frames.max(1) as f64).sqrt() as f32 / 32768.0,
+                    peak: peak as f32 / 32768.0,
+                }
+            })
+            .collect();
+        if frames == 0 {
+            return Vec::new();
+        }
+        levels
+    }
+}
+
+/// The latest interleaved samples as sent, kept only once someone asks for them.
+#[derive(Default)]
+pub struct AudioTap {
+    capacity: AtomicUsize,
+    samples: Mutex<VecDeque<i16>>,
+}
+
+impl AudioTap {
+    /// Starts keeping the last `capacity` samples.
+    pub fn enable(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    pub fn record(&self, samples: &[i16]) {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if capacity == 0 {
+            return;
+        }
+        let mut kept = self.samples.lock().unwrap();
+        kept.extend(samples);
+        let excess = kept.len().saturating_sub(capacity);
+        kept.drain(..excess);
+    }
+
+    /// The kept samples, oldest first; the next call only sees what is recorded after this.
+    pub fn take(&self) -> Vec<i16> {
+        std::mem::take(&mut *self.samples.lock().unwrap()).into()
+    }
+}
+
+/// A step of the pipeline timed by `StageTimes`, in the order audio goes through them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// The whole capture callback, conversion and queueing included.
+    Capture,
+    /// Turning the device samples into PCM16 inside the callback.
+    Convert,
+    /// Built-in DSP and effect plugins.
+    Dsp,
+    /// The codec plugin or PCM16 serialization, with the packet header.
+    Encode,
+    /// PTP timestamps, flags and sealing of the built packet.
+    Packetize,
+    /// Handing a batch to the sockets.
+    Send,
+}
+
+impl Stage {
+    /// cbindgen:ignore
+    pub const ALL: [Stage; 6] = [
+        Stage::Capture,
+        Stage::Convert,
+        Stage::Dsp,
+        Stage::Encode,
+        Stage::Packetize,
+        Stage::Send,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stage::Capture => "capture",
+            Stage::Convert => "convert",
+            Stage::Dsp => "dsp",
+            Stage::Encode => "encode",
+            Stage::Packetize => "packetize",
+            Stage::Send => "send",
+        }
+    }
+}
+
+/// Time spent in each `Stage`, summed in nanoseconds over its calls.
+#[derive(Default)]
+pub struct StageTimes {
+    ns: [AtomicU64; Stage::ALL.len()],
+    calls: [AtomicU64; Stage::ALL.len()],
+}
+
+impl StageTimes {
+    pub fn record(&self, stage: Stage, elapsed: Duration) {
+        self.ns[stage as usize].fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        self.calls[stage as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StageSnapshot {
+        StageSnapshot {
+            ns: self.ns.each_ref().map(|ns| ns.load(Ordering::Relaxed)),
+            calls: self
+                .calls
+                .each_ref()
+                .map(|calls| calls.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StageSnapshot {
+    ns: [u64; Stage::ALL.len()],
+    calls: [u64; Stage::ALL.len()],
+}
+
+impl StageSnapshot {
+    /// Time and calls since `earlier`, taken from the same `StageTimes`.
+    pub fn since(&self, earlier: &StageSnapshot) -> StageSnapshot {
+        StageSnapshot {
+            ns: std::array::from_fn(|i| self.ns[i].saturating_sub(earlier.ns[i])),
+            calls: std::array::from_fn(|i| self.calls[i].saturating_sub(earlier.calls[i])),
+        }
+    }
+
+    pub fn total(&self, stage: Stage) -> Duration {
+        Duration::from_nanos(self.ns[stage as usize])
+    }
+
+    /// Mean time per call, or zero before the first.
+    pub fn mean(&self, stage: Stage) -> Duration {
+        let calls = self.calls[stage as usize];
+        Duration::from_nanos(self.ns[stage as usize].checked_div(calls).unwrap_or(0))
+    }
+
+    /// The totals in the folded-stack format flame graph tools read (`inferno`,
+    /// `flamegraph.pl`, speedscope): one `thread;stage nanoseconds` line per stage, with
+    /// the callback's own time as `capture;enqueue`.
+    pub fn folded(&self) -> String {
+        let ns = |stage: Stage| self.ns[stage as usize];
+        let lines = [
+            ("capture;convert", ns(Stage::Convert)),
+            (
+                "capture;enqueue",
+                ns(Stage::Capture).saturating_sub(ns(Stage::Convert)),
+            ),
+            ("send-loop;dsp", ns(Stage::Dsp)),
+            ("send-loop;encode", ns(Stage::Encode)),
+            ("send-loop;packetize", ns(Stage::Packetize)),
+            ("send-loop;send", ns(Stage::Send)),
+        ];
+        lines
+            .iter()
+            .filter(|(_, ns)| *ns > 0)
+            .map(|(stack, ns)| format!("{stack} {ns}\n"))
+            .collect()
+    }
+}
+
+/// Exact below 32 µs, then 16 sub-buckets per power of two (about 6% resolution).
+const HIST_LINEAR: u64 = 32;
+const HIST_SUB_BITS: u32 = 4;
+const HIST_MAX_US: u64 = (1 << 27) - 1;
+const HIST_BUCKETS: usize = 384;
+
+/// Lock-free log-linear histogram of durations in microseconds, in the spirit of
+/// HdrHistogram. Values above ~134 s land in the last bucket.
+pub struct LatencyHistogram {
+    buckets: Box<[AtomicU64]>,
+    max_us: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: (0..HIST_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            max_us: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, us: u64) {
+        self.buckets[bucket_index(us)].fetch_add(1, Ordering::Relaxed);
+        self.max_us.fetch_max(us, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            counts: self
+                .buckets
+                .iter()
+                .map(|bucket| bucket.load(Ordering::Relaxed))
+                .collect(),
+            max_us: self.max_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn bucket_index(us: u64) -> usize {
+    let us = us.min(HIST_MAX_US);
+    if us < HIST_LINEAR {
+        return us as usize;
+    }
+    let shift = us.ilog2() - HIST_SUB_BITS;
+    ((shift as u64) << HIST_SUB_BITS) as usize + (us >> shift) as usize
+}
+
+/// Largest value that falls into bucket `index`.
+fn bucket_upper(index: usize) -> u64 {
+    let index = index as u64;
+    if index < HIST_LINEAR {
+        return index;
+    }
+    let shift = (index >> HIST_SUB_BITS) - 1;
+    let mantissa = (index & ((1 << HIST_SUB_BITS) - 1)) + (1 << HIST_SUB_BITS);
+    ((mantissa + 1) << shift) - 1
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HistogramSnapshot {
+    counts: Vec<u64>,
+    max_us: u64,
+}
+
+impl HistogramSnapshot {
+    /// Counts recorded since `earlier`, taken from the same histogram.
+    pub fn since(&self, earlier: &HistogramSnapshot) -> HistogramSnapshot {
+        HistogramSnapshot {
+            counts: self
+                .counts
+                .iter()
+                .enumerate()
+                .map(|(index, count)| {
+                    count.saturating_sub(earlier.counts.get(index).copied().unwrap_or(0))
+                })
+                .collect(),
+            max_us: self.max_us,
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile (0.0..=1.0), in µs.
+    pub fn quantile_us(&self, q: f64) -> u64 {
+        let total = self.count();
+        if total == 0 {
+            return 0;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_upper(index).min(self.max_us);
+            }
+        }
+        self.max_us
+    }
+
+    pub fn percentiles(&self) -> Percentiles {
+        let ms = |us: u64| us as f64 / 1000.0;
+        Percentiles {
+            p50_ms: ms(self.quantile_us(0.50)),
+            p95_ms: ms(self.quantile_us(0.95)),
+            p99_ms: ms(self.quantile_us(0.99)),
+            max_ms: ms(self.quantile_us(1.0)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub struct Percentiles {
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Cumulative delivery counts kept by a receiver and reported back to the sender.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SequenceCounts {
+    /// Every packet that arrived, duplicates included.
+    pub received: u64,
+    /// Sequence numbers skipped and not (yet) filled in by a late packet.
+    pub lost: u64,
+    /// Packets that arrived after a higher sequence number.
+    pub reordered: u64,
+    pub duplicates: u64,
+}
+
+/// Packets this far behind the highest sequence number can still be told apart from
+/// duplicates; anything further back is taken as a restarted sender.
+const SEQ_WINDOW: u32 = 1024;
+/// A forward jump larger than this is also taken as a restarted sender.
+const SEQ_MAX_JUMP: u32 = 1 << 15;
+
+/// Tracks loss, reordering and duplicates from packet sequence numbers, RTP style.
+pub struct SequenceTracker {
+    highest: Option<u32>,
+    /// `seq + 1` of the packet last seen in each slot, 0 when empty.
+    seen: Box<[u64]>,
+    expected: u64,
+    unique: u64,
+    lost_before: u64,
+    counts: SequenceCounts,
+}
+
+impl Default for SequenceTracker {
+    fn default() -> Self {
+        Self {
+            highest: None,
+            seen: vec![0; SEQ_WINDOW as usize].into_boxed_slice(),
+            expected: 0,
+            unique: 0,
+            lost_before: 0,
+            counts: SequenceCounts::default(),
+        }
+    }
+}
+
+impl SequenceTracker {
+    pub fn record(&mut self, seq: u32) {
+        self.counts.received += 1;
+        let slot = (seq % SEQ_WINDOW) as usize;
+        let Some(highest) = self.highest else {
+            self.restart(seq);
+            return;
+        };
+        let ahead = seq.wrapping_sub(highest);
+        let behind = highest.wrapping_sub(seq);
+        if ahead > SEQ_MAX_JUMP && behind >= SEQ_WINDOW {
+            self.lost_before += self.expected.saturating_sub(self.unique);
+            self.restart(seq);
+            return;
+        }
+        if self.seen[slot] == seq as u64 + 1 {
+            self.counts.duplicates += 1;
+            return;
+        }
+        self.seen[slot] = seq as u64 + 1;
+        self.unique += 1;
+        if ahead != 0 && ahead <= SEQ_MAX_JUMP {
+            self.expected += ahead as u64;
+            self.highest = Some(seq);
+        } else {
+            self.counts.reordered += 1;
+        }
+    }
+
+    pub fn counts(&self) -> SequenceCounts {
+        SequenceCounts {
+            lost: self.lost_before + self.expected.saturating_sub(self.unique),
+            ..self.counts
+        }
+    }
+
+    fn restart(&mut self, seq: u32) {
+        self.seen.fill(0);
+        self.seen[(seq % SEQ_WINDOW) as usize] = seq as u64 + 1;
+        self.highest = Some(seq);
+        self.expected = 1;
+        self.unique = 1;
+    }
+}
+
+#[derive(Default)]
+pub struct ReceiverStats {
+    pub rx_packets: AtomicU64,
+    pub rx_bytes: AtomicU64,
+    pub parse_errors: AtomicU64,
+    pub payload_mismatch: AtomicU64,
+    pub probe_bytes: AtomicU64,
+    pub auth_failures: AtomicU64,
+    pub replays: AtomicU64,
+    pub discontinuities: AtomicU64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub struct ReceiverStatsSnapshot {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub parse_errors: u64,
+    pub payload_mismatch: u64,
+    pub buffered_frames: u64,
+    pub target_frames: u64,
+    pub pushed: u64,
+    pub played: u64,
+    pub missing: u64,
+    pub overflow_dropped: u64,
+    pub lost: u64,
+    pub reordered: u64,
+    pub duplicates: u64,
+    /// Interarrival jitter; only `AudioReceiver` knows arrival times, so 0 elsewhere.
+    pub jitter_us: u64,
+    pub probe_bytes: u64,
+    /// Packets dropped for a wrong or missing key, or for failing authentication.
+    pub auth_failures: u64,
+    /// Authentic packets dropped because they were already played or are too old to tell;
+    /// network duplicates of a sealed stream land here too.
+    pub replays: u64,
+    /// Clock exchanges with the sender; only `AudioReceiver` pings it, so 0 elsewhere.
+    pub rtt_us: u64,
+    /// Sender clock minus receiver clock.
+    pub clock_offset_us: i64,
+    /// How many nanoseconds per second the sender clock gains; 0 until a few seconds of
+    /// exchanges.
+    pub clock_drift_ppb: i64,
+    /// `send_time_us` of the newest packet to its arrival, corrected for the offset.
+    pub transit_us: u64,
+    /// Frames dropped for missing their slot on the PTP domain clock.
+    pub late: u64,
+    /// Packets flagged with `FLAG_DISCONTINUITY`: the sender dropped late audio before each.
+    pub discontinuities: u64,
+}
@@ -0,0 +1,598 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tracing::{warn, Span};
+use windows_sender::stats::{
+    HistogramSnapshot, Percentiles, SenderStats, SenderStatsSnapshot, Stage, StageSnapshot,
+    TargetStatsSnapshot,
+};
+use windows_sender::timer::{Resolution, Sleeper};
+
+use crate::console::{self, Tint};
+use crate::logfile::RotatingFile;
+use crate::webhook::Webhook;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Metric groups a stats line can include.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsGroup {
+    /// Packet rate, bitrate, queue backlog and capture drops.
+    Network,
+    /// Capture rate, average level, share of non-silent samples and device glitch flags.
+    Audio,
+    /// Stage timings and latency percentiles.
+    Perf,
+}
+
+impl StatsGroup {
+    pub const ALL: [StatsGroup; 3] = [StatsGroup::Network, StatsGroup::Audio, StatsGroup::Perf];
+}
+
+/// Where and how one session reports its stats.
+#[derive(Clone, Debug)]
+pub struct StatsLogger {
+    pub session: String,
+    /// Prefix for text lines; empty when only one session runs.
+    pub label: String,
+    pub frame_ms: f32,
+    pub format: StatsFormat,
+    /// Lines are appended here instead of stdout.
+    pub file: Option<PathBuf>,
+    /// False when the terminal belongs to the dashboard and only the webhook wants stats.
+    pub print: bool,
+    pub webhook: Option<Webhook>,
+    pub interval: Duration,
+    pub groups: Vec<StatsGroup>,
+}
+
+/// Counters and histograms as of the end of the previous interval.
+struct Sample {
+    at: Instant,
+    stats: SenderStatsSnapshot,
+    /// Peak backlog since the previous sample.
+    backlog_max: u64,
+    capsend: HistogramSnapshot,
+    sock: HistogramSnapshot,
+    playout: HistogramSnapshot,
+    send_jitter: HistogramSnapshot,
+    stages: StageSnapshot,
+    targets: Vec<(SocketAddr, TargetStatsSnapshot)>,
+}
+
+impl Sample {
+    fn take(stats: &SenderStats) -> Self {
+        Self {
+            at: Instant::now(),
+            stats: stats.snapshot(),
+            backlog_max: stats.backlog_high_water.swap(0, Ordering::Relaxed),
+            capsend: stats.capture_to_send_hist.snapshot(),
+            sock: stats.socket_send_hist.snapshot(),
+            playout: stats.capture_to_playout_hist.snapshot(),
+            send_jitter: stats.send_jitter_hist.snapshot(),
+            stages: stats.stages.snapshot(),
+            targets: stats.targets.snapshot(),
+        }
+    }
+}
+
+/// One interval: derived rates for the selected groups plus the cumulative counters
+/// they came from.
+#[derive(Serialize)]
+struct StatsRecord<'a> {
+    ts_ms: u64,
+    session: &'a str,
+    frame_ms: f32,
+    interval_ms: u64,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    network: Option<NetworkStats>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    audio: Option<AudioStats>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    perf: Option<PerfStats>,
+    /// Listed with the network group when there is more than one target.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    targets: Vec<TargetRecord>,
+    totals: SenderStatsSnapshot,
+}
+
+/// The delivery percentages come from receiver reports and are missing without them.
+#[derive(Serialize)]
+struct NetworkStats {
+    tx_pps: f64,
+    tx_kbps: f64,
+    drops: u64,
+    backlog: usize,
+    /// Most chunks queued at any point of the interval.
+    backlog_max: u64,
+    /// Audio dropped for being too late to send; see `--late-audio`.
+    late_ms: f64,
+    /// Audio dropped for going over `--max-kbps`.
+    capped_ms: f64,
+    /// Audio held between capture and the sockets; see `--max-buffered-ms`.
+    buffered_ms: f64,
+    /// Approximate heap taken by the buffers holding it.
+    buffer_kb: f64,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    delivery: Option<DeliveryStats>,
+}
+
+/// One target's share of the interval. Loss and round trip come from its own reports.
+#[derive(Serialize)]
+struct TargetRecord {
+    addr: SocketAddr,
+    tx_kbps: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    loss_pct: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rtt_ms: Option<f64>,
+    send_errors: u64,
+    /// Audio packets it missed while set aside.
+    skipped: u64,
+    set_aside: bool,
+}
+
+/// Mean microseconds per call of each pipeline stage; see `Stage`.
+#[derive(Serialize)]
+struct StageMeans {
+    capture: f64,
+    convert: f64,
+    dsp: f64,
+    encode: f64,
+    packetize: f64,
+    send: f64,
+}
+
+impl StageMeans {
+    fn new(stages: &StageSnapshot) -> Self {
+        let mean = |stage| stages.mean(stage).as_secs_f64() * 1e6;
+        Self {
+            capture: mean(Stage::Capture),
+            convert: mean(Stage::Convert),
+            dsp: mean(Stage::Dsp),
+            encode: mean(Stage::Encode),
+            packetize: mean(Stage::Packetize),
+            send: mean(Stage::Send),
+        }
+    }
+}
+
+/// Loss is relative to the packets the receiver expected; reorder and duplicates to the
+/// packets it got.
+#[derive(Serialize)]
+struct DeliveryStats {
+    loss_pct: f64,
+    reorder_pct: f64,
+    dup_pct: f64,
+}
+
+/// The buffer flag counts cover the interval and stay 0 outside WASAPI capture.
+#[derive(Serialize)]
+struct AudioStats {
+    cap_chunks_per_s: f64,
+    cap_samples_per_s: f64,
+    avg_abs: f64,
+    active_pct: f64,
+    cap_discontinuities: u64,
+    cap_silent: u64,
+    cap_timestamp_errors: u64,
+    /// How fast the capture device clock runs against the monotonic clock.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_drift_ppm: Option<f64>,
+}
+
+/// Averages over the interval; latencies are p50/p95/p99/max over the interval.
+///
+/// The receiver-side figures are only present when reports came back during the interval.
+#[derive(Serialize)]
+struct PerfStats {
+    capq_ms: f64,
+    capsend_ms: f64,
+    pkt_ms: f64,
+    sock_ms: f64,
+    capsend_latency: Percentiles,
+    sock_latency: Percentiles,
+    /// Deviation of send intervals from the frame duration.
+    send_jitter: Percentiles,
+    stages_us: StageMeans,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rx_jitter_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    playout_latency: Option<Percentiles>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rtt_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clock_offset_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clock_drift_ppm: Option<f64>,
+}
+
+impl StatsRecord<'_> {
+    /// With `color`, the parts that point at trouble stand out.
+    fn text(&self, label: &str, color: bool) -> String {
+        let trouble = |text: String| Tint::Yellow.paint(text, color);
+        let mut line = format!(
+            "{label}{} frame={}ms",
+            Tint::Cyan.paint("stats", color),
+            self.frame_ms
+        );
+        if let Some(net) = &self.network {
+            line += &format!(" tx={:.0}pps {:.1}kbps ", net.tx_pps, net.tx_kbps);
+            line += &match net.drops {
+                0 => "drop=0".to_string(),
+                drops => trouble(format!("drop={drops}")),
+            };
+            line += &format!(" q={} qmax={}", net.backlog, net.backlog_max);
+            if net.late_ms > 0.0 {
+                line += &trouble(format!(" late={:.0}ms", net.late_ms));
+            }
+            if net.capped_ms > 0.0 {
+                line += &trouble(format!(" capped={:.0}ms", net.capped_ms));
+            }
+            line += &format!(" buf={:.0}ms heap={:.0}KB", net.buffered_ms, net.buffer_kb);
+            if let Some(delivery) = &net.delivery {
+                let loss = format!(" loss={:.1}%", delivery.loss_pct);
+                line += &if delivery.loss_pct > 0.0 {
+                    trouble(loss)
+                } else {
+                    loss
+                };
+                line += &format!(
+                    " reorder={:.1}% dup={:.1}%",
+                    delivery.reorder_pct, delivery.dup_pct
+                );
+            }
+        }
+        if let Some(audio) = &self.audio {
+            line += &format!(
+                " cap={:.0}chunks/s {:.0}samples/s avgAbs={:.1} active={:.1}%",
+                audio.cap_chunks_per_s, audio.cap_samples_per_s, audio.avg_abs, audio.active_pct
+            );
+            if audio.cap_discontinuities + audio.cap_silent + audio.cap_timestamp_errors > 0 {
+                line += &trouble(format!(
+                    " glitch disc={} silent={} tsErr={}",
+                    audio.cap_discontinuities, audio.cap_silent, audio.cap_timestamp_errors
+                ));
+            }
+            if let Some(drift) = audio.device_drift_ppm {
+                line += &format!(" devDrift={drift:+.1}ppm");
+            }
+        }
+        if let Some(perf) = &self.perf {
+            let latency = |p: &Percentiles| {
+                format!(
+                    "{:.1}/{:.1}/{:.1}/{:.1}ms",
+                    p.p50_ms, p.p95_ms, p.p99_ms, p.max_ms
+                )
+            };
+            line += &format!(
+                " perf capQ={:.3}ms capSend={:.3}ms pkt={:.3}ms sock={:.3}ms lat capSend={} sock={}",
+                perf.capq_ms,
+                perf.capsend_ms,
+                perf.pkt_ms,
+                perf.sock_ms,
+                latency(&perf.capsend_latency),
+                latency(&perf.sock_latency),
+            );
+            line += &format!(" jitter send={}", latency(&perf.send_jitter));
+            let stages = &perf.stages_us;
+            line += &format!(
+                " stages cap={:.1} conv={:.1} dsp={:.1} enc={:.1} pack={:.1} send={:.1}us",
+                stages.capture,
+                stages.convert,
+                stages.dsp,
+                stages.encode,
+                stages.packetize,
+                stages.send
+            );
+            if let Some(rx_jitter) = perf.rx_jitter_ms {
+                line += &format!(" rx={rx_jitter:.1}ms");
+            }
+            if let Some(playout) = &perf.playout_latency {
+                line += &format!(" e2e={}", latency(playout));
+            }
+            if let (Some(rtt), Some(offset)) = (perf.rtt_ms, perf.clock_offset_ms) {
+                line += &format!(" rtt={rtt:.1}ms offset={offset:+.1}ms");
+            }
+            if let Some(drift) = perf.clock_drift_ppm {
+                line += &format!(" drift={drift:+.1}ppm");
+            }
+        }
+        if self.totals.restarts > 0 {
+            line += &trouble(format!(" restarts={}", self.totals.restarts));
+        }
+        for target in &self.targets {
+            line += &format!(
+                "\n{label}  target {} tx={:.1}kbps",
+                target.addr, target.tx_kbps
+            );
+            match target.loss_pct {
+                Some(loss) if loss > 0.0 => line += &trouble(format!(" loss={loss:.1}%")),
+                Some(loss) => line += &format!(" loss={loss:.1}%"),
+                None => {}
+            }
+            if let Some(rtt) = target.rtt_ms {
+                line += &format!(" rtt={rtt:.1}ms");
+            }
+            if target.send_errors > 0 {
+                line += &trouble(format!(" errors={}", target.send_errors));
+            }
+            if target.skipped > 0 {
+                line += &trouble(format!(" skipped={}", target.skipped));
+            }
+            if target.set_aside {
+                line += &trouble(" reconnecting".to_string());
+            }
+        }
+        line
+    }
+}
+
+impl StatsLogger {
+    pub fn spawn<B>(
+        &self,
+        stats: Arc<SenderStats>,
+        backlog: B,
+        running: Arc<AtomicBool>,
+    ) -> thread::JoinHandle<()>
+    where
+        B: Fn() -> usize + Send + 'static,
+    {
+        let logger = self.clone();
+        let span = Span::current();
+        thread::spawn(move || {
+            let _span = span.entered();
+            let mut out = logger.print.then(|| logger.open());
+            // A stats file takes whole lines; the terminal gets them on the status row.
+            let on_terminal = logger.file.is_none();
+            let status_row =
+                on_terminal && logger.format == StatsFormat::Text && console::status_row();
+            let color = on_terminal && console::stdout_color();
+            let mut last = Sample::take(&stats);
+            // Keeps the report interval on time on Windows.
+            let _resolution = Resolution::fine();
+            let sleeper = Sleeper::new();
+
+            loop {
+                sleeper.sleep(logger.interval);
+                if !running.load(Ordering::Relaxed) {
+                    break;
+                }
+                let now = Sample::take(&stats);
+                let record = logger.record(&last, &now, backlog());
+                if let Some(webhook) = &logger.webhook {
+                    webhook.stats(
+                        serde_json::to_value(&record).expect("stats record serializes to JSON"),
+                    );
+                }
+                last = now;
+                let log_file = RotatingFile::shared();
+                if out.is_none() && log_file.is_none() {
+                    continue;
+                }
+                let line = |color| match logger.format {
+                    StatsFormat::Text => record.text(&logger.label, color),
+                    StatsFormat::Json => {
+                        serde_json::to_string(&record).expect("stats record serializes to JSON")
+                    }
+                };
+                if let Some(log_file) = log_file {
+                    log_file.write_line(&line(false));
+                }
+                let Some(out) = &mut out else {
+                    continue;
+                };
+                let written = if status_row {
+                    console::show_status(&logger.session, &line(color))
+                } else {
+                    writeln!(out, "{}", line(color)).and_then(|_| out.flush())
+                };
+                if let Err(err) = written {
+                    warn!("failed to write stats: {err}");
+                }
+            }
+            if status_row {
+                let _ = console::end_status(&logger.session);
+            }
+        })
+    }
+
+    /// Opens the stats file, falling back to stdout when it cannot be written.
+    fn open(&self) -> Box<dyn Write + Send> {
+        let Some(path) = &self.file else {
+            return Box::new(io::stdout());
+        };
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Box::new(io::LineWriter::new(file)),
+            Err(err) => {
+                warn!(
+                    "failed to open stats file {}: {err}; writing stats to stdout",
+                    path.display()
+                );
+                Box::new(io::stdout())
+            }
+        }
+    }
+
+    fn record(&self, last: &Sample, now: &Sample, backlog: usize) -> StatsRecord<'_> {
+        let elapsed = now.at - last.at;
+        let capsend_latency = now.capsend.since(&last.capsend).percentiles();
+        let sock_latency = now.sock.since(&last.sock).percentiles();
+        let send_jitter = now.send_jitter.since(&last.send_jitter).percentiles();
+        let stages_us = StageMeans::new(&now.stages.since(&last.stages));
+        let playout = now.playout.since(&last.playout);
+        let playout_latency = (playout.count() > 0).then(|| playout.percentiles());
+        let backlog_max = now.backlog_max.max(backlog as u64);
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let targets = if self.groups.contains(&StatsGroup::Network) && now.targets.len() > 1 {
+            target_records(&last.targets, &now.targets, secs)
+        } else {
+            Vec::new()
+        };
+        let (last, now) = (&last.stats, &now.stats);
+        let per_sec = |now: u64, last: u64| now.saturating_sub(last) as f64 / secs;
+        let avg = |sum_now: u64, sum_last: u64, count_now: u64, count_last: u64, scale: f64| {
+            let count = count_now.saturating_sub(count_last);
+            if count > 0 {
+                sum_now.saturating_sub(sum_last) as f64 / count as f64 / scale
+            } else {
+                0.0
+            }
+        };
+        let reported = now.feedback_reports > last.feedback_reports;
+        let received = now.remote_received.saturating_sub(last.remote_received);
+        let delivery = (received > 0).then(|| {
+            let lost = now.remote_lost.saturating_sub(last.remote_lost);
+            let pct = |count: u64, of: u64| count as f64 * 100.0 / of.max(1) as f64;
+            DeliveryStats {
+                loss_pct: pct(lost, received + lost),
+                reorder_pct: pct(
+                    now.remote_reordered.saturating_sub(last.remote_reordered),
+                    received,
+                ),
+                dup_pct: pct(
+                    now.remote_duplicates.saturating_sub(last.remote_duplicates),
+                    received,
+                ),
+            }
+        });
+        let samples = now.captured_samples.saturating_sub(last.captured_samples);
+        let (avg_abs, active_pct) = if samples > 0 {
+            (
+                now.captured_abs_sum.saturating_sub(last.captured_abs_sum) as f64 / samples as f64,
+                now.captured_nonzero_samples
+                    .saturating_sub(last.captured_nonzero_samples) as f64
+                    * 100.0
+                    / samples as f64,
+            )
+        } else {
+            (0.0, 0.0)
+        };
+
+        let has = |group| self.groups.contains(&group);
+        StatsRecord {
+            ts_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_millis() as u64)
+                .unwrap_or(0),
+            session: &self.session,
+            frame_ms: self.frame_ms,
+            interval_ms: elapsed.as_millis() as u64,
+            network: has(StatsGroup::Network).then(|| NetworkStats {
+                tx_pps: per_sec(now.sent_packets, last.sent_packets),
+                tx_kbps: per_sec(now.sent_bytes, last.sent_bytes) * 8.0 / 1000.0,
+                drops: now.capture_drops.saturating_sub(last.capture_drops),
+                backlog,
+                backlog_max,
+                late_ms: now.late_dropped_us.saturating_sub(last.late_dropped_us) as f64 / 1000.0,
+                capped_ms: now.capped_us.saturating_sub(last.capped_us) as f64 / 1000.0,
+                buffered_ms: now.buffered_audio_us as f64 / 1000.0,
+                buffer_kb: now.buffer_bytes as f64 / 1024.0,
+                delivery,
+            }),
+            audio: has(StatsGroup::Audio).then(|| AudioStats {
+                cap_chunks_per_s: per_sec(now.captured_chunks, last.captured_chunks),
+                cap_samples_per_s: per_sec(now.captured_samples, last.captured_samples),
+                avg_abs,
+                active_pct,
+                cap_discontinuities: now
+                    .capture_discontinuities
+                    .saturating_sub(last.capture_discontinuities),
+                cap_silent: now.capture_silent.saturating_sub(last.capture_silent),
+                cap_timestamp_errors: now
+                    .capture_timestamp_errors
+                    .saturating_sub(last.capture_timestamp_errors),
+                device_drift_ppm: (now.device_drift_ppb != 0)
+                    .then(|| now.device_drift_ppb as f64 / 1000.0),
+            }),
+            perf: has(StatsGroup::Perf).then(|| PerfStats {
+                capq_ms: avg(
+                    now.capture_queue_us_sum,
+                    last.capture_queue_us_sum,
+                    now.capture_queue_count,
+                    last.capture_queue_count,
+                    1000.0,
+                ),
+                capsend_ms: avg(
+                    now.capture_to_send_us_sum,
+                    last.capture_to_send_us_sum,
+                    now.capture_to_send_count,
+                    last.capture_to_send_count,
+                    1000.0,
+                ),
+                pkt_ms: avg(
+                    now.packet_build_us_sum,
+                    last.packet_build_us_sum,
+                    now.packet_build_count,
+                    last.packet_build_count,
+                    1000.0,
+                ),
+                sock_ms: avg(
+                    now.socket_send_us_sum,
+                    last.socket_send_us_sum,
+                    now.socket_send_count,
+                    last.socket_send_count,
+                    1000.0,
+                ),
+                capsend_latency,
+                sock_latency,
+                send_jitter,
+                stages_us,
+                rx_jitter_ms: reported.then(|| now.remote_jitter_us as f64 / 1000.0),
+                playout_latency,
+                rtt_ms: reported.then(|| now.rtt_us as f64 / 1000.0),
+                clock_offset_ms: reported.then(|| now.clock_offset_us as f64 / 1000.0),
+                // Stays 0 until enough exchanges span the estimate.
+                clock_drift_ppm: (now.clock_drift_ppb != 0)
+                    .then(|| now.clock_drift_ppb as f64 / 1000.0),
+            }),
+            targets,
+            totals: *now,
+        }
+    }
+}
+
+fn target_records(
+    last: &[(SocketAddr, TargetStatsSnapshot)],
+    now: &[(SocketAddr, TargetStatsSnapshot)],
+    secs: f64,
+) -> Vec<TargetRecord> {
+    now.iter()
+        .map(|(addr, now)| {
+            // A target added during the interval counts from zero.
+            let last = last
+                .iter()
+                .find(|(known, _)| known == addr)
+                .map(|(_, last)| *last)
+                .unwrap_or_default();
+            let received = now.remote_received.saturating_sub(last.remote_received);
+            let lost = now.remote_lost.saturating_sub(last.remote_lost);
+            TargetRecord {
+                addr: *addr,
+                tx_kbps: now.sent_bytes.saturating_sub(last.sent_bytes) as f64 * 8.0
+                    / 1000.0
+                    / secs,
+                loss_pct: (received > 0).then(|| lost as f64 * 100.0 / (received + lost) as f64),
+                rtt_ms: (now.rtt_us > 0).then(|| now.rtt_us as f64 / 1000.0),
+                send_errors: now.send_errors.saturating_sub(last.send_errors),
+                skipped: now.skipped_packets.saturating_sub(last.skipped_packets),
+                set_aside: now.set_aside,
+            }
+        })
+        .collect()
+}
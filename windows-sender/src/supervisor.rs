@@ -0,0 +1,644 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc as std_mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use tracing::{debug, info, info_span, warn};
+
+use crate::archive::OpusArchive;
+use crate::capture::{
+    start_capture, CaptureChunk, CaptureGuard, CaptureQueue, CaptureSetup, QueueOverflow,
+    SourceKind, DEFAULT_QUEUE_DEPTH,
+};
+use crate::dsp::SharedDspSettings;
+use crate::error::{error_chain, CaptureError, Error};
+use crate::feedback::FeedbackTracker;
+use crate::journal::{EventJournal, EventKind};
+use crate::ndi::NdiSource;
+use crate::plugin::Plugin;
+use crate::ptp::{PtpClock, PtpSource};
+use crate::sched::Scheduling;
+use crate::sender::{send_loop, LatePolicy, StreamFormat, StreamPosition};
+use crate::stats::SenderStats;
+use crate::tee::Tee;
+use crate::transport::TargetSet;
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(200);
+const MAX_RESTARTS: usize = 5;
+const RESTART_WINDOW: Duration = Duration::from_secs(60);
+const AUDIBLE_DROP_BURST: u64 = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Stage {
+    Capture,
+    Send,
+}
+
+impl Stage {
+    fn as_str(self) -> &'static str {
+        match self {
+            Stage::Capture => "capture",
+            Stage::Send => "send",
+        }
+    }
+}
+
+/// The source streaming now and its format.
+pub(crate) struct Started {
+    pub source: SourceKind,
+    pub format: StreamFormat,
+    pub source_name: String,
+}
+
+/// A request to move the stream to another source, answered with its name.
+pub(crate) struct SourceSwitch {
+    pub source: SourceKind,
+    pub reply: std_mpsc::SyncSender<Result<String, Error>>,
+}
+
+/// Owns the capture and send stages of one sender.
+///
+/// With a watchdog timeout it restarts a stage that dies or stops making progress;
+/// without one, the first failure ends the sender as before.
+pub(crate) struct Supervisor {
+    pub source: SourceKind,
+    pub desktop_device: Option<String>,
+    pub source_file: Option<PathBuf>,
+    pub frame_ms: f32,
+    pub watchdog: Option<Duration>,
+    pub plugins: Vec<Plugin>,
+    pub tx: Sender<CaptureChunk>,
+    pub rx: Receiver<CaptureChunk>,
+    pub stats: Arc<SenderStats>,
+    pub feedback: Arc<FeedbackTracker>,
+    pub journal: Arc<EventJournal>,
+    pub targets: TargetSet,
+    pub dsp: Arc<SharedDspSettings>,
+    pub running: Arc<AtomicBool>,
+    pub paused: Arc<AtomicBool>,
+    /// Bytes sealing adds to each packet; the targets seal them.
+    pub seal_overhead: usize,
+    pub ptp: Option<PtpSource>,
+    pub pace: bool,
+    pub pause_notice: bool,
+    /// Names of sync markers waiting to go out with the next capture chunk.
+    pub markers: Receiver<String>,
+    pub switches: Receiver<SourceSwitch>,
+    pub start_at: Option<SystemTime>,
+    pub late_policy: LatePolicy,
+    pub max_buffered: Option<Duration>,
+    pub max_kbps: Option<u32>,
+    pub tee: Option<Arc<Tee>>,
+    pub ndi: Option<Arc<NdiSource>>,
+    pub output: Option<Arc<OpusArchive>>,
+    pub passthrough: bool,
+    pub encode_workers: usize,
+    pub queue_overflow: QueueOverflow,
+    pub scheduling: Scheduling,
+}
+
+struct CaptureStage {
+    running: Arc<AtomicBool>,
+    guard: CaptureGuard,
+    sample_rate: u32,
+    channels: usize,
+    source_name: String,
+}
+
+impl CaptureStage {
+    fn is_finished(&self) -> bool {
+        match &self.guard {
+            CaptureGuard::Thread(handle) => handle.is_finished(),
+            #[cfg(mic_backend)]
+            CaptureGuard::Mic(_) => false,
+        }
+    }
+
+    fn stop(self) {
+        self.running.store(false, Ordering::Relaxed);
+        match self.guard {
+            CaptureGuard::Thread(handle) => {
+                let _ = handle.join();
+            }
+            #[cfg(mic_backend)]
+            CaptureGuard::Mic(_) => {}
+        }
+    }
+}
+
+struct SendStage {
+    running: Arc<AtomicBool>,
+    handle: thread::JoinHandle<Result<StreamPosition, Error>>,
+}
+
+impl SendStage {
+    fn stop(self) -> Result<(), Error> {
+        self.hand_over().map(|_| ())
+    }
+
+    /// Stops the send loop and returns where it left the stream, if it was still running.
+    fn hand_over(self) -> Result<StreamPosition, Error> {
+        self.running.store(false, Ordering::Relaxed);
+        self.handle
+            .join()
+            .unwrap_or(Err(CaptureError::ChannelClosed.into()))
+    }
+}
+
+/// Remembers when each stage last made progress.
+struct Progress {
+    sent_packets: u64,
+    sent_at: Instant,
+    nonzero_at_send: u64,
+    captured_chunks: u64,
+    captured_at: Instant,
+}
+
+impl Progress {
+    fn new(stats: &SenderStats) -> Self {
+        let now = Instant::now();
+        Self {
+            sent_packets: stats.sent_packets.load(Ordering::Relaxed),
+            sent_at: now,
+            nonzero_at_send: stats.captured_nonzero_samples.load(Ordering::Relaxed),
+            captured_chunks: stats.captured_chunks.load(Ordering::Relaxed),
+            captured_at: now,
+        }
+    }
+
+    /// The send stage is stalled when no packet went out for `timeout` although the
+    /// capture kept producing non-silent audio. Loopback capture delivers nothing while
+    /// the desktop is idle, so only the microphone is held to a steady chunk rate.
+    fn stalled(
+        &mut self,
+        stats: &SenderStats,
+        source: SourceKind,
+        paused: bool,
+        timeout: Duration,
+    ) -> Option<Stage> {
+        let now = Instant::now();
+        let sent_packets = stats.sent_packets.load(Ordering::Relaxed);
+        let nonzero = stats.captured_nonzero_samples.load(Ordering::Relaxed);
+        let captured_chunks = stats.captured_chunks.load(Ordering::Relaxed);
+        if sent_packets != self.sent_packets || paused {
+            self.sent_packets = sent_packets;
+            self.sent_at = now;
+            self.nonzero_at_send = nonzero;
+        }
+        if captured_chunks != self.captured_chunks {
+            self.captured_chunks = captured_chunks;
+            self.captured_at = now;
+        }
+
+        if source == SourceKind::Mic && now - self.captured_at >= timeout {
+            return Some(Stage::Capture);
+        }
+        if now - self.sent_at >= timeout && nonzero > self.nonzero_at_send {
+            return Some(Stage::Send);
+        }
+        None
+    }
+}
+
+/// Gives up on a stage that keeps failing and spaces out the retries.
+#[derive(Default)]
+struct RestartBudget {
+    recent: VecDeque<Instant>,
+}
+
+impl RestartBudget {
+    fn next_delay(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        while let Some(at) = self.recent.front() {
+            if now - *at < RESTART_WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= MAX_RESTARTS {
+            return None;
+        }
+        self.recent.push_back(now);
+        Some(Duration::from_secs(self.recent.len() as u64 - 1))
+    }
+}
+
+impl Supervisor {
+    /// Starts the pipeline on a new thread and reports the capture format on `ready`.
+    pub(crate) fn spawn(
+        self,
+        ready: std_mpsc::SyncSender<Result<Arc<Mutex<Started>>, Error>>,
+    ) -> Result<thread::JoinHandle<Result<(), Error>>, Error> {
+        let span = info_span!("supervisor", source = self.source.as_str());
+        thread::Builder::new()
+            .name("supervisor".to_string())
+            .spawn(move || span.in_scope(|| self.run(ready)))
+            .map_err(|source| Error::Spawn {
+                name: "supervisor",
+                source,
+            })
+    }
+
+    fn run(
+        mut self,
+        ready: std_mpsc::SyncSender<Result<Arc<Mutex<Started>>, Error>>,
+    ) -> Result<(), Error> {
+        let capture = match self.start_capture() {
+            Ok(started) => started,
+            Err(err) => {
+                let _ = ready.send(Err(err));
+                return Ok(());
+            }
+        };
+
+        let format =
+            match StreamFormat::new(capture.sample_rate, capture.channels as u8, self.frame_ms) {
+                Ok(format) => format,
+                Err(err) => {
+                    capture.stop();
+                    let _ = ready.send(Err(err.into()));
+                    return Ok(());
+                }
+            };
+        let send = match self.spawn_send_loop(format, None) {
+            Ok(send) => send,
+            Err(err) => {
+                capture.stop();
+                let _ = ready.send(Err(err));
+                return Ok(());
+            }
+        };
+        self.journal.record(
+            EventKind::Start,
+            format!(
+                "capturing from {} ({} Hz, {} ch)",
+                capture.source_name, format.sample_rate, format.channels
+            ),
+        );
+        let active = Arc::new(Mutex::new(Started {
+            source: self.source,
+            format,
+            source_name: capture.source_name.clone(),
+        }));
+        let _ = ready.send(Ok(Arc::clone(&active)));
+
+        let mut capture = Some(capture);
+        let mut send = Some(send);
+        let result = self.supervise(&mut capture, &mut send, &active);
+        // The send loop goes first; stopping the capture closes the channel it reads.
+        let send_result = send.map(SendStage::stop).unwrap_or(Ok(()));
+        if let Some(capture) = capture {
+            capture.stop();
+        }
+        result.and(send_result)
+    }
+
+    fn supervise(
+        &mut self,
+        capture: &mut Option<CaptureStage>,
+        send: &mut Option<SendStage>,
+        active: &Mutex<Started>,
+    ) -> Result<(), Error> {
+        let mut format = active.lock().unwrap().format;
+        let mut progress = Progress::new(&self.stats);
+        let mut device = capture.as_ref().map(|stage| stage.source_name.clone());
+        let mut capture_budget = RestartBudget::default();
+        let mut send_budget = RestartBudget::default();
+
+        while self.running.load(Ordering::Relaxed) {
+            match self.switches.recv_timeout(CHECK_INTERVAL) {
+                Ok(switch) => {
+                    let result = self.switch_source(switch.source, capture, send, &mut format)?;
+                    if let Ok(name) = &result {
+                        *active.lock().unwrap() = Started {
+                            source: self.source,
+                            format,
+                            source_name: name.clone(),
+                        };
+                        device = Some(name.clone());
+                    }
+                    let _ = switch.reply.send(result);
+                    progress = Progress::new(&self.stats);
+                    continue;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(CHECK_INTERVAL),
+            }
+            self.report_drop_bursts();
+
+            if send
+                .as_ref()
+                .is_some_and(|stage| stage.handle.is_finished())
+            {
+                let result = send.take().map(SendStage::stop).unwrap_or(Ok(()));
+                if self.watchdog.is_none() || !self.running.load(Ordering::Relaxed) {
+                    return result;
+                }
+                let message = match result {
+                    Ok(()) => "send loop stopped".to_string(),
+                    Err(err) => format!("send loop failed: {}", error_chain(&err)),
+                };
+                warn!("{message}; restarting it");
+                self.journal.record(EventKind::SendError, message);
+            }
+            let Some(timeout) = self.watchdog else {
+                // Nothing restarts a capture that ended, so the sender ends with it.
+                if capture.as_ref().is_none_or(CaptureStage::is_finished) {
+                    return Err(CaptureError::ChannelClosed.into());
+                }
+                continue;
+            };
+
+            if send.is_none() {
+                self.backoff(&mut send_budget, Stage::Send)?;
+                match self.targets.reconnect() {
+                    Ok(()) => self
+                        .journal
+                        .record(EventKind::Reconnect, "reconnected to all targets"),
+                    Err(err) => {
+                        let message = format!("failed to reconnect targets: {}", error_chain(&err));
+                        warn!("{message}");
+                        self.journal.record(EventKind::SendError, message);
+                    }
+                }
+                *send = Some(self.spawn_send_loop(format, None)?);
+                self.stats.restarts.fetch_add(1, Ordering::Relaxed);
+                progress = Progress::new(&self.stats);
+            }
+
+            if capture.as_ref().is_none_or(CaptureStage::is_finished) {
+                if let Some(stage) = capture.take() {
+                    stage.stop();
+                    warn!("{} capture stopped; restarting it", self.source.as_str());
+                    self.journal.record(
+                        EventKind::Capture,
+                        format!("{} capture stopped", self.source.as_str()),
+                    );
+                }
+                self.backoff(&mut capture_budget, Stage::Capture)?;
+                match self.start_capture() {
+                    Ok(stage) => {
+                        if stage.sample_rate != format.sample_rate
+                            || stage.channels != format.channels as usize
+                        {
+                            let (sample_rate, channels) = (stage.sample_rate, stage.channels);
+                            stage.stop();
+                            return Err(CaptureError::FormatChanged {
+                                sample_rate,
+                                channels,
+                            }
+                            .into());
+                        }
+                        let message = if device.as_ref() != Some(&stage.source_name) {
+                            device = Some(stage.source_name.clone());
+                            format!("capture device is now {}", stage.source_name)
+                        } else {
+                            format!("capture is back on {}", stage.source_name)
+                        };
+                        self.journal.record(EventKind::Device, message);
+                        *capture = Some(stage);
+                        self.stats.restarts.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(err) => {
+                        let message = format!("capture restart failed: {}", error_chain(&err));
+                        warn!("{message}");
+                        self.journal.record(EventKind::Capture, message);
+                    }
+                }
+                progress = Progress::new(&self.stats);
+                continue;
+            }
+
+            let paused = self.paused.load(Ordering::Relaxed);
+            match progress.stalled(&self.stats, self.source, paused, timeout) {
+                Some(Stage::Capture) => {
+                    let message = format!(
+                        "{} capture delivered no audio for {}s",
+                        self.source.as_str(),
+                        timeout.as_secs()
+                    );
+                    warn!("{message}; restarting it");
+                    self.journal.record(EventKind::Capture, message);
+                    if let Some(stage) = capture.take() {
+                        stage.stop();
+                    }
+                }
+                Some(Stage::Send) => {
+                    if let Some(stage) = send.as_ref() {
+                        if stage.running.swap(false, Ordering::Relaxed) {
+                            let message =
+                                format!("send loop sent nothing for {}s", timeout.as_secs());
+                            warn!("{message}; restarting it");
+                            self.journal.record(EventKind::SendError, message);
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops the capture and the send loop, starts `source` and carries the stream on from
+    /// where the send loop left it. If `source` does not start, the previous source is
+    /// started again and the inner error says why; the outer one is a send loop that could
+    /// not be started at all.
+    fn switch_source(
+        &mut self,
+        source: SourceKind,
+        capture: &mut Option<CaptureStage>,
+        send: &mut Option<SendStage>,
+        format: &mut StreamFormat,
+    ) -> Result<Result<String, Error>, Error> {
+        if let Some(stage) = capture.take() {
+            stage.stop();
+        }
+        let position = send.take().and_then(|stage| stage.hand_over().ok());
+        // Audio the old source left queued may not fit the new format; it is at most a
+        // capture period.
+        for chunk in self.rx.try_iter() {
+            self.stats
+                .queued_samples
+                .fetch_sub(chunk.samples.len() as u64, Ordering::Relaxed);
+        }
+
+        let previous = std::mem::replace(&mut self.source, source);
+        let started = self.start_capture().and_then(|stage| {
+            match StreamFormat::new(stage.sample_rate, stage.channels as u8, self.frame_ms) {
+                Ok(format) => Ok((stage, format)),
+                Err(err) => {
+                    stage.stop();
+                    Err(err.into())
+                }
+            }
+        });
+        let result = match started {
+            Ok((stage, new_format)) => {
+                let message = format!(
+                    "switched to {} capture from {} ({} Hz, {} ch)",
+                    source.as_str(),
+                    stage.source_name,
+                    new_format.sample_rate,
+                    new_format.channels
+                );
+                info!("{message}");
+                self.journal.record(EventKind::Source, message);
+                *format = new_format;
+                let name = stage.source_name.clone();
+                *capture = Some(stage);
+                Ok(name)
+            }
+            Err(err) => {
+                self.source = previous;
+                let message = format!(
+                    "failed to switch to {} capture: {}",
+                    source.as_str(),
+                    error_chain(&err)
+                );
+                warn!("{message}; staying on {}", previous.as_str());
+                self.journal.record(EventKind::Source, message);
+                // A failure here is left to the watchdog, or ends the sender without one.
+                *capture = self.start_capture().ok();
+                Err(err)
+            }
+        };
+        *send = Some(self.spawn_send_loop(*format, position)?);
+        Ok(result)
+    }
+
+    /// Journals each finished drop burst; the longer ones are audible, so they are logged too.
+    fn report_drop_bursts(&self) {
+        for burst in self.stats.drop_bursts.take_finished() {
+            let message = format!(
+                "dropped {} capture chunks in a {} ms burst",
+                burst.chunks,
+                burst.duration().as_millis()
+            );
+            if burst.chunks >= AUDIBLE_DROP_BURST {
+                warn!("{message}");
+            } else {
+                debug!("{message}");
+            }
+            self.journal.record(EventKind::Drops, message);
+        }
+    }
+
+    /// Waits before a restart, or fails once `stage` used up its restarts.
+    fn backoff(&self, budget: &mut RestartBudget, stage: Stage) -> Result<(), Error> {
+        let delay = budget.next_delay().ok_or(Error::Stalled {
+            stage: stage.as_str(),
+            restarts: MAX_RESTARTS,
+        })?;
+        let until = Instant::now() + delay;
+        while Instant::now() < until && self.running.load(Ordering::Relaxed) {
+            thread::sleep(CHECK_INTERVAL);
+        }
+        Ok(())
+    }
+
+    fn start_capture(&self) -> Result<CaptureStage, Error> {
+        let tx = self.tx.clone();
+        let running = Arc::new(AtomicBool::new(true));
+        let queue = CaptureQueue::new(
+            tx,
+            self.rx.clone(),
+            self.queue_overflow,
+            self.max_buffered.unwrap_or(DEFAULT_QUEUE_DEPTH),
+        );
+        let CaptureSetup {
+            sample_rate,
+            channels,
+            source_name,
+            guard,
+        } = start_capture(
+            self.source,
+            queue.clone(),
+            Arc::clone(&self.stats),
+            self.desktop_device.as_deref(),
+            self.source_file.as_deref(),
+            Arc::clone(&running),
+            &self.scheduling,
+        )?;
+        queue.fit(sample_rate, channels);
+        Ok(CaptureStage {
+            running,
+            guard,
+            sample_rate,
+            channels,
+            source_name,
+        })
+    }
+
+    fn spawn_send_loop(
+        &self,
+        format: StreamFormat,
+        resume: Option<StreamPosition>,
+    ) -> Result<SendStage, Error> {
+        let running = Arc::new(AtomicBool::new(true));
+        let rx = self.rx.clone();
+        let markers = self.markers.clone();
+        let start_at = self.start_at;
+        let late_policy = self.late_policy;
+        let max_buffered = self.max_buffered;
+        let max_kbps = self.max_kbps;
+        let tee = self.tee.clone();
+        let ndi = self.ndi.clone();
+        let output = self.output.clone();
+        let passthrough = self.passthrough;
+        let encode_workers = self.encode_workers;
+        let scheduling = self.scheduling.clone();
+        let stats = Arc::clone(&self.stats);
+        let feedback = Arc::clone(&self.feedback);
+        let dsp = Arc::clone(&self.dsp);
+        let paused = Arc::clone(&self.paused);
+        let plugins = self.plugins.clone();
+        let seal_overhead = self.seal_overhead;
+        let ptp = self.ptp.as_ref().map(PtpClock::open).transpose()?;
+        let pace = self.pace;
+        let pause_notice = self.pause_notice;
+        let loop_running = Arc::clone(&running);
+        let mut transport = self.targets.clone();
+        let span = info_span!("send_loop", frame_ms = self.frame_ms);
+        let handle = thread::Builder::new()
+            .name("send-loop".to_string())
+            .spawn(move || {
+                let _span = span.entered();
+                scheduling.apply("send-loop");
+                send_loop(
+                    rx,
+                    format,
+                    stats,
+                    &feedback,
+                    &mut transport,
+                    &dsp,
+                    &plugins,
+                    &loop_running,
+                    &paused,
+                    seal_overhead,
+                    ptp.as_ref(),
+                    pace,
+                    pause_notice,
+                    &markers,
+                    start_at,
+                    late_policy,
+                    max_buffered,
+                    max_kbps,
+                    tee.as_deref(),
+                    ndi.as_deref(),
+                    output.as_deref(),
+                    passthrough,
+                    encode_workers,
+                    resume,
+                )
+            })
+            .map_err(|source| Error::Spawn {
+                name: "send-loop",
+                source,
+            })?;
+        Ok(SendStage { running, handle })
+    }
+}
@@ -0,0 +1,292 @@
+//! `--tee`: the audio being sent, written to a WAV, FLAC or Ogg FLAC file from a thread of
+//! its own, so a slow disk never holds up the stream.
+
+mod flac;
+pub(crate) mod ogg;
+mod wav;
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender, TrySendError};
+use tracing::{info, warn};
+
+use crate::error::Error;
+use crate::sender::StreamFormat;
+
+/// Frames waiting for the disk; past this they are dropped rather than held in memory.
+const QUEUE_FRAMES: usize = 1000;
+/// Gaps in the frames that went out up to this long are written as silence; longer ones
+/// are pauses and left out.
+const MAX_GAP: Duration = Duration::from_secs(5);
+/// How often the file is brought up to date (sizes in the header, Ogg pages) while the
+/// stream runs, so it stays playable if the sender is killed.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where in the pipeline `--tee` takes the audio from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TeePoint {
+    /// Every frame as it goes into the encoder, after the DSP and effect plugins.
+    #[default]
+    PreCodec,
+    /// Only the frames that went out, as PCM16 on the wire, with silence where frames were
+    /// dropped on the way. Needs PCM: codec plugin packets cannot be decoded here.
+    PostCodec,
+}
+
+impl TeePoint {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TeePoint::PreCodec => "pre-codec",
+            TeePoint::PostCodec => "post-codec",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Container {
+    Wav,
+    Flac,
+    OggFlac,
+}
+
+impl Container {
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "wav" => Some(Container::Wav),
+            "flac" => Some(Container::Flac),
+            "ogg" | "oga" => Some(Container::OggFlac),
+            _ => None,
+        }
+    }
+}
+
+/// An audio file being written, in the format it was opened with.
+trait AudioFile: Send {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+#[derive(Debug)]
+struct Frame {
+    format: StreamFormat,
+    media_time: u32,
+    samples: Vec<i16>,
+}
+
+/// The file `--tee` writes. Shared by the restarts of a sender, so one session makes one
+/// file; it is finished when the last handle is dropped.
+#[derive(Debug)]
+pub struct Tee {
+    point: TeePoint,
+    path: PathBuf,
+    tx: Option<Sender<Frame>>,
+    dropped: Arc<AtomicU64>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Tee {
+    /// Starts the writer for `path`, a `.wav`, `.flac` or `.ogg` file. The file is created
+    /// once the first frame arrives, in that frame's format.
+    pub fn new(path: &Path, point: TeePoint) -> Result<Self, Error> {
+        let container = Container::from_path(path).ok_or_else(|| {
+            Error::Config(format!(
+                "--tee {} needs a .wav, .flac or .ogg file",
+                path.display()
+            ))
+        })?;
+        let (tx, rx) = bounded(QUEUE_FRAMES);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer = Writer {
+            path: path.to_path_buf(),
+            container,
+            point,
+            dropped: Arc::clone(&dropped),
+        };
+        let writer = thread::Builder::new()
+            .name("tee".to_string())
+            .spawn(move || writer.run(&rx))
+            .map_err(|source| Error::Spawn {
+                name: "tee",
+                source,
+            })?;
+        Ok(Self {
+            point,
+            path: path.to_path_buf(),
+            tx: Some(tx),
+            dropped,
+            writer: Some(writer),
+        })
+    }
+
+    pub fn point(&self) -> TeePoint {
+        self.point
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Queues one frame starting at `media_time`; it is dropped, and counted, when the disk
+    /// falls behind.
+    pub fn write(&self, format: StreamFormat, media_time: u32, samples: Vec<i16>) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let frame = Frame {
+            format,
+            media_time,
+            samples,
+        };
+        if let Err(TrySendError::Full(_)) = tx.try_send(frame) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for Tee {
+    fn drop(&mut self) {
+        self.tx = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+struct Writer {
+    path: PathBuf,
+    container: Container,
+    point: TeePoint,
+    dropped: Arc<AtomicU64>,
+}
+
+/// The file being written, with the format it holds.
+struct Open {
+    file: Box<dyn AudioFile>,
+    format: StreamFormat,
+    /// Media time right after the last frame written.
+    next_time: u32,
+}
+
+impl Writer {
+    fn run(self, rx: &Receiver<Frame>) {
+        if let Err(err) = self.write_all(rx) {
+            warn!(
+                "tee: failed to write {}: {err}; no longer teeing",
+                self.path.display()
+            );
+        }
+    }
+
+    fn write_all(&self, rx: &Receiver<Frame>) -> io::Result<()> {
+        let mut open: Option<Open> = None;
+        let mut part = 1;
+        let mut flushed = Instant::now();
+        let mut reported = 0;
+        loop {
+            match rx.recv_timeout(FLUSH_INTERVAL) {
+                Ok(frame) => {
+                    if open
+                        .as_ref()
+                        .is_some_and(|open| open.format != frame.format)
+                    {
+                        if let Some(open) = open.take() {
+                            open.file.finish()?;
+                        }
+                        part += 1;
+                    }
+                    let open = match &mut open {
+                        Some(open) => open,
+                        None => open.insert(self.open(part, frame.format, frame.media_time)?),
+                    };
+                    self.write_frame(open, &frame)?;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if flushed.elapsed() >= FLUSH_INTERVAL {
+                flushed = Instant::now();
+                if let Some(open) = &mut open {
+                    open.file.flush()?;
+                }
+                let dropped = self.dropped.load(Ordering::Relaxed);
+                if dropped > reported {
+                    warn!(
+                        "tee: {} frames dropped; the disk cannot keep up",
+                        dropped - reported
+                    );
+                    reported = dropped;
+                }
+            }
+        }
+        match open {
+            Some(open) => open.file.finish(),
+            None => Ok(()),
+        }
+    }
+
+    fn open(&self, part: usize, format: StreamFormat, media_time: u32) -> io::Result<Open> {
+        let path = match part {
+            1 => self.path.clone(),
+            part => numbered(&self.path, part),
+        };
+        let out = File::create(&path)?;
+        let file: Box<dyn AudioFile> = match self.container {
+            Container::Wav => Box::new(wav::WavFile::new(out, format)?),
+            Container::Flac => Box::new(flac::FlacFile::new(out, format)?),
+            Container::OggFlac => Box::new(flac::OggFlacFile::new(out, format)?),
+        };
+        info!(
+            "tee: writing {} Hz, {} ch ({}) to {}",
+            format.sample_rate,
+            format.channels,
+            self.point.as_str(),
+            path.display()
+        );
+        Ok(Open {
+            file,
+            format,
+            next_time: media_time,
+        })
+    }
+
+    fn write_frame(&self, open: &mut Open, frame: &Frame) -> io::Result<()> {
+        let channels = open.format.channels as usize;
+        if self.point == TeePoint::PostCodec {
+            let gap = frame.media_time.wrapping_sub(open.next_time) as u64;
+            let max_gap = MAX_GAP.as_secs() * open.format.sample_rate as u64;
+            if gap > 0 && gap <= max_gap {
+                let silence = vec![0; frame.samples.len().max(channels)];
+                let mut left = gap as usize * channels;
+                while left > 0 {
+                    let take = left.min(silence.len());
+                    open.file.write(&silence[..take])?;
+                    left -= take;
+                }
+            }
+        }
+        open.file.write(&frame.samples)?;
+        let frames = frame.samples.len() / channels.max(1);
+        open.next_time = frame.media_time.wrapping_add(frames as u32);
+        Ok(())
+    }
+}
+
+/// `out.wav` as `out-2.wav`, for the file after a format change.
+pub(crate) fn numbered(path: &Path, part: usize) -> PathBuf {
+    let mut name = OsString::from(path.file_stem().unwrap_or_default());
+    name.push(format!("-{part}"));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
@@ -0,0 +1,272 @@
+//! FLAC with verbatim subframes: lossless and bit-exact, only not compressed, which keeps
+//! the encoder small enough to live here. Native, or in Ogg per the FLAC Ogg mapping.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+use super::ogg::OggWriter;
+use super::AudioFile;
+use crate::sender::StreamFormat;
+
+/// Samples per channel in each FLAC frame; the last one of the file may be shorter.
+const BLOCK_SIZE: usize = 4096;
+const MAX_CHANNELS: u8 = 8;
+const STREAMINFO_SIZE: usize = 34;
+const BLOCK_STREAMINFO: u8 = 0;
+const BLOCK_VORBIS_COMMENT: u8 = 4;
+const LAST_BLOCK: u8 = 0x80;
+/// Frame header: blocksize - 1 follows as 16 bits, sample rate as in STREAMINFO.
+const BLOCKSIZE_16_BIT: u8 = 0x70;
+const SAMPLE_SIZE_16: u8 = 0x08;
+/// Subframe header of a verbatim subframe without wasted bits.
+const SUBFRAME_VERBATIM: u8 = 0x02;
+const VENDOR: &str = "windows-sender";
+
+const CRC8_TABLE: [u8; 256] = {
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+const CRC16_TABLE: [u16; 256] = {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter()
+        .fold(0, |crc, &byte| CRC8_TABLE[(crc ^ byte) as usize])
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ byte) as usize]
+    })
+}
+
+/// Cuts interleaved samples into FLAC frames of `BLOCK_SIZE`.
+struct Encoder {
+    format: StreamFormat,
+    pending: Vec<i16>,
+    frames: u64,
+    samples: u64,
+}
+
+impl Encoder {
+    fn new(format: StreamFormat) -> io::Result<Self> {
+        if format.channels == 0 || format.channels > MAX_CHANNELS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("FLAC holds 1 to 8 channels, not {}", format.channels),
+            ));
+        }
+        Ok(Self {
+            format,
+            pending: Vec::new(),
+            frames: 0,
+            samples: 0,
+        })
+    }
+
+    /// Takes in `samples`, handing each frame they complete to `out`.
+    fn push(
+        &mut self,
+        samples: &[i16],
+        mut out: impl FnMut(&[u8], u64) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.pending.extend_from_slice(samples);
+        let block = BLOCK_SIZE * self.format.channels as usize;
+        while self.pending.len() >= block {
+            let frame = self.frame(block);
+            out(&frame, self.samples)?;
+        }
+        Ok(())
+    }
+
+    /// The last, shorter frame, if samples are left over.
+    fn rest(&mut self) -> Option<Vec<u8>> {
+        (!self.pending.is_empty()).then(|| self.frame(self.pending.len()))
+    }
+
+    fn frame(&mut self, len: usize) -> Vec<u8> {
+        let channels = self.format.channels as usize;
+        let block = len / channels;
+        let mut frame = Vec::with_capacity(16 + len * 2 + channels);
+        frame.extend_from_slice(&[0xFF, 0xF8, BLOCKSIZE_16_BIT]);
+        frame.push(((channels as u8 - 1) << 4) | SAMPLE_SIZE_16);
+        push_coded_number(&mut frame, self.frames);
+        frame.extend_from_slice(&(block as u16 - 1).to_be_bytes());
+        frame.push(crc8(&frame));
+        for channel in 0..channels {
+            frame.push(SUBFRAME_VERBATIM);
+            for sample in self.pending[..len].iter().skip(channel).step_by(channels) {
+                frame.extend_from_slice(&sample.to_be_bytes());
+            }
+        }
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        self.pending.drain(..len);
+        self.frames += 1;
+        self.samples += block as u64;
+        frame
+    }
+
+    fn stream_info(&self, last: bool) -> Vec<u8> {
+        let mut block = metadata_header(BLOCK_STREAMINFO, STREAMINFO_SIZE, last);
+        let size = (BLOCK_SIZE as u16).to_be_bytes();
+        block.extend_from_slice(&size);
+        block.extend_from_slice(&size);
+        // Smallest and largest frame sizes are left unknown.
+        block.extend_from_slice(&[0; 6]);
+        let packed = (self.format.sample_rate as u64) << 44
+            | ((self.format.channels as u64 - 1) << 41)
+            | (15 << 36)
+            | (self.samples & 0xF_FFFF_FFFF);
+        block.extend_from_slice(&packed.to_be_bytes());
+        // No MD5 of the audio.
+        block.extend_from_slice(&[0; 16]);
+        block
+    }
+}
+
+fn metadata_header(kind: u8, len: usize, last: bool) -> Vec<u8> {
+    let mut header = vec![if last { LAST_BLOCK | kind } else { kind }];
+    header.extend_from_slice(&(len as u32).to_be_bytes()[1..]);
+    header
+}
+
+fn vorbis_comment(last: bool) -> Vec<u8> {
+    let mut block = metadata_header(BLOCK_VORBIS_COMMENT, 8 + VENDOR.len(), last);
+    block.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+    block.extend_from_slice(VENDOR.as_bytes());
+    block.extend_from_slice(&0u32.to_le_bytes());
+    block
+}
+
+/// The frame number in the UTF-8-like coding of FLAC frame headers.
+fn push_coded_number(out: &mut Vec<u8>, number: u64) {
+    if number < 0x80 {
+        out.push(number as u8);
+        return;
+    }
+    let bits = 64 - number.leading_zeros() as usize;
+    // Each continuation byte holds 6 bits; the first byte what is left after its prefix.
+    let extra = (1..=6).find(|&n| bits <= 6 - n + 6 * n).unwrap_or(6);
+    let prefix = !(0xFFu8 >> (extra + 1));
+    out.push(prefix | (number >> (6 * extra)) as u8);
+    for n in (0..extra).rev() {
+        out.push(0x80 | ((number >> (6 * n)) & 0x3F) as u8);
+    }
+}
+
+/// A native `.flac` file; the sample count in STREAMINFO is brought up to date on every
+/// flush.
+pub struct FlacFile {
+    out: BufWriter<File>,
+    encoder: Encoder,
+}
+
+impl FlacFile {
+    pub fn new(file: File, format: StreamFormat) -> io::Result<Self> {
+        let encoder = Encoder::new(format)?;
+        let mut out = BufWriter::new(file);
+        out.write_all(b"fLaC")?;
+        out.write_all(&encoder.stream_info(false))?;
+        out.write_all(&vorbis_comment(true))?;
+        Ok(Self { out, encoder })
+    }
+}
+
+impl AudioFile for FlacFile {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        let out = &mut self.out;
+        self.encoder.push(samples, |frame, _| out.write_all(frame))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&self.encoder.stream_info(false))?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        if let Some(frame) = self.encoder.rest() {
+            self.out.write_all(&frame)?;
+        }
+        self.flush()
+    }
+}
+
+/// FLAC in Ogg (`.ogg`/`.oga`): a mapping header with STREAMINFO, a comment packet, then
+/// one frame per packet. The sample count stays unknown; players take it from the last page.
+pub struct OggFlacFile {
+    ogg: OggWriter<BufWriter<File>>,
+    encoder: Encoder,
+}
+
+impl OggFlacFile {
+    pub fn new(file: File, format: StreamFormat) -> io::Result<Self> {
+        let encoder = Encoder::new(format)?;
+        let mut ogg = OggWriter::new(BufWriter::new(file));
+        let mut header = vec![0x7F];
+        header.extend_from_slice(b"FLAC");
+        // Mapping version 1.0, followed by one more header packet.
+        header.extend_from_slice(&[1, 0, 0, 1]);
+        header.extend_from_slice(b"fLaC");
+        header.extend_from_slice(&encoder.stream_info(false));
+        ogg.packet(&header, 0)?;
+        ogg.flush()?;
+        ogg.packet(&vorbis_comment(true), 0)?;
+        ogg.flush()?;
+        Ok(Self { ogg, encoder })
+    }
+}
+
+impl AudioFile for OggFlacFile {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        let ogg = &mut self.ogg;
+        self.encoder
+            .push(samples, |frame, granule| ogg.packet(frame, granule))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.ogg.flush()
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        if let Some(frame) = self.encoder.rest() {
+            self.ogg.packet(&frame, self.encoder.samples)?;
+        }
+        self.ogg.finish()
+    }
+}
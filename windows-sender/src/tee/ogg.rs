@@ -0,0 +1,147 @@
+//! Ogg pages (RFC 3533) for one logical stream.
+
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Pages are closed once their body reaches this size.
+const PAGE_TARGET: usize = 4096;
+const MAX_SEGMENTS: usize = 255;
+
+const CONTINUED: u8 = 0x01;
+const BEGIN: u8 = 0x02;
+const END: u8 = 0x04;
+
+/// CRC-32 with polynomial 0x04c11db7, unreflected and starting from 0, as Ogg uses it.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0, |crc, &byte| {
+        (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ byte) as usize]
+    })
+}
+
+pub struct OggWriter<W: Write> {
+    out: W,
+    serial: u32,
+    sequence: u32,
+    segments: Vec<u8>,
+    body: Vec<u8>,
+    /// Granule position of the last packet that ends on the page being built.
+    granule: Option<u64>,
+    /// Granule position of the last packet written, for an empty last page.
+    last_granule: u64,
+    /// The page being built starts with the rest of a packet from the previous one.
+    continued: bool,
+    first: bool,
+}
+
+impl<W: Write> OggWriter<W> {
+    pub fn new(out: W) -> Self {
+        let serial = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.subsec_nanos() ^ since.as_secs() as u32);
+        Self {
+            out,
+            serial,
+            sequence: 0,
+            segments: Vec::new(),
+            body: Vec::new(),
+            granule: None,
+            last_granule: 0,
+            continued: false,
+            first: true,
+        }
+    }
+
+    /// Adds a packet ending at `granule`, spreading it over several pages if it has to.
+    pub fn packet(&mut self, data: &[u8], granule: u64) -> io::Result<()> {
+        let mut rest = data;
+        loop {
+            let take = rest.len().min(255);
+            self.segments.push(take as u8);
+            self.body.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            // A lacing value under 255 ends the packet.
+            let done = take < 255;
+            if done {
+                self.granule = Some(granule);
+                self.last_granule = granule;
+            }
+            if self.segments.len() == MAX_SEGMENTS {
+                self.page(0)?;
+                self.continued = !done;
+            }
+            if done {
+                break;
+            }
+        }
+        if self.body.len() >= PAGE_TARGET {
+            self.page(0)?;
+        }
+        Ok(())
+    }
+
+    /// Closes the page being built, if it holds anything, so the next packet starts a
+    /// fresh one.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if !self.segments.is_empty() {
+            self.page(0)?;
+        }
+        self.out.flush()
+    }
+
+    /// Closes the stream with a last page marked as its end.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.page(END)?;
+        self.out.flush()
+    }
+
+    fn page(&mut self, flags: u8) -> io::Result<()> {
+        let mut header_type = flags;
+        if std::mem::take(&mut self.continued) {
+            header_type |= CONTINUED;
+        }
+        if std::mem::take(&mut self.first) {
+            header_type |= BEGIN;
+        }
+        // -1 marks a page on which no packet ends.
+        let granule = match self.granule.take() {
+            Some(granule) => granule,
+            None if self.segments.is_empty() => self.last_granule,
+            None => u64::MAX,
+        };
+        let mut page = Vec::with_capacity(27 + self.segments.len() + self.body.len());
+        page.extend_from_slice(b"OggS");
+        page.push(0);
+        page.push(header_type);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&self.serial.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(self.segments.len() as u8);
+        page.append(&mut self.segments);
+        page.append(&mut self.body);
+        let crc = crc32(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        self.sequence += 1;
+        self.out.write_all(&page)
+    }
+}
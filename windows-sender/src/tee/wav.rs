@@ -0,0 +1,61 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+use super::AudioFile;
+use crate::sender::StreamFormat;
+
+const HEADER_SIZE: u32 = 44;
+const FORMAT_PCM: u16 = 1;
+
+/// PCM16 WAV. The sizes in the header are filled in on every flush.
+pub struct WavFile {
+    out: BufWriter<File>,
+    data_bytes: u64,
+}
+
+impl WavFile {
+    pub fn new(file: File, format: StreamFormat) -> io::Result<Self> {
+        let mut out = BufWriter::new(file);
+        let channels = format.channels as u16;
+        let block_align = channels * 2;
+        out.write_all(b"RIFF")?;
+        out.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&FORMAT_PCM.to_le_bytes())?;
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&format.sample_rate.to_le_bytes())?;
+        out.write_all(&(format.sample_rate * block_align as u32).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(Self { out, data_bytes: 0 })
+    }
+}
+
+impl AudioFile for WavFile {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        for sample in samples {
+            self.out.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_bytes += samples.len() as u64 * 2;
+        Ok(())
+    }
+
+    /// WAV sizes are 32 bits; past 4 GB the header stays at the most it can say.
+    fn flush(&mut self) -> io::Result<()> {
+        let data = self.data_bytes.min((u32::MAX - HEADER_SIZE) as u64) as u32;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out
+            .write_all(&(data + HEADER_SIZE - 8).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        self.out.write_all(&data.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()
+    }
+}
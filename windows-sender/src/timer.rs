@@ -0,0 +1,108 @@
+//! Fine-grained sleeps for the threads that keep the stream's cadence.
+//!
+//! Windows rounds sleeps and timed waits up to the system timer tick, 15.6 ms unless some
+//! process asks for less, which turns a 5 ms pacing sleep into a 15 ms one. `Resolution`
+//! asks for 1 ms while it lives, and `Sleeper` waits on a high-resolution waitable timer
+//! where the OS has them. Elsewhere both fall back to `thread::sleep`.
+
+use std::thread;
+use std::time::Duration;
+
+/// Holds the system timer at 1 ms while alive.
+pub struct Resolution {
+    _private: (),
+}
+
+impl Resolution {
+    pub fn fine() -> Self {
+        #[cfg(windows)]
+        // SAFETY: balanced by `timeEndPeriod` in `drop`.
+        unsafe {
+            windows_sys::Win32::Media::timeBeginPeriod(1);
+        }
+        Self { _private: () }
+    }
+}
+
+impl Drop for Resolution {
+    fn drop(&mut self) {
+        #[cfg(windows)]
+        // SAFETY: matches the `timeBeginPeriod(1)` in `fine`.
+        unsafe {
+            windows_sys::Win32::Media::timeEndPeriod(1);
+        }
+    }
+}
+
+/// Sleeps with sub-tick precision; owned by one thread.
+pub struct Sleeper {
+    #[cfg(windows)]
+    timer: Option<windows_sys::Win32::Foundation::HANDLE>,
+}
+
+impl Sleeper {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(windows)]
+            timer: high_resolution_timer(),
+        }
+    }
+
+    pub fn sleep(&self, duration: Duration) {
+        #[cfg(windows)]
+        if let Some(timer) = self.timer {
+            use windows_sys::Win32::System::Threading::{
+                SetWaitableTimer, WaitForSingleObject, INFINITE,
+            };
+
+            // Relative due times are negative, in 100 ns units.
+            let due = -((duration.as_nanos() / 100).min(i64::MAX as u128) as i64);
+            // SAFETY: the timer is open for the lifetime of `self` and `due` outlives the
+            // call.
+            unsafe {
+                if SetWaitableTimer(timer, &due, 0, None, std::ptr::null(), 0) != 0 {
+                    WaitForSingleObject(timer, INFINITE);
+                    return;
+                }
+            }
+        }
+        thread::sleep(duration);
+    }
+}
+
+#[cfg(windows)]
+fn high_resolution_timer() -> Option<windows_sys::Win32::Foundation::HANDLE> {
+    use windows_sys::Win32::System::Threading::{
+        CreateWaitableTimerExW, CREATE_WAITABLE_TIMER_HIGH_RESOLUTION, TIMER_ALL_ACCESS,
+    };
+
+    // SAFETY: an unnamed timer with default security, closed by `Sleeper::drop`. Windows
+    // before 10 1803 rejects the flag, and sleeps fall back to `thread::sleep`.
+    let timer = unsafe {
+        CreateWaitableTimerExW(
+            std::ptr::null(),
+            std::ptr::null(),
+            CREATE_WAITABLE_TIMER_HIGH_RESOLUTION,
+            TIMER_ALL_ACCESS,
+        )
+    };
+    (!timer.is_null()).then_some(timer)
+}
+
+impl Default for Sleeper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Sleeper {
+    fn drop(&mut self) {
+        #[cfg(windows)]
+        if let Some(timer) = self.timer {
+            // SAFETY: the handle came from `CreateWaitableTimerExW` and is closed once.
+            unsafe {
+                windows_sys::Win32::Foundation::CloseHandle(timer);
+            }
+        }
+    }
+}
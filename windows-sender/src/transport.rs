@@ -0,0 +1,1524 @@
+use std::collections::VecDeque;
+use std::io::{self, IoSlice, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{bounded, Receiver, TryRecvError};
+use tracing::{debug, info, warn};
+
+use crate::aes67::{self, Session, SessionName};
+use crate::crypto::{PacketSealer, Sealing};
+use crate::error::{error_chain, ProtocolError, TransportError};
+use crate::feedback::{FeedbackReader, FeedbackTracker};
+use crate::journal::{EventJournal, EventKind};
+use crate::pairing::SenderPairing;
+use crate::protocol::{mark_discontinuity, set_seq};
+use crate::resolve::{Endpoint, TargetName};
+use crate::scream;
+use crate::stats::TargetStats;
+#[cfg(uring_backend)]
+use crate::uring::SendRing;
+use crate::vban::{self, StreamName};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransportKind {
+    Udp,
+    Tcp,
+    /// UDP in VBAN packets; see `vban`.
+    Vban,
+    /// UDP in Scream packets, often to its multicast group; see `scream`.
+    Scream,
+    /// RTP to AES67 devices, usually to a multicast group; see `aes67`.
+    Aes67,
+    /// TCP over a Bluetooth RFCOMM link, through `bluetooth::Relay`.
+    Bluetooth,
+}
+
+impl TransportKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TransportKind::Udp => "udp",
+            TransportKind::Tcp => "tcp",
+            TransportKind::Vban => "vban",
+            TransportKind::Scream => "scream",
+            TransportKind::Aes67 => "aes67",
+            TransportKind::Bluetooth => "bluetooth",
+        }
+    }
+
+    /// Whether the receivers are ours, which send reports back and take pairing, sealing
+    /// and codec plugins, rather than another protocol's.
+    pub fn is_native(self) -> bool {
+        matches!(
+            self,
+            TransportKind::Udp | TransportKind::Tcp | TransportKind::Bluetooth
+        )
+    }
+
+    /// Whether a target is a connection, which drops and has to be made again, rather than
+    /// datagrams sent to an address.
+    pub fn is_stream(self) -> bool {
+        matches!(self, TransportKind::Tcp | TransportKind::Bluetooth)
+    }
+}
+
+/// How the sockets to the targets are written.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SendOptions {
+    /// See `UdpTransport::with_io_uring`.
+    pub io_uring: bool,
+    /// See `UdpTransport::with_gso`.
+    pub gso: bool,
+    /// `SO_SNDBUF` of every socket, in bytes; `None` keeps the OS default.
+    pub send_buffer: Option<u32>,
+    /// See `TcpTransport::with_backlog`.
+    pub tcp_backlog: Option<usize>,
+    /// The stream name in VBAN packets.
+    pub vban_stream: StreamName,
+    /// The session name AES67 devices list the stream by.
+    pub aes67_session: SessionName,
+    pub aes67_encoding: aes67::Encoding,
+}
+
+/// A receiver that stops reading must not block the send loop forever.
+const TCP_WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub trait Transport {
+    /// Sends one packet and returns the number of bytes put on the wire.
+    fn send_packet(&mut self, packet: &[u8], seq: u32) -> Result<usize, TransportError>;
+
+    /// Sends packets numbered on from `first_seq`, in one system call where the transport
+    /// can batch them.
+    fn send_batch(&mut self, packets: &[Vec<u8>], first_seq: u32) -> Result<usize, TransportError> {
+        let mut sent = 0;
+        for (i, packet) in packets.iter().enumerate() {
+            sent += self.send_packet(packet, first_seq.wrapping_add(i as u32))?;
+        }
+        Ok(sent)
+    }
+
+    /// Packets dropped since the last call because a target fell too far behind; see
+    /// `TcpTransport::with_backlog`.
+    fn take_dropped(&mut self) -> u32 {
+        0
+    }
+
+    /// Packets and bytes waiting in the sender for room in a socket, for the target
+    /// furthest behind; see `TcpTransport::with_backlog`.
+    fn backlog(&mut self) -> (usize, usize) {
+        (0, 0)
+    }
+
+    /// The SDP (RFC 4566) players join an RTP stream with; `None` until the stream is
+    /// known, or for protocols that have none.
+    fn sdp(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Connects to `target`, pairing first when `pairing` is set; reports it sends back are
+/// passed to `feedback`.
+pub fn connect(
+    kind: TransportKind,
+    target: SocketAddr,
+    feedback: &Arc<FeedbackTracker>,
+    pairing: Option<&SenderPairing>,
+    options: SendOptions,
+) -> Result<Box<dyn Transport + Send>, TransportError> {
+    Ok(match kind {
+        TransportKind::Udp => {
+            let mut udp = UdpTransport::new(target, feedback, pairing)?;
+            if options.io_uring {
+                udp = udp.with_io_uring()?;
+            }
+            if options.gso {
+                udp = udp.with_gso()?;
+            }
+            if let Some(bytes) = options.send_buffer {
+                set_buffer_size(&udp.socket, SocketBuffer::Send, bytes)?;
+            }
+            Box::new(udp)
+        }
+        TransportKind::Tcp | TransportKind::Bluetooth => {
+            let mut tcp = TcpTransport::connect(target, feedback, pairing)?;
+            if let Some(packets) = options.tcp_backlog {
+                tcp = tcp.with_backlog(packets);
+            }
+            if let Some(bytes) = options.send_buffer {
+                set_buffer_size(
+                    tcp.writer.lock().unwrap().stream(),
+                    SocketBuffer::Send,
+                    bytes,
+                )?;
+            }
+            Box::new(tcp)
+        }
+        TransportKind::Vban => interop(
+            InteropTransport::new(target, Box::new(vban::Packetizer::new(options.vban_stream)))?,
+            options,
+        )?,
+        TransportKind::Scream => interop(
+            InteropTransport::new(target, Box::new(scream::Packetizer::new()))?,
+            options,
+        )?,
+        TransportKind::Aes67 => {
+            let (SocketAddr::V4(group), IpAddr::V4(source)) = (target, route_source(target)?)
+            else {
+                return Err(TransportError::Connect {
+                    addr: target,
+                    source: io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "AES67 streams go to IPv4 addresses",
+                    ),
+                });
+            };
+            let session =
+                Session::new(options.aes67_session, options.aes67_encoding, source, group);
+            interop(
+                InteropTransport::new(target, Box::new(aes67::Packetizer::new(session)))?
+                    .with_multicast_ttl(aes67::MULTICAST_TTL)?,
+                options,
+            )?
+        }
+    })
+}
+
+/// A UDP socket on any port of the address family of `target`, which a socket of the
+/// other family cannot send to.
+pub fn bind_for(target: SocketAddr) -> io::Result<UdpSocket> {
+    let any = match target {
+        SocketAddr::V4(_) => IpAddr::from(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::from(Ipv6Addr::UNSPECIFIED),
+    };
+    UdpSocket::bind((any, 0))
+}
+
+/// This host's address on the route to `target`.
+fn route_source(target: SocketAddr) -> Result<IpAddr, TransportError> {
+    let socket = bind_for(target).map_err(TransportError::Bind)?;
+    socket
+        .connect(target)
+        .and_then(|()| socket.local_addr())
+        .map(|local| local.ip())
+        .map_err(|source| TransportError::Connect {
+            addr: target,
+            source,
+        })
+}
+
+fn interop(
+    interop: InteropTransport,
+    options: SendOptions,
+) -> Result<Box<dyn Transport + Send>, TransportError> {
+    if let Some(bytes) = options.send_buffer {
+        set_buffer_size(&interop.socket, SocketBuffer::Send, bytes)?;
+    }
+    Ok(Box::new(interop))
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum SocketBuffer {
+    Send,
+    Receive,
+}
+
+/// Sizes the kernel buffer of `socket`. A smaller one makes a stalled network drop audio
+/// sooner instead of queueing it; Linux doubles the value and caps it at
+/// `net.core.wmem_max` / `rmem_max`.
+#[cfg(unix)]
+pub(crate) fn set_buffer_size<S: std::os::fd::AsRawFd>(
+    socket: &S,
+    buffer: SocketBuffer,
+    bytes: u32,
+) -> Result<(), TransportError> {
+    let (option, what) = match buffer {
+        SocketBuffer::Send => (libc::SO_SNDBUF, "SO_SNDBUF"),
+        SocketBuffer::Receive => (libc::SO_RCVBUF, "SO_RCVBUF"),
+    };
+    let value = bytes.min(i32::MAX as u32) as libc::c_int;
+    // SAFETY: `value` outlives the call and the length matches it.
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc < 0 {
+        return Err(TransportError::Configure {
+            what,
+            source: io::Error::last_os_error(),
+        });
+    }
+    Ok(())
+}
+
+/// Sizes the kernel buffer of `socket`. A smaller one makes a stalled network drop audio
+/// sooner instead of queueing it.
+#[cfg(windows)]
+pub(crate) fn set_buffer_size<S: std::os::windows::io::AsRawSocket>(
+    socket: &S,
+    buffer: SocketBuffer,
+    bytes: u32,
+) -> Result<(), TransportError> {
+    use windows_sys::Win32::Networking::WinSock::{setsockopt, SOL_SOCKET, SO_RCVBUF, SO_SNDBUF};
+
+    let (option, what) = match buffer {
+        SocketBuffer::Send => (SO_SNDBUF, "SO_SNDBUF"),
+        SocketBuffer::Receive => (SO_RCVBUF, "SO_RCVBUF"),
+    };
+    let value = bytes.min(i32::MAX as u32) as i32;
+    // SAFETY: `value` outlives the call and the length matches it.
+    let rc = unsafe {
+        setsockopt(
+            socket.as_raw_socket() as usize,
+            SOL_SOCKET,
+            option,
+            (&value as *const i32).cast(),
+            std::mem::size_of::<i32>() as i32,
+        )
+    };
+    if rc != 0 {
+        return Err(TransportError::Configure {
+            what,
+            source: io::Error::last_os_error(),
+        });
+    }
+    Ok(())
+}
+
+/// First wait before a target set aside after a send error is connected again; it doubles
+/// with every failed attempt, up to `RETRY_MAX`.
+const RETRY_FIRST: Duration = Duration::from_millis(250);
+const RETRY_MAX: Duration = Duration::from_secs(8);
+
+type Connected = Result<(SocketAddr, Box<dyn Transport + Send>), TransportError>;
+
+/// A target taken out of the fan-out after a send error, waiting to be connected again.
+struct Retry {
+    at: Instant,
+    delay: Duration,
+    /// The reconnect under way, on a thread of its own so a slow connect never holds up
+    /// the other targets.
+    attempt: Option<Receiver<Connected>>,
+}
+
+struct TargetEntry {
+    addr: SocketAddr,
+    /// Set for targets given by name, which are resolved again on every reconnect.
+    name: Option<TargetName>,
+    transport: Box<dyn Transport + Send>,
+    /// Sequence number of the next audio packet. Every target counts its own from 0 at each
+    /// connect, so one that was set aside or reconnected leaves the numbering of the others
+    /// alone.
+    seq: u32,
+    /// The send loop's sequence number minus `seq`, as last told to the feedback tracker.
+    seq_offset: Option<u32>,
+    sealer: Option<PacketSealer>,
+    /// The next audio packet is flagged with `FLAG_DISCONTINUITY`.
+    discontinuity: bool,
+    /// Packets the transport dropped since `take_dropped` last asked.
+    dropped: u32,
+    retry: Option<Retry>,
+    /// Audio packets kept while the target is set aside, with the send loop's sequence
+    /// numbers, to go out first once it is back; see `TargetSet::fan_out`.
+    held: VecDeque<(u32, Vec<u8>)>,
+    /// Renumbered and sealed copies of the batch being sent.
+    scratch: Vec<Vec<u8>>,
+    stats: Arc<TargetStats>,
+}
+
+impl TargetEntry {
+    /// Sends a batch of audio packets, renumbered from this target's own `seq` and sealed
+    /// for it.
+    fn send_batch(
+        &mut self,
+        packets: &[Vec<u8>],
+        first_seq: u32,
+        feedback: &FeedbackTracker,
+    ) -> Result<usize, TransportError> {
+        let offset = first_seq.wrapping_sub(self.seq);
+        if self.seq_offset != Some(offset) {
+            feedback.set_seq_offset(self.addr, offset);
+            self.seq_offset = Some(offset);
+        }
+        if self.scratch.len() < packets.len() {
+            self.scratch.resize_with(packets.len(), Vec::new);
+        }
+        let addr = self.addr;
+        let overhead = self.sealer.as_ref().map_or(0, PacketSealer::overhead);
+        for (i, (packet, copy)) in packets.iter().zip(&mut self.scratch).enumerate() {
+            let seq = self.seq.wrapping_add(i as u32);
+            copy.clear();
+            copy.reserve(packet.len() + overhead);
+            copy.extend_from_slice(packet);
+            set_seq(copy, seq);
+            if i == 0 && self.discontinuity {
+                mark_discontinuity(copy);
+            }
+            if let Some(sealer) = self.sealer.as_mut() {
+                sealer
+                    .seal(copy, seq)
+                    .map_err(|source| TransportError::Seal { addr, source })?;
+            }
+        }
+        let sent = self
+            .transport
+            .send_batch(&self.scratch[..packets.len()], self.seq)?;
+        self.seq = self.seq.wrapping_add(packets.len() as u32);
+        self.discontinuity = false;
+        let dropped = self.transport.take_dropped();
+        if dropped > 0 {
+            self.dropped = self.dropped.saturating_add(dropped);
+            self.discontinuity = true;
+        }
+        self.stats
+            .sent_packets
+            .fetch_add(packets.len() as u64, Ordering::Relaxed);
+        self.stats
+            .sent_bytes
+            .fetch_add(sent as u64, Ordering::Relaxed);
+        Ok(sent)
+    }
+
+    /// Keeps a batch the target misses while set aside, dropping the oldest packets past
+    /// `limit`; returns how many were dropped.
+    fn hold(&mut self, packets: &[Vec<u8>], first_seq: u32, limit: usize) -> usize {
+        for (i, packet) in packets.iter().enumerate() {
+            self.held
+                .push_back((first_seq.wrapping_add(i as u32), packet.clone()));
+        }
+        let excess = self.held.len().saturating_sub(limit);
+        self.held.drain(..excess);
+        excess
+    }
+
+    /// Sends the packets held while the target was set aside, oldest first. Those not sent
+    /// stay held should the connection fail again.
+    fn send_held(&mut self, feedback: &FeedbackTracker) -> Result<usize, TransportError> {
+        let mut sent = 0;
+        while let Some((seq, packet)) = self.held.pop_front() {
+            match self.send_batch(std::slice::from_ref(&packet), seq, feedback) {
+                Ok(bytes) => sent += bytes,
+                Err(err) => {
+                    self.held.push_front((seq, packet));
+                    return Err(err);
+                }
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Starts over on `transport`, as a new stream for the receiver.
+    fn restart(&mut self, transport: Box<dyn Transport + Send>, sealer: Option<PacketSealer>) {
+        self.transport = transport;
+        self.sealer = sealer;
+        self.seq = 0;
+        self.seq_offset = None;
+        self.dropped = 0;
+        self.retry = None;
+        self.stats.set_aside.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Fans every packet out to a set of receivers that can change while streaming.
+///
+/// Each target numbers and seals the audio packets it gets on its own. One whose send fails
+/// is set aside and connected again in the background while the others carry on. Over UDP
+/// the error reaches the send loop when no target is left; over TCP the targets are
+/// reconnected however long it takes.
+///
+/// Clones share the same set, so one clone can be handed to the send loop while
+/// another adds or removes targets.
+#[derive(Clone)]
+pub struct TargetSet {
+    kind: TransportKind,
+    feedback: Arc<FeedbackTracker>,
+    pairing: Option<Arc<SenderPairing>>,
+    sealing: Option<Sealing>,
+    options: SendOptions,
+    journal: Option<Arc<EventJournal>>,
+    entries: Arc<Mutex<Vec<TargetEntry>>>,
+}
+
+impl TargetSet {
+    /// With `sealing`, every target seals the audio packets with a sealer of its own.
+    pub fn connect(
+        kind: TransportKind,
+        targets: &[Endpoint],
+        feedback: Arc<FeedbackTracker>,
+        pairing: Option<Arc<SenderPairing>>,
+        sealing: Option<Sealing>,
+        options: SendOptions,
+    ) -> Result<Self, TransportError> {
+        let set = Self {
+            kind,
+            feedback,
+            pairing,
+            sealing,
+            options,
+            journal: None,
+            entries: Arc::new(Mutex::new(Vec::with_capacity(targets.len()))),
+        };
+        for target in targets {
+            set.add_endpoint(target)?;
+        }
+        Ok(set)
+    }
+
+    /// Records targets set aside and reconnected in `journal`.
+    pub fn with_journal(mut self, journal: Arc<EventJournal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    pub fn kind(&self) -> TransportKind {
+        self.kind
+    }
+
+    /// Connects to `addr` and starts sending to it. Returns false if it was already a target.
+    pub fn add(&self, addr: SocketAddr) -> Result<bool, TransportError> {
+        self.insert(addr, None)
+    }
+
+    /// Like [`TargetSet::add`], resolving `endpoint` first if it is a name.
+    pub fn add_endpoint(&self, endpoint: &Endpoint) -> Result<bool, TransportError> {
+        match endpoint {
+            Endpoint::Addr(addr) => self.insert(*addr, None),
+            Endpoint::Name(name) => {
+                let addr = name.resolve()?;
+                info!("{name} resolved to {addr}");
+                self.insert(addr, Some(name.clone()))
+            }
+        }
+    }
+
+    fn insert(&self, addr: SocketAddr, name: Option<TargetName>) -> Result<bool, TransportError> {
+        if self.contains(addr) {
+            return Ok(false);
+        }
+        let transport = connect(
+            self.kind,
+            addr,
+            &self.feedback,
+            self.pairing.as_deref(),
+            self.options,
+        )?;
+        let sealer = self.sealer(addr)?;
+        let mut entries = self.entries.lock().unwrap();
+        if entries.iter().any(|entry| entry.addr == addr) {
+            return Ok(false);
+        }
+        entries.push(TargetEntry {
+            addr,
+            name,
+            transport,
+            seq: 0,
+            seq_offset: None,
+            sealer,
+            discontinuity: false,
+            dropped: 0,
+            retry: None,
+            held: VecDeque::new(),
+            scratch: Vec::new(),
+            stats: self.feedback.target_stats(addr),
+        });
+        Ok(true)
+    }
+
+    fn sealer(&self, addr: SocketAddr) -> Result<Option<PacketSealer>, TransportError> {
+        self.sealing
+            .as_ref()
+            .map(Sealing::sealer)
+            .transpose()
+            .map_err(|source| TransportError::Seal { addr, source })
+    }
+
+    /// Stops sending to `addr`. Returns false if it was not a target.
+    pub fn remove(&self, addr: SocketAddr) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|entry| entry.addr != addr);
+        let removed = entries.len() != before;
+        if removed {
+            self.feedback.forget_target(addr);
+        }
+        removed
+    }
+
+    /// Replaces the socket of every target with a fresh connection, after looking up the
+    /// targets given by name again. A name that no longer resolves keeps its last address.
+    pub fn reconnect(&self) -> Result<(), TransportError> {
+        let names: Vec<TargetName> = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|entry| entry.name.clone())
+            .collect();
+        let resolved: Vec<(TargetName, SocketAddr)> = names
+            .into_iter()
+            .filter_map(|name| match name.resolve() {
+                Ok(addr) => Some((name, addr)),
+                Err(err) => {
+                    warn!("{}; keeping its last address", error_chain(&err));
+                    None
+                }
+            })
+            .collect();
+        let mut entries = self.entries.lock().unwrap();
+        for entry in entries.iter_mut() {
+            let moved = resolved
+                .iter()
+                .find(|(name, addr)| entry.name.as_ref() == Some(name) && *addr != entry.addr);
+            if let Some((name, addr)) = moved {
+                info!("{name} moved from {} to {addr}", entry.addr);
+                self.move_entry(entry, *addr);
+            }
+            let transport = connect(
+                self.kind,
+                entry.addr,
+                &self.feedback,
+                self.pairing.as_deref(),
+                self.options,
+            )?;
+            entry.restart(transport, self.sealer(entry.addr)?);
+            entry.discontinuity = false;
+            entry.held.clear();
+        }
+        Ok(())
+    }
+
+    fn move_entry(&self, entry: &mut TargetEntry, addr: SocketAddr) {
+        self.feedback.forget_target(entry.addr);
+        entry.stats = self.feedback.target_stats(addr);
+        entry.addr = addr;
+    }
+
+    pub fn contains(&self, addr: SocketAddr) -> bool {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|entry| entry.addr == addr)
+    }
+
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| entry.addr)
+            .collect()
+    }
+
+    /// The targets as they were given, names included.
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| match &entry.name {
+                Some(name) => Endpoint::Name(name.clone()),
+                None => Endpoint::Addr(entry.addr),
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Runs `send` on every target that is not set aside, and sets aside those it fails
+    /// on. `batch`, the audio packets being sent, is what the targets set aside miss; over
+    /// TCP they hold on to up to `tcp_backlog` packets of it to send once reconnected.
+    ///
+    /// Over UDP the error comes back once every target is set aside. A TCP target whose
+    /// connection dropped is reconnected for as long as it takes instead, while the
+    /// capture carries on.
+    fn fan_out<F>(
+        &self,
+        batch: &[Vec<u8>],
+        first_seq: u32,
+        mut send: F,
+    ) -> Result<usize, TransportError>
+    where
+        F: FnMut(&mut TargetEntry) -> Result<usize, TransportError>,
+    {
+        let mut entries = self.entries.lock().unwrap();
+        let mut sent = 0;
+        let mut failed = Vec::new();
+        let hold = self.options.tcp_backlog.filter(|_| self.kind.is_stream());
+        for entry in entries.iter_mut() {
+            if !self.poll_retry(entry) {
+                let skipped = match hold {
+                    Some(limit) => entry.hold(batch, first_seq, limit),
+                    None => batch.len(),
+                };
+                entry
+                    .stats
+                    .skipped_packets
+                    .fetch_add(skipped as u64, Ordering::Relaxed);
+                continue;
+            }
+            let result = entry
+                .send_held(&self.feedback)
+                .and_then(|held| Ok(held + send(entry)?));
+            match result {
+                Ok(bytes) => sent += bytes,
+                Err(err) => {
+                    // The batch that failed is held too, so the replay starts where it broke.
+                    if let Some(limit) = hold {
+                        let skipped = entry.hold(batch, first_seq, limit);
+                        entry
+                            .stats
+                            .skipped_packets
+                            .fetch_add(skipped as u64, Ordering::Relaxed);
+                    }
+                    entry.stats.send_errors.fetch_add(1, Ordering::Relaxed);
+                    entry.stats.set_aside.store(true, Ordering::Relaxed);
+                    entry.retry = Some(Retry {
+                        at: Instant::now() + RETRY_FIRST,
+                        delay: RETRY_FIRST,
+                        attempt: None,
+                    });
+                    failed.push((entry.addr, err));
+                }
+            }
+        }
+        if !failed.is_empty()
+            && !self.kind.is_stream()
+            && entries.iter().all(|entry| entry.retry.is_some())
+        {
+            return Err(failed.swap_remove(0).1);
+        }
+        for (addr, err) in failed {
+            warn!(
+                "{}; carrying on without {addr} while it reconnects",
+                error_chain(&err)
+            );
+            if let Some(journal) = &self.journal {
+                journal.record(
+                    EventKind::SendError,
+                    format!("{addr} set aside: {}", error_chain(&err)),
+                );
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Moves the reconnect of a target that was set aside along. Returns true once the
+    /// target takes packets again.
+    fn poll_retry(&self, entry: &mut TargetEntry) -> bool {
+        let Some(retry) = entry.retry.as_mut() else {
+            return true;
+        };
+        let Some(attempt) = &retry.attempt else {
+            if Instant::now() >= retry.at {
+                retry.attempt = Some(self.spawn_reconnect(entry.addr, entry.name.clone()));
+            }
+            return false;
+        };
+        let result = match attempt.try_recv() {
+            Err(TryRecvError::Empty) => return false,
+            Ok(Ok((addr, transport))) => self.sealer(addr).map(|sealer| (addr, transport, sealer)),
+            Ok(Err(err)) => Err(err),
+            Err(TryRecvError::Disconnected) => Err(TransportError::Configure {
+                what: "reconnect thread",
+                source: io::Error::other("failed to start"),
+            }),
+        };
+        match result {
+            Ok((addr, transport, sealer)) => {
+                if addr != entry.addr {
+                    info!(
+                        "{} moved from {} to {addr}",
+                        display_name(entry),
+                        entry.addr
+                    );
+                    self.move_entry(entry, addr);
+                }
+                entry.restart(transport, sealer);
+                // The receiver missed audio while the target was set aside.
+                entry.discontinuity = true;
+                entry.stats.reconnects.fetch_add(1, Ordering::Relaxed);
+                info!("{addr} reconnected; sending to it again");
+                if let Some(journal) = &self.journal {
+                    journal.record(EventKind::Reconnect, format!("{addr} reconnected"));
+                }
+                true
+            }
+            Err(err) => {
+                retry.delay = (retry.delay * 2).min(RETRY_MAX);
+                retry.at = Instant::now() + retry.delay;
+                retry.attempt = None;
+                debug!(
+                    "reconnecting {}: {}; next attempt in {} ms",
+                    entry.addr,
+                    error_chain(&err),
+                    retry.delay.as_millis()
+                );
+                false
+            }
+        }
+    }
+
+    /// Connects to `addr` again on a thread of its own, looking `name` up first.
+    fn spawn_reconnect(&self, addr: SocketAddr, name: Option<TargetName>) -> Receiver<Connected> {
+        let (tx, rx) = bounded(1);
+        let kind = self.kind;
+        let feedback = Arc::clone(&self.feedback);
+        let pairing = self.pairing.clone();
+        let options = self.options;
+        // Should the thread not start, the dropped sender counts as a failed attempt.
+        let _ = thread::Builder::new()
+            .name("reconnect".to_string())
+            .spawn(move || {
+                let addr = name.and_then(|name| name.resolve().ok()).unwrap_or(addr);
+                let connected = connect(kind, addr, &feedback, pairing.as_deref(), options);
+                let _ = tx.send(connected.map(|transport| (addr, transport)));
+            });
+        rx
+    }
+}
+
+fn display_name(entry: &TargetEntry) -> String {
+    match &entry.name {
+        Some(name) => name.to_string(),
+        None => entry.addr.to_string(),
+    }
+}
+
+impl Transport for TargetSet {
+    /// Control and probe packets go out as they are, without renumbering or sealing.
+    fn send_packet(&mut self, packet: &[u8], seq: u32) -> Result<usize, TransportError> {
+        self.fan_out(&[], seq, |entry| entry.transport.send_packet(packet, seq))
+    }
+
+    fn send_batch(&mut self, packets: &[Vec<u8>], first_seq: u32) -> Result<usize, TransportError> {
+        let feedback = Arc::clone(&self.feedback);
+        self.fan_out(packets, first_seq, |entry| {
+            entry.send_batch(packets, first_seq, &feedback)
+        })
+    }
+
+    /// The most any one target dropped, which is what the send loop has to account for.
+    /// Each target flags the gap in its own stream.
+    fn take_dropped(&mut self) -> u32 {
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .iter_mut()
+            .map(|entry| std::mem::take(&mut entry.dropped))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Targets set aside are left out: what their dropped connection had pending is gone,
+    /// and what they hold meanwhile is bounded on its own.
+    fn backlog(&mut self) -> (usize, usize) {
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .iter_mut()
+            .filter(|entry| entry.retry.is_none())
+            .map(|entry| entry.transport.backlog())
+            .max()
+            .unwrap_or((0, 0))
+    }
+
+    /// The description of the first target that has one.
+    fn sdp(&self) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        entries.iter().find_map(|entry| entry.transport.sdp())
+    }
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    target: SocketAddr,
+    _feedback: FeedbackReader,
+    #[cfg(uring_backend)]
+    ring: Option<SendRing>,
+    #[cfg(target_os = "linux")]
+    gso: bool,
+}
+
+impl UdpTransport {
+    pub fn new(
+        target: SocketAddr,
+        feedback: &Arc<FeedbackTracker>,
+        pairing: Option<&SenderPairing>,
+    ) -> Result<Self, TransportError> {
+        let socket = bind_for(target).map_err(TransportError::Bind)?;
+        socket
+            .set_nonblocking(false)
+            .map_err(|source| TransportError::Configure {
+                what: "UDP socket",
+                source,
+            })?;
+        if let Some(pairing) = pairing {
+            pairing.pair_udp(&socket, target)?;
+        }
+        let feedback = FeedbackReader::udp(&socket, target, Arc::clone(feedback))?;
+        Ok(Self {
+            socket,
+            target,
+            _feedback: feedback,
+            #[cfg(uring_backend)]
+            ring: None,
+            #[cfg(target_os = "linux")]
+            gso: false,
+        })
+    }
+
+    /// Hands each batch to the kernel as a few large buffers that it splits into packets
+    /// (`UDP_SEGMENT`), instead of one buffer per packet. Needs Linux 4.18 or later.
+    #[cfg(not(target_os = "linux"))]
+    pub fn with_gso(self) -> Result<Self, TransportError> {
+        Err(TransportError::Configure {
+            what: "UDP segmentation offload",
+            source: io::Error::new(io::ErrorKind::Unsupported, "needs Linux"),
+        })
+    }
+
+    /// Hands each batch to the kernel as a few large buffers that it splits into packets
+    /// (`UDP_SEGMENT`), instead of one buffer per packet. Needs Linux 4.18 or later.
+    #[cfg(target_os = "linux")]
+    pub fn with_gso(self) -> Result<Self, TransportError> {
+        use std::os::fd::AsRawFd;
+
+        let mut size: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: `size` and `len` are valid for the kernel to write an int into.
+        let rc = unsafe {
+            libc::getsockopt(
+                self.socket.as_raw_fd(),
+                libc::SOL_UDP,
+                libc::UDP_SEGMENT,
+                (&mut size as *mut libc::c_int).cast(),
+                &mut len,
+            )
+        };
+        if rc < 0 {
+            return Err(TransportError::Configure {
+                what: "UDP segmentation offload",
+                source: io::Error::last_os_error(),
+            });
+        }
+        Ok(Self { gso: true, ..self })
+    }
+
+    /// Sends batches through an io_uring instead of `sendmmsg`. Needs Linux and the
+    /// `io-uring` feature.
+    #[cfg(not(uring_backend))]
+    pub fn with_io_uring(self) -> Result<Self, TransportError> {
+        Err(io_uring_unsupported())
+    }
+
+    /// Sends batches through an io_uring instead of `sendmmsg`. Needs Linux and the
+    /// `io-uring` feature.
+    #[cfg(uring_backend)]
+    pub fn with_io_uring(self) -> Result<Self, TransportError> {
+        let ring = SendRing::new().map_err(|source| TransportError::Configure {
+            what: "io_uring",
+            source,
+        })?;
+        Ok(Self {
+            ring: Some(ring),
+            ..self
+        })
+    }
+}
+
+#[cfg(not(uring_backend))]
+pub(crate) fn io_uring_unsupported() -> TransportError {
+    TransportError::Configure {
+        what: "io_uring",
+        source: io::Error::new(
+            io::ErrorKind::Unsupported,
+            "needs Linux and a build with the io-uring feature",
+        ),
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_packet(&mut self, packet: &[u8], seq: u32) -> Result<usize, TransportError> {
+        self.socket
+            .send_to(packet, self.target)
+            .map_err(|source| TransportError::Send {
+                what: "UDP packet",
+                seq,
+                source,
+            })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn send_batch(&mut self, packets: &[Vec<u8>], first_seq: u32) -> Result<usize, TransportError> {
+        if let [packet] = packets {
+            return self.send_packet(packet, first_seq);
+        }
+        if self.gso {
+            match send_gso(&self.socket, self.target, packets) {
+                // Devices without checksum offload refuse segmented sends.
+                Err((0, err)) if err.raw_os_error() == Some(libc::EIO) => {
+                    warn!(
+                        "{} refused segmentation offload; sending packets one by one",
+                        self.target
+                    );
+                    self.gso = false;
+                }
+                sent => {
+                    return sent.map_err(|(sent, source)| TransportError::Send {
+                        what: "UDP segmented batch",
+                        seq: first_seq.wrapping_add(sent as u32),
+                        source,
+                    })
+                }
+            }
+        }
+        #[cfg(uring_backend)]
+        let sent = match &mut self.ring {
+            Some(ring) => ring.send_all(&self.socket, self.target, packets),
+            None => send_mmsg(&self.socket, self.target, packets),
+        };
+        #[cfg(not(uring_backend))]
+        let sent = send_mmsg(&self.socket, self.target, packets);
+        sent.map_err(|(sent, source)| TransportError::Send {
+            what: "UDP packet batch",
+            seq: first_seq.wrapping_add(sent as u32),
+            source,
+        })
+    }
+}
+
+/// Sends every packet to `target` with as few `sendmmsg` calls as the kernel allows.
+/// On failure, returns how many packets went out before it.
+#[cfg(target_os = "linux")]
+fn send_mmsg(
+    socket: &UdpSocket,
+    target: SocketAddr,
+    packets: &[Vec<u8>],
+) -> Result<usize, (usize, io::Error)> {
+    let (addr, addr_len) = sockaddr(target);
+    let mut iovecs: Vec<libc::iovec> = packets
+        .iter()
+        .map(|packet| libc::iovec {
+            iov_base: packet.as_ptr() as *mut libc::c_void,
+            iov_len: packet.len(),
+        })
+        .collect();
+    let mut messages: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .map(|iovec| {
+            // SAFETY: all-zero is a valid `msghdr`; the fields that matter are set below.
+            let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+            header.msg_name = (&addr as *const libc::sockaddr_storage).cast_mut().cast();
+            header.msg_namelen = addr_len;
+            header.msg_iov = iovec;
+            header.msg_iovlen = 1;
+            libc::mmsghdr {
+                msg_hdr: header,
+                msg_len: 0,
+            }
+        })
+        .collect();
+    send_messages(socket, &mut messages)
+}
+
+/// Sends each run of same-sized packets as one segmented datagram, with as few
+/// `sendmmsg` calls as the kernel allows. On failure, returns how many packets went out
+/// before it.
+#[cfg(target_os = "linux")]
+fn send_gso(
+    socket: &UdpSocket,
+    target: SocketAddr,
+    packets: &[Vec<u8>],
+) -> Result<usize, (usize, io::Error)> {
+    let (addr, addr_len) = sockaddr(target);
+    let mut iovecs: Vec<libc::iovec> = packets
+        .iter()
+        .map(|packet| libc::iovec {
+            iov_base: packet.as_ptr() as *mut libc::c_void,
+            iov_len: packet.len(),
+        })
+        .collect();
+    let runs = segment_runs(packets);
+    // One aligned `cmsghdr` plus the segment size for each run.
+    let mut controls = vec![[0u64; 4]; runs.len()];
+    let mut messages: Vec<libc::mmsghdr> = runs
+        .iter()
+        .zip(controls.iter_mut())
+        .map(|(run, control)| {
+            // SAFETY: all-zero is a valid `msghdr`; the fields that matter are set below.
+            let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+            header.msg_name = (&addr as *const libc::sockaddr_storage).cast_mut().cast();
+            header.msg_namelen = addr_len;
+            header.msg_iov = iovecs[run.start..].as_mut_ptr();
+            header.msg_iovlen = run.len();
+            if run.len() > 1 {
+                let segment = packets[run.start].len() as u16;
+                // SAFETY: `control` is 8-byte aligned and holds a `cmsghdr` and a `u16`.
+                unsafe {
+                    header.msg_control = control.as_mut_ptr().cast();
+                    header.msg_controllen = libc::CMSG_SPACE(2) as usize;
+                    let cmsg = libc::CMSG_FIRSTHDR(&header);
+                    (*cmsg).cmsg_level = libc::SOL_UDP;
+                    (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+                    (*cmsg).cmsg_len = libc::CMSG_LEN(2) as usize;
+                    std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast(), segment);
+                }
+            }
+            libc::mmsghdr {
+                msg_hdr: header,
+                msg_len: 0,
+            }
+        })
+        .collect();
+    send_messages(socket, &mut messages).map_err(|(sent, err)| (runs[sent].start, err))
+}
+
+/// Splits `packets` into runs the kernel can segment: every packet the size of the
+/// first but the last, which may be shorter, within the per-send limits.
+#[cfg(target_os = "linux")]
+fn segment_runs(packets: &[Vec<u8>]) -> Vec<std::ops::Range<usize>> {
+    const MAX_SEGMENTS: usize = 64;
+    const MAX_BYTES: usize = 65_000;
+
+    let mut runs = Vec::new();
+    let mut start = 0;
+    while start < packets.len() {
+        let segment = packets[start].len();
+        let mut end = start + 1;
+        let mut bytes = segment;
+        while end < packets.len()
+            && end - start < MAX_SEGMENTS
+            && bytes + packets[end].len() <= MAX_BYTES
+            && packets[end].len() <= segment
+        {
+            bytes += packets[end].len();
+            end += 1;
+            if packets[end - 1].len() < segment {
+                break;
+            }
+        }
+        runs.push(start..end);
+        start = end;
+    }
+    runs
+}
+
+/// Sends every message with as few `sendmmsg` calls as the kernel allows and returns the
+/// bytes sent. On failure, returns how many messages went out before it.
+#[cfg(target_os = "linux")]
+fn send_messages(
+    socket: &UdpSocket,
+    messages: &mut [libc::mmsghdr],
+) -> Result<usize, (usize, io::Error)> {
+    use std::os::fd::AsRawFd;
+
+    let mut sent = 0;
+    while sent < messages.len() {
+        // SAFETY: the headers point into buffers the caller keeps alive across the call,
+        // and the kernel writes only `msg_len`.
+        let rc = unsafe {
+            libc::sendmmsg(
+                socket.as_raw_fd(),
+                messages[sent..].as_mut_ptr(),
+                (messages.len() - sent) as libc::c_uint,
+                0,
+            )
+        };
+        if rc < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err((sent, err));
+        }
+        sent += rc as usize;
+    }
+    Ok(messages
+        .iter()
+        .map(|message| message.msg_len as usize)
+        .sum())
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    // SAFETY: all-zero is a valid `sockaddr_storage`.
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sin = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: addr.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from_ne_bytes(addr.ip().octets()),
+                },
+                sin_zero: [0; 8],
+            };
+            // SAFETY: `sockaddr_storage` is large and aligned enough for any address.
+            unsafe { std::ptr::write((&mut storage as *mut libc::sockaddr_storage).cast(), sin) };
+            std::mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: addr.port().to_be(),
+                sin6_flowinfo: addr.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: addr.ip().octets(),
+                },
+                sin6_scope_id: addr.scope_id(),
+            };
+            // SAFETY: as above.
+            unsafe { std::ptr::write((&mut storage as *mut libc::sockaddr_storage).cast(), sin6) };
+            std::mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+/// Repackages the stream for receivers that speak another protocol.
+pub trait Repacketizer: Send {
+    /// What the packets are called in errors, such as `VBAN packet`.
+    fn packet_name(&self) -> &'static str;
+
+    /// The packets carrying `packet`, one of ours. Control and probe packets have no place
+    /// in other protocols and give none.
+    fn packetize(&mut self, packet: &[u8]) -> Result<&[Vec<u8>], ProtocolError>;
+
+    /// A packet due now for another address, such as a session announcement.
+    fn announcement(&mut self) -> Option<(SocketAddr, &[u8])> {
+        None
+    }
+
+    /// The packet that takes the announcement back when the stream stops.
+    fn withdrawal(&mut self) -> Option<(SocketAddr, &[u8])> {
+        None
+    }
+
+    /// See `Transport::sdp`.
+    fn sdp(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Sends the stream over UDP in another protocol's packets. Those receivers answer
+/// nothing, so there are no reports, and know nothing of pairing or sealing.
+pub struct InteropTransport {
+    socket: UdpSocket,
+    target: SocketAddr,
+    packetizer: Box<dyn Repacketizer>,
+}
+
+impl InteropTransport {
+    pub fn new(
+        target: SocketAddr,
+        packetizer: Box<dyn Repacketizer>,
+    ) -> Result<Self, TransportError> {
+        let socket = bind_for(target).map_err(TransportError::Bind)?;
+        Ok(Self {
+            socket,
+            target,
+            packetizer,
+        })
+    }
+
+    /// Lets multicast packets cross up to `hops` routers, rather than stay on the link.
+    /// IPv6 sockets keep the system's hop limit.
+    pub fn with_multicast_ttl(self, hops: u32) -> Result<Self, TransportError> {
+        if self.target.is_ipv6() {
+            return Ok(self);
+        }
+        self.socket
+            .set_multicast_ttl_v4(hops)
+            .map_err(|source| TransportError::Configure {
+                what: "multicast TTL on sender socket",
+                source,
+            })?;
+        Ok(self)
+    }
+}
+
+impl Transport for InteropTransport {
+    fn send_packet(&mut self, packet: &[u8], seq: u32) -> Result<usize, TransportError> {
+        let what = self.packetizer.packet_name();
+        let packets =
+            self.packetizer
+                .packetize(packet)
+                .map_err(|source| TransportError::Repacketize {
+                    what,
+                    addr: self.target,
+                    source,
+                })?;
+        let mut sent = 0;
+        for out in packets {
+            sent += self
+                .socket
+                .send_to(out, self.target)
+                .map_err(|source| TransportError::Send { what, seq, source })?;
+        }
+        // Announcements go out with the audio; the stream does not stop for them.
+        if let Some((addr, announcement)) = self.packetizer.announcement() {
+            if let Err(err) = self.socket.send_to(announcement, addr) {
+                debug!("failed to send announcement to {addr}: {err}");
+            }
+        }
+        Ok(sent)
+    }
+
+    fn sdp(&self) -> Option<String> {
+        self.packetizer.sdp()
+    }
+}
+
+impl Drop for InteropTransport {
+    fn drop(&mut self) {
+        if let Some((addr, withdrawal)) = self.packetizer.withdrawal() {
+            let _ = self.socket.send_to(withdrawal, addr);
+        }
+    }
+}
+
+pub struct TcpTransport {
+    writer: Arc<Mutex<TcpWriter>>,
+    _feedback: FeedbackReader,
+}
+
+impl TcpTransport {
+    pub fn connect(
+        target: SocketAddr,
+        feedback: &Arc<FeedbackTracker>,
+        pairing: Option<&SenderPairing>,
+    ) -> Result<Self, TransportError> {
+        let mut stream = TcpStream::connect(target).map_err(|source| TransportError::Connect {
+            addr: target,
+            source,
+        })?;
+        stream
+            .set_nodelay(true)
+            .map_err(|source| TransportError::Configure {
+                what: "TCP_NODELAY on sender socket",
+                source,
+            })?;
+        stream
+            .set_write_timeout(Some(TCP_WRITE_TIMEOUT))
+            .map_err(|source| TransportError::Configure {
+                what: "write timeout on sender socket",
+                source,
+            })?;
+        FeedbackReader::admit_tcp(&mut stream, target, feedback)?;
+        if let Some(pairing) = pairing {
+            pairing.pair_tcp(&mut stream, target)?;
+        }
+        let writer = Arc::new(Mutex::new(TcpWriter {
+            stream,
+            pending: VecDeque::new(),
+            written: 0,
+            backlog: None,
+            dropped: 0,
+        }));
+        let feedback = FeedbackReader::tcp(Arc::clone(&writer), target, Arc::clone(feedback))?;
+        Ok(Self {
+            writer,
+            _feedback: feedback,
+        })
+    }
+
+    /// Writes without blocking and keeps what the socket has no room for, dropping the
+    /// oldest once more than `packets` wait, so a receiver that stalls costs audio rather
+    /// than delay. `TargetSet` flags the next packet with `FLAG_DISCONTINUITY`.
+    pub fn with_backlog(self, packets: usize) -> Self {
+        self.writer.lock().unwrap().backlog = Some(packets.max(1));
+        self
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send_packet(&mut self, packet: &[u8], seq: u32) -> Result<usize, TransportError> {
+        self.send_framed(&[packet], seq)
+    }
+
+    fn send_batch(&mut self, packets: &[Vec<u8>], first_seq: u32) -> Result<usize, TransportError> {
+        self.send_framed(packets, first_seq)
+    }
+
+    fn take_dropped(&mut self) -> u32 {
+        std::mem::take(&mut self.writer.lock().unwrap().dropped)
+    }
+
+    fn backlog(&mut self) -> (usize, usize) {
+        let writer = self.writer.lock().unwrap();
+        let bytes: usize = writer.pending.iter().map(Vec::len).sum();
+        (writer.pending.len(), bytes - writer.written)
+    }
+}
+
+impl TcpTransport {
+    /// Writes every packet behind its little-endian length, gathered into as few writes as
+    /// the socket takes.
+    fn send_framed<P: AsRef<[u8]>>(
+        &mut self,
+        packets: &[P],
+        first_seq: u32,
+    ) -> Result<usize, TransportError> {
+        let mut prefixes = Vec::with_capacity(packets.len());
+        for packet in packets {
+            let len = packet.as_ref().len();
+            let len = u16::try_from(len).map_err(|_| TransportError::PacketTooLarge { len })?;
+            prefixes.push(len.to_le_bytes());
+        }
+        let mut writer = self.writer.lock().unwrap();
+        if writer.backlog.is_some() {
+            for (prefix, packet) in prefixes.iter().zip(packets) {
+                writer.queue(prefix, packet.as_ref());
+            }
+            return writer.flush().map_err(|source| TransportError::Send {
+                what: "TCP packet",
+                seq: first_seq,
+                source,
+            });
+        }
+        let mut slices: Vec<IoSlice> = prefixes
+            .iter()
+            .zip(packets)
+            .flat_map(|(prefix, packet)| [IoSlice::new(prefix), IoSlice::new(packet.as_ref())])
+            .collect();
+        let total: usize = slices.iter().map(|slice| slice.len()).sum();
+
+        let stream = &mut writer.stream;
+        let mut remaining = &mut slices[..];
+        let mut written = 0;
+        while !remaining.is_empty() {
+            let result = match stream.write_vectored(remaining) {
+                Ok(0) => Err(io::ErrorKind::WriteZero.into()),
+                result => result,
+            };
+            match result {
+                Ok(n) => {
+                    written += n;
+                    IoSlice::advance_slices(&mut remaining, n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(source) => {
+                    // Names the first packet that did not go out whole.
+                    let mut end = 0;
+                    let done = packets
+                        .iter()
+                        .take_while(|packet| {
+                            end += packet.as_ref().len() + 2;
+                            end <= written
+                        })
+                        .count();
+                    return Err(TransportError::Send {
+                        what: "TCP packet",
+                        seq: first_seq.wrapping_add(done as u32),
+                        source,
+                    });
+                }
+            }
+        }
+        Ok(total)
+    }
+}
+
+/// The sending end of a TCP target, shared with the feedback reader, which answers clock
+/// pings on it.
+pub(crate) struct TcpWriter {
+    stream: TcpStream,
+    /// Length-prefixed frames the socket had no room for yet, the first possibly in part;
+    /// only kept with a backlog.
+    pending: VecDeque<Vec<u8>>,
+    /// Bytes of the first pending frame already written.
+    written: usize,
+    /// Frames kept pending at most; `None` writes blocking instead.
+    backlog: Option<usize>,
+    /// Frames dropped for the backlog since the send loop last asked.
+    dropped: u32,
+}
+
+impl TcpWriter {
+    pub(crate) fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Writes one length-prefixed frame, after any pending ones.
+    pub(crate) fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let prefix = (frame.len() as u16).to_le_bytes();
+        if self.backlog.is_some() {
+            self.queue(&prefix, frame);
+            return self.flush().map(drop);
+        }
+        let mut buf = Vec::with_capacity(frame.len() + 2);
+        buf.extend_from_slice(&prefix);
+        buf.extend_from_slice(frame);
+        self.stream.write_all(&buf)
+    }
+
+    /// Adds a frame behind the pending ones, dropping the oldest that were not started
+    /// while more than the backlog wait.
+    fn queue(&mut self, prefix: &[u8; 2], packet: &[u8]) {
+        let mut frame = Vec::with_capacity(packet.len() + 2);
+        frame.extend_from_slice(prefix);
+        frame.extend_from_slice(packet);
+        self.pending.push_back(frame);
+        let backlog = self.backlog.unwrap_or(usize::MAX);
+        // A frame cut off halfway would desync the stream, so a started one stays.
+        let started = usize::from(self.written > 0);
+        while self.pending.len() > backlog.max(started + 1) {
+            self.pending.remove(started);
+            self.dropped = self.dropped.saturating_add(1);
+        }
+    }
+
+    /// Writes pending frames until the socket would block; returns the bytes written.
+    fn flush(&mut self) -> io::Result<usize> {
+        self.stream.set_nonblocking(true)?;
+        let result = self.write_pending();
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+
+    fn write_pending(&mut self) -> io::Result<usize> {
+        let mut total = 0;
+        while let Some(first) = self.pending.front() {
+            let mut slices = vec![IoSlice::new(&first[self.written..])];
+            slices.extend(self.pending.iter().skip(1).map(|frame| IoSlice::new(frame)));
+            let mut n = match self.stream.write_vectored(&slices) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            };
+            total += n;
+            while let Some(frame) = self.pending.front() {
+                let left = frame.len() - self.written;
+                if n < left {
+                    self.written += n;
+                    break;
+                }
+                n -= left;
+                self.written = 0;
+                self.pending.pop_front();
+            }
+        }
+        Ok(total)
+    }
+}
+
+pub(crate) fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
@@ -0,0 +1,443 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Gauge, Paragraph, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+use serde_json::Value;
+
+use crate::control::{ControlCommand, Router};
+use crate::logging::LogBuffer;
+
+const HISTORY: usize = 120;
+const INPUT_POLL: Duration = Duration::from_millis(100);
+const GAIN_STEP_DB: f32 = 1.0;
+/// Level meter range; quieter signals show as an empty bar.
+const METER_FLOOR_DB: f64 = -60.0;
+
+/// The terminal dashboard; dropping it restores the terminal.
+pub struct Dashboard {
+    running: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Dashboard {
+    pub fn spawn(router: Router, logs: LogBuffer, interval: Duration) -> Result<Self> {
+        let terminal = ratatui::try_init().context("failed to set up the terminal for --tui")?;
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = Arc::clone(&running);
+        let handle = thread::Builder::new()
+            .name("tui".to_string())
+            .spawn(move || {
+                let mut app = App::new(router, logs, interval);
+                app.run(terminal, &thread_running);
+                ratatui::restore();
+            })
+            .context("failed to spawn dashboard thread")?;
+        Ok(Self {
+            running,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// One session's latest status and the per-interval values derived from it.
+#[derive(Default)]
+struct SessionView {
+    status: Value,
+    last_stats: Option<(Instant, Value)>,
+    kbps: VecDeque<u64>,
+    backlog: VecDeque<u64>,
+    drops: VecDeque<u64>,
+    capsend_us: VecDeque<u64>,
+    stage_ms: [f64; 4],
+    level_db: f64,
+    active_pct: f64,
+    error: Option<String>,
+}
+
+impl SessionView {
+    fn update(&mut self, status: Value) {
+        let now = Instant::now();
+        let stats = status["stats"].clone();
+        if let Some((at, last)) = &self.last_stats {
+            let secs = (now - *at).as_secs_f64().max(f64::EPSILON);
+            let delta = |field: &str| counter(&stats, field).saturating_sub(counter(last, field));
+            let avg_ms = |sum: &str, count: &str| match delta(count) {
+                0 => 0.0,
+                count => delta(sum) as f64 / count as f64 / 1000.0,
+            };
+            push(
+                &mut self.kbps,
+                (delta("sent_bytes") as f64 * 8.0 / 1000.0 / secs) as u64,
+            );
+            push(&mut self.drops, delta("capture_drops"));
+            let capsend_ms = avg_ms("capture_to_send_us_sum", "capture_to_send_count");
+            push(&mut self.capsend_us, (capsend_ms * 1000.0) as u64);
+            self.stage_ms = [
+                avg_ms("capture_queue_us_sum", "capture_queue_count"),
+                capsend_ms,
+                avg_ms("packet_build_us_sum", "packet_build_count"),
+                avg_ms("socket_send_us_sum", "socket_send_count"),
+            ];
+            let samples = delta("captured_samples");
+            (self.level_db, self.active_pct) = if samples > 0 {
+                let avg_abs = delta("captured_abs_sum") as f64 / samples as f64;
+                (
+                    20.0 * (avg_abs.max(1.0) / 32768.0).log10(),
+                    delta("captured_nonzero_samples") as f64 * 100.0 / samples as f64,
+                )
+            } else {
+                (f64::NEG_INFINITY, 0.0)
+            };
+        }
+        push(&mut self.backlog, status["backlog"].as_u64().unwrap_or(0));
+        self.last_stats = Some((now, stats));
+        self.status = status;
+        self.error = None;
+    }
+}
+
+fn counter(stats: &Value, field: &str) -> u64 {
+    stats[field].as_u64().unwrap_or(0)
+}
+
+fn push(history: &mut VecDeque<u64>, value: u64) {
+    if history.len() == HISTORY {
+        history.pop_front();
+    }
+    history.push_back(value);
+}
+
+struct App {
+    router: Router,
+    logs: LogBuffer,
+    interval: Duration,
+    names: Vec<String>,
+    selected: usize,
+    views: HashMap<String, SessionView>,
+    message: Option<String>,
+}
+
+impl App {
+    fn new(router: Router, logs: LogBuffer, interval: Duration) -> Self {
+        let names = router.names();
+        Self {
+            router,
+            logs,
+            interval,
+            names,
+            selected: 0,
+            views: HashMap::new(),
+            message: None,
+        }
+    }
+
+    fn run(&mut self, mut terminal: DefaultTerminal, running: &AtomicBool) {
+        let mut next_poll = Instant::now();
+        while running.load(Ordering::Relaxed) {
+            if Instant::now() >= next_poll {
+                self.poll();
+                next_poll = Instant::now() + self.interval;
+            }
+            if terminal.draw(|frame| self.draw(frame)).is_err() {
+                break;
+            }
+            match event::poll(INPUT_POLL) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(_) => break,
+            }
+            let Ok(Event::Key(key)) = event::read() else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => {
+                    self.router.broadcast(ControlCommand::Quit);
+                    break;
+                }
+                KeyCode::Tab | KeyCode::Right => {
+                    self.selected = (self.selected + 1) % self.names.len().max(1);
+                }
+                KeyCode::BackTab | KeyCode::Left => {
+                    let count = self.names.len().max(1);
+                    self.selected = (self.selected + count - 1) % count;
+                }
+                KeyCode::Char('m') => {
+                    let muted = self.status()["muted"].as_bool().unwrap_or(false);
+                    let command = if muted {
+                        ControlCommand::Unmute
+                    } else {
+                        ControlCommand::Mute
+                    };
+                    self.send(command);
+                }
+                KeyCode::Char('+') | KeyCode::Char('=') | KeyCode::Up => {
+                    self.nudge_gain(GAIN_STEP_DB)
+                }
+                KeyCode::Char('-') | KeyCode::Down => self.nudge_gain(-GAIN_STEP_DB),
+                KeyCode::Char('p') => {
+                    let paused = self.status()["paused"].as_bool().unwrap_or(false);
+                    let command = if paused {
+                        ControlCommand::Resume
+                    } else {
+                        ControlCommand::Pause
+                    };
+                    self.send(command);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn session(&self) -> Option<&str> {
+        self.names.get(self.selected).map(String::as_str)
+    }
+
+    fn status(&self) -> &Value {
+        self.session()
+            .and_then(|name| self.views.get(name))
+            .map(|view| &view.status)
+            .unwrap_or(&Value::Null)
+    }
+
+    fn nudge_gain(&mut self, step: f32) {
+        let gain_db = self.status()["gain_db"].as_f64().unwrap_or(0.0) as f32;
+        self.send(ControlCommand::SetGain(gain_db + step));
+    }
+
+    /// Sends `command` to the selected session and refreshes its status.
+    fn send(&mut self, command: ControlCommand) {
+        let session = self.session().map(str::to_string);
+        self.message = self
+            .router
+            .request(session.as_deref(), command)
+            .err()
+            .map(|err| format!("command failed: {err}"));
+        self.poll();
+    }
+
+    fn poll(&mut self) {
+        for name in &self.names {
+            let view = self.views.entry(name.clone()).or_default();
+            match self.router.request(Some(name), ControlCommand::Status) {
+                Ok(status) => view.update(status),
+                Err(err) => view.error = Some(err),
+            }
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [header, graphs, stages, logs, footer] = Layout::vertical([
+            Constraint::Length(4),
+            Constraint::Min(10),
+            Constraint::Length(4),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let empty = SessionView::default();
+        let view = self
+            .session()
+            .and_then(|name| self.views.get(name))
+            .unwrap_or(&empty);
+        self.draw_header(frame, header, view);
+        draw_graphs(frame, graphs, view);
+        draw_stages(frame, stages, view);
+
+        let lines = self.logs.tail(logs.height.saturating_sub(2) as usize);
+        frame.render_widget(
+            Paragraph::new(lines.into_iter().map(Line::from).collect::<Vec<_>>())
+                .block(Block::bordered().title("Log")),
+            logs,
+        );
+
+        let help = self
+            .message
+            .clone()
+            .unwrap_or_else(|| "q quit  m mute  +/- gain  p pause  Tab next session".to_string());
+        frame.render_widget(
+            Paragraph::new(help).style(Style::default().add_modifier(Modifier::DIM)),
+            footer,
+        );
+    }
+
+    fn draw_header(&self, frame: &mut Frame, area: Rect, view: &SessionView) {
+        let status = &view.status;
+        let name = self.session().unwrap_or("-");
+        let mut flags = Vec::new();
+        if status["muted"].as_bool().unwrap_or(false) {
+            flags.push(Span::styled(
+                " MUTED ",
+                Style::default().fg(Color::Black).bg(Color::Yellow),
+            ));
+        }
+        if status["paused"].as_bool().unwrap_or(false) {
+            flags.push(Span::styled(
+                " PAUSED ",
+                Style::default().fg(Color::Black).bg(Color::Cyan),
+            ));
+        }
+        if let Some(err) = &view.error {
+            flags.push(Span::styled(
+                format!(" {err} "),
+                Style::default().fg(Color::Red),
+            ));
+        }
+        let targets = status["targets"]
+            .as_array()
+            .map(|targets| {
+                targets
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .unwrap_or_default();
+        let text = vec![
+            Line::from(format!(
+                "{} ({}) -> {} over {}",
+                status["source"].as_str().unwrap_or("-"),
+                status["source_name"].as_str().unwrap_or("-"),
+                targets,
+                status["transport"].as_str().unwrap_or("-"),
+            )),
+            Line::from(
+                [
+                    vec![Span::raw(format!(
+                        "{} Hz, {} ch, {} ms frames, gain {:+.1} dB ",
+                        status["sample_rate"].as_u64().unwrap_or(0),
+                        status["channels"].as_u64().unwrap_or(0),
+                        status["frame_ms"].as_f64().unwrap_or(0.0),
+                        status["gain_db"].as_f64().unwrap_or(0.0),
+                    ))],
+                    flags,
+                ]
+                .concat(),
+            ),
+        ];
+        let title = format!(
+            "Session {name} [{}/{}]",
+            self.selected + 1,
+            self.names.len()
+        );
+        frame.render_widget(
+            Paragraph::new(text).block(Block::bordered().title(title)),
+            area,
+        );
+    }
+}
+
+fn draw_graphs(frame: &mut Frame, area: Rect, view: &SessionView) {
+    let [top, bottom] = Layout::vertical([Constraint::Fill(1); 2]).areas(area);
+    let [bitrate, backlog] = Layout::horizontal([Constraint::Fill(1); 2]).areas(top);
+    let [drops, latency] = Layout::horizontal([Constraint::Fill(1); 2]).areas(bottom);
+    let last = |history: &VecDeque<u64>| history.back().copied().unwrap_or(0);
+
+    let graphs = [
+        (
+            bitrate,
+            &view.kbps,
+            format!("Bitrate {} kbps", last(&view.kbps)),
+            Color::Green,
+        ),
+        (
+            backlog,
+            &view.backlog,
+            format!("Queue {} chunks", last(&view.backlog)),
+            Color::Cyan,
+        ),
+        (
+            drops,
+            &view.drops,
+            format!("Drops {}", last(&view.drops)),
+            Color::Red,
+        ),
+        (
+            latency,
+            &view.capsend_us,
+            format!(
+                "Capture->send {:.2} ms",
+                last(&view.capsend_us) as f64 / 1000.0
+            ),
+            Color::Magenta,
+        ),
+    ];
+    for (area, history, title, color) in graphs {
+        // Newest samples on the right edge.
+        let width = area.width.saturating_sub(2) as usize;
+        let data: Vec<u64> = history
+            .iter()
+            .skip(history.len().saturating_sub(width))
+            .copied()
+            .collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(title))
+                .data(&data)
+                .style(Style::default().fg(color)),
+            area,
+        );
+    }
+}
+
+fn draw_stages(frame: &mut Frame, area: Rect, view: &SessionView) {
+    let [stages, meter] = Layout::horizontal([Constraint::Fill(1); 2]).areas(area);
+    let [capq, capsend, pkt, sock] = view.stage_ms;
+    frame.render_widget(
+        Paragraph::new(vec![
+            Line::from(format!(
+                "capture queue {capq:.3} ms   capture->send {capsend:.3} ms"
+            )),
+            Line::from(format!(
+                "packet build  {pkt:.3} ms   socket send   {sock:.3} ms"
+            )),
+        ])
+        .block(Block::bordered().title("Stage timings (avg)")),
+        stages,
+    );
+
+    let ratio = if view.level_db.is_finite() {
+        ((view.level_db - METER_FLOOR_DB) / -METER_FLOOR_DB).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let label = if view.level_db.is_finite() {
+        format!("{:.1} dBFS, {:.0}% active", view.level_db, view.active_pct)
+    } else {
+        "silence".to_string()
+    };
+    let color = match view.level_db {
+        db if db > -6.0 => Color::Red,
+        db if db > -18.0 => Color::Yellow,
+        _ => Color::Green,
+    };
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::bordered().title("Level"))
+            .gauge_style(Style::default().fg(color))
+            .ratio(ratio)
+            .label(label),
+        meter,
+    );
+}
@@ -0,0 +1,587 @@
+//! A minimal io_uring binding for the UDP paths on Linux, over raw system calls.
+//!
+//! The sender hands each batch of packets to the kernel as linked `sendmsg` operations in
+//! one `io_uring_enter`. The receiver keeps one multishot `recvmsg` armed over a ring of
+//! registered buffers, so datagrams that arrive while it is busy cost no system call to
+//! collect. Needs Linux 6.0 or later.
+
+use std::io;
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+use std::time::Duration;
+
+use crate::transport::sockaddr;
+
+/// Submission slots of a sender ring; larger batches go out in several calls.
+const SEND_ENTRIES: u32 = 64;
+const RECV_ENTRIES: u32 = 8;
+/// Registered receive buffers; a power of two.
+const RECV_BUFFERS: u16 = 64;
+/// Each buffer holds the `recvmsg` header and the source address ahead of the datagram.
+const RECV_BUFFER_SIZE: usize = 9216;
+const RECV_BUFFER_GROUP: u16 = 0;
+
+const OFF_SQ_RING: i64 = 0;
+const OFF_CQ_RING: i64 = 0x800_0000;
+const OFF_SQES: i64 = 0x1000_0000;
+const FEAT_EXT_ARG: u32 = 1 << 8;
+const ENTER_GETEVENTS: u32 = 1;
+const ENTER_EXT_ARG: u32 = 1 << 3;
+const OP_SENDMSG: u8 = 9;
+const OP_RECVMSG: u8 = 10;
+const SQE_IO_LINK: u8 = 1 << 2;
+const SQE_BUFFER_SELECT: u8 = 1 << 5;
+const RECV_MULTISHOT: u16 = 1 << 1;
+const CQE_F_BUFFER: u32 = 1;
+const CQE_F_MORE: u32 = 1 << 1;
+const CQE_BUFFER_SHIFT: u32 = 16;
+const REGISTER_PBUF_RING: u32 = 22;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_group: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+#[repr(C)]
+struct GeteventsArg {
+    sigmask: u64,
+    sigmask_sz: u32,
+    min_wait_usec: u32,
+    ts: u64,
+}
+
+#[repr(C)]
+struct KernelTimespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct BufReg {
+    ring_addr: u64,
+    ring_entries: u32,
+    bgid: u16,
+    flags: u16,
+    resv: [u64; 3],
+}
+
+#[repr(C)]
+struct Buf {
+    addr: u64,
+    len: u32,
+    bid: u16,
+    resv: u16,
+}
+
+/// Leads every multishot `recvmsg` buffer, followed by the address and the payload.
+#[repr(C)]
+struct RecvmsgOut {
+    namelen: u32,
+    controllen: u32,
+    payloadlen: u32,
+    flags: u32,
+}
+
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mmap {
+    /// Maps `len` bytes of the ring at `offset`, or anonymous memory without a ring.
+    fn new(ring: Option<&OwnedFd>, len: usize, offset: i64) -> io::Result<Self> {
+        let (fd, flags) = match ring {
+            Some(fd) => (fd.as_raw_fd(), libc::MAP_SHARED | libc::MAP_POPULATE),
+            None => (-1, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS),
+        };
+        // SAFETY: a fresh mapping that nothing else refers to; unmapped in `drop`.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    /// # Safety
+    /// `offset` plus the size of `T` must lie inside the mapping, aligned for `T`.
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        self.ptr.add(offset as usize).cast()
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: maps exactly what `new` mapped.
+        unsafe {
+            libc::munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
+
+struct Ring {
+    fd: OwnedFd,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_entries: u32,
+    sq_array: *mut u32,
+    sqes: *mut Sqe,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const Cqe,
+    _sq_map: Mmap,
+    _cq_map: Mmap,
+    _sqes_map: Mmap,
+}
+
+// SAFETY: the pointers all point into mappings the ring owns, and every access to them
+// goes through `&mut self`.
+unsafe impl Send for Ring {}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Self> {
+        let mut params = Params::default();
+        // SAFETY: `params` is a valid `io_uring_params` for the kernel to fill in.
+        let fd = unsafe { libc::syscall(libc::SYS_io_uring_setup, entries, &mut params) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: a new descriptor that nothing else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+        if params.features & FEAT_EXT_ARG == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "io_uring on this kernel is too old",
+            ));
+        }
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len =
+            params.cq_off.cqes as usize + params.cq_entries as usize * std::mem::size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * std::mem::size_of::<Sqe>();
+        let sq_map = Mmap::new(Some(&fd), sq_len, OFF_SQ_RING)?;
+        let cq_map = Mmap::new(Some(&fd), cq_len, OFF_CQ_RING)?;
+        let sqes_map = Mmap::new(Some(&fd), sqes_len, OFF_SQES)?;
+        // SAFETY: the kernel reports these offsets inside the mappings it sized.
+        unsafe {
+            Ok(Self {
+                sq_head: sq_map.at(params.sq_off.head),
+                sq_tail: sq_map.at(params.sq_off.tail),
+                sq_mask: *sq_map.at::<u32>(params.sq_off.ring_mask),
+                sq_entries: *sq_map.at::<u32>(params.sq_off.ring_entries),
+                sq_array: sq_map.at(params.sq_off.array),
+                sqes: sqes_map.at(0),
+                cq_head: cq_map.at(params.cq_off.head),
+                cq_tail: cq_map.at(params.cq_off.tail),
+                cq_mask: *cq_map.at::<u32>(params.cq_off.ring_mask),
+                cqes: cq_map.at(params.cq_off.cqes),
+                fd,
+                _sq_map: sq_map,
+                _cq_map: cq_map,
+                _sqes_map: sqes_map,
+            })
+        }
+    }
+
+    /// Queues an operation for the next `enter`; false when the queue is full.
+    fn push(&mut self, sqe: Sqe) -> bool {
+        // SAFETY: head and tail live in the SQ mapping; the slot is ours until the tail
+        // moves past it.
+        unsafe {
+            let head = (*self.sq_head).load(Ordering::Acquire);
+            let tail = (*self.sq_tail).load(Ordering::Relaxed);
+            if tail.wrapping_sub(head) >= self.sq_entries {
+                return false;
+            }
+            let index = tail & self.sq_mask;
+            self.sqes.add(index as usize).write(sqe);
+            self.sq_array.add(index as usize).write(index);
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+        true
+    }
+
+    /// Submits what was queued and waits for `wait` completions, at most `timeout`.
+    fn enter(&mut self, wait: u32, timeout: Option<Duration>) -> io::Result<()> {
+        let ts = timeout.map(|timeout| KernelTimespec {
+            tv_sec: timeout.as_secs() as i64,
+            tv_nsec: timeout.subsec_nanos() as i64,
+        });
+        let arg = GeteventsArg {
+            sigmask: 0,
+            sigmask_sz: 0,
+            min_wait_usec: 0,
+            ts: ts
+                .as_ref()
+                .map_or(0, |ts| ts as *const KernelTimespec as u64),
+        };
+        let flags = ENTER_EXT_ARG | if wait > 0 { ENTER_GETEVENTS } else { 0 };
+        loop {
+            // SAFETY: head and tail live in the SQ mapping.
+            let queued = unsafe {
+                (*self.sq_tail)
+                    .load(Ordering::Relaxed)
+                    .wrapping_sub((*self.sq_head).load(Ordering::Acquire))
+            };
+            // SAFETY: `arg` and the timespec it points to outlive the call.
+            let rc = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd.as_raw_fd(),
+                    queued,
+                    wait,
+                    flags,
+                    &arg as *const GeteventsArg,
+                    std::mem::size_of::<GeteventsArg>(),
+                )
+            };
+            if rc >= 0 {
+                return Ok(());
+            }
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::EINTR) => continue,
+                Some(libc::ETIME) => return Err(io::ErrorKind::TimedOut.into()),
+                _ => return Err(err),
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<Cqe> {
+        // SAFETY: head and tail live in the CQ mapping, and the entry at the head is
+        // complete once the tail moved past it.
+        unsafe {
+            let head = (*self.cq_head).load(Ordering::Relaxed);
+            if head == (*self.cq_tail).load(Ordering::Acquire) {
+                return None;
+            }
+            let cqe = self.cqes.add((head & self.cq_mask) as usize).read();
+            (*self.cq_head).store(head.wrapping_add(1), Ordering::Release);
+            Some(cqe)
+        }
+    }
+}
+
+/// Sends UDP datagrams through a ring, a batch per system call.
+pub(crate) struct SendRing {
+    ring: Ring,
+}
+
+impl SendRing {
+    pub(crate) fn new() -> io::Result<Self> {
+        Ok(Self {
+            ring: Ring::new(SEND_ENTRIES)?,
+        })
+    }
+
+    /// Sends every packet to `target`, in order. On failure, returns the index of the
+    /// first packet that did not go out.
+    pub(crate) fn send_all(
+        &mut self,
+        socket: &UdpSocket,
+        target: SocketAddr,
+        packets: &[Vec<u8>],
+    ) -> Result<usize, (usize, io::Error)> {
+        let (addr, addr_len) = sockaddr(target);
+        let mut iovecs: Vec<libc::iovec> = packets
+            .iter()
+            .map(|packet| libc::iovec {
+                iov_base: packet.as_ptr() as *mut libc::c_void,
+                iov_len: packet.len(),
+            })
+            .collect();
+        let headers: Vec<libc::msghdr> = iovecs
+            .iter_mut()
+            .map(|iovec| {
+                // SAFETY: all-zero is a valid `msghdr`; the fields that matter are set below.
+                let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+                header.msg_name = (&addr as *const libc::sockaddr_storage).cast_mut().cast();
+                header.msg_namelen = addr_len;
+                header.msg_iov = iovec;
+                header.msg_iovlen = 1;
+                header
+            })
+            .collect();
+
+        let mut sent = 0;
+        let mut first_failed: Option<(usize, io::Error)> = None;
+        for (n, chunk) in headers.chunks(SEND_ENTRIES as usize).enumerate() {
+            let start = n * SEND_ENTRIES as usize;
+            for (offset, header) in chunk.iter().enumerate() {
+                // Linked so the kernel sends them in order even if one has to wait.
+                let last = offset + 1 == chunk.len();
+                let queued = self.ring.push(Sqe {
+                    opcode: OP_SENDMSG,
+                    flags: if last { 0 } else { SQE_IO_LINK },
+                    fd: socket.as_raw_fd(),
+                    addr: header as *const libc::msghdr as u64,
+                    len: 1,
+                    user_data: (start + offset) as u64,
+                    ..Sqe::default()
+                });
+                debug_assert!(queued, "the queue drains between chunks");
+            }
+            // The headers must stay put until every operation completes.
+            let mut pending = chunk.len();
+            while pending > 0 {
+                self.ring
+                    .enter(pending as u32, None)
+                    .map_err(|err| (start, err))?;
+                while let Some(cqe) = self.ring.pop() {
+                    pending -= 1;
+                    let index = cqe.user_data as usize;
+                    if cqe.res < 0 {
+                        if first_failed.as_ref().is_none_or(|(at, _)| index < *at) {
+                            first_failed = Some((index, io::Error::from_raw_os_error(-cqe.res)));
+                        }
+                    } else {
+                        sent += cqe.res as usize;
+                    }
+                }
+            }
+            if let Some(failed) = first_failed {
+                return Err(failed);
+            }
+        }
+        Ok(sent)
+    }
+}
+
+/// Receives UDP datagrams with one multishot `recvmsg` kept armed over registered buffers.
+pub(crate) struct RecvRing {
+    ring: Ring,
+    socket: UdpSocket,
+    buffers: Mmap,
+    pool: Vec<u8>,
+    /// Read by the kernel for as long as the receive is armed.
+    header: Box<libc::msghdr>,
+    armed: bool,
+}
+
+// SAFETY: as for `Ring`; the buffer ring and the pool are only touched through `&mut self`.
+unsafe impl Send for RecvRing {}
+
+impl RecvRing {
+    pub(crate) fn new(socket: &UdpSocket) -> io::Result<Self> {
+        let mut ring = Ring::new(RECV_ENTRIES)?;
+        let socket = socket.try_clone()?;
+        let buffers = Mmap::new(None, RECV_BUFFERS as usize * std::mem::size_of::<Buf>(), 0)?;
+        let pool = vec![0u8; RECV_BUFFERS as usize * RECV_BUFFER_SIZE];
+        let reg = BufReg {
+            ring_addr: buffers.ptr as u64,
+            ring_entries: RECV_BUFFERS as u32,
+            bgid: RECV_BUFFER_GROUP,
+            ..BufReg::default()
+        };
+        // SAFETY: `reg` describes the page-aligned buffer ring mapped above.
+        let rc = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_register,
+                ring.fd.as_raw_fd(),
+                REGISTER_PBUF_RING,
+                &reg as *const BufReg,
+                1,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: all-zero is a valid `msghdr`; only the address length matters here.
+        let mut header: Box<libc::msghdr> = Box::new(unsafe { std::mem::zeroed() });
+        header.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as u32;
+        // Makes sure the ring takes operations before any buffer is handed over.
+        ring.enter(0, None)?;
+        let mut recv = Self {
+            ring,
+            socket,
+            buffers,
+            pool,
+            header,
+            armed: false,
+        };
+        for bid in 0..RECV_BUFFERS {
+            recv.recycle(bid);
+        }
+        Ok(recv)
+    }
+
+    /// Like `UdpSocket::recv_from`, failing with `TimedOut` after `timeout`.
+    pub(crate) fn recv_from(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<(usize, SocketAddr)> {
+        loop {
+            if !self.armed {
+                self.armed = self.ring.push(Sqe {
+                    opcode: OP_RECVMSG,
+                    flags: SQE_BUFFER_SELECT,
+                    ioprio: RECV_MULTISHOT,
+                    fd: self.socket.as_raw_fd(),
+                    addr: &*self.header as *const libc::msghdr as u64,
+                    len: 1,
+                    buf_group: RECV_BUFFER_GROUP,
+                    ..Sqe::default()
+                });
+            }
+            let Some(cqe) = self.ring.pop() else {
+                self.ring.enter(1, Some(timeout))?;
+                continue;
+            };
+            if cqe.flags & CQE_F_MORE == 0 {
+                self.armed = false;
+            }
+            if cqe.res < 0 {
+                match -cqe.res {
+                    // Every buffer was taken; the receive is armed again once one is back.
+                    libc::ENOBUFS => continue,
+                    errno => return Err(io::Error::from_raw_os_error(errno)),
+                }
+            }
+            if cqe.flags & CQE_F_BUFFER == 0 {
+                continue;
+            }
+            let bid = (cqe.flags >> CQE_BUFFER_SHIFT) as u16;
+            let received = self.datagram(bid, cqe.res as usize, buf);
+            self.recycle(bid);
+            if let Some(received) = received {
+                return Ok(received);
+            }
+        }
+    }
+
+    /// Copies the datagram in buffer `bid` out to `buf`, with its source.
+    fn datagram(&self, bid: u16, len: usize, buf: &mut [u8]) -> Option<(usize, SocketAddr)> {
+        let start = bid as usize * RECV_BUFFER_SIZE;
+        let data = &self.pool[start..start + len.min(RECV_BUFFER_SIZE)];
+        let header_len = std::mem::size_of::<RecvmsgOut>();
+        let name_len = self.header.msg_namelen as usize;
+        if data.len() < header_len + name_len {
+            return None;
+        }
+        // SAFETY: the kernel wrote a `RecvmsgOut` at the start of the buffer.
+        let out = unsafe { std::ptr::read_unaligned(data.as_ptr().cast::<RecvmsgOut>()) };
+        let from = socket_addr(&data[header_len..header_len + out.namelen as usize])?;
+        let payload = &data[header_len + name_len..];
+        let len = (out.payloadlen as usize).min(payload.len()).min(buf.len());
+        buf[..len].copy_from_slice(&payload[..len]);
+        Some((len, from))
+    }
+
+    /// Hands buffer `bid` back to the kernel.
+    fn recycle(&mut self, bid: u16) {
+        let addr = self.pool.as_mut_ptr() as u64 + bid as u64 * RECV_BUFFER_SIZE as u64;
+        // SAFETY: the tail overlays `resv` of the first entry, so entries are written field
+        // by field around it; the entry at the tail is not the kernel's until the tail
+        // moves past it.
+        unsafe {
+            let tail = &*self.buffers.at::<AtomicU16>(14);
+            let index = tail.load(Ordering::Relaxed) & (RECV_BUFFERS - 1);
+            let entry = self.buffers.at::<Buf>(0).add(index as usize);
+            std::ptr::addr_of_mut!((*entry).addr).write(addr);
+            std::ptr::addr_of_mut!((*entry).len).write(RECV_BUFFER_SIZE as u32);
+            std::ptr::addr_of_mut!((*entry).bid).write(bid);
+            tail.fetch_add(1, Ordering::Release);
+        }
+    }
+}
+
+fn socket_addr(name: &[u8]) -> Option<SocketAddr> {
+    if name.len() < 2 {
+        return None;
+    }
+    let family = u16::from_ne_bytes([name[0], name[1]]) as i32;
+    let port = u16::from_be_bytes(name.get(2..4)?.try_into().ok()?);
+    match family {
+        libc::AF_INET => {
+            let ip: [u8; 4] = name.get(4..8)?.try_into().ok()?;
+            Some(SocketAddrV4::new(ip.into(), port).into())
+        }
+        libc::AF_INET6 => {
+            let flowinfo = u32::from_ne_bytes(name.get(4..8)?.try_into().ok()?);
+            let ip: [u8; 16] = name.get(8..24)?.try_into().ok()?;
+            let scope_id = u32::from_ne_bytes(name.get(24..28)?.try_into().ok()?);
+            Some(SocketAddrV6::new(ip.into(), port, flowinfo, scope_id).into())
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,117 @@
+//! VBAN, VB-Audio's audio-over-UDP protocol: the stream as VBAN Receptor, Voicemeeter and
+//! other VBAN receivers play it, with nothing of ours on the receiving end.
+
+use crate::error::{Error, ProtocolError};
+use crate::protocol::pcm_audio;
+use crate::transport::Repacketizer;
+
+pub const MAGIC: [u8; 4] = *b"VBAN";
+pub const HEADER_SIZE: usize = 28;
+/// Most samples per channel in one packet.
+pub const MAX_SAMPLES: usize = 256;
+/// Most payload in one packet, so that it fits an Ethernet frame.
+pub const MAX_PAYLOAD: usize = 1436;
+/// Longest stream name; shorter ones are padded with zeros.
+pub const MAX_NAME: usize = 16;
+
+const SUB_PROTOCOL_AUDIO: u8 = 0x00;
+const CODEC_PCM: u8 = 0x00;
+const DATATYPE_INT16: u8 = 0x01;
+
+/// Sample rates by their index in the header.
+const SAMPLE_RATES: [u32; 21] = [
+    6000, 12000, 24000, 48000, 96000, 192000, 384000, 8000, 16000, 32000, 64000, 128000, 256000,
+    512000, 11025, 22050, 44100, 88200, 176400, 352800, 705600,
+];
+
+/// The name receivers pick a stream by; VBAN's own default is `Stream1`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StreamName([u8; MAX_NAME]);
+
+impl StreamName {
+    pub fn new(name: &str) -> Result<Self, Error> {
+        if name.is_empty()
+            || name.len() > MAX_NAME
+            || !name
+                .bytes()
+                .all(|byte| byte.is_ascii_graphic() || byte == b' ')
+        {
+            return Err(Error::Config(format!(
+                "VBAN stream name {name:?} must be 1 to {MAX_NAME} ASCII characters"
+            )));
+        }
+        let mut bytes = [0; MAX_NAME];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(Self(bytes))
+    }
+}
+
+impl Default for StreamName {
+    fn default() -> Self {
+        let mut bytes = [0; MAX_NAME];
+        bytes[..7].copy_from_slice(b"Stream1");
+        Self(bytes)
+    }
+}
+
+/// Turns the audio packets of a stream into VBAN packets, numbering them as it goes.
+#[derive(Debug)]
+pub struct Packetizer {
+    name: StreamName,
+    frame: u32,
+    packets: Vec<Vec<u8>>,
+}
+
+impl Packetizer {
+    pub fn new(name: StreamName) -> Self {
+        Self {
+            name,
+            frame: 0,
+            packets: Vec::new(),
+        }
+    }
+}
+
+impl Repacketizer for Packetizer {
+    fn packet_name(&self) -> &'static str {
+        "VBAN packet"
+    }
+
+    /// Splits each of our packets evenly over as few VBAN packets as hold it.
+    fn packetize(&mut self, packet: &[u8]) -> Result<&[Vec<u8>], ProtocolError> {
+        let Some(audio) = pcm_audio(packet)? else {
+            return Ok(&[]);
+        };
+        let channels = audio.channels;
+        let rate_index = SAMPLE_RATES
+            .iter()
+            .position(|&rate| rate == audio.sample_rate)
+            .ok_or(ProtocolError::UnsupportedSampleRate(audio.sample_rate))?;
+
+        let frame_bytes = 2 * channels as usize;
+        let frames = audio.samples.len() / frame_bytes;
+        let max_frames = MAX_SAMPLES.min(MAX_PAYLOAD / frame_bytes).max(1);
+        let per_packet = frames.div_ceil(frames.div_ceil(max_frames).max(1)).max(1);
+        let count = audio.samples.chunks(per_packet * frame_bytes).len();
+        if self.packets.len() < count {
+            self.packets.resize_with(count, Vec::new);
+        }
+        for (chunk, out) in audio
+            .samples
+            .chunks(per_packet * frame_bytes)
+            .zip(&mut self.packets)
+        {
+            out.clear();
+            out.extend_from_slice(&MAGIC);
+            out.push(SUB_PROTOCOL_AUDIO | rate_index as u8);
+            out.push((chunk.len() / frame_bytes - 1) as u8);
+            out.push(channels - 1);
+            out.push(CODEC_PCM | DATATYPE_INT16);
+            out.extend_from_slice(&self.name.0);
+            out.extend_from_slice(&self.frame.to_le_bytes());
+            out.extend_from_slice(chunk);
+            self.frame = self.frame.wrapping_add(1);
+        }
+        Ok(&self.packets[..count])
+    }
+}
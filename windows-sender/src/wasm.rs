@@ -0,0 +1,84 @@
+use wasm_bindgen::prelude::*;
+
+use crate::crypto::Key;
+use crate::decoder::StreamDecoder;
+use crate::protocol::is_marker;
+
+#[wasm_bindgen]
+pub struct WasmReceiver {
+    decoder: StreamDecoder,
+}
+
+#[wasm_bindgen]
+impl WasmReceiver {
+    #[wasm_bindgen(constructor)]
+    pub fn new(jitter_ms: u32) -> WasmReceiver {
+        WasmReceiver {
+            decoder: StreamDecoder::new(jitter_ms),
+        }
+    }
+
+    /// Decrypts a stream sent with `--key`, given as 64 hex digits or base64.
+    pub fn set_key(&mut self, key: &str) -> Result<(), JsError> {
+        let key = key.parse::<Key>()?;
+        self.decoder.set_key(Some(&key));
+        Ok(())
+    }
+
+    /// Feeds one packet as received from a WebSocket/WebTransport message. Sync markers
+    /// are skipped.
+    pub fn push_packet(&self, packet: &[u8]) -> bool {
+        !is_marker(packet) && self.decoder.push_packet(packet, 0)
+    }
+
+    /// Interleaved PCM16 samples for the next playout frame, if one is ready.
+    pub fn pop_frame_pcm16(&self) -> Option<Vec<i16>> {
+        self.decoder.try_pop_frame()
+    }
+
+    /// Interleaved float samples in [-1, 1] for feeding Web Audio.
+    pub fn pop_frame_f32(&self) -> Option<Vec<f32>> {
+        self.decoder
+            .try_pop_frame()
+            .map(|frame| frame.iter().map(|s| *s as f32 / i16::MAX as f32).collect())
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.decoder.format().map(|f| f.sample_rate).unwrap_or(0)
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.decoder.format().map(|f| f.channels).unwrap_or(0)
+    }
+
+    pub fn samples_per_channel(&self) -> u16 {
+        self.decoder
+            .format()
+            .map(|f| f.samples_per_channel)
+            .unwrap_or(0)
+    }
+
+    pub fn rx_packets(&self) -> u64 {
+        self.decoder.stats().rx_packets
+    }
+
+    pub fn parse_errors(&self) -> u64 {
+        self.decoder.stats().parse_errors
+    }
+
+    pub fn auth_failures(&self) -> u64 {
+        self.decoder.stats().auth_failures
+    }
+
+    pub fn replays(&self) -> u64 {
+        self.decoder.stats().replays
+    }
+
+    pub fn buffered_frames(&self) -> u64 {
+        self.decoder.stats().buffered_frames
+    }
+
+    pub fn missing_frames(&self) -> u64 {
+        self.decoder.stats().missing
+    }
+}
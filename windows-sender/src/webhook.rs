@@ -0,0 +1,167 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use crossbeam_channel::{select, Receiver, Sender};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+use windows_sender::journal::{Event, EventJournal, EventKind};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Stats payloads waiting for a slow endpoint; older intervals are dropped beyond this.
+const QUEUE: usize = 16;
+
+/// `host:port/path` of a plain HTTP endpoint.
+#[derive(Clone, Debug)]
+struct Endpoint {
+    authority: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            bail!("webhook URL {url} is not supported; only http:// endpoints are");
+        };
+        let (authority, path) = match rest.split_once('/') {
+            Some((authority, path)) => (authority, format!("/{path}")),
+            None => (rest, "/".to_string()),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse()
+                    .with_context(|| format!("invalid port in webhook URL {url}"))?,
+            ),
+            _ => (authority, 80),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            bail!("webhook URL {url} has no host");
+        }
+        Ok(Self {
+            authority: authority.to_string(),
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+
+    /// POSTs one JSON body and returns the response status.
+    fn post(&self, body: &str) -> io::Result<u16> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no address"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.path,
+            self.authority,
+            body.len()
+        )?;
+        stream.flush()?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        status
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))
+    }
+}
+
+/// Posts stats intervals and error events of one session to `--stats-webhook`.
+#[derive(Clone, Debug)]
+pub struct Webhook {
+    tx: Sender<Value>,
+}
+
+impl Webhook {
+    /// Starts the posting thread. Events are sent as they are recorded, not with the next
+    /// stats interval.
+    pub fn spawn(url: &str, session: &str, journal: &EventJournal) -> Result<Self> {
+        let endpoint = Endpoint::parse(url)?;
+        let (tx, rx) = crossbeam_channel::bounded(QUEUE);
+        let events = journal.subscribe();
+        let session = session.to_string();
+        thread::Builder::new()
+            .name("webhook".to_string())
+            .spawn(move || post_loop(&endpoint, &session, &rx, &events))
+            .context("failed to spawn webhook thread")?;
+        Ok(Self { tx })
+    }
+
+    /// Queues one stats record; it is dropped when the endpoint is too slow to keep up.
+    pub fn stats(&self, record: Value) {
+        let _ = self.tx.try_send(record);
+    }
+}
+
+/// Runs until every `Webhook` handle of the session is gone.
+fn post_loop(
+    endpoint: &Endpoint,
+    session: &str,
+    stats: &Receiver<Value>,
+    events: &Receiver<Event>,
+) {
+    let mut failing = false;
+    loop {
+        let payload = select! {
+            recv(stats) -> record => match record {
+                Ok(Value::Object(mut record)) => {
+                    record.insert("type".to_string(), json!("stats"));
+                    Value::Object(record)
+                }
+                Ok(_) => continue,
+                Err(_) => return,
+            },
+            recv(events) -> event => match event {
+                Ok(event) if is_alert(event.kind) => json!({
+                    "type": "alert",
+                    "session": session,
+                    "time_ms": event.time_ms,
+                    "kind": event.kind,
+                    "message": event.message,
+                }),
+                Ok(_) => continue,
+                Err(_) => return,
+            },
+        };
+        // One warning per outage instead of one per interval.
+        match endpoint.post(&payload.to_string()) {
+            Ok(status) if (200..300).contains(&status) => {
+                if failing {
+                    info!("stats webhook delivering again");
+                    failing = false;
+                }
+            }
+            Ok(status) => {
+                if !failing {
+                    warn!("stats webhook answered HTTP {status}");
+                    failing = true;
+                }
+            }
+            Err(err) => {
+                if !failing {
+                    warn!("stats webhook unreachable: {err}");
+                    failing = true;
+                }
+            }
+        }
+    }
+}
+
+/// Everything that points at trouble; starts and target changes are routine.
+fn is_alert(kind: EventKind) -> bool {
+    !matches!(kind, EventKind::Start | EventKind::Targets)
+}
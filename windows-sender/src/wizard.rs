@@ -0,0 +1,215 @@
+use std::io::{self, IsTerminal, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::style::Print;
+use ratatui::crossterm::terminal::{self, ClearType};
+use ratatui::crossterm::{cursor, queue};
+use serde::Serialize;
+use windows_sender::capture;
+use windows_sender::error::error_chain;
+use windows_sender::probe;
+use windows_sender::resolve::{Endpoint, IpPreference};
+
+use crate::{Args, AudioSource};
+
+/// Long enough for a few reports from every receiver that heard the broadcast.
+const DISCOVERY_WAIT: Duration = Duration::from_millis(1500);
+const DEFAULT_PROFILE: &str = "sender.toml";
+
+/// The settings the wizard picked, as a `--config` file.
+#[derive(Serialize)]
+struct Profile<'a> {
+    target_ip: &'a str,
+    port: u16,
+    source: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    desktop_device: Option<&'a str>,
+}
+
+/// Whether someone is at the terminal to answer the wizard.
+pub fn available() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+/// Asks for the receiver and the source, filling them into `args`, and offers to save
+/// them as a profile for `--config`.
+pub fn run(args: &mut Args) -> Result<()> {
+    println!("No --target-ip given; setting up interactively (arrows and Enter, Esc cancels).");
+    println!("Looking for receivers on UDP port {}...", args.port);
+    let found = probe::discover(args.port, DISCOVERY_WAIT).unwrap_or_else(|err| {
+        println!("Receiver discovery failed: {:#}", anyhow::Error::from(err));
+        Vec::new()
+    });
+    let mut options: Vec<String> = found.iter().map(ToString::to_string).collect();
+    options.push("Enter an address".to_string());
+    let target = match pick("Receiver", &options)? {
+        choice if choice < found.len() => Endpoint::Addr(found[choice]),
+        _ => ask_target(args.port, args.prefer_ip.into())?,
+    };
+    let (host, port) = match target {
+        Endpoint::Addr(addr) => match addr.ip() {
+            IpAddr::V6(ip) => (format!("[{ip}]"), addr.port()),
+            ip => (ip.to_string(), addr.port()),
+        },
+        Endpoint::Name(name) => (name.host, name.port),
+    };
+    args.target_ip = Some(host);
+    args.port = port;
+
+    if !args.stress {
+        pick_source(args)?;
+    }
+    let options = ["Save as a profile".to_string(), "Just stream".to_string()];
+    if pick("Save these settings?", &options)? == 0 {
+        save_profile(args)?;
+    }
+    Ok(())
+}
+
+/// A desktop render device or the default microphone.
+fn pick_source(args: &mut Args) -> Result<()> {
+    let devices = capture::desktop_devices().unwrap_or_default();
+    let mut options: Vec<String> = devices
+        .iter()
+        .map(|device| {
+            if device.is_default {
+                format!("Desktop: {} [default]", device.name)
+            } else {
+                format!("Desktop: {}", device.name)
+            }
+        })
+        .collect();
+    options.push("Microphone (default input)".to_string());
+    match pick("Source", &options)? {
+        choice if choice < devices.len() => {
+            args.source = AudioSource::Desktop;
+            args.desktop_device =
+                (!devices[choice].is_default).then(|| devices[choice].name.clone());
+        }
+        _ => {
+            args.source = AudioSource::Mic;
+            args.desktop_device = None;
+        }
+    }
+    Ok(())
+}
+
+fn save_profile(args: &Args) -> Result<()> {
+    let profile = Profile {
+        target_ip: args.target_ip.as_deref().unwrap_or_default(),
+        port: args.port,
+        source: match args.source {
+            AudioSource::Desktop => "desktop",
+            AudioSource::Mic => "mic",
+            AudioSource::Tone => "tone",
+            AudioSource::File => "file",
+        },
+        desktop_device: args.desktop_device.as_deref(),
+    };
+    let text = toml::to_string(&profile).context("failed to serialize the profile")?;
+    let path = loop {
+        let path = ask("Profile file", DEFAULT_PROFILE)?;
+        if !Path::new(&path).exists() {
+            break path;
+        }
+        println!("{path} already exists; pick another name.");
+    };
+    std::fs::write(&path, text).with_context(|| format!("failed to write {path}"))?;
+    println!("Saved {path}; next time run with --config {path}");
+    Ok(())
+}
+
+/// An IP or host name, with or without a port, typed in until one resolves.
+fn ask_target(port: u16, prefer: IpPreference) -> Result<Endpoint> {
+    loop {
+        let answer = ask("Receiver (ip or host name, optionally with :port)", "")?;
+        let target = Endpoint::parse(&answer, prefer)
+            .unwrap_or_else(|_| Endpoint::new(&answer, port, prefer));
+        match target.resolve() {
+            Ok(_) => return Ok(target),
+            Err(err) => println!("{}", error_chain(&err)),
+        }
+    }
+}
+
+/// A line from stdin, or `default` when it is left empty.
+fn ask(prompt: &str, default: &str) -> Result<String> {
+    match default {
+        "" => print!("{prompt}: "),
+        default => print!("{prompt} [{default}]: "),
+    }
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line)? == 0 {
+        bail!("setup cancelled");
+    }
+    Ok(match line.trim() {
+        "" => default.to_string(),
+        answer => answer.to_string(),
+    })
+}
+
+/// Leaves raw mode when dropped, however the picker returns.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> Result<Self> {
+        terminal::enable_raw_mode().context("failed to read keys from the terminal")?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Lets the user move through `options` with the arrow keys and take one with Enter,
+/// then collapses the list to the choice. Esc, `q` and Ctrl+C cancel the setup.
+fn pick(prompt: &str, options: &[String]) -> Result<usize> {
+    let mut out = io::stdout();
+    println!("{prompt}:");
+    let raw = RawMode::enable()?;
+    let mut selected = 0;
+    let choice = loop {
+        for (i, option) in options.iter().enumerate() {
+            let marker = if i == selected { ">" } else { " " };
+            queue!(
+                out,
+                terminal::Clear(ClearType::CurrentLine),
+                Print(format!("{marker} {option}\r\n"))
+            )?;
+        }
+        out.flush()?;
+        let Event::Key(key) = event::read()? else {
+            queue!(out, cursor::MoveUp(options.len() as u16))?;
+            continue;
+        };
+        queue!(out, cursor::MoveUp(options.len() as u16))?;
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Up => selected = selected.checked_sub(1).unwrap_or(options.len() - 1),
+            KeyCode::Down => selected = (selected + 1) % options.len(),
+            KeyCode::Enter => break Some(selected),
+            KeyCode::Esc | KeyCode::Char('q') => break None,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break None,
+            _ => {}
+        }
+    };
+    queue!(out, terminal::Clear(ClearType::FromCursorDown))?;
+    drop(raw);
+    match choice {
+        Some(choice) => {
+            println!("> {}", options[choice]);
+            Ok(choice)
+        }
+        None => bail!("setup cancelled"),
+    }
+}